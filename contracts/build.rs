@@ -1,7 +1,60 @@
 // Deactivate in clippy, we don't really need to recompile and it breaks our CI.
-#[cfg(any(clippy, not(feature = "build")))]
+#[cfg(clippy)]
 fn main() {}
 
+// Without the build feature the prebuilt images are used, which only fit the guest sources they
+// were built from.
+#[cfg(all(not(clippy), not(feature = "build")))]
+fn main() {
+    for name in ["wallet"] {
+        println!("cargo:rerun-if-changed={name}/src");
+        println!("cargo:rerun-if-changed={name}/{name}.sources");
+        let built_from =
+            std::fs::read_to_string(format!("{name}/{name}.sources")).unwrap_or_default();
+        if built_from.trim() != guest_sources_digest(name) {
+            panic!(
+                "{name}/{name}.img was not built from the current {name} sources, rebuild it with \
+                 `cargo build -p contracts -F build,{name}`"
+            );
+        }
+    }
+}
+
+/// FNV-1a digest of the sources of a guest, recorded next to its image when it is built.
+/// The client module is left out as it is never part of the guest.
+#[cfg(not(clippy))]
+fn guest_sources_digest(name: &str) -> String {
+    fn collect(dir: &std::path::Path, files: &mut Vec<std::path::PathBuf>) {
+        for entry in std::fs::read_dir(dir).expect("failed to read guest sources") {
+            let path = entry.expect("failed to read guest sources").path();
+            if !path.is_dir() {
+                files.push(path);
+            } else if path.file_name() != Some("client".as_ref()) {
+                collect(&path, files);
+            }
+        }
+    }
+
+    let src = std::path::Path::new(name).join("src");
+    let mut files = vec![];
+    collect(&src, &mut files);
+    files.sort();
+
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for file in files {
+        let relative = file
+            .strip_prefix(&src)
+            .unwrap()
+            .to_string_lossy()
+            .replace('\\', "/");
+        let contents = std::fs::read(&file).expect("failed to read guest source");
+        for byte in relative.bytes().chain([0]).chain(contents) {
+            hash = (hash ^ byte as u64).wrapping_mul(0x100000001b3);
+        }
+    }
+    format!("{hash:016x}")
+}
+
 #[cfg(all(feature = "build", not(feature = "wallet")))]
 fn main() {
     compile_error!("When the 'build' feature is enabled, at least one of the following features must also be enabled: all, wallet.");
@@ -118,6 +171,11 @@ fn main() {
             .join("");
         std::fs::write(format!("{}/{}.txt", data.name, data.name), &hex_image_id)
             .expect("failed to write program ID");
+        std::fs::write(
+            format!("{}/{}.sources", data.name, data.name),
+            guest_sources_digest(&data.name),
+        )
+        .expect("failed to write sources digest");
     });
 
     std::env::set_var("RUSTC_WORKSPACE_WRAPPER", env_wrapper.unwrap_or_default());
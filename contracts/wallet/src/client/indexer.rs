@@ -1,6 +1,12 @@
-use std::{str, sync::Arc};
+use std::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    ops::Deref,
+    str,
+    sync::{Arc, Mutex},
+};
 
 use anyhow::{anyhow, Context, Result};
+use borsh::{BorshDeserialize, BorshSerialize};
 use client_sdk::{
     contract_indexer::{
         axum::{
@@ -18,21 +24,182 @@ use client_sdk::{
 };
 use hyli_modules::bus::BusMessage;
 use sdk::{tracing, Hashed};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
-use crate::{client::tx_executor_handler::Wallet, *};
+use crate::{
+    client::{journal, session_key::SessionKeyTransfer, tx_executor_handler::Wallet},
+    *,
+};
 use client_sdk::contract_indexer::axum;
 use client_sdk::contract_indexer::utoipa;
 
 #[derive(Debug, Clone, Default, Serialize)]
 pub struct WalletEvent {
     pub account: sdk::Identity,
+    pub tx_hash: sdk::TxHash,
+    pub outcome: TxOutcome,
     pub program_outputs: String,
+    /// Set when the transaction failed because the account's authentication did.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub failed_auth: Option<FailedAuthentication>,
+}
+
+/// How the transaction of a [`WalletEvent`] ended.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub enum TxOutcome {
+    #[default]
+    Success,
+    Failure,
+    Timeout,
+}
+
+/// A settled transaction that failed to authenticate `account`, to report with
+/// [`WalletAction::ReportFailedAuth`].
+#[derive(Debug, Clone, Serialize)]
+pub struct FailedAuthentication {
+    pub contract_name: sdk::ContractName,
+    pub account: String,
+    pub attempt: u32,
+    /// Account nonce the report must be signed for.
+    pub nonce: u128,
+}
+
+/// A [`WalletEvent`] as sent on the bus, shared by its subscribers instead of cloned for each.
+#[derive(Debug, Clone)]
+pub struct SharedWalletEvent(pub Arc<WalletEvent>);
+
+impl From<WalletEvent> for SharedWalletEvent {
+    fn from(event: WalletEvent) -> Self {
+        Self(Arc::new(event))
+    }
+}
+
+impl Deref for SharedWalletEvent {
+    type Target = WalletEvent;
+
+    fn deref(&self) -> &WalletEvent {
+        &self.0
+    }
+}
+
+impl BusMessage for SharedWalletEvent {}
+
+/// Stores of the wallet contracts indexed by this process, for off-chain data to be updated
+/// outside of the indexer routes. Only handles are kept here, registered again as indexers start;
+/// the state behind them is persisted by the indexer and the [`journal`].
+static STORES: Mutex<BTreeMap<String, ContractHandlerStore<Wallet>>> = Mutex::new(BTreeMap::new());
+
+/// Indexed state of `contract_name`, once its indexer is built.
+pub fn store(contract_name: &sdk::ContractName) -> Option<ContractHandlerStore<Wallet>> {
+    STORES.lock().ok()?.get(&contract_name.0).cloned()
+}
+
+/// Uses of a session key kept for its audit trail, the oldest dropped first.
+const SESSION_KEY_USAGE_LIMIT: usize = 1_000;
+
+/// Uses of session keys, keyed by contract name, account and public key.
+type SessionKeyUsages = BTreeMap<(String, String, String), VecDeque<SessionKeyUsage>>;
+
+/// Settled uses of each session key, restored from the [`journal`] when it is configured.
+static SESSION_KEY_USAGE: Mutex<SessionKeyUsages> = Mutex::new(BTreeMap::new());
+
+/// A settled `UseSessionKey`, as listed by `GET /account/{account}/session_keys/{key}/usage`.
+#[derive(Debug, Clone, Serialize, ToSchema, BorshSerialize, BorshDeserialize)]
+pub struct SessionKeyUsage {
+    pub tx_hash: String,
+    pub timestamp: u128,
+    /// Contracts of the blobs the key authorized.
+    pub contracts: Vec<String>,
+}
+
+/// Adds the `UseSessionKey` of `account` at `nonce` to the audit trail of the key that signed it.
+fn record_session_key_usage(
+    contract_name: &str,
+    calldata: &sdk::Calldata,
+    account: String,
+    nonce: u128,
+) -> Result<()> {
+    let (public_key, _, verifier_index) =
+        AccountInfo::session_key_signature(calldata, &account, nonce).map_err(|e| anyhow!(e))?;
+    let contracts: BTreeSet<_> = calldata
+        .blobs
+        .iter()
+        .filter(|(index, _)| *index != calldata.index && *index != verifier_index)
+        .map(|(_, blob)| blob.contract_name.0.clone())
+        .collect();
+    let usage = SessionKeyUsage {
+        tx_hash: calldata.tx_hash.0.clone(),
+        timestamp: calldata
+            .tx_ctx
+            .as_ref()
+            .map(|tx_ctx| tx_ctx.timestamp.0)
+            .unwrap_or_default(),
+        contracts: contracts.into_iter().collect(),
+    };
+
+    let entry = journal::SessionKeyUse {
+        account,
+        public_key,
+        usage,
+    };
+    push_session_key_usage(
+        &mut SESSION_KEY_USAGE
+            .lock()
+            .map_err(|_| anyhow!("Session key usage lock poisoned"))?,
+        contract_name,
+        entry.clone(),
+    );
+    journal::record_session_key_use(contract_name, &entry)
 }
 
-impl BusMessage for WalletEvent {}
+/// Adds a use to the audit trail of its key, dropping the oldest one past the limit.
+fn push_session_key_usage(
+    usages: &mut SessionKeyUsages,
+    contract_name: &str,
+    entry: journal::SessionKeyUse,
+) {
+    let uses = usages
+        .entry((contract_name.to_string(), entry.account, entry.public_key))
+        .or_default();
+    uses.push_back(entry.usage);
+    if uses.len() > SESSION_KEY_USAGE_LIMIT {
+        uses.pop_front();
+    }
+}
+
+/// Reloads the session key uses journaled for `contract_name`, rewriting its log without the
+/// uses past the limit.
+fn restore_session_key_usage(contract_name: &str) -> Result<()> {
+    let entries = journal::restore_session_key_uses(contract_name)?;
+    if entries.is_empty() {
+        return Ok(());
+    }
+    let recorded = entries.len();
+    let mut usages = SESSION_KEY_USAGE
+        .lock()
+        .map_err(|_| anyhow!("Session key usage lock poisoned"))?;
+    for entry in entries {
+        push_session_key_usage(&mut usages, contract_name, entry);
+    }
+    let kept: Vec<_> = usages
+        .iter()
+        .filter(|((contract, _, _), _)| contract == contract_name)
+        .flat_map(|((_, account, public_key), uses)| {
+            uses.iter().map(|usage| journal::SessionKeyUse {
+                account: account.clone(),
+                public_key: public_key.clone(),
+                usage: usage.clone(),
+            })
+        })
+        .collect();
+    if kept.len() < recorded {
+        journal::rewrite_session_key_uses(contract_name, &kept)?;
+    }
+    Ok(())
+}
 
 impl Wallet {
+    #[tracing::instrument(skip_all, fields(tx_hash = %tx.hashed(), index = index.0))]
     fn handle_transaction(
         &mut self,
         tx: &sdk::BlobTransaction,
@@ -41,7 +208,7 @@ impl Wallet {
     ) -> Result<Option<WalletEvent>> {
         let sdk::Blob {
             contract_name,
-            data: _,
+            data,
         } = tx.blobs.get(index.0).context("Failed to get blob")?;
 
         let calldata = sdk::Calldata {
@@ -57,6 +224,23 @@ impl Wallet {
         let res = self.handle(&calldata);
         let event = match res {
             Ok(hyli_output) => {
+                if hyli_output.success {
+                    if let Ok(WalletAction::UseSessionKey { account, nonce }) =
+                        WalletAction::from_blob_data(data)
+                    {
+                        if let Err(e) =
+                            record_session_key_usage(&contract_name.0, &calldata, account, nonce)
+                        {
+                            tracing::error!("Failed to record session key usage: {e:#}");
+                        }
+                    }
+                    if let Err(e) = WalletAction::from_blob_data(data)
+                        .and_then(|action| self.journal_entries(&action))
+                        .and_then(|entries| journal::record(&contract_name.0, self, &entries))
+                    {
+                        tracing::error!("Failed to journal wallet state: {e:#}");
+                    }
+                }
                 let program_outputs =
                     str::from_utf8(&hyli_output.program_outputs).unwrap_or("no output");
 
@@ -67,42 +251,94 @@ impl Wallet {
                 );
                 WalletEvent {
                     account: tx.identity.clone(),
+                    tx_hash: tx.hashed(),
+                    outcome: TxOutcome::Success,
                     program_outputs: program_outputs.to_string(),
+                    failed_auth: None,
                 }
             }
             Err(e) => {
                 sdk::info!("🚀 Executed {contract_name} with error: {}", e);
                 WalletEvent {
                     account: tx.identity.clone(),
+                    tx_hash: tx.hashed(),
+                    outcome: TxOutcome::Success,
                     program_outputs: format!("Error: {e:?}"),
+                    failed_auth: None,
                 }
             }
         };
 
         Ok(Some(event))
     }
+
+    /// Whether the wallet blob at `index` fails to authenticate its account against the current
+    /// state, as opposed to the transaction failing for other reasons.
+    fn failed_authentication(
+        &self,
+        tx: &sdk::BlobTransaction,
+        index: sdk::BlobIndex,
+        tx_context: &sdk::TxContext,
+    ) -> Option<FailedAuthentication> {
+        let blob = tx.blobs.get(index.0)?;
+        let (account, nonce) = match WalletAction::from_blob_data(&blob.data).ok()? {
+            WalletAction::VerifyIdentity { account, nonce }
+            | WalletAction::AddSessionKey { account, nonce, .. }
+            | WalletAction::AddLimitedSessionKey { account, nonce, .. }
+            | WalletAction::FreezeAccount { account, nonce }
+            | WalletAction::UnfreezeAccount { account, nonce }
+            | WalletAction::RemoveSessionKey { account, nonce, .. }
+            | WalletAction::RenewSessionKey { account, nonce, .. }
+            | WalletAction::UpdateTimePolicy { account, nonce, .. } => (account, nonce),
+            _ => return None,
+        };
+        let account_info = self.get(&account).ok()?;
+        let calldata = sdk::Calldata {
+            identity: tx.identity.clone(),
+            index,
+            blobs: tx.blobs.clone().into(),
+            tx_blob_count: tx.blobs.len(),
+            tx_hash: tx.hashed(),
+            tx_ctx: Some(tx_context.clone()),
+            private_input: vec![],
+        };
+        account_info.auth_method.verify(&calldata, nonce).err()?;
+        Some(FailedAuthentication {
+            contract_name: blob.contract_name.clone(),
+            account,
+            attempt: account_info.failed_auth.count.saturating_add(1),
+            nonce: account_info.nonce,
+        })
+    }
 }
 
-impl ContractHandler<WalletEvent> for Wallet {
+impl ContractHandler<SharedWalletEvent> for Wallet {
     fn handle_transaction_success(
         &mut self,
         tx: &sdk::BlobTransaction,
         index: sdk::BlobIndex,
         tx_context: Arc<sdk::TxContext>,
-    ) -> Result<Option<WalletEvent>> {
+    ) -> Result<Option<SharedWalletEvent>> {
         self.handle_transaction(tx, index, tx_context)
+            .map(|event| event.map(Into::into))
     }
 
     fn on_transaction_failed(
         &mut self,
         tx: &sdk::BlobTransaction,
-        _index: sdk::BlobIndex,
-        _tx_context: Arc<sdk::TxContext>,
-    ) -> Result<Option<WalletEvent>> {
-        Ok(Some(WalletEvent {
-            account: tx.identity.clone(),
-            program_outputs: "Transaction failed".to_string(),
-        }))
+        index: sdk::BlobIndex,
+        tx_context: Arc<sdk::TxContext>,
+    ) -> Result<Option<SharedWalletEvent>> {
+        Ok(Some(
+            WalletEvent {
+                account: tx.identity.clone(),
+                tx_hash: tx.hashed(),
+                outcome: TxOutcome::Failure,
+                program_outputs: "Transaction failed".to_string(),
+                failed_auth: self.failed_authentication(tx, index, &tx_context),
+            }
+            .into(),
+        ))
     }
 
     fn on_transaction_timeout(
@@ -110,17 +346,62 @@ impl ContractHandler<WalletEvent> for Wallet {
         tx: &sdk::BlobTransaction,
         _index: sdk::BlobIndex,
         _tx_context: Arc<sdk::TxContext>,
-    ) -> Result<Option<WalletEvent>> {
-        Ok(Some(WalletEvent {
-            account: tx.identity.clone(),
-            program_outputs: "Transaction timeout".to_string(),
-        }))
+    ) -> Result<Option<SharedWalletEvent>> {
+        Ok(Some(
+            WalletEvent {
+                account: tx.identity.clone(),
+                tx_hash: tx.hashed(),
+                outcome: TxOutcome::Timeout,
+                program_outputs: "Transaction timeout".to_string(),
+                failed_auth: None,
+            }
+            .into(),
+        ))
     }
 
     async fn api(store: ContractHandlerStore<Wallet>) -> (Router<()>, OpenApi) {
+        {
+            let mut store = store.write().await;
+            match journal::restore(&store.contract_name.0) {
+                Ok(Some(wallet)) => {
+                    tracing::info!(
+                        "Restored wallet state of {} from its journal",
+                        store.contract_name
+                    );
+                    store.state = Some(wallet);
+                }
+                Ok(None) => {}
+                Err(e) => tracing::error!(
+                    "Failed to restore wallet state of {} from its journal, keeping the indexer's: {e:#}",
+                    store.contract_name
+                ),
+            }
+            if let Err(e) = restore_session_key_usage(&store.contract_name.0) {
+                tracing::error!(
+                    "Failed to restore session key usage of {}: {e:#}",
+                    store.contract_name
+                );
+            }
+        }
+        let contract_name = store.read().await.contract_name.0.clone();
+        if let Ok(mut stores) = STORES.lock() {
+            stores.insert(contract_name, store.clone());
+        }
+
         let (router, api) = OpenApiRouter::default()
             .routes(routes!(get_state))
+            .routes(routes!(dump))
+            .routes(routes!(get_commitment))
             .routes(routes!(get_account_info))
+            .routes(routes!(get_session_key_usage))
+            .routes(routes!(simulate))
+            .routes(routes!(session_key_transfer))
+            .routes(routes!(authenticate))
+            .routes(routes!(get_recovery))
+            .routes(routes!(initiate_recovery))
+            .routes(routes!(finalize_recovery))
+            .routes(routes!(prune_expired_session_keys))
+            .routes(routes!(recover_with_backup_key))
             .split_for_parts();
 
         (router.with_state(store), api)
@@ -135,20 +416,103 @@ impl ContractHandler<WalletEvent> for Wallet {
         (status = OK, description = "Get json state of contract")
     )
 )]
-pub async fn get_state<S: Serialize + Clone + 'static>(
+pub async fn get_state<S: Serialize + Send + Sync + 'static>(
     State(state): State<ContractHandlerStore<S>>,
+) -> Result<impl IntoResponse, AppError> {
+    let store = state.read_owned().await;
+    let json = blocking(move || {
+        let state = store.state.as_ref().ok_or(AppError(
+            StatusCode::NOT_FOUND,
+            anyhow!("No state found for contract '{}'", store.contract_name),
+        ))?;
+        Ok(serde_json::to_vec(state).context("encoding contract state")?)
+    })
+    .await?;
+    Ok((
+        [(axum::http::header::CONTENT_TYPE, "application/json")],
+        json,
+    ))
+}
+
+/// Runs `f` on the blocking pool. Encoding or copying the whole state takes long enough on a
+/// large tree to stall the REST and WebSocket tasks sharing the runtime.
+async fn blocking<T: Send + 'static>(
+    f: impl FnOnce() -> Result<T, AppError> + Send + 'static,
+) -> Result<T, AppError> {
+    tokio::task::spawn_blocking(f).await.map_err(|e| {
+        AppError(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            anyhow!("blocking task failed: {e}"),
+        )
+    })?
+}
+
+#[utoipa::path(
+    get,
+    path = "/dump",
+    tag = "Contract",
+    responses(
+        (status = OK, description = "Borsh-encoded state of the contract, restorable with Wallet::restore"),
+        (status = NOT_FOUND, description = "Contract state not found")
+    )
+)]
+pub async fn dump(
+    State(state): State<ContractHandlerStore<Wallet>>,
+) -> Result<impl IntoResponse, AppError> {
+    let store = state.read_owned().await;
+    let dump = blocking(move || {
+        let wallet = store.state.as_ref().ok_or(AppError(
+            StatusCode::NOT_FOUND,
+            anyhow!("No state found for contract '{}'", store.contract_name),
+        ))?;
+        Ok(borsh::to_vec(wallet).context("encoding wallet state")?)
+    })
+    .await?;
+    Ok((
+        [(axum::http::header::CONTENT_TYPE, "application/octet-stream")],
+        dump,
+    ))
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct CommitmentResponse {
+    /// Hex-encoded state commitment, comparable with the contract state reported by the node.
+    pub commitment: String,
+}
+
+#[utoipa::path(
+    get,
+    path = "/commitment",
+    tag = "Contract",
+    responses(
+        (status = OK, description = "State commitment of the indexed wallet", body = CommitmentResponse),
+        (status = NOT_FOUND, description = "Contract state not found")
+    )
+)]
+pub async fn get_commitment(
+    State(state): State<ContractHandlerStore<Wallet>>,
 ) -> Result<impl IntoResponse, AppError> {
     let store = state.read().await;
-    store.state.clone().map(Json).ok_or(AppError(
+    let wallet = store.state.as_ref().ok_or(AppError(
         StatusCode::NOT_FOUND,
         anyhow!("No state found for contract '{}'", store.contract_name),
-    ))
+    ))?;
+    Ok(Json(CommitmentResponse {
+        commitment: hex::encode(&wallet.get_state_commitment().0),
+    }))
 }
 
 #[derive(Serialize, ToSchema)]
 struct ApiSessionKey {
     key: String,
     expiration_date: u128,
+    /// Contracts the key may send blobs to, any if unset.
+    whitelist: Option<Vec<sdk::ContractName>>,
+    /// Uses left, unlimited if unset.
+    remaining_uses: Option<u32>,
+    /// Most tokens a transaction signed with the key may move, unlimited if unset.
+    max_value_per_tx: Option<u128>,
+    key_type: SessionKeyType,
 }
 
 #[derive(Serialize, ToSchema)]
@@ -158,6 +522,8 @@ struct ApiAccountInfo {
     session_keys: Vec<ApiSessionKey>,
     nonce: u128,
     salt: String,
+    /// Whether session keys and the auth method are blocked, but for unfreezing.
+    frozen: bool,
 }
 
 #[utoipa::path(
@@ -177,24 +543,20 @@ pub async fn get_account_info(
     State(state): State<ContractHandlerStore<Wallet>>,
 ) -> Result<impl IntoResponse, AppError> {
     let store = state.read().await;
-    let state = store.state.clone().ok_or(AppError(
+    let state = store.state.as_ref().ok_or(AppError(
         StatusCode::NOT_FOUND,
         anyhow!("Contract '{}' not found", store.contract_name),
     ))?;
 
-    let account_info = state.get(&account);
-    let salt = state.get_salt(&account);
-
-    let (account_info, salt) = match (account_info, salt) {
-        (Ok(info), Ok(salt)) => (info, salt),
-        (Err(e), _) | (_, Err(e)) => {
-            tracing::debug!("Error retrieving account info or salt: {}", e);
-            return Err(AppError(
-                StatusCode::NOT_FOUND,
-                anyhow!("Account '{account}' not found"),
-            ));
-        }
-    };
+    let account_info = state.get(&account).map_err(|e| {
+        tracing::debug!("Error retrieving account info: {}", e);
+        AppError(
+            StatusCode::NOT_FOUND,
+            anyhow!("Account '{account}' not found"),
+        )
+    })?;
+    // Empty once erased at the owner's request.
+    let salt = state.get_salt(&account).unwrap_or_default();
 
     let session_keys = account_info
         .session_keys
@@ -202,6 +564,10 @@ pub async fn get_account_info(
         .map(|sk| ApiSessionKey {
             key: sk.public_key.clone(),
             expiration_date: sk.expiration_date.0,
+            whitelist: sk.whitelist.clone(),
+            remaining_uses: sk.remaining_uses,
+            max_value_per_tx: sk.max_value_per_tx,
+            key_type: sk.key_type,
         })
         .collect();
 
@@ -211,5 +577,715 @@ pub async fn get_account_info(
         session_keys,
         nonce: account_info.nonce,
         salt,
+        frozen: account_info.frozen,
+    }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/account/{account}/session_keys/{key}/usage",
+    tag = "Contract",
+    responses(
+        (status = OK, description = "Last 1000 settled uses of the session key, oldest first. They are kept across restarts only when the state journal is configured", body = Vec<SessionKeyUsage>),
+        (status = NOT_FOUND, description = "Account not found")
+    ),
+    params(
+        ("account" = String, Path, description = "The account identity"),
+        ("key" = String, Path, description = "Hex-encoded public key of the session key")
+    )
+)]
+pub async fn get_session_key_usage(
+    Path((account, key)): Path<(String, String)>,
+    State(state): State<ContractHandlerStore<Wallet>>,
+) -> Result<impl IntoResponse, AppError> {
+    let store = state.read().await;
+    let wallet = store.state.as_ref().ok_or(AppError(
+        StatusCode::NOT_FOUND,
+        anyhow!("Contract '{}' not found", store.contract_name),
+    ))?;
+    // Uses of removed keys are still listed, the account must exist.
+    wallet
+        .get(&account)
+        .map_err(|e| AppError(StatusCode::NOT_FOUND, e))?;
+
+    let usage = SESSION_KEY_USAGE
+        .lock()
+        .map_err(|_| {
+            AppError(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                anyhow!("Session key usage lock poisoned"),
+            )
+        })?
+        .get(&(store.contract_name.0.clone(), account, key.to_lowercase()))
+        .map(|uses| uses.iter().cloned().collect::<Vec<_>>())
+        .unwrap_or_default();
+    Ok(Json(usage))
+}
+
+#[derive(Serialize, ToSchema)]
+struct SimulatedBlob {
+    index: usize,
+    success: bool,
+    program_outputs: String,
+}
+
+#[derive(Serialize, ToSchema)]
+struct SimulationResult {
+    success: bool,
+    blobs: Vec<SimulatedBlob>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/simulate",
+    tag = "Contract",
+    responses(
+        (status = OK, description = "Execute the wallet blobs of a transaction against the current state, without proving or submitting it", body = SimulationResult),
+        (status = NOT_FOUND, description = "Contract state not found")
+    )
+)]
+pub async fn simulate(
+    State(state): State<ContractHandlerStore<Wallet>>,
+    Json(tx): Json<sdk::BlobTransaction>,
+) -> Result<impl IntoResponse, AppError> {
+    // Session keys are checked against the block timestamp, use the current time instead.
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or_default();
+    let tx_ctx = sdk::TxContext {
+        timestamp: sdk::TimestampMs(timestamp),
+        ..Default::default()
+    };
+    let tx_hash = tx.hashed();
+
+    let store = state.read_owned().await;
+    let blobs: Vec<SimulatedBlob> = blocking(move || {
+        let mut wallet = store.state.clone().ok_or(AppError(
+            StatusCode::NOT_FOUND,
+            anyhow!("Contract '{}' not found", store.contract_name),
+        ))?;
+        let contract_name = store.contract_name.clone();
+        drop(store);
+
+        Ok(tx
+            .blobs
+            .iter()
+            .enumerate()
+            .filter(|(_, blob)| blob.contract_name == contract_name)
+            .map(|(index, _)| {
+                let calldata = sdk::Calldata {
+                    identity: tx.identity.clone(),
+                    index: sdk::BlobIndex(index),
+                    blobs: tx.blobs.clone().into(),
+                    tx_blob_count: tx.blobs.len(),
+                    tx_hash: tx_hash.clone(),
+                    tx_ctx: Some(tx_ctx.clone()),
+                    private_input: vec![],
+                };
+                match wallet.handle(&calldata) {
+                    Ok(output) => SimulatedBlob {
+                        index,
+                        success: output.success,
+                        program_outputs: String::from_utf8_lossy(&output.program_outputs)
+                            .to_string(),
+                    },
+                    Err(e) => SimulatedBlob {
+                        index,
+                        success: false,
+                        program_outputs: format!("Error: {e:#}"),
+                    },
+                }
+            })
+            .collect())
+    })
+    .await?;
+
+    Ok(Json(SimulationResult {
+        success: blobs.iter().all(|blob| blob.success),
+        blobs,
+    }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/session_key_transfer",
+    tag = "Contract",
+    request_body = SessionKeyTransfer,
+    responses(
+        (status = OK, description = "Transaction transferring tokens with a session key, ready to be sent to the node"),
+        (status = BAD_REQUEST, description = "The session key cannot authorize this transfer")
+    )
+)]
+pub async fn session_key_transfer(
+    State(state): State<ContractHandlerStore<Wallet>>,
+    Json(transfer): Json<SessionKeyTransfer>,
+) -> Result<impl IntoResponse, AppError> {
+    let store = state.read().await;
+    let wallet = store.state.as_ref().ok_or(AppError(
+        StatusCode::NOT_FOUND,
+        anyhow!("Contract '{}' not found", store.contract_name),
+    ))?;
+
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or_default();
+    wallet
+        .check_session_key(
+            &transfer.account,
+            &transfer.public_key,
+            &[sdk::ContractName(transfer.token.clone())],
+            sdk::hyli_model_utils::TimestampMs(now),
+        )
+        .map_err(|e| AppError(StatusCode::BAD_REQUEST, e))?;
+
+    let tx = transfer
+        .into_transaction(&store.contract_name)
+        .map_err(|e| AppError(StatusCode::BAD_REQUEST, e))?;
+    Ok(Json(tx))
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct AuthenticateRequest {
+    /// Wallet account name, without the `@wallet` suffix.
+    account: String,
+    /// Blobs of the transaction to authenticate.
+    #[schema(value_type = Vec<Object>)]
+    blobs: Vec<sdk::Blob>,
+    /// Hex-encoded public key of the session key to use. Without one, the account's own auth
+    /// method is used.
+    session_key: Option<String>,
+}
+
+/// What the caller still has to add to the transaction before sending it.
+#[derive(Serialize, ToSchema)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum AuthRequirement {
+    /// A secp256k1 blob signing `message`, inserted at `blob_index`.
+    Secp256k1 { message: String, blob_index: usize },
+    /// An Ethereum personal_sign secp256k1 blob of `message`, inserted at `blob_index`.
+    Ethereum { message: String, blob_index: usize },
+    /// A `check_secret` blob proving knowledge of the password.
+    Password,
+    /// A `check_jwt` blob bound to the returned nonce.
+    Jwt,
+    /// A `WebAuthnAssertion` wallet blob of a passkey assertion of `challenge`, base64url-encoded
+    /// as in the client data.
+    WebAuthn { challenge: String },
+    /// Everything each factor of a multi-factor auth method requires.
+    Multi {
+        #[schema(no_recursion)]
+        factors: Vec<AuthRequirement>,
+    },
+}
+
+/// What `auth_method` requires to authenticate `account` at `nonce`, `None` when uninitialized.
+fn auth_requirement(
+    auth_method: &AuthMethod,
+    account: &str,
+    identity: &str,
+    nonce: u128,
+) -> Option<AuthRequirement> {
+    Some(match auth_method {
+        AuthMethod::HyliApp { .. } => AuthRequirement::Secp256k1 {
+            message: format!("{identity}:{nonce}:hyliapp"),
+            blob_index: 1,
+        },
+        AuthMethod::Ethereum { .. } => AuthRequirement::Ethereum {
+            message: format!("Sign in to Hyli as {identity} with nonce {nonce}"),
+            blob_index: 1,
+        },
+        AuthMethod::Password { .. } => AuthRequirement::Password,
+        AuthMethod::Jwt { .. } => AuthRequirement::Jwt,
+        AuthMethod::WebAuthn { .. } => AuthRequirement::WebAuthn {
+            challenge: URL_SAFE_NO_PAD.encode(webauthn_challenge(account, nonce)),
+        },
+        AuthMethod::Multi(factors) if !factors.is_empty() => AuthRequirement::Multi {
+            factors: factors
+                .iter()
+                .map(|factor| auth_requirement(factor, account, identity, nonce))
+                .collect::<Option<_>>()?,
+        },
+        AuthMethod::Multi(_) | AuthMethod::Uninitialized => return None,
+    })
+}
+
+#[derive(Serialize, ToSchema)]
+struct AuthenticatedTransaction {
+    identity: String,
+    nonce: u128,
+    /// The wallet blob first, followed by the given blobs.
+    #[schema(value_type = Vec<Object>)]
+    blobs: Vec<sdk::Blob>,
+    auth: AuthRequirement,
+}
+
+#[utoipa::path(
+    post,
+    path = "/authenticate",
+    tag = "Contract",
+    request_body = AuthenticateRequest,
+    responses(
+        (status = OK, description = "Transaction with the wallet VerifyIdentity or UseSessionKey blob and a fresh nonce", body = AuthenticatedTransaction),
+        (status = BAD_REQUEST, description = "The account or session key cannot authenticate this transaction")
+    )
+)]
+pub async fn authenticate(
+    State(state): State<ContractHandlerStore<Wallet>>,
+    Json(request): Json<AuthenticateRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    let store = state.read().await;
+    let wallet = store.state.as_ref().ok_or(AppError(
+        StatusCode::NOT_FOUND,
+        anyhow!("Contract '{}' not found", store.contract_name),
+    ))?;
+    let account_info = wallet
+        .get(&request.account)
+        .map_err(|e| AppError(StatusCode::BAD_REQUEST, e))?;
+
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or_default();
+    // Nonces must increase, and the frontend uses the current time.
+    let nonce = now.max(account_info.nonce + 1);
+    let identity = format!("{}@{}", request.account, store.contract_name.0);
+
+    let (action, auth) = match request.session_key {
+        Some(session_key) => {
+            let contracts: Vec<sdk::ContractName> = request
+                .blobs
+                .iter()
+                .map(|blob| blob.contract_name.clone())
+                .filter(|contract| contract.0 != "secp256k1")
+                .collect();
+            wallet
+                .check_session_key(
+                    &request.account,
+                    &session_key,
+                    &contracts,
+                    sdk::hyli_model_utils::TimestampMs(now),
+                )
+                .map_err(|e| AppError(StatusCode::BAD_REQUEST, e))?;
+            (
+                WalletAction::UseSessionKey {
+                    account: request.account,
+                    nonce,
+                },
+                AuthRequirement::Secp256k1 {
+                    message: nonce.to_string(),
+                    blob_index: 1,
+                },
+            )
+        }
+        None => {
+            if account_info.frozen {
+                return Err(AppError(
+                    StatusCode::BAD_REQUEST,
+                    anyhow!("Account '{}' is frozen", request.account),
+                ));
+            }
+            let auth = auth_requirement(
+                &account_info.auth_method,
+                &request.account,
+                &identity,
+                nonce,
+            )
+            .ok_or_else(|| {
+                AppError(
+                    StatusCode::BAD_REQUEST,
+                    anyhow!("Account '{}' is not initialized", request.account),
+                )
+            })?;
+            (
+                WalletAction::VerifyIdentity {
+                    account: request.account,
+                    nonce,
+                },
+                auth,
+            )
+        }
+    };
+
+    let mut blobs = vec![action.as_blob(store.contract_name.clone())];
+    blobs.extend(request.blobs);
+
+    Ok(Json(AuthenticatedTransaction {
+        identity,
+        nonce,
+        blobs,
+        auth,
     }))
 }
+
+#[derive(Serialize, ToSchema)]
+struct RecoveryStatus {
+    account: String,
+    /// A recovery email is registered, so a JWT for it can initiate a recovery.
+    email_recovery: bool,
+    pending: Option<PendingRecovery>,
+    /// The pending recovery can be finalized now.
+    executable: bool,
+    /// A backup key is registered and can reset the auth method right away.
+    backup_key: bool,
+}
+
+#[utoipa::path(
+    get,
+    path = "/recovery/{account}",
+    tag = "Contract",
+    responses(
+        (status = OK, description = "Recovery settings and pending recovery of the account", body = RecoveryStatus),
+        (status = NOT_FOUND, description = "Account not found")
+    ),
+    params(
+        ("account" = String, Path, description = "The account identity")
+    )
+)]
+pub async fn get_recovery(
+    Path(account): Path<String>,
+    State(state): State<ContractHandlerStore<Wallet>>,
+) -> Result<impl IntoResponse, AppError> {
+    let store = state.read().await;
+    let wallet = store.state.as_ref().ok_or(AppError(
+        StatusCode::NOT_FOUND,
+        anyhow!("Contract '{}' not found", store.contract_name),
+    ))?;
+    let account_info = wallet
+        .get(&account)
+        .map_err(|e| AppError(StatusCode::NOT_FOUND, e))?;
+
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or_default();
+    let executable = account_info
+        .recovery
+        .pending
+        .as_ref()
+        .is_some_and(|pending| {
+            wallet.time_policy().is_expired(
+                &pending.executable_at,
+                &sdk::hyli_model_utils::TimestampMs(now),
+            )
+        });
+    Ok(Json(RecoveryStatus {
+        account,
+        email_recovery: account_info.recovery.email_hash.is_some(),
+        pending: account_info.recovery.pending,
+        executable,
+        backup_key: account_info.recovery.backup_key.is_some(),
+    }))
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct InitiateRecoveryRequest {
+    /// Wallet account name, without the `@wallet` suffix.
+    account: String,
+    /// Auth method to switch to once the recovery is finalized.
+    auth_method: AuthMethod,
+}
+
+#[utoipa::path(
+    post,
+    path = "/recovery/initiate",
+    tag = "Contract",
+    request_body = InitiateRecoveryRequest,
+    responses(
+        (status = OK, description = "Transaction with the wallet InitiateRecovery blob and a fresh nonce, to complete with a check_jwt blob of the recovery email", body = AuthenticatedTransaction),
+        (status = BAD_REQUEST, description = "The account cannot be recovered by email")
+    )
+)]
+pub async fn initiate_recovery(
+    State(state): State<ContractHandlerStore<Wallet>>,
+    Json(request): Json<InitiateRecoveryRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    let store = state.read().await;
+    let wallet = store.state.as_ref().ok_or(AppError(
+        StatusCode::NOT_FOUND,
+        anyhow!("Contract '{}' not found", store.contract_name),
+    ))?;
+    let account_info = wallet
+        .get(&request.account)
+        .map_err(|e| AppError(StatusCode::BAD_REQUEST, e))?;
+    if account_info.recovery.email_hash.is_none() {
+        return Err(AppError(
+            StatusCode::BAD_REQUEST,
+            anyhow!("Account '{}' has no recovery email", request.account),
+        ));
+    }
+    if account_info.recovery.pending.is_some() {
+        return Err(AppError(
+            StatusCode::BAD_REQUEST,
+            anyhow!("A recovery of '{}' is already pending", request.account),
+        ));
+    }
+
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or_default();
+    let nonce = now.max(account_info.nonce + 1);
+    let identity = format!("{}@{}", request.account, store.contract_name.0);
+    let action = WalletAction::InitiateRecovery {
+        account: request.account,
+        auth_method: request.auth_method,
+        nonce,
+    };
+
+    Ok(Json(AuthenticatedTransaction {
+        identity,
+        nonce,
+        blobs: vec![action.as_blob(store.contract_name.clone())],
+        auth: AuthRequirement::Jwt,
+    }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/recovery/{account}/finalize",
+    tag = "Contract",
+    responses(
+        (status = OK, description = "Transaction finalizing the pending recovery, ready to be sent to the node"),
+        (status = BAD_REQUEST, description = "No recovery of the account can be finalized yet")
+    ),
+    params(
+        ("account" = String, Path, description = "The account identity")
+    )
+)]
+pub async fn finalize_recovery(
+    Path(account): Path<String>,
+    State(state): State<ContractHandlerStore<Wallet>>,
+) -> Result<impl IntoResponse, AppError> {
+    let store = state.read().await;
+    let wallet = store.state.as_ref().ok_or(AppError(
+        StatusCode::NOT_FOUND,
+        anyhow!("Contract '{}' not found", store.contract_name),
+    ))?;
+    let account_info = wallet
+        .get(&account)
+        .map_err(|e| AppError(StatusCode::BAD_REQUEST, e))?;
+    let Some(pending) = account_info.recovery.pending else {
+        return Err(AppError(
+            StatusCode::BAD_REQUEST,
+            anyhow!("No pending recovery for '{account}'"),
+        ));
+    };
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or_default();
+    if !wallet.time_policy().is_expired(
+        &pending.executable_at,
+        &sdk::hyli_model_utils::TimestampMs(now),
+    ) {
+        return Err(AppError(
+            StatusCode::BAD_REQUEST,
+            anyhow!(
+                "Recovery of '{account}' not executable before {}",
+                pending.executable_at.0
+            ),
+        ));
+    }
+
+    let identity = sdk::Identity::new(format!("{account}@{}", store.contract_name.0));
+    let action = WalletAction::FinalizeRecovery { account };
+    Ok(Json(sdk::BlobTransaction::new(
+        identity,
+        vec![action.as_blob(store.contract_name.clone())],
+    )))
+}
+
+#[utoipa::path(
+    get,
+    path = "/session_keys/expired",
+    tag = "Contract",
+    responses(
+        (status = OK, description = "One transaction per account holding expired session keys, pruning them, ready to be sent to the node")
+    )
+)]
+pub async fn prune_expired_session_keys(
+    State(state): State<ContractHandlerStore<Wallet>>,
+) -> Result<impl IntoResponse, AppError> {
+    let store = state.read_owned().await;
+    let txs = blocking(move || {
+        let wallet = store.state.as_ref().ok_or(AppError(
+            StatusCode::NOT_FOUND,
+            anyhow!("Contract '{}' not found", store.contract_name),
+        ))?;
+        let now = sdk::hyli_model_utils::TimestampMs(
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_millis())
+                .unwrap_or_default(),
+        );
+        let time_policy = wallet.time_policy();
+        Ok(wallet
+            .iter_accounts()
+            .filter(|account| {
+                account
+                    .session_keys
+                    .iter()
+                    .any(|sk| time_policy.is_expired(&sk.expiration_date, &now))
+            })
+            .map(|account| {
+                let action = WalletAction::PruneSessionKeys {
+                    account: account.identity.clone(),
+                };
+                sdk::BlobTransaction::new(
+                    sdk::Identity::new(format!("{}@{}", account.identity, store.contract_name.0)),
+                    vec![action.as_blob(store.contract_name.clone())],
+                )
+            })
+            .collect::<Vec<_>>())
+    })
+    .await?;
+    Ok(Json(txs))
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct BackupKeyRecoveryRequest {
+    /// Wallet account name, without the `@wallet` suffix.
+    account: String,
+    /// Auth method replacing the current one.
+    auth_method: AuthMethod,
+}
+
+#[utoipa::path(
+    post,
+    path = "/recovery/backup_key",
+    tag = "Contract",
+    request_body = BackupKeyRecoveryRequest,
+    responses(
+        (status = OK, description = "Transaction with the wallet RecoverWithBackupKey blob and a fresh nonce, to complete with a secp256k1 blob signed with the backup key", body = AuthenticatedTransaction),
+        (status = BAD_REQUEST, description = "The account has no backup key")
+    )
+)]
+pub async fn recover_with_backup_key(
+    State(state): State<ContractHandlerStore<Wallet>>,
+    Json(request): Json<BackupKeyRecoveryRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    let store = state.read().await;
+    let wallet = store.state.as_ref().ok_or(AppError(
+        StatusCode::NOT_FOUND,
+        anyhow!("Contract '{}' not found", store.contract_name),
+    ))?;
+    let account_info = wallet
+        .get(&request.account)
+        .map_err(|e| AppError(StatusCode::BAD_REQUEST, e))?;
+    if account_info.recovery.backup_key.is_none() {
+        return Err(AppError(
+            StatusCode::BAD_REQUEST,
+            anyhow!("Account '{}' has no backup key", request.account),
+        ));
+    }
+
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or_default();
+    let nonce = now.max(account_info.nonce + 1);
+    let identity = format!("{}@{}", request.account, store.contract_name.0);
+    let message = backup_key_recovery_data(&request.account, &request.auth_method, nonce);
+    let action = WalletAction::RecoverWithBackupKey {
+        account: request.account,
+        auth_method: request.auth_method,
+        nonce,
+    };
+
+    Ok(Json(AuthenticatedTransaction {
+        identity,
+        nonce,
+        blobs: vec![action.as_blob(store.contract_name.clone())],
+        auth: AuthRequirement::Secp256k1 {
+            message,
+            blob_index: 1,
+        },
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{Signer, SigningKey};
+    use sdk::{Blob, BlobIndex, Calldata, ContractName, IndexedBlobs, TimestampMs, TxHash};
+
+    #[test]
+    fn test_record_session_key_usage() {
+        let signing_key = SigningKey::from_bytes(&[7; 32]);
+        let public_key = hex::encode(signing_key.verifying_key().as_bytes());
+        let calldata = |i: usize| {
+            let blobs = vec![
+                WalletAction::UseSessionKey {
+                    account: "bob".to_string(),
+                    nonce: 1,
+                }
+                .as_blob(ContractName::new("usage_wallet")),
+                WalletAction::Ed25519Signature {
+                    account: "bob".to_string(),
+                    public_key: public_key.clone(),
+                    signature: signing_key
+                        .sign(ed25519_session_key_message("bob", 1).as_bytes())
+                        .to_bytes()
+                        .to_vec(),
+                }
+                .as_blob(ContractName::new("usage_wallet")),
+                Blob {
+                    contract_name: ContractName::new("oranj"),
+                    data: sdk::BlobData(vec![]),
+                },
+            ];
+            Calldata {
+                identity: "bob@usage_wallet".into(),
+                tx_blob_count: blobs.len(),
+                blobs: IndexedBlobs::from(blobs),
+                index: BlobIndex(0),
+                tx_hash: TxHash(format!("tx{i}")),
+                tx_ctx: Some(sdk::TxContext {
+                    timestamp: TimestampMs(i as u128),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }
+        };
+        let usage = || {
+            SESSION_KEY_USAGE
+                .lock()
+                .unwrap()
+                .get(&(
+                    "usage_wallet".to_string(),
+                    "bob".to_string(),
+                    public_key.clone(),
+                ))
+                .cloned()
+                .unwrap_or_default()
+        };
+
+        // Signed for another nonce, nothing is recorded.
+        assert!(
+            record_session_key_usage("usage_wallet", &calldata(0), "bob".to_string(), 2).is_err()
+        );
+        assert!(usage().is_empty());
+
+        record_session_key_usage("usage_wallet", &calldata(0), "bob".to_string(), 1).unwrap();
+        let uses = usage();
+        assert_eq!(uses.len(), 1);
+        assert_eq!(uses[0].tx_hash, "tx0");
+        assert_eq!(uses[0].timestamp, 0);
+        // Neither the wallet blob nor the signature blob are authorized by the key.
+        assert_eq!(uses[0].contracts, vec!["oranj".to_string()]);
+
+        // Past the limit, the oldest uses are dropped first.
+        for i in 1..=SESSION_KEY_USAGE_LIMIT {
+            record_session_key_usage("usage_wallet", &calldata(i), "bob".to_string(), 1).unwrap();
+        }
+        let uses = usage();
+        assert_eq!(uses.len(), SESSION_KEY_USAGE_LIMIT);
+        assert_eq!(uses.front().unwrap().tx_hash, "tx1");
+        assert_eq!(
+            uses.back().unwrap().tx_hash,
+            format!("tx{SESSION_KEY_USAGE_LIMIT}")
+        );
+    }
+}
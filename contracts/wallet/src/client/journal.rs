@@ -0,0 +1,362 @@
+//! Incremental persistence of the indexed wallet state.
+//!
+//! Each settled transaction appends the accounts it changed to a log, so a checkpoint costs
+//! O(changes) instead of a borsh rewrite of every account. Once the log holds `compact_after`
+//! entries, the full state is written to a snapshot and the log starts over. On startup the
+//! indexer rebuilds its state from the snapshot and the log.
+//!
+//! The settled uses of session keys, kept outside of the wallet state, have a log of their own
+//! per contract, trimmed on startup.
+//!
+//! Journaling is off until [`configure`] is called, once per process.
+
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    fs::{self, File, OpenOptions},
+    io::{BufReader, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::{Mutex, OnceLock},
+};
+
+use anyhow::{anyhow, Context, Result};
+use borsh::{BorshDeserialize, BorshSerialize};
+use sdk::tracing;
+
+use crate::{
+    client::{indexer::SessionKeyUsage, mapped::MappedWallet, tx_executor_handler::Wallet},
+    AccountInfo, InviteCodePubKey, TimePolicy,
+};
+
+/// A change to the wallet state, as recorded in the log.
+#[derive(Debug, Clone, BorshSerialize, BorshDeserialize)]
+pub enum JournalEntry {
+    /// New value of an account, keyed by its identity.
+    Account(AccountInfo),
+    Salt {
+        account: String,
+        salt: String,
+    },
+    InviteCodePublicKey(InviteCodePubKey),
+    TimePolicy(TimePolicy),
+}
+
+/// A settled use of a session key, as recorded in the usage log.
+#[derive(Debug, Clone, BorshSerialize, BorshDeserialize)]
+pub(crate) struct SessionKeyUse {
+    pub account: String,
+    /// Hex-encoded public key of the session key.
+    pub public_key: String,
+    pub usage: SessionKeyUsage,
+}
+
+#[derive(Debug)]
+struct Journals {
+    directory: PathBuf,
+    compact_after: usize,
+    open: Mutex<HashMap<String, StateJournal>>,
+}
+
+static JOURNALS: OnceLock<Journals> = OnceLock::new();
+
+/// Journals the state of every wallet contract indexed by this process under `directory`,
+/// compacting each log after `compact_after` entries.
+pub fn configure(directory: PathBuf, compact_after: usize) -> Result<()> {
+    fs::create_dir_all(&directory)
+        .with_context(|| format!("creating journal directory {}", directory.display()))?;
+    JOURNALS
+        .set(Journals {
+            directory,
+            compact_after: compact_after.max(1),
+            open: Mutex::new(HashMap::new()),
+        })
+        .map_err(|_| anyhow!("State journal already configured"))
+}
+
+/// Snapshot and log of a contract.
+#[derive(Debug)]
+struct StateJournal {
+    snapshot_path: PathBuf,
+    log: BufWriter<File>,
+    /// Entries in the log since the last snapshot.
+    entries: usize,
+    /// Whether the snapshot on disk is the state the log applies to. Unset until this process
+    /// restored or wrote it.
+    has_snapshot: bool,
+}
+
+impl StateJournal {
+    fn open(directory: &Path, contract_name: &str) -> Result<Self> {
+        let snapshot_path = directory.join(format!("{contract_name}.snapshot"));
+        let log_path = directory.join(format!("{contract_name}.log"));
+        let log = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&log_path)
+            .with_context(|| format!("opening state log {}", log_path.display()))?;
+        Ok(Self {
+            snapshot_path,
+            log: BufWriter::new(log),
+            entries: 0,
+            has_snapshot: false,
+        })
+    }
+
+    fn append(&mut self, entries: &[JournalEntry]) -> Result<()> {
+        for entry in entries {
+            write_entry(&mut self.log, entry)?;
+        }
+        self.log.flush().context("writing state log")?;
+        self.log.get_ref().sync_data()?;
+        self.entries += entries.len();
+        Ok(())
+    }
+
+    /// Writes `wallet` as the new snapshot and empties the log.
+    fn compact(&mut self, wallet: &Wallet) -> Result<()> {
+        let tmp = self.snapshot_path.with_extension("snapshot.tmp");
+        {
+            let mut file = BufWriter::new(File::create(&tmp)?);
+            borsh::to_writer(&mut file, wallet).context("encoding state snapshot")?;
+            file.flush()?;
+            file.get_ref().sync_all()?;
+        }
+        // Only drop the log once the snapshot holding its changes is in place.
+        fs::rename(&tmp, &self.snapshot_path).context("replacing state snapshot")?;
+        self.log
+            .get_ref()
+            .set_len(0)
+            .context("truncating state log")?;
+        self.entries = 0;
+        self.has_snapshot = true;
+        Ok(())
+    }
+}
+
+/// Writes `entry` to a log, prefixed with its length.
+fn write_entry<T: BorshSerialize>(log: &mut impl Write, entry: &T) -> Result<()> {
+    let bytes = borsh::to_vec(entry).context("encoding journal entry")?;
+    log.write_all(&(bytes.len() as u32).to_le_bytes())?;
+    log.write_all(&bytes)?;
+    Ok(())
+}
+
+/// Reads the entries of a log, dropping a last entry cut short by a crash.
+fn read_log<T: BorshDeserialize>(path: &Path) -> Result<Vec<T>> {
+    let mut file = match OpenOptions::new().read(true).write(true).open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e).with_context(|| format!("opening {}", path.display())),
+    };
+    let mut reader = BufReader::new(&mut file);
+    let mut entries = vec![];
+    let mut valid_len = 0u64;
+    loop {
+        let mut len = [0u8; 4];
+        match reader.read_exact(&mut len) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.into()),
+        }
+        let mut bytes = vec![0u8; u32::from_le_bytes(len) as usize];
+        match reader.read_exact(&mut bytes) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.into()),
+        }
+        let Ok(entry) = borsh::from_slice(&bytes) else {
+            break;
+        };
+        entries.push(entry);
+        valid_len += 4 + bytes.len() as u64;
+    }
+    drop(reader);
+
+    if file.seek(SeekFrom::End(0))? != valid_len {
+        tracing::warn!(
+            "Dropping the incomplete tail of state log {}",
+            path.display()
+        );
+        file.set_len(valid_len)?;
+    }
+    Ok(entries)
+}
+
+/// Rebuilds the journaled state of `contract_name`, `None` when nothing was journaled yet.
+pub fn restore(contract_name: &str) -> Result<Option<Wallet>> {
+    let Some(journals) = JOURNALS.get() else {
+        return Ok(None);
+    };
+    let snapshot_path = journals.directory.join(format!("{contract_name}.snapshot"));
+    if !snapshot_path.exists() {
+        return Ok(None);
+    }
+    let mut wallet = MappedWallet::open(&snapshot_path)?
+        .materialize()
+        .context("decoding state snapshot")?;
+
+    let entries: Vec<JournalEntry> =
+        read_log(&journals.directory.join(format!("{contract_name}.log")))?;
+    let count = entries.len();
+    wallet.apply_journal_entries(entries)?;
+
+    let mut journal = StateJournal::open(&journals.directory, contract_name)?;
+    journal.entries = count;
+    journal.has_snapshot = true;
+    journals
+        .open
+        .lock()
+        .map_err(|_| anyhow!("State journal lock poisoned"))?
+        .insert(contract_name.to_string(), journal);
+    Ok(Some(wallet))
+}
+
+/// Records the changes a transaction made to `wallet`, the state after it.
+pub(crate) fn record(contract_name: &str, wallet: &Wallet, entries: &[JournalEntry]) -> Result<()> {
+    let Some(journals) = JOURNALS.get() else {
+        return Ok(());
+    };
+    let mut open = journals
+        .open
+        .lock()
+        .map_err(|_| anyhow!("State journal lock poisoned"))?;
+    let journal = match open.entry(contract_name.to_string()) {
+        Entry::Occupied(e) => e.into_mut(),
+        Entry::Vacant(e) => e.insert(StateJournal::open(&journals.directory, contract_name)?),
+    };
+
+    // Without a snapshot of this state the log has nothing to apply to, start from the full
+    // state.
+    let res = if !journal.has_snapshot || journal.entries + entries.len() >= journals.compact_after
+    {
+        journal.compact(wallet)
+    } else {
+        journal.append(entries)
+    };
+    if res.is_err() {
+        // The log may miss these changes, the next record rewrites the snapshot.
+        journal.has_snapshot = false;
+    }
+    res
+}
+
+/// Replaces the snapshot of `contract_name` with `wallet` and empties its log, so data removed
+/// from the state outside of a transaction is no longer on disk.
+pub fn rewrite(contract_name: &str, wallet: &Wallet) -> Result<()> {
+    let Some(journals) = JOURNALS.get() else {
+        return Ok(());
+    };
+    let mut open = journals
+        .open
+        .lock()
+        .map_err(|_| anyhow!("State journal lock poisoned"))?;
+    let journal = match open.entry(contract_name.to_string()) {
+        Entry::Occupied(e) => e.into_mut(),
+        Entry::Vacant(e) => e.insert(StateJournal::open(&journals.directory, contract_name)?),
+    };
+    let res = journal.compact(wallet);
+    if res.is_err() {
+        journal.has_snapshot = false;
+    }
+    res
+}
+
+fn session_key_log_path(directory: &Path, contract_name: &str) -> PathBuf {
+    directory.join(format!("{contract_name}.session_keys.log"))
+}
+
+/// Appends a settled use of a session key to the usage log of `contract_name`.
+pub(crate) fn record_session_key_use(contract_name: &str, entry: &SessionKeyUse) -> Result<()> {
+    let Some(journals) = JOURNALS.get() else {
+        return Ok(());
+    };
+    let path = session_key_log_path(&journals.directory, contract_name);
+    let mut log = BufWriter::new(
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("opening session key usage log {}", path.display()))?,
+    );
+    write_entry(&mut log, entry)?;
+    log.flush().context("writing session key usage log")?;
+    log.get_ref().sync_data()?;
+    Ok(())
+}
+
+/// Uses of session keys recorded for `contract_name`, oldest first.
+pub(crate) fn restore_session_key_uses(contract_name: &str) -> Result<Vec<SessionKeyUse>> {
+    let Some(journals) = JOURNALS.get() else {
+        return Ok(vec![]);
+    };
+    read_log(&session_key_log_path(&journals.directory, contract_name))
+}
+
+/// Replaces the usage log of `contract_name` with `uses`, dropping the evicted ones from disk.
+pub(crate) fn rewrite_session_key_uses(contract_name: &str, uses: &[SessionKeyUse]) -> Result<()> {
+    let Some(journals) = JOURNALS.get() else {
+        return Ok(());
+    };
+    let path = session_key_log_path(&journals.directory, contract_name);
+    let tmp = path.with_extension("log.tmp");
+    {
+        let mut file = BufWriter::new(File::create(&tmp)?);
+        for entry in uses {
+            write_entry(&mut file, entry)?;
+        }
+        file.flush()?;
+        file.get_ref().sync_all()?;
+    }
+    fs::rename(&tmp, &path).context("replacing session key usage log")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AuthMethod, WalletAction};
+    use client_sdk::transaction_builder::TxExecutorHandler;
+    use sdk::{Blob, BlobIndex, Calldata, ContractName, IndexedBlobs};
+
+    #[test]
+    fn test_journal_entries_replay_changes() {
+        let password_hash = "test_hash".to_string().into_bytes();
+        let mut wallet = Wallet::new(&ContractName::new("test"), &None).unwrap();
+        let mut replayed = wallet.clone();
+
+        let action = WalletAction::RegisterIdentity {
+            account: "bob".to_string(),
+            nonce: 1,
+            salt: "bob_salt".to_string(),
+            auth_method: AuthMethod::Password {
+                hash: hex::encode(&password_hash),
+            },
+            invite_code: "test_invite_code".to_string(),
+        };
+        let ho = wallet
+            .handle(&Calldata {
+                blobs: IndexedBlobs::from(vec![
+                    action.clone().as_blob(ContractName::new("test")),
+                    Blob {
+                        contract_name: sdk::ContractName("check_secret".to_string()),
+                        data: sdk::BlobData(password_hash),
+                    },
+                ]),
+                index: BlobIndex(0),
+                ..Default::default()
+            })
+            .unwrap();
+        assert!(ho.success);
+
+        let entries = wallet.journal_entries(&action).unwrap();
+        // Entries survive the log's encoding.
+        let entries: Vec<JournalEntry> =
+            borsh::from_slice(&borsh::to_vec(&entries).unwrap()).unwrap();
+        for entry in entries {
+            replayed.apply_journal_entry(entry).unwrap();
+        }
+        assert_eq!(
+            replayed.get_state_commitment(),
+            wallet.get_state_commitment()
+        );
+        assert_eq!(replayed.get_salt(&"bob".to_string()).unwrap(), "bob_salt");
+    }
+}
@@ -0,0 +1,191 @@
+//! Password-encrypted export of an account's session keys and backup key, to move them to
+//! another device without provisioning new session keys with every dapp.
+//!
+//! The keystore is a JSON document: the secrets are borsh-encoded, encrypted with AES-256-GCM
+//! under a key derived from the password with scrypt, and bound to the account name.
+
+use aes_gcm::{
+    aead::{rand_core::RngCore, Aead, KeyInit, OsRng, Payload},
+    Aes256Gcm, Nonce,
+};
+use anyhow::{anyhow, bail, Context, Result};
+use borsh::{BorshDeserialize, BorshSerialize};
+use serde::{Deserialize, Serialize};
+
+/// Version of the keystore format, bumped on incompatible changes.
+pub const KEYSTORE_VERSION: u32 = 1;
+
+/// A session key with what's needed to keep using it after an import.
+#[derive(Debug, Clone, PartialEq, Eq, BorshSerialize, BorshDeserialize)]
+pub struct KeystoreSessionKey {
+    pub secret_key: [u8; 32],
+    pub expiration_date: u128,
+    pub whitelist: Option<Vec<String>>,
+}
+
+/// Secrets held by a keystore.
+#[derive(Debug, Clone, Default, PartialEq, Eq, BorshSerialize, BorshDeserialize)]
+pub struct KeystoreContents {
+    pub session_keys: Vec<KeystoreSessionKey>,
+    pub backup_key: Option<[u8; 32]>,
+}
+
+/// scrypt cost parameters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KdfParams {
+    pub log_n: u8,
+    pub r: u32,
+    pub p: u32,
+}
+
+impl Default for KdfParams {
+    fn default() -> Self {
+        Self {
+            log_n: 17,
+            r: 8,
+            p: 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "name", rename_all = "lowercase")]
+pub enum Kdf {
+    Scrypt {
+        #[serde(flatten)]
+        params: KdfParams,
+        /// Hex-encoded salt.
+        salt: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "name", rename_all = "kebab-case")]
+pub enum Cipher {
+    Aes256Gcm {
+        /// Hex-encoded 96-bit nonce.
+        nonce: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Keystore {
+    pub version: u32,
+    /// Account the keys belong to, authenticated along the ciphertext.
+    pub account: String,
+    pub kdf: Kdf,
+    pub cipher: Cipher,
+    /// Hex-encoded ciphertext, tag included.
+    pub ciphertext: String,
+}
+
+fn derive_key(password: &str, params: &KdfParams, salt: &[u8]) -> Result<[u8; 32]> {
+    let params = scrypt::Params::new(params.log_n, params.r, params.p, 32)
+        .map_err(|e| anyhow!("invalid scrypt parameters: {e}"))?;
+    let mut key = [0; 32];
+    scrypt::scrypt(password.as_bytes(), salt, &params, &mut key)
+        .map_err(|e| anyhow!("deriving keystore key: {e}"))?;
+    Ok(key)
+}
+
+/// Authenticated data of the ciphertext, so the account can't be swapped.
+fn associated_data(version: u32, account: &str) -> Vec<u8> {
+    format!("hyli-wallet-keystore:{version}:{account}").into_bytes()
+}
+
+impl Keystore {
+    pub fn encrypt(
+        account: &str,
+        contents: &KeystoreContents,
+        password: &str,
+        params: KdfParams,
+    ) -> Result<Self> {
+        let mut salt = [0u8; 32];
+        OsRng.fill_bytes(&mut salt);
+        let mut nonce = [0u8; 12];
+        OsRng.fill_bytes(&mut nonce);
+
+        let key = derive_key(password, &params, &salt)?;
+        let plaintext = borsh::to_vec(contents).context("encoding keystore contents")?;
+        let ciphertext = Aes256Gcm::new(&key.into())
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: &plaintext,
+                    aad: &associated_data(KEYSTORE_VERSION, account),
+                },
+            )
+            .map_err(|_| anyhow!("encrypting keystore"))?;
+
+        Ok(Self {
+            version: KEYSTORE_VERSION,
+            account: account.to_string(),
+            kdf: Kdf::Scrypt {
+                params,
+                salt: hex::encode(salt),
+            },
+            cipher: Cipher::Aes256Gcm {
+                nonce: hex::encode(nonce),
+            },
+            ciphertext: hex::encode(ciphertext),
+        })
+    }
+
+    pub fn decrypt(&self, password: &str) -> Result<KeystoreContents> {
+        if self.version != KEYSTORE_VERSION {
+            bail!("unsupported keystore version {}", self.version);
+        }
+        let Kdf::Scrypt { params, salt } = &self.kdf;
+        let Cipher::Aes256Gcm { nonce } = &self.cipher;
+
+        let salt = hex::decode(salt).context("invalid keystore salt")?;
+        let nonce: [u8; 12] = hex::decode(nonce)
+            .ok()
+            .and_then(|n| n.try_into().ok())
+            .context("invalid keystore nonce")?;
+        let ciphertext = hex::decode(&self.ciphertext).context("invalid keystore ciphertext")?;
+
+        let key = derive_key(password, params, &salt)?;
+        let plaintext = Aes256Gcm::new(&key.into())
+            .decrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: &ciphertext,
+                    aad: &associated_data(self.version, &self.account),
+                },
+            )
+            .map_err(|_| anyhow!("wrong password or corrupted keystore"))?;
+        borsh::from_slice(&plaintext).context("decoding keystore contents")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keystore_roundtrip() {
+        let contents = KeystoreContents {
+            session_keys: vec![KeystoreSessionKey {
+                secret_key: [3; 32],
+                expiration_date: 1_000,
+                whitelist: Some(vec!["oranj".to_string()]),
+            }],
+            backup_key: Some([4; 32]),
+        };
+        // Cheap parameters, the defaults take seconds.
+        let params = KdfParams {
+            log_n: 4,
+            r: 8,
+            p: 1,
+        };
+        let keystore = Keystore::encrypt("bob", &contents, "hunter2", params).unwrap();
+
+        assert_eq!(keystore.decrypt("hunter2").unwrap(), contents);
+        assert!(keystore.decrypt("hunter3").is_err());
+
+        let mut moved = keystore.clone();
+        moved.account = "alice".to_string();
+        assert!(moved.decrypt("hunter2").is_err());
+    }
+}
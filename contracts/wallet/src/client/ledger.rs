@@ -0,0 +1,165 @@
+//! APDUs of the Ledger Ethereum app, for wallets signing through a Ledger. The device is reached
+//! by a WebHID relay, typically a browser page, that forwards these commands and returns the
+//! raw responses.
+//!
+//! The Ethereum app only signs EIP-191 `personal_sign` digests, which is what
+//! [`AuthMethod::Ethereum`](crate::AuthMethod::Ethereum) checks.
+
+use anyhow::{anyhow, bail, Context, Result};
+use sdk::{verifiers::Secp256k1Blob, Identity};
+
+use crate::utils;
+
+/// First account of the standard Ethereum derivation.
+pub const DEFAULT_DERIVATION_PATH: &str = "m/44'/60'/0'/0/0";
+
+const CLA: u8 = 0xe0;
+const INS_GET_PUBLIC_KEY: u8 = 0x02;
+const INS_SIGN_PERSONAL_MESSAGE: u8 = 0x08;
+/// Largest APDU payload.
+const MAX_CHUNK: usize = 255;
+
+const SW_OK: u16 = 0x9000;
+const SW_DENIED: u16 = 0x6985;
+
+/// Parses a BIP-32 path such as `m/44'/60'/0'/0/0`.
+pub fn parse_derivation_path(path: &str) -> Result<Vec<u32>> {
+    let mut components = path.split('/');
+    if components.next() != Some("m") {
+        bail!("derivation path {path} must start with m/");
+    }
+    let indices = components
+        .map(|component| {
+            let (index, hardened) = match component.strip_suffix('\'') {
+                Some(index) => (index, true),
+                None => (component, false),
+            };
+            let index: u32 = index
+                .parse()
+                .with_context(|| format!("invalid derivation path component {component}"))?;
+            if index >= 0x8000_0000 {
+                bail!("derivation path component {component} out of range");
+            }
+            Ok(if hardened { index | 0x8000_0000 } else { index })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    if indices.is_empty() || indices.len() > 10 {
+        bail!("derivation path {path} must have between 1 and 10 components");
+    }
+    Ok(indices)
+}
+
+fn encode_path(path: &[u32]) -> Vec<u8> {
+    let mut data = vec![path.len() as u8];
+    for index in path {
+        data.extend_from_slice(&index.to_be_bytes());
+    }
+    data
+}
+
+fn apdu(ins: u8, p1: u8, data: &[u8]) -> Vec<u8> {
+    let mut apdu = vec![CLA, ins, p1, 0x00, data.len() as u8];
+    apdu.extend_from_slice(data);
+    apdu
+}
+
+/// Asks for the public key at `path`, without confirmation on the device.
+pub fn get_public_key_apdu(path: &[u32]) -> Vec<u8> {
+    apdu(INS_GET_PUBLIC_KEY, 0x00, &encode_path(path))
+}
+
+/// Asks the device to `personal_sign` `message` with the key at `path`, in as many APDUs as the
+/// message needs. Only the response to the last one carries the signature.
+pub fn sign_personal_message_apdus(path: &[u32], message: &[u8]) -> Vec<Vec<u8>> {
+    let mut first = encode_path(path);
+    first.extend_from_slice(&(message.len() as u32).to_be_bytes());
+    let first_len = (MAX_CHUNK - first.len()).min(message.len());
+    first.extend_from_slice(&message[..first_len]);
+
+    let mut apdus = vec![apdu(INS_SIGN_PERSONAL_MESSAGE, 0x00, &first)];
+    for chunk in message[first_len..].chunks(MAX_CHUNK) {
+        apdus.push(apdu(INS_SIGN_PERSONAL_MESSAGE, 0x80, chunk));
+    }
+    apdus
+}
+
+/// Splits the status word off a response, failing unless it's a success.
+fn response_data(response: &[u8]) -> Result<&[u8]> {
+    let Some((data, sw)) = response.split_last_chunk::<2>() else {
+        bail!("Ledger response too short");
+    };
+    match u16::from_be_bytes(*sw) {
+        SW_OK => Ok(data),
+        SW_DENIED => bail!("Rejected on the Ledger"),
+        sw => bail!("Ledger error {sw:#06x}"),
+    }
+}
+
+/// Whether a response means the user refused on the device.
+pub fn is_denied(response: &[u8]) -> bool {
+    response
+        .split_last_chunk::<2>()
+        .is_some_and(|(_, sw)| u16::from_be_bytes(*sw) == SW_DENIED)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LedgerPublicKey {
+    /// Compressed public key.
+    pub public_key: [u8; 33],
+    /// Ethereum address, lowercase hex without `0x`.
+    pub address: String,
+}
+
+/// Reads the answer to [`get_public_key_apdu`], checking the address the device derived.
+pub fn parse_public_key_response(response: &[u8]) -> Result<LedgerPublicKey> {
+    let data = response_data(response)?;
+    let (&key_len, rest) = data.split_first().context("empty Ledger response")?;
+    let key = rest
+        .get(..key_len as usize)
+        .context("truncated Ledger public key")?;
+    let public_key = secp256k1::PublicKey::from_slice(key)
+        .map_err(|e| anyhow!("invalid Ledger public key: {e}"))?;
+    let address = utils::ethereum_address_from_public_key(&public_key);
+
+    let rest = &rest[key_len as usize..];
+    if let Some((&address_len, rest)) = rest.split_first() {
+        let reported = rest
+            .get(..address_len as usize)
+            .context("truncated Ledger address")?;
+        if !String::from_utf8_lossy(reported).eq_ignore_ascii_case(&address) {
+            bail!("Ledger reported another address than its public key's");
+        }
+    }
+
+    Ok(LedgerPublicKey {
+        public_key: public_key.serialize(),
+        address,
+    })
+}
+
+/// Reads the answer to the last of [`sign_personal_message_apdus`] as a compact signature.
+pub fn parse_signature_response(response: &[u8]) -> Result<[u8; 64]> {
+    let data = response_data(response)?;
+    // v, then r and s.
+    let Some((_, rs)) = data.split_first() else {
+        bail!("empty Ledger signature");
+    };
+    rs.try_into()
+        .map_err(|_| anyhow!("Ledger signature has {} bytes, expected 64", rs.len()))
+}
+
+/// Native verifier blob for a Ledger `personal_sign` of `message`, as the Ethereum auth method
+/// expects it.
+pub fn secp256k1_blob(
+    identity: Identity,
+    message: &[u8],
+    public_key: [u8; 33],
+    signature: [u8; 64],
+) -> Secp256k1Blob {
+    Secp256k1Blob {
+        identity,
+        data: utils::ethereum_personal_sign_digest(message),
+        public_key,
+        signature,
+    }
+}
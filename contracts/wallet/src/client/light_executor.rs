@@ -5,8 +5,8 @@ use sdk::{BlobIndex, BlobTransaction, Calldata, Hashed, Identity, IndexedBlobs,
 use std::collections::HashMap;
 
 use crate::{
-    check_for_invite_code, client::tx_executor_handler::WalletConstructor, AccountInfo, AuthMethod,
-    WalletAction, DEFAULT_INVITE_CODE_PUBLIC_KEY,
+    check_invite_key_rotation, client::tx_executor_handler::WalletConstructor, AccountInfo,
+    AuthMethod, FailedAuth, Recovery, TimePolicy, WalletAction, DEFAULT_INVITE_CODE_PUBLIC_KEY,
 };
 
 #[derive(Debug, Clone, BorshSerialize, BorshDeserialize)]
@@ -14,6 +14,7 @@ pub struct LightWalletExecutor {
     pub accounts: HashMap<String, AccountInfo>,
     pub salts: HashMap<String, String>,
     pub invite_code_public_key: [u8; 33],
+    pub time_policy: TimePolicy,
 }
 
 impl Default for LightWalletExecutor {
@@ -22,6 +23,7 @@ impl Default for LightWalletExecutor {
             accounts: HashMap::new(),
             salts: HashMap::new(),
             invite_code_public_key: [0u8; 33],
+            time_policy: TimePolicy::default(),
         }
     }
 }
@@ -35,17 +37,25 @@ fn parse_raw_blob_from_tx(tx: &BlobTransaction, index: BlobIndex) -> Option<Wall
 }
 
 impl<'a> LightContractExecutor<'a, '_> for LightWalletExecutor {
-    type Scratchpad = (&'a BlobTransaction, Option<AccountInfo>);
+    /// The account touched by the blob and its state before the transaction, if it existed,
+    /// along with the time policy before the transaction.
+    type Scratchpad = (Option<(String, Option<AccountInfo>)>, TimePolicy);
     type ExtraData = ();
 
     fn prepare_for_tx(
         &mut self,
         tx: &'a BlobTransaction,
-        _index: BlobIndex,
+        index: BlobIndex,
         _tx_ctx: Option<&TxContext>,
         _extra_data: Self::ExtraData,
     ) -> Result<Self::Scratchpad> {
-        Ok((tx, self.accounts.get(&tx.identity.0).cloned()))
+        let account = parse_raw_blob_from_tx(tx, index)
+            .and_then(|action| action.account().cloned())
+            .map(|account| {
+                let account_info = self.accounts.get(&account).cloned();
+                (account, account_info)
+            });
+        Ok((account, self.time_policy))
     }
 
     fn handle_blob(
@@ -72,10 +82,16 @@ impl<'a> LightContractExecutor<'a, '_> for LightWalletExecutor {
             })
     }
 
-    fn on_failure(&mut self, scratchpad: Self::Scratchpad) -> Result<()> {
-        if let (blob_tx, Some(account_info)) = scratchpad {
-            self.accounts
-                .insert(blob_tx.identity.0.clone(), account_info);
+    fn on_failure(&mut self, (account, time_policy): Self::Scratchpad) -> Result<()> {
+        self.time_policy = time_policy;
+        match account {
+            Some((account, Some(account_info))) => {
+                self.accounts.insert(account, account_info);
+            }
+            Some((account, None)) => {
+                self.accounts.remove(&account);
+            }
+            None => {}
         }
         Ok(())
     }
@@ -91,6 +107,7 @@ impl LightWalletExecutor {
             invite_code_public_key: DEFAULT_INVITE_CODE_PUBLIC_KEY,
             accounts: HashMap::new(),
             salts: HashMap::new(),
+            time_policy: TimePolicy::default(),
         };
         if let Some(constructor_data) = constructor {
             this.invite_code_public_key = constructor_data.invite_code_public_key;
@@ -103,6 +120,10 @@ impl LightWalletExecutor {
                     },
                     session_keys: vec![],
                     nonce: 0,
+                    used_jwt_ids: vec![],
+                    failed_auth: FailedAuth::default(),
+                    recovery: Recovery::default(),
+                    frozen: false,
                 },
             );
             this.salts
@@ -119,13 +140,31 @@ impl LightWalletExecutor {
         tx_ctx: Option<&TxContext>,
         action: WalletAction,
     ) -> Result<String, String> {
-        let acc = match action.clone() {
-            WalletAction::RegisterIdentity { account, .. }
-            | WalletAction::VerifyIdentity { account, .. }
-            | WalletAction::UseSessionKey { account, .. }
-            | WalletAction::AddSessionKey { account, .. }
-            | WalletAction::RemoveSessionKey { account, .. } => account,
-            _ => unreachable!(),
+        if let WalletAction::UpdateInviteCodePublicKey {
+            invite_code_public_key,
+            smt_root,
+        } = action
+        {
+            let calldata = Calldata {
+                tx_hash: tx.hashed(),
+                identity: tx.identity.clone(),
+                blobs: IndexedBlobs::from(tx.blobs.clone()),
+                tx_blob_count: tx.blobs.len(),
+                index,
+                tx_ctx: tx_ctx.cloned(),
+                private_input: vec![],
+            };
+            check_invite_key_rotation(
+                &self.invite_code_public_key,
+                &invite_code_public_key,
+                &smt_root,
+                &calldata,
+            )?;
+            self.invite_code_public_key = invite_code_public_key;
+            return Ok("Updated public key".to_string());
+        }
+        let Some(acc) = action.account().cloned() else {
+            return Err("Batch has no actions".to_string());
         };
         let account_info = self
             .accounts
@@ -145,39 +184,23 @@ impl LightWalletExecutor {
             private_input: vec![],
         };
 
-        match action {
-            WalletAction::RegisterIdentity {
-                account,
-                nonce,
-                salt,
-                auth_method,
-                invite_code,
-            } => {
-                check_for_invite_code(
-                    &account,
-                    &invite_code,
-                    calldata,
-                    &self.invite_code_public_key,
-                )?;
-                let res =
-                    account_info.handle_registration(account.clone(), nonce, auth_method, calldata);
-                self.salts.insert(account, salt);
-                res
-            }
-            WalletAction::UseSessionKey { account, nonce } => {
-                account_info.handle_session_key_usage(account, nonce, calldata)
-            }
-            WalletAction::UpdateInviteCodePublicKey {
-                invite_code_public_key,
-                ..
-            } => {
-                if self.invite_code_public_key != DEFAULT_INVITE_CODE_PUBLIC_KEY {
-                    return Err("Invite code public key already set".to_string());
-                }
-                self.invite_code_public_key = invite_code_public_key;
-                Ok("Updated public key".to_string())
-            }
-            _ => account_info.handle_authenticated_action(action, calldata),
+        let new_time_policy = match &action {
+            WalletAction::UpdateTimePolicy { time_policy, .. } => Some(*time_policy),
+            _ => None,
+        };
+        let new_salt = action.new_salt().cloned();
+        let res = account_info.handle_action(
+            action,
+            calldata,
+            &self.invite_code_public_key,
+            &self.time_policy,
+        );
+        if let (Ok(_), Some(time_policy)) = (&res, new_time_policy) {
+            self.time_policy = time_policy;
+        }
+        if let (Ok(_), Some(salt)) = (&res, new_salt) {
+            self.salts.insert(acc, salt);
         }
+        res
     }
 }
@@ -0,0 +1,166 @@
+//! Read path for persisted wallet states (dumps and journal snapshots) that maps the file
+//! instead of decoding it up front.
+//!
+//! Opening a state only records where each account lies in the file, and accounts are decoded
+//! when they are looked up. A large state can be inspected at the cost of its index, and the
+//! full [`Wallet`] is only materialized, without copying the file, when asked for.
+
+use std::{collections::HashMap, fs::File, path::Path};
+
+use anyhow::{bail, Context, Result};
+use borsh::BorshDeserialize;
+use memmap2::Mmap;
+use sparse_merkle_tree::H256;
+
+use crate::{client::tx_executor_handler::Wallet, AccountInfo, InviteCodePubKey, TimePolicy};
+
+/// A persisted wallet state, mapped in memory.
+pub struct MappedWallet {
+    mmap: Mmap,
+    invite_code_public_key: InviteCodePubKey,
+    /// SMT key and byte range of each account, sorted by key.
+    accounts: Vec<(H256, usize, usize)>,
+    /// Offset of the salts, following the accounts.
+    salts_offset: usize,
+}
+
+impl MappedWallet {
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = File::open(path).with_context(|| format!("opening {}", path.display()))?;
+        // Safety: the file is only read, and persisted states are replaced by renaming a new
+        // file over them rather than written in place.
+        let mmap =
+            unsafe { Mmap::map(&file) }.with_context(|| format!("mapping {}", path.display()))?;
+
+        let mut data: &[u8] = &mmap;
+        let invite_code_public_key =
+            InviteCodePubKey::deserialize(&mut data).context("decoding invite code key")?;
+        let count = u32::deserialize(&mut data).context("decoding account count")?;
+
+        let mut accounts = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let start = mmap.len() - data.len();
+            // Decoded only to find where it ends, the account isn't kept.
+            let account = AccountInfo::deserialize(&mut data)
+                .with_context(|| format!("decoding account at offset {start}"))?;
+            accounts.push((
+                AccountInfo::compute_key(&account.identity),
+                start,
+                mmap.len() - data.len(),
+            ));
+        }
+        accounts.sort_unstable_by_key(|(key, ..)| *key);
+        let salts_offset = mmap.len() - data.len();
+
+        Ok(Self {
+            mmap,
+            invite_code_public_key,
+            accounts,
+            salts_offset,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.accounts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.accounts.is_empty()
+    }
+
+    pub fn invite_code_public_key(&self) -> InviteCodePubKey {
+        self.invite_code_public_key
+    }
+
+    fn decode(&self, start: usize, end: usize) -> Result<AccountInfo> {
+        borsh::from_slice(&self.mmap[start..end]).context("decoding account")
+    }
+
+    /// Decodes `account` from the file, `None` when the state doesn't hold it.
+    pub fn get(&self, account: &String) -> Result<Option<AccountInfo>> {
+        let key = AccountInfo::compute_key(account);
+        match self.accounts.binary_search_by_key(&key, |(key, ..)| *key) {
+            Ok(i) => {
+                let (_, start, end) = self.accounts[i];
+                self.decode(start, end).map(Some)
+            }
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// Decodes the accounts one at a time, in key order.
+    pub fn accounts(&self) -> impl Iterator<Item = Result<AccountInfo>> + '_ {
+        self.accounts
+            .iter()
+            .map(|&(_, start, end)| self.decode(start, end))
+    }
+
+    fn tail(&self) -> Result<(HashMap<String, String>, TimePolicy)> {
+        let mut data = &self.mmap[self.salts_offset..];
+        let salts = HashMap::deserialize(&mut data).context("decoding salts")?;
+        let time_policy = TimePolicy::deserialize(&mut data).context("decoding time policy")?;
+        if !data.is_empty() {
+            bail!("{} trailing bytes after the wallet state", data.len());
+        }
+        Ok((salts, time_policy))
+    }
+
+    pub fn salts(&self) -> Result<HashMap<String, String>> {
+        self.tail().map(|(salts, _)| salts)
+    }
+
+    pub fn time_policy(&self) -> Result<TimePolicy> {
+        self.tail().map(|(_, time_policy)| time_policy)
+    }
+
+    /// Builds the full wallet, decoding every account.
+    pub fn materialize(&self) -> Result<Wallet> {
+        let (salts, time_policy) = self.tail()?;
+        Wallet::from_parts(
+            self.invite_code_public_key,
+            self.accounts().collect::<Result<Vec<_>>>()?,
+            salts,
+            time_policy,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AuthMethod, DEFAULT_INVITE_CODE_PUBLIC_KEY};
+    use client_sdk::transaction_builder::TxExecutorHandler;
+
+    #[test]
+    fn test_mapped_wallet_reads_accounts_lazily() {
+        let wallet = Wallet::from_parts(
+            DEFAULT_INVITE_CODE_PUBLIC_KEY,
+            ["alice", "bob", "carol"].map(|identity| AccountInfo {
+                identity: identity.to_string(),
+                auth_method: AuthMethod::Password {
+                    hash: hex::encode(identity),
+                },
+                ..Default::default()
+            }),
+            [("bob".to_string(), "bob_salt".to_string())].into(),
+            TimePolicy { skew_ms: 500 },
+        )
+        .unwrap();
+        let path = std::env::temp_dir().join(format!("mapped-wallet-{}", std::process::id()));
+        std::fs::write(&path, borsh::to_vec(&wallet).unwrap()).unwrap();
+
+        let mapped = MappedWallet::open(&path).unwrap();
+        assert_eq!(mapped.len(), 3);
+        assert_eq!(
+            mapped.get(&"bob".to_string()).unwrap(),
+            Some(wallet.get(&"bob".to_string()).unwrap())
+        );
+        assert_eq!(mapped.get(&"dave".to_string()).unwrap(), None);
+        assert_eq!(mapped.time_policy().unwrap(), TimePolicy { skew_ms: 500 });
+        assert_eq!(
+            mapped.materialize().unwrap().get_state_commitment(),
+            wallet.get_state_commitment()
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+}
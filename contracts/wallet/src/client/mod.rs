@@ -1,5 +1,12 @@
 pub mod indexer;
+pub mod journal;
+pub mod keystore;
+pub mod ledger;
 pub mod light_executor;
+pub mod mapped;
+#[cfg(test)]
+mod proptests;
+pub mod session_key;
 pub mod tx_executor_handler;
 
 pub mod metadata {
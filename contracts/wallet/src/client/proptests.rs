@@ -0,0 +1,400 @@
+//! Runs random sequences of wallet actions through [`Wallet`], [`LightWalletExecutor`] and
+//! [`WalletZkView`], checking that they agree on the outcome of every action and on the
+//! resulting state.
+
+use client_sdk::{light_executor::LightContractExecutor, transaction_builder::TxExecutorHandler};
+use proptest::prelude::*;
+use sdk::{
+    hyli_model_utils::TimestampMs, verifiers::Secp256k1Blob, Blob, BlobData, BlobIndex,
+    BlobTransaction, Calldata, ContractName, Hashed, Identity, IndexedBlobs, TxContext, ZkContract,
+};
+use secp256k1::{Message, PublicKey, Secp256k1, SecretKey};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    client::{light_executor::LightWalletExecutor, tx_executor_handler::Wallet},
+    AccountInfo, AuthMethod, WalletAction, WalletZkView,
+};
+
+const ACCOUNTS: [&str; 3] = ["alice", "bob", "carol"];
+const PASSWORD: &[u8] = b"proptest password";
+/// Seeds of the session keys actions pick from.
+const SESSION_KEYS: [u8; 2] = [1, 2];
+
+#[derive(Debug, Clone)]
+enum Op {
+    Register {
+        account: usize,
+        nonce: u128,
+        valid_invite: bool,
+    },
+    Verify {
+        account: usize,
+        nonce: u128,
+    },
+    AddSessionKey {
+        account: usize,
+        key: usize,
+        nonce: u128,
+        expiration: u128,
+        /// `Some(true)` whitelists the token used with the key, `Some(false)` another one.
+        whitelist: Option<bool>,
+    },
+    RemoveSessionKey {
+        account: usize,
+        key: usize,
+        nonce: u128,
+    },
+    UseSessionKey {
+        account: usize,
+        key: usize,
+        nonce: u128,
+        now: u128,
+    },
+    UpdateInviteKey {
+        key: u8,
+    },
+}
+
+/// Ways to tamper with the calldata of an otherwise well-formed action.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mutation {
+    None,
+    WrongPassword,
+    DropAuthBlob,
+    MissingTxContext,
+    CorruptAction,
+}
+
+fn op() -> impl Strategy<Value = Op> {
+    let account = 0..ACCOUNTS.len();
+    let key = 0..SESSION_KEYS.len();
+    let nonce = 0u128..6;
+    prop_oneof![
+        (account.clone(), nonce.clone(), any::<bool>()).prop_map(
+            |(account, nonce, valid_invite)| Op::Register {
+                account,
+                nonce,
+                valid_invite
+            }
+        ),
+        (account.clone(), nonce.clone()).prop_map(|(account, nonce)| Op::Verify { account, nonce }),
+        (
+            account.clone(),
+            key.clone(),
+            nonce.clone(),
+            0u128..200,
+            prop::option::of(any::<bool>())
+        )
+            .prop_map(|(account, key, nonce, expiration, whitelist)| {
+                Op::AddSessionKey {
+                    account,
+                    key,
+                    nonce,
+                    expiration,
+                    whitelist,
+                }
+            }),
+        (account.clone(), key.clone(), nonce.clone()).prop_map(|(account, key, nonce)| {
+            Op::RemoveSessionKey {
+                account,
+                key,
+                nonce,
+            }
+        }),
+        (account, key, nonce, 0u128..200).prop_map(|(account, key, nonce, now)| {
+            Op::UseSessionKey {
+                account,
+                key,
+                nonce,
+                now,
+            }
+        }),
+        any::<u8>().prop_map(|key| Op::UpdateInviteKey { key }),
+    ]
+}
+
+fn mutation() -> impl Strategy<Value = Mutation> {
+    prop_oneof![
+        6 => Just(Mutation::None),
+        1 => Just(Mutation::WrongPassword),
+        1 => Just(Mutation::DropAuthBlob),
+        1 => Just(Mutation::MissingTxContext),
+        1 => Just(Mutation::CorruptAction),
+    ]
+}
+
+fn session_key(index: usize) -> (SecretKey, String) {
+    let secret_key =
+        SecretKey::from_slice(&[SESSION_KEYS[index]; 32]).expect("valid session key seed");
+    let public_key = PublicKey::from_secret_key(&Secp256k1::new(), &secret_key);
+    (secret_key, hex::encode(public_key.serialize()))
+}
+
+fn password_blob(password: &[u8]) -> Blob {
+    Blob {
+        contract_name: ContractName("check_secret".to_string()),
+        data: BlobData(password.to_vec()),
+    }
+}
+
+fn session_key_blob(identity: &Identity, key: usize, nonce: u128) -> Blob {
+    let (secret_key, public_key) = session_key(key);
+    let data = nonce.to_string();
+    let digest: [u8; 32] = Sha256::digest(data.as_bytes()).into();
+    let signature = Secp256k1::new().sign_ecdsa(&Message::from_digest(digest), &secret_key);
+    Secp256k1Blob::new(
+        identity.clone(),
+        data.as_bytes(),
+        &public_key,
+        &signature.to_string(),
+    )
+    .expect("valid secp256k1 blob")
+    .as_blob()
+}
+
+/// Builds the transaction for `op`, with the wallet blob first.
+fn transaction(op: &Op, mutation: Mutation, smt_root: [u8; 32]) -> (BlobTransaction, u128) {
+    let password = if mutation == Mutation::WrongPassword {
+        b"wrong password".as_slice()
+    } else {
+        PASSWORD
+    };
+    let wallet = ContractName("wallet".to_string());
+    let (account, action, mut auth_blobs, now) = match op.clone() {
+        Op::Register {
+            account,
+            nonce,
+            valid_invite,
+        } => (
+            ACCOUNTS[account],
+            WalletAction::RegisterIdentity {
+                account: ACCOUNTS[account].to_string(),
+                nonce,
+                salt: "salt".to_string(),
+                auth_method: AuthMethod::Password {
+                    hash: hex::encode(PASSWORD),
+                },
+                invite_code: if valid_invite {
+                    "test_invite_code".to_string()
+                } else {
+                    "bad_invite_code".to_string()
+                },
+            },
+            vec![password_blob(password)],
+            0,
+        ),
+        Op::Verify { account, nonce } => (
+            ACCOUNTS[account],
+            WalletAction::VerifyIdentity {
+                account: ACCOUNTS[account].to_string(),
+                nonce,
+            },
+            vec![password_blob(password)],
+            0,
+        ),
+        Op::AddSessionKey {
+            account,
+            key,
+            nonce,
+            expiration,
+            whitelist,
+        } => (
+            ACCOUNTS[account],
+            WalletAction::AddSessionKey {
+                account: ACCOUNTS[account].to_string(),
+                key: session_key(key).1,
+                expiration_date: expiration,
+                whitelist: whitelist.map(|allowed| {
+                    vec![ContractName(
+                        if allowed { "oranj" } else { "vitamin" }.to_string(),
+                    )]
+                }),
+                lane_id: None,
+                nonce,
+            },
+            vec![password_blob(password)],
+            0,
+        ),
+        Op::RemoveSessionKey {
+            account,
+            key,
+            nonce,
+        } => (
+            ACCOUNTS[account],
+            WalletAction::RemoveSessionKey {
+                account: ACCOUNTS[account].to_string(),
+                key: session_key(key).1,
+                nonce,
+            },
+            vec![password_blob(password)],
+            0,
+        ),
+        Op::UseSessionKey {
+            account,
+            key,
+            nonce,
+            now,
+        } => {
+            let identity = Identity::new(format!("{}@wallet", ACCOUNTS[account]));
+            (
+                ACCOUNTS[account],
+                WalletAction::UseSessionKey {
+                    account: ACCOUNTS[account].to_string(),
+                    nonce,
+                },
+                vec![
+                    session_key_blob(&identity, key, nonce),
+                    Blob {
+                        contract_name: ContractName("oranj".to_string()),
+                        data: BlobData(vec![]),
+                    },
+                ],
+                now,
+            )
+        }
+        Op::UpdateInviteKey { key } => (
+            "hyli",
+            WalletAction::UpdateInviteCodePublicKey {
+                invite_code_public_key: [key; 33],
+                smt_root,
+            },
+            vec![],
+            0,
+        ),
+    };
+
+    let mut action_blob = action.as_blob(wallet);
+    if mutation == Mutation::CorruptAction {
+        action_blob.data.0.truncate(action_blob.data.0.len() / 2);
+    }
+    if mutation == Mutation::DropAuthBlob && !auth_blobs.is_empty() {
+        auth_blobs.remove(0);
+    }
+    let mut blobs = vec![action_blob];
+    blobs.extend(auth_blobs);
+    (
+        BlobTransaction::new(Identity::new(format!("{account}@wallet")), blobs),
+        now,
+    )
+}
+
+/// Accounts as seen by the wallet, `None` for unregistered ones.
+fn wallet_accounts(wallet: &Wallet) -> Vec<Option<AccountInfo>> {
+    ACCOUNTS
+        .iter()
+        .map(|account| wallet.get(&account.to_string()).ok())
+        .collect()
+}
+
+fn light_accounts(light: &LightWalletExecutor) -> Vec<Option<AccountInfo>> {
+    ACCOUNTS
+        .iter()
+        .map(|account| {
+            light
+                .accounts
+                .get(*account)
+                .filter(|info| info.auth_method != AuthMethod::Uninitialized)
+                .cloned()
+        })
+        .collect()
+}
+
+fn run_sequence(ops: Vec<(Op, Mutation)>) -> Result<(), TestCaseError> {
+    let mut wallet = Wallet::new(&ContractName::new("wallet"), &None).expect("new wallet");
+    let mut light = LightWalletExecutor::new(&None).expect("new light executor");
+
+    for (op, mutation) in ops {
+        let (tx, now) = transaction(&op, mutation, wallet.get_smt_root());
+        let tx_ctx = (mutation != Mutation::MissingTxContext).then(|| TxContext {
+            timestamp: TimestampMs(now),
+            ..Default::default()
+        });
+        let calldata = Calldata {
+            tx_hash: tx.hashed(),
+            identity: tx.identity.clone(),
+            blobs: IndexedBlobs::from(tx.blobs.clone()),
+            tx_blob_count: tx.blobs.len(),
+            index: BlobIndex(0),
+            tx_ctx: tx_ctx.clone(),
+            private_input: vec![],
+        };
+
+        // Guest: executes against the partial state the prover would be given.
+        let initial = wallet.get_state_commitment();
+        let metadata = wallet
+            .build_commitment_metadata(&calldata)
+            .expect("build commitment metadata");
+        let mut zk_view: WalletZkView = borsh::from_slice(&metadata).expect("decode zk view");
+        let zk_result = zk_view.execute(&calldata);
+        let zk_commitment = match &zk_result {
+            Ok(_) => zk_view.commit(),
+            Err(_) => initial.clone(),
+        };
+
+        // Indexer and autoprover state.
+        let output = wallet.handle(&calldata).expect("handle calldata");
+
+        // Light executor, reverted by the caller when the blob fails.
+        let scratchpad = light
+            .prepare_for_tx(&tx, BlobIndex(0), tx_ctx.as_ref(), ())
+            .expect("prepare light executor");
+        let light_success = match light.handle_blob(&tx, BlobIndex(0), tx_ctx.as_ref(), ()) {
+            Ok(light_output) if light_output.success => {
+                light.on_success(scratchpad).expect("light on_success");
+                true
+            }
+            _ => {
+                light.on_failure(scratchpad).expect("light on_failure");
+                false
+            }
+        };
+
+        prop_assert_eq!(
+            &zk_commitment,
+            &wallet.get_state_commitment(),
+            "commitment diverged after {:?} with {:?}",
+            op,
+            mutation
+        );
+        prop_assert_eq!(
+            wallet_accounts(&wallet),
+            light_accounts(&light),
+            "accounts diverged after {:?} with {:?}",
+            op,
+            mutation
+        );
+
+        if mutation == Mutation::CorruptAction {
+            // Undecodable blobs are ignored by `Wallet` but fail the guest; neither changes state.
+            prop_assert!(zk_result.is_err());
+            prop_assert_eq!(&initial, &wallet.get_state_commitment());
+        } else {
+            prop_assert_eq!(
+                output.success,
+                zk_result.is_ok(),
+                "wallet and guest disagree on {:?} with {:?}: {:?}",
+                op,
+                mutation,
+                zk_result
+            );
+            prop_assert_eq!(
+                output.success,
+                light_success,
+                "wallet and light executor disagree on {:?} with {:?}",
+                op,
+                mutation
+            );
+        }
+    }
+    Ok(())
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(128))]
+
+    #[test]
+    fn executors_agree(ops in prop::collection::vec((op(), mutation()), 1..24)) {
+        run_sequence(ops)?;
+    }
+}
@@ -0,0 +1,59 @@
+use anyhow::{Context, Result};
+use client_sdk::contract_indexer::utoipa;
+use hyli_smt_token::SmtTokenAction;
+use sdk::{verifiers::Secp256k1Blob, Blob, BlobData, BlobTransaction, ContractName, Identity};
+
+use crate::WalletAction;
+
+/// A token transfer authorized by a session key signature over `nonce.to_string()`.
+#[derive(Debug, Clone, serde::Deserialize, utoipa::ToSchema)]
+pub struct SessionKeyTransfer {
+    /// Wallet account name, without the `@wallet` suffix.
+    pub account: String,
+    /// Hex-encoded compressed public key of the session key.
+    pub public_key: String,
+    /// Hex-encoded signature of `nonce.to_string()` by the session key.
+    pub signature: String,
+    pub nonce: u128,
+    pub token: String,
+    pub recipient: String,
+    pub amount: u128,
+}
+
+impl SessionKeyTransfer {
+    pub fn identity(&self, wallet_cn: &ContractName) -> Identity {
+        Identity::new(format!("{}@{}", self.account, wallet_cn.0))
+    }
+
+    /// Assembles the secp256k1, wallet `UseSessionKey` and token `Transfer` blobs, in the order
+    /// the frontend sends them.
+    pub fn into_transaction(self, wallet_cn: &ContractName) -> Result<BlobTransaction> {
+        let identity = self.identity(wallet_cn);
+        let secp256k1 = Secp256k1Blob::new(
+            identity.clone(),
+            self.nonce.to_string().as_bytes(),
+            &self.public_key,
+            &self.signature,
+        )
+        .context("invalid session key signature")?
+        .as_blob();
+        let transfer = SmtTokenAction::Transfer {
+            sender: identity.clone(),
+            recipient: Identity::new(self.recipient),
+            amount: self.amount,
+        };
+        let blobs = vec![
+            secp256k1,
+            WalletAction::UseSessionKey {
+                account: self.account,
+                nonce: self.nonce,
+            }
+            .as_blob(wallet_cn.clone()),
+            Blob {
+                contract_name: self.token.into(),
+                data: BlobData(borsh::to_vec(&transfer).context("encoding transfer")?),
+            },
+        ];
+        Ok(BlobTransaction::new(identity, blobs))
+    }
+}
@@ -5,14 +5,15 @@ use anyhow::Context;
 use borsh::{BorshDeserialize, BorshSerialize};
 use client_sdk::transaction_builder::TxExecutorHandler;
 use sdk::{
-    caller::ExecutionContext, merkle_utils::BorshableMerkleProof, utils::as_hyli_output, Calldata,
-    Contract, ContractName, HyliOutput, StateCommitment,
+    caller::ExecutionContext, hyli_model_utils::TimestampMs, utils::as_hyli_output, Calldata,
+    Contract, ContractName, HyliOutput, StateCommitment, TransactionalZkContract, ZkContract,
 };
 use serde::Serialize;
 
 use crate::{
-    check_for_invite_code, get_state_commitment, smt::AccountSMT, AccountInfo, AuthMethod,
-    InviteCodePubKey, PartialWalletData, WalletAction, WalletZkView,
+    check_invite_key_rotation, client::journal::JournalEntry, get_state_commitment,
+    proof::ProofSiblings, smt::AccountSMT, AccountInfo, AuthMethod, FailedAuth, InviteCodePubKey,
+    PartialWalletData, Recovery, TimePolicy, WalletAction, WalletZkView,
     DEFAULT_INVITE_CODE_PUBLIC_KEY,
 };
 
@@ -24,6 +25,7 @@ pub struct Wallet {
     smt: AccountSMT,
     // Keep track of salts so users can query them.
     salts: HashMap<String, String>,
+    time_policy: TimePolicy,
 }
 
 #[serde_with::serde_as]
@@ -34,18 +36,18 @@ pub struct WalletConstructor {
     pub invite_code_public_key: InviteCodePubKey,
 }
 
-impl WalletConstructor {
-    pub fn new(hyli_password: String, invite_code_public_key: InviteCodePubKey) -> Self {
-        let mut d = "hyli@wallet:".as_bytes().to_vec();
-
-        d.extend_from_slice(&sha2::Sha256::digest(format!(
-            "{}:{}",
-            hyli_password, "hyli-random-salt"
-        )));
-        let hash = sha2::Sha256::digest(&d);
+/// Hex-encoded hash of a [`AuthMethod::Password`], as the `check_secret` verifier computes it
+/// for `identity`.
+pub fn password_hash(identity: &str, password: &str, salt: &str) -> String {
+    let mut d = format!("{identity}:").into_bytes();
+    d.extend_from_slice(&sha2::Sha256::digest(format!("{password}:{salt}")));
+    hex::encode(sha2::Sha256::digest(&d))
+}
 
+impl WalletConstructor {
+    pub fn new(hyli_password: &str, invite_code_public_key: InviteCodePubKey) -> Self {
         Self {
-            hyli_password_hash: hex::encode(hash),
+            hyli_password_hash: password_hash("hyli@wallet", hyli_password, "hyli-random-salt"),
             invite_code_public_key,
         }
     }
@@ -73,34 +75,33 @@ impl TxExecutorHandler for Wallet {
         let wallet_action: Result<WalletAction, _> = WalletAction::from_blob_data(&blob.data);
 
         let zk_view = match wallet_action {
-            Ok(wallet_action) => match wallet_action {
-                WalletAction::UpdateInviteCodePublicKey { .. } => WalletZkView {
-                    commitment: get_state_commitment(
-                        *self.smt.0.root(),
-                        self.invite_code_public_key,
-                    ),
+            Ok(wallet_action) => match wallet_action.account() {
+                None => WalletZkView {
+                    commitment: self.get_state_commitment(),
                     invite_code_public_key: self.invite_code_public_key,
+                    siblings: ProofSiblings::default(),
                     partial_data: vec![],
+                    time_policy: self.time_policy,
                 },
-                WalletAction::RegisterIdentity { account, .. }
-                | WalletAction::VerifyIdentity { account, .. }
-                | WalletAction::UseSessionKey { account, .. }
-                | WalletAction::AddSessionKey { account, .. }
-                | WalletAction::RemoveSessionKey { account, .. } => {
-                    let mut account_info = self.smt.0.get(&AccountInfo::compute_key(&account))?;
+                Some(account) => {
+                    let mut account_info = self.smt.0.get(&AccountInfo::compute_key(account))?;
                     account_info.identity = account.clone();
+                    let mut siblings = ProofSiblings::default();
+                    let proof = siblings.compress(
+                        self.smt
+                            .0
+                            .merkle_proof(vec![AccountInfo::compute_key(account)])
+                            .expect("Failed to generate proof"),
+                    );
                     WalletZkView {
                         commitment: self.get_state_commitment(),
                         invite_code_public_key: self.invite_code_public_key,
+                        siblings,
                         partial_data: vec![PartialWalletData {
-                            proof: BorshableMerkleProof(
-                                self.smt
-                                    .0
-                                    .merkle_proof(vec![AccountInfo::compute_key(&account)])
-                                    .expect("Failed to generate proof"),
-                            ),
+                            proof,
                             account_info,
                         }],
+                        time_policy: self.time_policy,
                     }
                 }
             },
@@ -109,7 +110,9 @@ impl TxExecutorHandler for Wallet {
                 WalletZkView {
                     commitment: self.get_state_commitment(),
                     invite_code_public_key: self.invite_code_public_key,
+                    siblings: ProofSiblings::default(),
                     partial_data: vec![],
+                    time_policy: self.time_policy,
                 }
             }
         };
@@ -125,12 +128,27 @@ impl TxExecutorHandler for Wallet {
         initial: Vec<u8>,
         next: Vec<u8>,
     ) -> anyhow::Result<Vec<u8>, String> {
-        let initial_view: WalletZkView = borsh::from_slice(&initial)
+        let mut initial_view: WalletZkView = borsh::from_slice(&initial)
             .map_err(|e| format!("Failed to deserialize initial view: {e}"))?;
         let mut next_view: WalletZkView = borsh::from_slice(&next)
             .map_err(|e| format!("Failed to deserialize next view: {e}"))?;
 
-        next_view.partial_data.extend(initial_view.partial_data);
+        // Move the next proofs into the initial sibling table, so the initial proofs keep
+        // their indices and siblings common to both views are stored once.
+        let mut partial_data = next_view
+            .partial_data
+            .into_iter()
+            .map(|data| {
+                let proof = next_view.siblings.decompress(&data.proof)?;
+                Ok(PartialWalletData {
+                    proof: initial_view.siblings.compress(proof),
+                    account_info: data.account_info,
+                })
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        partial_data.extend(initial_view.partial_data);
+        next_view.partial_data = partial_data;
+        next_view.siblings = initial_view.siblings;
         next_view.commitment = initial_view.commitment;
 
         borsh::to_vec(&next_view).map_err(|e| format!("Failed to serialize combined view: {e}"))
@@ -143,7 +161,11 @@ impl TxExecutorHandler for Wallet {
     }
 
     fn get_state_commitment(&self) -> StateCommitment {
-        get_state_commitment(*self.smt.0.root(), self.invite_code_public_key)
+        get_state_commitment(
+            *self.smt.0.root(),
+            self.invite_code_public_key,
+            &self.time_policy,
+        )
     }
 
     fn construct_state(
@@ -156,6 +178,7 @@ impl TxExecutorHandler for Wallet {
             invite_code_public_key: DEFAULT_INVITE_CODE_PUBLIC_KEY,
             smt: AccountSMT::default(),
             salts: HashMap::new(),
+            time_policy: TimePolicy::default(),
         };
         if let Some(Ok(constructor_data)) = metadata
             .as_ref()
@@ -173,6 +196,10 @@ impl TxExecutorHandler for Wallet {
                         },
                         session_keys: vec![],
                         nonce: 0,
+                        used_jwt_ids: vec![],
+                        failed_auth: FailedAuth::default(),
+                        recovery: Recovery::default(),
+                        frozen: false,
                     },
                 )
                 .map_err(|e| anyhow::anyhow!("Failed to update account info in SMT: {e}"))?;
@@ -199,6 +226,192 @@ impl Wallet {
         )
     }
 
+    /// Rebuilds a wallet from its accounts, e.g. when converting a dump from an older format.
+    pub fn from_parts(
+        invite_code_public_key: InviteCodePubKey,
+        accounts: impl IntoIterator<Item = AccountInfo>,
+        salts: HashMap<String, String>,
+        time_policy: TimePolicy,
+    ) -> anyhow::Result<Self> {
+        let mut smt = AccountSMT::default();
+        smt.update_accounts(accounts.into_iter().collect())
+            .map_err(|e| anyhow::anyhow!("Failed to update account info in SMT: {e}"))?;
+        Ok(Self {
+            invite_code_public_key,
+            smt,
+            salts,
+            time_policy,
+        })
+    }
+
+    /// Decodes a borsh state dump, rejecting it unless the commitment recomputed from its
+    /// accounts matches `expected`, typically the on-chain state of the contract.
+    pub fn restore(dump: &[u8], expected: &StateCommitment) -> anyhow::Result<Self> {
+        let wallet: Wallet = borsh::from_slice(dump).context("decoding wallet dump")?;
+        let commitment = wallet.get_state_commitment();
+        if commitment != *expected {
+            anyhow::bail!(
+                "wallet dump commitment {} does not match expected {}",
+                hex::encode(&commitment.0),
+                hex::encode(&expected.0)
+            );
+        }
+        Ok(wallet)
+    }
+
+    /// State `action` may have changed, as recorded by the state journal.
+    pub fn journal_entries(&self, action: &WalletAction) -> anyhow::Result<Vec<JournalEntry>> {
+        let Some(account) = action.account() else {
+            return Ok(vec![JournalEntry::InviteCodePublicKey(
+                self.invite_code_public_key,
+            )]);
+        };
+        let mut account_info = self
+            .smt
+            .0
+            .get(&AccountInfo::compute_key(account))
+            .map_err(|e| anyhow::anyhow!("Failed to get account {account} info from SMT: {e}"))?;
+        account_info.identity = account.clone();
+
+        let mut entries = vec![JournalEntry::Account(account_info)];
+        if action.new_salt().is_some() {
+            if let Some(salt) = self.salts.get(account) {
+                entries.push(JournalEntry::Salt {
+                    account: account.clone(),
+                    salt: salt.clone(),
+                });
+            }
+        }
+        if let WalletAction::UpdateTimePolicy { .. } = action {
+            entries.push(JournalEntry::TimePolicy(self.time_policy));
+        }
+        Ok(entries)
+    }
+
+    /// Replays changes recorded by the state journal, updating the accounts in one batch.
+    pub fn apply_journal_entries(&mut self, entries: Vec<JournalEntry>) -> anyhow::Result<()> {
+        let mut accounts = vec![];
+        for entry in entries {
+            match entry {
+                JournalEntry::Account(account_info) => accounts.push(account_info),
+                entry => self.apply_journal_entry(entry)?,
+            }
+        }
+        self.smt
+            .update_accounts(accounts)
+            .map_err(|e| anyhow::anyhow!("Failed to update account info in SMT: {e}"))?;
+        Ok(())
+    }
+
+    /// Replays a change recorded by the state journal.
+    pub fn apply_journal_entry(&mut self, entry: JournalEntry) -> anyhow::Result<()> {
+        match entry {
+            JournalEntry::Account(account_info) => {
+                self.smt
+                    .0
+                    .update(
+                        AccountInfo::compute_key(&account_info.identity),
+                        account_info,
+                    )
+                    .map_err(|e| anyhow::anyhow!("Failed to update account info in SMT: {e}"))?;
+            }
+            JournalEntry::Salt { account, salt } => {
+                self.salts.insert(account, salt);
+            }
+            JournalEntry::InviteCodePublicKey(invite_code_public_key) => {
+                self.invite_code_public_key = invite_code_public_key;
+            }
+            JournalEntry::TimePolicy(time_policy) => self.time_policy = time_policy,
+        }
+        Ok(())
+    }
+
+    /// Checks, as `use_session_key` will, that `public_key` is a live session key of `account`
+    /// allowed to touch every contract of `contracts`.
+    pub fn check_session_key(
+        &self,
+        account: &String,
+        public_key: &str,
+        contracts: &[ContractName],
+        now: TimestampMs,
+    ) -> anyhow::Result<()> {
+        let account_info = self.get(account)?;
+        if account_info.frozen {
+            anyhow::bail!("Account is frozen");
+        }
+        let session_key = account_info
+            .session_keys
+            .iter()
+            .find(|sk| sk.public_key == public_key)
+            .ok_or_else(|| anyhow::anyhow!("Session key not found"))?;
+        if self
+            .time_policy
+            .is_expired(&session_key.expiration_date, &now)
+        {
+            anyhow::bail!("Session key expired");
+        }
+        if session_key.remaining_uses == Some(0) {
+            anyhow::bail!("Session key has no uses left");
+        }
+        if let Some(whitelist) = &session_key.whitelist {
+            if let Some(contract) = contracts.iter().find(|c| !whitelist.contains(c)) {
+                anyhow::bail!("Blob: {} not whitelisted", contract.0);
+            }
+        }
+        Ok(())
+    }
+
+    /// Merkle proof of the given accounts against the current SMT root.
+    pub fn merkle_proof(&self, accounts: &[String]) -> anyhow::Result<BorshableMerkleProof> {
+        let keys = accounts.iter().map(AccountInfo::compute_key).collect();
+        self.smt
+            .0
+            .merkle_proof(keys)
+            .map(BorshableMerkleProof)
+            .map_err(|e| anyhow::anyhow!("Failed to generate merkle proof: {e}"))
+    }
+
+    /// Splits the commitment metadata of a batch into up to `parts` sub-batches of consecutive
+    /// calldatas, each with the metadata to prove it on its own.
+    ///
+    /// The batch is executed ahead on its view, so every sub-batch starts from the state the
+    /// previous ones leave and their proofs chain up like the batch's single proof would.
+    pub fn split_commitment_metadata(
+        metadata: &[u8],
+        calldata: Vec<Calldata>,
+        parts: usize,
+    ) -> Result<Vec<(Vec<u8>, Vec<Calldata>)>, String> {
+        let mut view: WalletZkView = borsh::from_slice(metadata)
+            .map_err(|e| format!("Failed to deserialize batch view: {e}"))?;
+        let size = calldata.len().div_ceil(parts.max(1)).max(1);
+        let mut calldata = calldata.into_iter().peekable();
+        let mut split = Vec::new();
+        while calldata.peek().is_some() {
+            let part: Vec<Calldata> = calldata.by_ref().take(size).collect();
+            let mut part_view = view.clone();
+            for calldata in &part {
+                let initial_state = view.initial_state();
+                if view.execute(calldata).is_err() {
+                    view.revert(initial_state);
+                }
+            }
+            // Partial data is popped from the end, what's left belongs to the next sub-batches.
+            part_view.partial_data.drain(..view.partial_data.len());
+            let metadata = borsh::to_vec(&part_view)
+                .map_err(|e| format!("Failed to serialize sub-batch view: {e}"))?;
+            split.push((metadata, part));
+        }
+        Ok(split)
+    }
+
+    pub fn time_policy(&self) -> TimePolicy {
+        self.time_policy
+    }
+
+    pub fn invite_code_public_key(&self) -> InviteCodePubKey {
+        self.invite_code_public_key
+    }
+
     pub fn get_smt_root(&self) -> [u8; 32] {
         self.smt
             .0
@@ -228,6 +441,13 @@ impl Wallet {
             .ok_or_else(|| anyhow::anyhow!("Salt for account {account} not found"))
     }
 
+    /// Forgets the salt of `account`, returning whether one was kept. The account's on-chain
+    /// commitment is left as is, so a password account can't log in anymore: its password hash
+    /// is derived from the salt.
+    pub fn erase_salt(&mut self, account: &str) -> bool {
+        self.salts.remove(account).is_some()
+    }
+
     /// Returns an iterator over all accounts in the wallet
     pub fn iter_accounts(&self) -> impl Iterator<Item = &AccountInfo> {
         self.smt.0.store().leaves_map().values()
@@ -242,12 +462,17 @@ impl Wallet {
     ) -> Result<HyliOutput, String> {
         if let WalletAction::UpdateInviteCodePublicKey {
             invite_code_public_key,
-            ..
+            smt_root,
         } = action
         {
-            // Source of trust is trust me bro for this one.
-            if self.invite_code_public_key != DEFAULT_INVITE_CODE_PUBLIC_KEY {
-                return Err("Invite code public key already set".to_string());
+            check_invite_key_rotation(
+                &self.invite_code_public_key,
+                &invite_code_public_key,
+                &smt_root,
+                calldata,
+            )?;
+            if smt_root != self.get_smt_root() {
+                return Err("SMT root doesn't match the current state".to_string());
             }
             self.invite_code_public_key = invite_code_public_key;
             return Ok(as_hyli_output(
@@ -257,13 +482,8 @@ impl Wallet {
                 &mut Ok(("Updated public key".as_bytes().to_vec(), exec_ctx, vec![])),
             ));
         }
-        let acc = match action.clone() {
-            WalletAction::RegisterIdentity { account, .. }
-            | WalletAction::VerifyIdentity { account, .. }
-            | WalletAction::UseSessionKey { account, .. }
-            | WalletAction::AddSessionKey { account, .. }
-            | WalletAction::RemoveSessionKey { account, .. } => account,
-            _ => unreachable!(),
+        let Some(acc) = action.account().cloned() else {
+            return Err("Batch has no actions".to_string());
         };
         let mut account_info = self
             .smt
@@ -272,35 +492,32 @@ impl Wallet {
             .map_err(|e| format!("Failed to get account info from SMT: {e}"))?;
         account_info.identity = acc.clone();
 
-        let result = match action {
-            WalletAction::RegisterIdentity {
-                account,
-                nonce,
-                salt,
-                auth_method,
-                invite_code,
-            } => {
-                check_for_invite_code(
-                    &account,
-                    &invite_code,
-                    calldata,
-                    &self.invite_code_public_key,
-                )?;
-                let res =
-                    account_info.handle_registration(account.clone(), nonce, auth_method, calldata);
-                self.salts.insert(account, salt);
-                res
+        let new_time_policy = match &action {
+            WalletAction::UpdateTimePolicy { time_policy, .. } => Some(*time_policy),
+            _ => None,
+        };
+        let new_salt = action.new_salt().cloned();
+        let result = account_info.handle_action(
+            action,
+            calldata,
+            &self.invite_code_public_key,
+            &self.time_policy,
+        );
+
+        // Failed actions may have partially updated the account (e.g. its nonce), but the
+        // transaction reverts, as it does in the guest.
+        if result.is_ok() {
+            self.smt
+                .0
+                .update(AccountInfo::compute_key(&acc), account_info)
+                .map_err(|e| format!("Failed to update account info in SMT: {e}"))?;
+            if let Some(time_policy) = new_time_policy {
+                self.time_policy = time_policy;
             }
-            WalletAction::UseSessionKey { account, nonce } => {
-                account_info.handle_session_key_usage(account, nonce, calldata)
+            if let Some(salt) = new_salt {
+                self.salts.insert(acc.clone(), salt);
             }
-            _ => account_info.handle_authenticated_action(action, calldata),
-        };
-
-        self.smt
-            .0
-            .update(AccountInfo::compute_key(&acc), account_info)
-            .map_err(|e| format!("Failed to update account info in SMT: {e}"))?;
+        }
 
         let next_state_commitment = self.get_state_commitment();
 
@@ -347,6 +564,22 @@ mod tests {
         ZkContract,
     };
 
+    #[test]
+    fn test_restore_checks_commitment() {
+        let wallet =
+            Wallet::new(&ContractName::new("Test"), &None).expect("Failed to create wallet");
+        let dump = borsh::to_vec(&wallet).expect("Failed to dump wallet");
+
+        let restored = Wallet::restore(&dump, &wallet.get_state_commitment())
+            .expect("Failed to restore wallet");
+        assert_eq!(
+            restored.get_state_commitment(),
+            wallet.get_state_commitment()
+        );
+
+        assert!(Wallet::restore(&dump, &StateCommitment(vec![0; 32])).is_err());
+    }
+
     #[test]
     fn test_proof_of_failure() {
         let wallet =
@@ -382,4 +615,71 @@ mod tests {
         assert!(result.is_err());
         assert_eq!(result.unwrap_err(), "Could not deserialize Blob at index 0");
     }
+
+    #[test]
+    fn test_split_commitment_metadata() {
+        let password_hash = b"test_hash".to_vec();
+        let register_call = |account: &str| Calldata {
+            blobs: IndexedBlobs::from(vec![
+                WalletAction::RegisterIdentity {
+                    account: account.to_string(),
+                    nonce: 1,
+                    salt: "test_salt".to_string(),
+                    auth_method: AuthMethod::Password {
+                        hash: hex::encode(&password_hash),
+                    },
+                    invite_code: "test_invite_code".to_string(),
+                }
+                .as_blob(ContractName::new("test")),
+                Blob {
+                    contract_name: ContractName::new("check_secret"),
+                    data: BlobData(password_hash.clone()),
+                },
+            ]),
+            index: BlobIndex(0),
+            ..Default::default()
+        };
+
+        let mut wallet =
+            Wallet::new(&ContractName::new("test"), &None).expect("Failed to create wallet");
+        let mut metadata: Option<Vec<u8>> = None;
+        let mut commitments = vec![wallet.get_state_commitment()];
+        let batch: Vec<Calldata> = ["alice", "bob", "carol", "dave", "erin"]
+            .into_iter()
+            .map(register_call)
+            .collect();
+        for calldata in &batch {
+            let next = wallet.build_commitment_metadata(calldata).unwrap();
+            metadata = Some(match metadata {
+                Some(initial) => wallet.merge_commitment_metadata(initial, next).unwrap(),
+                None => next,
+            });
+            wallet
+                .handle(calldata)
+                .expect("Failed to handle register call");
+            commitments.push(wallet.get_state_commitment());
+        }
+
+        let split = Wallet::split_commitment_metadata(&metadata.unwrap(), batch, 2).unwrap();
+        assert_eq!(
+            split.iter().map(|(_, part)| part.len()).collect::<Vec<_>>(),
+            vec![3, 2]
+        );
+
+        // Each sub-batch is proven from the state the previous one leaves.
+        let mut proven = 0;
+        for (metadata, part) in split {
+            let mut zk_view: WalletZkView = borsh::from_slice(&metadata).unwrap();
+            assert_eq!(zk_view.commitment, commitments[proven]);
+            assert_eq!(zk_view.partial_data.len(), part.len());
+            for calldata in &part {
+                zk_view
+                    .execute(calldata)
+                    .expect("Failed to execute zk view");
+            }
+            proven += part.len();
+            assert_eq!(zk_view.commit(), commitments[proven]);
+        }
+        assert_eq!(proven, 5);
+    }
 }
@@ -1,22 +1,24 @@
 use std::vec;
 
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
 use borsh::{io::Error, BorshDeserialize, BorshSerialize};
 #[cfg(feature = "client")]
 use client_sdk::contract_indexer::utoipa;
+use hyli_smt_token::SmtTokenAction;
+use p256::ecdsa::{signature::Verifier, Signature as P256Signature, VerifyingKey};
+use proof::{CompressedProof, ProofSiblings};
 use sdk::{
-    hyli_model_utils::TimestampMs,
-    merkle_utils::{BorshableMerkleProof, SHA256Hasher},
-    secp256k1::CheckSecp256k1,
-    verifiers::Secp256k1Blob,
-    BlobData, BlobIndex, ContractName, LaneId, RunResult, StateCommitment,
+    hyli_model_utils::TimestampMs, merkle_utils::SHA256Hasher, secp256k1::CheckSecp256k1,
+    verifiers::Secp256k1Blob, BlobData, BlobIndex, ContractName, LaneId, RunResult,
+    StateCommitment,
 };
 use serde::{Deserialize, Serialize};
 use sha2::{digest::Digest, Sha256};
-use sha3::Keccak256;
 use sparse_merkle_tree::{traits::Value, H256};
 
 #[cfg(any(feature = "client", test))]
 pub mod client;
+pub mod proof;
 pub mod smt;
 pub mod utils;
 
@@ -26,10 +28,24 @@ pub const DEFAULT_INVITE_CODE_PUBLIC_KEY: InviteCodePubKey = [
     234, 13, 218, 118, 136, 8, 202, 95, 190, 184, 177, 226,
 ];
 
-fn get_state_commitment(root: H256, pubkey: InviteCodePubKey) -> StateCommitment {
+/// Contract name of the native secp256k1 signature verifier.
+const SECP256K1_VERIFIER: &str = "secp256k1";
+
+/// Only account allowed to change contract-level settings such as the [`TimePolicy`].
+const HYLI_ACCOUNT: &str = "hyli";
+
+fn get_state_commitment(
+    root: H256,
+    pubkey: InviteCodePubKey,
+    time_policy: &TimePolicy,
+) -> StateCommitment {
     let mut hasher = Sha256::new();
     hasher.update(root.as_slice());
     hasher.update(pubkey);
+    // The default policy is left out so existing commitments stay valid.
+    if *time_policy != TimePolicy::default() {
+        hasher.update(time_policy.skew_ms.to_le_bytes());
+    }
     let result = hasher.finalize();
     StateCommitment(result.to_vec())
 }
@@ -55,15 +71,34 @@ impl sdk::ZkContract for WalletZkView {
             smt_root,
         } = action
         {
-            // Source of trust is trust me bro for this one.
-            if self.invite_code_public_key != DEFAULT_INVITE_CODE_PUBLIC_KEY {
-                return Err("Invite code public key already set".to_string());
+            check_invite_key_rotation(
+                &self.invite_code_public_key,
+                &invite_code_public_key,
+                &smt_root,
+                calldata,
+            )?;
+            // The root isn't otherwise proven, it must be the one committed to.
+            if get_state_commitment(
+                H256::from(smt_root),
+                self.invite_code_public_key,
+                &self.time_policy,
+            ) != self.commitment
+            {
+                return Err("SMT root doesn't match the current state".to_string());
             }
             self.invite_code_public_key = invite_code_public_key;
-            self.commitment = get_state_commitment(H256::from(smt_root), invite_code_public_key);
+            self.commitment = get_state_commitment(
+                H256::from(smt_root),
+                invite_code_public_key,
+                &self.time_policy,
+            );
             return Ok(("Updated public key".as_bytes().to_vec(), ctx, vec![]));
         }
 
+        if action.account().is_none() {
+            return Err("Batch has no actions".to_string());
+        }
+
         // If we don't have state for this calldata, then the proof cannot be generated and we must panic.
         let PartialWalletData {
             proof,
@@ -72,26 +107,25 @@ impl sdk::ZkContract for WalletZkView {
             .partial_data
             .pop()
             .expect("No partial data available for the contract state");
+        let proof = self.siblings.decompress(&proof)?;
 
         let account_key = AccountInfo::compute_key(&account_info.identity);
         let leaves = vec![(account_key, account_info.to_h256())];
 
         // Validate internal consistency, then check hash.
         let root = proof
-            .0
             .clone()
             .compute_root::<SHA256Hasher>(leaves.clone())
             .expect("Failed to compute root from proof");
         let verified = proof
-            .0
             .clone()
             .verify::<SHA256Hasher>(&root, leaves.clone())
             .map_err(|e| format!("Failed to verify proof: {e}"))?;
-        if self.commitment != get_state_commitment(root, self.invite_code_public_key) {
+        let commitment = get_state_commitment(root, self.invite_code_public_key, &self.time_policy);
+        if self.commitment != commitment {
             panic!(
                 "State commitment mismatch: expected {:?}, got {:?}",
-                self.commitment,
-                get_state_commitment(root, self.invite_code_public_key)
+                self.commitment, commitment
             );
         }
 
@@ -100,36 +134,28 @@ impl sdk::ZkContract for WalletZkView {
             panic!("Proof verification failed for the contract state",);
         }
 
-        let res = match action {
-            WalletAction::RegisterIdentity {
-                account,
-                nonce,
-                auth_method,
-                invite_code,
-                salt: _,
-            } => {
-                check_for_invite_code(
-                    &account,
-                    &invite_code,
-                    calldata,
-                    &self.invite_code_public_key,
-                )?;
-                account_info.handle_registration(account, nonce, auth_method, calldata)?
-            }
-            WalletAction::UseSessionKey { account, nonce } => {
-                account_info.handle_session_key_usage(account, nonce, calldata)?
-            }
-            _ => account_info.handle_authenticated_action(action, calldata)?,
+        let new_time_policy = match &action {
+            WalletAction::UpdateTimePolicy { time_policy, .. } => Some(*time_policy),
+            _ => None,
         };
+        let res = account_info.handle_action(
+            action,
+            calldata,
+            &self.invite_code_public_key,
+            &self.time_policy,
+        )?;
+        if let Some(time_policy) = new_time_policy {
+            self.time_policy = time_policy;
+        }
 
         // Now update the commitment
         let leaves = vec![(account_key, account_info.to_h256())];
         let new_root = proof
-            .0
             .compute_root::<SHA256Hasher>(leaves)
             .expect("Failed to compute new root");
 
-        self.commitment = get_state_commitment(new_root, self.invite_code_public_key);
+        self.commitment =
+            get_state_commitment(new_root, self.invite_code_public_key, &self.time_policy);
 
         Ok((res.into_bytes(), ctx, vec![]))
     }
@@ -145,16 +171,57 @@ impl sdk::ZkContract for WalletZkView {
 pub struct WalletZkView {
     pub commitment: sdk::StateCommitment,
     pub invite_code_public_key: InviteCodePubKey,
+    /// Siblings shared by the proofs of `partial_data`.
+    pub siblings: ProofSiblings,
     pub partial_data: Vec<PartialWalletData>,
+    pub time_policy: TimePolicy,
+}
+
+/// How timestamps of the transaction context are compared with deadlines. The skew is a grace
+/// period absorbing block-time jitter and clients' clock drift.
+#[derive(
+    BorshSerialize,
+    BorshDeserialize,
+    Serialize,
+    Deserialize,
+    Debug,
+    Default,
+    Clone,
+    Copy,
+    Eq,
+    PartialEq,
+)]
+#[cfg_attr(
+    feature = "client",
+    derive(client_sdk::contract_indexer::utoipa::ToSchema)
+)]
+pub struct TimePolicy {
+    pub skew_ms: u128,
+}
+
+impl TimePolicy {
+    /// Whether `deadline` has passed at `now`, once the skew is allowed for.
+    pub fn is_expired(&self, deadline: &TimestampMs, now: &TimestampMs) -> bool {
+        deadline.0.saturating_add(self.skew_ms) <= now.0
+    }
+
+    /// Whether `time` is further ahead of `now` than the skew allows, e.g. for a token issued
+    /// in the future.
+    pub fn is_in_future(&self, time: &TimestampMs, now: &TimestampMs) -> bool {
+        time.0 > now.0.saturating_add(self.skew_ms)
+    }
 }
 
 #[derive(BorshSerialize, BorshDeserialize, Debug, Clone)]
 pub struct PartialWalletData {
-    pub proof: BorshableMerkleProof,
+    pub proof: CompressedProof,
     pub account_info: AccountInfo,
 }
 
 /// Struct to hold account's information
+///
+/// The fields after `nonce` were appended since the first release, which changed the borsh
+/// encoding: wallet states written before them must be converted by `migrate_state`.
 #[derive(
     BorshSerialize, BorshDeserialize, Serialize, Deserialize, Debug, Default, Clone, Eq, PartialEq,
 )]
@@ -169,6 +236,104 @@ pub struct AccountInfo {
     pub auth_method: AuthMethod,
     pub session_keys: Vec<SessionKey>,
     pub nonce: u128,
+    /// JWT ids recently used to authenticate, so a token can't authorize another transaction.
+    /// Left out of the leaf hash while empty.
+    pub used_jwt_ids: Vec<UsedJwtId>,
+    /// Failed authentications reported by the operator, and the resulting lockout. Left out of
+    /// the leaf hash while at its default.
+    pub failed_auth: FailedAuth,
+    /// Recovery settings and the pending recovery, if any. Left out of the leaf hash while at
+    /// its default.
+    pub recovery: Recovery,
+    /// Set by the owner after a suspected key compromise: neither the session keys nor the auth
+    /// method authenticate anything but `UnfreezeAccount` until unfrozen. Left out of the leaf
+    /// hash while unset.
+    pub frozen: bool,
+}
+
+/// Maximum number of JWT ids remembered per account, oldest evicted first.
+pub const MAX_USED_JWT_IDS: usize = 16;
+
+/// Marks the `jti` section of a `check_jwt` blob, which follows the nonce as this tag, one length
+/// byte and the claim itself.
+pub const JWT_ID_TAG: &[u8] = b":jti:";
+
+/// Failed authentications tolerated before the account gets locked.
+pub const FAILED_AUTH_THRESHOLD: u32 = 5;
+/// Lockout after reaching the threshold, doubled on each further failure.
+pub const LOCKOUT_BASE_MS: u128 = 60_000;
+pub const LOCKOUT_MAX_MS: u128 = 24 * 60 * 60 * 1000;
+
+#[derive(
+    BorshSerialize, BorshDeserialize, Serialize, Deserialize, Debug, Default, Clone, Eq, PartialEq,
+)]
+#[cfg_attr(
+    feature = "client",
+    derive(client_sdk::contract_indexer::utoipa::ToSchema)
+)]
+pub struct FailedAuth {
+    /// Consecutive failures since the last successful authentication.
+    pub count: u32,
+    pub locked_until: TimestampMs,
+}
+
+impl FailedAuth {
+    fn lockout_ms(count: u32) -> u128 {
+        if count < FAILED_AUTH_THRESHOLD {
+            return 0;
+        }
+        let doublings = (count - FAILED_AUTH_THRESHOLD).min(32);
+        LOCKOUT_BASE_MS
+            .saturating_mul(1 << doublings)
+            .min(LOCKOUT_MAX_MS)
+    }
+}
+
+/// Delay between the initiation of a recovery and the moment it can be finalized, during which
+/// the current auth method can cancel it.
+pub const RECOVERY_TIMELOCK_MS: u128 = 48 * 60 * 60 * 1000;
+
+#[derive(
+    BorshSerialize, BorshDeserialize, Serialize, Deserialize, Debug, Default, Clone, Eq, PartialEq,
+)]
+#[cfg_attr(
+    feature = "client",
+    derive(client_sdk::contract_indexer::utoipa::ToSchema)
+)]
+pub struct Recovery {
+    /// Hash of the email whose JWT can initiate a recovery, as in [`AuthMethod::Jwt`].
+    pub email_hash: Option<[u8; 32]>,
+    pub pending: Option<PendingRecovery>,
+    /// Hex-encoded secp256k1 public key that can reset the auth method once, through
+    /// [`WalletAction::RecoverWithBackupKey`]. It can't authenticate anything else.
+    pub backup_key: Option<String>,
+}
+
+#[derive(
+    BorshSerialize, BorshDeserialize, Serialize, Deserialize, Debug, Default, Clone, Eq, PartialEq,
+)]
+#[cfg_attr(
+    feature = "client",
+    derive(client_sdk::contract_indexer::utoipa::ToSchema)
+)]
+pub struct PendingRecovery {
+    /// Auth method replacing the current one once the recovery is finalized.
+    pub auth_method: AuthMethod,
+    pub executable_at: TimestampMs,
+}
+
+#[derive(
+    BorshSerialize, BorshDeserialize, Serialize, Deserialize, Debug, Default, Clone, Eq, PartialEq,
+)]
+#[cfg_attr(
+    feature = "client",
+    derive(client_sdk::contract_indexer::utoipa::ToSchema)
+)]
+pub struct UsedJwtId {
+    /// SHA-256 of the token's `jti` claim.
+    pub jti_hash: [u8; 32],
+    /// Transaction the token was used in, where it may authorize several wallet blobs.
+    pub tx_hash: String,
 }
 
 #[derive(
@@ -183,6 +348,55 @@ pub struct SessionKey {
     pub expiration_date: TimestampMs,
     pub whitelist: Option<Vec<ContractName>>,
     pub lane_id: Option<LaneId>,
+    /// Uses left, decremented by each `UseSessionKey`. `None` for unlimited uses.
+    pub remaining_uses: Option<u32>,
+    /// Most tokens a transaction may move out of the account, summed over its smt-token
+    /// transfers and approvals. `None` for no limit.
+    pub max_value_per_tx: Option<u128>,
+    /// Signature scheme of the key, told apart by the length of `public_key` when it is added.
+    pub key_type: SessionKeyType,
+}
+
+impl SessionKey {
+    /// Whether the key has no usage limit, as all keys had before limits existed.
+    pub fn is_unlimited(&self) -> bool {
+        self.remaining_uses.is_none() && self.max_value_per_tx.is_none()
+    }
+}
+
+#[derive(
+    BorshSerialize,
+    BorshDeserialize,
+    Serialize,
+    Deserialize,
+    Debug,
+    Default,
+    Clone,
+    Copy,
+    Eq,
+    PartialEq,
+)]
+#[cfg_attr(
+    feature = "client",
+    derive(client_sdk::contract_indexer::utoipa::ToSchema)
+)]
+pub enum SessionKeyType {
+    /// Compressed public key, signing through the secp256k1 native verifier.
+    #[default]
+    Secp256k1,
+    /// Signing through a [`WalletAction::Ed25519Signature`] blob, checked by the contract.
+    Ed25519,
+}
+
+impl SessionKeyType {
+    /// Type of a hex-encoded public key: 32 bytes for ed25519, 33 for a compressed secp256k1 key.
+    pub fn of_public_key(public_key: &str) -> Self {
+        if public_key.len() == 64 {
+            SessionKeyType::Ed25519
+        } else {
+            SessionKeyType::Secp256k1
+        }
+    }
 }
 
 #[derive(
@@ -209,9 +423,56 @@ pub enum AuthMethod {
     HyliApp {
         address: String, // Hex-encoded address derived from secp256k1 public key
     },
+    /// Passkey, authenticated by a [`WalletAction::WebAuthnAssertion`] blob of the transaction.
+    WebAuthn {
+        public_key: String, // Hex-encoded compressed P-256 public key
+        /// Relying party id the passkey is scoped to, e.g. `wallet.hyli.org`. Assertions must
+        /// be made for it, from one of its origins.
+        rp_id: String,
+    },
+    /// Several factors, all of which must authenticate the transaction, e.g. a password and a
+    /// JWT. Secp256k1-based factors can't be combined, they all read the blob at index 1.
+    #[cfg_attr(feature = "client", schema(no_recursion))]
+    Multi(Vec<AuthMethod>),
 }
 
 impl AuthMethod {
+    /// Whether an account can't be registered with or switched to the method, as it can't
+    /// authenticate anything. Factors of a multi-factor method can't be multi-factor themselves,
+    /// and at most one of them can be secp256k1-based.
+    fn is_unusable(&self) -> bool {
+        match self {
+            AuthMethod::Uninitialized => true,
+            AuthMethod::Multi(factors) => {
+                let secp256k1_factors = factors
+                    .iter()
+                    .filter(|factor| {
+                        matches!(
+                            factor,
+                            AuthMethod::HyliApp { .. } | AuthMethod::Ethereum { .. }
+                        )
+                    })
+                    .count();
+                factors.is_empty()
+                    || secp256k1_factors > 1
+                    || factors.iter().any(|factor| {
+                        matches!(factor, AuthMethod::Uninitialized | AuthMethod::Multi(_))
+                    })
+            }
+            AuthMethod::WebAuthn { rp_id, .. } => rp_id.is_empty(),
+            _ => false,
+        }
+    }
+
+    /// Whether authenticating takes a JWT, alone or as one of the factors.
+    fn uses_jwt(&self) -> bool {
+        match self {
+            AuthMethod::Jwt { .. } => true,
+            AuthMethod::Multi(factors) => factors.iter().any(AuthMethod::uses_jwt),
+            _ => false,
+        }
+    }
+
     fn parse_blob_infos(data: &BlobData) -> Result<(&[u8; 32], u128), String> {
         let Some((mail_hash, rest)): Option<(&[u8; 32], &[u8])> = data.0.split_first_chunk() else {
             return Err("Invalid check_jwt blob size".to_string());
@@ -235,6 +496,56 @@ impl AuthMethod {
         Ok((mail_hash, nonce))
     }
 
+    /// The `jti` claim of a `check_jwt` blob, empty unless the nonce is followed by a
+    /// [`JWT_ID_TAG`] section. Other bytes after the nonce are ignored.
+    fn parse_jwt_id(data: &BlobData) -> Result<&[u8], String> {
+        // 32 bytes hash + ":" separator + 13 bytes ASCII nonce
+        let Some(section) = data
+            .0
+            .get(46..)
+            .and_then(|rest| rest.strip_prefix(JWT_ID_TAG))
+        else {
+            return Ok(&[]);
+        };
+        section
+            .split_first()
+            .and_then(|(len, jti)| (jti.len() == *len as usize).then_some(jti))
+            .ok_or_else(|| "Invalid check_jwt jti section".to_string())
+    }
+
+    /// Signature of the transaction's blob at index 1, which must be checked by the secp256k1
+    /// verifier for the signature to be proven.
+    fn secp256k1_blob(calldata: &sdk::Calldata) -> Result<Secp256k1Blob, String> {
+        let blob = calldata
+            .blobs
+            .get(&BlobIndex(1)) // FIXME: hardcoded index for now
+            .ok_or("Invalid blob index for secp256k1")?;
+        if blob.contract_name.0 != SECP256K1_VERIFIER {
+            return Err(format!(
+                "Blob at index 1 is for {}, expected the {SECP256K1_VERIFIER} verifier",
+                blob.contract_name.0
+            ));
+        }
+        borsh::from_slice(&blob.data.0).map_err(|e| format!("Failed to decode Secp256k1Blob: {e}"))
+    }
+
+    /// Hash of the `jti` claim of the JWT in the transaction, if it has one.
+    fn jwt_id_hash(calldata: &sdk::Calldata) -> Result<Option<[u8; 32]>, String> {
+        let Some(check_jwt) = calldata
+            .blobs
+            .iter()
+            .find(|(_, b)| b.contract_name.0 == "check_jwt")
+            .map(|(_, b)| &b.data)
+        else {
+            return Ok(None);
+        };
+        let jti = AuthMethod::parse_jwt_id(check_jwt)?;
+        if jti.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(Sha256::digest(jti).into()))
+    }
+
     // Verifies the authentication method during use
     fn verify(&self, calldata: &sdk::Calldata, wallet_blob_nonce: u128) -> Result<String, String> {
         match self {
@@ -249,7 +560,7 @@ impl AuthMethod {
 
                 let (mail_hash, nonce) = AuthMethod::parse_blob_infos(check_jwt)?;
 
-                if mail_hash != hash {
+                if !utils::ct_eq(mail_hash, hash) {
                     return Err(format!(
                         "Invalid authentication, expected {hash:?}, got {mail_hash:?}"
                     ));
@@ -264,26 +575,17 @@ impl AuthMethod {
             }
 
             AuthMethod::Ethereum { address } => {
-                let blob = calldata
-                    .blobs
-                    .get(&BlobIndex(1)) // FIXME: hardcoded index for now
-                    .ok_or("Invalid blob index for secp256k1")?;
-                let secp256k1blob: Secp256k1Blob = borsh::from_slice(&blob.data.0)
-                    .map_err(|e| format!("Failed to decode Eth Secp256k1Blob: {e}"))?;
+                let secp256k1blob = AuthMethod::secp256k1_blob(calldata)?;
 
                 let identity = &calldata.identity;
 
                 let signing_message =
                     format!("Sign in to Hyli as {identity} with nonce {wallet_blob_nonce}");
-                let expected_message = format!(
-                    "\x19Ethereum Signed Message:\n{}{signing_message}",
-                    signing_message.len()
-                );
 
-                let digest: [u8; 32] = Keccak256::digest(expected_message.as_bytes()).into();
+                let digest = utils::ethereum_personal_sign_digest(&signing_message);
                 if secp256k1blob.data != digest {
                     return Err(format!(
-                        "Invalid signature data, expected {} got {}, expected_message was: {expected_message}, nonce is {wallet_blob_nonce}",
+                        "Invalid signature data, expected {} got {}, signing_message was: {signing_message}, nonce is {wallet_blob_nonce}",
                         hex::encode(digest),
                         hex::encode(secp256k1blob.data)
                     ));
@@ -311,22 +613,18 @@ impl AuthMethod {
                     .map(|(_, b)| b.data.clone())
                     .ok_or("Missing check_secret blob")?;
 
-                let checked_hash = hex::encode(check_secret.0);
-                if checked_hash != *hash {
+                let expected_hash = utils::decode_hex(hash)?;
+                if !utils::ct_eq(&check_secret.0, &expected_hash) {
                     return Err(format!(
-                        "Invalid authentication, expected {hash}, got {checked_hash}"
+                        "Invalid authentication, expected {hash}, got {}",
+                        hex::encode(check_secret.0)
                     ));
                 }
                 Ok("Authentication successful".to_string())
             }
 
             AuthMethod::HyliApp { address } => {
-                let blob = calldata
-                    .blobs
-                    .get(&BlobIndex(1)) // FIXME: hardcoded index for now
-                    .ok_or("Invalid blob index for secp256k1")?;
-                let secp256k1blob: Secp256k1Blob = borsh::from_slice(&blob.data.0)
-                    .map_err(|e| format!("Failed to decode Secp256k1Blob: {e}"))?;
+                let secp256k1blob = AuthMethod::secp256k1_blob(calldata)?;
 
                 let identity = &calldata.identity;
 
@@ -357,10 +655,138 @@ impl AuthMethod {
 
                 Ok("Authentication successful".to_string())
             }
+
+            AuthMethod::WebAuthn { public_key, rp_id } => {
+                let account = calldata
+                    .identity
+                    .0
+                    .split_once('@')
+                    .map(|(account, _)| account)
+                    .unwrap_or(&calldata.identity.0);
+                let contract_name = &calldata
+                    .blobs
+                    .get(&calldata.index)
+                    .ok_or("Missing wallet blob")?
+                    .contract_name;
+                let assertion = calldata
+                    .blobs
+                    .iter()
+                    .filter(|(_, b)| &b.contract_name == contract_name)
+                    .find_map(|(_, b)| match borsh::from_slice(&b.data.0) {
+                        Ok(WalletAction::WebAuthnAssertion {
+                            account: asserted,
+                            assertion,
+                        }) if asserted == account => Some(assertion),
+                        _ => None,
+                    })
+                    .ok_or("Missing WebAuthn assertion blob")?;
+
+                assertion.verify(public_key, rp_id, account, wallet_blob_nonce)?;
+                Ok("Authentication successful".to_string())
+            }
+
+            AuthMethod::Multi(factors) => {
+                if factors.is_empty() {
+                    return Err("Multi-factor auth method without factors".to_string());
+                }
+                for factor in factors {
+                    factor.verify(calldata, wallet_blob_nonce)?;
+                }
+                Ok("Authentication successful".to_string())
+            }
+        }
+    }
+}
+
+/// Passkey assertion, as returned by `navigator.credentials.get`.
+#[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+pub struct WebAuthnAssertion {
+    pub authenticator_data: Vec<u8>,
+    pub client_data_json: Vec<u8>,
+    /// Compact (r‖s) ECDSA signature.
+    pub signature: Vec<u8>,
+}
+
+/// Fields of the WebAuthn client data checked by the contract.
+#[derive(Deserialize)]
+struct ClientData {
+    #[serde(rename = "type")]
+    kind: String,
+    challenge: String,
+    origin: String,
+    #[serde(default, rename = "crossOrigin")]
+    cross_origin: bool,
+}
+
+impl WebAuthnAssertion {
+    /// Checks the assertion was made by the passkey of `public_key`, with the user present, over
+    /// the [`webauthn_challenge`] of `account` at `nonce`, for `rp_id` and from one of its
+    /// origins.
+    fn verify(
+        &self,
+        public_key: &str,
+        rp_id: &str,
+        account: &str,
+        nonce: u128,
+    ) -> Result<(), String> {
+        let client_data: ClientData = serde_json::from_slice(&self.client_data_json)
+            .map_err(|e| format!("Invalid WebAuthn client data: {e}"))?;
+        let challenge = URL_SAFE_NO_PAD.encode(webauthn_challenge(account, nonce));
+        if client_data.kind != "webauthn.get" || client_data.challenge != challenge {
+            return Err(format!(
+                "Invalid WebAuthn client data, expected a webauthn.get of challenge {challenge}"
+            ));
+        }
+        if client_data.cross_origin || !is_rp_origin(&client_data.origin, rp_id) {
+            return Err(format!(
+                "WebAuthn assertion made from {}, outside of {rp_id}",
+                client_data.origin
+            ));
+        }
+
+        // rpIdHash (32 bytes), flags (1 byte), signCount (4 bytes)
+        if self.authenticator_data.len() < 37 {
+            return Err("Invalid WebAuthn authenticator data size".to_string());
         }
+        if self.authenticator_data[..32] != Sha256::digest(rp_id.as_bytes())[..] {
+            return Err(format!(
+                "WebAuthn assertion made for another relying party than {rp_id}"
+            ));
+        }
+        if self.authenticator_data[32] & 0x01 == 0 {
+            return Err("WebAuthn assertion made without user presence".to_string());
+        }
+
+        let public_key = VerifyingKey::from_sec1_bytes(&utils::decode_hex(public_key)?)
+            .map_err(|_| "Invalid P-256 public key".to_string())?;
+        let signature = P256Signature::from_slice(&self.signature)
+            .map_err(|_| "Invalid WebAuthn signature encoding".to_string())?;
+        let mut signed = self.authenticator_data.clone();
+        signed.extend_from_slice(&Sha256::digest(&self.client_data_json));
+        public_key
+            .verify(&signed, &signature)
+            .map_err(|_| "Invalid WebAuthn signature".to_string())
     }
 }
 
+/// Whether `origin` may assert for `rp_id`: an https origin of the domain or of one of its
+/// subdomains, or plain http on `localhost`.
+fn is_rp_origin(origin: &str, rp_id: &str) -> bool {
+    let host = match origin.strip_prefix("https://") {
+        Some(host) => host,
+        None if rp_id == "localhost" => match origin.strip_prefix("http://") {
+            Some(host) => host,
+            None => return false,
+        },
+        None => return false,
+    };
+    let host = host.split_once(':').map_or(host, |(host, _)| host);
+    host == rp_id
+        || host
+            .strip_suffix(rp_id)
+            .is_some_and(|subdomain| subdomain.ends_with('.'))
+}
+
 #[allow(dead_code, unused)]
 fn check_for_invite_code(
     account: &String,
@@ -377,16 +803,195 @@ fn check_for_invite_code(
     }
     // Data to sign: "Invite - {invite_code} for {account}"
     let data = format!("Invite - {invite_code} for {account}");
-    // Check if the calldata contains a secp256k1 blob with the expected data
+    check_operator_signature(&data, calldata, invite_code_public_key)?;
+    Ok(())
+}
+
+/// Data the operator signs to report the `attempt`-th failed authentication of `account`.
+pub fn failed_auth_report_data(account: &str, attempt: u32, nonce: u128) -> String {
+    format!("Failed auth - {account} attempt {attempt} at nonce {nonce}")
+}
+
+/// Data the backup key signs to switch the auth method of `account` to `auth_method`.
+pub fn backup_key_recovery_data(account: &str, auth_method: &AuthMethod, nonce: u128) -> String {
+    let auth_method = borsh::to_vec(auth_method).expect("Failed to encode AuthMethod");
+    format!(
+        "Backup key recovery - {account} at nonce {nonce} to {}",
+        hex::encode(Sha256::digest(auth_method))
+    )
+}
+
+/// Data the current invite code key signs to hand over to `invite_code_public_key`, with the
+/// SMT root the new state commitment is computed from.
+pub fn invite_key_rotation_data(
+    invite_code_public_key: &InviteCodePubKey,
+    smt_root: &[u8; 32],
+) -> String {
+    format!(
+        "Invite key rotation - to {} at root {}",
+        hex::encode(invite_code_public_key),
+        hex::encode(smt_root)
+    )
+}
+
+/// Challenge a passkey signs to authenticate `account` at `nonce`. It appears base64url-encoded
+/// in the WebAuthn client data.
+pub fn webauthn_challenge(account: &str, nonce: u128) -> [u8; 32] {
+    Sha256::digest(format!("WebAuthn - {account} at nonce {nonce}")).into()
+}
+
+/// Message an ed25519 session key signs to be used by `account` at `nonce`.
+pub fn ed25519_session_key_message(account: &str, nonce: u128) -> String {
+    format!("Session key - {account} at nonce {nonce}")
+}
+
+/// Checks that the calldata contains a secp256k1 blob of `data` signed with the operator's
+/// invite code key, and returns its index.
+fn check_operator_signature(
+    data: &str,
+    calldata: &sdk::Calldata,
+    invite_code_public_key: &InviteCodePubKey,
+) -> Result<BlobIndex, String> {
     let blob = CheckSecp256k1::new(calldata, data.as_bytes()).expect()?;
     if blob.public_key != *invite_code_public_key {
         return Err("Invalid public key".to_string());
     }
+    AccountInfo::verifier_blob_index(calldata, &blob)
+}
+
+/// Fails unless the wallet blob is alone in its transaction, but for the `signatures` it
+/// checks. Actions that don't authenticate the account with its auth method or a session key
+/// succeed for anyone, so they must not authorize the account's identity for other blobs.
+fn check_alone_in_tx(calldata: &sdk::Calldata, signatures: &[BlobIndex]) -> Result<(), String> {
+    if calldata.blobs.len() != calldata.tx_blob_count {
+        return Err("All blobs should be in the Calldata".to_string());
+    }
+    if calldata
+        .blobs
+        .iter()
+        .any(|(index, _)| index != &calldata.index && !signatures.contains(index))
+    {
+        return Err("Action must be alone in its transaction".to_string());
+    }
+    Ok(())
+}
+
+/// Checks an `UpdateInviteCodePublicKey` action. The placeholder key the contract is deployed
+/// with is replaced as is, later rotations must be signed with the current key.
+fn check_invite_key_rotation(
+    current: &InviteCodePubKey,
+    invite_code_public_key: &InviteCodePubKey,
+    smt_root: &[u8; 32],
+    calldata: &sdk::Calldata,
+) -> Result<(), String> {
+    if *current == DEFAULT_INVITE_CODE_PUBLIC_KEY {
+        return Ok(());
+    }
+    if invite_code_public_key == current {
+        return Err("Invite code public key already set".to_string());
+    }
+    let data = invite_key_rotation_data(invite_code_public_key, smt_root);
+    check_operator_signature(&data, calldata, current)?;
     Ok(())
 }
 
 /// Methods to handle the actions of the Wallet contract
 impl AccountInfo {
+    /// Dispatches an action of the account, contract-level actions are handled beforehand.
+    fn handle_action(
+        &mut self,
+        action: WalletAction,
+        calldata: &sdk::Calldata,
+        invite_code_public_key: &InviteCodePubKey,
+        time_policy: &TimePolicy,
+    ) -> Result<String, String> {
+        match action {
+            WalletAction::RegisterIdentity {
+                account,
+                nonce,
+                auth_method,
+                invite_code,
+                salt: _,
+            } => {
+                check_for_invite_code(&account, &invite_code, calldata, invite_code_public_key)?;
+                self.handle_registration(account, nonce, auth_method, calldata)
+            }
+            WalletAction::UseSessionKey { account, nonce } => {
+                self.handle_session_key_usage(account, nonce, calldata, time_policy)
+            }
+            WalletAction::ReportFailedAuth { account, attempt } => {
+                self.handle_failed_auth_report(account, attempt, calldata, invite_code_public_key)
+            }
+            WalletAction::InitiateRecovery { .. }
+            | WalletAction::FinalizeRecovery { .. }
+            | WalletAction::RecoverWithBackupKey { .. } => {
+                self.handle_recovery_action(action, calldata, time_policy)
+            }
+            WalletAction::PruneSessionKeys { account } => {
+                self.prune_session_keys(account, calldata, time_policy)
+            }
+            WalletAction::WebAuthnAssertion { account, .. } => {
+                self.carry_webauthn_assertion(account, calldata)
+            }
+            WalletAction::Ed25519Signature { account, .. } => {
+                self.carry_ed25519_signature(account, calldata)
+            }
+            WalletAction::Batch(actions) => {
+                self.handle_batch(actions, calldata, invite_code_public_key, time_policy)
+            }
+            _ => self.handle_authenticated_action(action, calldata, time_policy),
+        }
+    }
+
+    /// Applies the actions in order on a copy of the account, kept only if all of them succeed.
+    ///
+    /// Once an action advanced the nonce, the following ones may authenticate at that nonce, as
+    /// they could from later blobs of the transaction.
+    fn handle_batch(
+        &mut self,
+        actions: Vec<WalletAction>,
+        calldata: &sdk::Calldata,
+        invite_code_public_key: &InviteCodePubKey,
+        time_policy: &TimePolicy,
+    ) -> Result<String, String> {
+        if actions.is_empty() {
+            return Err("Batch has no actions".to_string());
+        }
+        let initial_nonce = self.nonce;
+        let mut account_info = self.clone();
+        let mut results = Vec::with_capacity(actions.len());
+        for action in actions {
+            if matches!(
+                action,
+                WalletAction::Batch(_)
+                    | WalletAction::UpdateInviteCodePublicKey { .. }
+                    | WalletAction::UpdateTimePolicy { .. }
+            ) {
+                return Err("Action cannot be batched".to_string());
+            }
+            if action.account() != Some(&self.identity) {
+                return Err("Account does not match registered identity".to_string());
+            }
+            let nonce = account_info.nonce;
+            if nonce > initial_nonce {
+                if action
+                    .nonce()
+                    .is_some_and(|action_nonce| action_nonce < nonce)
+                {
+                    return Err("Invalid nonce".to_string());
+                }
+                // The nonce wasn't used before this batch, there's nothing to replay.
+                account_info.nonce = initial_nonce;
+            }
+            let result =
+                account_info.handle_action(action, calldata, invite_code_public_key, time_policy);
+            account_info.nonce = account_info.nonce.max(nonce);
+            results.push(result?);
+        }
+        *self = account_info;
+        Ok(results.join("; "))
+    }
+
     fn handle_registration(
         &mut self,
         account: String,
@@ -395,67 +1000,544 @@ impl AccountInfo {
         calldata: &sdk::Calldata,
     ) -> Result<String, String> {
         auth_method.verify(calldata, nonce)?;
-        self.register_identity(account, nonce, auth_method)
+        if auth_method.is_unusable() {
+            return Err("Invalid auth method".to_string());
+        }
+        let res = self.register_identity(account, nonce, auth_method)?;
+        self.consume_jwt_id(calldata)?;
+        Ok(res)
     }
 
-    fn handle_session_key_usage(
+    /// Checks the account isn't frozen, then authenticates it like [`Self::authenticate_frozen`].
+    /// Every authenticated action goes through it, except the one unfreezing the account.
+    fn authenticate(
         &mut self,
-        account: String,
-        nonce: u128,
         calldata: &sdk::Calldata,
+        nonce: u128,
+        time_policy: &TimePolicy,
     ) -> Result<String, String> {
-        // TODO: think this is now un-necessary, we can just check Identity
-        if self.identity != account {
-            return Err("Account does not match registered identity".to_string());
+        if self.frozen {
+            return Err("Account is frozen".to_string());
         }
-        let secp256k1blob = CheckSecp256k1::new(calldata, nonce.to_string().as_bytes()).expect()?;
-        let public_key = hex::encode(secp256k1blob.public_key);
-
-        self.verify_and_update_nonce(nonce, calldata)?;
-
-        self.use_session_key(public_key, calldata)
+        self.authenticate_frozen(calldata, nonce, time_policy)
     }
 
-    fn handle_authenticated_action(
+    /// Checks the account isn't locked out and its auth method, and records the JWT id it was
+    /// used with, whether or not the account is frozen. A success clears previous failures.
+    fn authenticate_frozen(
         &mut self,
-        action: WalletAction,
         calldata: &sdk::Calldata,
+        nonce: u128,
+        time_policy: &TimePolicy,
     ) -> Result<String, String> {
-        match action {
-            WalletAction::VerifyIdentity { nonce, account } => {
-                // Verify identity before executing the action
-                self.auth_method.verify(calldata, nonce)?;
-                if self.identity != account {
-                    return Err("Account does not match registered identity".to_string());
-                }
-                self.verify_and_update_nonce(nonce, calldata)
+        if self.failed_auth.locked_until != TimestampMs::default() {
+            let Some(tx_ctx) = &calldata.tx_ctx else {
+                return Err("tx_ctx is missing".to_string());
+            };
+            if !time_policy.is_expired(&self.failed_auth.locked_until, &tx_ctx.timestamp) {
+                return Err(format!(
+                    "Account locked until {}",
+                    self.failed_auth.locked_until.0
+                ));
             }
-            WalletAction::AddSessionKey {
-                account,
-                key,
-                expiration_date,
-                whitelist,
-                lane_id,
-                nonce,
-            } => {
-                // Verify identity before executing the action
-                self.auth_method.verify(calldata, nonce)?;
-
-                self.verify_and_update_nonce(nonce, calldata)?;
+        }
+        let res = self.auth_method.verify(calldata, nonce)?;
+        self.consume_jwt_id(calldata)?;
+        self.failed_auth = FailedAuth::default();
+        Ok(res)
+    }
 
-                if self.identity != account {
-                    return Err("Account does not match registered identity".to_string());
+    /// Records a failed authentication the operator observed in a settled transaction, locking
+    /// the account once failures reach [`FAILED_AUTH_THRESHOLD`]. Reports are numbered and bound
+    /// to the account's nonce so they can't be replayed, and sent alone with their signature.
+    fn handle_failed_auth_report(
+        &mut self,
+        account: String,
+        attempt: u32,
+        calldata: &sdk::Calldata,
+        invite_code_public_key: &InviteCodePubKey,
+    ) -> Result<String, String> {
+        if self.identity != account || self.auth_method == AuthMethod::Uninitialized {
+            return Err("Account does not match registered identity".to_string());
+        }
+        let Some(tx_ctx) = &calldata.tx_ctx else {
+            return Err("tx_ctx is missing".to_string());
+        };
+        if attempt != self.failed_auth.count.saturating_add(1) {
+            return Err(format!(
+                "Unexpected failed authentication attempt {attempt}, expected {}",
+                self.failed_auth.count.saturating_add(1)
+            ));
+        }
+        let signature = check_operator_signature(
+            &failed_auth_report_data(&account, attempt, self.nonce),
+            calldata,
+            invite_code_public_key,
+        )?;
+        // The report says nothing of the account's consent, it mustn't authorize anything else.
+        check_alone_in_tx(calldata, &[signature])?;
+
+        self.failed_auth.count = attempt;
+        let lockout_ms = FailedAuth::lockout_ms(attempt);
+        if lockout_ms == 0 {
+            return Ok(format!("Recorded failed authentication {attempt}"));
+        }
+        self.failed_auth.locked_until = TimestampMs(tx_ctx.timestamp.0.saturating_add(lockout_ms));
+        Ok(format!(
+            "Account locked until {}",
+            self.failed_auth.locked_until.0
+        ))
+    }
+
+    /// Rejects a JWT whose id was already used by another transaction of this account.
+    fn consume_jwt_id(&mut self, calldata: &sdk::Calldata) -> Result<(), String> {
+        if !self.auth_method.uses_jwt() {
+            return Ok(());
+        }
+        self.record_jwt_id(calldata)
+    }
+
+    /// Records the id of the JWT in the transaction, whatever the account's auth method.
+    fn record_jwt_id(&mut self, calldata: &sdk::Calldata) -> Result<(), String> {
+        let Some(jti_hash) = AuthMethod::jwt_id_hash(calldata)? else {
+            return Ok(());
+        };
+        if let Some(used) = self.used_jwt_ids.iter().find(|u| u.jti_hash == jti_hash) {
+            if used.tx_hash == calldata.tx_hash.0 {
+                return Ok(());
+            }
+            return Err("JWT already used".to_string());
+        }
+        if self.used_jwt_ids.len() >= MAX_USED_JWT_IDS {
+            self.used_jwt_ids.remove(0);
+        }
+        self.used_jwt_ids.push(UsedJwtId {
+            jti_hash,
+            tx_hash: calldata.tx_hash.0.clone(),
+        });
+        Ok(())
+    }
+
+    /// Handles the recovery actions that don't authenticate with the current auth method:
+    /// initiation, authenticated by a JWT of the recovery email, finalization, which anyone can
+    /// submit alone in a transaction once the timelock elapsed, and the immediate reset signed
+    /// with the backup key.
+    fn handle_recovery_action(
+        &mut self,
+        action: WalletAction,
+        calldata: &sdk::Calldata,
+        time_policy: &TimePolicy,
+    ) -> Result<String, String> {
+        let Some(tx_ctx) = &calldata.tx_ctx else {
+            return Err("tx_ctx is missing".to_string());
+        };
+        match action {
+            WalletAction::InitiateRecovery {
+                account,
+                auth_method,
+                nonce,
+            } => {
+                if self.identity != account || self.auth_method == AuthMethod::Uninitialized {
+                    return Err("Account does not match registered identity".to_string());
+                }
+                let Some(email_hash) = self.recovery.email_hash else {
+                    return Err("No recovery email registered".to_string());
+                };
+                if self.recovery.pending.is_some() {
+                    return Err("A recovery is already pending".to_string());
+                }
+                if auth_method.is_unusable() {
+                    return Err("Invalid recovery auth method".to_string());
+                }
+                AuthMethod::Jwt { hash: email_hash }.verify(calldata, nonce)?;
+                // The JWT only proves control of the recovery email, it mustn't authorize the
+                // account for other blobs.
+                let check_jwt = calldata
+                    .blobs
+                    .iter()
+                    .find(|(_, b)| b.contract_name.0 == "check_jwt")
+                    .map(|(index, _)| *index)
+                    .ok_or("Missing check_mail blob")?;
+                check_alone_in_tx(calldata, &[check_jwt])?;
+                self.verify_and_update_nonce(nonce, calldata)?;
+                self.record_jwt_id(calldata)?;
+
+                let executable_at =
+                    TimestampMs(tx_ctx.timestamp.0.saturating_add(RECOVERY_TIMELOCK_MS));
+                self.recovery.pending = Some(PendingRecovery {
+                    auth_method,
+                    executable_at: executable_at.clone(),
+                });
+                Ok(format!("Recovery executable from {}", executable_at.0))
+            }
+            WalletAction::FinalizeRecovery { account } => {
+                if self.identity != account {
+                    return Err("Account does not match registered identity".to_string());
+                }
+                check_alone_in_tx(calldata, &[])?;
+                let Some(pending) = &self.recovery.pending else {
+                    return Err("No pending recovery".to_string());
+                };
+                if !time_policy.is_expired(&pending.executable_at, &tx_ctx.timestamp) {
+                    return Err(format!(
+                        "Recovery not executable before {}",
+                        pending.executable_at.0
+                    ));
+                }
+                let Some(pending) = self.recovery.pending.take() else {
+                    unreachable!()
+                };
+                self.auth_method = pending.auth_method;
+                // Keys registered with the lost auth method can't be trusted anymore.
+                self.session_keys.clear();
+                self.failed_auth = FailedAuth::default();
+                Ok("Recovery finalized".to_string())
+            }
+            WalletAction::RecoverWithBackupKey {
+                account,
+                auth_method,
+                nonce,
+            } => {
+                if self.identity != account || self.auth_method == AuthMethod::Uninitialized {
+                    return Err("Account does not match registered identity".to_string());
+                }
+                let Some(backup_key) = &self.recovery.backup_key else {
+                    return Err("No backup key registered".to_string());
+                };
+                if auth_method.is_unusable() {
+                    return Err("Invalid recovery auth method".to_string());
+                }
+                let blob = CheckSecp256k1::new(
+                    calldata,
+                    backup_key_recovery_data(&account, &auth_method, nonce).as_bytes(),
+                )
+                .expect()?;
+                if hex::encode(blob.public_key) != *backup_key {
+                    return Err("Invalid backup key".to_string());
+                }
+                // The signature doesn't cover the rest of the transaction, which could be
+                // rewrapped around it.
+                check_alone_in_tx(
+                    calldata,
+                    &[AccountInfo::verifier_blob_index(calldata, &blob)?],
+                )?;
+                self.verify_and_update_nonce(nonce, calldata)?;
+
+                self.auth_method = auth_method;
+                self.session_keys.clear();
+                self.failed_auth = FailedAuth::default();
+                // The backup key is single use, and supersedes any recovery in progress.
+                self.recovery.backup_key = None;
+                self.recovery.pending = None;
+                Ok("Recovered with backup key".to_string())
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn handle_session_key_usage(
+        &mut self,
+        account: String,
+        nonce: u128,
+        calldata: &sdk::Calldata,
+        time_policy: &TimePolicy,
+    ) -> Result<String, String> {
+        // TODO: think this is now un-necessary, we can just check Identity
+        if self.identity != account {
+            return Err("Account does not match registered identity".to_string());
+        }
+        if self.frozen {
+            return Err("Account is frozen".to_string());
+        }
+        let (public_key, key_type, verifier_index) =
+            Self::session_key_signature(calldata, &account, nonce)?;
+
+        self.verify_and_update_nonce(nonce, calldata)?;
+
+        self.use_session_key(public_key, key_type, verifier_index, calldata, time_policy)
+    }
+
+    /// Public key, type and blob index of the session key signature authorizing `account` at
+    /// `nonce`: an [`WalletAction::Ed25519Signature`] blob, or else a secp256k1 verifier blob.
+    pub(crate) fn session_key_signature(
+        calldata: &sdk::Calldata,
+        account: &str,
+        nonce: u128,
+    ) -> Result<(String, SessionKeyType, BlobIndex), String> {
+        if let Some((public_key, index)) = Self::ed25519_signature(calldata, account, nonce)? {
+            return Ok((public_key, SessionKeyType::Ed25519, index));
+        }
+        let secp256k1blob = CheckSecp256k1::new(calldata, nonce.to_string().as_bytes()).expect()?;
+        let verifier_index = Self::verifier_blob_index(calldata, &secp256k1blob)?;
+        Ok((
+            hex::encode(secp256k1blob.public_key),
+            SessionKeyType::Secp256k1,
+            verifier_index,
+        ))
+    }
+
+    /// Public key and blob index of the [`WalletAction::Ed25519Signature`] of `account` in the
+    /// transaction, once its signature for `nonce` is checked. `None` without such a blob.
+    fn ed25519_signature(
+        calldata: &sdk::Calldata,
+        account: &str,
+        nonce: u128,
+    ) -> Result<Option<(String, BlobIndex)>, String> {
+        let contract_name = &calldata
+            .blobs
+            .get(&calldata.index)
+            .ok_or("Missing wallet blob")?
+            .contract_name;
+        let Some((index, public_key, signature)) = calldata
+            .blobs
+            .iter()
+            .filter(|(_, b)| &b.contract_name == contract_name)
+            .find_map(|(index, b)| match borsh::from_slice(&b.data.0) {
+                Ok(WalletAction::Ed25519Signature {
+                    account: signer,
+                    public_key,
+                    signature,
+                }) if signer == account => Some((*index, public_key, signature)),
+                _ => None,
+            })
+        else {
+            return Ok(None);
+        };
+
+        let key_bytes: [u8; 32] = hex::decode(&public_key)
+            .ok()
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or("Invalid ed25519 public key")?;
+        let verifying_key = ed25519_dalek::VerifyingKey::from_bytes(&key_bytes)
+            .map_err(|e| format!("Invalid ed25519 public key: {e}"))?;
+        let signature = ed25519_dalek::Signature::from_slice(&signature)
+            .map_err(|e| format!("Invalid ed25519 signature: {e}"))?;
+        verifying_key
+            .verify_strict(
+                ed25519_session_key_message(account, nonce).as_bytes(),
+                &signature,
+            )
+            .map_err(|_| "Invalid ed25519 session key signature".to_string())?;
+        Ok(Some((hex::encode(key_bytes), index)))
+    }
+
+    /// Index of the native verifier blob carrying the session key signature. It's identified by
+    /// its content rather than its contract name alone, so that other blobs sent to a contract
+    /// named like the verifier still go through the whitelist.
+    fn verifier_blob_index(
+        calldata: &sdk::Calldata,
+        verified: &Secp256k1Blob,
+    ) -> Result<BlobIndex, String> {
+        let expected =
+            borsh::to_vec(verified).map_err(|e| format!("Failed to encode Secp256k1Blob: {e}"))?;
+        calldata
+            .blobs
+            .iter()
+            .find(|(_, blob)| blob.contract_name.0 == SECP256K1_VERIFIER && blob.data.0 == expected)
+            .map(|(index, _)| *index)
+            .ok_or_else(|| "Missing secp256k1 blob for session key".to_string())
+    }
+
+    fn handle_authenticated_action(
+        &mut self,
+        action: WalletAction,
+        calldata: &sdk::Calldata,
+        time_policy: &TimePolicy,
+    ) -> Result<String, String> {
+        match action {
+            WalletAction::VerifyIdentity { nonce, account } => {
+                // Verify identity before executing the action
+                self.authenticate(calldata, nonce, time_policy)?;
+                if self.identity != account {
+                    return Err("Account does not match registered identity".to_string());
+                }
+                self.verify_and_update_nonce(nonce, calldata)
+            }
+            WalletAction::FreezeAccount { account, nonce } => {
+                self.authenticate(calldata, nonce, time_policy)?;
+
+                self.verify_and_update_nonce(nonce, calldata)?;
+
+                if self.identity != account {
+                    return Err("Account does not match registered identity".to_string());
                 }
-                self.add_session_key(key, expiration_date, whitelist, lane_id)
+                self.frozen = true;
+                Ok("Account frozen".to_string())
+            }
+            WalletAction::UnfreezeAccount { account, nonce } => {
+                self.authenticate_frozen(calldata, nonce, time_policy)?;
+
+                self.verify_and_update_nonce(nonce, calldata)?;
+
+                if self.identity != account {
+                    return Err("Account does not match registered identity".to_string());
+                }
+                if !self.frozen {
+                    return Err("Account is not frozen".to_string());
+                }
+                self.frozen = false;
+                Ok("Account unfrozen".to_string())
+            }
+            WalletAction::AddSessionKey {
+                account,
+                key,
+                expiration_date,
+                whitelist,
+                lane_id,
+                nonce,
+            } => {
+                // Verify identity before executing the action
+                self.authenticate(calldata, nonce, time_policy)?;
+
+                self.verify_and_update_nonce(nonce, calldata)?;
+
+                if self.identity != account {
+                    return Err("Account does not match registered identity".to_string());
+                }
+                self.add_session_key(SessionKey {
+                    public_key: key,
+                    expiration_date: TimestampMs(expiration_date),
+                    whitelist,
+                    lane_id,
+                    ..Default::default()
+                })
+            }
+            WalletAction::AddLimitedSessionKey {
+                account,
+                key,
+                expiration_date,
+                whitelist,
+                lane_id,
+                max_uses,
+                max_value_per_tx,
+                nonce,
+            } => {
+                self.authenticate(calldata, nonce, time_policy)?;
+
+                self.verify_and_update_nonce(nonce, calldata)?;
+
+                if self.identity != account {
+                    return Err("Account does not match registered identity".to_string());
+                }
+                self.add_session_key(SessionKey {
+                    public_key: key,
+                    expiration_date: TimestampMs(expiration_date),
+                    whitelist,
+                    lane_id,
+                    remaining_uses: max_uses,
+                    max_value_per_tx,
+                    ..Default::default()
+                })
             }
             WalletAction::RemoveSessionKey { key, nonce, .. } => {
                 // Verify identity before executing the action
-                self.auth_method.verify(calldata, nonce)?;
+                self.authenticate(calldata, nonce, time_policy)?;
 
                 self.verify_and_update_nonce(nonce, calldata)?;
 
                 self.remove_session_key(key)
             }
+            WalletAction::RenewSessionKey {
+                account,
+                key,
+                new_expiration,
+                nonce,
+            } => {
+                self.authenticate(calldata, nonce, time_policy)?;
+
+                self.verify_and_update_nonce(nonce, calldata)?;
+
+                if self.identity != account {
+                    return Err("Account does not match registered identity".to_string());
+                }
+                self.renew_session_key(key, new_expiration)
+            }
+            WalletAction::UpdateTimePolicy { account, nonce, .. } => {
+                if account != HYLI_ACCOUNT {
+                    return Err("Only the hyli account can update the time policy".to_string());
+                }
+                if self.identity != account {
+                    return Err("Account does not match registered identity".to_string());
+                }
+                self.authenticate(calldata, nonce, time_policy)?;
+
+                self.verify_and_update_nonce(nonce, calldata)?;
+
+                Ok("Time policy updated".to_string())
+            }
+            WalletAction::SetRecoveryEmail {
+                account,
+                email_hash,
+                nonce,
+            } => {
+                self.authenticate(calldata, nonce, time_policy)?;
+
+                self.verify_and_update_nonce(nonce, calldata)?;
+
+                if self.identity != account {
+                    return Err("Account does not match registered identity".to_string());
+                }
+                self.recovery.email_hash = email_hash;
+                Ok("Recovery email updated".to_string())
+            }
+            WalletAction::CancelRecovery { account, nonce } => {
+                self.authenticate(calldata, nonce, time_policy)?;
+
+                self.verify_and_update_nonce(nonce, calldata)?;
+
+                if self.identity != account {
+                    return Err("Account does not match registered identity".to_string());
+                }
+                if self.recovery.pending.take().is_none() {
+                    return Err("No pending recovery".to_string());
+                }
+                Ok("Recovery cancelled".to_string())
+            }
+            WalletAction::RegisterBackupKey {
+                account,
+                public_key,
+                nonce,
+            } => {
+                self.authenticate(calldata, nonce, time_policy)?;
+
+                self.verify_and_update_nonce(nonce, calldata)?;
+
+                if self.identity != account {
+                    return Err("Account does not match registered identity".to_string());
+                }
+                // Set once, so a stolen auth method can't swap it for the thief's key.
+                if self.recovery.backup_key.is_some() {
+                    return Err("Backup key already registered".to_string());
+                }
+                if self
+                    .session_keys
+                    .iter()
+                    .any(|sk| sk.public_key == public_key)
+                {
+                    return Err("Backup key can't be a session key".to_string());
+                }
+                self.recovery.backup_key = Some(public_key);
+                Ok("Backup key registered".to_string())
+            }
+            WalletAction::UpdateAuthMethod {
+                account,
+                nonce,
+                auth_method,
+                ..
+            } => {
+                self.authenticate(calldata, nonce, time_policy)?;
+
+                self.verify_and_update_nonce(nonce, calldata)?;
+
+                if self.identity != account {
+                    return Err("Account does not match registered identity".to_string());
+                }
+                if auth_method.is_unusable() {
+                    return Err("Auth method can't be reset".to_string());
+                }
+                self.auth_method = auth_method;
+                Ok("Auth method updated".to_string())
+            }
             _ => unreachable!(),
         }
     }
@@ -466,6 +1548,9 @@ impl AccountInfo {
         calldata: &sdk::Calldata,
         expected_account: &str,
     ) -> Result<(), String> {
+        let Some(wallet_blob) = calldata.blobs.get(&calldata.index) else {
+            return Err("Missing wallet blob".to_string());
+        };
         // Iterate through blobs before the current one
         for (blob_index, blob) in &calldata.blobs {
             // Skip the current blob and any after it
@@ -473,8 +1558,8 @@ impl AccountInfo {
                 break;
             }
 
-            // Check if this is a wallet blob
-            if blob.contract_name.0 == "wallet" {
+            // Check if this is a blob of this wallet instance
+            if blob.contract_name == wallet_blob.contract_name {
                 // Try to decode the blob as a WalletAction
                 if let Ok(
                     WalletAction::VerifyIdentity { account, .. }
@@ -531,26 +1616,35 @@ impl AccountInfo {
         Ok("Identity verified".to_string())
     }
 
-    fn add_session_key(
-        &mut self,
-        key: String,
-        expiration_date: u128,
-        whitelist: Option<Vec<ContractName>>,
-        lane_id: Option<LaneId>,
-    ) -> Result<String, String> {
-        if self.session_keys.iter().any(|sk| sk.public_key == key) {
+    fn add_session_key(&mut self, mut session_key: SessionKey) -> Result<String, String> {
+        if self
+            .session_keys
+            .iter()
+            .any(|sk| sk.public_key == session_key.public_key)
+        {
             return Err("Session key already exists".to_string());
         }
+        if self.recovery.backup_key.as_ref() == Some(&session_key.public_key) {
+            return Err("Backup key can't be a session key".to_string());
+        }
 
-        self.session_keys.push(SessionKey {
-            public_key: key,
-            expiration_date: TimestampMs(expiration_date),
-            whitelist,
-            lane_id,
-        });
+        session_key.key_type = SessionKeyType::of_public_key(&session_key.public_key);
+        self.session_keys.push(session_key);
         Ok("Session key added".to_string())
     }
 
+    /// Pushes back the expiration of a session key, keeping its whitelist and lane.
+    fn renew_session_key(&mut self, key: String, new_expiration: u128) -> Result<String, String> {
+        let Some(session_key) = self.session_keys.iter_mut().find(|sk| sk.public_key == key) else {
+            return Err("Session key not found".to_string());
+        };
+        if new_expiration <= session_key.expiration_date.0 {
+            return Err("Session key renewal must extend its expiration".to_string());
+        }
+        session_key.expiration_date = TimestampMs(new_expiration);
+        Ok("Session key renewed".to_string())
+    }
+
     fn remove_session_key(&mut self, key: String) -> Result<String, String> {
         let initial_len = self.session_keys.len();
         self.session_keys.retain(|sk| sk.public_key != key);
@@ -562,31 +1656,145 @@ impl AccountInfo {
         Ok("Session key removed".to_string())
     }
 
-    fn use_session_key(
+    /// Removes the expired session keys. Needs no authentication: expired keys can't be used
+    /// anymore, so anyone may clean them up, alone in a transaction.
+    fn prune_session_keys(
         &mut self,
-        public_key: String,
+        account: String,
         calldata: &sdk::Calldata,
+        time_policy: &TimePolicy,
     ) -> Result<String, String> {
+        if self.identity != account || self.auth_method == AuthMethod::Uninitialized {
+            return Err("Account does not match registered identity".to_string());
+        }
+        check_alone_in_tx(calldata, &[])?;
         let Some(tx_ctx) = &calldata.tx_ctx else {
             return Err("tx_ctx is missing".to_string());
         };
-        // Check that all blobs of the transaction are in the Calldata
-        if calldata.blobs.len() != calldata.tx_blob_count {
-            return Err("All blobs should be in the Calldata for whitelist validation".to_string());
+        let initial_len = self.session_keys.len();
+        self.session_keys
+            .retain(|sk| !time_policy.is_expired(&sk.expiration_date, &tx_ctx.timestamp));
+        let pruned = initial_len - self.session_keys.len();
+        if pruned == 0 {
+            return Err("No expired session key".to_string());
+        }
+        Ok(format!("Pruned {pruned} expired session keys"))
+    }
+
+    /// Accepts the blob carrying the passkey assertion of the transaction, checked by the blobs
+    /// it authenticates, which must be in the transaction. It may come before the registration
+    /// of the account.
+    fn carry_webauthn_assertion(
+        &self,
+        account: String,
+        calldata: &sdk::Calldata,
+    ) -> Result<String, String> {
+        if self.identity != account {
+            return Err("Account does not match registered identity".to_string());
+        }
+        if !Self::is_consumed(calldata, &account, WalletAction::uses_auth_method) {
+            return Err("WebAuthn assertion without an action it authenticates".to_string());
+        }
+        Ok(format!("WebAuthn assertion for {account}"))
+    }
+
+    /// Whether another wallet blob of the transaction, on the same contract, is an action of
+    /// `account` accepted by `consumes`. Blobs carried for the checks of other blobs
+    /// authenticate nothing by themselves, so they must not succeed, authorizing the account's
+    /// identity for the transaction, without them.
+    fn is_consumed(
+        calldata: &sdk::Calldata,
+        account: &str,
+        consumes: impl Fn(&WalletAction) -> bool,
+    ) -> bool {
+        let Some(wallet_blob) = calldata.blobs.get(&calldata.index) else {
+            return false;
+        };
+        calldata
+            .blobs
+            .iter()
+            .filter(|(index, b)| {
+                index != &calldata.index && b.contract_name == wallet_blob.contract_name
+            })
+            .filter_map(|(_, b)| borsh::from_slice::<WalletAction>(&b.data.0).ok())
+            .flat_map(|action| match action {
+                WalletAction::Batch(actions) => actions,
+                action => vec![action],
+            })
+            .any(|action| {
+                action.account().map(String::as_str) == Some(account) && consumes(&action)
+            })
+    }
+
+    /// Accepts the blob carrying the ed25519 session key signature of the transaction, checked
+    /// by the `UseSessionKey` it authorizes, which must be in the transaction.
+    fn carry_ed25519_signature(
+        &self,
+        account: String,
+        calldata: &sdk::Calldata,
+    ) -> Result<String, String> {
+        if self.identity != account {
+            return Err("Account does not match registered identity".to_string());
+        }
+        if !Self::is_consumed(calldata, &account, |action| {
+            matches!(action, WalletAction::UseSessionKey { .. })
+        }) {
+            return Err("Ed25519 signature without a session key use".to_string());
+        }
+        Ok(format!("Ed25519 signature for {account}"))
+    }
+
+    /// Tokens the smt-token blobs of the transaction move out of the calldata's identity, through
+    /// transfers or approvals.
+    fn value_moved(calldata: &sdk::Calldata) -> u128 {
+        calldata
+            .blobs
+            .iter()
+            .filter(|(index, _)| index != &calldata.index)
+            .filter_map(|(_, blob)| borsh::from_slice::<SmtTokenAction>(&blob.data.0).ok())
+            .map(|action| match action {
+                SmtTokenAction::Transfer { sender, amount, .. } if sender == calldata.identity => {
+                    amount
+                }
+                SmtTokenAction::TransferFrom { owner, amount, .. }
+                | SmtTokenAction::Approve { owner, amount, .. }
+                    if owner == calldata.identity =>
+                {
+                    amount
+                }
+                _ => 0,
+            })
+            .fold(0, u128::saturating_add)
+    }
+
+    fn use_session_key(
+        &mut self,
+        public_key: String,
+        key_type: SessionKeyType,
+        verifier_index: BlobIndex,
+        calldata: &sdk::Calldata,
+        time_policy: &TimePolicy,
+    ) -> Result<String, String> {
+        let Some(tx_ctx) = &calldata.tx_ctx else {
+            return Err("tx_ctx is missing".to_string());
+        };
+        // Check that all blobs of the transaction are in the Calldata
+        if calldata.blobs.len() != calldata.tx_blob_count {
+            return Err("All blobs should be in the Calldata for whitelist validation".to_string());
         }
 
         if let Some(session_key) = self
             .session_keys
             .iter_mut()
-            .find(|sk| sk.public_key == public_key)
+            .find(|sk| sk.public_key == public_key && sk.key_type == key_type)
         {
             // Check if all blobs in the transaction context are whitelisted
             for (index, blob) in &calldata.blobs {
                 if index == &calldata.index {
                     continue; // Skip the blob for this contract
                 }
-                if blob.contract_name.0 == "secp256k1" {
-                    continue; // Skip the secp256k1 blob
+                if index == &verifier_index {
+                    continue; // Skip the signature of this session key
                 }
                 if let Some(ref whitelist) = session_key.whitelist {
                     if !whitelist.contains(&blob.contract_name) {
@@ -599,11 +1807,24 @@ impl AccountInfo {
             {
                 return Err("Session key not valid for this lane".to_string());
             }
-            if session_key.expiration_date > tx_ctx.timestamp {
-                return Ok("Session key is valid".to_string());
-            } else {
+            if time_policy.is_expired(&session_key.expiration_date, &tx_ctx.timestamp) {
                 return Err("Session key expired".to_string());
             }
+            if let Some(max_value) = session_key.max_value_per_tx {
+                let value = Self::value_moved(calldata);
+                if value > max_value {
+                    return Err(format!(
+                        "Session key can move at most {max_value} per transaction, got {value}"
+                    ));
+                }
+            }
+            if let Some(remaining_uses) = session_key.remaining_uses.as_mut() {
+                if *remaining_uses == 0 {
+                    return Err("Session key has no uses left".to_string());
+                }
+                *remaining_uses -= 1;
+            }
+            return Ok("Session key is valid".to_string());
         }
         Err("Session key not found".to_string())
     }
@@ -654,6 +1875,113 @@ pub enum WalletAction {
         invite_code_public_key: InviteCodePubKey,
         smt_root: [u8; 32],
     },
+    /// Changes the contract's [`TimePolicy`], authenticated as the hyli account.
+    UpdateTimePolicy {
+        account: String,
+        time_policy: TimePolicy,
+        nonce: u128,
+    },
+    /// Records a failed authentication of `account`, signed with the invite code key by the
+    /// operator that observed it.
+    ReportFailedAuth {
+        account: String,
+        attempt: u32,
+    },
+    /// Sets the email whose JWT can initiate a recovery, `None` disabling email recovery.
+    SetRecoveryEmail {
+        account: String,
+        email_hash: Option<[u8; 32]>,
+        nonce: u128,
+    },
+    /// Starts replacing the auth method of `account`, authenticated by a `check_jwt` blob of the
+    /// recovery email. It can be finalized after [`RECOVERY_TIMELOCK_MS`].
+    InitiateRecovery {
+        account: String,
+        auth_method: AuthMethod,
+        nonce: u128,
+    },
+    /// Cancels the pending recovery, authenticated with the current auth method.
+    CancelRecovery {
+        account: String,
+        nonce: u128,
+    },
+    /// Applies the pending recovery once its timelock elapsed. Needs no authentication.
+    FinalizeRecovery {
+        account: String,
+    },
+    /// Registers the dormant backup key of `account`, sent along the `RegisterIdentity` blob at
+    /// signup. It can only be set once.
+    RegisterBackupKey {
+        account: String,
+        public_key: String,
+        nonce: u128,
+    },
+    /// Replaces the auth method right away, authenticated by a secp256k1 blob of
+    /// [`backup_key_recovery_data`] signed with the backup key, which is then discarded.
+    RecoverWithBackupKey {
+        account: String,
+        auth_method: AuthMethod,
+        nonce: u128,
+    },
+    /// Removes the session keys of `account` that expired. Needs no authentication.
+    PruneSessionKeys {
+        account: String,
+    },
+    /// Replaces the auth method of `account`, authenticated with the current one. `salt` is the
+    /// salt of a new password, kept off-chain by the indexers like the one of `RegisterIdentity`.
+    UpdateAuthMethod {
+        account: String,
+        nonce: u128,
+        auth_method: AuthMethod,
+        salt: String,
+    },
+    /// Passkey assertion authenticating the other wallet blobs of the transaction for an account
+    /// with [`AuthMethod::WebAuthn`]. It changes nothing by itself.
+    WebAuthnAssertion {
+        account: String,
+        assertion: WebAuthnAssertion,
+    },
+    /// Extends the expiration of a session key in place, authenticated with the auth method.
+    RenewSessionKey {
+        account: String,
+        key: String,
+        new_expiration: u128,
+        nonce: u128,
+    },
+    /// Adds a session key usable `max_uses` times at most, in transactions moving at most
+    /// `max_value_per_tx` tokens out of the account. Authenticated like `AddSessionKey`.
+    AddLimitedSessionKey {
+        account: String,
+        key: String,
+        expiration_date: u128,
+        whitelist: Option<Vec<ContractName>>,
+        lane_id: Option<LaneId>,
+        max_uses: Option<u32>,
+        max_value_per_tx: Option<u128>,
+        nonce: u128,
+    },
+    /// Blocks every action of `account` authenticated with a session key or the auth method
+    /// but `UnfreezeAccount`, authenticated with the auth method, e.g. after a session key leaked.
+    FreezeAccount {
+        account: String,
+        nonce: u128,
+    },
+    /// Lifts a freeze, authenticated with the auth method.
+    UnfreezeAccount {
+        account: String,
+        nonce: u128,
+    },
+    /// Actions of a single account applied in order, all or none of them, e.g. a registration
+    /// and the session key to use right away.
+    Batch(Vec<WalletAction>),
+    /// Signature of an ed25519 session key of `account` over [`ed25519_session_key_message`],
+    /// authorizing the `UseSessionKey` of the transaction. It changes nothing by itself.
+    Ed25519Signature {
+        account: String,
+        /// Hex-encoded public key.
+        public_key: String,
+        signature: Vec<u8>,
+    },
 }
 
 impl WalletAction {
@@ -664,6 +1992,88 @@ impl WalletAction {
         }
     }
 
+    /// Account the action applies to, `None` for contract-level actions.
+    pub fn account(&self) -> Option<&String> {
+        match self {
+            WalletAction::RegisterIdentity { account, .. }
+            | WalletAction::VerifyIdentity { account, .. }
+            | WalletAction::AddSessionKey { account, .. }
+            | WalletAction::RemoveSessionKey { account, .. }
+            | WalletAction::UseSessionKey { account, .. }
+            | WalletAction::UpdateTimePolicy { account, .. }
+            | WalletAction::ReportFailedAuth { account, .. }
+            | WalletAction::SetRecoveryEmail { account, .. }
+            | WalletAction::InitiateRecovery { account, .. }
+            | WalletAction::CancelRecovery { account, .. }
+            | WalletAction::FinalizeRecovery { account }
+            | WalletAction::RegisterBackupKey { account, .. }
+            | WalletAction::RecoverWithBackupKey { account, .. }
+            | WalletAction::PruneSessionKeys { account }
+            | WalletAction::UpdateAuthMethod { account, .. }
+            | WalletAction::WebAuthnAssertion { account, .. }
+            | WalletAction::RenewSessionKey { account, .. }
+            | WalletAction::AddLimitedSessionKey { account, .. }
+            | WalletAction::FreezeAccount { account, .. }
+            | WalletAction::UnfreezeAccount { account, .. }
+            | WalletAction::Ed25519Signature { account, .. } => Some(account),
+            WalletAction::Batch(actions) => actions.first().and_then(WalletAction::account),
+            WalletAction::UpdateInviteCodePublicKey { .. } => None,
+        }
+    }
+
+    /// Nonce the action authenticates at, if it is authenticated.
+    pub fn nonce(&self) -> Option<u128> {
+        match self {
+            WalletAction::RegisterIdentity { nonce, .. }
+            | WalletAction::VerifyIdentity { nonce, .. }
+            | WalletAction::AddSessionKey { nonce, .. }
+            | WalletAction::RemoveSessionKey { nonce, .. }
+            | WalletAction::UseSessionKey { nonce, .. }
+            | WalletAction::UpdateTimePolicy { nonce, .. }
+            | WalletAction::SetRecoveryEmail { nonce, .. }
+            | WalletAction::InitiateRecovery { nonce, .. }
+            | WalletAction::CancelRecovery { nonce, .. }
+            | WalletAction::RegisterBackupKey { nonce, .. }
+            | WalletAction::RecoverWithBackupKey { nonce, .. }
+            | WalletAction::UpdateAuthMethod { nonce, .. }
+            | WalletAction::RenewSessionKey { nonce, .. }
+            | WalletAction::AddLimitedSessionKey { nonce, .. }
+            | WalletAction::FreezeAccount { nonce, .. }
+            | WalletAction::UnfreezeAccount { nonce, .. } => Some(*nonce),
+            _ => None,
+        }
+    }
+
+    /// Whether the action is authenticated with the account's auth method, or registers it.
+    pub fn uses_auth_method(&self) -> bool {
+        matches!(
+            self,
+            WalletAction::RegisterIdentity { .. }
+                | WalletAction::VerifyIdentity { .. }
+                | WalletAction::AddSessionKey { .. }
+                | WalletAction::RemoveSessionKey { .. }
+                | WalletAction::UpdateTimePolicy { .. }
+                | WalletAction::SetRecoveryEmail { .. }
+                | WalletAction::CancelRecovery { .. }
+                | WalletAction::RegisterBackupKey { .. }
+                | WalletAction::UpdateAuthMethod { .. }
+                | WalletAction::RenewSessionKey { .. }
+                | WalletAction::AddLimitedSessionKey { .. }
+                | WalletAction::FreezeAccount { .. }
+                | WalletAction::UnfreezeAccount { .. }
+        )
+    }
+
+    /// Salt the account's hashed credentials use from now on, if the action sets one.
+    pub fn new_salt(&self) -> Option<&String> {
+        match self {
+            WalletAction::RegisterIdentity { salt, .. }
+            | WalletAction::UpdateAuthMethod { salt, .. } => Some(salt),
+            WalletAction::Batch(actions) => actions.iter().rev().find_map(WalletAction::new_salt),
+            _ => None,
+        }
+    }
+
     pub fn from_blob_data(blob_data: &sdk::BlobData) -> anyhow::Result<Self> {
         borsh::from_slice(&blob_data.0)
             .map_err(|e| anyhow::anyhow!("Failed to decode WalletAction from blob data: {e}"))
@@ -702,6 +2112,57 @@ mod tests {
         assert_eq!(parsed_time, time);
     }
 
+    #[test]
+    fn test_blob_data_decode_jti() {
+        let hash32bytes = [1u8; 32];
+        let blob_data = |section: &[u8]| {
+            sdk::BlobData([hash32bytes.as_slice(), b":1672531199000", section].concat())
+        };
+
+        let jti = AuthMethod::parse_jwt_id(&blob_data(b"")).unwrap();
+        assert!(jti.is_empty());
+        let jti = AuthMethod::parse_jwt_id(&blob_data(b"ejb")).unwrap();
+        assert!(jti.is_empty());
+        let jti = AuthMethod::parse_jwt_id(&blob_data(b":jti:\x03ejb")).unwrap();
+        assert_eq!(jti, b"ejb");
+
+        assert!(AuthMethod::parse_jwt_id(&blob_data(b":jti:\x04ejb")).is_err());
+        assert!(AuthMethod::parse_jwt_id(&blob_data(b":jti:\x02ejb")).is_err());
+        assert!(AuthMethod::parse_jwt_id(&blob_data(b":jti:")).is_err());
+    }
+
+    #[test]
+    fn test_password_hash_encodings() {
+        let secret = b"test_hash".to_vec();
+        let calldata = |data: Vec<u8>| Calldata {
+            blobs: IndexedBlobs::from(vec![Blob {
+                contract_name: sdk::ContractName("check_secret".to_string()),
+                data: sdk::BlobData(data),
+            }]),
+            ..Default::default()
+        };
+        let password = |hash: String| AuthMethod::Password { hash };
+
+        let lower = hex::encode(&secret);
+        assert!(password(lower.clone())
+            .verify(&calldata(secret.clone()), 1)
+            .is_ok());
+        assert!(password(lower.to_uppercase())
+            .verify(&calldata(secret.clone()), 1)
+            .is_ok());
+        assert!(password(format!("0x{lower}"))
+            .verify(&calldata(secret.clone()), 1)
+            .is_ok());
+
+        // A prefix of the secret or a malformed hash must not match.
+        assert!(password(lower.clone())
+            .verify(&calldata(secret[..4].to_vec()), 1)
+            .is_err());
+        assert!(password("not hex".to_string())
+            .verify(&calldata(secret.clone()), 1)
+            .is_err());
+    }
+
     #[test]
     fn test_wallet_logic() {
         let mut wallet = Wallet::new(&ContractName::new("test"), &None).unwrap();
@@ -970,6 +2431,84 @@ mod tests {
             .expect("Failed to execute zk view");
     }
 
+    #[test]
+    fn test_merkle_combined_shares_siblings() {
+        let password_hash = "test_hash".to_string().into_bytes();
+        let hex_encoded_hash = hex::encode(password_hash.clone());
+        let mut wallet = Wallet::new(&ContractName::new("test"), &None).unwrap();
+
+        // Populate the tree so proofs have siblings to share.
+        for i in 0..8 {
+            let register_call = Calldata {
+                blobs: IndexedBlobs::from(vec![
+                    WalletAction::RegisterIdentity {
+                        account: format!("user{i}"),
+                        nonce: 1,
+                        auth_method: AuthMethod::Password {
+                            hash: hex_encoded_hash.clone(),
+                        },
+                        invite_code: "test_invite_code".to_string(),
+                        salt: "test_salt".to_string(),
+                    }
+                    .as_blob(ContractName::new("test")),
+                    Blob {
+                        contract_name: sdk::ContractName("check_secret".to_string()),
+                        data: sdk::BlobData(password_hash.clone()),
+                    },
+                ]),
+                index: BlobIndex(0),
+                ..Default::default()
+            };
+            wallet
+                .handle(&register_call)
+                .expect("Failed to handle register call");
+        }
+
+        let verify_call = |nonce| Calldata {
+            blobs: IndexedBlobs::from(vec![
+                WalletAction::VerifyIdentity {
+                    account: "user0".to_string(),
+                    nonce,
+                }
+                .as_blob(ContractName::new("test")),
+                Blob {
+                    contract_name: sdk::ContractName("check_secret".to_string()),
+                    data: sdk::BlobData(password_hash.clone()),
+                },
+            ]),
+            index: BlobIndex(0),
+            ..Default::default()
+        };
+        let (first_call, second_call) = (verify_call(2), verify_call(3));
+
+        let iv = wallet.build_commitment_metadata(&first_call).unwrap();
+        wallet.handle(&first_call).unwrap();
+        let nv = wallet.build_commitment_metadata(&second_call).unwrap();
+        let iv_siblings = borsh::from_slice::<WalletZkView>(&iv)
+            .unwrap()
+            .siblings
+            .len();
+        let nv_siblings = borsh::from_slice::<WalletZkView>(&nv)
+            .unwrap()
+            .siblings
+            .len();
+
+        let cv = wallet.merge_commitment_metadata(iv, nv).unwrap();
+        let mut zk_view: WalletZkView = borsh::from_slice(&cv).unwrap();
+        assert_eq!(zk_view.partial_data.len(), 2);
+        // Only the account's leaf changed in between, its siblings are all shared.
+        assert!(iv_siblings > 0);
+        assert_eq!(zk_view.siblings.len(), iv_siblings);
+        assert_eq!(nv_siblings, iv_siblings);
+
+        zk_view
+            .execute(&first_call)
+            .expect("Failed to execute zk view");
+        zk_view
+            .execute(&second_call)
+            .expect("Failed to execute zk view");
+    }
+
     #[test]
     fn test_merkle_invite_code() {
         let password_hash = "test_hash".to_string().into_bytes();
@@ -1091,6 +2630,10 @@ mod tests {
             },
             session_keys: vec![],
             nonce,
+            used_jwt_ids: vec![],
+            failed_auth: FailedAuth::default(),
+            recovery: Recovery::default(),
+            frozen: false,
         };
 
         // Create blob #0 - secp256k1 blob (from image)
@@ -1201,5 +2744,1948 @@ mod tests {
             result.is_ok(),
             "Should find VerifyIdentity in previous blobs"
         );
+
+        // Test case 5: Another wallet instance, whose blobs only trust its own
+        let staging = sdk::ContractName("wallet-staging".to_string());
+        let staging_calldata = |proof: &Blob| Calldata {
+            blobs: IndexedBlobs::from(vec![
+                proof.clone(),
+                WalletAction::AddSessionKey {
+                    account: "bob".to_string(),
+                    key: "key".to_string(),
+                    expiration_date: 1769945603806,
+                    whitelist: None,
+                    lane_id: None,
+                    nonce,
+                }
+                .as_blob(staging.clone()),
+            ]),
+            index: BlobIndex(1),
+            identity: "bob".into(),
+            ..Default::default()
+        };
+        let staging_verify = WalletAction::VerifyIdentity {
+            account: "bob".to_string(),
+            nonce,
+        }
+        .as_blob(staging.clone());
+        assert!(account_info
+            .check_verify_identity_in_previous_blobs(&staging_calldata(&staging_verify), "bob")
+            .is_ok());
+        assert!(account_info
+            .check_verify_identity_in_previous_blobs(
+                &staging_calldata(&verify_identity_blob),
+                "bob"
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn test_session_key_skips_only_its_verifier_blob() {
+        use secp256k1::{Message, PublicKey, Secp256k1, SecretKey};
+
+        let secp = Secp256k1::new();
+        let secret_key = SecretKey::from_slice(&[7; 32]).unwrap();
+        let public_key = PublicKey::from_secret_key(&secp, &secret_key);
+        let nonce: u128 = 1;
+        let identity: sdk::Identity = "bob@wallet".into();
+
+        let digest: [u8; 32] = Sha256::digest(nonce.to_string().as_bytes()).into();
+        let signature = secp.sign_ecdsa(&Message::from_digest(digest), &secret_key);
+        let secp256k1_blob = Secp256k1Blob::new(
+            identity.clone(),
+            nonce.to_string().as_bytes(),
+            &public_key.to_string(),
+            &signature.to_string(),
+        )
+        .unwrap()
+        .as_blob();
+
+        let account_info = AccountInfo {
+            identity: "bob".to_string(),
+            auth_method: AuthMethod::Password {
+                hash: "00".to_string(),
+            },
+            session_keys: vec![SessionKey {
+                public_key: hex::encode(public_key.serialize()),
+                expiration_date: TimestampMs(100),
+                whitelist: Some(vec![ContractName::new("oranj")]),
+                lane_id: None,
+                ..Default::default()
+            }],
+            nonce: 0,
+            used_jwt_ids: vec![],
+            failed_auth: FailedAuth::default(),
+            recovery: Recovery::default(),
+            frozen: false,
+        };
+        let calldata = |extra: Vec<Blob>| {
+            let mut blobs = vec![
+                WalletAction::UseSessionKey {
+                    account: "bob".to_string(),
+                    nonce,
+                }
+                .as_blob(ContractName::new("wallet")),
+                secp256k1_blob.clone(),
+                Blob {
+                    contract_name: ContractName::new("oranj"),
+                    data: sdk::BlobData(vec![]),
+                },
+            ];
+            blobs.extend(extra);
+            Calldata {
+                identity: identity.clone(),
+                tx_blob_count: blobs.len(),
+                blobs: IndexedBlobs::from(blobs),
+                index: BlobIndex(0),
+                tx_ctx: Some(sdk::TxContext {
+                    timestamp: TimestampMs(10),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }
+        };
+
+        assert!(account_info
+            .clone()
+            .handle_session_key_usage(
+                "bob".to_string(),
+                nonce,
+                &calldata(vec![]),
+                &TimePolicy::default()
+            )
+            .is_ok());
+
+        // A blob sent to a contract named like the verifier isn't exempt from the whitelist.
+        let result = account_info.clone().handle_session_key_usage(
+            "bob".to_string(),
+            nonce,
+            &calldata(vec![Blob {
+                contract_name: ContractName::new(SECP256K1_VERIFIER),
+                data: sdk::BlobData(b"not a signature".to_vec()),
+            }]),
+            &TimePolicy::default(),
+        );
+        assert_eq!(
+            result.unwrap_err(),
+            "Blob: secp256k1 not whitelisted".to_string()
+        );
+    }
+
+    #[test]
+    fn test_jwt_id_replay() {
+        let mail_hash = [3u8; 32];
+        let mut account_info = AccountInfo {
+            identity: "bob".to_string(),
+            auth_method: AuthMethod::Jwt { hash: mail_hash },
+            session_keys: vec![],
+            nonce: 0,
+            used_jwt_ids: vec![],
+            failed_auth: FailedAuth::default(),
+            recovery: Recovery::default(),
+            frozen: false,
+        };
+
+        // Accounts that never used a JWT id keep their previous leaf hash.
+        let legacy = borsh::to_vec(&(
+            &account_info.identity,
+            &account_info.auth_method,
+            &account_info.session_keys,
+            account_info.nonce,
+        ))
+        .unwrap();
+        let legacy_hash: [u8; 32] = Sha256::digest(&legacy).into();
+        assert_eq!(account_info.to_h256(), H256::from(legacy_hash));
+
+        let verify = |nonce: u128, jti: &[u8], tx_hash: &str| {
+            let mut check_jwt = mail_hash.to_vec();
+            check_jwt.push(b':');
+            check_jwt.extend_from_slice(nonce.to_string().as_bytes());
+            if !jti.is_empty() {
+                check_jwt.extend_from_slice(JWT_ID_TAG);
+                check_jwt.push(jti.len() as u8);
+                check_jwt.extend_from_slice(jti);
+            }
+            Calldata {
+                tx_hash: sdk::TxHash(tx_hash.to_string()),
+                blobs: IndexedBlobs::from(vec![
+                    WalletAction::VerifyIdentity {
+                        account: "bob".to_string(),
+                        nonce,
+                    }
+                    .as_blob(ContractName::new("wallet")),
+                    Blob {
+                        contract_name: ContractName::new("check_jwt"),
+                        data: sdk::BlobData(check_jwt),
+                    },
+                ]),
+                index: BlobIndex(0),
+                ..Default::default()
+            }
+        };
+        let action = |nonce: u128| WalletAction::VerifyIdentity {
+            account: "bob".to_string(),
+            nonce,
+        };
+
+        account_info
+            .handle_authenticated_action(
+                action(1700000000001),
+                &verify(1700000000001, b"jti-1", "aa"),
+                &TimePolicy::default(),
+            )
+            .expect("first use of the token");
+        assert_eq!(account_info.used_jwt_ids.len(), 1);
+
+        let result = account_info.clone().handle_authenticated_action(
+            action(1700000000002),
+            &verify(1700000000002, b"jti-1", "bb"),
+            &TimePolicy::default(),
+        );
+        assert_eq!(result.unwrap_err(), "JWT already used".to_string());
+
+        account_info
+            .handle_authenticated_action(
+                action(1700000000003),
+                &verify(1700000000003, b"jti-2", "cc"),
+                &TimePolicy::default(),
+            )
+            .expect("another token");
+
+        // Tokens without a jti only rely on the nonce.
+        account_info
+            .handle_authenticated_action(
+                action(1700000000004),
+                &verify(1700000000004, b"", "dd"),
+                &TimePolicy::default(),
+            )
+            .expect("token without jti");
+        assert_eq!(account_info.used_jwt_ids.len(), 2);
+    }
+
+    #[test]
+    fn test_time_policy_skew() {
+        let deadline = TimestampMs(100);
+        let strict = TimePolicy::default();
+        assert!(!strict.is_expired(&deadline, &TimestampMs(99)));
+        assert!(strict.is_expired(&deadline, &TimestampMs(100)));
+
+        let lenient = TimePolicy { skew_ms: 10 };
+        assert!(!lenient.is_expired(&deadline, &TimestampMs(109)));
+        assert!(lenient.is_expired(&deadline, &TimestampMs(110)));
+        assert!(!lenient.is_in_future(&TimestampMs(110), &deadline));
+        assert!(lenient.is_in_future(&TimestampMs(111), &deadline));
+
+        // The default policy keeps the previous commitment.
+        let root = H256::from([7u8; 32]);
+        let mut hasher = Sha256::new();
+        hasher.update(root.as_slice());
+        hasher.update(DEFAULT_INVITE_CODE_PUBLIC_KEY);
+        assert_eq!(
+            get_state_commitment(root, DEFAULT_INVITE_CODE_PUBLIC_KEY, &strict),
+            StateCommitment(hasher.finalize().to_vec())
+        );
+        assert_ne!(
+            get_state_commitment(root, DEFAULT_INVITE_CODE_PUBLIC_KEY, &strict),
+            get_state_commitment(root, DEFAULT_INVITE_CODE_PUBLIC_KEY, &lenient)
+        );
+    }
+
+    #[test]
+    fn test_update_time_policy_requires_hyli_leaf() {
+        let password = b"password".to_vec();
+        let account_info = |identity: &str| AccountInfo {
+            identity: identity.to_string(),
+            auth_method: AuthMethod::Password {
+                hash: hex::encode(&password),
+            },
+            ..Default::default()
+        };
+        let action = WalletAction::UpdateTimePolicy {
+            account: HYLI_ACCOUNT.to_string(),
+            time_policy: TimePolicy { skew_ms: 10 },
+            nonce: 1,
+        };
+        let calldata = Calldata {
+            blobs: IndexedBlobs::from(vec![
+                action.as_blob(ContractName::new("wallet")),
+                Blob {
+                    contract_name: ContractName::new("check_secret"),
+                    data: sdk::BlobData(password.clone()),
+                },
+            ]),
+            index: BlobIndex(0),
+            tx_ctx: Some(sdk::TxContext::default()),
+            ..Default::default()
+        };
+
+        // Bob's own auth method doesn't make him the hyli account.
+        assert_eq!(
+            account_info("bob").handle_authenticated_action(
+                action.clone(),
+                &calldata,
+                &TimePolicy::default()
+            ),
+            Err("Account does not match registered identity".to_string())
+        );
+        account_info(HYLI_ACCOUNT)
+            .handle_authenticated_action(action, &calldata, &TimePolicy::default())
+            .expect("update time policy as hyli");
+    }
+
+    #[test]
+    fn test_failed_auth_lockout() {
+        use secp256k1::{Message, PublicKey, Secp256k1, SecretKey};
+
+        let secp = Secp256k1::new();
+        let operator_key = SecretKey::from_slice(&[9; 32]).unwrap();
+        let operator_public_key = PublicKey::from_secret_key(&secp, &operator_key);
+        let invite_code_public_key = operator_public_key.serialize();
+        let identity: sdk::Identity = "bob@wallet".into();
+        let password = b"password".to_vec();
+
+        let mut account_info = AccountInfo {
+            identity: "bob".to_string(),
+            auth_method: AuthMethod::Password {
+                hash: hex::encode(&password),
+            },
+            session_keys: vec![],
+            nonce: 0,
+            used_jwt_ids: vec![],
+            failed_auth: FailedAuth::default(),
+            recovery: Recovery::default(),
+            frozen: false,
+        };
+        let unlocked_hash = account_info.to_h256();
+
+        let report = |attempt: u32, nonce: u128, key: &SecretKey, now: u128| {
+            let data = failed_auth_report_data("bob", attempt, nonce);
+            let digest: [u8; 32] = Sha256::digest(data.as_bytes()).into();
+            let signature = secp.sign_ecdsa(&Message::from_digest(digest), key);
+            let blobs = vec![
+                WalletAction::ReportFailedAuth {
+                    account: "bob".to_string(),
+                    attempt,
+                }
+                .as_blob(ContractName::new("wallet")),
+                Secp256k1Blob::new(
+                    identity.clone(),
+                    data.as_bytes(),
+                    &PublicKey::from_secret_key(&secp, key).to_string(),
+                    &signature.to_string(),
+                )
+                .unwrap()
+                .as_blob(),
+            ];
+            Calldata {
+                tx_blob_count: blobs.len(),
+                blobs: IndexedBlobs::from(blobs),
+                index: BlobIndex(0),
+                tx_ctx: Some(sdk::TxContext {
+                    timestamp: TimestampMs(now),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }
+        };
+        let verify = |nonce: u128, now: u128| Calldata {
+            blobs: IndexedBlobs::from(vec![
+                WalletAction::VerifyIdentity {
+                    account: "bob".to_string(),
+                    nonce,
+                }
+                .as_blob(ContractName::new("wallet")),
+                Blob {
+                    contract_name: ContractName::new("check_secret"),
+                    data: sdk::BlobData(password.clone()),
+                },
+            ]),
+            index: BlobIndex(0),
+            tx_ctx: Some(sdk::TxContext {
+                timestamp: TimestampMs(now),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let handle_report = |account_info: &mut AccountInfo, attempt: u32, calldata: &Calldata| {
+            account_info.handle_failed_auth_report(
+                "bob".to_string(),
+                attempt,
+                calldata,
+                &invite_code_public_key,
+            )
+        };
+
+        // Only the operator can report, with consecutive attempt numbers.
+        let other_key = SecretKey::from_slice(&[8; 32]).unwrap();
+        assert_eq!(
+            handle_report(&mut account_info.clone(), 1, &report(1, 0, &other_key, 0)),
+            Err("Invalid public key".to_string())
+        );
+        assert!(handle_report(
+            &mut account_info.clone(),
+            2,
+            &report(2, 0, &operator_key, 0)
+        )
+        .is_err());
+        // Nor can a report authorize the account for other blobs.
+        let mut with_transfer = report(1, 0, &operator_key, 0);
+        let mut blobs: Vec<Blob> = with_transfer
+            .blobs
+            .iter()
+            .map(|(_, blob)| blob.clone())
+            .collect();
+        blobs.push(Blob {
+            contract_name: ContractName::new("oranj"),
+            data: sdk::BlobData(vec![]),
+        });
+        with_transfer.tx_blob_count = blobs.len();
+        with_transfer.blobs = IndexedBlobs::from(blobs);
+        assert_eq!(
+            handle_report(&mut account_info.clone(), 1, &with_transfer),
+            Err("Action must be alone in its transaction".to_string())
+        );
+
+        for attempt in 1..FAILED_AUTH_THRESHOLD {
+            handle_report(
+                &mut account_info,
+                attempt,
+                &report(attempt, 0, &operator_key, 0),
+            )
+            .expect("record failure");
+        }
+        assert_eq!(account_info.failed_auth.locked_until, TimestampMs(0));
+        handle_report(
+            &mut account_info,
+            FAILED_AUTH_THRESHOLD,
+            &report(FAILED_AUTH_THRESHOLD, 0, &operator_key, 1000),
+        )
+        .expect("record failure");
+        assert_eq!(
+            account_info.failed_auth.locked_until,
+            TimestampMs(1000 + LOCKOUT_BASE_MS)
+        );
+
+        let action = |nonce: u128| WalletAction::VerifyIdentity {
+            account: "bob".to_string(),
+            nonce,
+        };
+        let result = account_info.clone().handle_authenticated_action(
+            action(1),
+            &verify(1, 1000 + LOCKOUT_BASE_MS - 1),
+            &TimePolicy::default(),
+        );
+        assert!(result.unwrap_err().starts_with("Account locked"));
+
+        account_info
+            .handle_authenticated_action(
+                action(1),
+                &verify(1, 1000 + LOCKOUT_BASE_MS),
+                &TimePolicy::default(),
+            )
+            .expect("lockout is over");
+        assert_eq!(account_info.failed_auth, FailedAuth::default());
+        // Cleared failures don't change the leaf hash, and old reports no longer apply.
+        account_info.nonce = 0;
+        assert_eq!(account_info.to_h256(), unlocked_hash);
+        account_info.nonce = 1;
+        assert!(handle_report(&mut account_info, 1, &report(1, 0, &operator_key, 0)).is_err());
+    }
+
+    #[test]
+    fn test_email_recovery_timelock() {
+        let mail_hash = [5u8; 32];
+        let password = b"password".to_vec();
+        let mut account_info = AccountInfo {
+            identity: "bob".to_string(),
+            auth_method: AuthMethod::Password {
+                hash: hex::encode(&password),
+            },
+            session_keys: vec![SessionKey {
+                public_key: "key".to_string(),
+                ..Default::default()
+            }],
+            nonce: 0,
+            used_jwt_ids: vec![],
+            failed_auth: FailedAuth::default(),
+            recovery: Recovery::default(),
+            frozen: false,
+        };
+        let new_auth_method = AuthMethod::Password {
+            hash: hex::encode(b"new password"),
+        };
+
+        let calldata = |action: WalletAction, auth: Blob, now: u128| Calldata {
+            blobs: IndexedBlobs::from(vec![action.as_blob(ContractName::new("wallet")), auth]),
+            index: BlobIndex(0),
+            tx_blob_count: 2,
+            tx_ctx: Some(sdk::TxContext {
+                timestamp: TimestampMs(now),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let check_secret = || Blob {
+            contract_name: ContractName::new("check_secret"),
+            data: sdk::BlobData(password.clone()),
+        };
+        let check_jwt = |nonce: u128| {
+            let mut data = mail_hash.to_vec();
+            data.push(b':');
+            data.extend_from_slice(format!("{nonce:0>13}").as_bytes());
+            Blob {
+                contract_name: ContractName::new("check_jwt"),
+                data: sdk::BlobData(data),
+            }
+        };
+        let initiate = |nonce: u128| WalletAction::InitiateRecovery {
+            account: "bob".to_string(),
+            auth_method: new_auth_method.clone(),
+            nonce,
+        };
+
+        // Without a recovery email, a JWT can't initiate anything.
+        assert_eq!(
+            account_info.clone().handle_recovery_action(
+                initiate(1),
+                &calldata(initiate(1), check_jwt(1), 0),
+                &TimePolicy::default(),
+            ),
+            Err("No recovery email registered".to_string())
+        );
+
+        let set_email = WalletAction::SetRecoveryEmail {
+            account: "bob".to_string(),
+            email_hash: Some(mail_hash),
+            nonce: 1,
+        };
+        account_info
+            .handle_authenticated_action(
+                set_email.clone(),
+                &calldata(set_email, check_secret(), 0),
+                &TimePolicy::default(),
+            )
+            .expect("set recovery email");
+
+        // The JWT doesn't authorize the account for other blobs.
+        let mut with_transfer = calldata(initiate(2), check_jwt(2), 1000);
+        with_transfer.blobs = IndexedBlobs::from(vec![
+            initiate(2).as_blob(ContractName::new("wallet")),
+            check_jwt(2),
+            Blob {
+                contract_name: ContractName::new("oranj"),
+                data: sdk::BlobData(vec![]),
+            },
+        ]);
+        with_transfer.tx_blob_count = 3;
+        assert_eq!(
+            account_info.clone().handle_recovery_action(
+                initiate(2),
+                &with_transfer,
+                &TimePolicy::default(),
+            ),
+            Err("Action must be alone in its transaction".to_string())
+        );
+
+        account_info
+            .handle_recovery_action(
+                initiate(2),
+                &calldata(initiate(2), check_jwt(2), 1000),
+                &TimePolicy::default(),
+            )
+            .expect("initiate recovery");
+        assert_eq!(
+            account_info
+                .recovery
+                .pending
+                .as_ref()
+                .unwrap()
+                .executable_at,
+            TimestampMs(1000 + RECOVERY_TIMELOCK_MS)
+        );
+
+        // The current auth method can cancel it during the timelock.
+        let cancel = WalletAction::CancelRecovery {
+            account: "bob".to_string(),
+            nonce: 3,
+        };
+        let mut cancelled = account_info.clone();
+        cancelled
+            .handle_authenticated_action(
+                cancel.clone(),
+                &calldata(cancel, check_secret(), 2000),
+                &TimePolicy::default(),
+            )
+            .expect("cancel recovery");
+        assert_eq!(cancelled.recovery.pending, None);
+
+        let finalize = WalletAction::FinalizeRecovery {
+            account: "bob".to_string(),
+        };
+        let finalize_at = |now: u128| Calldata {
+            blobs: IndexedBlobs::from(vec![finalize.as_blob(ContractName::new("wallet"))]),
+            tx_blob_count: 1,
+            ..calldata(finalize.clone(), check_secret(), now)
+        };
+        assert!(account_info
+            .clone()
+            .handle_recovery_action(
+                finalize.clone(),
+                &finalize_at(1000 + RECOVERY_TIMELOCK_MS - 1),
+                &TimePolicy::default(),
+            )
+            .unwrap_err()
+            .starts_with("Recovery not executable"));
+        // Anyone may finalize, so it doesn't authorize the account for other blobs.
+        let mut with_transfer = calldata(
+            finalize.clone(),
+            Blob {
+                contract_name: ContractName::new("oranj"),
+                data: sdk::BlobData(vec![]),
+            },
+            1000 + RECOVERY_TIMELOCK_MS,
+        );
+        with_transfer.tx_blob_count = 2;
+        assert_eq!(
+            account_info.clone().handle_recovery_action(
+                finalize.clone(),
+                &with_transfer,
+                &TimePolicy::default(),
+            ),
+            Err("Action must be alone in its transaction".to_string())
+        );
+
+        account_info
+            .handle_recovery_action(
+                finalize.clone(),
+                &finalize_at(1000 + RECOVERY_TIMELOCK_MS),
+                &TimePolicy::default(),
+            )
+            .expect("finalize recovery");
+        assert_eq!(account_info.auth_method, new_auth_method);
+        assert!(account_info.session_keys.is_empty());
+        assert_eq!(account_info.recovery.pending, None);
+    }
+
+    #[test]
+    fn test_backup_key_recovery() {
+        use secp256k1::{Message, PublicKey, Secp256k1, SecretKey};
+
+        let secp = Secp256k1::new();
+        let backup_key = SecretKey::from_slice(&[11; 32]).unwrap();
+        let backup_public_key =
+            hex::encode(PublicKey::from_secret_key(&secp, &backup_key).serialize());
+        let other_key = SecretKey::from_slice(&[12; 32]).unwrap();
+        let identity: sdk::Identity = "bob@wallet".into();
+        let password = b"password".to_vec();
+        let new_auth_method = AuthMethod::Password {
+            hash: hex::encode(b"new password"),
+        };
+
+        let mut account_info = AccountInfo {
+            identity: "bob".to_string(),
+            auth_method: AuthMethod::Password {
+                hash: hex::encode(&password),
+            },
+            session_keys: vec![SessionKey {
+                public_key: "key".to_string(),
+                ..Default::default()
+            }],
+            nonce: 0,
+            used_jwt_ids: vec![],
+            failed_auth: FailedAuth::default(),
+            recovery: Recovery::default(),
+            frozen: false,
+        };
+
+        let register = WalletAction::RegisterBackupKey {
+            account: "bob".to_string(),
+            public_key: backup_public_key.clone(),
+            nonce: 1,
+        };
+        let register_calldata = Calldata {
+            blobs: IndexedBlobs::from(vec![
+                register.as_blob(ContractName::new("wallet")),
+                Blob {
+                    contract_name: ContractName::new("check_secret"),
+                    data: sdk::BlobData(password.clone()),
+                },
+            ]),
+            index: BlobIndex(0),
+            ..Default::default()
+        };
+        account_info
+            .handle_authenticated_action(
+                register.clone(),
+                &register_calldata,
+                &TimePolicy::default(),
+            )
+            .expect("register backup key");
+        assert_eq!(
+            account_info.clone().handle_authenticated_action(
+                register,
+                &register_calldata,
+                &TimePolicy::default(),
+            ),
+            Err("Backup key already registered".to_string())
+        );
+        // The backup key can't transact as a session key.
+        assert_eq!(
+            account_info
+                .clone()
+                .add_session_key(backup_public_key.clone(), u128::MAX, None, None),
+            Err("Backup key can't be a session key".to_string())
+        );
+
+        let recover = |key: &SecretKey, signed_auth_method: &AuthMethod| {
+            let action = WalletAction::RecoverWithBackupKey {
+                account: "bob".to_string(),
+                auth_method: new_auth_method.clone(),
+                nonce: 2,
+            };
+            let data = backup_key_recovery_data("bob", signed_auth_method, 2);
+            let digest: [u8; 32] = Sha256::digest(data.as_bytes()).into();
+            let signature = secp.sign_ecdsa(&Message::from_digest(digest), key);
+            let calldata = Calldata {
+                blobs: IndexedBlobs::from(vec![
+                    action.as_blob(ContractName::new("wallet")),
+                    Secp256k1Blob::new(
+                        identity.clone(),
+                        data.as_bytes(),
+                        &PublicKey::from_secret_key(&secp, key).to_string(),
+                        &signature.to_string(),
+                    )
+                    .unwrap()
+                    .as_blob(),
+                ]),
+                index: BlobIndex(0),
+                tx_blob_count: 2,
+                tx_ctx: Some(sdk::TxContext::default()),
+                ..Default::default()
+            };
+            (action, calldata)
+        };
+
+        let (action, calldata) = recover(&other_key, &new_auth_method);
+        assert_eq!(
+            account_info
+                .clone()
+                .handle_recovery_action(action, &calldata, &TimePolicy::default()),
+            Err("Invalid backup key".to_string())
+        );
+        // The signature covers the new auth method.
+        let (action, calldata) = recover(
+            &backup_key,
+            &AuthMethod::Password {
+                hash: hex::encode(b"other password"),
+            },
+        );
+        assert!(account_info
+            .clone()
+            .handle_recovery_action(action, &calldata, &TimePolicy::default())
+            .is_err());
+
+        let (action, calldata) = recover(&backup_key, &new_auth_method);
+        // A signed recovery seen in the mempool can't be rewrapped with other blobs.
+        let (_, mut with_transfer) = recover(&backup_key, &new_auth_method);
+        let mut blobs: Vec<Blob> = with_transfer
+            .blobs
+            .iter()
+            .map(|(_, blob)| blob.clone())
+            .collect();
+        blobs.push(Blob {
+            contract_name: ContractName::new("oranj"),
+            data: sdk::BlobData(vec![]),
+        });
+        with_transfer.tx_blob_count = blobs.len();
+        with_transfer.blobs = IndexedBlobs::from(blobs);
+        assert_eq!(
+            account_info.clone().handle_recovery_action(
+                action.clone(),
+                &with_transfer,
+                &TimePolicy::default()
+            ),
+            Err("Action must be alone in its transaction".to_string())
+        );
+
+        account_info
+            .handle_recovery_action(action.clone(), &calldata, &TimePolicy::default())
+            .expect("recover with backup key");
+        assert_eq!(account_info.auth_method, new_auth_method);
+        assert!(account_info.session_keys.is_empty());
+        assert_eq!(account_info.recovery.backup_key, None);
+
+        // The backup key is single use.
+        assert_eq!(
+            account_info.handle_recovery_action(action, &calldata, &TimePolicy::default()),
+            Err("No backup key registered".to_string())
+        );
+    }
+
+    #[test]
+    fn test_ledger_signature_authenticates_ethereum_account() {
+        use crate::client::ledger;
+        use secp256k1::{Message, PublicKey, Secp256k1, SecretKey};
+
+        let secp = Secp256k1::new();
+        let secret_key = SecretKey::from_slice(&[9; 32]).unwrap();
+        let public_key = PublicKey::from_secret_key(&secp, &secret_key);
+        let address = utils::ethereum_address_from_public_key(&public_key);
+        let nonce: u128 = 42;
+
+        // GET_PUBLIC_KEY answer: uncompressed key, address, status word.
+        let mut response = vec![65];
+        response.extend_from_slice(&public_key.serialize_uncompressed());
+        response.push(40);
+        response.extend_from_slice(address.to_uppercase().as_bytes());
+        response.extend_from_slice(&[0x90, 0x00]);
+        let ledger_key = ledger::parse_public_key_response(&response).unwrap();
+        assert_eq!(ledger_key.address, address);
+
+        let message = format!("Sign in to Hyli as bob with nonce {nonce}");
+        let path = ledger::parse_derivation_path(ledger::DEFAULT_DERIVATION_PATH).unwrap();
+        assert_eq!(
+            ledger::sign_personal_message_apdus(&path, message.as_bytes()).len(),
+            1
+        );
+        assert_eq!(
+            ledger::sign_personal_message_apdus(&path, &[0; 600]).len(),
+            3
+        );
+
+        let signature = secp.sign_ecdsa(
+            &Message::from_digest(utils::ethereum_personal_sign_digest(&message)),
+            &secret_key,
+        );
+        let mut response = vec![27];
+        response.extend_from_slice(&signature.serialize_compact());
+        response.extend_from_slice(&[0x90, 0x00]);
+        let signature = ledger::parse_signature_response(&response).unwrap();
+        assert!(ledger::is_denied(&[0x69, 0x85]));
+        assert!(ledger::parse_signature_response(&[0x69, 0x85]).is_err());
+
+        let blob = ledger::secp256k1_blob(
+            "bob".into(),
+            message.as_bytes(),
+            ledger_key.public_key,
+            signature,
+        )
+        .as_blob();
+        let calldata = Calldata {
+            blobs: IndexedBlobs::from(vec![
+                WalletAction::VerifyIdentity {
+                    account: "bob".to_string(),
+                    nonce,
+                }
+                .as_blob(ContractName::new("wallet")),
+                blob,
+            ]),
+            index: BlobIndex(0),
+            identity: "bob".into(),
+            ..Default::default()
+        };
+
+        let auth_method = AuthMethod::Ethereum {
+            address: format!("0x{address}"),
+        };
+        assert!(auth_method.verify(&calldata, nonce).is_ok());
+        assert!(auth_method.verify(&calldata, nonce + 1).is_err());
+
+        // The same signature isn't checked by the node under another contract.
+        let mut unverified = calldata.clone();
+        let mut blobs: Vec<Blob> = calldata.blobs.iter().map(|(_, b)| b.clone()).collect();
+        blobs[1].contract_name = ContractName::new("not_a_verifier");
+        unverified.blobs = IndexedBlobs::from(blobs);
+        assert!(auth_method.verify(&unverified, nonce).is_err());
+    }
+
+    #[test]
+    fn test_prune_expired_session_keys() {
+        let session_key = |public_key: &str, expiration_date: u128| SessionKey {
+            public_key: public_key.to_string(),
+            expiration_date: TimestampMs(expiration_date),
+            ..Default::default()
+        };
+        let mut account_info = AccountInfo {
+            identity: "bob".to_string(),
+            auth_method: AuthMethod::Password {
+                hash: hex::encode(b"password"),
+            },
+            session_keys: vec![session_key("old", 1_000), session_key("live", 5_000)],
+            ..Default::default()
+        };
+        let prune = WalletAction::PruneSessionKeys {
+            account: "bob".to_string(),
+        };
+        // No authentication blob is needed.
+        let calldata = |now: u128| Calldata {
+            blobs: IndexedBlobs::from(vec![prune.as_blob(ContractName::new("wallet"))]),
+            tx_blob_count: 1,
+            index: BlobIndex(0),
+            tx_ctx: Some(sdk::TxContext {
+                timestamp: TimestampMs(now),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            account_info.clone().prune_session_keys(
+                "bob".to_string(),
+                &calldata(999),
+                &TimePolicy::default()
+            ),
+            Err("No expired session key".to_string())
+        );
+        assert_eq!(
+            account_info.clone().prune_session_keys(
+                "alice".to_string(),
+                &calldata(2_000),
+                &TimePolicy::default()
+            ),
+            Err("Account does not match registered identity".to_string())
+        );
+
+        // Anyone may prune, so it doesn't authorize the account for other blobs.
+        let mut with_transfer = calldata(2_000);
+        with_transfer.blobs = IndexedBlobs::from(vec![
+            prune.as_blob(ContractName::new("wallet")),
+            Blob {
+                contract_name: ContractName::new("oranj"),
+                data: sdk::BlobData(vec![]),
+            },
+        ]);
+        with_transfer.tx_blob_count = 2;
+        assert_eq!(
+            account_info.clone().prune_session_keys(
+                "bob".to_string(),
+                &with_transfer,
+                &TimePolicy::default()
+            ),
+            Err("Action must be alone in its transaction".to_string())
+        );
+
+        account_info
+            .prune_session_keys("bob".to_string(), &calldata(2_000), &TimePolicy::default())
+            .expect("prune expired keys");
+        assert_eq!(account_info.session_keys, vec![session_key("live", 5_000)]);
+        assert_eq!(account_info.nonce, 0);
+    }
+
+    #[test]
+    fn test_update_auth_method() {
+        let password = b"old password".to_vec();
+        let mut account_info = AccountInfo {
+            identity: "hyli".to_string(),
+            auth_method: AuthMethod::Password {
+                hash: hex::encode(&password),
+            },
+            ..Default::default()
+        };
+        let new_auth_method = AuthMethod::Password {
+            hash: hex::encode(b"new password"),
+        };
+        let update = |auth_method: AuthMethod, nonce: u128| WalletAction::UpdateAuthMethod {
+            account: "hyli".to_string(),
+            nonce,
+            auth_method,
+            salt: "new-salt".to_string(),
+        };
+        let calldata = |action: &WalletAction, secret: &[u8]| Calldata {
+            blobs: IndexedBlobs::from(vec![
+                action.as_blob(ContractName::new("wallet")),
+                Blob {
+                    contract_name: ContractName::new("check_secret"),
+                    data: sdk::BlobData(secret.to_vec()),
+                },
+            ]),
+            index: BlobIndex(0),
+            tx_ctx: Some(sdk::TxContext::default()),
+            ..Default::default()
+        };
+
+        let wrong_secret = update(new_auth_method.clone(), 1);
+        assert!(account_info
+            .clone()
+            .handle_authenticated_action(
+                wrong_secret.clone(),
+                &calldata(&wrong_secret, b"guess"),
+                &TimePolicy::default(),
+            )
+            .is_err());
+
+        let reset = update(AuthMethod::Uninitialized, 1);
+        assert_eq!(
+            account_info.clone().handle_authenticated_action(
+                reset.clone(),
+                &calldata(&reset, &password),
+                &TimePolicy::default(),
+            ),
+            Err("Auth method can't be reset".to_string())
+        );
+
+        let rotate = update(new_auth_method.clone(), 1);
+        account_info
+            .handle_authenticated_action(
+                rotate.clone(),
+                &calldata(&rotate, &password),
+                &TimePolicy::default(),
+            )
+            .expect("update auth method");
+        assert_eq!(account_info.auth_method, new_auth_method);
+        assert_eq!(account_info.nonce, 1);
+
+        // The old password no longer authenticates.
+        let again = update(new_auth_method, 2);
+        assert!(account_info
+            .handle_authenticated_action(
+                again.clone(),
+                &calldata(&again, &password),
+                &TimePolicy::default(),
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn test_invite_key_rotation() {
+        use secp256k1::{Message, PublicKey, Secp256k1, SecretKey};
+
+        let secp = Secp256k1::new();
+        let first_key = SecretKey::from_slice(&[9; 32]).unwrap();
+        let next_key = SecretKey::from_slice(&[8; 32]).unwrap();
+        let first_public_key = PublicKey::from_secret_key(&secp, &first_key).serialize();
+        let next_public_key = PublicKey::from_secret_key(&secp, &next_key).serialize();
+
+        let calldata =
+            |public_key: InviteCodePubKey, smt_root: [u8; 32], signer: Option<&SecretKey>| {
+                let mut blobs = vec![WalletAction::UpdateInviteCodePublicKey {
+                    invite_code_public_key: public_key,
+                    smt_root,
+                }
+                .as_blob(ContractName::new("wallet"))];
+                if let Some(key) = signer {
+                    let data = invite_key_rotation_data(&public_key, &smt_root);
+                    let digest: [u8; 32] = Sha256::digest(data.as_bytes()).into();
+                    let signature = secp.sign_ecdsa(&Message::from_digest(digest), key);
+                    blobs.push(
+                        Secp256k1Blob::new(
+                            "hyli@wallet".into(),
+                            data.as_bytes(),
+                            &PublicKey::from_secret_key(&secp, key).to_string(),
+                            &signature.to_string(),
+                        )
+                        .unwrap()
+                        .as_blob(),
+                    );
+                }
+                Calldata {
+                    identity: "hyli@wallet".into(),
+                    tx_blob_count: blobs.len(),
+                    blobs: IndexedBlobs::from(blobs),
+                    index: BlobIndex(0),
+                    ..Default::default()
+                }
+            };
+        let execute = |wallet: &mut Wallet, calldata: &Calldata| {
+            let v = wallet.build_commitment_metadata(calldata).unwrap();
+            let mut zk_view: WalletZkView = borsh::from_slice(&v).unwrap();
+            let zk_result = zk_view.execute(calldata).map(|_| zk_view.commitment);
+            let result = wallet
+                .handle(calldata)
+                .map(|_| wallet.get_state_commitment());
+            assert_eq!(zk_result.is_ok(), result.is_ok());
+            zk_result
+        };
+
+        let mut wallet = Wallet::new(&ContractName::new("test"), &None).unwrap();
+        let root = wallet.get_smt_root();
+
+        // The placeholder key is replaced without a signature.
+        let commitment = execute(&mut wallet, &calldata(first_public_key, root, None)).unwrap();
+        assert_eq!(commitment, wallet.get_state_commitment());
+        assert_eq!(wallet.invite_code_public_key(), first_public_key);
+
+        // Later rotations are signed with the current key, at the current root.
+        assert!(execute(&mut wallet, &calldata(next_public_key, root, None)).is_err());
+        assert!(execute(
+            &mut wallet,
+            &calldata(next_public_key, root, Some(&next_key))
+        )
+        .is_err());
+        assert!(execute(
+            &mut wallet,
+            &calldata(next_public_key, [1; 32], Some(&first_key))
+        )
+        .is_err());
+        assert_eq!(wallet.invite_code_public_key(), first_public_key);
+
+        let commitment = execute(
+            &mut wallet,
+            &calldata(next_public_key, root, Some(&first_key)),
+        )
+        .unwrap();
+        assert_eq!(commitment, wallet.get_state_commitment());
+        assert_eq!(wallet.invite_code_public_key(), next_public_key);
+    }
+
+    #[test]
+    fn test_webauthn_assertion() {
+        use p256::{
+            ecdsa::{signature::Signer, SigningKey},
+            elliptic_curve::sec1::ToEncodedPoint,
+        };
+
+        let signing_key = SigningKey::from_slice(&[7; 32]).unwrap();
+        let public_key = hex::encode(
+            signing_key
+                .verifying_key()
+                .to_encoded_point(true)
+                .as_bytes(),
+        );
+        let signed_assertion = |client_data_json: String, rp_id: &str, flags: u8| {
+            let client_data_json = client_data_json.into_bytes();
+            let mut authenticator_data = Sha256::digest(rp_id.as_bytes()).to_vec();
+            authenticator_data.push(flags);
+            authenticator_data.extend_from_slice(&1u32.to_be_bytes());
+            let mut signed = authenticator_data.clone();
+            signed.extend_from_slice(&Sha256::digest(&client_data_json));
+            let signature: P256Signature = signing_key.sign(&signed);
+            WebAuthnAssertion {
+                authenticator_data,
+                client_data_json,
+                signature: signature.to_bytes().to_vec(),
+            }
+        };
+        let client_data = |account: &str, nonce: u128, origin: &str| {
+            let challenge = URL_SAFE_NO_PAD.encode(webauthn_challenge(account, nonce));
+            format!(
+                r#"{{"type":"webauthn.get","challenge":"{challenge}","origin":"{origin}","crossOrigin":false}}"#
+            )
+        };
+        let assertion = |account: &str, nonce: u128, flags: u8| {
+            signed_assertion(
+                client_data(account, nonce, "https://wallet.hyli.org"),
+                "wallet.hyli.org",
+                flags,
+            )
+        };
+        let auth_method = AuthMethod::WebAuthn {
+            public_key: public_key.clone(),
+            rp_id: "wallet.hyli.org".to_string(),
+        };
+        let register = WalletAction::RegisterIdentity {
+            account: "bob".to_string(),
+            nonce: 0,
+            salt: "salt".to_string(),
+            auth_method: auth_method.clone(),
+            invite_code: "test_invite_code".to_string(),
+        };
+        let calldata = |assertion: WebAuthnAssertion, index: usize| {
+            let blobs = vec![
+                register.as_blob(ContractName::new("wallet")),
+                WalletAction::WebAuthnAssertion {
+                    account: "bob".to_string(),
+                    assertion,
+                }
+                .as_blob(ContractName::new("wallet")),
+            ];
+            Calldata {
+                identity: "bob@wallet".into(),
+                tx_blob_count: blobs.len(),
+                blobs: IndexedBlobs::from(blobs),
+                index: BlobIndex(index),
+                ..Default::default()
+            }
+        };
+
+        assert!(auth_method
+            .verify(&calldata(assertion("bob", 0, 0x05), 0), 0)
+            .is_ok());
+        // Bound to the account and its nonce, with the user present.
+        assert!(auth_method
+            .verify(&calldata(assertion("bob", 1, 0x05), 0), 0)
+            .is_err());
+        assert!(auth_method
+            .verify(&calldata(assertion("alice", 0, 0x05), 0), 0)
+            .is_err());
+        assert!(auth_method
+            .verify(&calldata(assertion("bob", 0, 0x04), 0), 0)
+            .is_err());
+        let mut tampered = assertion("bob", 0, 0x05);
+        tampered.authenticator_data[0] ^= 1;
+        assert!(auth_method.verify(&calldata(tampered, 0), 0).is_err());
+        let other_key = AuthMethod::WebAuthn {
+            public_key: hex::encode(
+                SigningKey::from_slice(&[8; 32])
+                    .unwrap()
+                    .verifying_key()
+                    .to_encoded_point(true)
+                    .as_bytes(),
+            ),
+            rp_id: "wallet.hyli.org".to_string(),
+        };
+        assert!(other_key
+            .verify(&calldata(assertion("bob", 0, 0x05), 0), 0)
+            .is_err());
+
+        // Made for the relying party, from one of its origins.
+        let verify = |client_data_json: String, rp_id: &str| {
+            auth_method.verify(
+                &calldata(signed_assertion(client_data_json, rp_id, 0x05), 0),
+                0,
+            )
+        };
+        assert!(verify(client_data("bob", 0, "https://wallet.hyli.org"), "hyli.org").is_err());
+        for origin in [
+            "https://evil.org",
+            "https://wallet.hyli.org.evil.org",
+            "https://evilwallet.hyli.org",
+            "http://wallet.hyli.org",
+        ] {
+            assert!(verify(client_data("bob", 0, origin), "wallet.hyli.org").is_err());
+        }
+        assert!(verify(
+            client_data("bob", 0, "https://app.wallet.hyli.org:8443"),
+            "wallet.hyli.org"
+        )
+        .is_ok());
+        let challenge = URL_SAFE_NO_PAD.encode(webauthn_challenge("bob", 0));
+        assert!(verify(
+            format!(
+                r#"{{"type":"webauthn.get","challenge":"{challenge}","origin":"https://wallet.hyli.org","crossOrigin":true}}"#
+            ),
+            "wallet.hyli.org"
+        )
+        .is_err());
+        assert!(verify(
+            format!(
+                r#"{{"type":"webauthn.create","challenge":"{challenge}","origin":"https://wallet.hyli.org"}}"#
+            ),
+            "wallet.hyli.org"
+        )
+        .is_err());
+        // Parsed, not matched against one serialization.
+        assert!(verify(
+            format!(
+                r#"{{"origin": "https://wallet.hyli.org", "challenge": "{challenge}", "type": "webauthn.get", "other_keys_can_be_added_here": "do not compare clientDataJSON against a template"}}"#
+            ),
+            "wallet.hyli.org"
+        )
+        .is_ok());
+        assert!(AuthMethod::WebAuthn {
+            public_key: public_key.clone(),
+            rp_id: String::new(),
+        }
+        .is_unusable());
+
+        // Both blobs of the registration prove, the assertion changing nothing.
+        let mut wallet = Wallet::new(&ContractName::new("test"), &None).unwrap();
+        for index in [0, 1] {
+            let calldata = calldata(assertion("bob", 0, 0x05), index);
+            let v = wallet.build_commitment_metadata(&calldata).unwrap();
+            let mut zk_view: WalletZkView = borsh::from_slice(&v).unwrap();
+            zk_view.execute(&calldata).expect("zk execution");
+            wallet.handle(&calldata).expect("execution");
+            assert_eq!(zk_view.commitment, wallet.get_state_commitment());
+        }
+        assert_eq!(
+            wallet.get(&"bob".to_string()).unwrap().auth_method,
+            auth_method
+        );
+
+        // Without an action it authenticates, the assertion doesn't authorize the account for
+        // the other blobs of the transaction.
+        let blobs = vec![
+            WalletAction::WebAuthnAssertion {
+                account: "bob".to_string(),
+                assertion: assertion("bob", 1, 0x05),
+            }
+            .as_blob(ContractName::new("wallet")),
+            Blob {
+                contract_name: ContractName::new("oranj"),
+                data: sdk::BlobData(vec![]),
+            },
+        ];
+        let calldata = Calldata {
+            identity: "bob@wallet".into(),
+            tx_blob_count: blobs.len(),
+            blobs: IndexedBlobs::from(blobs),
+            index: BlobIndex(0),
+            ..Default::default()
+        };
+        assert!(!wallet.handle(&calldata).unwrap().success);
+    }
+
+    #[test]
+    fn test_multi_factor_auth() {
+        let password = b"password".to_vec();
+        let mail_hash = [4u8; 32];
+        let mut account_info = AccountInfo {
+            identity: "bob".to_string(),
+            auth_method: AuthMethod::Multi(vec![
+                AuthMethod::Password {
+                    hash: hex::encode(&password),
+                },
+                AuthMethod::Jwt { hash: mail_hash },
+            ]),
+            nonce: 1700000000000,
+            ..Default::default()
+        };
+        let add_session_key = |nonce: u128| WalletAction::AddSessionKey {
+            account: "bob".to_string(),
+            key: "session-key".to_string(),
+            expiration_date: u128::MAX,
+            whitelist: None,
+            lane_id: None,
+            nonce,
+        };
+        let calldata = |action: &WalletAction, secret: Option<&[u8]>, jwt_nonce: Option<u128>| {
+            let mut blobs = vec![action.as_blob(ContractName::new("wallet"))];
+            if let Some(secret) = secret {
+                blobs.push(Blob {
+                    contract_name: ContractName::new("check_secret"),
+                    data: sdk::BlobData(secret.to_vec()),
+                });
+            }
+            if let Some(nonce) = jwt_nonce {
+                let mut check_jwt = mail_hash.to_vec();
+                check_jwt.push(b':');
+                check_jwt.extend_from_slice(nonce.to_string().as_bytes());
+                check_jwt.extend_from_slice(JWT_ID_TAG);
+                check_jwt.push(5);
+                check_jwt.extend_from_slice(b"jti-1");
+                blobs.push(Blob {
+                    contract_name: ContractName::new("check_jwt"),
+                    data: sdk::BlobData(check_jwt),
+                });
+            }
+            Calldata {
+                tx_hash: sdk::TxHash("aa".to_string()),
+                blobs: IndexedBlobs::from(blobs),
+                index: BlobIndex(0),
+                tx_ctx: Some(sdk::TxContext::default()),
+                ..Default::default()
+            }
+        };
+
+        // Every factor is required.
+        let action = add_session_key(1700000000001);
+        for (secret, jwt_nonce) in [
+            (Some(password.as_slice()), None),
+            (None, Some(1700000000001)),
+            (Some(b"guess".as_slice()), Some(1700000000001)),
+            (Some(password.as_slice()), Some(1700000000002)),
+        ] {
+            assert!(account_info
+                .clone()
+                .handle_authenticated_action(
+                    action.clone(),
+                    &calldata(&action, secret, jwt_nonce),
+                    &TimePolicy::default(),
+                )
+                .is_err());
+        }
+
+        account_info
+            .handle_authenticated_action(
+                action.clone(),
+                &calldata(&action, Some(&password), Some(1700000000001)),
+                &TimePolicy::default(),
+            )
+            .expect("both factors");
+        assert_eq!(account_info.session_keys.len(), 1);
+        // The JWT factor's id is recorded as for a JWT auth method.
+        assert_eq!(account_info.used_jwt_ids.len(), 1);
+
+        // A multi-factor method without factors authenticates nothing.
+        let update = WalletAction::UpdateAuthMethod {
+            account: "bob".to_string(),
+            nonce: 1700000000002,
+            auth_method: AuthMethod::Multi(vec![]),
+            salt: String::new(),
+        };
+        assert_eq!(
+            account_info.handle_authenticated_action(
+                update.clone(),
+                &calldata(&update, Some(&password), Some(1700000000002)),
+                &TimePolicy::default(),
+            ),
+            Err("Auth method can't be reset".to_string())
+        );
+
+        // Nor can factors be multi-factor, or several read the secp256k1 blob.
+        let nested = AuthMethod::Multi(vec![AuthMethod::Multi(vec![AuthMethod::Password {
+            hash: hex::encode(&password),
+        }])]);
+        for auth_method in [
+            nested.clone(),
+            AuthMethod::Multi(vec![
+                AuthMethod::HyliApp {
+                    address: "aa".to_string(),
+                },
+                AuthMethod::Ethereum {
+                    address: "0xbb".to_string(),
+                },
+            ]),
+        ] {
+            let update = WalletAction::UpdateAuthMethod {
+                account: "bob".to_string(),
+                nonce: 1700000000002,
+                auth_method,
+                salt: String::new(),
+            };
+            assert_eq!(
+                account_info.clone().handle_authenticated_action(
+                    update.clone(),
+                    &calldata(&update, Some(&password), Some(1700000000002)),
+                    &TimePolicy::default(),
+                ),
+                Err("Auth method can't be reset".to_string())
+            );
+        }
+        let register = WalletAction::RegisterIdentity {
+            account: "alice".to_string(),
+            nonce: 1,
+            salt: String::new(),
+            auth_method: nested.clone(),
+            invite_code: String::new(),
+        };
+        let mut alice = AccountInfo {
+            identity: "alice".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            alice.handle_registration(
+                "alice".to_string(),
+                1,
+                nested,
+                &calldata(&register, Some(&password), None),
+            ),
+            Err("Invalid auth method".to_string())
+        );
+    }
+
+    #[test]
+    fn test_renew_session_key() {
+        let password = b"password".to_vec();
+        let session_key = SessionKey {
+            public_key: "session-key".to_string(),
+            expiration_date: TimestampMs(1_000),
+            whitelist: Some(vec![ContractName::new("oranj")]),
+            lane_id: Some(LaneId::default()),
+            ..Default::default()
+        };
+        let mut account_info = AccountInfo {
+            identity: "bob".to_string(),
+            auth_method: AuthMethod::Password {
+                hash: hex::encode(&password),
+            },
+            session_keys: vec![session_key.clone()],
+            ..Default::default()
+        };
+        let renew = |key: &str, new_expiration: u128, nonce: u128| WalletAction::RenewSessionKey {
+            account: "bob".to_string(),
+            key: key.to_string(),
+            new_expiration,
+            nonce,
+        };
+        let calldata = |action: &WalletAction, secret: &[u8]| Calldata {
+            blobs: IndexedBlobs::from(vec![
+                action.as_blob(ContractName::new("wallet")),
+                Blob {
+                    contract_name: ContractName::new("check_secret"),
+                    data: sdk::BlobData(secret.to_vec()),
+                },
+            ]),
+            index: BlobIndex(0),
+            tx_ctx: Some(sdk::TxContext::default()),
+            ..Default::default()
+        };
+        let handle = |account_info: &mut AccountInfo, action: WalletAction, secret: &[u8]| {
+            account_info.handle_authenticated_action(
+                action.clone(),
+                &calldata(&action, secret),
+                &TimePolicy::default(),
+            )
+        };
+
+        assert!(handle(
+            &mut account_info.clone(),
+            renew("session-key", 5_000, 1),
+            b"guess"
+        )
+        .is_err());
+        assert_eq!(
+            handle(
+                &mut account_info.clone(),
+                renew("unknown", 5_000, 1),
+                &password
+            ),
+            Err("Session key not found".to_string())
+        );
+        assert_eq!(
+            handle(
+                &mut account_info.clone(),
+                renew("session-key", 500, 1),
+                &password
+            ),
+            Err("Session key renewal must extend its expiration".to_string())
+        );
+
+        handle(&mut account_info, renew("session-key", 5_000, 1), &password)
+            .expect("renew session key");
+        assert_eq!(
+            account_info.session_keys,
+            vec![SessionKey {
+                expiration_date: TimestampMs(5_000),
+                ..session_key
+            }]
+        );
+        assert_eq!(account_info.nonce, 1);
+    }
+
+    #[test]
+    fn test_session_key_limits() {
+        use secp256k1::{Message, PublicKey, Secp256k1, SecretKey};
+
+        let secp = Secp256k1::new();
+        let secret_key = SecretKey::from_slice(&[7; 32]).unwrap();
+        let public_key = PublicKey::from_secret_key(&secp, &secret_key);
+        let identity: sdk::Identity = "bob@wallet".into();
+
+        let mut account_info = AccountInfo {
+            identity: "bob".to_string(),
+            auth_method: AuthMethod::Password {
+                hash: "00".to_string(),
+            },
+            session_keys: vec![SessionKey {
+                public_key: hex::encode(public_key.serialize()),
+                expiration_date: TimestampMs(100),
+                remaining_uses: Some(2),
+                max_value_per_tx: Some(100),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let transfer = |sender: &sdk::Identity, amount: u128| Blob {
+            contract_name: ContractName::new("oranj"),
+            data: sdk::BlobData(
+                borsh::to_vec(&SmtTokenAction::Transfer {
+                    sender: sender.clone(),
+                    recipient: "alice@wallet".into(),
+                    amount,
+                })
+                .unwrap(),
+            ),
+        };
+        let calldata = |nonce: u128, transfers: Vec<Blob>| {
+            let digest: [u8; 32] = Sha256::digest(nonce.to_string().as_bytes()).into();
+            let signature = secp.sign_ecdsa(&Message::from_digest(digest), &secret_key);
+            let mut blobs = vec![
+                WalletAction::UseSessionKey {
+                    account: "bob".to_string(),
+                    nonce,
+                }
+                .as_blob(ContractName::new("wallet")),
+                Secp256k1Blob::new(
+                    identity.clone(),
+                    nonce.to_string().as_bytes(),
+                    &public_key.to_string(),
+                    &signature.to_string(),
+                )
+                .unwrap()
+                .as_blob(),
+            ];
+            blobs.extend(transfers);
+            Calldata {
+                identity: identity.clone(),
+                tx_blob_count: blobs.len(),
+                blobs: IndexedBlobs::from(blobs),
+                index: BlobIndex(0),
+                tx_ctx: Some(sdk::TxContext {
+                    timestamp: TimestampMs(10),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }
+        };
+        let use_key = |account_info: &mut AccountInfo, nonce: u128, transfers: Vec<Blob>| {
+            account_info.handle_session_key_usage(
+                "bob".to_string(),
+                nonce,
+                &calldata(nonce, transfers),
+                &TimePolicy::default(),
+            )
+        };
+
+        // Transfers from other accounts don't count.
+        let other: sdk::Identity = "carol@wallet".into();
+        use_key(
+            &mut account_info,
+            1,
+            vec![transfer(&identity, 50), transfer(&other, 500)],
+        )
+        .expect("within the limits");
+        assert_eq!(account_info.session_keys[0].remaining_uses, Some(1));
+
+        assert_eq!(
+            use_key(
+                &mut account_info.clone(),
+                2,
+                vec![transfer(&identity, 60), transfer(&identity, 60)],
+            ),
+            Err("Session key can move at most 100 per transaction, got 120".to_string())
+        );
+
+        use_key(&mut account_info, 2, vec![transfer(&identity, 100)]).expect("at the value limit");
+        assert_eq!(account_info.session_keys[0].remaining_uses, Some(0));
+
+        assert_eq!(
+            use_key(&mut account_info, 3, vec![transfer(&identity, 1)]),
+            Err("Session key has no uses left".to_string())
+        );
+
+        // Keys with limits are hashed with them, keys without as before limits existed.
+        let mut unlimited = account_info.clone();
+        unlimited.session_keys[0].remaining_uses = None;
+        unlimited.session_keys[0].max_value_per_tx = None;
+        let legacy = borsh::to_vec(&(
+            &unlimited.identity,
+            &unlimited.auth_method,
+            unlimited
+                .session_keys
+                .iter()
+                .map(|sk| {
+                    (
+                        &sk.public_key,
+                        &sk.expiration_date,
+                        &sk.whitelist,
+                        &sk.lane_id,
+                    )
+                })
+                .collect::<Vec<_>>(),
+            unlimited.nonce,
+        ))
+        .unwrap();
+        let legacy_hash: [u8; 32] = Sha256::digest(&legacy).into();
+        assert_eq!(unlimited.to_h256(), H256::from(legacy_hash));
+        assert_ne!(account_info.to_h256(), unlimited.to_h256());
+    }
+
+    #[test]
+    fn test_freeze_account() {
+        let password = b"password".to_vec();
+        let mut account_info = AccountInfo {
+            identity: "bob".to_string(),
+            auth_method: AuthMethod::Password {
+                hash: hex::encode(&password),
+            },
+            ..Default::default()
+        };
+        let unfrozen_hash = account_info.to_h256();
+        let calldata = |action: &WalletAction, secret: &[u8]| Calldata {
+            blobs: IndexedBlobs::from(vec![
+                action.as_blob(ContractName::new("wallet")),
+                Blob {
+                    contract_name: ContractName::new("check_secret"),
+                    data: sdk::BlobData(secret.to_vec()),
+                },
+            ]),
+            index: BlobIndex(0),
+            tx_ctx: Some(sdk::TxContext::default()),
+            ..Default::default()
+        };
+        let handle = |account_info: &mut AccountInfo, action: WalletAction, secret: &[u8]| {
+            account_info.handle_authenticated_action(
+                action.clone(),
+                &calldata(&action, secret),
+                &TimePolicy::default(),
+            )
+        };
+        let account = "bob".to_string();
+
+        let freeze = WalletAction::FreezeAccount {
+            account: account.clone(),
+            nonce: 1,
+        };
+        assert!(handle(&mut account_info.clone(), freeze.clone(), b"guess").is_err());
+        handle(&mut account_info, freeze, &password).expect("freeze");
+        assert!(account_info.frozen);
+        assert_ne!(account_info.to_h256(), unfrozen_hash);
+
+        let verify = WalletAction::VerifyIdentity {
+            account: account.clone(),
+            nonce: 2,
+        };
+        assert_eq!(
+            handle(&mut account_info.clone(), verify.clone(), &password),
+            Err("Account is frozen".to_string())
+        );
+        let use_session_key = WalletAction::UseSessionKey {
+            account: account.clone(),
+            nonce: 2,
+        };
+        assert_eq!(
+            account_info.clone().handle_session_key_usage(
+                account.clone(),
+                2,
+                &calldata(&use_session_key, &[]),
+                &TimePolicy::default(),
+            ),
+            Err("Account is frozen".to_string())
+        );
+        // No other authenticated action authorizes the account while it is frozen.
+        let add_session_key = WalletAction::AddSessionKey {
+            account: account.clone(),
+            key: "key".to_string(),
+            expiration_date: 1_000,
+            whitelist: None,
+            lane_id: None,
+            nonce: 2,
+        };
+        assert_eq!(
+            handle(&mut account_info.clone(), add_session_key, &password),
+            Err("Account is frozen".to_string())
+        );
+        let freeze_again = WalletAction::FreezeAccount {
+            account: account.clone(),
+            nonce: 2,
+        };
+        assert_eq!(
+            handle(&mut account_info.clone(), freeze_again, &password),
+            Err("Account is frozen".to_string())
+        );
+
+        let unfreeze = WalletAction::UnfreezeAccount {
+            account: account.clone(),
+            nonce: 2,
+        };
+        handle(&mut account_info, unfreeze, &password).expect("unfreeze");
+        assert!(!account_info.frozen);
+        let verify = WalletAction::VerifyIdentity { account, nonce: 3 };
+        handle(&mut account_info, verify, &password).expect("verify once unfrozen");
+    }
+
+    #[test]
+    fn test_batch() {
+        let password = b"password".to_vec();
+        let register = WalletAction::RegisterIdentity {
+            account: "bob".to_string(),
+            nonce: 1,
+            salt: "salt".to_string(),
+            auth_method: AuthMethod::Password {
+                hash: hex::encode(&password),
+            },
+            invite_code: "test_invite_code".to_string(),
+        };
+        let add_session_key = |key: &str, nonce: u128| WalletAction::AddSessionKey {
+            account: "bob".to_string(),
+            key: key.to_string(),
+            expiration_date: 1_000,
+            whitelist: None,
+            lane_id: None,
+            nonce,
+        };
+        let calldata = |actions: Vec<WalletAction>| Calldata {
+            identity: "bob@wallet".into(),
+            blobs: IndexedBlobs::from(vec![
+                WalletAction::Batch(actions).as_blob(ContractName::new("wallet")),
+                Blob {
+                    contract_name: ContractName::new("check_secret"),
+                    data: sdk::BlobData(password.clone()),
+                },
+            ]),
+            index: BlobIndex(0),
+            tx_ctx: Some(sdk::TxContext::default()),
+            ..Default::default()
+        };
+        let execute = |wallet: &mut Wallet, calldata: &Calldata| {
+            let v = wallet.build_commitment_metadata(calldata).unwrap();
+            let mut zk_view: WalletZkView = borsh::from_slice(&v).unwrap();
+            let zk_result = zk_view.execute(calldata).map(|_| zk_view.commitment);
+            let result = wallet
+                .handle(calldata)
+                .map(|_| wallet.get_state_commitment());
+            assert_eq!(zk_result.is_ok(), result.is_ok());
+            zk_result
+        };
+
+        let mut wallet = Wallet::new(&ContractName::new("test"), &None).unwrap();
+        let initial = wallet.get_state_commitment();
+        assert!(execute(&mut wallet, &calldata(vec![])).is_err());
+        assert!(execute(
+            &mut wallet,
+            &calldata(vec![
+                register.clone(),
+                WalletAction::Batch(vec![add_session_key("key", 1)])
+            ])
+        )
+        .is_err());
+        assert!(execute(
+            &mut wallet,
+            &calldata(vec![
+                register.clone(),
+                WalletAction::AddSessionKey {
+                    account: "alice".to_string(),
+                    ..add_session_key("key", 1)
+                }
+            ])
+        )
+        .is_err());
+        // A failing action reverts the registration before it.
+        assert!(execute(
+            &mut wallet,
+            &calldata(vec![register.clone(), add_session_key("key", 0)])
+        )
+        .is_err());
+        assert_eq!(wallet.get_state_commitment(), initial);
+
+        // Later actions authenticate at the nonce the registration set.
+        let commitment = execute(
+            &mut wallet,
+            &calldata(vec![register.clone(), add_session_key("key", 1)]),
+        )
+        .expect("register with a session key");
+        assert_eq!(commitment, wallet.get_state_commitment());
+        let account_info = wallet.get(&"bob".to_string()).unwrap();
+        assert_eq!(account_info.nonce, 1);
+        assert_eq!(account_info.session_keys.len(), 1);
+        assert_eq!(wallet.get_salt(&"bob".to_string()).unwrap(), "salt");
+
+        // The nonce set by a previous transaction can't be reused.
+        assert!(execute(
+            &mut wallet,
+            &calldata(vec![
+                add_session_key("other", 1),
+                add_session_key("other", 2)
+            ])
+        )
+        .is_err());
+        execute(
+            &mut wallet,
+            &calldata(vec![
+                add_session_key("other", 2),
+                add_session_key("third", 2),
+            ]),
+        )
+        .expect("add session keys");
+        assert_eq!(
+            wallet.get(&"bob".to_string()).unwrap().session_keys.len(),
+            3
+        );
+    }
+
+    #[test]
+    fn test_ed25519_session_key() {
+        use ed25519_dalek::{Signer, SigningKey};
+
+        let signing_key = SigningKey::from_bytes(&[7; 32]);
+        let mut account_info = AccountInfo {
+            identity: "bob".to_string(),
+            auth_method: AuthMethod::Password {
+                hash: hex::encode(b"password"),
+            },
+            ..Default::default()
+        };
+        account_info
+            .add_session_key(SessionKey {
+                public_key: hex::encode(signing_key.verifying_key().as_bytes()),
+                expiration_date: TimestampMs(1_000),
+                whitelist: Some(vec![ContractName::new("oranj")]),
+                ..Default::default()
+            })
+            .expect("add ed25519 session key");
+        assert_eq!(
+            account_info.session_keys[0].key_type,
+            SessionKeyType::Ed25519
+        );
+
+        let calldata = |key: &SigningKey, signed_nonce: u128| {
+            let blobs = vec![
+                WalletAction::UseSessionKey {
+                    account: "bob".to_string(),
+                    nonce: 1,
+                }
+                .as_blob(ContractName::new("wallet")),
+                WalletAction::Ed25519Signature {
+                    account: "bob".to_string(),
+                    public_key: hex::encode(key.verifying_key().as_bytes()),
+                    signature: key
+                        .sign(ed25519_session_key_message("bob", signed_nonce).as_bytes())
+                        .to_bytes()
+                        .to_vec(),
+                }
+                .as_blob(ContractName::new("wallet")),
+                Blob {
+                    contract_name: ContractName::new("oranj"),
+                    data: sdk::BlobData(vec![]),
+                },
+            ];
+            Calldata {
+                identity: "bob@wallet".into(),
+                tx_blob_count: blobs.len(),
+                blobs: IndexedBlobs::from(blobs),
+                index: BlobIndex(0),
+                tx_ctx: Some(sdk::TxContext {
+                    timestamp: TimestampMs(500),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }
+        };
+        let use_key = |account_info: &mut AccountInfo, calldata: &Calldata| {
+            account_info.handle_session_key_usage(
+                "bob".to_string(),
+                1,
+                calldata,
+                &TimePolicy::default(),
+            )
+        };
+
+        // The signature blob is exempt from the whitelist, like the secp256k1 verifier blob.
+        assert_eq!(
+            use_key(&mut account_info.clone(), &calldata(&signing_key, 1)),
+            Ok("Session key is valid".to_string())
+        );
+        assert_eq!(
+            use_key(&mut account_info.clone(), &calldata(&signing_key, 2)),
+            Err("Invalid ed25519 session key signature".to_string())
+        );
+        assert_eq!(
+            use_key(
+                &mut account_info.clone(),
+                &calldata(&SigningKey::from_bytes(&[8; 32]), 1)
+            ),
+            Err("Session key not found".to_string())
+        );
+        // The signature blob authorizes nothing without the session key use.
+        let carry = |calldata: &Calldata| {
+            let blob = calldata.blobs.get(&calldata.index).unwrap();
+            account_info.clone().handle_action(
+                WalletAction::from_blob_data(&blob.data).unwrap(),
+                calldata,
+                &DEFAULT_INVITE_CODE_PUBLIC_KEY,
+                &TimePolicy::default(),
+            )
+        };
+        let mut carried = calldata(&signing_key, 1);
+        carried.index = BlobIndex(1);
+        assert!(carry(&carried).is_ok());
+        let mut alone = carried.clone();
+        alone.blobs = IndexedBlobs::from(vec![
+            carried.blobs.get(&BlobIndex(1)).unwrap().clone(),
+            carried.blobs.get(&BlobIndex(2)).unwrap().clone(),
+        ]);
+        alone.index = BlobIndex(0);
+        alone.tx_blob_count = 2;
+        assert_eq!(
+            carry(&alone),
+            Err("Ed25519 signature without a session key use".to_string())
+        );
+
+        // The key type is only hashed once an account has an ed25519 key.
+        let mut secp256k1 = account_info.clone();
+        secp256k1.session_keys[0].key_type = SessionKeyType::Secp256k1;
+        assert_ne!(account_info.to_h256(), secp256k1.to_h256());
     }
 }
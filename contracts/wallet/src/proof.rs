@@ -0,0 +1,115 @@
+//! Merkle proofs of a [`WalletZkView`](crate::WalletZkView) with their siblings shared.
+//!
+//! A batch carries one proof per wallet blob. Proofs of an account touched by several blobs, or
+//! of accounts close in the tree, have many siblings in common: the view stores each distinct
+//! sibling once and proofs refer to them by index, shrinking the guest input and the cycles
+//! spent decoding it.
+
+use std::collections::BTreeMap;
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use sdk::merkle_utils::{BorshableMerkleProof, SHA256Hasher};
+use sparse_merkle_tree::{merge::MergeValue, MerkleProof, H256};
+
+/// A proof whose path points into [`ProofSiblings`].
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct CompressedProof {
+    pub leaves_bitmap: Vec<[u8; 32]>,
+    /// Indices of the siblings of the proof, from the leaves up.
+    pub path: Vec<u32>,
+}
+
+/// Distinct siblings of the proofs of a view.
+#[derive(Debug, Clone, Default)]
+pub struct ProofSiblings {
+    siblings: Vec<MergeValue>,
+    /// Indices of the siblings by hash, to find duplicates when compressing. Not serialized,
+    /// rebuilt on the first compression after decoding.
+    index: BTreeMap<H256, Vec<u32>>,
+}
+
+impl ProofSiblings {
+    pub fn len(&self) -> usize {
+        self.siblings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.siblings.is_empty()
+    }
+
+    fn intern(&mut self, sibling: MergeValue) -> u32 {
+        if self.index.is_empty() && !self.siblings.is_empty() {
+            for (i, s) in self.siblings.iter().enumerate() {
+                self.index
+                    .entry(s.hash::<SHA256Hasher>())
+                    .or_default()
+                    .push(i as u32);
+            }
+        }
+
+        // Different encodings of a node can share a hash, only an identical sibling is reused.
+        let candidates = self
+            .index
+            .entry(sibling.hash::<SHA256Hasher>())
+            .or_default();
+        if let Some(&i) = candidates
+            .iter()
+            .find(|&&i| self.siblings[i as usize] == sibling)
+        {
+            return i;
+        }
+        let i = self.siblings.len() as u32;
+        candidates.push(i);
+        self.siblings.push(sibling);
+        i
+    }
+
+    /// Moves the siblings of `proof` into the table.
+    pub fn compress(&mut self, proof: MerkleProof) -> CompressedProof {
+        let (leaves_bitmap, merkle_path) = proof.take();
+        CompressedProof {
+            leaves_bitmap: leaves_bitmap.into_iter().map(Into::into).collect(),
+            path: merkle_path.into_iter().map(|s| self.intern(s)).collect(),
+        }
+    }
+
+    /// Rebuilds the full proof of `proof`.
+    pub fn decompress(&self, proof: &CompressedProof) -> Result<MerkleProof, String> {
+        let merkle_path = proof
+            .path
+            .iter()
+            .map(|&i| {
+                self.siblings
+                    .get(i as usize)
+                    .cloned()
+                    .ok_or_else(|| format!("Proof sibling {i} out of range"))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(MerkleProof::new(
+            proof
+                .leaves_bitmap
+                .iter()
+                .copied()
+                .map(H256::from)
+                .collect(),
+            merkle_path,
+        ))
+    }
+}
+
+// The table is encoded as the path of a proof without leaves, to reuse its sibling encoding.
+impl BorshSerialize for ProofSiblings {
+    fn serialize<W: borsh::io::Write>(&self, writer: &mut W) -> borsh::io::Result<()> {
+        BorshableMerkleProof(MerkleProof::new(vec![], self.siblings.clone())).serialize(writer)
+    }
+}
+
+impl BorshDeserialize for ProofSiblings {
+    fn deserialize_reader<R: borsh::io::Read>(reader: &mut R) -> borsh::io::Result<Self> {
+        let (_, siblings) = BorshableMerkleProof::deserialize_reader(reader)?.0.take();
+        Ok(Self {
+            siblings,
+            index: BTreeMap::new(),
+        })
+    }
+}
@@ -1,14 +1,178 @@
+use std::collections::VecDeque;
+
 use borsh::{BorshDeserialize, BorshSerialize};
 use sdk::merkle_utils::SHA256Hasher;
 use serde::ser::{Serialize, SerializeSeq, Serializer};
 use sha2::{Digest, Sha256};
-use sparse_merkle_tree::{default_store::DefaultStore, traits::Value, SparseMerkleTree, H256};
+use sparse_merkle_tree::{
+    default_store::DefaultStore,
+    error::Error,
+    merge::{merge, MergeValue},
+    traits::{StoreReadOps, StoreWriteOps, Value},
+    tree::{BranchKey, BranchNode},
+    SparseMerkleTree, H256,
+};
 
-use crate::{AccountInfo, AuthMethod};
+use crate::{AccountInfo, AuthMethod, FailedAuth, Recovery, SessionKey, SessionKeyType};
 
 #[derive(Debug, Default)]
 pub struct AccountSMT(pub SparseMerkleTree<SHA256Hasher, AccountInfo, DefaultStore<AccountInfo>>);
 
+/// Number of top levels of the tree above the shards of [`AccountSMT::update_accounts`], which
+/// splits keys in `2^SHARD_BITS` shards by their highest bits.
+const SHARD_BITS: u8 = 4;
+/// Below this many updates, threads cost more than they save.
+const MIN_PARALLEL_UPDATES: usize = 1024;
+
+/// Replaces the count of the legacy session keys encoding, which no account can reach, in
+/// the leaf of accounts whose session keys need another encoding. The encoding's version
+/// follows.
+const SESSION_KEYS_TAG: u32 = u32::MAX;
+/// Secp256k1 session keys with usage limits.
+const SESSION_KEYS_LIMITED: u8 = 1;
+/// Session keys of any type, with all their fields.
+const SESSION_KEYS_TYPED: u8 = 2;
+
+/// A node being merged up the tree: its key, value and height.
+type Node = (H256, MergeValue, u8);
+
+/// Merges sorted `nodes` level by level as `SparseMerkleTree::update_all` does, reading
+/// siblings missing from `nodes` in `store` and collecting the branches to write. Nodes reaching
+/// height `until` are returned unmerged, the root is returned once height 255 is merged.
+fn merge_levels(
+    store: &DefaultStore<AccountInfo>,
+    mut nodes: VecDeque<Node>,
+    until: Option<u8>,
+    branches: &mut Vec<(BranchKey, Option<BranchNode>)>,
+) -> Result<(Vec<Node>, Option<H256>), Error> {
+    let mut reached = vec![];
+    while let Some((key, value, height)) = nodes.pop_front() {
+        if Some(height) == until {
+            reached.push((key, value, height));
+            continue;
+        }
+        let parent_key = key.parent_path(height);
+        let parent_branch_key = BranchKey::new(height, parent_key);
+
+        let mut right = None;
+        if !key.is_right(height) {
+            if let Some((right_key, _, right_height)) = nodes.front() {
+                if *right_height == height && right_key.parent_path(height) == parent_key {
+                    right = nodes.pop_front().map(|(_, value, _)| value);
+                }
+            }
+        }
+        let (left, right) = match right {
+            Some(right) => (value, right),
+            None => match store.get_branch(&parent_branch_key)? {
+                Some(parent) if key.is_right(height) => (parent.left, value),
+                Some(parent) => (value, parent.right),
+                None if key.is_right(height) => (MergeValue::zero(), value),
+                None => (value, MergeValue::zero()),
+            },
+        };
+
+        let merged = merge::<SHA256Hasher>(height, &parent_key, &left, &right);
+        if !left.is_zero() || !right.is_zero() {
+            branches.push((parent_branch_key, Some(BranchNode { left, right })));
+        } else {
+            branches.push((parent_branch_key, None));
+        }
+        if height == u8::MAX {
+            return Ok((reached, Some(merged.hash::<SHA256Hasher>())));
+        }
+        nodes.push_back((parent_key, merged, height + 1));
+    }
+    Ok((reached, None))
+}
+
+impl AccountSMT {
+    /// Inserts or replaces many accounts at once, later ones winning. Accounts are split in
+    /// shards by key prefix, each merged on its own thread up to the shard's subtree, and the
+    /// subtree roots are combined into the tree's root, which is the same as updating them one
+    /// by one.
+    pub fn update_accounts(&mut self, accounts: Vec<AccountInfo>) -> Result<H256, Error> {
+        let mut leaves: Vec<(H256, AccountInfo)> = accounts
+            .into_iter()
+            .map(|account| (AccountInfo::compute_key(&account.identity), account))
+            .collect();
+        // Keep the last update of each key.
+        leaves.reverse();
+        leaves.sort_by_key(|(key, _)| *key);
+        leaves.dedup_by_key(|(key, _)| *key);
+        if leaves.is_empty() {
+            return Ok(*self.0.root());
+        }
+
+        let shard_of = |key: &H256| key.as_slice()[31] >> (8 - SHARD_BITS);
+        let mut shards: Vec<Vec<(H256, AccountInfo)>> = vec![vec![]; 1usize << SHARD_BITS];
+        let parallel = leaves.len() >= MIN_PARALLEL_UPDATES;
+        for (key, account) in leaves {
+            shards[shard_of(&key) as usize].push((key, account));
+        }
+
+        let store = self.0.store();
+        let merge_shard = |shard: &[(H256, AccountInfo)]| {
+            let nodes = shard
+                .iter()
+                .map(|(key, account)| (*key, MergeValue::from_h256(account.to_h256()), 0))
+                .collect();
+            let mut branches = vec![];
+            let (reached, _) =
+                merge_levels(store, nodes, Some(u8::MAX - SHARD_BITS + 1), &mut branches)?;
+            Ok::<_, Error>((reached, branches))
+        };
+        let merged: Vec<_> = if parallel {
+            std::thread::scope(|scope| {
+                let handles: Vec<_> = shards
+                    .iter()
+                    .filter(|shard| !shard.is_empty())
+                    .map(|shard| scope.spawn(|| merge_shard(shard)))
+                    .collect();
+                handles
+                    .into_iter()
+                    .map(|handle| handle.join().expect("SMT shard thread panicked"))
+                    .collect()
+            })
+        } else {
+            shards
+                .iter()
+                .filter(|shard| !shard.is_empty())
+                .map(|shard| merge_shard(shard))
+                .collect()
+        };
+
+        // Shards come in key order, so their subtree roots do as well.
+        let mut tops = VecDeque::new();
+        let mut branches = vec![];
+        for shard in merged {
+            let (reached, shard_branches) = shard?;
+            tops.extend(reached);
+            branches.extend(shard_branches);
+        }
+        let (_, root) = merge_levels(store, tops, None, &mut branches)?;
+        let root = root.expect("merging up to the root");
+
+        let mut store = std::mem::take(self.0.store_mut());
+        for (key, account) in shards.into_iter().flatten() {
+            // Uninitialized accounts hash to zero, which the tree doesn't store.
+            if account.auth_method == AuthMethod::Uninitialized {
+                store.remove_leaf(&key)?;
+            } else {
+                store.insert_leaf(key, account)?;
+            }
+        }
+        for (key, branch) in branches {
+            match branch {
+                Some(branch) => store.insert_branch(key, branch)?,
+                None => store.remove_branch(&key)?,
+            }
+        }
+        self.0 = SparseMerkleTree::new(root, store);
+        Ok(root)
+    }
+}
+
 impl Clone for AccountSMT {
     fn clone(&self) -> Self {
         let store = self.0.store().clone();
@@ -47,16 +211,18 @@ impl BorshSerialize for AccountSMT {
 impl BorshDeserialize for AccountSMT {
     fn deserialize_reader<R: std::io::Read>(reader: &mut R) -> std::io::Result<Self> {
         let len: u32 = borsh::BorshDeserialize::deserialize_reader(reader)?;
-        let mut accounts = SparseMerkleTree::default();
-        for _ in 0..len {
-            let account: AccountInfo = borsh::BorshDeserialize::deserialize_reader(reader)?;
-            let key = AccountInfo::compute_key(&account.identity);
-            accounts
-                .update(key, account)
-                .expect("Failed to deserialize account");
-        }
+        let accounts = (0..len)
+            .map(|_| borsh::BorshDeserialize::deserialize_reader(reader))
+            .collect::<std::io::Result<Vec<AccountInfo>>>()?;
 
-        Ok(AccountSMT(accounts))
+        let mut smt = AccountSMT::default();
+        smt.update_accounts(accounts).map_err(|e| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("Failed to insert deserialized accounts: {e:?}"),
+            )
+        })?;
+        Ok(smt)
     }
 }
 
@@ -69,6 +235,79 @@ impl AccountInfo {
         h.copy_from_slice(&result);
         H256::from(h)
     }
+
+    /// Encoding of the account hashed into its leaf.
+    fn leaf_data(&self) -> Vec<u8> {
+        let secp256k1_only = self
+            .session_keys
+            .iter()
+            .all(|sk| sk.key_type == SessionKeyType::Secp256k1);
+        let session_keys =
+            if secp256k1_only && self.session_keys.iter().all(SessionKey::is_unlimited) {
+                // Session keys without limits are serialized as before limits existed.
+                let session_keys: Vec<_> = self
+                    .session_keys
+                    .iter()
+                    .map(|sk| {
+                        (
+                            &sk.public_key,
+                            &sk.expiration_date,
+                            &sk.whitelist,
+                            &sk.lane_id,
+                        )
+                    })
+                    .collect();
+                borsh::to_vec(&session_keys).unwrap()
+            } else if secp256k1_only {
+                // Secp256k1 ones with limits without their type, as before key types existed.
+                let session_keys: Vec<_> = self
+                    .session_keys
+                    .iter()
+                    .map(|sk| {
+                        (
+                            &sk.public_key,
+                            &sk.expiration_date,
+                            &sk.whitelist,
+                            &sk.lane_id,
+                            &sk.remaining_uses,
+                            &sk.max_value_per_tx,
+                        )
+                    })
+                    .collect();
+                borsh::to_vec(&(SESSION_KEYS_TAG, SESSION_KEYS_LIMITED, session_keys)).unwrap()
+            } else {
+                borsh::to_vec(&(SESSION_KEYS_TAG, SESSION_KEYS_TYPED, &self.session_keys)).unwrap()
+            };
+        let mut serialized = borsh::to_vec(&(&self.identity, &self.auth_method)).unwrap();
+        serialized.extend(session_keys);
+        serialized.extend(
+            borsh::to_vec(&(
+                self.nonce,
+                &self.used_jwt_ids,
+                &self.failed_auth,
+                &self.recovery,
+                self.frozen,
+            ))
+            .unwrap(),
+        );
+        // Fields appended since the first release are dropped while at their default, trailing
+        // ones first, so accounts hash as they did before those fields existed.
+        if !self.frozen {
+            serialized.truncate(serialized.len() - 1);
+            if self.recovery == Recovery::default() {
+                let default_len = borsh::object_length(&self.recovery).unwrap();
+                serialized.truncate(serialized.len() - default_len);
+                if self.failed_auth == FailedAuth::default() {
+                    let default_len = borsh::object_length(&self.failed_auth).unwrap();
+                    serialized.truncate(serialized.len() - default_len);
+                    if self.used_jwt_ids.is_empty() {
+                        serialized.truncate(serialized.len() - 4);
+                    }
+                }
+            }
+        }
+        serialized
+    }
 }
 
 impl Value for AccountInfo {
@@ -77,9 +316,8 @@ impl Value for AccountInfo {
             return H256::zero();
         }
 
-        let serialized = borsh::to_vec(self).unwrap();
         let mut hasher = Sha256::new();
-        hasher.update(&serialized);
+        hasher.update(self.leaf_data());
         let result = hasher.finalize();
         let mut h = [0u8; 32];
         h.copy_from_slice(&result);
@@ -90,3 +328,137 @@ impl Value for AccountInfo {
         AccountInfo::default()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Password account `user{i}` at `nonce`.
+    fn account(i: usize, nonce: u128) -> AccountInfo {
+        AccountInfo {
+            identity: format!("user{i}"),
+            auth_method: AuthMethod::Password {
+                hash: hex::encode(format!("hash{i}")),
+            },
+            nonce,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_sharded_smt_update_matches_sequential() {
+        let mut sequential = AccountSMT::default();
+        let mut sharded = AccountSMT::default();
+
+        // Large enough for the parallel path, then a small update of existing and new accounts.
+        for batch in [
+            (0..1500).map(|i| account(i, 0)).collect::<Vec<_>>(),
+            (1400..1510).map(|i| account(i, 1)).collect(),
+        ] {
+            for account in &batch {
+                sequential
+                    .0
+                    .update(AccountInfo::compute_key(&account.identity), account.clone())
+                    .unwrap();
+            }
+            let root = sharded.update_accounts(batch).unwrap();
+            assert_eq!(root, *sequential.0.root());
+        }
+
+        let keys = vec![AccountInfo::compute_key(&"user1450".to_string())];
+        let proof = sharded.0.merkle_proof(keys.clone()).unwrap();
+        assert!(proof
+            .verify::<SHA256Hasher>(
+                sharded.0.root(),
+                vec![(keys[0], account(1450, 1).to_h256())]
+            )
+            .unwrap());
+    }
+
+    #[test]
+    fn test_sharded_smt_update_around_parallel_threshold() {
+        // One update short of the threshold merges on this thread, the threshold on threads.
+        for len in [MIN_PARALLEL_UPDATES - 1, MIN_PARALLEL_UPDATES] {
+            let mut sequential = AccountSMT::default();
+            let mut sharded = AccountSMT::default();
+            // Existing accounts, so the merge reads siblings from the store.
+            for batch in [
+                (0..100).map(|i| account(i, 0)).collect::<Vec<_>>(),
+                (50..50 + len).map(|i| account(i, 1)).collect(),
+            ] {
+                for account in &batch {
+                    sequential
+                        .0
+                        .update(AccountInfo::compute_key(&account.identity), account.clone())
+                        .unwrap();
+                }
+                let root = sharded.update_accounts(batch).unwrap();
+                assert_eq!(root, *sequential.0.root());
+                assert_eq!(sharded.0.root(), sequential.0.root());
+            }
+        }
+    }
+
+    #[test]
+    fn test_smt_borsh_roundtrip() {
+        let mut smt = AccountSMT::default();
+        smt.update_accounts((0..10).map(|i| account(i, 1)).collect())
+            .unwrap();
+        let decoded: AccountSMT = borsh::from_slice(&borsh::to_vec(&smt).unwrap()).unwrap();
+        assert_eq!(decoded.0.root(), smt.0.root());
+    }
+
+    #[test]
+    fn test_session_key_encodings_are_tagged() {
+        let key = SessionKey {
+            public_key: "02ab".to_string(),
+            ..Default::default()
+        };
+        let legacy = AccountInfo {
+            identity: "bob".to_string(),
+            auth_method: AuthMethod::Password {
+                hash: hex::encode("hash"),
+            },
+            session_keys: vec![key.clone()],
+            ..Default::default()
+        };
+        let limited = AccountInfo {
+            session_keys: vec![SessionKey {
+                remaining_uses: Some(3),
+                ..key.clone()
+            }],
+            ..legacy.clone()
+        };
+        let ed25519 = AccountInfo {
+            session_keys: vec![SessionKey {
+                key_type: SessionKeyType::Ed25519,
+                ..key.clone()
+            }],
+            ..legacy.clone()
+        };
+
+        // Legacy session keys start with their count, the others with a tag no count reaches
+        // and their version, so no encoding can be read as another.
+        let prefix_len = borsh::object_length(&(&legacy.identity, &legacy.auth_method)).unwrap();
+        let session_keys = |account: &AccountInfo| account.leaf_data()[prefix_len..].to_vec();
+        assert_eq!(session_keys(&legacy)[..4], 1u32.to_le_bytes());
+        let mut tag = SESSION_KEYS_TAG.to_le_bytes().to_vec();
+        tag.push(SESSION_KEYS_LIMITED);
+        assert_eq!(session_keys(&limited)[..5], tag);
+        tag[4] = SESSION_KEYS_TYPED;
+        assert_eq!(session_keys(&ed25519)[..5], tag);
+
+        assert_ne!(legacy.to_h256(), limited.to_h256());
+        assert_ne!(legacy.to_h256(), ed25519.to_h256());
+        assert_ne!(limited.to_h256(), ed25519.to_h256());
+        // A limited key with no limit left hashes as a legacy one.
+        let unlimited = AccountInfo {
+            session_keys: vec![SessionKey {
+                remaining_uses: None,
+                ..limited.session_keys[0].clone()
+            }],
+            ..limited.clone()
+        };
+        assert_eq!(unlimited.to_h256(), legacy.to_h256());
+    }
+}
@@ -10,3 +10,30 @@ pub fn ethereum_address_from_public_key(public_key: &PublicKey) -> String {
     let hash = Keccak256::digest(&uncompressed[1..]); // drop 0x04 prefix
     hex::encode(&hash[12..])
 }
+
+/// Digest an Ethereum wallet signs for `message` with `personal_sign` (EIP-191).
+pub fn ethereum_personal_sign_digest(message: impl AsRef<[u8]>) -> [u8; 32] {
+    let message = message.as_ref();
+    let mut hasher = Keccak256::new();
+    hasher.update(format!("\x19Ethereum Signed Message:\n{}", message.len()).as_bytes());
+    hasher.update(message);
+    hasher.finalize().into()
+}
+
+/// Compares two byte strings in time independent of their content.
+pub fn ct_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Decodes a hex string, ignoring a `0x` prefix and case.
+pub fn decode_hex(value: &str) -> Result<Vec<u8>, String> {
+    let value = value.trim();
+    let value = value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+        .unwrap_or(value);
+    hex::decode(value).map_err(|e| format!("Invalid hex encoding: {e}"))
+}
@@ -1,4 +1,22 @@
+import { ConfigService } from "../services/ConfigService";
+
+// Name of the provider in the wallet server's `oidc.providers`.
+const GOOGLE_OIDC_PROVIDER = "google";
+
 export async function fetchGooglePublicKeys<T extends JsonWebKey & { kid: string }>(): Promise<{ keys: T[] }> {
+    // The wallet server keeps the keys fresh, and still serves those Google just rotated out.
+    try {
+        const walletServerUrl = ConfigService.getConfig().walletServerBaseUrl;
+        const response = await fetch(`${walletServerUrl}/oidc/providers/${GOOGLE_OIDC_PROVIDER}/jwks`);
+        if (response.ok) {
+            const jwks: { keys: T[] } = await response.json();
+            if (jwks.keys.length > 0) {
+                return jwks;
+            }
+        }
+    } catch {
+        // Servers without the OIDC module fall back to Google directly.
+    }
     const response = await fetch("https://www.googleapis.com/oauth2/v3/certs");
     return await response.json();
 }
@@ -0,0 +1,294 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+use client_sdk::rest_client::NodeApiClient;
+use hyli_modules::{
+    bus::SharedMessageBus,
+    log_error, module_bus_client, module_handle_messages,
+    modules::{contract_state_indexer::CSIBusEvent, Module},
+};
+use serde::Serialize;
+use server::conf::AlertsConf;
+use server::provers::{ProofFailure, ProverControls};
+use sqlx::{postgres::PgPoolOptions, PgPool};
+use wallet::client::indexer::{SharedWalletEvent, TxOutcome};
+
+const PAGERDUTY_EVENTS_URL: &str = "https://events.pagerduty.com/v2/enqueue";
+
+/// Alerts operators when proving or settlement goes wrong: repeated proof failures, wallet
+/// transactions timing out, the indexer trailing the node, or queued proofs not progressing.
+///
+/// Conditions are evaluated every `check_interval_secs`. An alert is sent to the webhooks and
+/// to PagerDuty when its condition starts holding, and resolved once it stops, so a stuck
+/// autoprover pages once instead of on every check.
+pub struct AlertModule {
+    bus: AlertModuleBusClient,
+    conf: AlertsConf,
+    source: String,
+    node: Arc<dyn NodeApiClient + Send + Sync>,
+    indexer_db: PgPool,
+    prover_controls: Arc<ProverControls>,
+    http: reqwest::Client,
+    failures: VecDeque<(Instant, ProofFailure)>,
+    timeouts: VecDeque<(Instant, String)>,
+    /// Last time a proof was generated or nothing was waiting to be proven.
+    proving_progress: Instant,
+    /// Summaries of the alerts currently firing.
+    active: BTreeMap<AlertKind, String>,
+}
+
+pub struct AlertModuleCtx {
+    pub conf: AlertsConf,
+    /// Server id, reported as the source of the alerts.
+    pub source: String,
+    pub node: Arc<dyn NodeApiClient + Send + Sync>,
+    /// Database of the Hyli indexer the contract listener reads blocks from.
+    pub indexer_database_url: String,
+    pub prover_controls: Arc<ProverControls>,
+}
+
+module_bus_client! {
+#[derive(Debug)]
+pub struct AlertModuleBusClient {
+    receiver(CSIBusEvent<SharedWalletEvent>),
+}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum AlertKind {
+    ProofFailures,
+    TxTimeouts,
+    DaLag,
+    ProverStall,
+}
+
+impl AlertKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            AlertKind::ProofFailures => "proof_failures",
+            AlertKind::TxTimeouts => "tx_timeouts",
+            AlertKind::DaLag => "da_lag",
+            AlertKind::ProverStall => "prover_stall",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum AlertStatus {
+    Triggered,
+    Resolved,
+}
+
+#[derive(Debug, Serialize)]
+struct Alert<'a> {
+    alert: AlertKind,
+    status: AlertStatus,
+    summary: &'a str,
+    source: &'a str,
+}
+
+impl AlertModule {
+    fn window(&self) -> Duration {
+        Duration::from_secs(self.conf.window_secs)
+    }
+
+    fn on_failure(&mut self, failure: ProofFailure) {
+        self.failures.push_back((Instant::now(), failure));
+    }
+
+    fn on_settled(&mut self, event: &SharedWalletEvent) {
+        if event.outcome == TxOutcome::Timeout {
+            self.timeouts
+                .push_back((Instant::now(), event.tx_hash.0.clone()));
+        }
+    }
+
+    async fn da_lag(&self) -> Result<u64> {
+        let block_height = self
+            .node
+            .get_block_height()
+            .await
+            .context("fetching node block height")?
+            .0;
+        let indexed_height: Option<i64> = sqlx::query_scalar("SELECT MAX(height) FROM blocks")
+            .fetch_one(&self.indexer_db)
+            .await
+            .context("fetching indexed block height")?;
+        Ok(block_height.saturating_sub(indexed_height.unwrap_or_default() as u64))
+    }
+
+    async fn check(&mut self) {
+        let window = self.window();
+        self.failures.retain(|(at, _)| at.elapsed() < window);
+        self.timeouts.retain(|(at, _)| at.elapsed() < window);
+
+        let threshold = self.conf.proof_failures_threshold;
+        let failures = (threshold > 0 && self.failures.len() >= threshold)
+            .then(|| self.failures.back())
+            .flatten()
+            .map(|(_, last)| {
+                format!(
+                    "{} failed proof attempts in the last {}s, last for {}: {}",
+                    self.failures.len(),
+                    self.conf.window_secs,
+                    last.contract_name,
+                    last.error
+                )
+            });
+        self.update(AlertKind::ProofFailures, failures).await;
+
+        let threshold = self.conf.tx_timeouts_threshold;
+        let timeouts = (threshold > 0 && self.timeouts.len() >= threshold)
+            .then(|| self.timeouts.back())
+            .flatten()
+            .map(|(_, last)| {
+                format!(
+                    "{} wallet transactions timed out in the last {}s, last {last}",
+                    self.timeouts.len(),
+                    self.conf.window_secs,
+                )
+            });
+        self.update(AlertKind::TxTimeouts, timeouts).await;
+
+        if self.conf.da_lag_threshold > 0 {
+            // Left as it is when the lag can't be measured.
+            if let Ok(lag) = log_error!(self.da_lag().await, "Measuring DA lag") {
+                let da_lag = (lag >= self.conf.da_lag_threshold)
+                    .then(|| format!("The indexer trails the node by {lag} blocks"));
+                self.update(AlertKind::DaLag, da_lag).await;
+            }
+        }
+
+        let backlog: usize = self.prover_controls.backlog().values().sum();
+        if backlog == 0 {
+            self.proving_progress = Instant::now();
+        }
+        let stalled = self.proving_progress.elapsed();
+        let stall = (self.conf.prover_stall_secs > 0
+            && stalled >= Duration::from_secs(self.conf.prover_stall_secs))
+        .then(|| {
+            format!(
+                "{backlog} proofs queued and none generated for {}s",
+                stalled.as_secs()
+            )
+        });
+        self.update(AlertKind::ProverStall, stall).await;
+    }
+
+    /// Fires the alert when its condition starts holding, and resolves it once it stops.
+    async fn update(&mut self, kind: AlertKind, condition: Option<String>) {
+        match (condition, self.active.contains_key(&kind)) {
+            (Some(summary), false) => {
+                tracing::warn!("🚨 Alert {}: {summary}", kind.as_str());
+                self.dispatch(kind, AlertStatus::Triggered, &summary).await;
+                self.active.insert(kind, summary);
+            }
+            (None, true) => {
+                let summary = self.active.remove(&kind).unwrap_or_default();
+                tracing::info!("✅ Alert {} resolved", kind.as_str());
+                self.dispatch(kind, AlertStatus::Resolved, &summary).await;
+            }
+            _ => {}
+        }
+    }
+
+    async fn dispatch(&self, kind: AlertKind, status: AlertStatus, summary: &str) {
+        let alert = Alert {
+            alert: kind,
+            status,
+            summary,
+            source: &self.source,
+        };
+        for url in &self.conf.webhook_urls {
+            let _ = log_error!(self.post(url, &alert).await, "Sending alert webhook");
+        }
+        if let Some(routing_key) = &self.conf.pagerduty_routing_key {
+            let event = serde_json::json!({
+                "routing_key": routing_key,
+                "event_action": match status {
+                    AlertStatus::Triggered => "trigger",
+                    AlertStatus::Resolved => "resolve",
+                },
+                "dedup_key": format!("{}:{}", self.source, kind.as_str()),
+                "payload": {
+                    "summary": summary,
+                    "source": self.source,
+                    "severity": "critical",
+                    "component": "wallet-server",
+                    "class": kind.as_str(),
+                },
+            });
+            let _ = log_error!(
+                self.post(PAGERDUTY_EVENTS_URL, &event).await,
+                "Sending PagerDuty event"
+            );
+        }
+    }
+
+    async fn post(&self, url: &str, body: &impl Serialize) -> Result<()> {
+        self.http
+            .post(url)
+            .json(body)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+impl Module for AlertModule {
+    type Context = AlertModuleCtx;
+
+    async fn build(bus: SharedMessageBus, ctx: Self::Context) -> Result<Self> {
+        let indexer_db = PgPoolOptions::new()
+            .max_connections(1)
+            .connect_lazy(&ctx.indexer_database_url)
+            .context("parsing indexer database URL")?;
+        Ok(Self {
+            bus: AlertModuleBusClient::new_from_bus(bus.new_handle()).await,
+            http: reqwest::Client::builder()
+                .timeout(Duration::from_secs(ctx.conf.dispatch_timeout_secs))
+                .build()
+                .context("building alert HTTP client")?,
+            conf: ctx.conf,
+            source: ctx.source,
+            node: ctx.node,
+            indexer_db,
+            prover_controls: ctx.prover_controls,
+            failures: VecDeque::new(),
+            timeouts: VecDeque::new(),
+            proving_progress: Instant::now(),
+            active: BTreeMap::new(),
+        })
+    }
+
+    async fn run(&mut self) -> Result<()> {
+        let mut interval =
+            tokio::time::interval(Duration::from_secs(self.conf.check_interval_secs));
+        let mut proofs = self.prover_controls.subscribe_proofs();
+        let mut failures = self.prover_controls.subscribe_failures();
+
+        module_handle_messages! {
+            on_self self,
+            listen<CSIBusEvent<SharedWalletEvent>> event => {
+                self.on_settled(&event.event);
+            }
+            Ok(_) = proofs.recv() => {
+                self.proving_progress = Instant::now();
+            }
+            Ok(failure) = failures.recv() => {
+                self.on_failure(failure);
+            }
+            _ = interval.tick() => {
+                self.check().await;
+            }
+        };
+        Ok(())
+    }
+}
@@ -1,55 +1,198 @@
-use std::sync::Arc;
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use axum::{
     extract::{Json, State},
-    http::Method,
+    http::{HeaderName, Method},
     response::IntoResponse,
     routing::get,
     Router,
 };
+use client_sdk::rest_client::NodeApiClient;
 use hyli_modules::{
     bus::{BusClientSender, BusMessage, SharedMessageBus},
     module_bus_client, module_handle_messages,
     modules::{
-        contract_state_indexer::CSIBusEvent, websocket::WsTopicMessage, BuildApiContextInner,
-        Module,
+        contract_state_indexer::CSIBusEvent,
+        websocket::{WsInMessage, WsTopicMessage},
+        BuildApiContextInner, Module,
     },
 };
 
+use opentelemetry::{metrics::Counter, KeyValue};
 use sdk::ContractName;
 use serde::{Deserialize, Serialize};
+use server::conf::SystemStatusConf;
+use server::correlation::{CorrelationId, CORRELATION_HEADER};
+use server::provers::ProverControls;
+use sqlx::{postgres::PgPoolOptions, PgPool};
 use tower_http::cors::{Any, CorsLayer};
-use wallet::client::indexer::WalletEvent;
+use wallet::client::indexer::{SharedWalletEvent, TxOutcome, WalletEvent};
 
 use crate::history::HistoryEvent;
+use crate::signing::signing::SigningEvent;
+use crate::verify_signature::{verify_signature_router, SignatureVerifier};
+use crate::ws_auth::{ws_auth_router, WsAuth};
+use crate::ws_replay::EventReplay;
+
+/// WebSocket topic the [`SystemStatus`] is published on.
+pub const SYSTEM_TOPIC: &str = "system";
 
 pub struct WalletModule {
     bus: AppModuleBusClient,
+    ctx: Arc<WalletModuleCtx>,
+    indexer_db: PgPool,
+    metrics: WsMetrics,
 }
 
 pub struct WalletModuleCtx {
     pub api: Arc<BuildApiContextInner>,
     pub wallet_cn: ContractName,
+    pub ws_auth: Arc<WsAuth>,
+    pub replay: Arc<EventReplay>,
+    pub node: Arc<dyn NodeApiClient + Send + Sync>,
+    /// Database of the Hyli indexer the contract listener reads blocks from.
+    pub indexer_database_url: String,
+    /// Base URL of this server's REST API, serving the indexer routes.
+    pub indexer_url: String,
+    pub system_status: SystemStatusConf,
+    pub prover_controls: Arc<ProverControls>,
+}
+
+/// Network status, so frontends can tell a syncing or degraded network from a frozen app.
+#[derive(Debug, Clone, Serialize)]
+pub struct SystemStatus {
+    /// Height of the node's latest block.
+    pub block_height: u64,
+    /// Blocks the indexed data, and so the events pushed here, trail the node by.
+    pub da_lag: u64,
+    /// Proofs queued or running, per contract.
+    pub proving_backlog: BTreeMap<String, usize>,
 }
 
 /// Messages received from WebSocket clients that will be processed by the system
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub enum AppWsInMessage {}
+pub enum AppWsInMessage {
+    /// Replays the events of `account` after `cursor` on `topic`, which must have been granted
+    /// for the account through WebSocket auth.
+    Resume {
+        account: String,
+        topic: String,
+        cursor: u64,
+    },
+}
 
 /// Messages sent to WebSocket clients from the system
 #[derive(Debug, Clone, Serialize)]
 pub enum AppOutWsEvent {
     TxEvent(HistoryEvent),
-    WalletEvent { account: String, event: String }, // TODO: Type event for better error handling in frontend
+    WalletEvent {
+        account: String,
+        event: String,
+    }, // TODO: Type event for better error handling in frontend
+    SigningEvent(SigningEvent),
+    SystemStatus(SystemStatus),
+    /// A proof covering the transaction was generated and is being submitted.
+    ProofGenerated {
+        tx_hash: String,
+        contract_name: String,
+        correlation_id: CorrelationId,
+    },
+    /// The transaction settled, or timed out, on chain.
+    TxSettled {
+        tx_hash: String,
+        outcome: TxOutcome,
+        correlation_id: CorrelationId,
+    },
+    /// An account event, numbered so clients can resume after it. Sent on granted topics.
+    Sequenced {
+        cursor: u64,
+        event: Box<AppOutWsEvent>,
+    },
+    /// Ends a replay. Some events were missed for good if `truncated` is set.
+    Resumed {
+        account: String,
+        truncated: bool,
+    },
+}
+
+impl AppOutWsEvent {
+    /// Name of the event in metrics.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            AppOutWsEvent::TxEvent(_) => "tx_event",
+            AppOutWsEvent::WalletEvent { .. } => "wallet_event",
+            AppOutWsEvent::SigningEvent(_) => "signing_event",
+            AppOutWsEvent::SystemStatus(_) => "system_status",
+            AppOutWsEvent::ProofGenerated { .. } => "proof_generated",
+            AppOutWsEvent::TxSettled { .. } => "tx_settled",
+            AppOutWsEvent::Sequenced { event, .. } => event.kind(),
+            AppOutWsEvent::Resumed { .. } => "resumed",
+        }
+    }
+}
+
+/// Counts the messages published to WebSocket clients, by event.
+#[derive(Clone)]
+pub struct WsMetrics {
+    messages: Counter<u64>,
+}
+
+impl Default for WsMetrics {
+    fn default() -> Self {
+        let meter = opentelemetry::global::meter("wallet_ws");
+        Self {
+            messages: meter
+                .u64_counter("ws_messages_published_total")
+                .with_description("Number of messages published to WebSocket topics")
+                .build(),
+        }
+    }
+}
+
+impl WsMetrics {
+    pub fn published(&self, message: &WsTopicMessage<AppOutWsEvent>) {
+        self.messages
+            .add(1, &[KeyValue::new("event", message.message.kind())]);
+    }
+}
+
+/// Messages publishing an event of `account`: numbered on the topics granted through WebSocket
+/// auth, and as is on the account name for older clients.
+pub fn account_event_messages(
+    ws_auth: &WsAuth,
+    replay: &EventReplay,
+    account: &str,
+    event: AppOutWsEvent,
+) -> Vec<WsTopicMessage<AppOutWsEvent>> {
+    let cursor = replay.record(account, &event);
+    let mut messages: Vec<_> = ws_auth
+        .granted_topics(account)
+        .into_iter()
+        .map(|topic| {
+            WsTopicMessage::new(
+                topic,
+                AppOutWsEvent::Sequenced {
+                    cursor,
+                    event: Box::new(event.clone()),
+                },
+            )
+        })
+        .collect();
+    if ws_auth.publishes_on_account_name() {
+        messages.push(WsTopicMessage::new(account.to_string(), event));
+    }
+    messages
 }
 
 module_bus_client! {
 #[derive(Debug)]
 pub struct AppModuleBusClient {
     sender(WsTopicMessage<AppOutWsEvent>),
-    receiver(CSIBusEvent<Wrap<Vec<HistoryEvent>>>),
+    receiver(WsInMessage<AppWsInMessage>),
+    receiver(CSIBusEvent<Wrap<Arc<[HistoryEvent]>>>),
     receiver(CSIBusEvent<Wrap<WalletEvent>>),
+    receiver(CSIBusEvent<SharedWalletEvent>),
 }
 }
 
@@ -62,21 +205,9 @@ impl Module for WalletModule {
     type Context = Arc<WalletModuleCtx>;
 
     async fn build(bus: SharedMessageBus, ctx: Self::Context) -> Result<Self> {
-        let state = RouterCtx {
-            wallet_cn: ctx.wallet_cn.clone(),
-        };
-
-        // Create a CORS middleware
-        let cors = CorsLayer::new()
-            .allow_origin(Any) // Allow all origins (can be restricted)
-            .allow_methods(vec![Method::GET, Method::POST]) // Allow necessary methods
-            .allow_headers(Any); // Allow all headers
-
-        let api = Router::new()
-            .route("/_health", get(health))
-            .route("/api/config", get(get_config))
-            .with_state(state)
-            .layer(cors); // Apply the CORS middleware
+        let api = deployment_router(ctx.wallet_cn.clone(), ctx.indexer_url.clone())
+            .merge(ws_auth_router(ctx.ws_auth.clone()))
+            .layer(cors_layer());
 
         if let Ok(mut guard) = ctx.api.router.lock() {
             if let Some(router) = guard.take() {
@@ -84,29 +215,80 @@ impl Module for WalletModule {
             }
         }
         let bus = AppModuleBusClient::new_from_bus(bus.new_handle()).await;
+        let indexer_db = PgPoolOptions::new()
+            .max_connections(1)
+            .connect_lazy(&ctx.indexer_database_url)
+            .context("parsing indexer database URL")?;
 
-        Ok(WalletModule { bus })
+        Ok(WalletModule {
+            bus,
+            ctx,
+            indexer_db,
+            metrics: WsMetrics::default(),
+        })
     }
 
     async fn run(&mut self) -> Result<()> {
+        let interval_secs = self.ctx.system_status.interval_secs;
+        let mut status_interval = tokio::time::interval(Duration::from_secs(interval_secs.max(1)));
+        let mut proofs = self.ctx.prover_controls.subscribe_proofs();
+
         module_handle_messages! {
             on_self self,
-            listen <CSIBusEvent<Wrap<Vec<HistoryEvent>>>> event => {
-                for msg in event.event.0 {
-                    self.bus.send(WsTopicMessage::new(
-                        msg.account.0.clone(),
-                        AppOutWsEvent::TxEvent(msg),
-                    ))?;
+            _ = status_interval.tick() => {
+                if interval_secs > 0 {
+                    match self.system_status().await {
+                        Ok(status) => {
+                            self.send(WsTopicMessage::new(
+                                SYSTEM_TOPIC.to_string(),
+                                AppOutWsEvent::SystemStatus(status),
+                            ))?;
+                        }
+                        Err(e) => tracing::warn!("Failed to compute system status: {:#}", e),
+                    }
+                }
+            }
+            Ok(proven) = proofs.recv() => {
+                for (tx_hash, identity) in proven.txs {
+                    self.publish(
+                        &identity.0,
+                        AppOutWsEvent::ProofGenerated {
+                            correlation_id: CorrelationId::of_tx(&tx_hash),
+                            tx_hash: tx_hash.0,
+                            contract_name: proven.contract_name.0.clone(),
+                        },
+                    )?;
+                }
+            }
+            listen<CSIBusEvent<SharedWalletEvent>> event => {
+                self.publish(
+                    &event.event.account.0,
+                    AppOutWsEvent::TxSettled {
+                        tx_hash: event.event.tx_hash.0.clone(),
+                        outcome: event.event.outcome,
+                        correlation_id: CorrelationId::of_tx(&event.event.tx_hash),
+                    },
+                )?;
+            }
+            listen<WsInMessage<AppWsInMessage>> msg => {
+                match msg.message {
+                    AppWsInMessage::Resume { account, topic, cursor } => self.resume(account, topic, cursor)?,
+                }
+            }
+            listen <CSIBusEvent<Wrap<Arc<[HistoryEvent]>>>> event => {
+                for msg in event.event.0.iter() {
+                    self.publish(&msg.account.0, AppOutWsEvent::TxEvent(msg.clone()))?;
                 }
             }
             listen<CSIBusEvent<Wrap<WalletEvent>>> event => {
-                self.bus.send(WsTopicMessage::new(
-                    event.event.0.account.0.clone(),
+                let account = event.event.0.account.0.clone();
+                self.publish(
+                    &account,
                     AppOutWsEvent::WalletEvent {
-                        account: event.event.0.account.0.clone(),
-                        event:event.event.0.program_outputs
+                        account: account.clone(),
+                        event: event.event.0.program_outputs,
                     },
-                ))?;
+                )?;
             }
         };
 
@@ -114,6 +296,90 @@ impl Module for WalletModule {
     }
 }
 
+impl WalletModule {
+    fn send(&mut self, message: WsTopicMessage<AppOutWsEvent>) -> Result<()> {
+        self.metrics.published(&message);
+        self.bus.send(message)?;
+        Ok(())
+    }
+
+    fn publish(&mut self, account: &str, event: AppOutWsEvent) -> Result<()> {
+        for message in account_event_messages(&self.ctx.ws_auth, &self.ctx.replay, account, event) {
+            self.send(message)?;
+        }
+        Ok(())
+    }
+
+    fn resume(&mut self, account: String, topic: String, cursor: u64) -> Result<()> {
+        // Anyone can send this, only replay on topics the account's owner was granted.
+        if !self.ctx.ws_auth.granted_topics(&account).contains(&topic) {
+            tracing::debug!("Ignoring resume of {} on an ungranted topic", account);
+            return Ok(());
+        }
+        let replay = self.ctx.replay.since(&account, cursor);
+        for (cursor, event) in replay.events {
+            self.send(WsTopicMessage::new(
+                topic.clone(),
+                AppOutWsEvent::Sequenced {
+                    cursor,
+                    event: Box::new(event),
+                },
+            ))?;
+        }
+        self.send(WsTopicMessage::new(
+            topic,
+            AppOutWsEvent::Resumed {
+                account,
+                truncated: replay.truncated,
+            },
+        ))?;
+        Ok(())
+    }
+
+    async fn system_status(&self) -> Result<SystemStatus> {
+        let block_height = self
+            .ctx
+            .node
+            .get_block_height()
+            .await
+            .context("fetching node block height")?
+            .0;
+        let indexed_height: Option<i64> = sqlx::query_scalar("SELECT MAX(height) FROM blocks")
+            .fetch_one(&self.indexer_db)
+            .await
+            .context("fetching indexed block height")?;
+        Ok(SystemStatus {
+            block_height,
+            da_lag: block_height.saturating_sub(indexed_height.unwrap_or_default() as u64),
+            proving_backlog: self.ctx.prover_controls.backlog(),
+        })
+    }
+}
+
+/// CORS middleware of the routes frontends call.
+pub fn cors_layer() -> CorsLayer {
+    CorsLayer::new()
+        .allow_origin(Any) // Allow all origins (can be restricted)
+        .allow_methods(vec![Method::GET, Method::POST]) // Allow necessary methods
+        .allow_headers(Any) // Allow all headers
+        .expose_headers([HeaderName::from_static(CORRELATION_HEADER)])
+}
+
+/// Routes describing the wallet deployment of `wallet_cn`, whose indexer is served from
+/// `indexer_url`.
+pub fn deployment_router(wallet_cn: ContractName, indexer_url: String) -> Router {
+    Router::new()
+        .route("/_health", get(health))
+        .route("/api/config", get(get_config))
+        .with_state(RouterCtx {
+            wallet_cn: wallet_cn.clone(),
+        })
+        .merge(verify_signature_router(Arc::new(SignatureVerifier::new(
+            wallet_cn,
+            indexer_url,
+        ))))
+}
+
 #[derive(Clone)]
 struct RouterCtx {
     pub wallet_cn: ContractName,
@@ -0,0 +1,327 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use axum::{
+    extract::{Query, State},
+    routing::get,
+    Json, Router,
+};
+use client_sdk::AppError;
+use hyli_modules::{
+    bus::SharedMessageBus, log_error, module_bus_client, module_handle_messages, modules::Module,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::FromRow;
+use tokio::sync::mpsc;
+
+use crate::conf::DatabaseConf;
+use crate::db::DbPools;
+
+/// Key of the advisory lock serializing appends, so servers sharing a database extend a single
+/// chain.
+const AUDIT_LOCK_KEY: i64 = 0x6175_6469_74;
+
+/// Hash the first entry chains from.
+const GENESIS_HASH: &str = "";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditKind {
+    AdminOperation,
+    InviteIssued,
+    SigningApproval,
+    ProofGenerated,
+    Recovery,
+    DataErasure,
+}
+
+impl AuditKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            AuditKind::AdminOperation => "admin_operation",
+            AuditKind::InviteIssued => "invite_issued",
+            AuditKind::SigningApproval => "signing_approval",
+            AuditKind::ProofGenerated => "proof_generated",
+            AuditKind::Recovery => "recovery",
+            AuditKind::DataErasure => "data_erasure",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AuditEvent {
+    pub kind: AuditKind,
+    pub actor: String,
+    pub details: serde_json::Value,
+}
+
+/// Handle the modules record audit events with. Events are appended in order by the
+/// [`AuditModule`]; the default recorder drops them, for binaries running without it.
+#[derive(Debug, Clone, Default)]
+pub struct AuditRecorder {
+    events: Option<mpsc::UnboundedSender<AuditEvent>>,
+}
+
+impl AuditRecorder {
+    pub fn channel() -> (Self, mpsc::UnboundedReceiver<AuditEvent>) {
+        let (events, receiver) = mpsc::unbounded_channel();
+        (
+            Self {
+                events: Some(events),
+            },
+            receiver,
+        )
+    }
+
+    pub fn record(&self, kind: AuditKind, actor: impl Into<String>, details: serde_json::Value) {
+        let Some(events) = &self.events else {
+            return;
+        };
+        let event = AuditEvent {
+            kind,
+            actor: actor.into(),
+            details,
+        };
+        if events.send(event).is_err() {
+            tracing::warn!("Audit event channel closed, dropping {:?} event", kind);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct AuditEntry {
+    pub id: i64,
+    pub kind: String,
+    pub actor: String,
+    /// JSON details, kept as the exact text that was hashed.
+    pub details: String,
+    pub created_at: i64,
+    pub prev_hash: String,
+    pub hash: String,
+}
+
+impl AuditEntry {
+    fn compute_hash(
+        prev_hash: &str,
+        kind: &str,
+        actor: &str,
+        details: &str,
+        created_at: i64,
+    ) -> String {
+        let mut hasher = Sha256::new();
+        for field in [prev_hash, kind, actor, details] {
+            hasher.update((field.len() as u64).to_be_bytes());
+            hasher.update(field.as_bytes());
+        }
+        hasher.update(created_at.to_be_bytes());
+        hex::encode(hasher.finalize())
+    }
+
+    fn expected_hash(&self) -> String {
+        Self::compute_hash(
+            &self.prev_hash,
+            &self.kind,
+            &self.actor,
+            &self.details,
+            self.created_at,
+        )
+    }
+}
+
+/// Append-only, hash-chained log of admin operations, invite issuance, signing approvals and
+/// generated proofs. Each entry's hash covers the previous one, so edits or deletions break the
+/// chain from that point on.
+#[derive(Clone)]
+pub struct AuditLog {
+    db: DbPools,
+}
+
+impl AuditLog {
+    pub async fn connect(db_url: &str, database: &DatabaseConf) -> Result<Self> {
+        let db = DbPools::connect(db_url, database).await?;
+        sqlx::query(
+            r#"CREATE TABLE IF NOT EXISTS audit_log (
+                id BIGSERIAL PRIMARY KEY,
+                kind TEXT NOT NULL,
+                actor TEXT NOT NULL,
+                details TEXT NOT NULL,
+                created_at BIGINT NOT NULL,
+                prev_hash TEXT NOT NULL,
+                hash TEXT NOT NULL
+            )"#,
+        )
+        .execute(&db.primary)
+        .await?;
+        sqlx::query(
+            "CREATE INDEX IF NOT EXISTS audit_log_kind_actor_idx ON audit_log (kind, actor, id)",
+        )
+        .execute(&db.primary)
+        .await?;
+        Ok(Self { db })
+    }
+
+    async fn append(&self, event: &AuditEvent) -> Result<()> {
+        let details = serde_json::to_string(&event.details).context("encoding audit details")?;
+        let created_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or_default();
+
+        let mut tx = self.db.primary.begin().await?;
+        sqlx::query("SELECT pg_advisory_xact_lock($1)")
+            .bind(AUDIT_LOCK_KEY)
+            .execute(&mut *tx)
+            .await?;
+        let prev_hash: String =
+            sqlx::query_scalar("SELECT hash FROM audit_log ORDER BY id DESC LIMIT 1")
+                .fetch_optional(&mut *tx)
+                .await?
+                .unwrap_or_else(|| GENESIS_HASH.to_string());
+        let kind = event.kind.as_str();
+        let hash = AuditEntry::compute_hash(&prev_hash, kind, &event.actor, &details, created_at);
+        sqlx::query(
+            "
+            INSERT INTO audit_log (kind, actor, details, created_at, prev_hash, hash)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            ",
+        )
+        .bind(kind)
+        .bind(&event.actor)
+        .bind(&details)
+        .bind(created_at)
+        .bind(&prev_hash)
+        .bind(&hash)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn query(&self, query: &AuditQuery) -> Result<Vec<AuditEntry>> {
+        Ok(sqlx::query_as(
+            "
+            SELECT id, kind, actor, details, created_at, prev_hash, hash
+            FROM audit_log
+            WHERE ($1::TEXT IS NULL OR kind = $1)
+              AND ($2::TEXT IS NULL OR actor = $2)
+              AND id > $3
+            ORDER BY id
+            LIMIT $4
+            ",
+        )
+        .bind(query.kind.map(|k| k.as_str()))
+        .bind(&query.actor)
+        .bind(query.after_id.unwrap_or_default())
+        .bind(query.limit.unwrap_or(100).min(1000))
+        .fetch_all(self.db.reader())
+        .await?)
+    }
+
+    /// Walks the whole chain, returning the id of the first entry whose hash or link doesn't
+    /// match.
+    async fn verify(&self) -> Result<AuditVerification> {
+        let entries: Vec<AuditEntry> = sqlx::query_as(
+            "SELECT id, kind, actor, details, created_at, prev_hash, hash FROM audit_log ORDER BY id",
+        )
+        .fetch_all(self.db.reader())
+        .await?;
+
+        let mut prev_hash = GENESIS_HASH.to_string();
+        for entry in &entries {
+            if entry.prev_hash != prev_hash || entry.hash != entry.expected_hash() {
+                return Ok(AuditVerification {
+                    entries: entries.len(),
+                    first_invalid_id: Some(entry.id),
+                });
+            }
+            prev_hash = entry.hash.clone();
+        }
+        Ok(AuditVerification {
+            entries: entries.len(),
+            first_invalid_id: None,
+        })
+    }
+}
+
+pub struct AuditModule {
+    #[allow(unused)]
+    bus: AuditModuleBusClient,
+    log: AuditLog,
+    events: mpsc::UnboundedReceiver<AuditEvent>,
+}
+
+pub struct AuditModuleCtx {
+    pub log: AuditLog,
+    pub events: mpsc::UnboundedReceiver<AuditEvent>,
+}
+
+module_bus_client! {
+#[derive(Debug)]
+pub struct AuditModuleBusClient {
+}
+}
+
+impl Module for AuditModule {
+    type Context = AuditModuleCtx;
+
+    async fn build(bus: SharedMessageBus, ctx: Self::Context) -> Result<Self> {
+        tracing::info!("Audit module initialized");
+        Ok(Self {
+            bus: AuditModuleBusClient::new_from_bus(bus.new_handle()).await,
+            log: ctx.log,
+            events: ctx.events,
+        })
+    }
+
+    async fn run(&mut self) -> Result<()> {
+        module_handle_messages! {
+            on_self self,
+            Some(event) = self.events.recv() => {
+                let _ = log_error!(self.log.append(&event).await, "Appending audit event");
+            }
+        };
+        Ok(())
+    }
+}
+
+// --------------------------------------------------------
+//     Routes
+// --------------------------------------------------------
+
+#[derive(Debug, Deserialize)]
+pub struct AuditQuery {
+    pub kind: Option<AuditKind>,
+    pub actor: Option<String>,
+    /// Only return entries after this id, to page through the log.
+    pub after_id: Option<i64>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AuditVerification {
+    pub entries: usize,
+    /// First entry that was altered or doesn't chain from the previous one, if any.
+    pub first_invalid_id: Option<i64>,
+}
+
+/// Admin routes to query the audit log and check its hash chain.
+pub fn audit_admin_router(log: AuditLog) -> Router {
+    Router::new()
+        .route("/audit", get(get_audit_log))
+        .route("/audit/verify", get(verify_audit_log))
+        .with_state(log)
+}
+
+async fn get_audit_log(
+    State(log): State<AuditLog>,
+    Query(query): Query<AuditQuery>,
+) -> Result<Json<Vec<AuditEntry>>, AppError> {
+    Ok(Json(log.query(&query).await?))
+}
+
+async fn verify_audit_log(
+    State(log): State<AuditLog>,
+) -> Result<Json<AuditVerification>, AppError> {
+    Ok(Json(log.verify().await?))
+}
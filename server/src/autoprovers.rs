@@ -1,71 +1,221 @@
 use anyhow::Result;
-use client_sdk::{helpers::risc0::Risc0Prover, rest_client::NodeApiClient};
+use client_sdk::rest_client::NodeApiClient;
 use hyli_modules::modules::prover::{AutoProver, AutoProverCtx};
 use hyli_modules::modules::{BuildApiContextInner, ModulesHandler};
 use hyli_smt_token::client::tx_executor_handler::SmtTokenProvableState;
 use sdk::ContractName;
+use std::collections::HashMap;
 use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::Duration;
 use wallet::client::tx_executor_handler::Wallet;
 
-pub(crate) struct AutoProversConfig {
-    pub wallet_cn: ContractName,
+use crate::conf::{Conf, ProofSubmissionConf, ProverAccelerator};
+use crate::proof_submission::{DeadLetters, RetryingNodeClient};
+use crate::provers::{
+    app_prover, check_accelerator, check_bonsai, AppProver, BatchSplitting, ProofPriority,
+    ProverControls, ProvingScheduler,
+};
+
+/// Replaces the guest program built into the binary for one contract.
+#[derive(Debug, Clone, Default)]
+pub struct ProgramOverride {
+    pub elf: Option<Vec<u8>>,
+    pub program_id: Option<[u8; 32]>,
+}
+
+/// Settings shared by every binary that runs autoprovers.
+pub struct AutoProversConfig {
+    /// Wallet contracts to prove when `wallet_auto_prove` is set.
+    pub wallet_cns: Vec<ContractName>,
     pub wallet_auto_prove: bool,
     pub smt_auto_prove: bool,
+    /// Token contracts to prove when `smt_auto_prove` is set.
+    pub smt_contracts: Vec<ContractName>,
     pub data_directory: PathBuf,
     pub smt_max_txs_per_proof: usize,
     pub smt_tx_working_window_size: usize,
     pub wallet_max_txs_per_proof: usize,
     pub wallet_tx_working_window_size: usize,
+    /// Proofs each wallet batch is split into, proven concurrently.
+    pub wallet_parallel_proofs: usize,
     pub idle_flush_interval_secs: u64,
     pub tx_buffer_size: usize,
+    pub proof_cache: bool,
+    pub max_concurrent_proofs: usize,
+    pub accelerator: ProverAccelerator,
+    /// Contracts proven on Bonsai, locally when it fails.
+    pub bonsai_contracts: Vec<ContractName>,
+    pub proof_submission: ProofSubmissionConf,
+    pub program_overrides: HashMap<ContractName, ProgramOverride>,
+}
+
+impl AutoProversConfig {
+    pub fn new(conf: &Conf, wallet_cns: Vec<ContractName>) -> Self {
+        Self {
+            wallet_cns,
+            wallet_auto_prove: conf.wallet_auto_prover,
+            smt_auto_prove: conf.smt_auto_provers,
+            smt_contracts: conf
+                .tokens
+                .iter()
+                .filter(|token| token.auto_prove)
+                .map(|token| token.name.clone().into())
+                .collect(),
+            data_directory: conf.data_directory.clone(),
+            smt_max_txs_per_proof: conf.smt_max_txs_per_proof,
+            smt_tx_working_window_size: conf.smt_tx_working_window_size,
+            wallet_max_txs_per_proof: conf.wallet_max_txs_per_proof,
+            wallet_tx_working_window_size: conf.wallet_tx_working_window_size,
+            wallet_parallel_proofs: conf.wallet_parallel_proofs,
+            idle_flush_interval_secs: conf.auto_prover_idle_flush_interval_secs,
+            tx_buffer_size: conf.auto_prover_tx_buffer_size,
+            proof_cache: conf.auto_prover_proof_cache,
+            max_concurrent_proofs: conf.auto_prover_max_concurrent_proofs,
+            accelerator: conf.auto_prover_accelerator,
+            bonsai_contracts: conf
+                .remote_proving
+                .contracts
+                .iter()
+                .cloned()
+                .map(ContractName)
+                .collect(),
+            proof_submission: conf.proof_submission.clone(),
+            program_overrides: HashMap::new(),
+        }
+    }
+
+    /// The guest program and id to prove `contract_name` with, honouring overrides.
+    fn program<'a>(
+        &'a self,
+        contract_name: &ContractName,
+        elf: &'a [u8],
+        program_id: [u8; 32],
+    ) -> (&'a [u8], [u8; 32]) {
+        match self.program_overrides.get(contract_name) {
+            Some(program) => (
+                program.elf.as_deref().unwrap_or(elf),
+                program.program_id.unwrap_or(program_id),
+            ),
+            None => (elf, program_id),
+        }
+    }
 }
 
-pub(crate) async fn setup_autoprovers_modules(
+/// Builds the wallet and token autoprovers enabled in `config`, all sharing the same prover
+/// layers (cache, scheduling, metrics and admin controls).
+pub async fn setup_autoprovers_modules(
     config: &AutoProversConfig,
     handler: &mut ModulesHandler,
     api_ctx: Arc<BuildApiContextInner>,
     node_client: Arc<dyn NodeApiClient + Send + Sync>,
+    prover_controls: &ProverControls,
 ) -> Result<()> {
-    let oranj_cn: ContractName = "oranj".into();
-    let vitamin_cn: ContractName = "vitamin".into();
-    let oxygen_cn: ContractName = "oxygen".into();
+    if config.wallet_auto_prove || config.smt_auto_prove {
+        check_accelerator(config.accelerator)?;
+    }
+    let mut on_bonsai = Vec::new();
+    if config.wallet_auto_prove {
+        on_bonsai.extend(config.wallet_cns.iter().cloned());
+    }
+    if config.smt_auto_prove {
+        on_bonsai.extend(config.smt_contracts.iter().cloned());
+    }
+    on_bonsai.retain(|contract_name| config.bonsai_contracts.contains(contract_name));
+    check_bonsai(&on_bonsai)?;
 
     let idle_flush_interval = Duration::from_secs(config.idle_flush_interval_secs);
+    let cache_dir = |contract_name: &ContractName| {
+        config.proof_cache.then(|| {
+            config
+                .data_directory
+                .join("proof_cache")
+                .join(&contract_name.0)
+        })
+    };
+
+    // Proofs are submitted with retries, those refused every time are kept on disk.
+    let proof_node =
+        |contract_name: &ContractName| -> Result<Arc<dyn NodeApiClient + Send + Sync>> {
+            let dead_letters = Arc::new(DeadLetters::open(
+                config
+                    .data_directory
+                    .join("dead_letters")
+                    .join(&contract_name.0),
+                node_client.clone(),
+            )?);
+            prover_controls
+                .register(contract_name)
+                .track_dead_letters(dead_letters.clone());
+            Ok(Arc::new(RetryingNodeClient::new(
+                node_client.clone(),
+                config.proof_submission.clone(),
+                dead_letters,
+            )))
+        };
+
+    let scheduler = (config.max_concurrent_proofs > 0)
+        .then(|| Arc::new(ProvingScheduler::new(config.max_concurrent_proofs)));
+    let scheduling = |priority| scheduler.clone().map(|scheduler| (scheduler, priority));
 
     if config.wallet_auto_prove {
-        handler
-            .build_module::<AutoProver<Wallet, Risc0Prover>>(Arc::new(AutoProverCtx {
-                data_directory: config.data_directory.clone(),
-                prover: Arc::new(Risc0Prover::new(
-                    contracts::WALLET_ELF.to_vec(),
-                    contracts::WALLET_ID,
-                )),
-                contract_name: config.wallet_cn.clone(),
-                node: node_client.clone(),
-                api: Some(api_ctx.clone()),
-                max_txs_per_proof: config.wallet_max_txs_per_proof,
-                tx_working_window_size: config.wallet_tx_working_window_size,
-                idle_flush_interval,
-                tx_buffer_size: config.tx_buffer_size,
-            }))
-            .await?;
+        for wallet_cn in config.wallet_cns.iter() {
+            let (elf, program_id) =
+                config.program(wallet_cn, contracts::WALLET_ELF, contracts::WALLET_ID);
+            let node = proof_node(wallet_cn)?;
+            handler
+                .build_module::<AutoProver<Wallet, AppProver>>(Arc::new(AutoProverCtx {
+                    data_directory: config.data_directory.clone(),
+                    prover: Arc::new(app_prover(
+                        wallet_cn,
+                        elf,
+                        program_id,
+                        cache_dir(wallet_cn),
+                        scheduling(ProofPriority::High),
+                        (config.wallet_parallel_proofs > 1).then(|| BatchSplitting {
+                            parts: config.wallet_parallel_proofs,
+                            split: Wallet::split_commitment_metadata,
+                            node: node.clone(),
+                        }),
+                        config.bonsai_contracts.contains(wallet_cn),
+                        prover_controls,
+                    )?),
+                    contract_name: wallet_cn.clone(),
+                    node,
+                    api: Some(api_ctx.clone()),
+                    max_txs_per_proof: config.wallet_max_txs_per_proof,
+                    tx_working_window_size: config.wallet_tx_working_window_size,
+                    idle_flush_interval,
+                    tx_buffer_size: config.tx_buffer_size,
+                }))
+                .await?;
+        }
     }
 
     if config.smt_auto_prove {
-        for contract_name in [oranj_cn, vitamin_cn, oxygen_cn] {
+        for contract_name in config.smt_contracts.iter().cloned() {
+            let (elf, program_id) = config.program(
+                &contract_name,
+                hyli_smt_token::client::tx_executor_handler::metadata::SMT_TOKEN_ELF,
+                hyli_smt_token::client::tx_executor_handler::metadata::PROGRAM_ID,
+            );
+            let node = proof_node(&contract_name)?;
             handler
-                .build_module::<AutoProver<SmtTokenProvableState, Risc0Prover>>(Arc::new(
+                .build_module::<AutoProver<SmtTokenProvableState, AppProver>>(Arc::new(
                     AutoProverCtx {
                         data_directory: config.data_directory.clone(),
-                        prover: Arc::new(Risc0Prover::new(
-                            hyli_smt_token::client::tx_executor_handler::metadata::SMT_TOKEN_ELF
-                                .to_vec(),
-                            hyli_smt_token::client::tx_executor_handler::metadata::PROGRAM_ID,
-                        )),
+                        prover: Arc::new(app_prover(
+                            &contract_name,
+                            elf,
+                            program_id,
+                            cache_dir(&contract_name),
+                            scheduling(ProofPriority::Low),
+                            None,
+                            config.bonsai_contracts.contains(&contract_name),
+                            prover_controls,
+                        )?),
                         contract_name,
-                        node: node_client.clone(),
+                        node,
                         max_txs_per_proof: config.smt_max_txs_per_proof,
                         tx_working_window_size: config.smt_tx_working_window_size,
                         api: None,
@@ -0,0 +1,375 @@
+use std::{
+    fs::{self, File},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{bail, Context, Result};
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use futures::{StreamExt, TryStreamExt};
+use hyli_modules::{
+    bus::SharedMessageBus, log_error, module_bus_client, module_handle_messages, modules::Module,
+};
+use object_store::{aws::AmazonS3Builder, path::Path as ObjectPath, ObjectStore, WriteMultipart};
+use opentelemetry::{metrics::Counter, KeyValue};
+use serde::Serialize;
+use tokio::sync::mpsc;
+
+use crate::conf::BackupConf;
+
+/// Size of the archive chunks handed from the archiving thread to the upload.
+const CHUNK_SIZE: usize = 1024 * 1024;
+/// Parts of a backup uploaded concurrently.
+const MAX_CONCURRENT_PARTS: usize = 4;
+
+/// A backup stored in the bucket.
+#[derive(Debug, Clone, Serialize)]
+pub struct BackupInfo {
+    pub name: String,
+    pub size: u64,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Compressed tar archives of the data directory, in an S3-compatible bucket.
+///
+/// Credentials are read from the usual `AWS_*` environment variables.
+pub struct BackupStore {
+    store: Arc<dyn ObjectStore>,
+    prefix: String,
+}
+
+impl BackupStore {
+    pub fn new(conf: &BackupConf) -> Result<Self> {
+        let mut builder = AmazonS3Builder::from_env()
+            .with_bucket_name(&conf.bucket)
+            .with_region(&conf.region);
+        if let Some(endpoint) = &conf.endpoint {
+            builder = builder
+                .with_endpoint(endpoint)
+                .with_allow_http(endpoint.starts_with("http://"))
+                .with_virtual_hosted_style_request(false);
+        }
+        Ok(Self {
+            store: Arc::new(builder.build().context("configuring backup bucket")?),
+            prefix: conf.prefix.trim_matches('/').to_string(),
+        })
+    }
+
+    fn path(&self, name: &str) -> ObjectPath {
+        ObjectPath::from(format!("{}/{name}", self.prefix))
+    }
+
+    /// Backups in the bucket, most recent first.
+    pub async fn list(&self) -> Result<Vec<BackupInfo>> {
+        let prefix = ObjectPath::from(self.prefix.as_str());
+        let mut backups: Vec<BackupInfo> = self
+            .store
+            .list(Some(&prefix))
+            .map_ok(|meta| BackupInfo {
+                name: meta.location.filename().unwrap_or_default().to_string(),
+                size: meta.size,
+                created_at: meta.last_modified,
+            })
+            .try_filter(|backup| futures::future::ready(backup.name.ends_with(".tar.gz")))
+            .try_collect()
+            .await
+            .context("listing backups")?;
+        backups.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+        Ok(backups)
+    }
+
+    /// Archives `data_directory`, but for its top-level entries listed in `exclude`, and streams
+    /// the archive to the bucket as `<id>-<timestamp>.tar.gz`.
+    pub async fn upload(
+        &self,
+        id: &str,
+        data_directory: PathBuf,
+        exclude: Vec<String>,
+    ) -> Result<BackupInfo> {
+        let created_at = Utc::now();
+        let name = format!("{id}-{}.tar.gz", created_at.format("%Y%m%dT%H%M%SZ"));
+
+        let (chunks, mut received) = mpsc::channel(MAX_CONCURRENT_PARTS);
+        let archive = tokio::task::spawn_blocking(move || {
+            write_archive(&data_directory, &exclude, ChunkWriter::new(chunks))
+        });
+
+        let mut upload = WriteMultipart::new(
+            self.store
+                .put_multipart(&self.path(&name))
+                .await
+                .context("starting backup upload")?,
+        );
+        let mut size = 0;
+        while let Some(chunk) = received.recv().await {
+            size += chunk.len() as u64;
+            upload.wait_for_capacity(MAX_CONCURRENT_PARTS).await?;
+            upload.put(chunk);
+        }
+        match archive.await.context("archiving task failed")? {
+            Ok(()) => {
+                upload.finish().await.context("completing backup upload")?;
+            }
+            Err(e) => {
+                let _ = upload.abort().await;
+                return Err(e.context("archiving data directory"));
+            }
+        }
+
+        Ok(BackupInfo {
+            name,
+            size,
+            created_at,
+        })
+    }
+
+    /// Deletes the backups past the `keep_last` most recent ones, and those older than
+    /// `max_age_secs` when it's not 0. The most recent backup is always kept.
+    pub async fn apply_retention(&self, keep_last: usize, max_age_secs: u64) -> Result<usize> {
+        let now = Utc::now();
+        let max_age = Duration::from_secs(max_age_secs);
+        let mut deleted = 0;
+        for (i, backup) in self.list().await?.into_iter().enumerate().skip(1) {
+            let too_old = max_age_secs > 0
+                && (now - backup.created_at).to_std().unwrap_or_default() > max_age;
+            if i < keep_last.max(1) && !too_old {
+                continue;
+            }
+            self.store
+                .delete(&self.path(&backup.name))
+                .await
+                .with_context(|| format!("deleting backup {}", backup.name))?;
+            deleted += 1;
+        }
+        Ok(deleted)
+    }
+
+    /// Downloads backup `name`, or the most recent one for `latest`, and extracts it into
+    /// `target`, which must not hold anything yet.
+    pub async fn restore(&self, name: &str, target: &Path) -> Result<BackupInfo> {
+        if fs::read_dir(target).is_ok_and(|mut entries| entries.next().is_some()) {
+            bail!(
+                "{} is not empty, move it away before restoring",
+                target.display()
+            );
+        }
+        let backups = self.list().await?;
+        let backup = if name == "latest" {
+            backups.into_iter().next().context("no backup to restore")?
+        } else {
+            backups
+                .into_iter()
+                .find(|backup| backup.name == name)
+                .with_context(|| format!("backup {name} not found"))?
+        };
+
+        // Downloaded next to the target first, extracting doesn't need to keep up with the
+        // download.
+        fs::create_dir_all(target)?;
+        let download = target.with_extension("restore.tar.gz");
+        {
+            let mut file = File::create(&download)?;
+            let mut stream = self
+                .store
+                .get(&self.path(&backup.name))
+                .await
+                .with_context(|| format!("downloading backup {}", backup.name))?
+                .into_stream();
+            while let Some(chunk) = stream.next().await {
+                file.write_all(&chunk?)?;
+            }
+            file.sync_all()?;
+        }
+
+        let target = target.to_path_buf();
+        let archive = download.clone();
+        tokio::task::spawn_blocking(move || -> Result<()> {
+            tar::Archive::new(GzDecoder::new(File::open(&archive)?))
+                .unpack(&target)
+                .context("extracting backup")
+        })
+        .await??;
+        fs::remove_file(&download)?;
+        Ok(backup)
+    }
+}
+
+/// Hands what is written to it over to the upload, in [`CHUNK_SIZE`] chunks.
+struct ChunkWriter {
+    chunks: mpsc::Sender<Bytes>,
+    buffer: Vec<u8>,
+}
+
+impl ChunkWriter {
+    fn new(chunks: mpsc::Sender<Bytes>) -> Self {
+        Self {
+            chunks,
+            buffer: Vec::with_capacity(CHUNK_SIZE),
+        }
+    }
+
+    fn send(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let chunk = std::mem::replace(&mut self.buffer, Vec::with_capacity(CHUNK_SIZE));
+        self.chunks
+            .blocking_send(chunk.into())
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "backup upload stopped"))
+    }
+}
+
+impl Write for ChunkWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        if self.buffer.len() >= CHUNK_SIZE {
+            self.send()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.send()
+    }
+}
+
+fn write_archive(data_directory: &Path, exclude: &[String], writer: impl Write) -> Result<()> {
+    let mut archive = tar::Builder::new(GzEncoder::new(writer, Compression::default()));
+    for entry in fs::read_dir(data_directory)? {
+        let entry = entry?;
+        let name = PathBuf::from(entry.file_name());
+        if exclude.iter().any(|excluded| Path::new(excluded) == name) {
+            continue;
+        }
+        append(&mut archive, &entry.path(), &name)?;
+    }
+    archive.into_inner()?.finish()?.flush()?;
+    Ok(())
+}
+
+/// Appends `path` as `name`, recursing into directories.
+///
+/// Modules keep writing while the archive is made. Files are archived with the size they had
+/// when opened, so one appended to meanwhile is cut at that size, and one replaced meanwhile is
+/// archived as it was.
+fn append<W: Write>(archive: &mut tar::Builder<W>, path: &Path, name: &Path) -> Result<()> {
+    let metadata = fs::symlink_metadata(path)?;
+    if metadata.is_dir() {
+        archive.append_dir(name, path)?;
+        for entry in fs::read_dir(path)? {
+            let entry = entry?;
+            append(archive, &entry.path(), &name.join(entry.file_name()))?;
+        }
+        return Ok(());
+    }
+    if !metadata.is_file() {
+        return Ok(());
+    }
+    let file = match File::open(path) {
+        Ok(file) => file,
+        // Removed since it was listed.
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    let metadata = file.metadata()?;
+    let mut header = tar::Header::new_gnu();
+    header.set_metadata(&metadata);
+    let size = metadata.len();
+    // Pads a file truncated meanwhile, the archive must hold the size of its header.
+    let data = file.take(size).chain(io::repeat(0)).take(size);
+    archive
+        .append_data(&mut header, name, data)
+        .with_context(|| format!("archiving {}", path.display()))?;
+    Ok(())
+}
+
+/// Periodically backs the data directory up to the bucket of [`BackupConf`], and deletes the
+/// backups its retention policy doesn't keep. Backups are restored with the `restore-backup`
+/// binary.
+pub struct BackupModule {
+    #[allow(unused)]
+    bus: BackupModuleBusClient,
+    ctx: BackupModuleCtx,
+    store: BackupStore,
+    backups: Counter<u64>,
+}
+
+pub struct BackupModuleCtx {
+    pub conf: BackupConf,
+    /// Prefix of the backup names, the server id.
+    pub id: String,
+    pub data_directory: PathBuf,
+}
+
+module_bus_client! {
+#[derive(Debug)]
+pub struct BackupModuleBusClient {
+}
+}
+
+impl BackupModule {
+    async fn backup(&self) -> Result<()> {
+        let backup = self
+            .store
+            .upload(
+                &self.ctx.id,
+                self.ctx.data_directory.clone(),
+                self.ctx.conf.exclude.clone(),
+            )
+            .await?;
+        tracing::info!(
+            "💾 Backed up data directory as {} ({} bytes)",
+            backup.name,
+            backup.size
+        );
+
+        let deleted = self
+            .store
+            .apply_retention(self.ctx.conf.keep_last, self.ctx.conf.max_age_secs)
+            .await?;
+        if deleted > 0 {
+            tracing::info!("Deleted {deleted} backups past retention");
+        }
+        Ok(())
+    }
+}
+
+impl Module for BackupModule {
+    type Context = BackupModuleCtx;
+
+    async fn build(bus: SharedMessageBus, ctx: Self::Context) -> Result<Self> {
+        let meter = opentelemetry::global::meter("wallet_backup");
+        Ok(Self {
+            bus: BackupModuleBusClient::new_from_bus(bus.new_handle()).await,
+            store: BackupStore::new(&ctx.conf)?,
+            ctx,
+            backups: meter
+                .u64_counter("backups_total")
+                .with_description("Number of data directory backups, by outcome")
+                .build(),
+        })
+    }
+
+    async fn run(&mut self) -> Result<()> {
+        let mut interval = tokio::time::interval(Duration::from_secs(self.ctx.conf.interval_secs));
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        // The first tick is immediate, the data directory was just loaded.
+        interval.tick().await;
+
+        module_handle_messages! {
+            on_self self,
+            _ = interval.tick() => {
+                let outcome = match log_error!(self.backup().await, "Backing up data directory") {
+                    Ok(()) => "success",
+                    Err(_) => "failure",
+                };
+                self.backups.add(1, &[KeyValue::new("outcome", outcome)]);
+            }
+        };
+        Ok(())
+    }
+}
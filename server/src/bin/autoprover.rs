@@ -1,48 +1,104 @@
 use std::{
-    collections::HashSet,
+    collections::{HashMap, HashSet},
     sync::{Arc, Mutex},
     time::Duration,
 };
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use axum::Router;
 use clap::Parser;
-use client_sdk::{helpers::risc0::Risc0Prover, rest_client::NodeApiHttpClient};
+use client_sdk::rest_client::NodeApiHttpClient;
 use hyli_modules::{
     bus::SharedMessageBus,
     modules::{
         admin::{AdminApi, AdminApiRunContext},
         contract_listener::{ContractListener, ContractListenerConf},
-        prover::{AutoProver, AutoProverCtx},
         rest::{RestApi, RestApiRunContext},
         BuildApiContextInner, ModulesHandler, ModulesHandlerOptions,
     },
-    utils::logger::setup_tracing,
 };
 use sdk::{api::NodeInfo, info, ContractName};
-use server::conf::Conf;
-use wallet::client::tx_executor_handler::Wallet;
+use server::{
+    autoprovers::{setup_autoprovers_modules, AutoProversConfig, ProgramOverride},
+    conf::Conf,
+    provers::{prover_admin_router, ProverControls},
+    telemetry::setup_telemetry,
+};
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 pub struct Args {
     #[arg(long, default_value = "config.toml")]
     pub config_file: Vec<String>,
+
+    /// Contracts to prove. Those listed in `--wallet-cn` are proven as wallets, the others as
+    /// SMT tokens.
+    #[arg(long, value_delimiter = ',', default_value = "wallet")]
+    pub contracts: Vec<String>,
+
+    #[arg(long, value_delimiter = ',', default_value = "wallet")]
+    pub wallet_cn: Vec<String>,
+
+    /// Guest ELF to use for a contract instead of the built-in one, as `contract=path`.
+    #[arg(long = "elf", value_parser = parse_key_value)]
+    pub elfs: Vec<(String, String)>,
+
+    /// Hex program id to use for a contract instead of the built-in one, as `contract=id`.
+    #[arg(long = "program-id", value_parser = parse_key_value)]
+    pub program_ids: Vec<(String, String)>,
+}
+
+fn parse_key_value(arg: &str) -> Result<(String, String)> {
+    let (key, value) = arg
+        .split_once('=')
+        .with_context(|| format!("expected contract=value, got {arg}"))?;
+    Ok((key.to_string(), value.to_string()))
+}
+
+fn program_overrides(args: &Args) -> Result<HashMap<ContractName, ProgramOverride>> {
+    let mut overrides: HashMap<ContractName, ProgramOverride> = HashMap::new();
+    for (contract, path) in &args.elfs {
+        if !args.contracts.contains(contract) {
+            bail!("--elf given for {contract}, which is not in --contracts");
+        }
+        let elf = std::fs::read(path).with_context(|| format!("reading ELF {path}"))?;
+        overrides.entry(contract.clone().into()).or_default().elf = Some(elf);
+    }
+    for (contract, program_id) in &args.program_ids {
+        if !args.contracts.contains(contract) {
+            bail!("--program-id given for {contract}, which is not in --contracts");
+        }
+        let program_id: [u8; 32] = hex::decode(program_id.trim_start_matches("0x"))
+            .with_context(|| format!("program id of {contract} must be hex"))?
+            .try_into()
+            .map_err(|_| anyhow::anyhow!("program id of {contract} must be 32 bytes"))?;
+        overrides
+            .entry(contract.clone().into())
+            .or_default()
+            .program_id = Some(program_id);
+    }
+    Ok(overrides)
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let args = Args::parse();
-    let config = Conf::new(args.config_file).context("reading config file")?;
-    setup_tracing(
-        &config.log_format,
-        format!("{}(autoprover)", config.id.clone(),),
-    )
-    .context("setting up tracing")?;
+    let config = Conf::new(args.config_file.clone()).context("reading config file")?;
+    setup_telemetry(&config, format!("{}(autoprover)", config.id)).context("setting up tracing")?;
     let config = Arc::new(config);
 
     info!("Starting autoprover with config: {:?}", &config);
 
+    config
+        .validate(
+            &[
+                ("rest_server_port", config.rest_server_port),
+                ("admin_server_port", config.admin_server_port),
+            ],
+            false,
+        )
+        .await?;
+
     let node_client =
         Arc::new(NodeApiHttpClient::new(config.node_url.clone()).context("build node client")?);
 
@@ -58,39 +114,52 @@ async fn main() -> Result<()> {
         openapi: Default::default(),
     });
 
-    // Ajout de l'autoprover du wallet
-    let wallet_cn: ContractName = "wallet".into();
+    let prover_controls = Arc::new(ProverControls::default());
+
+    let wallet_cns: Vec<ContractName> = args.wallet_cn.iter().cloned().map(ContractName).collect();
+    let contracts: HashSet<ContractName> =
+        args.contracts.iter().cloned().map(ContractName).collect();
+    let program_overrides = program_overrides(&args)?;
 
     handler
         .build_module::<ContractListener>(ContractListenerConf {
             database_url: config.indexer_database_url.clone(),
             data_directory: config.data_directory.clone(),
-            contracts: HashSet::from([wallet_cn.clone()]),
+            contracts: contracts.clone(),
             poll_interval: Duration::from_secs(config.auto_prover_listener_poll_interval_secs),
-            replay_settled_from_start: true,
+            replay_settled_from_start: config.listener_replay_settled_from_start,
         })
         .await?;
-    handler
-        .build_module::<AutoProver<Wallet, Risc0Prover>>(Arc::new(AutoProverCtx {
-            data_directory: config.data_directory.clone(),
-            prover: Arc::new(client_sdk::helpers::risc0::Risc0Prover::new(
-                contracts::WALLET_ELF.to_vec(),
-                contracts::WALLET_ID,
-            )),
-            contract_name: wallet_cn,
-            node: node_client.clone(),
-            api: Some(api_ctx.clone()),
-            max_txs_per_proof: config.wallet_max_txs_per_proof,
-            tx_working_window_size: config.wallet_tx_working_window_size,
-            idle_flush_interval: Duration::from_secs(config.auto_prover_idle_flush_interval_secs),
-            tx_buffer_size: config.auto_prover_tx_buffer_size,
-        }))
-        .await?;
+    setup_autoprovers_modules(
+        &AutoProversConfig {
+            wallet_auto_prove: wallet_cns.iter().any(|c| contracts.contains(c)),
+            smt_auto_prove: contracts.iter().any(|c| !wallet_cns.contains(c)),
+            smt_contracts: contracts
+                .iter()
+                .filter(|c| !wallet_cns.contains(c))
+                .cloned()
+                .collect(),
+            program_overrides,
+            ..AutoProversConfig::new(
+                &config,
+                wallet_cns
+                    .iter()
+                    .filter(|c| contracts.contains(c))
+                    .cloned()
+                    .collect(),
+            )
+        },
+        &mut handler,
+        api_ctx.clone(),
+        node_client.clone(),
+        &prover_controls,
+    )
+    .await?;
 
     handler
         .build_module::<AdminApi>(AdminApiRunContext::new(
             config.admin_server_port,
-            Router::new(),
+            prover_admin_router(prover_controls),
             config.admin_server_max_body_size,
             config.data_directory.clone(),
         ))
@@ -0,0 +1,244 @@
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use clap::Parser;
+use client_sdk::{
+    helpers::{risc0::Risc0Prover, ClientSdkProver},
+    transaction_builder::TxExecutorHandler,
+};
+use sdk::{
+    verifiers::Secp256k1Blob, Blob, BlobIndex, Calldata, Identity, IndexedBlobs, ZkContract,
+};
+use secp256k1::{Message, PublicKey, Secp256k1, SecretKey};
+use server::{new_wallet, secrets::Secrets};
+use sha2::{Digest, Sha256};
+use wallet::{client::tx_executor_handler::Wallet, AuthMethod, WalletAction, WalletZkView};
+
+/// Measures wallet state and proving costs, to tune `wallet_max_txs_per_proof`.
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+pub struct Args {
+    /// Number of synthetic accounts to register before measuring.
+    #[arg(long, default_value_t = 10_000)]
+    pub accounts: usize,
+
+    /// Number of samples for the per-transaction measurements.
+    #[arg(long, default_value_t = 1_000)]
+    pub samples: usize,
+
+    /// Batch sizes to execute and prove.
+    #[arg(long, value_delimiter = ',', default_value = "1,10,50,100")]
+    pub batch_sizes: Vec<usize>,
+
+    /// Also generate proofs for each batch size.
+    #[arg(long)]
+    pub prove: bool,
+
+    /// Use risc0 dev mode, which executes the guest but skips the actual proving.
+    #[arg(long)]
+    pub dev_mode: bool,
+}
+
+const WALLET_CN: &str = "wallet";
+
+struct Signer {
+    secp: Secp256k1<secp256k1::All>,
+    key: SecretKey,
+    public_key: PublicKey,
+}
+
+impl Signer {
+    fn new(key: SecretKey) -> Self {
+        let secp = Secp256k1::new();
+        let public_key = PublicKey::from_secret_key(&secp, &key);
+        Self {
+            secp,
+            key,
+            public_key,
+        }
+    }
+
+    fn blob(&self, identity: &Identity, data: &str) -> Result<Blob> {
+        let digest: [u8; 32] = Sha256::digest(data.as_bytes()).into();
+        let signature = self
+            .secp
+            .sign_ecdsa(Message::from_digest(digest), &self.key);
+        Ok(Secp256k1Blob::new(
+            identity.clone(),
+            data.as_bytes(),
+            &self.public_key.to_string(),
+            &signature.to_string(),
+        )?
+        .as_blob())
+    }
+}
+
+fn account(i: usize) -> String {
+    format!("bench{i}")
+}
+
+fn identity(account: &str) -> Identity {
+    Identity::new(format!("{account}@{WALLET_CN}"))
+}
+
+fn calldata(identity: Identity, blobs: Vec<Blob>) -> Calldata {
+    Calldata {
+        tx_blob_count: blobs.len(),
+        identity,
+        blobs: IndexedBlobs::from(blobs),
+        index: BlobIndex(0),
+        ..Default::default()
+    }
+}
+
+fn register_calldata(account: &str, user: &Signer, invites: &Signer) -> Result<Calldata> {
+    let identity = identity(account);
+    let invite_code = "bench".to_string();
+    let blobs = vec![
+        WalletAction::RegisterIdentity {
+            account: account.to_string(),
+            nonce: 0,
+            salt: String::new(),
+            auth_method: AuthMethod::HyliApp {
+                address: hex::encode(&user.public_key.serialize()[..20]),
+            },
+            invite_code: invite_code.clone(),
+        }
+        .as_blob(WALLET_CN.into()),
+        user.blob(&identity, &format!("{identity}:0:hyliapp"))?,
+        invites.blob(&identity, &format!("Invite - {invite_code} for {account}"))?,
+    ];
+    Ok(calldata(identity, blobs))
+}
+
+fn verify_calldata(account: &str, nonce: u128, user: &Signer) -> Result<Calldata> {
+    let identity = identity(account);
+    let blobs = vec![
+        WalletAction::VerifyIdentity {
+            account: account.to_string(),
+            nonce,
+        }
+        .as_blob(WALLET_CN.into()),
+        user.blob(&identity, &format!("{identity}:{nonce}:hyliapp"))?,
+    ];
+    Ok(calldata(identity, blobs))
+}
+
+/// Builds the merged commitment metadata of a batch the way the autoprover does, applying each
+/// transaction to `wallet` along the way.
+fn batch_metadata(wallet: &mut Wallet, calldatas: &[Calldata]) -> Result<Vec<u8>> {
+    let mut merged: Option<Vec<u8>> = None;
+    for calldata in calldatas {
+        let metadata = wallet.build_commitment_metadata(calldata)?;
+        wallet.handle(calldata)?;
+        merged = Some(match merged {
+            None => metadata,
+            Some(initial) => wallet
+                .merge_commitment_metadata(initial, metadata)
+                .map_err(|e| anyhow::anyhow!(e))?,
+        });
+    }
+    merged.context("empty batch")
+}
+
+fn report(name: &str, total: Duration, count: usize) {
+    println!(
+        "{name:<32} {count:>8} ops {:>12.3?} total {:>12.3?}/op",
+        total,
+        total / count.max(1) as u32
+    );
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse();
+    if args.dev_mode {
+        std::env::set_var("RISC0_DEV_MODE", "1");
+    }
+
+    let invites = Signer::new(SecretKey::from_byte_array(rand::random())?);
+    let user = Signer::new(SecretKey::from_byte_array(rand::random())?);
+    let (_, mut wallet) = new_wallet(
+        &WALLET_CN.into(),
+        &Secrets {
+            invite_code_secret_key: invites.key,
+            hyli_password: zeroize::Zeroizing::new("bench".to_string()),
+            next_invite_code_secret_key: None,
+        },
+    );
+
+    let start = Instant::now();
+    for i in 0..args.accounts {
+        wallet
+            .handle(&register_calldata(&account(i), &user, &invites)?)
+            .with_context(|| format!("registering {}", account(i)))?;
+    }
+    report("register accounts", start.elapsed(), args.accounts);
+
+    let sample_accounts: Vec<String> = (0..args.samples)
+        .map(|i| account(i % args.accounts.max(1)))
+        .collect();
+    let mut nonce = 1;
+    let sample_calls = sample_accounts
+        .iter()
+        .map(|account| verify_calldata(account, nonce, &user))
+        .collect::<Result<Vec<_>>>()?;
+
+    let start = Instant::now();
+    for account in &sample_accounts {
+        wallet.merkle_proof(std::slice::from_ref(account))?;
+    }
+    report("merkle proof", start.elapsed(), args.samples);
+
+    let start = Instant::now();
+    for calldata in &sample_calls {
+        wallet.build_commitment_metadata(calldata)?;
+    }
+    report("build_commitment_metadata", start.elapsed(), args.samples);
+
+    let prover = Risc0Prover::new(contracts::WALLET_ELF.to_vec(), contracts::WALLET_ID);
+    for &batch_size in &args.batch_sizes {
+        nonce += 1;
+        let calldatas = (0..batch_size)
+            .map(|i| verify_calldata(&account(i % args.accounts.max(1)), nonce, &user))
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut batch_wallet = wallet.clone();
+        let start = Instant::now();
+        let metadata = batch_metadata(&mut batch_wallet, &calldatas)?;
+        report(
+            &format!("batch {batch_size}: metadata"),
+            start.elapsed(),
+            batch_size,
+        );
+
+        let start = Instant::now();
+        let mut zk_view: WalletZkView = borsh::from_slice(&metadata)?;
+        for calldata in &calldatas {
+            zk_view
+                .execute(calldata)
+                .map_err(|e| anyhow::anyhow!(e))
+                .context("executing guest logic")?;
+        }
+        report(
+            &format!("batch {batch_size}: execution"),
+            start.elapsed(),
+            batch_size,
+        );
+
+        if args.prove {
+            let start = Instant::now();
+            prover
+                .prove(metadata, calldatas)
+                .await
+                .with_context(|| format!("proving batch of {batch_size}"))?;
+            report(
+                &format!("batch {batch_size}: proving"),
+                start.elapsed(),
+                batch_size,
+            );
+        }
+    }
+
+    Ok(())
+}
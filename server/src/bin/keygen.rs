@@ -0,0 +1,78 @@
+use anyhow::{Context, Result};
+use clap::{Parser, Subcommand};
+use secp256k1::{PublicKey, Secp256k1, SecretKey};
+
+/// Generates secp256k1 keypairs and prints them in the formats used across the wallet.
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+pub struct Args {
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Invite code keypair, for `invite_code_pkey` and the contract's invite public key.
+    Invite {
+        /// Derive from an existing hex-encoded secret key instead of generating one.
+        #[arg(long)]
+        from: Option<String>,
+    },
+    /// Session keypair, as registered with `AddSessionKey`.
+    Session {
+        /// Derive from an existing hex-encoded secret key instead of generating one.
+        #[arg(long)]
+        from: Option<String>,
+    },
+}
+
+fn secret_key(from: Option<String>) -> Result<SecretKey> {
+    let bytes: [u8; 32] = match from {
+        Some(hex_key) => hex::decode(hex_key.trim_start_matches("0x"))
+            .context("key must be hex")?
+            .try_into()
+            .map_err(|_| anyhow::anyhow!("key must be 32 bytes"))?,
+        None => rand::random(),
+    };
+    SecretKey::from_byte_array(bytes).context("invalid secp256k1 key")
+}
+
+/// Formats a compressed public key like `DEFAULT_INVITE_CODE_PUBLIC_KEY`.
+fn rust_literal(public_key: &PublicKey) -> String {
+    let bytes = public_key
+        .serialize()
+        .iter()
+        .map(|b| b.to_string())
+        .collect::<Vec<_>>()
+        .join(", ");
+    format!("[{bytes}]")
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    let secp = Secp256k1::new();
+
+    match args.command {
+        Command::Invite { from } => {
+            let secret_key = secret_key(from)?;
+            let public_key = PublicKey::from_secret_key(&secp, &secret_key);
+            println!(
+                "secret key (invite_code_pkey): {}",
+                hex::encode(secret_key.secret_bytes())
+            );
+            println!("public key: {public_key}");
+            println!(
+                "public key (InviteCodePubKey): {}",
+                rust_literal(&public_key)
+            );
+        }
+        Command::Session { from } => {
+            let secret_key = secret_key(from)?;
+            let public_key = PublicKey::from_secret_key(&secp, &secret_key);
+            println!("secret key: {}", hex::encode(secret_key.secret_bytes()));
+            println!("public key: {public_key}");
+        }
+    }
+
+    Ok(())
+}
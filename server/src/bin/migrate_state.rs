@@ -0,0 +1,510 @@
+use std::{collections::HashMap, path::PathBuf};
+
+use anyhow::{bail, Context, Result};
+use borsh::BorshDeserialize;
+use clap::Parser;
+use client_sdk::{
+    rest_client::{NodeApiClient, NodeApiHttpClient},
+    transaction_builder::TxExecutorHandler,
+};
+use sdk::{hyli_model_utils::TimestampMs, ContractName, LaneId, StateCommitment};
+use wallet::{
+    client::tx_executor_handler::Wallet, AccountInfo, AuthMethod, FailedAuth, InviteCodePubKey,
+    PendingRecovery, Recovery, SessionKey, SessionKeyType, TimePolicy, UsedJwtId,
+};
+
+/// Wallet state format written by this version of the code.
+const CURRENT_VERSION: u32 = 9;
+
+/// Session keys before usage limits were added.
+#[derive(BorshDeserialize)]
+struct SessionKeyV1 {
+    public_key: String,
+    expiration_date: TimestampMs,
+    whitelist: Option<Vec<ContractName>>,
+    lane_id: Option<LaneId>,
+}
+
+impl From<SessionKeyV1> for SessionKey {
+    fn from(session_key: SessionKeyV1) -> Self {
+        SessionKey {
+            public_key: session_key.public_key,
+            expiration_date: session_key.expiration_date,
+            whitelist: session_key.whitelist,
+            lane_id: session_key.lane_id,
+            remaining_uses: None,
+            max_value_per_tx: None,
+            key_type: SessionKeyType::Secp256k1,
+        }
+    }
+}
+
+/// Session keys before key types were added, all secp256k1.
+#[derive(BorshDeserialize)]
+struct SessionKeyV2 {
+    public_key: String,
+    expiration_date: TimestampMs,
+    whitelist: Option<Vec<ContractName>>,
+    lane_id: Option<LaneId>,
+    remaining_uses: Option<u32>,
+    max_value_per_tx: Option<u128>,
+}
+
+impl From<SessionKeyV2> for SessionKey {
+    fn from(session_key: SessionKeyV2) -> Self {
+        SessionKey {
+            public_key: session_key.public_key,
+            expiration_date: session_key.expiration_date,
+            whitelist: session_key.whitelist,
+            lane_id: session_key.lane_id,
+            remaining_uses: session_key.remaining_uses,
+            max_value_per_tx: session_key.max_value_per_tx,
+            key_type: SessionKeyType::Secp256k1,
+        }
+    }
+}
+
+/// Accounts before `used_jwt_ids` was added.
+#[derive(BorshDeserialize)]
+struct AccountInfoV1 {
+    identity: String,
+    auth_method: AuthMethod,
+    session_keys: Vec<SessionKeyV1>,
+    nonce: u128,
+}
+
+impl From<AccountInfoV1> for AccountInfo {
+    fn from(account: AccountInfoV1) -> Self {
+        AccountInfo {
+            identity: account.identity,
+            auth_method: account.auth_method,
+            session_keys: account
+                .session_keys
+                .into_iter()
+                .map(SessionKey::from)
+                .collect(),
+            nonce: account.nonce,
+            used_jwt_ids: vec![],
+            failed_auth: FailedAuth::default(),
+            recovery: Recovery::default(),
+            frozen: false,
+        }
+    }
+}
+
+/// Accounts before `failed_auth` was added.
+#[derive(BorshDeserialize)]
+struct AccountInfoV2 {
+    identity: String,
+    auth_method: AuthMethod,
+    session_keys: Vec<SessionKeyV1>,
+    nonce: u128,
+    used_jwt_ids: Vec<UsedJwtId>,
+}
+
+impl From<AccountInfoV2> for AccountInfo {
+    fn from(account: AccountInfoV2) -> Self {
+        AccountInfo {
+            identity: account.identity,
+            auth_method: account.auth_method,
+            session_keys: account
+                .session_keys
+                .into_iter()
+                .map(SessionKey::from)
+                .collect(),
+            nonce: account.nonce,
+            used_jwt_ids: account.used_jwt_ids,
+            failed_auth: FailedAuth::default(),
+            recovery: Recovery::default(),
+            frozen: false,
+        }
+    }
+}
+
+/// Accounts before `recovery` was added.
+#[derive(BorshDeserialize)]
+struct AccountInfoV3 {
+    identity: String,
+    auth_method: AuthMethod,
+    session_keys: Vec<SessionKeyV1>,
+    nonce: u128,
+    used_jwt_ids: Vec<UsedJwtId>,
+    failed_auth: FailedAuth,
+}
+
+impl From<AccountInfoV3> for AccountInfo {
+    fn from(account: AccountInfoV3) -> Self {
+        AccountInfo {
+            identity: account.identity,
+            auth_method: account.auth_method,
+            session_keys: account
+                .session_keys
+                .into_iter()
+                .map(SessionKey::from)
+                .collect(),
+            nonce: account.nonce,
+            used_jwt_ids: account.used_jwt_ids,
+            failed_auth: account.failed_auth,
+            recovery: Recovery::default(),
+            frozen: false,
+        }
+    }
+}
+
+/// Recovery settings before `backup_key` was added.
+#[derive(BorshDeserialize)]
+struct RecoveryV1 {
+    email_hash: Option<[u8; 32]>,
+    pending: Option<PendingRecovery>,
+}
+
+impl From<RecoveryV1> for Recovery {
+    fn from(recovery: RecoveryV1) -> Self {
+        Recovery {
+            email_hash: recovery.email_hash,
+            pending: recovery.pending,
+            backup_key: None,
+        }
+    }
+}
+
+#[derive(BorshDeserialize)]
+struct AccountInfoV4 {
+    identity: String,
+    auth_method: AuthMethod,
+    session_keys: Vec<SessionKeyV1>,
+    nonce: u128,
+    used_jwt_ids: Vec<UsedJwtId>,
+    failed_auth: FailedAuth,
+    recovery: RecoveryV1,
+}
+
+impl From<AccountInfoV4> for AccountInfo {
+    fn from(account: AccountInfoV4) -> Self {
+        AccountInfo {
+            identity: account.identity,
+            auth_method: account.auth_method,
+            session_keys: account
+                .session_keys
+                .into_iter()
+                .map(SessionKey::from)
+                .collect(),
+            nonce: account.nonce,
+            used_jwt_ids: account.used_jwt_ids,
+            failed_auth: account.failed_auth,
+            recovery: account.recovery.into(),
+            frozen: false,
+        }
+    }
+}
+
+/// Accounts before session keys had usage limits.
+#[derive(BorshDeserialize)]
+struct AccountInfoV5 {
+    identity: String,
+    auth_method: AuthMethod,
+    session_keys: Vec<SessionKeyV1>,
+    nonce: u128,
+    used_jwt_ids: Vec<UsedJwtId>,
+    failed_auth: FailedAuth,
+    recovery: Recovery,
+}
+
+impl From<AccountInfoV5> for AccountInfo {
+    fn from(account: AccountInfoV5) -> Self {
+        AccountInfo {
+            identity: account.identity,
+            auth_method: account.auth_method,
+            session_keys: account
+                .session_keys
+                .into_iter()
+                .map(SessionKey::from)
+                .collect(),
+            nonce: account.nonce,
+            used_jwt_ids: account.used_jwt_ids,
+            failed_auth: account.failed_auth,
+            recovery: account.recovery,
+            frozen: false,
+        }
+    }
+}
+
+/// Accounts before `frozen` was added.
+#[derive(BorshDeserialize)]
+struct AccountInfoV6 {
+    identity: String,
+    auth_method: AuthMethod,
+    session_keys: Vec<SessionKeyV2>,
+    nonce: u128,
+    used_jwt_ids: Vec<UsedJwtId>,
+    failed_auth: FailedAuth,
+    recovery: Recovery,
+}
+
+impl From<AccountInfoV6> for AccountInfo {
+    fn from(account: AccountInfoV6) -> Self {
+        AccountInfo {
+            identity: account.identity,
+            auth_method: account.auth_method,
+            session_keys: account
+                .session_keys
+                .into_iter()
+                .map(SessionKey::from)
+                .collect(),
+            nonce: account.nonce,
+            used_jwt_ids: account.used_jwt_ids,
+            failed_auth: account.failed_auth,
+            recovery: account.recovery,
+            frozen: false,
+        }
+    }
+}
+
+/// Accounts before session key types were added.
+#[derive(BorshDeserialize)]
+struct AccountInfoV7 {
+    identity: String,
+    auth_method: AuthMethod,
+    session_keys: Vec<SessionKeyV2>,
+    nonce: u128,
+    used_jwt_ids: Vec<UsedJwtId>,
+    failed_auth: FailedAuth,
+    recovery: Recovery,
+    frozen: bool,
+}
+
+impl From<AccountInfoV7> for AccountInfo {
+    fn from(account: AccountInfoV7) -> Self {
+        AccountInfo {
+            identity: account.identity,
+            auth_method: account.auth_method,
+            session_keys: account
+                .session_keys
+                .into_iter()
+                .map(SessionKey::from)
+                .collect(),
+            nonce: account.nonce,
+            used_jwt_ids: account.used_jwt_ids,
+            failed_auth: account.failed_auth,
+            recovery: account.recovery,
+            frozen: account.frozen,
+        }
+    }
+}
+
+#[derive(BorshDeserialize)]
+struct WalletV1 {
+    invite_code_public_key: InviteCodePubKey,
+    accounts: Vec<AccountInfoV1>,
+    salts: HashMap<String, String>,
+}
+
+/// Wallets before the time policy was added.
+#[derive(BorshDeserialize)]
+struct WalletV2 {
+    invite_code_public_key: InviteCodePubKey,
+    accounts: Vec<AccountInfoV2>,
+    salts: HashMap<String, String>,
+}
+
+#[derive(BorshDeserialize)]
+struct WalletV3 {
+    invite_code_public_key: InviteCodePubKey,
+    accounts: Vec<AccountInfoV2>,
+    salts: HashMap<String, String>,
+    time_policy: TimePolicy,
+}
+
+#[derive(BorshDeserialize)]
+struct WalletV4 {
+    invite_code_public_key: InviteCodePubKey,
+    accounts: Vec<AccountInfoV3>,
+    salts: HashMap<String, String>,
+    time_policy: TimePolicy,
+}
+
+#[derive(BorshDeserialize)]
+struct WalletV5 {
+    invite_code_public_key: InviteCodePubKey,
+    accounts: Vec<AccountInfoV4>,
+    salts: HashMap<String, String>,
+    time_policy: TimePolicy,
+}
+
+#[derive(BorshDeserialize)]
+struct WalletV6 {
+    invite_code_public_key: InviteCodePubKey,
+    accounts: Vec<AccountInfoV5>,
+    salts: HashMap<String, String>,
+    time_policy: TimePolicy,
+}
+
+#[derive(BorshDeserialize)]
+struct WalletV7 {
+    invite_code_public_key: InviteCodePubKey,
+    accounts: Vec<AccountInfoV6>,
+    salts: HashMap<String, String>,
+    time_policy: TimePolicy,
+}
+
+#[derive(BorshDeserialize)]
+struct WalletV8 {
+    invite_code_public_key: InviteCodePubKey,
+    accounts: Vec<AccountInfoV7>,
+    salts: HashMap<String, String>,
+    time_policy: TimePolicy,
+}
+
+/// Upgrades a wallet state dump to the current format, checking that the migrated state still
+/// has the expected commitment.
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+pub struct Args {
+    pub input: PathBuf,
+    pub output: PathBuf,
+
+    /// Format version of the input.
+    #[arg(long, default_value_t = CURRENT_VERSION)]
+    pub from_version: u32,
+
+    /// Hex commitment the state must have. Defaults to the on-chain state of `--wallet-cn`.
+    #[arg(long)]
+    pub expected_commitment: Option<String>,
+
+    #[arg(long, default_value = "http://localhost:4321")]
+    pub node_url: String,
+
+    #[arg(long, default_value = "wallet")]
+    pub wallet_cn: String,
+}
+
+/// Decodes a dump written in format `version`. Add a branch here, converting to the current
+/// types, whenever `Wallet` or `AccountInfo` change their borsh layout.
+fn decode(version: u32, dump: &[u8]) -> Result<Wallet> {
+    match version {
+        1 => {
+            let v1: WalletV1 = borsh::from_slice(dump).context("decoding v1 wallet state")?;
+            Wallet::from_parts(
+                v1.invite_code_public_key,
+                v1.accounts.into_iter().map(AccountInfo::from),
+                v1.salts,
+                TimePolicy::default(),
+            )
+        }
+        2 => {
+            let v2: WalletV2 = borsh::from_slice(dump).context("decoding v2 wallet state")?;
+            Wallet::from_parts(
+                v2.invite_code_public_key,
+                v2.accounts.into_iter().map(AccountInfo::from),
+                v2.salts,
+                TimePolicy::default(),
+            )
+        }
+        3 => {
+            let v3: WalletV3 = borsh::from_slice(dump).context("decoding v3 wallet state")?;
+            Wallet::from_parts(
+                v3.invite_code_public_key,
+                v3.accounts.into_iter().map(AccountInfo::from),
+                v3.salts,
+                v3.time_policy,
+            )
+        }
+        4 => {
+            let v4: WalletV4 = borsh::from_slice(dump).context("decoding v4 wallet state")?;
+            Wallet::from_parts(
+                v4.invite_code_public_key,
+                v4.accounts.into_iter().map(AccountInfo::from),
+                v4.salts,
+                v4.time_policy,
+            )
+        }
+        5 => {
+            let v5: WalletV5 = borsh::from_slice(dump).context("decoding v5 wallet state")?;
+            Wallet::from_parts(
+                v5.invite_code_public_key,
+                v5.accounts.into_iter().map(AccountInfo::from),
+                v5.salts,
+                v5.time_policy,
+            )
+        }
+        6 => {
+            let v6: WalletV6 = borsh::from_slice(dump).context("decoding v6 wallet state")?;
+            Wallet::from_parts(
+                v6.invite_code_public_key,
+                v6.accounts.into_iter().map(AccountInfo::from),
+                v6.salts,
+                v6.time_policy,
+            )
+        }
+        7 => {
+            let v7: WalletV7 = borsh::from_slice(dump).context("decoding v7 wallet state")?;
+            Wallet::from_parts(
+                v7.invite_code_public_key,
+                v7.accounts.into_iter().map(AccountInfo::from),
+                v7.salts,
+                v7.time_policy,
+            )
+        }
+        8 => {
+            let v8: WalletV8 = borsh::from_slice(dump).context("decoding v8 wallet state")?;
+            Wallet::from_parts(
+                v8.invite_code_public_key,
+                v8.accounts.into_iter().map(AccountInfo::from),
+                v8.salts,
+                v8.time_policy,
+            )
+        }
+        CURRENT_VERSION => borsh::from_slice(dump).context("decoding wallet state"),
+        _ => bail!("unknown wallet state version {version}"),
+    }
+}
+
+async fn expected_commitment(args: &Args) -> Result<StateCommitment> {
+    match &args.expected_commitment {
+        Some(commitment) => Ok(StateCommitment(
+            hex::decode(commitment.trim_start_matches("0x"))
+                .context("expected commitment must be hex")?,
+        )),
+        None => {
+            let node =
+                NodeApiHttpClient::new(args.node_url.clone()).context("build node client")?;
+            Ok(node
+                .get_contract(ContractName(args.wallet_cn.clone()))
+                .await
+                .context("fetching on-chain wallet state")?
+                .state)
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse();
+    let expected = expected_commitment(&args).await?;
+
+    let dump = tokio::fs::read(&args.input)
+        .await
+        .with_context(|| format!("reading {}", args.input.display()))?;
+    let wallet = decode(args.from_version, &dump)?;
+    if wallet.get_state_commitment() != expected {
+        bail!(
+            "input state commitment {} does not match expected {}",
+            hex::encode(&wallet.get_state_commitment().0),
+            hex::encode(&expected.0)
+        );
+    }
+
+    let migrated = borsh::to_vec(&wallet).context("encoding wallet state")?;
+    // Decode again to make sure the written file restores to the same commitment.
+    Wallet::restore(&migrated, &expected).context("checking migrated state")?;
+
+    tokio::fs::write(&args.output, &migrated)
+        .await
+        .with_context(|| format!("writing {}", args.output.display()))?;
+    println!(
+        "migrated wallet state from version {} to {CURRENT_VERSION}, commitment {}",
+        args.from_version,
+        hex::encode(&expected.0)
+    );
+    Ok(())
+}
@@ -0,0 +1,51 @@
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use clap::Parser;
+use server::{backup::BackupStore, conf::Conf};
+
+/// Restores a backup of the data directory made by the server's backup module, reading the
+/// bucket from the server configuration. Run it while the server is stopped.
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+pub struct Args {
+    #[arg(long, default_value = "config.toml")]
+    pub config_file: Vec<String>,
+
+    /// Backup to restore, as listed by `--list`.
+    #[arg(default_value = "latest")]
+    pub name: String,
+
+    /// Directory to extract the backup into, which must be empty. Defaults to the configured
+    /// data directory.
+    #[arg(long)]
+    pub target: Option<PathBuf>,
+
+    /// List the backups instead of restoring one.
+    #[arg(long, default_value = "false")]
+    pub list: bool,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse();
+    let config = Conf::new(args.config_file).context("reading config file")?;
+    let store = BackupStore::new(&config.backup)?;
+
+    if args.list {
+        for backup in store.list().await? {
+            println!("{}\t{}\t{}", backup.name, backup.created_at, backup.size);
+        }
+        return Ok(());
+    }
+
+    let target = args.target.unwrap_or(config.data_directory);
+    let backup = store.restore(&args.name, &target).await?;
+    println!(
+        "Restored {} ({}) into {}",
+        backup.name,
+        backup.created_at,
+        target.display()
+    );
+    Ok(())
+}
@@ -0,0 +1,678 @@
+use std::{
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result};
+use clap::{Parser, Subcommand};
+use client_sdk::rest_client::{NodeApiClient, NodeApiHttpClient};
+use sdk::{verifiers::Secp256k1Blob, Blob, BlobTransaction, ContractName, Identity};
+use secp256k1::{Message, PublicKey, Secp256k1, SecretKey};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use wallet::{
+    backup_key_recovery_data,
+    client::{
+        keystore::{KdfParams, Keystore, KeystoreContents, KeystoreSessionKey},
+        mapped::MappedWallet,
+        tx_executor_handler::Wallet,
+    },
+    AuthMethod, WalletAction,
+};
+
+/// Scripts wallet operations against a running node and wallet server.
+///
+/// Accounts are authenticated with a secp256k1 key (the HyliApp auth method), since password
+/// accounts need a zero-knowledge proof generated by the frontend.
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+pub struct Args {
+    #[arg(long, default_value = "http://localhost:4321")]
+    pub node_url: String,
+
+    #[arg(long, default_value = "http://localhost:4000")]
+    pub server_url: String,
+
+    #[arg(long, default_value = "wallet")]
+    pub wallet_cn: String,
+
+    /// Hex-encoded secp256k1 secret key authenticating the account.
+    #[arg(long, env = "WALLET_CLI_KEY", hide_env_values = true)]
+    pub key: Option<String>,
+
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Register a new account authenticated by `--key`.
+    Register {
+        account: String,
+        #[arg(long)]
+        invite_code: String,
+        /// Dormant backup key (hex-encoded compressed public key) that can later reset the
+        /// account's auth method with `recover-with-backup-key`.
+        #[arg(long)]
+        backup_key: Option<String>,
+    },
+    /// Prove ownership of the account and bump its nonce.
+    Verify { account: String },
+    /// Add a session key (hex-encoded compressed public key).
+    AddSessionKey {
+        account: String,
+        session_key: String,
+        /// Expiration as a timestamp in milliseconds.
+        #[arg(long)]
+        expiration: u128,
+        /// Contracts the session key may be used with.
+        #[arg(long, value_delimiter = ',')]
+        whitelist: Option<Vec<String>>,
+        /// Number of times the session key may be used.
+        #[arg(long)]
+        max_uses: Option<u32>,
+        /// Most tokens a transaction signed with the session key may move.
+        #[arg(long)]
+        max_value_per_tx: Option<u128>,
+    },
+    /// Remove a session key (hex-encoded compressed public key).
+    RemoveSessionKey {
+        account: String,
+        session_key: String,
+    },
+    /// Extend the expiration of a session key (hex-encoded compressed public key).
+    RenewSessionKey {
+        account: String,
+        session_key: String,
+        /// New expiration as a timestamp in milliseconds.
+        #[arg(long)]
+        expiration: u128,
+    },
+    /// Block the session keys and identity verification of the account.
+    Freeze { account: String },
+    /// Lift a freeze.
+    Unfreeze { account: String },
+    /// Transfer tokens, signing with a session key.
+    Transfer {
+        account: String,
+        /// Hex-encoded secret key of a registered session key.
+        #[arg(long, env = "WALLET_CLI_SESSION_KEY", hide_env_values = true)]
+        session_key: String,
+        #[arg(long)]
+        token: String,
+        #[arg(long)]
+        to: String,
+        #[arg(long)]
+        amount: u128,
+    },
+    /// Switch the account to `--key`, signing with its backup key.
+    RecoverWithBackupKey {
+        account: String,
+        /// Hex-encoded secret key of the registered backup key.
+        #[arg(long, env = "WALLET_CLI_BACKUP_KEY", hide_env_values = true)]
+        backup_key: String,
+    },
+    /// Save session keys and a backup key to a password-encrypted keystore file.
+    ExportKeystore {
+        account: String,
+        output: PathBuf,
+        /// Hex-encoded secret keys of registered session keys.
+        #[arg(
+            long,
+            value_delimiter = ',',
+            env = "WALLET_CLI_SESSION_KEYS",
+            hide_env_values = true
+        )]
+        session_keys: Vec<String>,
+        /// Hex-encoded secret key of the registered backup key.
+        #[arg(long, env = "WALLET_CLI_BACKUP_KEY", hide_env_values = true)]
+        backup_key: Option<String>,
+        #[arg(long, env = "WALLET_CLI_KEYSTORE_PASSWORD", hide_env_values = true)]
+        password: String,
+    },
+    /// Decrypt a keystore file and print its keys, secrets included, for use with
+    /// `--session-key` and `--backup-key` on this device.
+    ImportKeystore {
+        path: PathBuf,
+        #[arg(long, env = "WALLET_CLI_KEYSTORE_PASSWORD", hide_env_values = true)]
+        password: String,
+    },
+    /// Print the account information known to the wallet indexer.
+    Account { account: String },
+    /// Save the wallet state held by the server's indexer.
+    Dump { output: PathBuf },
+    /// Check that a wallet dump matches the state committed on-chain.
+    VerifyDump { path: PathBuf },
+    /// Summarize a wallet dump or journal snapshot, or print one of its accounts, without
+    /// loading the whole state.
+    InspectState {
+        path: PathBuf,
+        #[arg(long)]
+        account: Option<String>,
+    },
+}
+
+fn now_ms() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or_default()
+}
+
+fn parse_secret_key(hex_key: &str) -> Result<SecretKey> {
+    let bytes = hex::decode(hex_key.trim_start_matches("0x")).context("key must be hex")?;
+    SecretKey::from_byte_array(
+        bytes
+            .try_into()
+            .map_err(|_| anyhow::anyhow!("key must be 32 bytes"))?,
+    )
+    .context("invalid secp256k1 key")
+}
+
+/// Signs `data` the way the native secp256k1 verifier expects it.
+fn secp256k1_blob(identity: &Identity, data: &str, key: &SecretKey) -> Result<Blob> {
+    let secp = Secp256k1::new();
+    let digest: [u8; 32] = Sha256::digest(data.as_bytes()).into();
+    let signature = secp.sign_ecdsa(Message::from_digest(digest), key);
+    let public_key = PublicKey::from_secret_key(&secp, key);
+    Ok(Secp256k1Blob::new(
+        identity.clone(),
+        data.as_bytes(),
+        &public_key.to_string(),
+        &signature.to_string(),
+    )?
+    .as_blob())
+}
+
+/// Address of a HyliApp account: the first 20 bytes of the compressed public key.
+fn hyliapp_address(key: &SecretKey) -> String {
+    let public_key = PublicKey::from_secret_key(&Secp256k1::new(), key);
+    hex::encode(&public_key.serialize()[..20])
+}
+
+struct Cli {
+    node: NodeApiHttpClient,
+    http: reqwest::Client,
+    server_url: String,
+    wallet_cn: ContractName,
+    key: Option<SecretKey>,
+}
+
+impl Cli {
+    fn identity(&self, account: &str) -> Identity {
+        Identity::new(format!("{account}@{}", self.wallet_cn.0))
+    }
+
+    fn key(&self) -> Result<&SecretKey> {
+        self.key
+            .as_ref()
+            .context("this command needs --key or WALLET_CLI_KEY")
+    }
+
+    /// Wallet blob at index 0 followed by the HyliApp signature the contract reads at index 1.
+    fn authenticated_blobs(
+        &self,
+        account: &str,
+        nonce: u128,
+        action: WalletAction,
+    ) -> Result<Vec<Blob>> {
+        let identity = self.identity(account);
+        Ok(vec![
+            action.as_blob(self.wallet_cn.clone()),
+            secp256k1_blob(
+                &identity,
+                &format!("{identity}:{nonce}:hyliapp"),
+                self.key()?,
+            )?,
+        ])
+    }
+
+    async fn send(&self, account: &str, blobs: Vec<Blob>) -> Result<()> {
+        let tx = BlobTransaction::new(self.identity(account), blobs);
+        let tx_hash = self.node.send_tx_blob(tx).await?;
+        println!("{tx_hash}");
+        Ok(())
+    }
+
+    async fn register(
+        &self,
+        account: &str,
+        invite_code: &str,
+        backup_key: Option<String>,
+    ) -> Result<()> {
+        let nonce = now_ms();
+        let invite: Blob = self
+            .http
+            .post(format!("{}/api/consume_invite", self.server_url))
+            .json(&json!({ "code": invite_code, "wallet": account }))
+            .send()
+            .await?
+            .error_for_status()
+            .context("consuming invite code")?
+            .json()
+            .await?;
+
+        let mut blobs = self.authenticated_blobs(
+            account,
+            nonce,
+            WalletAction::RegisterIdentity {
+                account: account.to_string(),
+                nonce,
+                salt: String::new(),
+                auth_method: AuthMethod::HyliApp {
+                    address: hyliapp_address(self.key()?),
+                },
+                invite_code: invite_code.to_string(),
+            },
+        )?;
+        if let Some(public_key) = backup_key {
+            // Authenticated by the same signature, the RegisterIdentity blob proving the nonce.
+            blobs.push(
+                WalletAction::RegisterBackupKey {
+                    account: account.to_string(),
+                    public_key,
+                    nonce,
+                }
+                .as_blob(self.wallet_cn.clone()),
+            );
+        }
+        blobs.push(invite);
+        self.send(account, blobs).await
+    }
+
+    async fn recover_with_backup_key(&self, account: &str, backup_key: &SecretKey) -> Result<()> {
+        let nonce = now_ms();
+        let auth_method = AuthMethod::HyliApp {
+            address: hyliapp_address(self.key()?),
+        };
+        let data = backup_key_recovery_data(account, &auth_method, nonce);
+        let action = WalletAction::RecoverWithBackupKey {
+            account: account.to_string(),
+            auth_method,
+            nonce,
+        };
+        let blobs = vec![
+            action.as_blob(self.wallet_cn.clone()),
+            secp256k1_blob(&self.identity(account), &data, backup_key)?,
+        ];
+        self.send(account, blobs).await
+    }
+
+    /// Has the server check the session key and assemble the transfer, then submits it.
+    async fn transfer(
+        &self,
+        account: &str,
+        session_key: &SecretKey,
+        token: &str,
+        to: &str,
+        amount: u128,
+    ) -> Result<()> {
+        let secp = Secp256k1::new();
+        let nonce = now_ms();
+        let digest: [u8; 32] = Sha256::digest(nonce.to_string().as_bytes()).into();
+        let signature = secp.sign_ecdsa(Message::from_digest(digest), session_key);
+
+        let tx: BlobTransaction = self
+            .http
+            .post(format!(
+                "{}/v1/indexer/contract/{}/session_key_transfer",
+                self.server_url, self.wallet_cn
+            ))
+            .json(&json!({
+                "account": account,
+                "public_key": PublicKey::from_secret_key(&secp, session_key).to_string(),
+                "signature": signature.to_string(),
+                "nonce": nonce,
+                "token": token,
+                "recipient": to,
+                "amount": amount,
+            }))
+            .send()
+            .await?
+            .error_for_status()
+            .context("preparing session key transfer")?
+            .json()
+            .await?;
+        let tx_hash = self.node.send_tx_blob(tx).await?;
+        println!("{tx_hash}");
+        Ok(())
+    }
+
+    /// Encrypts the given keys, with the expiration and whitelist the indexer knows for each
+    /// session key.
+    async fn export_keystore(
+        &self,
+        account: &str,
+        output: &PathBuf,
+        session_keys: &[SecretKey],
+        backup_key: Option<SecretKey>,
+        password: &str,
+    ) -> Result<()> {
+        let info = self.fetch_account(account).await?;
+        let registered = info["session_keys"].as_array().cloned().unwrap_or_default();
+
+        let secp = Secp256k1::new();
+        let session_keys = session_keys
+            .iter()
+            .map(|secret_key| {
+                let public_key = PublicKey::from_secret_key(&secp, secret_key).to_string();
+                let registered = registered
+                    .iter()
+                    .find(|sk| sk["key"].as_str() == Some(public_key.as_str()))
+                    .with_context(|| format!("{public_key} is not a session key of {account}"))?;
+                Ok(KeystoreSessionKey {
+                    secret_key: secret_key.secret_bytes(),
+                    expiration_date: registered["expiration_date"]
+                        .as_u64()
+                        .context("session key without expiration")?
+                        .into(),
+                    whitelist: serde_json::from_value(registered["whitelist"].clone())
+                        .context("decoding session key whitelist")?,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let contents = KeystoreContents {
+            session_keys,
+            backup_key: backup_key.map(|k| k.secret_bytes()),
+        };
+        let keystore = Keystore::encrypt(account, &contents, password, KdfParams::default())?;
+        tokio::fs::write(output, serde_json::to_vec_pretty(&keystore)?)
+            .await
+            .with_context(|| format!("writing {}", output.display()))?;
+        Ok(())
+    }
+
+    async fn import_keystore(&self, path: &PathBuf, password: &str) -> Result<()> {
+        let keystore: Keystore = serde_json::from_slice(
+            &tokio::fs::read(path)
+                .await
+                .with_context(|| format!("reading {}", path.display()))?,
+        )
+        .context("parsing keystore")?;
+        let contents = keystore.decrypt(password)?;
+
+        let secp = Secp256k1::new();
+        let public_key = |secret: &[u8; 32]| -> Result<String> {
+            Ok(
+                PublicKey::from_secret_key(&secp, &SecretKey::from_byte_array(*secret)?)
+                    .to_string(),
+            )
+        };
+        let session_keys = contents
+            .session_keys
+            .iter()
+            .map(|sk| {
+                Ok(json!({
+                    "public_key": public_key(&sk.secret_key)?,
+                    "secret_key": hex::encode(sk.secret_key),
+                    "expiration_date": sk.expiration_date,
+                    "whitelist": sk.whitelist,
+                }))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let backup_key = contents
+            .backup_key
+            .map(|secret| {
+                Ok::<_, anyhow::Error>(json!({
+                    "public_key": public_key(&secret)?,
+                    "secret_key": hex::encode(secret),
+                }))
+            })
+            .transpose()?;
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&json!({
+                "account": keystore.account,
+                "session_keys": session_keys,
+                "backup_key": backup_key,
+            }))?
+        );
+        Ok(())
+    }
+
+    async fn fetch_account(&self, account: &str) -> Result<serde_json::Value> {
+        self.http
+            .get(format!(
+                "{}/v1/indexer/contract/{}/account/{account}",
+                self.server_url, self.wallet_cn
+            ))
+            .send()
+            .await?
+            .error_for_status()
+            .context("fetching account")?
+            .json()
+            .await
+            .map_err(Into::into)
+    }
+
+    async fn account(&self, account: &str) -> Result<()> {
+        let info = self.fetch_account(account).await?;
+        println!("{}", serde_json::to_string_pretty(&info)?);
+        Ok(())
+    }
+
+    async fn dump(&self, output: &PathBuf) -> Result<()> {
+        let dump = self
+            .http
+            .get(format!(
+                "{}/v1/indexer/contract/{}/dump",
+                self.server_url, self.wallet_cn
+            ))
+            .send()
+            .await?
+            .error_for_status()
+            .context("fetching wallet dump")?
+            .bytes()
+            .await?;
+        // Refuse to save a dump that is already out of sync with the chain.
+        self.verify_dump(&dump).await?;
+        tokio::fs::write(output, &dump)
+            .await
+            .with_context(|| format!("writing {}", output.display()))?;
+        Ok(())
+    }
+
+    async fn verify_dump(&self, dump: &[u8]) -> Result<Wallet> {
+        let contract = self
+            .node
+            .get_contract(self.wallet_cn.clone())
+            .await
+            .context("fetching on-chain wallet state")?;
+        let wallet = Wallet::restore(dump, &contract.state)?;
+        println!(
+            "wallet dump matches on-chain state {}",
+            hex::encode(&contract.state.0)
+        );
+        Ok(wallet)
+    }
+}
+
+fn inspect_state(path: &Path, account: Option<&String>) -> Result<()> {
+    let state = MappedWallet::open(path)?;
+    match account {
+        Some(account) => {
+            let info = state
+                .get(account)?
+                .with_context(|| format!("account {account} not found"))?;
+            println!("{}", serde_json::to_string_pretty(&info)?);
+        }
+        None => println!(
+            "{}",
+            serde_json::to_string_pretty(&json!({
+                "accounts": state.len(),
+                "invite_code_public_key": hex::encode(state.invite_code_public_key()),
+                "time_policy": state.time_policy()?,
+            }))?
+        ),
+    }
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse();
+
+    let cli = Cli {
+        node: NodeApiHttpClient::new(args.node_url).context("build node client")?,
+        http: reqwest::Client::new(),
+        server_url: args.server_url.trim_end_matches('/').to_string(),
+        wallet_cn: args.wallet_cn.into(),
+        key: args.key.as_deref().map(parse_secret_key).transpose()?,
+    };
+
+    match args.command {
+        Command::Register {
+            account,
+            invite_code,
+            backup_key,
+        } => cli.register(&account, &invite_code, backup_key).await,
+        Command::Verify { account } => {
+            let nonce = now_ms();
+            let blobs = cli.authenticated_blobs(
+                &account,
+                nonce,
+                WalletAction::VerifyIdentity {
+                    account: account.clone(),
+                    nonce,
+                },
+            )?;
+            cli.send(&account, blobs).await
+        }
+        Command::AddSessionKey {
+            account,
+            session_key,
+            expiration,
+            whitelist,
+            max_uses,
+            max_value_per_tx,
+        } => {
+            let nonce = now_ms();
+            let whitelist = whitelist.map(|w| w.into_iter().map(ContractName).collect());
+            let action = if max_uses.is_none() && max_value_per_tx.is_none() {
+                WalletAction::AddSessionKey {
+                    account: account.clone(),
+                    key: session_key,
+                    expiration_date: expiration,
+                    whitelist,
+                    lane_id: None,
+                    nonce,
+                }
+            } else {
+                WalletAction::AddLimitedSessionKey {
+                    account: account.clone(),
+                    key: session_key,
+                    expiration_date: expiration,
+                    whitelist,
+                    lane_id: None,
+                    max_uses,
+                    max_value_per_tx,
+                    nonce,
+                }
+            };
+            let blobs = cli.authenticated_blobs(&account, nonce, action)?;
+            cli.send(&account, blobs).await
+        }
+        Command::RemoveSessionKey {
+            account,
+            session_key,
+        } => {
+            let nonce = now_ms();
+            let blobs = cli.authenticated_blobs(
+                &account,
+                nonce,
+                WalletAction::RemoveSessionKey {
+                    account: account.clone(),
+                    key: session_key,
+                    nonce,
+                },
+            )?;
+            cli.send(&account, blobs).await
+        }
+        Command::RenewSessionKey {
+            account,
+            session_key,
+            expiration,
+        } => {
+            let nonce = now_ms();
+            let blobs = cli.authenticated_blobs(
+                &account,
+                nonce,
+                WalletAction::RenewSessionKey {
+                    account: account.clone(),
+                    key: session_key,
+                    new_expiration: expiration,
+                    nonce,
+                },
+            )?;
+            cli.send(&account, blobs).await
+        }
+        Command::Freeze { account } => {
+            let nonce = now_ms();
+            let blobs = cli.authenticated_blobs(
+                &account,
+                nonce,
+                WalletAction::FreezeAccount {
+                    account: account.clone(),
+                    nonce,
+                },
+            )?;
+            cli.send(&account, blobs).await
+        }
+        Command::Unfreeze { account } => {
+            let nonce = now_ms();
+            let blobs = cli.authenticated_blobs(
+                &account,
+                nonce,
+                WalletAction::UnfreezeAccount {
+                    account: account.clone(),
+                    nonce,
+                },
+            )?;
+            cli.send(&account, blobs).await
+        }
+        Command::Transfer {
+            account,
+            session_key,
+            token,
+            to,
+            amount,
+        } => {
+            let session_key = parse_secret_key(&session_key)?;
+            cli.transfer(&account, &session_key, &token, &to, amount)
+                .await
+        }
+        Command::RecoverWithBackupKey {
+            account,
+            backup_key,
+        } => {
+            let backup_key = parse_secret_key(&backup_key)?;
+            cli.recover_with_backup_key(&account, &backup_key).await
+        }
+        Command::ExportKeystore {
+            account,
+            output,
+            session_keys,
+            backup_key,
+            password,
+        } => {
+            let session_keys = session_keys
+                .iter()
+                .map(|k| parse_secret_key(k))
+                .collect::<Result<Vec<_>>>()?;
+            let backup_key = backup_key.as_deref().map(parse_secret_key).transpose()?;
+            cli.export_keystore(&account, &output, &session_keys, backup_key, &password)
+                .await
+        }
+        Command::ImportKeystore { path, password } => cli.import_keystore(&path, &password).await,
+        Command::Account { account } => cli.account(&account).await,
+        Command::Dump { output } => cli.dump(&output).await,
+        Command::VerifyDump { path } => {
+            let dump = tokio::fs::read(&path)
+                .await
+                .with_context(|| format!("reading {}", path.display()))?;
+            cli.verify_dump(&dump).await.map(|_| ())
+        }
+        Command::InspectState { path, account } => inspect_state(&path, account.as_ref()),
+    }
+}
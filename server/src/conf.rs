@@ -1,7 +1,9 @@
 use config::{Config, Environment, File};
 use hyli_modules::modules::websocket::WebSocketConfig;
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::{collections::HashMap, path::PathBuf};
+
+use crate::secrets::SecretsConf;
 
 #[derive(Serialize, Deserialize, Clone, Debug, Default)]
 pub struct Conf {
@@ -17,6 +19,8 @@ pub struct Conf {
     pub indexer_database_url: String,
 
     pub db_url: String,
+    /// Connection settings for `db_url`
+    pub database: DatabaseConf,
 
     pub rest_server_port: u16,
     pub rest_server_max_body_size: usize,
@@ -27,17 +31,512 @@ pub struct Conf {
     pub wallet_auto_prover: bool,
     pub wallet_max_txs_per_proof: usize,
     pub wallet_tx_working_window_size: usize,
+    /// Proofs a wallet batch is split into, proven concurrently. 1 proves each batch at once.
+    pub wallet_parallel_proofs: usize,
+
+    /// Independent wallet deployments hosted next to the main one
+    pub tenants: Vec<TenantConf>,
+
+    /// SMT token contracts indexed by the server, and proven when `smt_auto_provers` is set.
+    pub tokens: Vec<TokenConf>,
+    /// Memory bounds of the token histories
+    pub token_history: TokenHistoryConf,
 
     pub smt_auto_provers: bool,
     pub smt_max_txs_per_proof: usize,
     pub smt_tx_working_window_size: usize,
 
     pub auto_prover_listener_poll_interval_secs: u64,
+    /// Replay already settled transactions from the indexer database when the contract listener
+    /// starts. Disable to resume from the state persisted in `data_directory` only.
+    pub listener_replay_settled_from_start: bool,
     pub auto_prover_idle_flush_interval_secs: u64,
     pub auto_prover_tx_buffer_size: usize,
+    /// Reuse proofs stored under `<data_directory>/proof_cache` instead of re-proving identical batches.
+    pub auto_prover_proof_cache: bool,
+    /// Maximum number of proofs generated concurrently across autoprovers, wallet proofs first.
+    /// 0 disables scheduling.
+    pub auto_prover_max_concurrent_proofs: usize,
+
+    /// Hardware the autoprovers are expected to prove on, checked against the build at startup.
+    pub auto_prover_accelerator: ProverAccelerator,
+    /// Contracts proven on Bonsai rather than locally
+    pub remote_proving: RemoteProvingConf,
+    /// Retries of the proofs the autoprovers submit to the node
+    pub proof_submission: ProofSubmissionConf,
 
     /// Websocket configuration
     pub websocket: WebSocketConfig,
+
+    /// Remote signing configuration
+    pub signing: SigningConf,
+
+    /// Session key challenge gating the account topics of the WebSocket
+    pub ws_auth: WsAuthConf,
+
+    /// WebSocket endpoint on the REST server with negotiated frame encoding
+    pub ws_gateway: WsGatewayConf,
+
+    /// Network status published on the `system` WebSocket topic
+    pub system_status: SystemStatusConf,
+
+    /// Hash-chained audit log of sensitive operations
+    pub audit: AuditConf,
+
+    /// Periodic comparison of the indexed wallet state with the on-chain commitment
+    pub invariants: InvariantsConf,
+
+    /// Incremental persistence of the indexed wallet state
+    pub state_journal: StateJournalConf,
+
+    /// Scheduled housekeeping: session key pruning, invite expiry and proof retries
+    pub maintenance: MaintenanceConf,
+
+    /// Reporting of failed authentications, for the contract's account lockout
+    pub lockout: LockoutConf,
+
+    /// Guided account recovery sessions
+    pub recovery: RecoveryConf,
+
+    /// Email, webhook and push notifications of account activity
+    pub notifications: NotificationsConf,
+
+    /// Per-transaction lifecycle timelines, from sequencing to settlement
+    pub settlement: SettlementConf,
+
+    /// Webhook and PagerDuty alerts on proving failures and settlement stalls
+    pub alerts: AlertsConf,
+
+    /// Aggregated counters for the operator dashboard
+    pub stats: StatsConf,
+
+    /// OpenID providers whose keys are discovered for JWT wallet actions
+    pub oidc: OidcConf,
+
+    /// Passkey registration and authentication ceremonies
+    pub webauthn: WebAuthnConf,
+
+    /// TLS termination for the REST and WebSocket servers
+    pub tls: TlsConf,
+
+    /// gRPC API for programmatic integrators
+    pub grpc: GrpcConf,
+
+    /// Security headers, body limits and timeouts of the public REST API
+    pub http: HttpConf,
+
+    /// Flags rolling out new endpoints and event formats to a share of the accounts
+    pub feature_flags: FeatureFlagsConf,
+
+    /// Periodic snapshots of `data_directory` to S3-compatible storage
+    pub backup: BackupConf,
+
+    /// Admin endpoints purging the off-chain personal data of an account
+    pub erasure: ErasureConf,
+
+    /// Where the invite code key and the hyli password are read from
+    pub secrets: SecretsConf,
+
+    /// Trace export to an OpenTelemetry collector
+    pub otlp: OtlpConf,
+}
+
+/// First segments of the public routes, which tenant prefixes mustn't shadow.
+const RESERVED_TENANT_NAMES: &[&str] = &[
+    "allowances",
+    "api",
+    "approve",
+    "history",
+    "notifications",
+    "oidc",
+    "recovery",
+    "signing",
+    "v1",
+    "webauthn",
+    "ws",
+];
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct TenantConf {
+    /// Name the tenant's routes are served under, as `/{name}`. Its indexed state and proofs
+    /// are kept under `<data_directory>/tenants/{name}`.
+    pub name: String,
+    /// Wallet contract of the deployment.
+    pub wallet_cn: String,
+    /// Database of the tenant's invite codes and signing requests, distinct from `db_url`.
+    pub db_url: String,
+    /// Hand out invites without checking codes, as `--mock-invites` does for the main wallet.
+    #[serde(default)]
+    pub mock_invites: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct DatabaseConf {
+    /// Maximum number of connections per pool.
+    pub max_connections: u32,
+    /// How long to wait for a free connection before failing.
+    pub acquire_timeout_secs: u64,
+    /// Server-side statement timeout, 0 to disable.
+    pub statement_timeout_ms: u64,
+    /// Optional replica used for read-only queries such as history.
+    pub read_replica_url: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct OtlpConf {
+    pub enabled: bool,
+    /// OTLP/HTTP traces endpoint, e.g. http://localhost:4318/v1/traces
+    pub endpoint: String,
+    /// Extra headers sent with each export, e.g. for collector authentication.
+    pub headers: HashMap<String, String>,
+    /// Fraction of root traces that are sampled, between 0 and 1.
+    pub sampling_ratio: f64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct GrpcConf {
+    pub enabled: bool,
+    pub port: u16,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct TlsConf {
+    pub enabled: bool,
+    /// PEM file holding the certificate chain.
+    pub cert_path: PathBuf,
+    /// PEM file holding the private key.
+    pub key_path: PathBuf,
+    /// How often the certificate files are checked for rotation.
+    pub reload_interval_secs: u64,
+    /// TLS port forwarding to `rest_server_port`.
+    pub rest_port: u16,
+    /// TLS port forwarding to the websocket port.
+    pub websocket_port: u16,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct BackupConf {
+    /// Upload a snapshot of `data_directory` every `interval_secs`. Credentials are read from
+    /// the `AWS_ACCESS_KEY_ID` and `AWS_SECRET_ACCESS_KEY` environment variables.
+    pub enabled: bool,
+    pub bucket: String,
+    pub region: String,
+    /// Endpoint of an S3-compatible service, e.g. MinIO. AWS when unset.
+    pub endpoint: Option<String>,
+    /// Key prefix the backups are stored under.
+    pub prefix: String,
+    pub interval_secs: u64,
+    /// Top-level entries of `data_directory` left out of the backups, e.g. caches.
+    pub exclude: Vec<String>,
+    /// Number of most recent backups kept.
+    pub keep_last: usize,
+    /// Backups older than this are deleted, whatever `keep_last`, 0 to disable. The most recent
+    /// backup is always kept.
+    pub max_age_secs: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct TokenConf {
+    /// Contract name of the token, which must run the SMT token program.
+    pub name: String,
+    /// Whether this token gets an autoprover when `smt_auto_provers` is enabled.
+    pub auto_prove: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ProverAccelerator {
+    #[default]
+    Cpu,
+    /// Requires building with the `cuda` feature.
+    Cuda,
+    /// Used automatically by RISC Zero on Apple silicon.
+    Metal,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct RemoteProvingConf {
+    /// Contracts whose proofs are requested from Bonsai, with the `BONSAI_API_URL` and
+    /// `BONSAI_API_KEY` environment variables. A proof Bonsai fails to generate is proven
+    /// locally instead.
+    pub contracts: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct ProofSubmissionConf {
+    /// Retries of a proof the node refused, before it's kept as a dead letter to resubmit
+    /// through the admin API.
+    pub max_retries: u32,
+    /// Wait before the first retry, doubled on every retry.
+    pub initial_backoff_ms: u64,
+    pub max_backoff_ms: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct SigningConf {
+    /// Whether the remote signing module and its routes are enabled.
+    pub enabled: bool,
+    /// Number of seconds a signing request stays open before it expires.
+    pub request_timeout_secs: u64,
+    /// How often expired signing requests are swept.
+    pub cleanup_interval_secs: u64,
+    /// Maximum number of concurrent pending requests an origin may open for one account.
+    pub max_pending_per_origin: usize,
+    /// Public base URL of this server. When set, created requests come with a `hyli://sign`
+    /// deep link whose callback is this server's `/signing/respond`.
+    pub public_url: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct WsAuthConf {
+    /// Only publish account events on topics granted through the challenge, not on the account
+    /// name.
+    pub required: bool,
+    /// Seconds a client has to answer a challenge.
+    pub challenge_ttl_secs: u64,
+    /// Seconds a granted topic keeps receiving events.
+    pub grant_ttl_secs: u64,
+    /// Recent events kept per account for clients resuming on a granted topic, 0 to disable.
+    pub replay_buffer_size: usize,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct WsGatewayConf {
+    /// Serve `/api/ws`, relaying WebSocket topics as JSON or MessagePack frames.
+    pub enabled: bool,
+    pub max_topics_per_connection: usize,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct SystemStatusConf {
+    /// How often block height, DA lag and proving backlog are published, 0 to disable.
+    pub interval_secs: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct AuditConf {
+    /// Record admin operations, invite issuance, signing approvals and proofs in `db_url`.
+    pub enabled: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct ErasureConf {
+    /// Serve the erasure endpoints on the admin server. Erasures are recorded in the audit log,
+    /// which must be enabled too.
+    pub enabled: bool,
+    /// Days completed signing requests are kept, after which the retention endpoint purges
+    /// them. 0 keeps them until their account's data is erased.
+    pub signing_history_retention_days: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct HttpConf {
+    /// Add `nosniff`, framing, referrer and content security policy headers to responses.
+    pub security_headers: bool,
+    /// `Strict-Transport-Security` max-age, 0 to leave the header out. Only set it when clients
+    /// reach the server over HTTPS.
+    pub hsts_max_age_secs: u64,
+    /// Requests still running after this many seconds are answered with 408, 0 to disable.
+    pub request_timeout_secs: u64,
+    /// Maximum body size by route prefix, the longest matching prefix applies. Other routes are
+    /// only bounded by `rest_server_max_body_size`.
+    pub body_limits: HashMap<String, usize>,
+    /// Requests answered after this many milliseconds are logged as slow, 0 to disable.
+    pub slow_request_ms: u64,
+    /// Slow request threshold by route prefix, the longest matching prefix applies.
+    pub slow_request_ms_by_route: HashMap<String, u64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct FeatureFlagsConf {
+    /// Store flag overrides in `db_url` and serve the `/feature_flags` admin routes. Without
+    /// it, only `flags` apply.
+    pub enabled: bool,
+    /// How often overrides are reloaded, for servers sharing the database.
+    pub refresh_interval_secs: u64,
+    /// Rollout of each flag, replaced by its override when there is one. Unlisted flags are off.
+    pub flags: HashMap<String, FeatureFlagRule>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct FeatureFlagRule {
+    /// Off for every account when unset.
+    pub enabled: bool,
+    /// Share of the accounts the flag is on for, from 0 to 100.
+    #[serde(default)]
+    pub rollout_percent: u8,
+    /// Accounts the flag is on for whatever the rollout, e.g. to try it out first.
+    #[serde(default)]
+    pub accounts: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct MaintenanceConf {
+    pub enabled: bool,
+    /// How often expired session keys are pruned on-chain, 0 to disable.
+    pub prune_session_keys_interval_secs: u64,
+    /// Maximum number of pruning transactions sent per run.
+    pub prune_session_keys_batch: usize,
+    /// How often unused invite codes are expired, 0 to disable.
+    pub expire_invites_interval_secs: u64,
+    /// Age after which an unused invite code is deleted.
+    pub invite_ttl_secs: u64,
+    /// How often proofs held after a failure are retried, 0 to disable.
+    pub retry_proofs_interval_secs: u64,
+    /// Retries of a failed proof before its failure is left to the autoprover.
+    pub max_proof_retries: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct LockoutConf {
+    /// Submit a signed report to the wallet contract for every settled transaction that failed
+    /// to authenticate its account.
+    pub report_failed_auth: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct RecoveryConf {
+    /// Serve the `/recovery` routes walking users through the recovery of their account.
+    pub enabled: bool,
+    /// How often timelocked email recoveries are checked for finalization.
+    pub poll_interval_secs: u64,
+    /// Seconds a session waits for its authentication blob before it fails.
+    pub session_ttl_secs: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct NotificationsConf {
+    /// Serve the `/notifications` routes and notify accounts of their activity.
+    pub enabled: bool,
+    /// Incoming transfers from this amount on are notified, unless the account set its own
+    /// threshold.
+    pub large_transfer_threshold: u64,
+    /// Endpoint emails are posted to as `{to, subject, text}`, no emails are sent when unset.
+    pub email_relay_url: Option<String>,
+    /// Endpoint push notifications are posted to as `{token, title, body, data}`, no pushes are
+    /// sent when unset.
+    pub push_gateway_url: Option<String>,
+    /// Timeout of each webhook, email or push request.
+    pub dispatch_timeout_secs: u64,
+    /// How far from the server time a preferences signature may be.
+    pub signature_ttl_secs: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct SettlementConf {
+    /// Track the lifecycle of the wallet transactions and serve `/api/tx/{hash}/timeline`.
+    pub enabled: bool,
+    /// How often the indexer database is polled for sequenced transactions and submitted proofs.
+    pub poll_interval_secs: u64,
+    /// Timelines are deleted this many days after their first event, 0 to keep them.
+    pub retention_days: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct AlertsConf {
+    /// Watch the provers, settlements and DA lag, and fire alerts when they go wrong.
+    pub enabled: bool,
+    /// How often the alert conditions are evaluated.
+    pub check_interval_secs: u64,
+    /// Period failures and timeouts are counted over.
+    pub window_secs: u64,
+    /// Failed proof attempts within `window_secs` firing an alert, 0 to disable.
+    pub proof_failures_threshold: usize,
+    /// Wallet transactions timing out within `window_secs` firing an alert, 0 to disable.
+    pub tx_timeouts_threshold: usize,
+    /// Blocks the indexer may trail the node by before an alert fires, 0 to disable.
+    pub da_lag_threshold: u64,
+    /// Time proofs may stay queued without any proof being generated before an alert fires,
+    /// 0 to disable.
+    pub prover_stall_secs: u64,
+    /// Endpoints alerts are posted to as JSON.
+    pub webhook_urls: Vec<String>,
+    /// Integration key of a PagerDuty service alerts are sent to through the Events API v2.
+    pub pagerduty_routing_key: Option<String>,
+    /// Timeout of each webhook or PagerDuty request.
+    pub dispatch_timeout_secs: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct StatsConf {
+    /// Serve `/api/stats`, combining accounts, transaction volume, proving backlog, invite usage
+    /// and WebSocket connections.
+    pub enabled: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct OidcConf {
+    /// Serve the `/oidc` routes with the signing keys of `providers`.
+    pub enabled: bool,
+    /// How often each provider's discovery document and JWKS are fetched again.
+    pub refresh_interval_secs: u64,
+    /// Minimum delay between two refreshes of a provider triggered by unknown key ids.
+    pub min_refresh_interval_secs: u64,
+    /// How long keys the provider stopped publishing are still served.
+    pub retired_key_grace_secs: u64,
+    /// Keys remembered per provider, retired ones included, under
+    /// `<data_directory>/oidc_keys.json`.
+    pub key_history_len: usize,
+    pub providers: Vec<OidcProviderConf>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct OidcProviderConf {
+    /// Name the provider is exposed under, e.g. `google`.
+    pub name: String,
+    /// Issuer URL, whose `.well-known/openid-configuration` is fetched.
+    pub issuer: String,
+    /// OAuth client ids of the wallet, the audiences its tokens are accepted for.
+    #[serde(default)]
+    pub client_ids: Vec<String>,
+    /// Claim identifying the user of a token, `email` when unset. GitHub OIDC tokens have no
+    /// email, their `sub` identifies the repository and workflow instead.
+    #[serde(default)]
+    pub identity_claim: Option<String>,
+    /// Signing algorithms the tokens may use, among `RS256` and `ES256`. Only `RS256` when
+    /// empty.
+    #[serde(default)]
+    pub algorithms: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct WebAuthnConf {
+    /// Serve the `/webauthn` routes and store passkeys in `db_url`.
+    pub enabled: bool,
+    /// Relying party id, the domain of the wallet frontend passkeys are scoped to.
+    pub rp_id: String,
+    /// Name shown by authenticators.
+    pub rp_name: String,
+    /// Origins ceremonies may come from, e.g. `https://wallet.hyli.org`.
+    pub origins: Vec<String>,
+    /// How long a challenge can be answered.
+    pub challenge_ttl_secs: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct TokenHistoryConf {
+    /// Spill older history entries under `<data_directory>/history` instead of keeping them
+    /// all in memory.
+    pub bounded: bool,
+    /// Most recent entries of an account kept in memory.
+    pub hot_entries_per_account: usize,
+    /// Entries kept in memory per token, least recently active accounts are spilled first.
+    pub max_entries: usize,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct StateJournalConf {
+    /// Log changes under `<data_directory>/journal` and restore the wallet indexers from it.
+    pub enabled: bool,
+    /// Number of logged changes after which the full state is snapshotted and the log reset.
+    pub compact_after: usize,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct InvariantsConf {
+    pub enabled: bool,
+    /// How often each wallet's indexed commitment is compared with the node's.
+    pub check_interval_secs: u64,
 }
 
 impl Conf {
@@ -60,4 +559,344 @@ impl Conf {
             .try_deserialize()?;
         Ok(conf)
     }
+
+    /// Checks the configuration before any module is built, reporting every problem at once.
+    ///
+    /// `ports` lists the ports this binary will bind, `check_db` whether `db_url` must be
+    /// reachable (invites, signing,
+    /// recovery, notifications, passkeys and the audit log need it).
+    pub async fn validate(&self, ports: &[(&str, u16)], check_db: bool) -> anyhow::Result<()> {
+        let mut errors = Vec::new();
+
+        if let Err(e) = reqwest::Url::parse(&self.node_url) {
+            errors.push(format!(
+                "node_url {:?} is not a valid URL: {e}",
+                self.node_url
+            ));
+        }
+        for (name, url) in [
+            ("db_url", &self.db_url),
+            ("indexer_database_url", &self.indexer_database_url),
+        ] {
+            if !url.starts_with("postgres://") && !url.starts_with("postgresql://") {
+                errors.push(format!("{name} {url:?} is not a postgres URL"));
+            }
+        }
+
+        if self.database.max_connections == 0 {
+            errors.push("database.max_connections must be greater than 0".into());
+        }
+        if let Some(url) = &self.database.read_replica_url {
+            if !url.starts_with("postgres://") && !url.starts_with("postgresql://") {
+                errors.push(format!(
+                    "database.read_replica_url {url:?} is not a postgres URL"
+                ));
+            }
+        }
+
+        let mut seen = std::collections::HashMap::new();
+        for (name, port) in ports {
+            if *port == 0 {
+                errors.push(format!("{name} must not be 0"));
+                continue;
+            }
+            if let Some(other) = seen.insert(*port, *name) {
+                errors.push(format!("{name} and {other} both use port {port}"));
+            } else if let Err(e) = std::net::TcpListener::bind(("0.0.0.0", *port)) {
+                errors.push(format!("{name} {port} is not available: {e}"));
+            }
+        }
+
+        let probe = self.data_directory.join(".write_check");
+        if let Err(e) = std::fs::create_dir_all(&self.data_directory)
+            .and_then(|_| std::fs::write(&probe, b""))
+            .and_then(|_| std::fs::remove_file(&probe))
+        {
+            errors.push(format!(
+                "data_directory {} is not writable: {e}",
+                self.data_directory.display()
+            ));
+        }
+
+        for (name, value) in [
+            ("wallet_max_txs_per_proof", self.wallet_max_txs_per_proof),
+            ("smt_max_txs_per_proof", self.smt_max_txs_per_proof),
+            (
+                "wallet_tx_working_window_size",
+                self.wallet_tx_working_window_size,
+            ),
+            ("wallet_parallel_proofs", self.wallet_parallel_proofs),
+            (
+                "smt_tx_working_window_size",
+                self.smt_tx_working_window_size,
+            ),
+            (
+                "auto_prover_tx_buffer_size",
+                self.auto_prover_tx_buffer_size,
+            ),
+            ("rest_server_max_body_size", self.rest_server_max_body_size),
+            (
+                "admin_server_max_body_size",
+                self.admin_server_max_body_size,
+            ),
+        ] {
+            if value == 0 {
+                errors.push(format!("{name} must be greater than 0"));
+            }
+        }
+        if self.wallet_tx_working_window_size < self.wallet_max_txs_per_proof {
+            errors.push(
+                "wallet_tx_working_window_size must be at least wallet_max_txs_per_proof".into(),
+            );
+        }
+        if self.smt_tx_working_window_size < self.smt_max_txs_per_proof {
+            errors.push("smt_tx_working_window_size must be at least smt_max_txs_per_proof".into());
+        }
+        if self.otlp.enabled {
+            if let Err(e) = reqwest::Url::parse(&self.otlp.endpoint) {
+                errors.push(format!("otlp.endpoint is not a valid URL: {e}"));
+            }
+            if !(0.0..=1.0).contains(&self.otlp.sampling_ratio) {
+                errors.push("otlp.sampling_ratio must be between 0 and 1".into());
+            }
+        }
+        if self.signing.enabled && self.signing.request_timeout_secs == 0 {
+            errors.push("signing.request_timeout_secs must be greater than 0".into());
+        }
+        if let Some(url) = &self.signing.public_url {
+            if let Err(e) = reqwest::Url::parse(url) {
+                errors.push(format!("signing.public_url is not a valid URL: {e}"));
+            }
+        }
+        for (prefix, limit) in &self.http.body_limits {
+            if !prefix.starts_with('/') {
+                errors.push(format!("http.body_limits route {prefix} must start with /"));
+            }
+            if *limit == 0 || *limit > self.rest_server_max_body_size {
+                errors.push(format!(
+                    "http.body_limits for {prefix} must be between 1 and rest_server_max_body_size"
+                ));
+            }
+        }
+        for prefix in self.http.slow_request_ms_by_route.keys() {
+            if !prefix.starts_with('/') {
+                errors.push(format!(
+                    "http.slow_request_ms_by_route route {prefix} must start with /"
+                ));
+            }
+        }
+        if self.ws_auth.challenge_ttl_secs == 0 || self.ws_auth.grant_ttl_secs == 0 {
+            errors.push(
+                "ws_auth.challenge_ttl_secs and grant_ttl_secs must be greater than 0".into(),
+            );
+        }
+        if self.maintenance.enabled {
+            if self.maintenance.prune_session_keys_interval_secs > 0
+                && self.maintenance.prune_session_keys_batch == 0
+            {
+                errors.push("maintenance.prune_session_keys_batch must be greater than 0".into());
+            }
+            if self.maintenance.expire_invites_interval_secs > 0
+                && self.maintenance.invite_ttl_secs == 0
+            {
+                errors.push("maintenance.invite_ttl_secs must be greater than 0".into());
+            }
+        }
+        if self.recovery.enabled && self.recovery.poll_interval_secs == 0 {
+            errors.push("recovery.poll_interval_secs must be greater than 0".into());
+        }
+        if self.notifications.enabled {
+            for (name, url) in [
+                (
+                    "notifications.email_relay_url",
+                    &self.notifications.email_relay_url,
+                ),
+                (
+                    "notifications.push_gateway_url",
+                    &self.notifications.push_gateway_url,
+                ),
+            ] {
+                if let Some(Err(e)) = url.as_deref().map(reqwest::Url::parse) {
+                    errors.push(format!("{name} is not a valid URL: {e}"));
+                }
+            }
+            if self.notifications.dispatch_timeout_secs == 0
+                || self.notifications.signature_ttl_secs == 0
+            {
+                errors.push(
+                    "notifications.dispatch_timeout_secs and signature_ttl_secs must be greater than 0"
+                        .into(),
+                );
+            }
+        }
+        for (name, rule) in &self.feature_flags.flags {
+            if rule.rollout_percent > 100 {
+                errors.push(format!(
+                    "feature_flags.flags.{name}.rollout_percent must be at most 100"
+                ));
+            }
+        }
+        if self.feature_flags.enabled && self.feature_flags.refresh_interval_secs == 0 {
+            errors.push("feature_flags.refresh_interval_secs must be greater than 0".into());
+        }
+        if self.alerts.enabled {
+            if self.alerts.check_interval_secs == 0
+                || self.alerts.window_secs == 0
+                || self.alerts.dispatch_timeout_secs == 0
+            {
+                errors.push(
+                    "alerts.check_interval_secs, window_secs and dispatch_timeout_secs must be greater than 0"
+                        .into(),
+                );
+            }
+            for url in &self.alerts.webhook_urls {
+                if let Err(e) = reqwest::Url::parse(url) {
+                    errors.push(format!("alerts.webhook_urls {url} is not a valid URL: {e}"));
+                }
+            }
+            if self.alerts.webhook_urls.is_empty() && self.alerts.pagerduty_routing_key.is_none() {
+                errors.push(
+                    "alerts need webhook_urls or a pagerduty_routing_key to be sent to".into(),
+                );
+            }
+        }
+        if self.oidc.enabled {
+            if self.oidc.refresh_interval_secs == 0 {
+                errors.push("oidc.refresh_interval_secs must be greater than 0".into());
+            }
+            if self.oidc.key_history_len == 0 {
+                errors.push("oidc.key_history_len must be greater than 0".into());
+            }
+            let mut names = std::collections::HashSet::new();
+            for provider in &self.oidc.providers {
+                if !names.insert(&provider.name) {
+                    errors.push(format!("oidc provider {} is listed twice", provider.name));
+                }
+                if let Err(e) = reqwest::Url::parse(&provider.issuer) {
+                    errors.push(format!(
+                        "oidc provider {} issuer is not a valid URL: {e}",
+                        provider.name
+                    ));
+                }
+                for algorithm in &provider.algorithms {
+                    if !["RS256", "ES256"].contains(&algorithm.as_str()) {
+                        errors.push(format!(
+                            "oidc provider {} algorithm {algorithm} is not supported",
+                            provider.name
+                        ));
+                    }
+                }
+            }
+        }
+        if self.webauthn.enabled {
+            if self.webauthn.rp_id.is_empty() {
+                errors.push("webauthn.rp_id must be set".into());
+            }
+            if self.webauthn.origins.is_empty() {
+                errors.push("webauthn.origins must list at least one origin".into());
+            }
+            if self.webauthn.challenge_ttl_secs == 0 {
+                errors.push("webauthn.challenge_ttl_secs must be greater than 0".into());
+            }
+        }
+        if self.invariants.enabled && self.invariants.check_interval_secs == 0 {
+            errors.push("invariants.check_interval_secs must be greater than 0".into());
+        }
+        if self.token_history.bounded && self.token_history.max_entries == 0 {
+            errors.push("token_history.max_entries must be greater than 0".into());
+        }
+        if self.state_journal.enabled && self.state_journal.compact_after == 0 {
+            errors.push("state_journal.compact_after must be greater than 0".into());
+        }
+        if self.backup.enabled {
+            if self.backup.bucket.is_empty() {
+                errors.push("backup.bucket must be set".into());
+            }
+            if let Some(Err(e)) = self.backup.endpoint.as_deref().map(reqwest::Url::parse) {
+                errors.push(format!("backup.endpoint is not a valid URL: {e}"));
+            }
+            if self.backup.interval_secs == 0 || self.backup.keep_last == 0 {
+                errors.push("backup.interval_secs and keep_last must be greater than 0".into());
+            }
+        }
+        if self.erasure.enabled && !self.audit.enabled {
+            errors.push("erasure.enabled requires audit.enabled, erasures must be audited".into());
+        }
+        if self.tls.enabled {
+            for (name, path) in [
+                ("tls.cert_path", &self.tls.cert_path),
+                ("tls.key_path", &self.tls.key_path),
+            ] {
+                if !path.is_file() {
+                    errors.push(format!("{name} {} does not exist", path.display()));
+                }
+            }
+        }
+
+        let mut tenant_names = std::collections::HashSet::new();
+        let mut tenant_cns = std::collections::HashSet::new();
+        let mut tenant_dbs = std::collections::HashSet::from([&self.db_url]);
+        for tenant in &self.tenants {
+            if tenant.name.is_empty()
+                || !tenant
+                    .name
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+            {
+                errors.push(format!(
+                    "tenant name {:?} must be made of lowercase letters, digits and dashes",
+                    tenant.name
+                ));
+            }
+            if RESERVED_TENANT_NAMES.contains(&tenant.name.as_str()) {
+                errors.push(format!("tenant name {} clashes with a route", tenant.name));
+            }
+            if !tenant_names.insert(&tenant.name) {
+                errors.push(format!("tenant {} is listed twice", tenant.name));
+            }
+            if !tenant_cns.insert(&tenant.wallet_cn) {
+                errors.push(format!(
+                    "tenants share the wallet contract {}",
+                    tenant.wallet_cn
+                ));
+            }
+            if !tenant.db_url.starts_with("postgres://")
+                && !tenant.db_url.starts_with("postgresql://")
+            {
+                errors.push(format!(
+                    "tenant {} db_url {:?} is not a postgres URL",
+                    tenant.name, tenant.db_url
+                ));
+            } else if !tenant_dbs.insert(&tenant.db_url) {
+                errors.push(format!(
+                    "tenant {} db_url is already used, tenants need their own database",
+                    tenant.name
+                ));
+            }
+        }
+
+        if check_db {
+            let databases = std::iter::once(("db_url".to_string(), &self.db_url)).chain(
+                self.tenants
+                    .iter()
+                    .map(|t| (format!("tenant {} db_url", t.name), &t.db_url)),
+            );
+            for (name, url) in databases {
+                let connection = sqlx::postgres::PgPoolOptions::new()
+                    .max_connections(1)
+                    .acquire_timeout(std::time::Duration::from_secs(5))
+                    .connect(url)
+                    .await;
+                match connection {
+                    Ok(pool) => pool.close().await,
+                    Err(e) => errors.push(format!("{name} is not reachable: {e}")),
+                }
+            }
+        }
+
+        if !errors.is_empty() {
+            anyhow::bail!("Invalid configuration:\n  - {}", errors.join("\n  - "));
+        }
+        Ok(())
+    }
 }
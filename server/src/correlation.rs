@@ -0,0 +1,154 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    future::Future,
+    sync::{LazyLock, Mutex},
+};
+
+use anyhow::Result;
+use axum::{
+    extract::Request,
+    http::HeaderValue,
+    middleware::{self, Next},
+    response::Response,
+    Router,
+};
+use client_sdk::rest_client::NodeApiClient;
+use sdk::{BlobTransaction, Hashed, TxHash};
+use serde::{Deserialize, Serialize};
+use tracing::Instrument;
+
+/// Header carrying the correlation id of a request, and of its response.
+pub const CORRELATION_HEADER: &str = "x-correlation-id";
+
+/// Longest correlation id accepted from a client.
+const MAX_LEN: usize = 64;
+
+/// Submitted transactions whose correlation id is remembered.
+const TRACKED_TXS: usize = 10_000;
+
+tokio::task_local! {
+    static CURRENT: CorrelationId;
+}
+
+static SUBMITTED: LazyLock<Mutex<SubmittedTxs>> = LazyLock::new(Default::default);
+
+/// Identifies one user action across the logs of the REST API, the bus, the provers and the
+/// node submissions.
+///
+/// A REST request gets the id of its `x-correlation-id` header, or a new one. Transactions sent
+/// while serving it keep the request's id, other transactions get one derived from their hash.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct CorrelationId(pub String);
+
+impl fmt::Display for CorrelationId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl CorrelationId {
+    pub fn generate() -> Self {
+        Self(hex::encode(rand::random::<[u8; 8]>()))
+    }
+
+    /// Id supplied by a client, unless too long or holding characters unsafe to log.
+    pub fn parse(id: &str) -> Option<Self> {
+        let valid = !id.is_empty()
+            && id.len() <= MAX_LEN
+            && id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+        valid.then(|| Self(id.to_string()))
+    }
+
+    /// Id of the request or task being served, if any.
+    pub fn current() -> Option<Self> {
+        CURRENT.try_with(Clone::clone).ok()
+    }
+
+    /// Id of the request that submitted the transaction, or one derived from its hash when it
+    /// was sent by someone else or before a restart.
+    pub fn of_tx(tx_hash: &TxHash) -> Self {
+        SUBMITTED
+            .lock()
+            .expect("submitted txs poisoned")
+            .ids
+            .get(&tx_hash.0)
+            .cloned()
+            .unwrap_or_else(|| {
+                Self(format!(
+                    "tx-{}",
+                    tx_hash.0.chars().take(16).collect::<String>()
+                ))
+            })
+    }
+
+    /// Runs `future` with this id as the current one.
+    pub async fn scope<F: Future>(self, future: F) -> F::Output {
+        CURRENT.scope(self, future).await
+    }
+}
+
+#[derive(Default)]
+struct SubmittedTxs {
+    ids: HashMap<String, CorrelationId>,
+    order: VecDeque<String>,
+}
+
+impl SubmittedTxs {
+    fn track(&mut self, tx_hash: &TxHash, id: CorrelationId) {
+        if self.ids.insert(tx_hash.0.clone(), id).is_none() {
+            self.order.push_back(tx_hash.0.clone());
+        }
+        while self.order.len() > TRACKED_TXS {
+            if let Some(oldest) = self.order.pop_front() {
+                self.ids.remove(&oldest);
+            }
+        }
+    }
+}
+
+/// Sends `tx` to the node under the current correlation id, so its proof and settlement are
+/// logged under the id of the request that sent it.
+pub async fn send_tx_blob<N>(node: &N, tx: BlobTransaction) -> Result<TxHash>
+where
+    N: NodeApiClient + ?Sized,
+{
+    let tx_hash = tx.hashed();
+    let id = CorrelationId::current().unwrap_or_else(|| CorrelationId::of_tx(&tx_hash));
+    // Tracked before sending, the proof may come back before the node answers.
+    SUBMITTED
+        .lock()
+        .expect("submitted txs poisoned")
+        .track(&tx_hash, id.clone());
+    let span = tracing::info_span!("send_tx_blob", correlation_id = %id, tx_hash = %tx_hash.0);
+    node.send_tx_blob(tx).instrument(span).await
+}
+
+/// Serves every route of `router` under a correlation id, returned in the `x-correlation-id`
+/// header of the response. Routes added afterwards are left as they are.
+pub fn with_correlation(router: Router) -> Router {
+    router.layer(middleware::from_fn(correlate))
+}
+
+async fn correlate(request: Request, next: Next) -> Response {
+    let id = request
+        .headers()
+        .get(CORRELATION_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(CorrelationId::parse)
+        .unwrap_or_else(CorrelationId::generate);
+    let span = tracing::info_span!(
+        "request",
+        correlation_id = %id,
+        method = %request.method(),
+        path = request.uri().path(),
+    );
+    let mut response = id.clone().scope(next.run(request)).instrument(span).await;
+    if let Ok(value) = HeaderValue::from_str(&id.0) {
+        response.headers_mut().insert(CORRELATION_HEADER, value);
+    }
+    response
+}
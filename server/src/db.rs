@@ -0,0 +1,61 @@
+use std::{str::FromStr, time::Duration};
+
+use anyhow::{Context, Result};
+use sqlx::{
+    postgres::{PgConnectOptions, PgPoolOptions},
+    PgPool,
+};
+
+use crate::conf::DatabaseConf;
+
+/// Postgres pools for modules that persist data: a primary for writes and an optional read
+/// replica for queries that tolerate replication lag.
+#[derive(Clone)]
+pub struct DbPools {
+    pub primary: PgPool,
+    replica: Option<PgPool>,
+}
+
+async fn connect_pool(url: &str, conf: &DatabaseConf) -> Result<PgPool> {
+    let mut options = PgConnectOptions::from_str(url).context("parsing database URL")?;
+    if conf.statement_timeout_ms > 0 {
+        options = options.options([("statement_timeout", conf.statement_timeout_ms.to_string())]);
+    }
+    PgPoolOptions::new()
+        .max_connections(conf.max_connections)
+        .acquire_timeout(Duration::from_secs(conf.acquire_timeout_secs))
+        .connect_with(options)
+        .await
+        .context("connecting to database")
+}
+
+impl DbPools {
+    pub async fn connect(url: &str, conf: &DatabaseConf) -> Result<Self> {
+        let primary = connect_pool(url, conf).await?;
+        let replica = match &conf.read_replica_url {
+            Some(replica_url) => Some(
+                connect_pool(replica_url, conf)
+                    .await
+                    .context("connecting to read replica")?,
+            ),
+            None => None,
+        };
+        Ok(Self { primary, replica })
+    }
+
+    /// Pool connecting to `url` on first use, without a replica.
+    pub fn connect_lazy(url: &str) -> Result<Self> {
+        let primary = PgPoolOptions::new()
+            .connect_lazy(url)
+            .context("parsing database URL")?;
+        Ok(Self {
+            primary,
+            replica: None,
+        })
+    }
+
+    /// Pool to use for read-only queries.
+    pub fn reader(&self) -> &PgPool {
+        self.replica.as_ref().unwrap_or(&self.primary)
+    }
+}
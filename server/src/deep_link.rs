@@ -0,0 +1,82 @@
+use anyhow::{bail, Context, Result};
+use reqwest::Url;
+use sha2::{Digest, Sha256};
+
+/// Scheme native wallet apps register to be opened on signing requests.
+pub const SCHEME: &str = "hyli";
+
+/// Version of the link format, bumped on incompatible changes.
+const VERSION: &str = "1";
+
+/// A `hyli://sign?...` link opening a native app on a signing request, an alternative to the
+/// QR code and WebSocket path for apps on the same device as the web wallet.
+///
+/// The link only carries the request id and a digest of the message. The app fetches the request
+/// with its device token, checks the message against the digest, and posts its answer to
+/// `callback`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SigningDeepLink {
+    pub request_id: String,
+    /// Where the app posts its response, the `/signing/respond` route of this server.
+    pub callback: String,
+    /// Sha256 of the message to sign.
+    pub digest: [u8; 32],
+}
+
+impl SigningDeepLink {
+    pub fn new(request_id: &str, callback: &str, message: &[u8]) -> Self {
+        Self {
+            request_id: request_id.to_string(),
+            callback: callback.to_string(),
+            digest: Sha256::digest(message).into(),
+        }
+    }
+
+    pub fn to_url(&self) -> String {
+        let mut url = Url::parse(&format!("{SCHEME}://sign")).expect("static deep link base");
+        url.query_pairs_mut()
+            .append_pair("v", VERSION)
+            .append_pair("id", &self.request_id)
+            .append_pair("callback", &self.callback)
+            .append_pair("digest", &hex::encode(self.digest));
+        url.into()
+    }
+
+    pub fn parse(link: &str) -> Result<Self> {
+        let url = Url::parse(link).context("parsing deep link")?;
+        if url.scheme() != SCHEME || url.host_str() != Some("sign") {
+            bail!("not a {SCHEME}://sign link");
+        }
+
+        let param = |name: &str| {
+            url.query_pairs()
+                .find(|(k, _)| k == name)
+                .map(|(_, v)| v.into_owned())
+                .with_context(|| format!("deep link has no {name}"))
+        };
+        let version = param("v")?;
+        if version != VERSION {
+            bail!("unsupported deep link version {version}");
+        }
+        let callback = param("callback")?;
+        let callback_url = Url::parse(&callback).context("invalid deep link callback")?;
+        if callback_url.scheme() != "https" && callback_url.host_str() != Some("localhost") {
+            bail!("deep link callback must use https");
+        }
+        let digest = hex::decode(param("digest")?)
+            .ok()
+            .and_then(|d| d.try_into().ok())
+            .context("invalid deep link digest")?;
+
+        Ok(Self {
+            request_id: param("id")?,
+            callback,
+            digest,
+        })
+    }
+
+    /// Whether `message` is the one the link was made for.
+    pub fn matches(&self, message: &[u8]) -> bool {
+        <[u8; 32]>::from(Sha256::digest(message)) == self.digest
+    }
+}
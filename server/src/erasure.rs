@@ -0,0 +1,326 @@
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use axum::{
+    extract::{Path, State},
+    routing::get,
+    Json, Router,
+};
+use client_sdk::AppError;
+use sdk::{ContractName, Identity};
+use serde::Serialize;
+use server::audit::{AuditKind, AuditRecorder};
+use server::conf::DatabaseConf;
+use server::db::DbPools;
+use wallet::AuthMethod;
+
+use crate::history;
+
+/// Personal data held off-chain for an account, or purged from it.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ErasureReport {
+    pub account: String,
+    /// Password salts kept by the wallet indexers.
+    pub salts: usize,
+    /// Salts of accounts still logging in with a password, which erasing would lock out: the
+    /// password hash the account is authenticated by is derived from it. They are never purged.
+    pub password_salts_kept: usize,
+    /// Token history entries, in memory and spilled.
+    pub history_entries: usize,
+    /// Invite codes bound to the account.
+    pub invite_bindings: u64,
+    /// Completed remote signing requests.
+    pub signing_requests: u64,
+    /// Notification contact details and settings.
+    pub notification_preferences: u64,
+}
+
+/// Completed signing requests past the retention period.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RetentionReport {
+    /// 0 when signing requests are kept until their account's data is erased.
+    pub signing_history_retention_days: u64,
+    pub expired_signing_requests: u64,
+}
+
+/// Purges the off-chain personal data of accounts, on request of their owners, and the
+/// signing history past its retention period.
+///
+/// Account commitments stay on-chain, and the counterparties of the account's transfers keep
+/// them in their own history. The salt of an account authenticated by a password is kept, as
+/// its owner couldn't log in anymore without it. The indexed states are written without the
+/// purged data by the state journal right away when it's enabled, and otherwise at the next
+/// shutdown; existing backups keep it until their retention runs out.
+pub struct Erasure {
+    db: DbPools,
+    signing_history_retention_days: u64,
+    wallet_cns: Vec<ContractName>,
+    tokens: Vec<ContractName>,
+    audit: AuditRecorder,
+}
+
+impl Erasure {
+    pub async fn connect(
+        db_url: &str,
+        database: &DatabaseConf,
+        signing_history_retention_days: u64,
+        wallet_cns: Vec<ContractName>,
+        tokens: Vec<ContractName>,
+        audit: AuditRecorder,
+    ) -> Result<Self> {
+        Ok(Self {
+            db: DbPools::connect(db_url, database).await?,
+            signing_history_retention_days,
+            wallet_cns,
+            tokens,
+            audit,
+        })
+    }
+
+    /// Tables only exist once the module owning them ran with this database.
+    async fn has_table(&self, table: &str) -> Result<bool> {
+        Ok(sqlx::query_scalar("SELECT to_regclass($1) IS NOT NULL")
+            .bind(table)
+            .fetch_one(&self.db.primary)
+            .await?)
+    }
+
+    async fn count_rows(&self, table: &str, column: &str, account: &str) -> Result<u64> {
+        if !self.has_table(table).await? {
+            return Ok(0);
+        }
+        let count: i64 =
+            sqlx::query_scalar(&format!("SELECT COUNT(*) FROM {table} WHERE {column} = $1"))
+                .bind(account)
+                .fetch_one(&self.db.primary)
+                .await?;
+        Ok(count as u64)
+    }
+
+    /// Personal data held for `account`, without touching it.
+    async fn report(&self, account: &str) -> Result<ErasureReport> {
+        let mut report = ErasureReport {
+            account: account.to_string(),
+            invite_bindings: self.count_rows("invite_codes", "wallet", account).await?,
+            signing_requests: self
+                .count_rows("signing_history", "account", account)
+                .await?,
+            notification_preferences: self
+                .count_rows("notification_preferences", "account", account)
+                .await?,
+            ..Default::default()
+        };
+        for contract_name in &self.wallet_cns {
+            let Some(store) = wallet::client::indexer::store(contract_name) else {
+                continue;
+            };
+            if let Some(wallet) = &store.read().await.state {
+                if wallet.get_salt(&account.to_string()).is_ok() {
+                    if logs_in_with_password(wallet, account) {
+                        report.password_salts_kept += 1;
+                    } else {
+                        report.salts += 1;
+                    }
+                }
+            }
+            for token in &self.tokens {
+                let Some(store) = history::store(token) else {
+                    continue;
+                };
+                if let Some(history) = &store.read().await.state {
+                    report.history_entries += history
+                        .count_entries(token, &identity(account, contract_name))
+                        .context("counting history entries")?;
+                }
+            }
+        }
+        Ok(report)
+    }
+
+    async fn erase(&self, account: &str) -> Result<ErasureReport> {
+        let mut report = ErasureReport {
+            account: account.to_string(),
+            ..Default::default()
+        };
+
+        let mut tx = self.db.primary.begin().await?;
+        if self.has_table("invite_codes").await? {
+            report.invite_bindings =
+                sqlx::query("UPDATE invite_codes SET wallet = NULL WHERE wallet = $1")
+                    .bind(account)
+                    .execute(&mut *tx)
+                    .await?
+                    .rows_affected();
+        }
+        if self.has_table("signing_history").await? {
+            report.signing_requests = sqlx::query("DELETE FROM signing_history WHERE account = $1")
+                .bind(account)
+                .execute(&mut *tx)
+                .await?
+                .rows_affected();
+        }
+        if self.has_table("notification_preferences").await? {
+            report.notification_preferences =
+                sqlx::query("DELETE FROM notification_preferences WHERE account = $1")
+                    .bind(account)
+                    .execute(&mut *tx)
+                    .await?
+                    .rows_affected();
+        }
+        tx.commit().await?;
+
+        for contract_name in &self.wallet_cns {
+            let Some(store) = wallet::client::indexer::store(contract_name) else {
+                continue;
+            };
+            let mut store = store.write().await;
+            if let Some(wallet) = &mut store.state {
+                if logs_in_with_password(wallet, account) {
+                    report.password_salts_kept +=
+                        wallet.get_salt(&account.to_string()).is_ok() as usize;
+                } else if wallet.erase_salt(account) {
+                    report.salts += 1;
+                    wallet::client::journal::rewrite(&contract_name.0, wallet)
+                        .context("rewriting state journal")?;
+                }
+            }
+            drop(store);
+
+            for token in &self.tokens {
+                let Some(store) = history::store(token) else {
+                    continue;
+                };
+                if let Some(history) = &mut store.write().await.state {
+                    report.history_entries += history
+                        .erase_account(token, &identity(account, contract_name))
+                        .context("erasing history entries")?;
+                }
+            }
+        }
+
+        tracing::info!("🗑️ Erased the personal data of {account}: {report:?}");
+        self.audit.record(
+            AuditKind::DataErasure,
+            "admin",
+            serde_json::json!({
+                "account": account,
+                "salts": report.salts,
+                "password_salts_kept": report.password_salts_kept,
+                "history_entries": report.history_entries,
+                "invite_bindings": report.invite_bindings,
+                "signing_requests": report.signing_requests,
+                "notification_preferences": report.notification_preferences,
+            }),
+        );
+        Ok(report)
+    }
+
+    /// Signing requests completed before the retention period, counted or deleted.
+    async fn retention(&self, purge: bool) -> Result<RetentionReport> {
+        let mut report = RetentionReport {
+            signing_history_retention_days: self.signing_history_retention_days,
+            ..Default::default()
+        };
+        if self.signing_history_retention_days == 0 || !self.has_table("signing_history").await? {
+            return Ok(report);
+        }
+        let condition = "completed_at < NOW() - make_interval(days => $1)";
+        let days = self.signing_history_retention_days as i32;
+        if !purge {
+            let count: i64 = sqlx::query_scalar(&format!(
+                "SELECT COUNT(*) FROM signing_history WHERE {condition}"
+            ))
+            .bind(days)
+            .fetch_one(&self.db.primary)
+            .await?;
+            report.expired_signing_requests = count as u64;
+            return Ok(report);
+        }
+        report.expired_signing_requests =
+            sqlx::query(&format!("DELETE FROM signing_history WHERE {condition}"))
+                .bind(days)
+                .execute(&self.db.primary)
+                .await?
+                .rows_affected();
+
+        tracing::info!(
+            "🗑️ Purged {} signing requests past their retention",
+            report.expired_signing_requests
+        );
+        self.audit.record(
+            AuditKind::DataErasure,
+            "admin",
+            serde_json::json!({
+                "retention_days": report.signing_history_retention_days,
+                "signing_requests": report.expired_signing_requests,
+            }),
+        );
+        Ok(report)
+    }
+}
+
+/// Whether `account` authenticates with a password, alone or as one of its factors.
+fn logs_in_with_password(
+    wallet: &wallet::client::tx_executor_handler::Wallet,
+    account: &str,
+) -> bool {
+    fn uses_password(auth_method: &AuthMethod) -> bool {
+        match auth_method {
+            AuthMethod::Password { .. } => true,
+            AuthMethod::Multi(factors) => factors.iter().any(uses_password),
+            _ => false,
+        }
+    }
+    wallet
+        .get(&account.to_string())
+        .is_ok_and(|info| uses_password(&info.auth_method))
+}
+
+fn identity(account: &str, contract_name: &ContractName) -> Identity {
+    Identity::new(format!("{account}@{}", contract_name.0))
+}
+
+// --------------------------------------------------------
+//     Routes
+// --------------------------------------------------------
+
+/// Admin routes listing, then purging, the off-chain personal data of an account, and the
+/// signing history past its retention period.
+pub fn erasure_admin_router(erasure: Arc<Erasure>) -> Router {
+    Router::new()
+        .route(
+            "/erasure/retention",
+            get(get_expired_data).delete(purge_expired_data),
+        )
+        .route(
+            "/erasure/{account}",
+            get(get_personal_data).delete(erase_personal_data),
+        )
+        .with_state(erasure)
+}
+
+async fn get_expired_data(
+    State(erasure): State<Arc<Erasure>>,
+) -> Result<Json<RetentionReport>, AppError> {
+    Ok(Json(erasure.retention(false).await?))
+}
+
+async fn purge_expired_data(
+    State(erasure): State<Arc<Erasure>>,
+) -> Result<Json<RetentionReport>, AppError> {
+    Ok(Json(erasure.retention(true).await?))
+}
+
+async fn get_personal_data(
+    State(erasure): State<Arc<Erasure>>,
+    Path(account): Path<String>,
+) -> Result<Json<ErasureReport>, AppError> {
+    Ok(Json(erasure.report(&account).await?))
+}
+
+async fn erase_personal_data(
+    State(erasure): State<Arc<Erasure>>,
+    Path(account): Path<String>,
+) -> Result<Json<ErasureReport>, AppError> {
+    Ok(Json(erasure.erase(&account).await?))
+}
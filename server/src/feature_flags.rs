@@ -0,0 +1,420 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use anyhow::{bail, Context, Result};
+use axum::{
+    extract::{Path, Query, Request, State},
+    http::StatusCode,
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{get, put},
+    Json, Router,
+};
+use client_sdk::AppError;
+use hyli_modules::{
+    bus::SharedMessageBus, log_error, module_bus_client, module_handle_messages, modules::Module,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::audit::{AuditKind, AuditRecorder};
+use crate::conf::{DatabaseConf, FeatureFlagRule, FeatureFlagsConf};
+use crate::db::DbPools;
+
+/// Header carrying the account a request is made for, to evaluate flags gating routes.
+pub const ACCOUNT_HEADER: &str = "x-wallet-account";
+
+/// Evaluates feature flags per account, to roll out new endpoints and event formats to a share
+/// of the accounts first.
+///
+/// Rules come from `feature_flags.flags`, replaced by the overrides stored in `db_url` when
+/// they are enabled. An account falls in a flag's rollout depending on a hash of the flag and
+/// the account, so raising the percentage only adds accounts and each flag picks its own.
+/// The default handle has no flag on, for binaries running without them.
+#[derive(Clone, Default)]
+pub struct FeatureFlags {
+    inner: Arc<FlagsInner>,
+}
+
+#[derive(Default)]
+struct FlagsInner {
+    defaults: HashMap<String, FeatureFlagRule>,
+    overrides: RwLock<BTreeMap<String, FeatureFlagRule>>,
+    db: Option<DbPools>,
+    audit: AuditRecorder,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FlagStatus {
+    pub name: String,
+    #[serde(flatten)]
+    pub rule: FeatureFlagRule,
+    /// Whether the rule comes from the database rather than the configuration.
+    pub overridden: bool,
+}
+
+/// Bucket of `account` in the rollout of `flag`, from 0 to 99.
+fn bucket(flag: &str, account: &str) -> u8 {
+    let digest = Sha256::digest(format!("{flag}:{account}"));
+    let mut value = [0u8; 8];
+    value.copy_from_slice(&digest[..8]);
+    (u64::from_be_bytes(value) % 100) as u8
+}
+
+impl FeatureFlags {
+    /// Flags of the configuration only.
+    pub fn new(conf: &FeatureFlagsConf) -> Self {
+        Self {
+            inner: Arc::new(FlagsInner {
+                defaults: conf.flags.clone(),
+                ..Default::default()
+            }),
+        }
+    }
+
+    /// Flags of the configuration, replaced by the overrides stored in `db_url`.
+    pub async fn connect(
+        conf: &FeatureFlagsConf,
+        db_url: &str,
+        database: &DatabaseConf,
+        audit: AuditRecorder,
+    ) -> Result<Self> {
+        let db = DbPools::connect(db_url, database).await?;
+        sqlx::query(
+            r#"CREATE TABLE IF NOT EXISTS feature_flags (
+                name TEXT PRIMARY KEY,
+                enabled BOOLEAN NOT NULL,
+                rollout_percent SMALLINT NOT NULL,
+                accounts TEXT[] NOT NULL,
+                updated_at TIMESTAMP NOT NULL DEFAULT NOW()
+            )"#,
+        )
+        .execute(&db.primary)
+        .await?;
+
+        let flags = Self {
+            inner: Arc::new(FlagsInner {
+                defaults: conf.flags.clone(),
+                overrides: RwLock::default(),
+                db: Some(db),
+                audit,
+            }),
+        };
+        flags.reload().await?;
+        Ok(flags)
+    }
+
+    fn rule(&self, flag: &str) -> Option<FeatureFlagRule> {
+        self.inner
+            .overrides
+            .read()
+            .expect("feature flags poisoned")
+            .get(flag)
+            .or_else(|| self.inner.defaults.get(flag))
+            .cloned()
+    }
+
+    /// Whether `flag` is on for `account`. Without an account, only flags rolled out to every
+    /// account are on.
+    pub fn is_enabled(&self, flag: &str, account: Option<&str>) -> bool {
+        let Some(rule) = self.rule(flag) else {
+            return false;
+        };
+        if !rule.enabled {
+            return false;
+        }
+        if rule.rollout_percent >= 100 {
+            return true;
+        }
+        let Some(account) = account else {
+            return false;
+        };
+        rule.accounts.iter().any(|a| a == account) || bucket(flag, account) < rule.rollout_percent
+    }
+
+    /// Every known flag and whether it's on for `account`.
+    pub fn evaluate(&self, account: Option<&str>) -> BTreeMap<String, bool> {
+        let mut names: Vec<String> = self.inner.defaults.keys().cloned().collect();
+        names.extend(
+            self.inner
+                .overrides
+                .read()
+                .expect("feature flags poisoned")
+                .keys()
+                .cloned(),
+        );
+        names
+            .into_iter()
+            .map(|name| {
+                let enabled = self.is_enabled(&name, account);
+                (name, enabled)
+            })
+            .collect()
+    }
+
+    /// Answers 404 on the routes of `router` for the requests whose account, read from
+    /// [`ACCOUNT_HEADER`], doesn't have `flag` on.
+    pub fn gate(&self, router: Router, flag: &str) -> Router {
+        router.layer(middleware::from_fn_with_state(
+            (self.clone(), flag.to_string()),
+            require_flag,
+        ))
+    }
+
+    async fn reload(&self) -> Result<()> {
+        let Some(db) = &self.inner.db else {
+            return Ok(());
+        };
+        let rows: Vec<(String, bool, i16, Vec<String>)> =
+            sqlx::query_as("SELECT name, enabled, rollout_percent, accounts FROM feature_flags")
+                .fetch_all(db.reader())
+                .await
+                .context("loading feature flags")?;
+        let overrides = rows
+            .into_iter()
+            .map(|(name, enabled, rollout_percent, accounts)| {
+                (
+                    name,
+                    FeatureFlagRule {
+                        enabled,
+                        rollout_percent: rollout_percent.clamp(0, 100) as u8,
+                        accounts,
+                    },
+                )
+            })
+            .collect();
+        *self
+            .inner
+            .overrides
+            .write()
+            .expect("feature flags poisoned") = overrides;
+        Ok(())
+    }
+
+    fn status(&self) -> Vec<FlagStatus> {
+        let overrides = self
+            .inner
+            .overrides
+            .read()
+            .expect("feature flags poisoned")
+            .clone();
+        let mut flags: BTreeMap<String, FlagStatus> = self
+            .inner
+            .defaults
+            .iter()
+            .map(|(name, rule)| {
+                (
+                    name.clone(),
+                    FlagStatus {
+                        name: name.clone(),
+                        rule: rule.clone(),
+                        overridden: false,
+                    },
+                )
+            })
+            .collect();
+        for (name, rule) in overrides {
+            flags.insert(
+                name.clone(),
+                FlagStatus {
+                    name,
+                    rule,
+                    overridden: true,
+                },
+            );
+        }
+        flags.into_values().collect()
+    }
+
+    async fn set(&self, name: &str, rule: &FeatureFlagRule) -> Result<()> {
+        let Some(db) = &self.inner.db else {
+            bail!("Feature flag overrides are disabled");
+        };
+        if rule.rollout_percent > 100 {
+            bail!("rollout_percent must be at most 100");
+        }
+        sqlx::query(
+            "INSERT INTO feature_flags (name, enabled, rollout_percent, accounts)
+             VALUES ($1, $2, $3, $4)
+             ON CONFLICT (name) DO UPDATE SET enabled = EXCLUDED.enabled,
+                rollout_percent = EXCLUDED.rollout_percent, accounts = EXCLUDED.accounts,
+                updated_at = NOW()",
+        )
+        .bind(name)
+        .bind(rule.enabled)
+        .bind(rule.rollout_percent as i16)
+        .bind(&rule.accounts)
+        .execute(&db.primary)
+        .await?;
+        self.inner
+            .overrides
+            .write()
+            .expect("feature flags poisoned")
+            .insert(name.to_string(), rule.clone());
+
+        tracing::info!("🚩 Feature flag {name} set to {rule:?}");
+        self.inner.audit.record(
+            AuditKind::AdminOperation,
+            "admin",
+            serde_json::json!({
+                "operation": "set_feature_flag",
+                "flag": name,
+                "enabled": rule.enabled,
+                "rollout_percent": rule.rollout_percent,
+                "accounts": rule.accounts,
+            }),
+        );
+        Ok(())
+    }
+
+    /// Drops the override of `name`, back to the configured rule.
+    async fn reset(&self, name: &str) -> Result<bool> {
+        let Some(db) = &self.inner.db else {
+            bail!("Feature flag overrides are disabled");
+        };
+        let deleted = sqlx::query("DELETE FROM feature_flags WHERE name = $1")
+            .bind(name)
+            .execute(&db.primary)
+            .await?
+            .rows_affected()
+            > 0;
+        self.inner
+            .overrides
+            .write()
+            .expect("feature flags poisoned")
+            .remove(name);
+        if deleted {
+            tracing::info!("🚩 Feature flag {name} back to its configuration");
+            self.inner.audit.record(
+                AuditKind::AdminOperation,
+                "admin",
+                serde_json::json!({ "operation": "reset_feature_flag", "flag": name }),
+            );
+        }
+        Ok(deleted)
+    }
+}
+
+async fn require_flag(
+    State((flags, flag)): State<(FeatureFlags, String)>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let account = request
+        .headers()
+        .get(ACCOUNT_HEADER)
+        .and_then(|value| value.to_str().ok());
+    if !flags.is_enabled(&flag, account) {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    next.run(request).await
+}
+
+/// Reloads the overrides, so changes made through another server sharing the database apply.
+pub struct FeatureFlagsModule {
+    #[allow(unused)]
+    bus: FeatureFlagsModuleBusClient,
+    flags: FeatureFlags,
+    refresh_interval: Duration,
+}
+
+pub struct FeatureFlagsModuleCtx {
+    pub flags: FeatureFlags,
+    pub refresh_interval_secs: u64,
+}
+
+module_bus_client! {
+#[derive(Debug)]
+pub struct FeatureFlagsModuleBusClient {
+}
+}
+
+impl Module for FeatureFlagsModule {
+    type Context = FeatureFlagsModuleCtx;
+
+    async fn build(bus: SharedMessageBus, ctx: Self::Context) -> Result<Self> {
+        Ok(Self {
+            bus: FeatureFlagsModuleBusClient::new_from_bus(bus.new_handle()).await,
+            flags: ctx.flags,
+            refresh_interval: Duration::from_secs(ctx.refresh_interval_secs.max(1)),
+        })
+    }
+
+    async fn run(&mut self) -> Result<()> {
+        let mut refresh = tokio::time::interval(self.refresh_interval);
+        module_handle_messages! {
+            on_self self,
+            _ = refresh.tick() => {
+                let _ = log_error!(self.flags.reload().await, "Reloading feature flags");
+            }
+        };
+        Ok(())
+    }
+}
+
+// --------------------------------------------------------
+//     Routes
+// --------------------------------------------------------
+
+#[derive(Debug, Deserialize)]
+pub struct FeaturesQuery {
+    pub account: Option<String>,
+}
+
+/// Public route telling frontends which flags are on for an account.
+pub fn feature_flags_router(flags: FeatureFlags) -> Router {
+    Router::new()
+        .route("/api/features", get(get_features))
+        .with_state(flags)
+}
+
+/// Admin routes to list the flags and override their rollout.
+pub fn feature_flags_admin_router(flags: FeatureFlags) -> Router {
+    Router::new()
+        .route("/feature_flags", get(list_flags))
+        .route("/feature_flags/{name}", put(set_flag).delete(reset_flag))
+        .with_state(flags)
+}
+
+async fn get_features(
+    State(flags): State<FeatureFlags>,
+    Query(query): Query<FeaturesQuery>,
+) -> Json<BTreeMap<String, bool>> {
+    Json(flags.evaluate(query.account.as_deref()))
+}
+
+async fn list_flags(State(flags): State<FeatureFlags>) -> Json<Vec<FlagStatus>> {
+    Json(flags.status())
+}
+
+async fn set_flag(
+    State(flags): State<FeatureFlags>,
+    Path(name): Path<String>,
+    Json(rule): Json<FeatureFlagRule>,
+) -> Result<Json<Vec<FlagStatus>>, AppError> {
+    flags
+        .set(&name, &rule)
+        .await
+        .map_err(|e| AppError(StatusCode::BAD_REQUEST, e))?;
+    Ok(Json(flags.status()))
+}
+
+async fn reset_flag(
+    State(flags): State<FeatureFlags>,
+    Path(name): Path<String>,
+) -> Result<Json<Vec<FlagStatus>>, AppError> {
+    if !flags
+        .reset(&name)
+        .await
+        .map_err(|e| AppError(StatusCode::BAD_REQUEST, e))?
+    {
+        return Err(AppError(
+            StatusCode::NOT_FOUND,
+            anyhow::anyhow!("Feature flag {name} has no override"),
+        ));
+    }
+    Ok(Json(flags.status()))
+}
@@ -0,0 +1,397 @@
+use std::{
+    collections::VecDeque,
+    net::SocketAddr,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result};
+use client_sdk::rest_client::NodeApiClient;
+use futures::Stream;
+use hyli_modules::{
+    bus::SharedMessageBus,
+    module_bus_client, module_handle_messages,
+    modules::{contract_state_indexer::CSIBusEvent, Module},
+};
+use sdk::{BlobData, BlobTransaction, ContractName, Hashed, Identity, TxHash};
+use server::correlation::{send_tx_blob, CorrelationId, CORRELATION_HEADER};
+use server::provers::ProverControls;
+use tokio::{
+    sync::{broadcast, mpsc},
+    task::JoinSet,
+};
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status};
+use wallet::{
+    client::indexer::{SharedWalletEvent, TxOutcome},
+    WalletAction,
+};
+
+use crate::conf::GrpcConf;
+
+pub mod proto {
+    tonic::include_proto!("hyli.wallet.v1");
+}
+
+use proto::wallet_service_server::{WalletService, WalletServiceServer};
+
+/// Statuses kept for the streams opened after a transaction moved on.
+const RECENT_STATUSES: usize = 10_000;
+
+/// Serves the `hyli.wallet.v1.WalletService` gRPC API next to the REST one.
+///
+/// Accounts are read from the same indexed wallet states as the REST API, and transaction
+/// statuses come from the wallet settlements and the autoprover proofs.
+pub struct GrpcModule {
+    bus: GrpcModuleBusClient,
+    port: u16,
+    service: GrpcService,
+    prover_controls: Arc<ProverControls>,
+}
+
+pub struct GrpcModuleCtx {
+    pub conf: GrpcConf,
+    /// Wallet targeted by requests leaving `contract_name` empty.
+    pub wallet_cn: ContractName,
+    pub node: Arc<dyn NodeApiClient + Send + Sync>,
+    pub prover_controls: Arc<ProverControls>,
+}
+
+module_bus_client! {
+#[derive(Debug)]
+pub struct GrpcModuleBusClient {
+    receiver(CSIBusEvent<SharedWalletEvent>),
+}
+}
+
+#[derive(Clone)]
+struct GrpcService {
+    wallet_cn: ContractName,
+    node: Arc<dyn NodeApiClient + Send + Sync>,
+    statuses: Arc<TxStatuses>,
+}
+
+struct TxStatuses {
+    updates: broadcast::Sender<proto::TxStatus>,
+    recent: Mutex<VecDeque<proto::TxStatus>>,
+}
+
+impl TxStatuses {
+    fn record(&self, tx_hash: String, stage: proto::Stage, contract_name: String, outputs: String) {
+        let correlation_id = CorrelationId::of_tx(&TxHash(tx_hash.clone())).0;
+        let status = proto::TxStatus {
+            tx_hash,
+            stage: stage.into(),
+            contract_name,
+            program_outputs: outputs,
+            timestamp_ms: now_ms(),
+            correlation_id,
+        };
+        {
+            let mut recent = self.recent.lock().expect("tx statuses poisoned");
+            if recent.len() >= RECENT_STATUSES {
+                recent.pop_front();
+            }
+            recent.push_back(status.clone());
+        }
+        // No receiver is not an error, nobody is following the transaction.
+        let _ = self.updates.send(status);
+    }
+
+    fn known(&self, tx_hash: &str) -> Vec<proto::TxStatus> {
+        self.recent
+            .lock()
+            .expect("tx statuses poisoned")
+            .iter()
+            .filter(|status| status.tx_hash == tx_hash)
+            .cloned()
+            .collect()
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+fn is_final(status: &proto::TxStatus) -> bool {
+    matches!(
+        status.stage(),
+        proto::Stage::Settled | proto::Stage::Failed | proto::Stage::TimedOut
+    )
+}
+
+impl GrpcService {
+    fn contract_name(&self, contract_name: String) -> ContractName {
+        if contract_name.is_empty() {
+            self.wallet_cn.clone()
+        } else {
+            ContractName(contract_name)
+        }
+    }
+
+    async fn account(&self, request: proto::GetAccountRequest) -> Result<proto::Account, Status> {
+        let contract_name = self.contract_name(request.contract_name);
+        let store = wallet::client::indexer::store(&contract_name)
+            .ok_or_else(|| Status::not_found(format!("Wallet {contract_name} is not indexed")))?;
+        let store = store.read().await;
+        let wallet = store.state.as_ref().ok_or_else(|| {
+            Status::unavailable(format!("Wallet {contract_name} has no state yet"))
+        })?;
+        let info = wallet
+            .get(&request.account)
+            .map_err(|_| Status::not_found(format!("Account '{}' not found", request.account)))?;
+
+        Ok(proto::Account {
+            auth_method_json: serde_json::to_string(&info.auth_method)
+                .map_err(|e| Status::internal(e.to_string()))?,
+            session_keys: info
+                .session_keys
+                .iter()
+                .map(|key| proto::SessionKey {
+                    public_key: key.public_key.clone(),
+                    expiration_date: key.expiration_date.0 as u64,
+                    whitelist: key
+                        .whitelist
+                        .iter()
+                        .flatten()
+                        .map(|contract| contract.0.clone())
+                        .collect(),
+                })
+                .collect(),
+            nonce: info.nonce as u64,
+            // Empty once erased at the owner's request.
+            salt: wallet.get_salt(&request.account).unwrap_or_default(),
+            account: request.account,
+        })
+    }
+}
+
+#[tonic::async_trait]
+impl WalletService for GrpcService {
+    async fn get_account(
+        &self,
+        request: Request<proto::GetAccountRequest>,
+    ) -> Result<Response<proto::Account>, Status> {
+        self.account(request.into_inner()).await.map(Response::new)
+    }
+
+    async fn get_nonce(
+        &self,
+        request: Request<proto::GetAccountRequest>,
+    ) -> Result<Response<proto::Nonce>, Status> {
+        let account = self.account(request.into_inner()).await?;
+        // Nonces must increase, and the frontend uses the current time.
+        Ok(Response::new(proto::Nonce {
+            nonce: account.nonce,
+            next: now_ms().max(account.nonce + 1),
+        }))
+    }
+
+    async fn assemble_transaction(
+        &self,
+        request: Request<proto::AssembleTransactionRequest>,
+    ) -> Result<Response<proto::Transaction>, Status> {
+        let request = request.into_inner();
+        let contract_name = self.contract_name(request.contract_name);
+        let action: WalletAction = serde_json::from_str(&request.action_json)
+            .map_err(|e| Status::invalid_argument(format!("Invalid wallet action: {e}")))?;
+        if action
+            .account()
+            .is_some_and(|account| *account != request.account)
+        {
+            return Err(Status::invalid_argument(
+                "The wallet action applies to another account",
+            ));
+        }
+        let identity = if request.identity.is_empty() {
+            format!("{}@{}", request.account, contract_name.0)
+        } else {
+            request.identity
+        };
+
+        let mut blobs = vec![action.as_blob(contract_name)];
+        blobs.extend(request.blobs.into_iter().map(|blob| sdk::Blob {
+            contract_name: ContractName(blob.contract_name),
+            data: BlobData(blob.data),
+        }));
+        let tx = BlobTransaction::new(Identity::new(identity), blobs);
+        Ok(Response::new(proto::Transaction {
+            tx_hash: tx.hashed().0,
+            identity: tx.identity.0,
+            blobs: tx
+                .blobs
+                .into_iter()
+                .map(|blob| proto::Blob {
+                    contract_name: blob.contract_name.0,
+                    data: blob.data.0,
+                })
+                .collect(),
+        }))
+    }
+
+    async fn send_transaction(
+        &self,
+        request: Request<proto::Transaction>,
+    ) -> Result<Response<proto::TxHash>, Status> {
+        // Same header as the REST API, so integrators can follow one id through both.
+        let correlation_id = request
+            .metadata()
+            .get(CORRELATION_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(CorrelationId::parse)
+            .unwrap_or_else(CorrelationId::generate);
+        let request = request.into_inner();
+        let tx = BlobTransaction::new(
+            Identity::new(request.identity),
+            request
+                .blobs
+                .into_iter()
+                .map(|blob| sdk::Blob {
+                    contract_name: ContractName(blob.contract_name),
+                    data: BlobData(blob.data),
+                })
+                .collect(),
+        );
+        let contract_name = tx
+            .blobs
+            .first()
+            .map(|blob| blob.contract_name.0.clone())
+            .unwrap_or_default();
+        let tx_hash = correlation_id
+            .clone()
+            .scope(send_tx_blob(self.node.as_ref(), tx))
+            .await
+            .map_err(|e| Status::unavailable(format!("Sending transaction: {e:#}")))?;
+        self.statuses.record(
+            tx_hash.0.clone(),
+            proto::Stage::Sequenced,
+            contract_name,
+            String::new(),
+        );
+        let mut response = Response::new(proto::TxHash { tx_hash: tx_hash.0 });
+        if let Ok(value) = correlation_id.0.parse() {
+            response.metadata_mut().insert(CORRELATION_HEADER, value);
+        }
+        Ok(response)
+    }
+
+    type StreamTxStatusStream =
+        Pin<Box<dyn Stream<Item = Result<proto::TxStatus, Status>> + Send + 'static>>;
+
+    async fn stream_tx_status(
+        &self,
+        request: Request<proto::TxHash>,
+    ) -> Result<Response<Self::StreamTxStatusStream>, Status> {
+        let tx_hash = request.into_inner().tx_hash;
+        // Subscribed first so nothing is missed between the two.
+        let mut updates = self.statuses.updates.subscribe();
+        let known = self.statuses.known(&tx_hash);
+        let (sender, receiver) = mpsc::channel(16);
+
+        tokio::spawn(async move {
+            for status in known {
+                let done = is_final(&status);
+                if sender.send(Ok(status)).await.is_err() || done {
+                    return;
+                }
+            }
+            loop {
+                match updates.recv().await {
+                    Ok(status) if status.tx_hash == tx_hash => {
+                        let done = is_final(&status);
+                        if sender.send(Ok(status)).await.is_err() || done {
+                            return;
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        tracing::debug!("gRPC status stream of {tx_hash} lagged by {skipped}");
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return,
+                }
+            }
+        });
+
+        Ok(Response::new(Box::pin(ReceiverStream::new(receiver))))
+    }
+}
+
+impl Module for GrpcModule {
+    type Context = GrpcModuleCtx;
+
+    async fn build(bus: SharedMessageBus, ctx: Self::Context) -> Result<Self> {
+        Ok(GrpcModule {
+            bus: GrpcModuleBusClient::new_from_bus(bus.new_handle()).await,
+            port: ctx.conf.port,
+            service: GrpcService {
+                wallet_cn: ctx.wallet_cn,
+                node: ctx.node,
+                statuses: Arc::new(TxStatuses {
+                    updates: broadcast::channel(1024).0,
+                    recent: Mutex::new(VecDeque::new()),
+                }),
+            },
+            prover_controls: ctx.prover_controls,
+        })
+    }
+
+    async fn run(&mut self) -> Result<()> {
+        let addr = SocketAddr::from(([0, 0, 0, 0], self.port));
+        let server = tonic::transport::Server::builder()
+            .add_service(WalletServiceServer::new(self.service.clone()))
+            .serve(addr);
+        let mut servers = JoinSet::new();
+        servers.spawn(async move { server.await.context("serving gRPC API") });
+        tracing::info!("📡 gRPC API on port {}", self.port);
+
+        let mut proofs = self.prover_controls.subscribe_proofs();
+        let statuses = self.service.statuses.clone();
+
+        module_handle_messages! {
+            on_self self,
+            Ok(proven) = proofs.recv() => {
+                for (tx_hash, _) in proven.txs {
+                    statuses.record(
+                        tx_hash.0,
+                        proto::Stage::Proven,
+                        proven.contract_name.0.clone(),
+                        String::new(),
+                    );
+                }
+            }
+            Some(served) = servers.join_next() => {
+                if let Ok(Err(e)) = served {
+                    tracing::error!("gRPC API stopped: {:#}", e);
+                }
+            }
+            listen<CSIBusEvent<SharedWalletEvent>> event => {
+                let stage = match event.event.outcome {
+                    TxOutcome::Success => proto::Stage::Settled,
+                    TxOutcome::Failure => proto::Stage::Failed,
+                    TxOutcome::Timeout => proto::Stage::TimedOut,
+                };
+                // Wallet identities are `{account}@{contract_name}`.
+                let contract_name = event
+                    .event
+                    .account
+                    .0
+                    .rsplit_once('@')
+                    .map(|(_, contract_name)| contract_name.to_string())
+                    .unwrap_or_default();
+                statuses.record(
+                    event.event.tx_hash.0.clone(),
+                    stage,
+                    contract_name,
+                    event.event.program_outputs.clone(),
+                );
+            }
+        };
+
+        servers.abort_all();
+        Ok(())
+    }
+}
@@ -9,8 +9,11 @@ use sdk::BlobIndex;
 use sdk::Calldata;
 use sdk::Hashed;
 use sdk::StateCommitment;
+use sha2::{Digest, Sha256};
 use std::collections::{BTreeMap, VecDeque};
-use std::sync::Arc;
+use std::io::ErrorKind;
+use std::path::{Path as FsPath, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
 
 use client_sdk::contract_indexer::axum;
 use client_sdk::contract_indexer::utoipa;
@@ -28,19 +31,20 @@ use client_sdk::contract_indexer::{
 use client_sdk::transaction_builder::TxExecutorHandler;
 use client_sdk::AppError;
 use sdk::utils::parse_calldata;
-use sdk::Identity;
 use sdk::TxHash;
-use serde::Serialize;
+use sdk::{Blob, ContractName, Identity};
+use serde::{Deserialize, Serialize};
+use wallet::WalletAction;
 
 use crate::app::Wrap;
 
 #[derive(Debug, Clone, Default, Serialize, ToSchema, BorshDeserialize, BorshSerialize)]
 pub struct TransactionDetails {
-    id: TxHash,
-    r#type: String,
-    status: String,
-    amount: u128,
-    address: Identity,
+    pub(crate) id: TxHash,
+    pub(crate) r#type: String,
+    pub(crate) status: String,
+    pub(crate) amount: u128,
+    pub(crate) address: Identity,
     timestamp: u128,
 }
 
@@ -48,6 +52,83 @@ pub struct TransactionDetails {
 pub struct TokenHistory {
     token: SmtTokenProvableState,
     history: BTreeMap<Identity, VecDeque<TransactionDetails>>,
+    /// When each account's history was last added to, to spill the least active ones first.
+    #[borsh(skip)]
+    last_used: BTreeMap<Identity, u64>,
+    #[borsh(skip)]
+    clock: u64,
+    /// Entries held in `history`, counted on first use after a restart.
+    #[borsh(skip)]
+    in_memory: Option<usize>,
+}
+
+/// Entries kept per account, in memory and spilled together.
+const HISTORY_LEN: usize = 100;
+/// Status of entries still waiting for their transaction to settle. They stay in memory, where
+/// their status gets updated.
+const PENDING_STATUS: &str = "Sequenced";
+
+/// Bounds of the in-memory token history. Without them, each account keeps its last
+/// [`HISTORY_LEN`] entries in memory.
+#[derive(Debug, Clone)]
+pub struct HistoryLimits {
+    /// Most recent entries of an account kept in memory, older settled ones are spilled.
+    pub hot_entries_per_account: usize,
+    /// Entries kept in memory across accounts, the least recently active accounts are spilled
+    /// first when it's exceeded.
+    pub max_entries: usize,
+    /// Spilled entries go to a file per account, under a directory per token.
+    pub spill_directory: PathBuf,
+}
+
+static LIMITS: OnceLock<HistoryLimits> = OnceLock::new();
+
+/// Bounds the history of every token indexed by this process, once at startup.
+pub fn configure(limits: HistoryLimits) -> anyhow::Result<()> {
+    LIMITS
+        .set(limits)
+        .map_err(|_| anyhow!("Token history limits already configured"))
+}
+
+fn spill_path(limits: &HistoryLimits, contract_name: &ContractName, account: &Identity) -> PathBuf {
+    limits
+        .spill_directory
+        .join(&contract_name.0)
+        .join(hex::encode(Sha256::digest(account.0.as_bytes())))
+}
+
+/// Spilled entries of an account, newest first.
+fn read_spilled(path: &FsPath) -> anyhow::Result<Vec<TransactionDetails>> {
+    match std::fs::read(path) {
+        Ok(bytes) => borsh::from_slice(&bytes)
+            .with_context(|| format!("decoding spilled history {}", path.display())),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(vec![]),
+        Err(e) => Err(e).with_context(|| format!("reading spilled history {}", path.display())),
+    }
+}
+
+/// Adds `cold` entries, newer than those already spilled, to the account's file.
+fn spill(path: &FsPath, cold: &[TransactionDetails]) -> anyhow::Result<()> {
+    let mut entries = cold.to_vec();
+    entries.extend(read_spilled(path)?);
+    entries.truncate(HISTORY_LEN);
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, borsh::to_vec(&entries)?)?;
+    std::fs::rename(&tmp, path)
+        .with_context(|| format!("writing spilled history {}", path.display()))
+}
+
+/// Stores of the token histories indexed by this process.
+static STORES: Mutex<BTreeMap<String, ContractHandlerStore<TokenHistory>>> =
+    Mutex::new(BTreeMap::new());
+
+/// Indexed history of `contract_name`, once its indexer is built.
+pub fn store(contract_name: &ContractName) -> Option<ContractHandlerStore<TokenHistory>> {
+    STORES.lock().ok()?.get(&contract_name.0).cloned()
 }
 
 #[derive(Debug, Clone, Default, Serialize)]
@@ -78,8 +159,10 @@ impl TokenHistory {
         };
         let history_deque = self.history.entry(identity.clone()).or_default();
         history_deque.push_front(transaction.clone());
-        if history_deque.len() > 100 {
-            history_deque.truncate(100);
+        if history_deque.len() > HISTORY_LEN {
+            history_deque.truncate(HISTORY_LEN);
+        } else if let Some(count) = &mut self.in_memory {
+            *count += 1;
         }
         HistoryEvent {
             account: identity,
@@ -87,6 +170,128 @@ impl TokenHistory {
         }
     }
 
+    /// Entries of `account`, in memory and spilled.
+    pub fn count_entries(
+        &self,
+        contract_name: &ContractName,
+        account: &Identity,
+    ) -> anyhow::Result<usize> {
+        let spilled = match LIMITS.get() {
+            Some(limits) => read_spilled(&spill_path(limits, contract_name, account))?.len(),
+            None => 0,
+        };
+        Ok(self.history.get(account).map_or(0, VecDeque::len) + spilled)
+    }
+
+    /// Removes the entries of `account`, in memory and spilled, returning how many there were.
+    /// Counterparties keep their own entries of the transactions.
+    pub fn erase_account(
+        &mut self,
+        contract_name: &ContractName,
+        account: &Identity,
+    ) -> anyhow::Result<usize> {
+        let count = self.count_entries(contract_name, account)?;
+        if let Some(limits) = LIMITS.get() {
+            match std::fs::remove_file(spill_path(limits, contract_name, account)) {
+                Ok(()) => {}
+                Err(e) if e.kind() == ErrorKind::NotFound => {}
+                Err(e) => return Err(e).context("removing spilled history"),
+            }
+        }
+        if let Some(history) = self.history.remove(account) {
+            if let Some(in_memory) = &mut self.in_memory {
+                *in_memory -= history.len();
+            }
+        }
+        self.last_used.remove(account);
+        Ok(count)
+    }
+
+    /// Spills the settled entries of `account` past its `keep` most recent ones, returning how
+    /// many left memory.
+    fn spill_account(
+        &mut self,
+        limits: &HistoryLimits,
+        contract_name: &ContractName,
+        account: &Identity,
+        keep: usize,
+    ) -> usize {
+        let Some(history) = self.history.get_mut(account) else {
+            return 0;
+        };
+        let is_cold = |i: usize, t: &TransactionDetails| i >= keep && t.status != PENDING_STATUS;
+        let cold: Vec<TransactionDetails> = history
+            .iter()
+            .enumerate()
+            .filter(|(i, t)| is_cold(*i, t))
+            .map(|(_, t)| t.clone())
+            .collect();
+        if cold.is_empty() {
+            return 0;
+        }
+        if let Err(e) = spill(&spill_path(limits, contract_name, account), &cold) {
+            // Kept in memory rather than lost.
+            tracing::warn!("Failed to spill history of {}: {:#}", account, e);
+            return 0;
+        }
+
+        let mut i = 0;
+        history.retain(|t| {
+            let keep = !is_cold(i, t);
+            i += 1;
+            keep
+        });
+        if history.is_empty() {
+            self.history.remove(account);
+            self.last_used.remove(account);
+        }
+        cold.len()
+    }
+
+    /// Enforces the [`HistoryLimits`] after entries were added for `touched` accounts.
+    fn bound_memory(&mut self, contract_name: &ContractName, touched: &[Identity]) {
+        let Some(limits) = LIMITS.get() else {
+            return;
+        };
+        self.clock += 1;
+        for account in touched {
+            self.last_used.insert(account.clone(), self.clock);
+        }
+        let mut in_memory = self
+            .in_memory
+            .unwrap_or_else(|| self.history.values().map(VecDeque::len).sum());
+
+        for account in touched {
+            in_memory -= self.spill_account(
+                limits,
+                contract_name,
+                account,
+                limits.hot_entries_per_account,
+            );
+        }
+
+        if in_memory > limits.max_entries {
+            // Down to 90% of the cap, so spilling doesn't happen on every transaction.
+            let target = limits.max_entries - limits.max_entries / 10;
+            let mut accounts: Vec<(u64, Identity)> = self
+                .history
+                .keys()
+                .map(|account| {
+                    let last_used = self.last_used.get(account).copied().unwrap_or_default();
+                    (last_used, account.clone())
+                })
+                .collect();
+            accounts.sort();
+            for (_, account) in accounts {
+                if in_memory <= target {
+                    break;
+                }
+                in_memory -= self.spill_account(limits, contract_name, &account, 0);
+            }
+        }
+        self.in_memory = Some(in_memory);
+    }
+
     fn get_action(tx: &sdk::BlobTransaction, index: BlobIndex) -> anyhow::Result<SmtTokenAction> {
         let calldata = Calldata {
             identity: tx.identity.clone(),
@@ -126,10 +331,17 @@ impl TxExecutorHandler for TokenHistory {
     }
 }
 
-impl ContractHandler<Wrap<Vec<HistoryEvent>>> for TokenHistory {
+impl ContractHandler<Wrap<Arc<[HistoryEvent]>>> for TokenHistory {
     async fn api(store: ContractHandlerStore<TokenHistory>) -> (Router<()>, OpenApi) {
+        let contract_name = store.read().await.contract_name.0.clone();
+        if let Ok(mut stores) = STORES.lock() {
+            stores.insert(contract_name, store.clone());
+        }
+
         let (router, api) = OpenApiRouter::default()
             .routes(routes!(get_history))
+            .routes(routes!(get_allowances))
+            .routes(routes!(build_approve))
             .split_for_parts();
 
         (router.with_state(store), api)
@@ -140,7 +352,7 @@ impl ContractHandler<Wrap<Vec<HistoryEvent>>> for TokenHistory {
         tx: &sdk::BlobTransaction,
         _index: sdk::BlobIndex,
         _tx_context: Arc<sdk::TxContext>,
-    ) -> anyhow::Result<Option<Wrap<Vec<HistoryEvent>>>> {
+    ) -> anyhow::Result<Option<Wrap<Arc<[HistoryEvent]>>>> {
         let mut events = vec![];
         let tx_hash = tx.hashed();
         self.history.iter_mut().for_each(|(account, history)| {
@@ -153,7 +365,7 @@ impl ContractHandler<Wrap<Vec<HistoryEvent>>> for TokenHistory {
             }
         });
         if !events.is_empty() {
-            Ok(Some(Wrap(events)))
+            Ok(Some(Wrap(events.into())))
         } else {
             Ok(None)
         }
@@ -164,7 +376,7 @@ impl ContractHandler<Wrap<Vec<HistoryEvent>>> for TokenHistory {
         tx: &sdk::BlobTransaction,
         _index: sdk::BlobIndex,
         _tx_context: Arc<sdk::TxContext>,
-    ) -> anyhow::Result<Option<Wrap<Vec<HistoryEvent>>>> {
+    ) -> anyhow::Result<Option<Wrap<Arc<[HistoryEvent]>>>> {
         let mut events = vec![];
         let tx_hash = tx.hashed();
         self.history.values_mut().for_each(|history| {
@@ -177,7 +389,7 @@ impl ContractHandler<Wrap<Vec<HistoryEvent>>> for TokenHistory {
             }
         });
         if !events.is_empty() {
-            Ok(Some(Wrap(events)))
+            Ok(Some(Wrap(events.into())))
         } else {
             Ok(None)
         }
@@ -188,7 +400,7 @@ impl ContractHandler<Wrap<Vec<HistoryEvent>>> for TokenHistory {
         tx: &sdk::BlobTransaction,
         _index: sdk::BlobIndex,
         _tx_context: Arc<sdk::TxContext>,
-    ) -> anyhow::Result<Option<Wrap<Vec<HistoryEvent>>>> {
+    ) -> anyhow::Result<Option<Wrap<Arc<[HistoryEvent]>>>> {
         let mut events = vec![];
         let tx_hash = tx.hashed();
         self.history.values_mut().for_each(|history| {
@@ -201,7 +413,7 @@ impl ContractHandler<Wrap<Vec<HistoryEvent>>> for TokenHistory {
             }
         });
         if !events.is_empty() {
-            Ok(Some(Wrap(events)))
+            Ok(Some(Wrap(events.into())))
         } else {
             Ok(None)
         }
@@ -212,7 +424,7 @@ impl ContractHandler<Wrap<Vec<HistoryEvent>>> for TokenHistory {
         tx: &sdk::BlobTransaction,
         index: sdk::BlobIndex,
         tx_context: Arc<sdk::TxContext>,
-    ) -> anyhow::Result<Option<Wrap<Vec<HistoryEvent>>>> {
+    ) -> anyhow::Result<Option<Wrap<Arc<[HistoryEvent]>>>> {
         let action = Self::get_action(tx, index)
             .with_context(|| format!("Failed to get action for transaction: {tx:?}"))?;
         let timestamp = tx_context.timestamp.0;
@@ -281,8 +493,12 @@ impl ContractHandler<Wrap<Vec<HistoryEvent>>> for TokenHistory {
                 ));
             }
         }
+        if let Some(blob) = tx.blobs.get(index.0) {
+            let touched: Vec<Identity> = events.iter().map(|e| e.account.clone()).collect();
+            self.bound_memory(&blob.contract_name, &touched);
+        }
         if !events.is_empty() {
-            Ok(Some(Wrap(events)))
+            Ok(Some(Wrap(events.into())))
         } else {
             Ok(None)
         }
@@ -308,26 +524,160 @@ struct HistoryResponse {
 pub async fn get_history(
     Path(account): Path<Identity>,
     State(state): State<ContractHandlerStore<TokenHistory>>,
+) -> Result<impl IntoResponse, AppError> {
+    let (contract_name, hot) = {
+        let store = state.read().await;
+        let state = store.state.as_ref().ok_or(AppError(
+            StatusCode::NOT_FOUND,
+            anyhow!("Contract '{}' not found", store.contract_name),
+        ))?;
+        (
+            store.contract_name.clone(),
+            state.history.get(&account).cloned().unwrap_or_default(),
+        )
+    };
+
+    let spilled = match LIMITS.get() {
+        Some(limits) => {
+            let path = spill_path(limits, &contract_name, &account);
+            tokio::task::spawn_blocking(move || read_spilled(&path))
+                .await
+                .context("reading spilled history")??
+        }
+        None => vec![],
+    };
+
+    let mut history: Vec<TransactionDetails> = hot.into_iter().chain(spilled).collect();
+    if history.is_empty() {
+        return Err(AppError(
+            StatusCode::NOT_FOUND,
+            anyhow!("No history found for account '{account}'"),
+        ));
+    }
+    // Pending entries stay in memory while newer settled ones may be spilled.
+    history.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
+    history.truncate(HISTORY_LEN);
+
+    Ok(Json(HistoryResponse {
+        account: account.0.clone(),
+        history,
+    }))
+}
+
+#[derive(Serialize, ToSchema)]
+struct Allowance {
+    spender: String,
+    amount: u128,
+}
+
+#[derive(Serialize, ToSchema)]
+struct AllowancesResponse {
+    account: String,
+    allowances: Vec<Allowance>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/allowances/{account}",
+    params(
+        ("account" = String, Path, description = "Account")
+    ),
+    tag = "Contract",
+    responses(
+        (status = OK, description = "Current approvals granted by the account", body = AllowancesResponse)
+    )
+)]
+pub async fn get_allowances(
+    Path(account): Path<Identity>,
+    State(state): State<ContractHandlerStore<TokenHistory>>,
 ) -> Result<impl IntoResponse, AppError> {
     let store = state.read().await;
-    let state = store.state.clone().ok_or(AppError(
+    let state = store.state.as_ref().ok_or(AppError(
         StatusCode::NOT_FOUND,
         anyhow!("Contract '{}' not found", store.contract_name),
     ))?;
 
-    state
-        .history
+    let allowances = state
+        .token
+        .get_state()
         .get(&account)
-        .cloned()
-        .map(|history: VecDeque<TransactionDetails>| HistoryResponse {
-            account: account.0.clone(),
-            history: history.into_iter().collect(),
-        })
-        .map(Json)
-        .ok_or_else(|| {
-            AppError(
-                StatusCode::NOT_FOUND,
-                anyhow!("No history found for account '{account}'"),
-            )
+        .map(|acc| {
+            acc.allowances
+                .iter()
+                .filter(|(_, amount)| **amount > 0)
+                .map(|(spender, amount)| Allowance {
+                    spender: spender.0.clone(),
+                    amount: *amount,
+                })
+                .collect()
         })
+        .unwrap_or_default();
+
+    Ok(Json(AllowancesResponse {
+        account: account.0,
+        allowances,
+    }))
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct ApproveRequest {
+    /// Wallet account name, without the `@wallet` suffix.
+    account: String,
+    spender: String,
+    /// Use 0 to revoke the allowance.
+    amount: u128,
+}
+
+#[derive(Serialize, ToSchema)]
+struct UnsignedTransaction {
+    identity: String,
+    nonce: u128,
+    /// Blobs to send after a secp256k1 blob signing `nonce` with one of the account's session
+    /// keys.
+    #[schema(value_type = Vec<Object>)]
+    blobs: Vec<Blob>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/approve",
+    tag = "Contract",
+    request_body = ApproveRequest,
+    responses(
+        (status = OK, description = "Approve (or revoke, with a zero amount) transaction to sign with a session key", body = UnsignedTransaction)
+    )
+)]
+pub async fn build_approve(
+    State(state): State<ContractHandlerStore<TokenHistory>>,
+    Json(request): Json<ApproveRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    let contract_name = state.read().await.contract_name.clone();
+    let identity = Identity::new(format!("{}@wallet", request.account));
+    let nonce = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or_default();
+
+    let approve = SmtTokenAction::Approve {
+        owner: identity.clone(),
+        spender: Identity::new(request.spender),
+        amount: request.amount,
+    };
+    let blobs = vec![
+        WalletAction::UseSessionKey {
+            account: request.account,
+            nonce,
+        }
+        .as_blob("wallet".into()),
+        Blob {
+            contract_name,
+            data: sdk::BlobData(borsh::to_vec(&approve).context("encoding approve action")?),
+        },
+    ];
+
+    Ok(Json(UnsignedTransaction {
+        identity: identity.0,
+        nonce,
+        blobs,
+    }))
 }
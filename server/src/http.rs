@@ -0,0 +1,175 @@
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use axum::{
+    body::Body,
+    extract::{MatchedPath, Request, State},
+    http::{header, HeaderName, HeaderValue, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    Router,
+};
+use opentelemetry::{metrics::Histogram, KeyValue};
+use tower_http::{set_header::SetResponseHeaderLayer, timeout::TimeoutLayer};
+
+use crate::conf::HttpConf;
+use crate::correlation::{with_correlation, CorrelationId};
+
+/// Applies the body limits, request timeout, security headers of `conf`, latency tracking and
+/// correlation ids to every route of `router`. Routes added afterwards are left as they are.
+pub fn harden(router: Router, conf: &HttpConf) -> Router {
+    let mut router = router.layer(middleware::from_fn_with_state(
+        Arc::new(conf.body_limits.clone()),
+        limit_body,
+    ));
+    if conf.request_timeout_secs > 0 {
+        router = router.layer(TimeoutLayer::new(Duration::from_secs(
+            conf.request_timeout_secs,
+        )));
+    }
+    if conf.security_headers {
+        for (name, value) in security_headers(conf) {
+            router = router.layer(SetResponseHeaderLayer::if_not_present(name, value));
+        }
+    }
+    // Outside the timeout, so requests answered with 408 are measured too.
+    router = router.layer(middleware::from_fn_with_state(
+        Arc::new(LatencyTracker::new(conf)),
+        track_latency,
+    ));
+    // Outermost, so responses rejected by the layers above carry the id too.
+    with_correlation(router)
+}
+
+fn security_headers(conf: &HttpConf) -> Vec<(HeaderName, HeaderValue)> {
+    let mut headers = vec![
+        (
+            header::X_CONTENT_TYPE_OPTIONS,
+            HeaderValue::from_static("nosniff"),
+        ),
+        (header::X_FRAME_OPTIONS, HeaderValue::from_static("DENY")),
+        (
+            header::REFERRER_POLICY,
+            HeaderValue::from_static("no-referrer"),
+        ),
+        // The server only serves JSON, nothing it returns should load resources or be framed.
+        (
+            header::CONTENT_SECURITY_POLICY,
+            HeaderValue::from_static("default-src 'none'; frame-ancestors 'none'"),
+        ),
+    ];
+    if conf.hsts_max_age_secs > 0 {
+        headers.push((
+            header::STRICT_TRANSPORT_SECURITY,
+            HeaderValue::from_str(&format!(
+                "max-age={}; includeSubDomains",
+                conf.hsts_max_age_secs
+            ))
+            .expect("valid header value"),
+        ));
+    }
+    headers
+}
+
+/// Value of the longest route prefix matching `path`, if any.
+fn by_route<T: Copy>(values: &HashMap<String, T>, path: &str) -> Option<T> {
+    values
+        .iter()
+        .filter(|(prefix, _)| path.starts_with(prefix.as_str()))
+        .max_by_key(|(prefix, _)| prefix.len())
+        .map(|(_, value)| *value)
+}
+
+async fn limit_body(
+    State(limits): State<Arc<HashMap<String, usize>>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(limit) = by_route(&limits, request.uri().path()) else {
+        return next.run(request).await;
+    };
+    let declared = request
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<usize>().ok());
+    if declared.is_some_and(|len| len > limit) {
+        return StatusCode::PAYLOAD_TOO_LARGE.into_response();
+    }
+
+    // Chunked bodies don't declare their length, so buffer up to the limit.
+    let (parts, body) = request.into_parts();
+    match axum::body::to_bytes(body, limit).await {
+        Ok(bytes) => {
+            next.run(Request::from_parts(parts, Body::from(bytes)))
+                .await
+        }
+        Err(_) => StatusCode::PAYLOAD_TOO_LARGE.into_response(),
+    }
+}
+
+/// Records the latency of every request by route, and logs the slow ones.
+struct LatencyTracker {
+    slow_request_ms: u64,
+    slow_request_ms_by_route: HashMap<String, u64>,
+    duration: Histogram<f64>,
+}
+
+impl LatencyTracker {
+    fn new(conf: &HttpConf) -> Self {
+        Self {
+            slow_request_ms: conf.slow_request_ms,
+            slow_request_ms_by_route: conf.slow_request_ms_by_route.clone(),
+            duration: opentelemetry::global::meter("wallet_http")
+                .f64_histogram("http_request_duration_seconds")
+                .with_description("Time spent answering REST requests and WebSocket upgrades")
+                .build(),
+        }
+    }
+}
+
+async fn track_latency(
+    State(tracker): State<Arc<LatencyTracker>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    // The route template rather than the path, so ids in paths don't explode the labels.
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+    let method = request.method().clone();
+    let started = Instant::now();
+    let response = next.run(request).await;
+    let elapsed = started.elapsed();
+
+    let status = response.status();
+    tracker.duration.record(
+        elapsed.as_secs_f64(),
+        &[
+            KeyValue::new("route", route.clone()),
+            KeyValue::new("method", method.to_string()),
+            KeyValue::new("status", i64::from(status.as_u16())),
+        ],
+    );
+
+    let threshold =
+        by_route(&tracker.slow_request_ms_by_route, &route).unwrap_or(tracker.slow_request_ms);
+    if threshold > 0 && elapsed.as_millis() >= u128::from(threshold) {
+        let correlation_id = CorrelationId::current().map(|id| id.0).unwrap_or_default();
+        tracing::warn!(
+            route = %route,
+            method = %method,
+            status = status.as_u16(),
+            duration_ms = elapsed.as_millis() as u64,
+            threshold_ms = threshold,
+            correlation_id = %correlation_id,
+            "Slow request"
+        );
+    }
+    response
+}
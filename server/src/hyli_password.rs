@@ -0,0 +1,262 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    routing::{get, post},
+    Json, Router,
+};
+use client_sdk::{rest_client::NodeApiClient, AppError};
+use hyli_modules::{
+    bus::SharedMessageBus,
+    module_bus_client, module_handle_messages,
+    modules::{contract_state_indexer::CSIBusEvent, Module},
+};
+use sdk::{Blob, BlobData, BlobTransaction, ContractName};
+use serde::{Deserialize, Serialize};
+use server::audit::{AuditKind, AuditRecorder};
+use server::correlation::send_tx_blob;
+use server::secrets::DEFAULT_HYLI_PASSWORD;
+use wallet::{
+    client::{
+        indexer::{SharedWalletEvent, TxOutcome},
+        tx_executor_handler::password_hash,
+    },
+    AuthMethod, WalletAction,
+};
+
+/// Bootstrap account created with the contract, authenticated by the configured hyli password.
+const HYLI_ACCOUNT: &str = "hyli";
+const MIN_PASSWORD_LEN: usize = 12;
+
+/// Rotates the password of the `hyli` account of a wallet, which starts out as the
+/// configured `hyli_password`.
+///
+/// The `UpdateAuthMethod` transaction is authenticated with the current password through a
+/// `check_secret` blob. Its proof must come from the `check_secret` tooling with the current
+/// password, as for any password login; the wallet blob is proven by the wallet autoprover. The
+/// outcome is reported once the transaction settles, after which `secrets.hyli_password` should
+/// hold the new password.
+pub struct HyliPassword {
+    wallet_cn: ContractName,
+    node: Arc<dyn NodeApiClient + Send + Sync>,
+    wallet_auto_prove: bool,
+    audit: AuditRecorder,
+    rotations: Mutex<HashMap<String, Rotation>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Rotation {
+    pub tx_hash: String,
+    pub identity: String,
+    /// Index of the `check_secret` blob to prove with the current password.
+    pub check_secret_blob_index: usize,
+    pub tx_blob_count: usize,
+    /// `None` until the transaction settles or times out.
+    pub outcome: Option<TxOutcome>,
+    pub program_outputs: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct RotateRequest {
+    current_password: String,
+    new_password: String,
+}
+
+impl HyliPassword {
+    pub fn new(
+        wallet_cn: ContractName,
+        node: Arc<dyn NodeApiClient + Send + Sync>,
+        wallet_auto_prove: bool,
+        audit: AuditRecorder,
+    ) -> Self {
+        Self {
+            wallet_cn,
+            node,
+            wallet_auto_prove,
+            audit,
+            rotations: Mutex::new(HashMap::new()),
+        }
+    }
+
+    async fn rotate(&self, request: &RotateRequest) -> Result<Rotation> {
+        if !self.wallet_auto_prove {
+            bail!("The wallet autoprover is disabled, nothing would prove the rotation");
+        }
+        if request.new_password.len() < MIN_PASSWORD_LEN
+            || request.new_password == DEFAULT_HYLI_PASSWORD
+        {
+            bail!("The new password must have at least {MIN_PASSWORD_LEN} characters");
+        }
+
+        let identity = format!("{HYLI_ACCOUNT}@{}", self.wallet_cn.0);
+        let (current_hash, nonce) = {
+            let store = wallet::client::indexer::store(&self.wallet_cn)
+                .ok_or_else(|| anyhow!("Wallet {} is not indexed", self.wallet_cn))?;
+            let store = store.read().await;
+            let wallet = store
+                .state
+                .as_ref()
+                .ok_or_else(|| anyhow!("Wallet {} has no state yet", self.wallet_cn))?;
+            let account = HYLI_ACCOUNT.to_string();
+            let info = wallet.get(&account)?;
+            let AuthMethod::Password { hash } = info.auth_method else {
+                bail!("The {HYLI_ACCOUNT} account is not authenticated by a password");
+            };
+            let salt = wallet.get_salt(&account)?;
+            if password_hash(&identity, &request.current_password, &salt) != hash {
+                bail!("Invalid current password");
+            }
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_millis())
+                .unwrap_or_default();
+            // Nonces must increase, and the frontend uses the current time.
+            (hash, now.max(info.nonce + 1))
+        };
+
+        let salt = hex::encode(rand::random::<[u8; 16]>());
+        let tx = BlobTransaction::new(
+            identity.clone(),
+            vec![
+                Blob {
+                    contract_name: ContractName::new("check_secret"),
+                    data: BlobData(hex::decode(&current_hash).context("decoding password hash")?),
+                },
+                WalletAction::UpdateAuthMethod {
+                    account: HYLI_ACCOUNT.to_string(),
+                    nonce,
+                    auth_method: AuthMethod::Password {
+                        hash: password_hash(&identity, &request.new_password, &salt),
+                    },
+                    salt,
+                }
+                .as_blob(self.wallet_cn.clone()),
+            ],
+        );
+        let tx_blob_count = tx.blobs.len();
+        let tx_hash = send_tx_blob(self.node.as_ref(), tx)
+            .await
+            .context("sending password rotation")?;
+
+        tracing::info!("🔑 Rotating the {identity} password in {tx_hash}");
+        self.audit.record(
+            AuditKind::AdminOperation,
+            "admin",
+            serde_json::json!({
+                "operation": "rotate_hyli_password",
+                "contract": self.wallet_cn.0,
+                "tx_hash": tx_hash.0,
+            }),
+        );
+        let rotation = Rotation {
+            tx_hash: tx_hash.0.clone(),
+            identity,
+            check_secret_blob_index: 0,
+            tx_blob_count,
+            outcome: None,
+            program_outputs: None,
+        };
+        self.rotations
+            .lock()
+            .expect("rotations poisoned")
+            .insert(tx_hash.0, rotation.clone());
+        Ok(rotation)
+    }
+
+    fn settle(&self, event: &SharedWalletEvent) {
+        let mut rotations = self.rotations.lock().expect("rotations poisoned");
+        let Some(rotation) = rotations.get_mut(&event.tx_hash.0) else {
+            return;
+        };
+        rotation.outcome = Some(event.outcome);
+        rotation.program_outputs = Some(event.program_outputs.clone());
+        match event.outcome {
+            TxOutcome::Success => tracing::info!(
+                "🔑 Rotated the {} password, update secrets.hyli_password",
+                rotation.identity
+            ),
+            outcome => tracing::warn!(
+                "Rotating the {} password ended with {outcome:?}: {}",
+                rotation.identity,
+                event.program_outputs
+            ),
+        }
+    }
+}
+
+/// Follows the rotation transactions until they settle.
+pub struct HyliPasswordModule {
+    bus: HyliPasswordModuleBusClient,
+    hyli_password: Arc<HyliPassword>,
+}
+
+module_bus_client! {
+#[derive(Debug)]
+pub struct HyliPasswordModuleBusClient {
+    receiver(CSIBusEvent<SharedWalletEvent>),
+}
+}
+
+impl Module for HyliPasswordModule {
+    type Context = Arc<HyliPassword>;
+
+    async fn build(bus: SharedMessageBus, ctx: Self::Context) -> Result<Self> {
+        Ok(Self {
+            bus: HyliPasswordModuleBusClient::new_from_bus(bus.new_handle()).await,
+            hyli_password: ctx,
+        })
+    }
+
+    async fn run(&mut self) -> Result<()> {
+        module_handle_messages! {
+            on_self self,
+            listen<CSIBusEvent<SharedWalletEvent>> event => {
+                self.hyli_password.settle(&event.event);
+            }
+        };
+        Ok(())
+    }
+}
+
+// --------------------------------------------------------
+//     Routes
+// --------------------------------------------------------
+
+/// Admin routes starting a rotation of the hyli password and reporting its settlement.
+pub fn hyli_password_admin_router(hyli_password: Arc<HyliPassword>) -> Router {
+    Router::new()
+        .route("/hyli/password", post(rotate_password))
+        .route("/hyli/password/{tx_hash}", get(get_rotation))
+        .with_state(hyli_password)
+}
+
+async fn rotate_password(
+    State(hyli_password): State<Arc<HyliPassword>>,
+    Json(request): Json<RotateRequest>,
+) -> Result<Json<Rotation>, AppError> {
+    hyli_password
+        .rotate(&request)
+        .await
+        .map(Json)
+        .map_err(|e| AppError(StatusCode::BAD_REQUEST, e))
+}
+
+async fn get_rotation(
+    State(hyli_password): State<Arc<HyliPassword>>,
+    Path(tx_hash): Path<String>,
+) -> Result<Json<Rotation>, AppError> {
+    hyli_password
+        .rotations
+        .lock()
+        .expect("rotations poisoned")
+        .get(&tx_hash)
+        .cloned()
+        .map(Json)
+        .ok_or_else(|| AppError(StatusCode::NOT_FOUND, anyhow!("No rotation in {tx_hash}")))
+}
@@ -0,0 +1,190 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result};
+use axum::{extract::State, routing::get, Json, Router};
+use client_sdk::rest_client::NodeApiClient;
+use hyli_modules::{
+    bus::SharedMessageBus, module_bus_client, module_handle_messages, modules::Module,
+};
+use opentelemetry::{metrics::Counter, KeyValue};
+use sdk::ContractName;
+use serde::Serialize;
+use server::conf::InvariantsConf;
+use wallet::client::indexer::CommitmentResponse;
+
+/// Result of the last comparison for one wallet contract, exposed on the admin API.
+#[derive(Debug, Clone, Serialize)]
+pub struct InvariantStatus {
+    pub contract_name: String,
+    pub indexer_commitment: String,
+    pub onchain_commitment: String,
+    /// Both commitments were stable across two checks yet different.
+    pub diverged: bool,
+    pub checked_at: u64,
+}
+
+pub type InvariantStatuses = Arc<Mutex<BTreeMap<ContractName, InvariantStatus>>>;
+
+/// Periodically compares the commitment of each indexed wallet with the one the node reports.
+///
+/// The indexer trails the node while transactions settle, so a mismatch is only reported once
+/// neither side moved since the previous check.
+pub struct InvariantChecker {
+    #[allow(unused)]
+    bus: InvariantCheckerBusClient,
+    ctx: InvariantCheckerCtx,
+    http: reqwest::Client,
+    checks: Counter<u64>,
+    divergences: Counter<u64>,
+}
+
+pub struct InvariantCheckerCtx {
+    pub conf: InvariantsConf,
+    pub wallet_cns: Vec<ContractName>,
+    pub node: Arc<dyn NodeApiClient + Send + Sync>,
+    /// Base URL of this server's REST API, serving the indexer routes.
+    pub indexer_url: String,
+    pub statuses: InvariantStatuses,
+}
+
+module_bus_client! {
+#[derive(Debug)]
+pub struct InvariantCheckerBusClient {
+}
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+impl InvariantChecker {
+    async fn indexer_commitment(&self, contract_name: &ContractName) -> Result<String> {
+        let response: CommitmentResponse = self
+            .http
+            .get(format!(
+                "{}/v1/indexer/contract/{}/commitment",
+                self.ctx.indexer_url, contract_name.0
+            ))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(response.commitment)
+    }
+
+    async fn check(&self, contract_name: &ContractName) -> Result<()> {
+        let indexer_commitment = self
+            .indexer_commitment(contract_name)
+            .await
+            .context("fetching indexed commitment")?;
+        let onchain_commitment = hex::encode(
+            self.ctx
+                .node
+                .get_contract(contract_name.clone())
+                .await
+                .context("fetching on-chain commitment")?
+                .state
+                .0,
+        );
+
+        let attributes = [KeyValue::new("contract", contract_name.0.clone())];
+        self.checks.add(1, &attributes);
+
+        let mut statuses = self
+            .ctx
+            .statuses
+            .lock()
+            .expect("invariant statuses poisoned");
+        let stable = statuses.get(contract_name).is_some_and(|previous| {
+            previous.indexer_commitment == indexer_commitment
+                && previous.onchain_commitment == onchain_commitment
+        });
+        let diverged = stable && indexer_commitment != onchain_commitment;
+        if diverged {
+            self.divergences.add(1, &attributes);
+            tracing::error!(
+                "🚨 Indexed state of {} diverged from the chain: indexer {} vs on-chain {}",
+                contract_name,
+                indexer_commitment,
+                onchain_commitment
+            );
+        }
+        statuses.insert(
+            contract_name.clone(),
+            InvariantStatus {
+                contract_name: contract_name.0.clone(),
+                indexer_commitment,
+                onchain_commitment,
+                diverged,
+                checked_at: now_secs(),
+            },
+        );
+        Ok(())
+    }
+}
+
+impl Module for InvariantChecker {
+    type Context = InvariantCheckerCtx;
+
+    async fn build(bus: SharedMessageBus, ctx: Self::Context) -> Result<Self> {
+        let meter = opentelemetry::global::meter("wallet_invariants");
+        Ok(Self {
+            bus: InvariantCheckerBusClient::new_from_bus(bus.new_handle()).await,
+            ctx,
+            http: reqwest::Client::new(),
+            checks: meter
+                .u64_counter("invariant_checks_total")
+                .with_description("Number of indexed vs on-chain commitment comparisons")
+                .build(),
+            divergences: meter
+                .u64_counter("invariant_divergences_total")
+                .with_description(
+                    "Number of checks where a stable indexed state differed from the chain",
+                )
+                .build(),
+        })
+    }
+
+    async fn run(&mut self) -> Result<()> {
+        let mut interval =
+            tokio::time::interval(Duration::from_secs(self.ctx.conf.check_interval_secs));
+
+        module_handle_messages! {
+            on_self self,
+            _ = interval.tick() => {
+                for contract_name in &self.ctx.wallet_cns {
+                    if let Err(e) = self.check(contract_name).await {
+                        tracing::warn!("Invariant check for {} failed: {:#}", contract_name, e);
+                    }
+                }
+            }
+        };
+        Ok(())
+    }
+}
+
+/// Admin route listing the last comparison of each wallet, to decide whether to reindex.
+pub fn invariants_admin_router(statuses: InvariantStatuses) -> Router {
+    Router::new()
+        .route("/invariants", get(get_invariants))
+        .with_state(statuses)
+}
+
+async fn get_invariants(State(statuses): State<InvariantStatuses>) -> Json<Vec<InvariantStatus>> {
+    Json(
+        statuses
+            .lock()
+            .expect("invariant statuses poisoned")
+            .values()
+            .cloned()
+            .collect(),
+    )
+}
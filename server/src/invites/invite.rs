@@ -7,15 +7,28 @@ use hyli_modules::modules::BuildApiContextInner;
 use hyli_modules::{
     bus::SharedMessageBus, module_bus_client, module_handle_messages, modules::Module,
 };
+use opentelemetry::{
+    metrics::{Counter, Gauge},
+    KeyValue,
+};
 use sdk::verifiers::Secp256k1Blob;
 use sdk::{Blob, Identity};
-use secp256k1::{Message, PublicKey, Secp256k1, SecretKey};
+use secp256k1::{All, Message, PublicKey, Secp256k1, SecretKey};
 use serde::{Deserialize, Serialize};
+use server::audit::{AuditKind, AuditRecorder};
+use server::conf::DatabaseConf;
+use server::db::DbPools;
+use server::secrets::DEFAULT_INVITE_CODE_PKEY;
 use sha2::{Digest, Sha256};
-use sqlx::{postgres::PgPoolOptions, FromRow, Pool};
-use std::env;
-use std::sync::Arc;
+use sqlx::{FromRow, Pool};
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
 use std::time::Duration;
+use tokio::sync::{OwnedRwLockWriteGuard, RwLock, RwLockReadGuard};
+
+use crate::stats::StatsCounters;
 
 #[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
 pub struct InviteCode {
@@ -33,6 +46,11 @@ pub struct ConsumeInviteBody {
 
 impl InviteModuleInner {
     async fn consume_invite(&self, code: &str, wallet: &str) -> Result<Blob> {
+        // Taken first so that codes aren't consumed while a rotation is settling.
+        let invite_key = self
+            .invite_key
+            .current()
+            .inspect_err(|_| self.metrics.consumed("key_rotating"))?;
         let invite: Option<InviteCode> = log_error!(
             sqlx::query_as(
                 "
@@ -52,31 +70,34 @@ impl InviteModuleInner {
             .fetch_optional(&self.pool)
             .await,
             "SQL query failed"
-        )?;
+        )
+        .inspect_err(|_| self.metrics.consumed("error"))?;
 
         if invite.is_none() {
+            self.metrics.consumed("invalid");
             return Err(anyhow::anyhow!("Invite code not found or already used"));
         }
+        self.metrics.consumed("consumed");
 
         tracing::info!("Invite code consumed: {}", code);
+        self.audit.record(
+            AuditKind::InviteIssued,
+            wallet,
+            serde_json::json!({ "code": code }),
+        );
         // Let's create a secp2561k1 blob signing the data
         let identity = Identity::new(format!("{wallet}@wallet"));
         let data = format!("Invite - {code} for {wallet}");
-        let mut hasher = Sha256::new();
-        hasher.update(data.clone());
-        let message_hash: [u8; 32] = hasher.finalize().into();
-        let signature = self
-            .crypto
-            .secp
-            .sign_ecdsa(Message::from_digest(message_hash), &self.crypto.secret_key);
+        invite_key.sign(identity, &data)
+    }
 
-        Ok(Secp256k1Blob::new(
-            identity,
-            data.as_bytes(),
-            &self.crypto.public_key.to_string(),
-            &signature.to_string(),
-        )?
-        .as_blob())
+    async fn record_available(&self) -> Result<()> {
+        let available: i64 =
+            sqlx::query_scalar("SELECT COUNT(*) FROM invite_codes WHERE used_at IS NULL")
+                .fetch_one(&self.pool)
+                .await?;
+        self.metrics.record_available(available as u64);
+        Ok(())
     }
 }
 
@@ -93,27 +114,167 @@ async fn route_consume_invite(
     }
 }
 
-pub struct CryptoContext {
-    pub secp: secp256k1::Secp256k1<secp256k1::All>,
-    pub secret_key: secp256k1::SecretKey,
-    pub public_key: secp256k1::PublicKey,
+/// The operator's invite code key, shared by everything signing with it. Signing is refused
+/// while a rotation settles, then resumes with the key the contract ends up trusting.
+pub struct InviteKey {
+    secp: Secp256k1<All>,
+    secret_key: Arc<RwLock<SecretKey>>,
+}
+
+/// Read access to the current invite code key.
+pub struct InviteKeyGuard<'a> {
+    key: &'a InviteKey,
+    secret_key: RwLockReadGuard<'a, SecretKey>,
+}
+
+impl InviteKey {
+    pub fn new(secret_key: SecretKey) -> Self {
+        Self {
+            secp: Secp256k1::new(),
+            secret_key: Arc::new(RwLock::new(secret_key)),
+        }
+    }
+
+    /// The current key, or an error while a rotation is settling.
+    pub fn current(&self) -> Result<InviteKeyGuard<'_>> {
+        let secret_key = self
+            .secret_key
+            .try_read()
+            .map_err(|_| anyhow::anyhow!("The invite code key is being rotated, retry shortly"))?;
+        Ok(InviteKeyGuard {
+            key: self,
+            secret_key,
+        })
+    }
+
+    /// The current key, once any rotation has settled.
+    pub async fn settled(&self) -> InviteKeyGuard<'_> {
+        InviteKeyGuard {
+            key: self,
+            secret_key: self.secret_key.read().await,
+        }
+    }
+
+    /// Exclusive access to the key, held by a rotation until it settles.
+    pub async fn lock(&self) -> OwnedRwLockWriteGuard<SecretKey> {
+        self.secret_key.clone().write_owned().await
+    }
+
+    pub fn public_key(&self, secret_key: &SecretKey) -> PublicKey {
+        PublicKey::from_secret_key(&self.secp, secret_key)
+    }
+
+    /// Secp256k1 blob of `data` signed with `secret_key`, as the contract checks operator
+    /// signatures.
+    pub fn sign_with(
+        &self,
+        secret_key: &SecretKey,
+        identity: Identity,
+        data: &str,
+    ) -> Result<Blob> {
+        let digest: [u8; 32] = Sha256::digest(data.as_bytes()).into();
+        let signature = self
+            .secp
+            .sign_ecdsa(Message::from_digest(digest), secret_key);
+        Ok(Secp256k1Blob::new(
+            identity,
+            data.as_bytes(),
+            &self.public_key(secret_key).to_string(),
+            &signature.to_string(),
+        )?
+        .as_blob())
+    }
 }
 
+impl InviteKeyGuard<'_> {
+    pub fn public_key(&self) -> PublicKey {
+        self.key.public_key(&self.secret_key)
+    }
+
+    pub fn sign(&self, identity: Identity, data: &str) -> Result<Blob> {
+        self.key.sign_with(&self.secret_key, identity, data)
+    }
+}
+
+impl Drop for InviteKey {
+    fn drop(&mut self) {
+        if let Some(secret_key) = Arc::get_mut(&mut self.secret_key) {
+            secret_key.get_mut().non_secure_erase();
+        }
+    }
+}
+
+/// How often the available invite codes are counted.
+const INVITE_METRICS_INTERVAL: Duration = Duration::from_secs(60);
+
 pub struct InviteModule {
     pub bus: InviteModuleBusClient,
-    #[allow(unused)]
     pub inner: Arc<InviteModuleInner>,
 }
 
 pub struct InviteModuleInner {
     pub pool: Pool<sqlx::Postgres>,
-    pub crypto: CryptoContext,
+    pub invite_key: Arc<InviteKey>,
+    pub audit: AuditRecorder,
+    metrics: InviteMetrics,
+}
+
+/// Counts the invite code consumptions by outcome, and the codes left to hand out.
+struct InviteMetrics {
+    consumptions: Counter<u64>,
+    available: Gauge<u64>,
+    /// Shared with the tenants' invite modules, each adding its own count.
+    stats: Arc<StatsCounters>,
+    last_available: AtomicU64,
+}
+
+impl InviteMetrics {
+    fn new(stats: Arc<StatsCounters>) -> Self {
+        let meter = opentelemetry::global::meter("wallet_invites");
+        Self {
+            consumptions: meter
+                .u64_counter("invite_codes_consumptions_total")
+                .with_description("Number of invite code consumption attempts, by outcome")
+                .build(),
+            available: meter
+                .u64_gauge("invite_codes_available")
+                .with_description("Number of invite codes not used yet")
+                .build(),
+            stats,
+            last_available: AtomicU64::new(0),
+        }
+    }
+
+    fn consumed(&self, outcome: &'static str) {
+        self.consumptions
+            .add(1, &[KeyValue::new("outcome", outcome)]);
+        let counter = match outcome {
+            "consumed" => &self.stats.invites_consumed,
+            _ => &self.stats.invites_rejected,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn record_available(&self, available: u64) {
+        self.available.record(available, &[]);
+        let previous = self.last_available.swap(available, Ordering::Relaxed);
+        self.stats
+            .invites_available
+            .fetch_add(available, Ordering::Relaxed);
+        self.stats
+            .invites_available
+            .fetch_sub(previous, Ordering::Relaxed);
+    }
 }
 
 #[derive(Clone)]
 pub struct InviteModuleCtx {
     pub db_url: String,
+    pub database: DatabaseConf,
     pub api_ctx: Arc<BuildApiContextInner>,
+    pub invite_key: Arc<InviteKey>,
+    pub audit: AuditRecorder,
+    pub stats: Arc<StatsCounters>,
 }
 
 module_bus_client! {
@@ -126,11 +287,7 @@ impl Module for InviteModule {
     type Context = InviteModuleCtx;
 
     async fn build(bus: SharedMessageBus, ctx: Self::Context) -> Result<Self> {
-        let db = PgPoolOptions::new()
-            .max_connections(5)
-            .acquire_timeout(Duration::from_secs(5))
-            .connect(&ctx.db_url)
-            .await?;
+        let db = DbPools::connect(&ctx.db_url, &ctx.database).await?.primary;
 
         sqlx::query(
             r#"CREATE TABLE IF NOT EXISTS invite_codes (
@@ -142,29 +299,30 @@ impl Module for InviteModule {
         )
         .execute(&db)
         .await?;
+        // Lets the maintenance module expire the codes nobody used.
+        sqlx::query(
+            "ALTER TABLE invite_codes ADD COLUMN IF NOT EXISTS created_at TIMESTAMP NOT NULL DEFAULT NOW()",
+        )
+        .execute(&db)
+        .await?;
 
-        let secp = Secp256k1::new();
-        let secret_key = hex::decode(env::var("INVITE_CODE_PKEY").unwrap_or(
-            "0000000000000001000000000000000100000000000000010000000000000001".to_string(),
-        ))
-        .expect("INVITE_CODE_PKEY must be a hex string");
-        let secret_key = SecretKey::from_byte_array(secret_key.try_into().expect("32 bytes"))
-            .expect("32 bytes, within curve order");
-        let public_key = PublicKey::from_secret_key(&secp, &secret_key);
-
-        // If we're using the default private key, add some invite codes.
-        if hex::decode("0000000000000001000000000000000100000000000000010000000000000001").unwrap()
-            == secret_key.secret_bytes()
-        {
-            tracing::warn!("Adding default invite codes, this is not secure for production!");
-            let invite_codes = vec!["TOTO", "TOTO", "TOTO", "HYLI", "GORANGE", "vip", "vip"];
-            for code in invite_codes {
-                sqlx::query("INSERT INTO invite_codes (code) VALUES ($1)")
-                    .bind(code)
-                    .execute(&db)
-                    .await?;
+        let public_key = {
+            let invite_key = ctx.invite_key.settled().await;
+            // If we're using the default private key, add some invite codes.
+            if hex::decode(DEFAULT_INVITE_CODE_PKEY).unwrap()
+                == invite_key.secret_key.secret_bytes()
+            {
+                tracing::warn!("Adding default invite codes, this is not secure for production!");
+                let invite_codes = vec!["TOTO", "TOTO", "TOTO", "HYLI", "GORANGE", "vip", "vip"];
+                for code in invite_codes {
+                    sqlx::query("INSERT INTO invite_codes (code) VALUES ($1)")
+                        .bind(code)
+                        .execute(&db)
+                        .await?;
+                }
             }
-        }
+            invite_key.public_key()
+        };
 
         tracing::info!(
             "Invite module initialized with public key: {:?}",
@@ -173,11 +331,9 @@ impl Module for InviteModule {
 
         let inner = Arc::new(InviteModuleInner {
             pool: db,
-            crypto: CryptoContext {
-                secp,
-                secret_key,
-                public_key,
-            },
+            invite_key: ctx.invite_key.clone(),
+            audit: ctx.audit.clone(),
+            metrics: InviteMetrics::new(ctx.stats.clone()),
         });
 
         let api = Router::new().route(
@@ -198,8 +354,15 @@ impl Module for InviteModule {
     }
 
     async fn run(&mut self) -> Result<()> {
+        let mut metrics_interval = tokio::time::interval(INVITE_METRICS_INTERVAL);
         module_handle_messages! {
             on_self self,
+            _ = metrics_interval.tick() => {
+                let _ = log_error!(
+                    self.inner.record_available().await,
+                    "Counting available invite codes"
+                );
+            }
         };
         Ok(())
     }
@@ -212,30 +375,23 @@ pub struct MockInviteModule {
 }
 
 pub struct MockInviteModuleInner {
-    pub crypto: CryptoContext,
+    pub invite_key: Arc<InviteKey>,
+    pub audit: AuditRecorder,
 }
 
 impl MockInviteModuleInner {
     async fn consume_invite(&self, code: &str, wallet: &str) -> Result<Blob> {
+        let invite_key = self.invite_key.current()?;
         tracing::info!("Invite code consumed: {}", code);
+        self.audit.record(
+            AuditKind::InviteIssued,
+            wallet,
+            serde_json::json!({ "code": code, "mock": true }),
+        );
         // Let's create a secp2561k1 blob signing the data
         let identity = Identity::new(format!("{wallet}@wallet"));
         let data = format!("Invite - {code} for {wallet}");
-        let mut hasher = Sha256::new();
-        hasher.update(data.clone());
-        let message_hash: [u8; 32] = hasher.finalize().into();
-        let signature = self
-            .crypto
-            .secp
-            .sign_ecdsa(Message::from_digest(message_hash), &self.crypto.secret_key);
-
-        Ok(Secp256k1Blob::new(
-            identity,
-            data.as_bytes(),
-            &self.crypto.public_key.to_string(),
-            &signature.to_string(),
-        )?
-        .as_blob())
+        invite_key.sign(identity, &data)
     }
 }
 
@@ -256,21 +412,9 @@ impl Module for MockInviteModule {
     type Context = InviteModuleCtx;
 
     async fn build(bus: SharedMessageBus, ctx: Self::Context) -> Result<Self> {
-        let secp = Secp256k1::new();
-        let secret_key = hex::decode(env::var("INVITE_CODE_PKEY").unwrap_or(
-            "0000000000000001000000000000000100000000000000010000000000000001".to_string(),
-        ))
-        .expect("INVITE_CODE_PKEY must be a hex string");
-        let secret_key = SecretKey::from_byte_array(secret_key.try_into().expect("32 bytes"))
-            .expect("32 bytes, within curve order");
-        let public_key = PublicKey::from_secret_key(&secp, &secret_key);
-
         let inner = Arc::new(MockInviteModuleInner {
-            crypto: CryptoContext {
-                secp,
-                secret_key,
-                public_key,
-            },
+            invite_key: ctx.invite_key.clone(),
+            audit: ctx.audit.clone(),
         });
         let api = Router::new().route(
             "/api/consume_invite",
@@ -0,0 +1,310 @@
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, bail, Context, Result};
+use axum::{
+    extract::State,
+    http::StatusCode,
+    routing::{get, post},
+    Json, Router,
+};
+use client_sdk::{rest_client::NodeApiClient, AppError};
+use hyli_modules::{
+    bus::SharedMessageBus,
+    module_bus_client, module_handle_messages,
+    modules::{contract_state_indexer::CSIBusEvent, Module},
+};
+use sdk::{BlobTransaction, ContractName, Hashed, Identity};
+use secp256k1::SecretKey;
+use serde::Serialize;
+use server::audit::{AuditKind, AuditRecorder};
+use server::correlation::send_tx_blob;
+use tokio::sync::OwnedRwLockWriteGuard;
+use wallet::{
+    client::indexer::{SharedWalletEvent, TxOutcome},
+    invite_key_rotation_data, WalletAction,
+};
+
+use super::invite::InviteKey;
+
+/// Rotates the invite code key the main wallet trusts to `secrets.next_invite_code_pkey`.
+///
+/// The `UpdateInviteCodePublicKey` transaction is signed with the current key and carries the
+/// indexed SMT root, which the contract checks against its state: a transaction of the wallet
+/// settling in between makes the rotation fail, and it can be started again. No invite is handed
+/// out until the rotation settles, then they are signed with the key the contract trusts. Once it
+/// succeeded, `secrets.invite_code_pkey` should hold the new key.
+///
+/// The key is shared by every wallet served, which would have to rotate at once, so rotations
+/// are refused when there is more than one.
+pub struct InviteKeyRotation {
+    wallet_cn: ContractName,
+    /// Wallets trusting the invite code key other than the main one.
+    other_wallet_cns: Vec<ContractName>,
+    node: Arc<dyn NodeApiClient + Send + Sync>,
+    invite_key: Arc<InviteKey>,
+    next_key: Option<SecretKey>,
+    audit: AuditRecorder,
+    rotations: Mutex<Rotations>,
+}
+
+#[derive(Default)]
+struct Rotations {
+    /// The invite key, held by the rotation waiting for its transaction to settle.
+    settling: Option<(String, OwnedRwLockWriteGuard<SecretKey>)>,
+    last: Option<Rotation>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Rotation {
+    pub tx_hash: String,
+    /// Hex-encoded compressed public keys.
+    pub from: String,
+    pub to: String,
+    pub smt_root: String,
+    /// `None` until the transaction settles.
+    pub outcome: Option<TxOutcome>,
+    pub program_outputs: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct InviteKeyStatus {
+    /// `None` while a rotation is settling.
+    pub public_key: Option<String>,
+    pub next_public_key: Option<String>,
+    pub last_rotation: Option<Rotation>,
+}
+
+impl InviteKeyRotation {
+    /// `wallet_cns` are all the wallets trusting the invite code key, the main one first.
+    pub fn new(
+        mut wallet_cns: Vec<ContractName>,
+        node: Arc<dyn NodeApiClient + Send + Sync>,
+        invite_key: Arc<InviteKey>,
+        next_key: Option<SecretKey>,
+        audit: AuditRecorder,
+    ) -> Self {
+        let wallet_cn = if wallet_cns.is_empty() {
+            ContractName::default()
+        } else {
+            wallet_cns.remove(0)
+        };
+        Self {
+            wallet_cn,
+            other_wallet_cns: wallet_cns,
+            node,
+            invite_key,
+            next_key,
+            audit,
+            rotations: Mutex::new(Rotations::default()),
+        }
+    }
+
+    async fn rotate(&self) -> Result<Rotation> {
+        let next_key = self
+            .next_key
+            .ok_or_else(|| anyhow!("secrets.next_invite_code_pkey is not set"))?;
+        if !self.other_wallet_cns.is_empty() {
+            bail!(
+                "The invite code key is shared with {}, which would keep trusting the current one",
+                self.other_wallet_cns
+                    .iter()
+                    .map(|cn| cn.0.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+        if self
+            .rotations
+            .lock()
+            .expect("rotations poisoned")
+            .settling
+            .is_some()
+        {
+            bail!("A rotation is already settling");
+        }
+
+        // Waits for the invites being signed, then holds the next ones until settlement.
+        let current_key = self.invite_key.lock().await;
+        let from = self.invite_key.public_key(&current_key).serialize();
+        let to = self.invite_key.public_key(&next_key).serialize();
+        if from == to {
+            bail!("The invite code key is already the next one");
+        }
+        let smt_root = {
+            let store = wallet::client::indexer::store(&self.wallet_cn)
+                .ok_or_else(|| anyhow!("Wallet {} is not indexed", self.wallet_cn))?;
+            let store = store.read().await;
+            let wallet = store
+                .state
+                .as_ref()
+                .ok_or_else(|| anyhow!("Wallet {} has no state yet", self.wallet_cn))?;
+            if wallet.invite_code_public_key() != from {
+                bail!(
+                    "Wallet {} doesn't trust the current invite code key",
+                    self.wallet_cn
+                );
+            }
+            wallet.get_smt_root()
+        };
+
+        let identity = Identity::new(format!("hyli@{}", self.wallet_cn.0));
+        let data = invite_key_rotation_data(&to, &smt_root);
+        let tx = BlobTransaction::new(
+            identity.clone(),
+            vec![
+                WalletAction::UpdateInviteCodePublicKey {
+                    invite_code_public_key: to,
+                    smt_root,
+                }
+                .as_blob(self.wallet_cn.clone()),
+                self.invite_key.sign_with(&current_key, identity, &data)?,
+            ],
+        );
+        let tx_hash = tx.hashed();
+        let rotation = Rotation {
+            tx_hash: tx_hash.0.clone(),
+            from: hex::encode(from),
+            to: hex::encode(to),
+            smt_root: hex::encode(smt_root),
+            outcome: None,
+            program_outputs: None,
+        };
+        // Recorded before sending so the settlement can't be missed.
+        let previous = {
+            let mut rotations = self.rotations.lock().expect("rotations poisoned");
+            rotations.settling = Some((tx_hash.0.clone(), current_key));
+            rotations.last.replace(rotation.clone())
+        };
+        if let Err(e) = send_tx_blob(self.node.as_ref(), tx).await {
+            let mut rotations = self.rotations.lock().expect("rotations poisoned");
+            rotations.settling = None;
+            rotations.last = previous;
+            return Err(e.context("sending invite key rotation"));
+        }
+
+        tracing::info!(
+            "🔑 Rotating the invite code key of {} in {tx_hash}",
+            self.wallet_cn
+        );
+        self.audit.record(
+            AuditKind::AdminOperation,
+            "admin",
+            serde_json::json!({
+                "operation": "rotate_invite_key",
+                "contract": self.wallet_cn.0,
+                "tx_hash": rotation.tx_hash,
+                "from": rotation.from,
+                "to": rotation.to,
+            }),
+        );
+        Ok(rotation)
+    }
+
+    /// Switches to the next key if the rotation succeeded, and releases the invite key.
+    fn settle(&self, event: &SharedWalletEvent) {
+        let mut rotations = self.rotations.lock().expect("rotations poisoned");
+        let Some((_, mut current_key)) = rotations
+            .settling
+            .take_if(|(tx_hash, _)| *tx_hash == event.tx_hash.0)
+        else {
+            return;
+        };
+        if let Some(rotation) = &mut rotations.last {
+            rotation.outcome = Some(event.outcome);
+            rotation.program_outputs = Some(event.program_outputs.clone());
+        }
+        match (event.outcome, self.next_key) {
+            (TxOutcome::Success, Some(next_key)) => {
+                *current_key = next_key;
+                tracing::info!(
+                    "🔑 Rotated the invite code key of {}, update secrets.invite_code_pkey",
+                    self.wallet_cn
+                );
+            }
+            (outcome, _) => tracing::warn!(
+                "Rotating the invite code key of {} ended with {outcome:?}, keeping the current one: {}",
+                self.wallet_cn,
+                event.program_outputs
+            ),
+        }
+    }
+
+    fn status(&self) -> InviteKeyStatus {
+        InviteKeyStatus {
+            public_key: self
+                .invite_key
+                .current()
+                .ok()
+                .map(|key| hex::encode(key.public_key().serialize())),
+            next_public_key: self
+                .next_key
+                .map(|key| hex::encode(self.invite_key.public_key(&key).serialize())),
+            last_rotation: self
+                .rotations
+                .lock()
+                .expect("rotations poisoned")
+                .last
+                .clone(),
+        }
+    }
+}
+
+/// Follows the rotation transactions until they settle.
+pub struct InviteKeyRotationModule {
+    bus: InviteKeyRotationModuleBusClient,
+    rotation: Arc<InviteKeyRotation>,
+}
+
+module_bus_client! {
+#[derive(Debug)]
+pub struct InviteKeyRotationModuleBusClient {
+    receiver(CSIBusEvent<SharedWalletEvent>),
+}
+}
+
+impl Module for InviteKeyRotationModule {
+    type Context = Arc<InviteKeyRotation>;
+
+    async fn build(bus: SharedMessageBus, ctx: Self::Context) -> Result<Self> {
+        Ok(Self {
+            bus: InviteKeyRotationModuleBusClient::new_from_bus(bus.new_handle()).await,
+            rotation: ctx,
+        })
+    }
+
+    async fn run(&mut self) -> Result<()> {
+        module_handle_messages! {
+            on_self self,
+            listen<CSIBusEvent<SharedWalletEvent>> event => {
+                self.rotation.settle(&event.event);
+            }
+        };
+        Ok(())
+    }
+}
+
+// --------------------------------------------------------
+//     Routes
+// --------------------------------------------------------
+
+/// Admin routes starting a rotation of the invite code key and reporting its settlement.
+pub fn invite_key_admin_router(rotation: Arc<InviteKeyRotation>) -> Router {
+    Router::new()
+        .route("/invites/key", get(get_invite_key))
+        .route("/invites/key/rotate", post(rotate_invite_key))
+        .with_state(rotation)
+}
+
+async fn get_invite_key(State(rotation): State<Arc<InviteKeyRotation>>) -> Json<InviteKeyStatus> {
+    Json(rotation.status())
+}
+
+async fn rotate_invite_key(
+    State(rotation): State<Arc<InviteKeyRotation>>,
+) -> Result<Json<Rotation>, AppError> {
+    rotation
+        .rotate()
+        .await
+        .map(Json)
+        .map_err(|e| AppError(StatusCode::BAD_REQUEST, e))
+}
@@ -1,23 +1,30 @@
-use std::env;
-
-use secp256k1::{PublicKey, Secp256k1, SecretKey};
+use secp256k1::{PublicKey, Secp256k1};
 use wallet::client::tx_executor_handler::{Wallet, WalletConstructor};
 
+use crate::secrets::Secrets;
+
+pub mod audit;
+pub mod autoprovers;
+pub mod backup;
 pub mod conf;
+pub mod correlation;
+pub mod db;
+pub mod deep_link;
+pub mod feature_flags;
+pub mod http;
+pub mod proof_submission;
+pub mod provers;
+pub mod secrets;
+pub mod telemetry;
 
-pub fn new_wallet(contract_name: &sdk::ContractName) -> (WalletConstructor, Wallet) {
+pub fn new_wallet(
+    contract_name: &sdk::ContractName,
+    secrets: &Secrets,
+) -> (WalletConstructor, Wallet) {
     let secp = Secp256k1::new();
-    let secret_key =
-        hex::decode(env::var("INVITE_CODE_PKEY").unwrap_or(
-            "0000000000000001000000000000000100000000000000010000000000000001".to_string(),
-        ))
-        .expect("INVITE_CODE_PKEY must be a hex string");
-    let secret_key = SecretKey::from_byte_array(secret_key.try_into().expect("32 bytes"))
-        .expect("32 bytes, within curve order");
-    let public_key = PublicKey::from_secret_key(&secp, &secret_key);
+    let public_key = PublicKey::from_secret_key(&secp, &secrets.invite_code_secret_key);
 
-    let hyli_password = env::var("HYLI_PASSWORD").unwrap_or("hylisecure".to_string());
-    let wallet_constructor = WalletConstructor::new(hyli_password, public_key.serialize());
+    let wallet_constructor = WalletConstructor::new(&secrets.hyli_password, public_key.serialize());
 
     (
         wallet_constructor.clone(),
@@ -0,0 +1,107 @@
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use client_sdk::rest_client::NodeApiClient;
+use hyli_modules::{
+    bus::SharedMessageBus,
+    log_error, module_bus_client, module_handle_messages,
+    modules::{contract_state_indexer::CSIBusEvent, Module},
+};
+use sdk::{BlobTransaction, Identity};
+use server::audit::{AuditKind, AuditRecorder};
+use server::correlation::send_tx_blob;
+use wallet::{
+    client::indexer::{FailedAuthentication, SharedWalletEvent},
+    failed_auth_report_data, WalletAction,
+};
+
+use crate::invites::invite::InviteKey;
+
+/// Reports the failed authentications the wallet indexers observe in settled transactions, so
+/// the contract can lock accounts under repeated password guessing. Reports are signed with the
+/// invite code key, which the contract trusts as the operator's.
+pub struct LockoutReporter {
+    bus: LockoutReporterBusClient,
+    ctx: LockoutReporterCtx,
+}
+
+pub struct LockoutReporterCtx {
+    pub node: Arc<dyn NodeApiClient + Send + Sync>,
+    pub invite_key: Arc<InviteKey>,
+    pub audit: AuditRecorder,
+}
+
+module_bus_client! {
+#[derive(Debug)]
+pub struct LockoutReporterBusClient {
+    receiver(CSIBusEvent<SharedWalletEvent>),
+}
+}
+
+impl LockoutReporter {
+    async fn report(&self, failed: &FailedAuthentication) -> Result<()> {
+        let identity = Identity::new(format!("{}@{}", failed.account, failed.contract_name));
+        let data = failed_auth_report_data(&failed.account, failed.attempt, failed.nonce);
+        let signature_blob = self
+            .ctx
+            .invite_key
+            .settled()
+            .await
+            .sign(identity.clone(), &data)?;
+        let tx = BlobTransaction::new(
+            identity.clone(),
+            vec![
+                WalletAction::ReportFailedAuth {
+                    account: failed.account.clone(),
+                    attempt: failed.attempt,
+                }
+                .as_blob(failed.contract_name.clone()),
+                signature_blob,
+            ],
+        );
+        let tx_hash = send_tx_blob(self.ctx.node.as_ref(), tx)
+            .await
+            .context("sending failed authentication report")?;
+
+        tracing::info!(
+            "Reported failed authentication {} of {} in {}",
+            failed.attempt,
+            failed.account,
+            tx_hash
+        );
+        self.ctx.audit.record(
+            AuditKind::AdminOperation,
+            &failed.account,
+            serde_json::json!({
+                "operation": "report_failed_auth",
+                "contract_name": failed.contract_name.0,
+                "attempt": failed.attempt,
+                "tx_hash": tx_hash.0,
+            }),
+        );
+        Ok(())
+    }
+}
+
+impl Module for LockoutReporter {
+    type Context = LockoutReporterCtx;
+
+    async fn build(bus: SharedMessageBus, ctx: Self::Context) -> Result<Self> {
+        Ok(Self {
+            bus: LockoutReporterBusClient::new_from_bus(bus.new_handle()).await,
+            ctx,
+        })
+    }
+
+    async fn run(&mut self) -> Result<()> {
+        module_handle_messages! {
+            on_self self,
+            listen<CSIBusEvent<SharedWalletEvent>> event => {
+                if let Some(failed) = &event.event.failed_auth {
+                    let _ = log_error!(self.report(failed).await, "Reporting failed authentication");
+                }
+            }
+        };
+        Ok(())
+    }
+}
@@ -1,9 +1,8 @@
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use app::{AppOutWsEvent, AppWsInMessage};
 use axum::Router;
 use clap::Parser;
 use client_sdk::rest_client::NodeApiHttpClient;
-use conf::Conf;
 use history::{HistoryEvent, TokenHistory};
 use hyli_modules::modules::admin::{AdminApi, AdminApiRunContext};
 use hyli_modules::modules::ModulesHandlerOptions;
@@ -15,24 +14,58 @@ use hyli_modules::{
         websocket::WebSocketModule,
         BuildApiContextInner, ModulesHandler,
     },
-    utils::logger::setup_tracing,
 };
 use sdk::{api::NodeInfo, info, ContractName};
+use server::audit::{audit_admin_router, AuditLog, AuditModule, AuditModuleCtx, AuditRecorder};
+use server::autoprovers::{self, AutoProversConfig};
+use server::backup::{BackupModule, BackupModuleCtx};
+use server::conf::{self, Conf};
+use server::correlation::with_correlation;
+use server::feature_flags::{
+    feature_flags_admin_router, feature_flags_router, FeatureFlags, FeatureFlagsModule,
+    FeatureFlagsModuleCtx,
+};
+use server::http::harden;
+use server::provers::{prover_admin_router, ProverControls};
+use server::secrets::Secrets;
+use server::telemetry::setup_telemetry;
 use std::collections::HashSet;
 use std::sync::{Arc, Mutex};
 
 use crate::app::Wrap;
-use crate::autoprovers::AutoProversConfig;
 use crate::sdk_wallet::SdkWalletConfig;
 
+mod alerts;
 mod app;
-mod autoprovers;
-mod conf;
+mod erasure;
+mod grpc;
 mod history;
+mod hyli_password;
 mod init;
+mod invariants;
+mod lockout;
+mod maintenance;
+mod notifications;
+mod oidc;
+mod recovery;
 mod sdk_wallet;
+mod settlement;
+mod stats;
+mod tenants;
+mod tls;
+mod verify_signature;
+mod webauthn;
+mod ws_auth;
+mod ws_gateway;
+mod ws_replay;
 mod invites {
     pub mod invite;
+    pub mod rotation;
+}
+mod signing {
+    pub mod decode;
+    #[allow(clippy::module_inception)]
+    pub mod signing;
 }
 
 #[derive(Parser, Debug)]
@@ -41,8 +74,11 @@ pub struct Args {
     #[arg(long, default_value = "config.toml")]
     pub config_file: Vec<String>,
 
-    #[arg(long, default_value = "wallet")]
-    pub wallet_cn: String,
+    /// Wallet contracts to serve, e.g. `wallet,wallet-staging`. The first one is the main wallet,
+    /// the only one recovery, notifications, gRPC, signing, WebAuthn and required WebSocket auth
+    /// can serve, so they must be disabled to serve several.
+    #[arg(long, value_delimiter = ',', default_value = "wallet")]
+    pub wallet_cn: Vec<String>,
 
     #[arg(short, long, default_value = "false")]
     pub mock_invites: bool,
@@ -81,11 +117,7 @@ async fn actual_main() -> Result<()> {
     let args = Args::parse();
     let config = Conf::new(args.config_file).context("reading config file")?;
 
-    setup_tracing(
-        &config.log_format,
-        format!("{}(nopkey)", config.id.clone(),),
-    )
-    .context("setting up tracing")?;
+    setup_telemetry(&config, format!("{}(nopkey)", config.id)).context("setting up tracing")?;
 
     let config = Arc::new(config);
 
@@ -94,17 +126,85 @@ async fn actual_main() -> Result<()> {
         std::fs::remove_dir_all(&config.data_directory).context("cleaning data directory")?;
     }
 
+    let mut ports = vec![
+        ("rest_server_port", config.rest_server_port),
+        ("admin_server_port", config.admin_server_port),
+        ("websocket.port", config.websocket.port),
+    ];
+    if config.tls.enabled {
+        ports.push(("tls.rest_port", config.tls.rest_port));
+        ports.push(("tls.websocket_port", config.tls.websocket_port));
+    }
+    if config.grpc.enabled {
+        ports.push(("grpc.port", config.grpc.port));
+    }
+    config
+        .validate(
+            &ports,
+            !args.mock_invites
+                || config.signing.enabled
+                || config.audit.enabled
+                || config.recovery.enabled
+                || config.notifications.enabled
+                || config.settlement.enabled
+                || config.feature_flags.enabled
+                || config.webauthn.enabled
+                || !config.tenants.is_empty(),
+        )
+        .await?;
+
     info!("Starting app with config: {:?}", &config);
 
     let node_client =
         Arc::new(NodeApiHttpClient::new(config.node_url.clone()).context("build node client")?);
 
-    let wallet_cn: ContractName = args.wallet_cn.clone().into();
+    let secrets = Secrets::resolve(&config.secrets)
+        .await
+        .context("resolving secrets")?;
+
+    let wallet_cns: Vec<ContractName> = args.wallet_cn.iter().cloned().map(ContractName).collect();
+    if wallet_cns.len() > 1 {
+        let main_wallet_only = [
+            ("recovery", config.recovery.enabled),
+            ("notifications", config.notifications.enabled),
+            ("grpc", config.grpc.enabled),
+            ("signing", config.signing.enabled),
+            ("webauthn", config.webauthn.enabled),
+            ("ws_auth.required", config.ws_auth.required),
+        ]
+        .into_iter()
+        .filter_map(|(module, enabled)| enabled.then_some(module))
+        .collect::<Vec<_>>();
+        if !main_wallet_only.is_empty() {
+            bail!(
+                "{} only serve the main wallet, they can't be enabled with several wallets",
+                main_wallet_only.join(", ")
+            );
+        }
+    }
     let smt_auto_prove = config.smt_auto_provers || args.auto_provers;
+    let autoprovers_config = AutoProversConfig {
+        wallet_auto_prove: config.wallet_auto_prover || args.wallet_auto_prover,
+        smt_auto_prove,
+        ..AutoProversConfig::new(&config, wallet_cns.clone())
+    };
 
     let bus = SharedMessageBus::new();
 
     std::fs::create_dir_all(&config.data_directory).context("creating data directory")?;
+    if config.token_history.bounded {
+        history::configure(history::HistoryLimits {
+            hot_entries_per_account: config.token_history.hot_entries_per_account,
+            max_entries: config.token_history.max_entries,
+            spill_directory: config.data_directory.join("history"),
+        })?;
+    }
+    if config.state_journal.enabled {
+        wallet::client::journal::configure(
+            config.data_directory.join("journal"),
+            config.state_journal.compact_after,
+        )?;
+    }
 
     let mut handler = ModulesHandler::new(
         &bus,
@@ -117,22 +217,89 @@ async fn actual_main() -> Result<()> {
         openapi: Default::default(),
     });
 
+    let indexer_url = format!(
+        "http://localhost:{}",
+        args.server_port.unwrap_or(config.rest_server_port)
+    );
+
+    let mut admin_router = Router::new();
+    let audit = if config.audit.enabled {
+        let log = AuditLog::connect(&config.db_url, &config.database)
+            .await
+            .context("connecting audit log")?;
+        let (recorder, events) = AuditRecorder::channel();
+        handler
+            .build_module::<AuditModule>(AuditModuleCtx {
+                log: log.clone(),
+                events,
+            })
+            .await?;
+        admin_router = admin_router.merge(audit_admin_router(log));
+        recorder
+    } else {
+        AuditRecorder::default()
+    };
+
+    let feature_flags = if config.feature_flags.enabled {
+        let flags = FeatureFlags::connect(
+            &config.feature_flags,
+            &config.db_url,
+            &config.database,
+            audit.clone(),
+        )
+        .await
+        .context("loading feature flags")?;
+        handler
+            .build_module::<FeatureFlagsModule>(FeatureFlagsModuleCtx {
+                flags: flags.clone(),
+                refresh_interval_secs: config.feature_flags.refresh_interval_secs,
+            })
+            .await?;
+        admin_router = admin_router.merge(feature_flags_admin_router(flags.clone()));
+        flags
+    } else {
+        FeatureFlags::new(&config.feature_flags)
+    };
+    if let Ok(mut guard) = api_ctx.router.lock() {
+        if let Some(router) = guard.take() {
+            guard.replace(
+                router.merge(feature_flags_router(feature_flags.clone()).layer(app::cors_layer())),
+            );
+        }
+    }
+
+    let ws_auth = Arc::new(ws_auth::WsAuth::new(
+        config.ws_auth.clone(),
+        wallet_cns.first().cloned().unwrap_or_default(),
+        indexer_url.clone(),
+        audit.clone(),
+    ));
+    admin_router = admin_router.merge(ws_auth::ws_auth_admin_router(ws_auth.clone()));
+    let replay = Arc::new(ws_replay::EventReplay::new(
+        config.ws_auth.replay_buffer_size,
+    ));
+
+    let prover_controls = Arc::new(ProverControls::with_audit(audit.clone()));
+
     sdk_wallet::setup_wallet_modules(
         &SdkWalletConfig {
-            wallet_cn: wallet_cn.clone(),
+            wallet_cns: wallet_cns.clone(),
             noinit: args.noinit,
             data_directory: config.data_directory.clone(),
             indexer_database_url: config.indexer_database_url.clone(),
+            indexer_url: indexer_url.clone(),
             listener_poll_interval_secs: config.auto_prover_listener_poll_interval_secs,
+            listener_replay_settled_from_start: config.listener_replay_settled_from_start,
             additional_listener_contracts: if smt_auto_prove {
-                HashSet::from([
-                    ContractName::from("oranj"),
-                    ContractName::from("vitamin"),
-                    ContractName::from("oxygen"),
-                ])
+                autoprovers_config.smt_contracts.iter().cloned().collect()
             } else {
                 HashSet::new()
             },
+            secrets: secrets.clone(),
+            ws_auth: ws_auth.clone(),
+            replay: replay.clone(),
+            system_status: config.system_status.clone(),
+            prover_controls: prover_controls.clone(),
         },
         &mut handler,
         api_ctx.clone(),
@@ -141,70 +308,340 @@ async fn actual_main() -> Result<()> {
     .await
     .context("initializing wallet modules")?;
 
+    // The main wallet's routes are served at the root, the others' under `/{wallet_cn}`.
+    for (i, wallet_cn) in wallet_cns.iter().enumerate() {
+        let hyli_password = Arc::new(hyli_password::HyliPassword::new(
+            wallet_cn.clone(),
+            node_client.clone(),
+            autoprovers_config.wallet_auto_prove,
+            audit.clone(),
+        ));
+        handler
+            .build_module::<hyli_password::HyliPasswordModule>(hyli_password.clone())
+            .await?;
+        let router = hyli_password::hyli_password_admin_router(hyli_password);
+        admin_router = if i == 0 {
+            admin_router.merge(router)
+        } else {
+            admin_router.nest(&format!("/{}", wallet_cn.0), router)
+        };
+    }
+
+    let invite_key = Arc::new(invites::invite::InviteKey::new(
+        secrets.invite_code_secret_key,
+    ));
+    let invite_key_rotation = Arc::new(invites::rotation::InviteKeyRotation::new(
+        wallet_cns
+            .iter()
+            .cloned()
+            .chain(
+                config
+                    .tenants
+                    .iter()
+                    .map(|tenant| ContractName(tenant.wallet_cn.clone())),
+            )
+            .collect(),
+        node_client.clone(),
+        invite_key.clone(),
+        secrets.next_invite_code_secret_key,
+        audit.clone(),
+    ));
+    handler
+        .build_module::<invites::rotation::InviteKeyRotationModule>(invite_key_rotation.clone())
+        .await?;
+    admin_router = admin_router.merge(invites::rotation::invite_key_admin_router(
+        invite_key_rotation,
+    ));
+
+    if config.invariants.enabled {
+        let statuses = invariants::InvariantStatuses::default();
+        handler
+            .build_module::<invariants::InvariantChecker>(invariants::InvariantCheckerCtx {
+                conf: config.invariants.clone(),
+                wallet_cns: wallet_cns.clone(),
+                node: node_client.clone(),
+                indexer_url: indexer_url.clone(),
+                statuses: statuses.clone(),
+            })
+            .await?;
+        admin_router = admin_router.merge(invariants::invariants_admin_router(statuses));
+    }
+
+    if config.erasure.enabled {
+        let erasure = erasure::Erasure::connect(
+            &config.db_url,
+            &config.database,
+            config.erasure.signing_history_retention_days,
+            wallet_cns.clone(),
+            config
+                .tokens
+                .iter()
+                .map(|token| ContractName(token.name.clone()))
+                .collect(),
+            audit.clone(),
+        )
+        .await
+        .context("connecting erasure database")?;
+        admin_router = admin_router.merge(erasure::erasure_admin_router(Arc::new(erasure)));
+    }
+
+    if config.maintenance.enabled {
+        handler
+            .build_module::<maintenance::MaintenanceModule>(maintenance::MaintenanceModuleCtx {
+                conf: config.maintenance.clone(),
+                wallet_cns: wallet_cns.clone(),
+                node: node_client.clone(),
+                indexer_url: indexer_url.clone(),
+                invites_db: (!args.mock_invites)
+                    .then(|| (config.db_url.clone(), config.database.clone())),
+                prover_controls: prover_controls.clone(),
+            })
+            .await?;
+    }
+
+    if config.lockout.report_failed_auth {
+        handler
+            .build_module::<lockout::LockoutReporter>(lockout::LockoutReporterCtx {
+                node: node_client.clone(),
+                invite_key: invite_key.clone(),
+                audit: audit.clone(),
+            })
+            .await?;
+    }
+
+    if config.recovery.enabled {
+        handler
+            .build_module::<recovery::RecoveryModule>(recovery::RecoveryModuleCtx {
+                api_ctx: api_ctx.clone(),
+                conf: config.recovery.clone(),
+                db_url: config.db_url.clone(),
+                database: config.database.clone(),
+                node: node_client.clone(),
+                wallet_cn: wallet_cns.first().cloned().unwrap_or_default(),
+                indexer_url: indexer_url.clone(),
+                audit: audit.clone(),
+            })
+            .await?;
+    }
+
+    if config.notifications.enabled {
+        handler
+            .build_module::<notifications::NotificationModule>(
+                notifications::NotificationModuleCtx {
+                    api_ctx: api_ctx.clone(),
+                    conf: config.notifications.clone(),
+                    db_url: config.db_url.clone(),
+                    database: config.database.clone(),
+                    wallet_cn: wallet_cns.first().cloned().unwrap_or_default(),
+                    indexer_url: indexer_url.clone(),
+                },
+            )
+            .await?;
+    }
+
+    if config.oidc.enabled {
+        handler
+            .build_module::<oidc::OidcModule>(oidc::OidcModuleCtx {
+                api_ctx: api_ctx.clone(),
+                conf: config.oidc.clone(),
+                data_directory: config.data_directory.clone(),
+            })
+            .await?;
+    }
+
+    if config.webauthn.enabled {
+        handler
+            .build_module::<webauthn::WebAuthnModule>(webauthn::WebAuthnModuleCtx {
+                api_ctx: api_ctx.clone(),
+                conf: config.webauthn.clone(),
+                db_url: config.db_url.clone(),
+                database: config.database.clone(),
+                ws_auth: ws_auth.clone(),
+            })
+            .await?;
+    }
+
     autoprovers::setup_autoprovers_modules(
-        &AutoProversConfig {
-            wallet_cn: wallet_cn.clone(),
-            wallet_auto_prove: config.wallet_auto_prover || args.wallet_auto_prover,
-            smt_auto_prove,
-            data_directory: config.data_directory.clone(),
-            smt_max_txs_per_proof: config.smt_max_txs_per_proof,
-            smt_tx_working_window_size: config.smt_tx_working_window_size,
-            wallet_max_txs_per_proof: config.wallet_max_txs_per_proof,
-            wallet_tx_working_window_size: config.wallet_tx_working_window_size,
-            idle_flush_interval_secs: config.auto_prover_idle_flush_interval_secs,
-            tx_buffer_size: config.auto_prover_tx_buffer_size,
-        },
+        &autoprovers_config,
         &mut handler,
         api_ctx.clone(),
         node_client.clone(),
+        &prover_controls,
     )
     .await
     .context("initializing autoprover modules")?;
 
-    handler
-        .build_module::<ContractStateIndexer<TokenHistory, Wrap<Vec<HistoryEvent>>>>(
-            ContractStateIndexerCtx {
-                contract_name: "oranj".into(),
-                data_directory: config.data_directory.clone(),
-                api: api_ctx.clone(),
-            },
-        )
-        .await?;
-    handler
-        .build_module::<ContractStateIndexer<TokenHistory, Wrap<Vec<HistoryEvent>>>>(
-            ContractStateIndexerCtx {
-                contract_name: "vitamin".into(),
-                data_directory: config.data_directory.clone(),
-                api: api_ctx.clone(),
-            },
-        )
-        .await?;
-    handler
-        .build_module::<ContractStateIndexer<TokenHistory, Wrap<Vec<HistoryEvent>>>>(
-            ContractStateIndexerCtx {
-                contract_name: "oxygen".into(),
-                data_directory: config.data_directory.clone(),
-                api: api_ctx.clone(),
-            },
-        )
-        .await?;
+    for token in &config.tokens {
+        handler
+            .build_module::<ContractStateIndexer<TokenHistory, Wrap<Arc<[HistoryEvent]>>>>(
+                ContractStateIndexerCtx {
+                    contract_name: token.name.clone().into(),
+                    data_directory: config.data_directory.clone(),
+                    api: api_ctx.clone(),
+                },
+            )
+            .await?;
+    }
 
     handler
         .build_module::<WebSocketModule<AppWsInMessage, AppOutWsEvent>>(config.websocket.clone())
         .await?;
 
+    let stats = Arc::new(stats::StatsCounters::default());
+    if config.stats.enabled {
+        handler
+            .build_module::<stats::StatsModule>(stats::StatsModuleCtx {
+                api_ctx: api_ctx.clone(),
+                counters: stats.clone(),
+                wallet_cns: wallet_cns
+                    .iter()
+                    .cloned()
+                    .chain(
+                        config
+                            .tenants
+                            .iter()
+                            .map(|tenant| ContractName(tenant.wallet_cn.clone())),
+                    )
+                    .collect(),
+                prover_controls: prover_controls.clone(),
+            })
+            .await?;
+    }
+
+    if config.ws_gateway.enabled {
+        handler
+            .build_module::<ws_gateway::WsGateway>(ws_gateway::WsGatewayCtx {
+                api: api_ctx.clone(),
+                conf: config.ws_gateway.clone(),
+                stats: stats.clone(),
+            })
+            .await?;
+    }
+
     if args.mock_invites {
         handler
             .build_module::<invites::invite::MockInviteModule>(invites::invite::InviteModuleCtx {
                 db_url: config.db_url.clone(),
+                database: config.database.clone(),
                 api_ctx: api_ctx.clone(),
+                invite_key: invite_key.clone(),
+                audit: audit.clone(),
+                stats: stats.clone(),
             })
             .await?;
     } else {
         handler
             .build_module::<invites::invite::InviteModule>(invites::invite::InviteModuleCtx {
                 db_url: config.db_url.clone(),
+                database: config.database.clone(),
+                api_ctx: api_ctx.clone(),
+                invite_key: invite_key.clone(),
+                audit: audit.clone(),
+                stats: stats.clone(),
+            })
+            .await?;
+    }
+
+    if config.signing.enabled {
+        handler
+            .build_module::<signing::signing::SigningModule>(signing::signing::SigningModuleCtx {
                 api_ctx: api_ctx.clone(),
+                config: config.signing.clone(),
+                db_url: config.db_url.clone(),
+                database: config.database.clone(),
+                audit: audit.clone(),
+                ws_auth: ws_auth.clone(),
+                replay: replay.clone(),
+            })
+            .await?;
+    }
+
+    if config.settlement.enabled {
+        handler
+            .build_module::<settlement::SettlementModule>(settlement::SettlementModuleCtx {
+                api_ctx: api_ctx.clone(),
+                conf: config.settlement.clone(),
+                db_url: config.db_url.clone(),
+                database: config.database.clone(),
+                indexer_database_url: config.indexer_database_url.clone(),
+                wallet_cns: wallet_cns
+                    .iter()
+                    .cloned()
+                    .chain(
+                        config
+                            .tenants
+                            .iter()
+                            .map(|tenant| ContractName(tenant.wallet_cn.clone())),
+                    )
+                    .collect(),
+                prover_controls: prover_controls.clone(),
+            })
+            .await?;
+    }
+
+    if config.alerts.enabled {
+        handler
+            .build_module::<alerts::AlertModule>(alerts::AlertModuleCtx {
+                conf: config.alerts.clone(),
+                source: config.id.clone(),
+                node: node_client.clone(),
+                indexer_database_url: config.indexer_database_url.clone(),
+                prover_controls: prover_controls.clone(),
+            })
+            .await?;
+    }
+
+    tenants::setup_tenants(
+        &tenants::TenantsCtx {
+            conf: config.clone(),
+            wallet_cns: wallet_cns.clone(),
+            noinit: args.noinit,
+            indexer_url: indexer_url.clone(),
+            wallet_auto_prove: autoprovers_config.wallet_auto_prove,
+            secrets: secrets.clone(),
+            invite_key: invite_key.clone(),
+            audit: audit.clone(),
+            ws_auth: ws_auth.clone(),
+            replay: replay.clone(),
+            prover_controls: prover_controls.clone(),
+            stats: stats.clone(),
+        },
+        &mut handler,
+        api_ctx.clone(),
+        node_client.clone(),
+    )
+    .await
+    .context("initializing tenants")?;
+
+    if config.backup.enabled {
+        handler
+            .build_module::<BackupModule>(BackupModuleCtx {
+                conf: config.backup.clone(),
+                id: config.id.clone(),
+                data_directory: config.data_directory.clone(),
+            })
+            .await?;
+    }
+
+    if config.tls.enabled {
+        handler
+            .build_module::<tls::TlsProxy>(tls::TlsProxyCtx {
+                conf: config.tls.clone(),
+                routes: vec![
+                    (config.tls.rest_port, config.rest_server_port),
+                    (config.tls.websocket_port, config.websocket.port),
+                ],
+            })
+            .await?;
+    }
+
+    if config.grpc.enabled {
+        handler
+            .build_module::<grpc::GrpcModule>(grpc::GrpcModuleCtx {
+                conf: config.grpc.clone(),
+                wallet_cn: wallet_cns.first().cloned().unwrap_or_default(),
+                node: node_client.clone(),
+                prover_controls: prover_controls.clone(),
             })
             .await?;
     }
@@ -212,7 +649,7 @@ async fn actual_main() -> Result<()> {
     handler
         .build_module::<AdminApi>(AdminApiRunContext::new(
             config.admin_server_port,
-            Router::new(),
+            with_correlation(admin_router.merge(prover_admin_router(prover_controls))),
             config.admin_server_max_body_size,
             config.data_directory.clone(),
         ))
@@ -237,7 +674,7 @@ async fn actual_main() -> Result<()> {
         .build_module::<RestApi>(RestApiRunContext {
             port: args.server_port.unwrap_or(config.rest_server_port),
             max_body_size: config.rest_server_max_body_size,
-            router,
+            router: harden(router, &config.http),
             openapi,
             info: NodeInfo {
                 id: config.id.clone(),
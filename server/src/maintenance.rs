@@ -0,0 +1,191 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::{Context, Result};
+use client_sdk::rest_client::NodeApiClient;
+use hyli_modules::{
+    bus::SharedMessageBus, module_bus_client, module_handle_messages, modules::Module,
+};
+use opentelemetry::{metrics::Counter, KeyValue};
+use sdk::{BlobTransaction, ContractName};
+use server::conf::{DatabaseConf, MaintenanceConf};
+use server::correlation::send_tx_blob;
+use server::db::DbPools;
+use server::provers::ProverControls;
+use tokio::time::{Interval, MissedTickBehavior};
+
+/// Runs the housekeeping jobs, each on its own schedule from [`MaintenanceConf`]:
+///
+/// - `prune_session_keys` sends a `PruneSessionKeys` transaction for every account of the served
+///   wallets holding expired session keys,
+/// - `expire_invites` deletes the invite codes nobody used within `invite_ttl_secs`,
+/// - `retry_proofs` retries the proofs held after a failure, see
+///   [`ProverControls::hold_failed_proofs`].
+///
+/// Job outcomes are counted in the `maintenance_job_runs_total` metric.
+pub struct MaintenanceModule {
+    #[allow(unused)]
+    bus: MaintenanceModuleBusClient,
+    ctx: MaintenanceModuleCtx,
+    db: Option<DbPools>,
+    http: reqwest::Client,
+    runs: Counter<u64>,
+    items: Counter<u64>,
+}
+
+pub struct MaintenanceModuleCtx {
+    pub conf: MaintenanceConf,
+    pub wallet_cns: Vec<ContractName>,
+    pub node: Arc<dyn NodeApiClient + Send + Sync>,
+    /// Base URL of this server's REST API, serving the indexer routes.
+    pub indexer_url: String,
+    /// Database of the invite codes, `None` when invites are mocked.
+    pub invites_db: Option<(String, DatabaseConf)>,
+    pub prover_controls: Arc<ProverControls>,
+}
+
+module_bus_client! {
+#[derive(Debug)]
+pub struct MaintenanceModuleBusClient {
+}
+}
+
+/// Ticks every `secs`, or never when it's 0.
+fn schedule(secs: u64) -> Option<Interval> {
+    (secs > 0).then(|| {
+        let mut interval = tokio::time::interval(Duration::from_secs(secs));
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        interval
+    })
+}
+
+async fn tick(interval: &mut Option<Interval>) {
+    match interval {
+        Some(interval) => {
+            interval.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+impl MaintenanceModule {
+    /// Records the outcome of a run of `job`, and how many items it handled.
+    fn record(&self, job: &'static str, result: Result<usize>) {
+        let outcome = match result {
+            Ok(items) => {
+                if items > 0 {
+                    tracing::info!("🧹 Maintenance job {job} handled {items} items");
+                }
+                self.items.add(items as u64, &[KeyValue::new("job", job)]);
+                "success"
+            }
+            Err(e) => {
+                tracing::warn!("Maintenance job {job} failed: {e:#}");
+                "failure"
+            }
+        };
+        self.runs.add(
+            1,
+            &[KeyValue::new("job", job), KeyValue::new("outcome", outcome)],
+        );
+    }
+
+    async fn prune_session_keys(&self) -> Result<usize> {
+        let mut sent = 0;
+        for contract_name in &self.ctx.wallet_cns {
+            let txs: Vec<BlobTransaction> = self
+                .http
+                .get(format!(
+                    "{}/v1/indexer/contract/{}/session_keys/expired",
+                    self.ctx.indexer_url, contract_name.0
+                ))
+                .send()
+                .await?
+                .error_for_status()
+                .context("listing expired session keys")?
+                .json()
+                .await?;
+            for tx in txs
+                .into_iter()
+                .take(self.ctx.conf.prune_session_keys_batch - sent)
+            {
+                let identity = tx.identity.clone();
+                send_tx_blob(self.ctx.node.as_ref(), tx)
+                    .await
+                    .with_context(|| format!("pruning session keys of {identity}"))?;
+                sent += 1;
+            }
+        }
+        Ok(sent)
+    }
+
+    async fn expire_invites(&self) -> Result<usize> {
+        let Some(db) = &self.db else {
+            return Ok(0);
+        };
+        let expired = sqlx::query(
+            "
+            DELETE FROM invite_codes
+            WHERE used_at IS NULL AND created_at < NOW() - make_interval(secs => $1)
+            ",
+        )
+        .bind(self.ctx.conf.invite_ttl_secs as f64)
+        .execute(&db.primary)
+        .await?;
+        Ok(expired.rows_affected() as usize)
+    }
+}
+
+impl Module for MaintenanceModule {
+    type Context = MaintenanceModuleCtx;
+
+    async fn build(bus: SharedMessageBus, ctx: Self::Context) -> Result<Self> {
+        let db = match &ctx.invites_db {
+            Some((db_url, database)) => Some(DbPools::connect(db_url, database).await?),
+            None => None,
+        };
+        ctx.prover_controls
+            .hold_failed_proofs(ctx.conf.max_proof_retries);
+
+        let meter = opentelemetry::global::meter("wallet_maintenance");
+        Ok(Self {
+            bus: MaintenanceModuleBusClient::new_from_bus(bus.new_handle()).await,
+            ctx,
+            db,
+            http: reqwest::Client::new(),
+            runs: meter
+                .u64_counter("maintenance_job_runs_total")
+                .with_description("Number of maintenance job runs, by job and outcome")
+                .build(),
+            items: meter
+                .u64_counter("maintenance_job_items_total")
+                .with_description(
+                    "Number of transactions sent, invites expired or proofs retried, by job",
+                )
+                .build(),
+        })
+    }
+
+    async fn run(&mut self) -> Result<()> {
+        let conf = &self.ctx.conf;
+        let mut prune_session_keys = schedule(conf.prune_session_keys_interval_secs);
+        let mut expire_invites = schedule(conf.expire_invites_interval_secs);
+        let mut retry_proofs = schedule(conf.retry_proofs_interval_secs);
+
+        module_handle_messages! {
+            on_self self,
+            _ = tick(&mut prune_session_keys) => {
+                let result = self.prune_session_keys().await;
+                self.record("prune_session_keys", result);
+            }
+            _ = tick(&mut expire_invites) => {
+                let result = self.expire_invites().await;
+                self.record("expire_invites", result);
+            }
+            _ = tick(&mut retry_proofs) => {
+                let retried = self.ctx.prover_controls.retry_failed();
+                self.record("retry_proofs", Ok(retried));
+            }
+        };
+        Ok(())
+    }
+}
@@ -0,0 +1,512 @@
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{bail, Context, Result};
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+use chrono::NaiveDateTime;
+use client_sdk::contract_indexer::utoipa;
+use client_sdk::contract_indexer::{
+    utoipa::{IntoParams, ToSchema},
+    utoipa_axum::{router::OpenApiRouter, routes},
+};
+use client_sdk::AppError;
+use hyli_modules::{
+    bus::SharedMessageBus,
+    log_error, module_bus_client, module_handle_messages,
+    modules::{contract_state_indexer::CSIBusEvent, BuildApiContextInner, Module},
+};
+use sdk::{ContractName, Identity};
+use serde::{Deserialize, Serialize};
+use server::conf::{DatabaseConf, NotificationsConf};
+use server::db::DbPools;
+use sha2::{Digest, Sha256};
+use sqlx::FromRow;
+use wallet::client::indexer::{SharedWalletEvent, TxOutcome, WalletEvent};
+
+use crate::app::Wrap;
+use crate::history::HistoryEvent;
+use crate::verify_signature::{SignatureVerifier, VerifySignatureRequest};
+
+/// Notifies account owners of activity on their account: large incoming transfers, new session
+/// keys and failed authentications.
+///
+/// Owners choose the events and the channels (email, webhook, push) through the
+/// `/notifications` routes, and their preferences are stored in `db_url`. Emails and pushes go
+/// through the relays of [`NotificationsConf`], webhooks are called directly. Reading or
+/// changing preferences takes a signature of the account, as checked by `/api/verify_signature`.
+pub struct NotificationModule {
+    bus: NotificationModuleBusClient,
+    inner: Arc<NotificationModuleInner>,
+}
+
+pub struct NotificationModuleCtx {
+    pub api_ctx: Arc<BuildApiContextInner>,
+    pub conf: NotificationsConf,
+    pub db_url: String,
+    pub database: DatabaseConf,
+    pub wallet_cn: ContractName,
+    /// Base URL of this server's REST API, serving the indexer routes.
+    pub indexer_url: String,
+}
+
+module_bus_client! {
+#[derive(Debug)]
+pub struct NotificationModuleBusClient {
+    receiver(CSIBusEvent<SharedWalletEvent>),
+    receiver(CSIBusEvent<Wrap<Arc<[HistoryEvent]>>>),
+}
+}
+
+struct NotificationModuleInner {
+    conf: NotificationsConf,
+    db: DbPools,
+    wallet_cn: ContractName,
+    verifier: SignatureVerifier,
+    http: reqwest::Client,
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// Message the account signs to read its preferences at `timestamp`, or to replace them with
+/// `preferences` when given.
+pub fn preferences_message(
+    account: &str,
+    timestamp: u64,
+    preferences: Option<&NotificationPreferences>,
+) -> Result<String> {
+    let digest = match preferences {
+        Some(preferences) => hex::encode(Sha256::digest(serde_json::to_vec(preferences)?)),
+        None => String::new(),
+    };
+    Ok(format!("{account}:{timestamp}:notifications:{digest}"))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum NotificationKind {
+    LargeTransfer,
+    SessionKeyAdded,
+    FailedAuth,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct Notification {
+    account: String,
+    kind: NotificationKind,
+    title: String,
+    body: String,
+    tx_hash: String,
+    /// Amount of a transfer.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    amount: Option<u128>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema, FromRow)]
+pub struct NotificationPreferences {
+    /// Address emails are sent to, through the email relay.
+    pub email: Option<String>,
+    /// URL notifications are posted to as JSON.
+    pub webhook_url: Option<String>,
+    /// Device token handed to the push gateway.
+    pub push_token: Option<String>,
+    /// Notify incoming transfers of at least this amount, the server default when unset.
+    pub large_transfer_threshold: Option<i64>,
+    pub on_large_transfer: bool,
+    pub on_session_key_added: bool,
+    pub on_failed_auth: bool,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct AccountNotificationPreferences {
+    pub account: String,
+    #[serde(flatten)]
+    pub preferences: NotificationPreferences,
+    #[schema(value_type = Option<String>)]
+    pub updated_at: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Deserialize, ToSchema, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AccountSignature {
+    /// Milliseconds at which the message was signed.
+    pub timestamp: u64,
+    /// Hex-encoded signature of [`preferences_message`], by the account's key or a session key.
+    pub signature: String,
+    /// Hex-encoded compressed public key that made the signature.
+    pub public_key: String,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct UpdatePreferencesBody {
+    pub preferences: NotificationPreferences,
+    #[serde(flatten)]
+    pub signature: AccountSignature,
+}
+
+const PREFERENCE_COLUMNS: &str = "email, webhook_url, push_token, large_transfer_threshold, \
+     on_large_transfer, on_session_key_added, on_failed_auth";
+
+impl NotificationModuleInner {
+    async fn check_signature(
+        &self,
+        account: &str,
+        signature: &AccountSignature,
+        preferences: Option<&NotificationPreferences>,
+    ) -> Result<()> {
+        let now = now_ms();
+        let ttl = self.conf.signature_ttl_secs * 1000;
+        if signature.timestamp + ttl < now || signature.timestamp > now + ttl {
+            bail!("Signature timestamp is too far from the server time");
+        }
+        let response = self
+            .verifier
+            .verify(VerifySignatureRequest {
+                account: account.to_string(),
+                message: preferences_message(account, signature.timestamp, preferences)?,
+                signature: signature.signature.clone(),
+                public_key: signature.public_key.clone(),
+                contract: None,
+            })
+            .await?;
+        if !response.valid {
+            bail!("Invalid signature: {}", response.reason.unwrap_or_default());
+        }
+        Ok(())
+    }
+
+    async fn preferences(&self, account: &str) -> Result<Option<NotificationPreferences>> {
+        Ok(sqlx::query_as(&format!(
+            "SELECT {PREFERENCE_COLUMNS} FROM notification_preferences WHERE account = $1"
+        ))
+        .bind(account)
+        .fetch_optional(&self.db.primary)
+        .await?)
+    }
+
+    async fn account_preferences(&self, account: &str) -> Result<AccountNotificationPreferences> {
+        let row: Option<(NaiveDateTime,)> =
+            sqlx::query_as("SELECT updated_at FROM notification_preferences WHERE account = $1")
+                .bind(account)
+                .fetch_optional(&self.db.primary)
+                .await?;
+        Ok(AccountNotificationPreferences {
+            account: account.to_string(),
+            preferences: self.preferences(account).await?.unwrap_or_default(),
+            updated_at: row.map(|(updated_at,)| updated_at),
+        })
+    }
+
+    async fn update(
+        &self,
+        account: &str,
+        preferences: &NotificationPreferences,
+    ) -> Result<AccountNotificationPreferences> {
+        if let Some(url) = &preferences.webhook_url {
+            let url = reqwest::Url::parse(url).context("parsing webhook URL")?;
+            if url.scheme() != "https" && url.scheme() != "http" {
+                bail!("Webhook URL must be http or https");
+            }
+        }
+        if preferences.large_transfer_threshold.is_some_and(|t| t < 0) {
+            bail!("Large transfer threshold must not be negative");
+        }
+        sqlx::query(
+            "
+            INSERT INTO notification_preferences (account, email, webhook_url, push_token,
+                large_transfer_threshold, on_large_transfer, on_session_key_added, on_failed_auth)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            ON CONFLICT (account) DO UPDATE
+            SET email = $2, webhook_url = $3, push_token = $4, large_transfer_threshold = $5,
+                on_large_transfer = $6, on_session_key_added = $7, on_failed_auth = $8,
+                updated_at = NOW()
+            ",
+        )
+        .bind(account)
+        .bind(&preferences.email)
+        .bind(&preferences.webhook_url)
+        .bind(&preferences.push_token)
+        .bind(preferences.large_transfer_threshold)
+        .bind(preferences.on_large_transfer)
+        .bind(preferences.on_session_key_added)
+        .bind(preferences.on_failed_auth)
+        .execute(&self.db.primary)
+        .await?;
+        self.account_preferences(account).await
+    }
+
+    /// Account name of a wallet identity, `None` for identities of other contracts.
+    fn wallet_account(&self, identity: &Identity) -> Option<String> {
+        let (account, contract) = identity.0.rsplit_once('@')?;
+        (contract == self.wallet_cn.0).then(|| account.to_string())
+    }
+
+    fn on_wallet_event(self: &Arc<Self>, event: &WalletEvent) {
+        let Some(account) = self.wallet_account(&event.account) else {
+            return;
+        };
+        if let Some(failed) = &event.failed_auth {
+            self.notify(Notification {
+                account: failed.account.clone(),
+                kind: NotificationKind::FailedAuth,
+                title: "Failed sign-in attempt".into(),
+                body: format!(
+                    "A transaction failed to authenticate as {} (attempt {}).",
+                    failed.account, failed.attempt
+                ),
+                tx_hash: event.tx_hash.0.clone(),
+                amount: None,
+            });
+        } else if event.outcome == TxOutcome::Success
+            && event.program_outputs.starts_with("Session key added")
+        {
+            self.notify(Notification {
+                body: format!("A new session key was added to {account}."),
+                account,
+                kind: NotificationKind::SessionKeyAdded,
+                title: "New session key".into(),
+                tx_hash: event.tx_hash.0.clone(),
+                amount: None,
+            });
+        }
+    }
+
+    fn on_history_event(self: &Arc<Self>, event: &HistoryEvent) {
+        if event.tx.r#type != "Receive" || event.tx.status != "Success" {
+            return;
+        }
+        let Some(account) = self.wallet_account(&event.account) else {
+            return;
+        };
+        self.notify(Notification {
+            body: format!(
+                "{account} received {} from {}.",
+                event.tx.amount, event.tx.address
+            ),
+            account,
+            kind: NotificationKind::LargeTransfer,
+            title: "Incoming transfer".into(),
+            tx_hash: event.tx.id.0.clone(),
+            amount: Some(event.tx.amount),
+        });
+    }
+
+    /// Dispatches `notification` in the background, the bus isn't held up by slow channels.
+    fn notify(self: &Arc<Self>, notification: Notification) {
+        let inner = self.clone();
+        tokio::spawn(async move {
+            let _ = log_error!(
+                inner.dispatch(&notification).await,
+                "Dispatching notification"
+            );
+        });
+    }
+
+    async fn dispatch(&self, notification: &Notification) -> Result<()> {
+        let Some(preferences) = self.preferences(&notification.account).await? else {
+            return Ok(());
+        };
+        let wanted = match notification.kind {
+            NotificationKind::LargeTransfer => {
+                let threshold = preferences
+                    .large_transfer_threshold
+                    .map(|t| t as u128)
+                    .unwrap_or(self.conf.large_transfer_threshold as u128);
+                preferences.on_large_transfer
+                    && notification
+                        .amount
+                        .is_some_and(|amount| amount >= threshold)
+            }
+            NotificationKind::SessionKeyAdded => preferences.on_session_key_added,
+            NotificationKind::FailedAuth => preferences.on_failed_auth,
+        };
+        if !wanted {
+            return Ok(());
+        }
+
+        let mut errors = vec![];
+        if let Some(url) = &preferences.webhook_url {
+            if let Err(e) = self.post(url, notification).await {
+                errors.push(format!("webhook: {e:#}"));
+            }
+        }
+        if let (Some(to), Some(relay)) = (&preferences.email, &self.conf.email_relay_url) {
+            let email = serde_json::json!({
+                "to": to,
+                "subject": notification.title,
+                "text": notification.body,
+            });
+            if let Err(e) = self.post(relay, &email).await {
+                errors.push(format!("email: {e:#}"));
+            }
+        }
+        if let (Some(token), Some(gateway)) = (&preferences.push_token, &self.conf.push_gateway_url)
+        {
+            let push = serde_json::json!({
+                "token": token,
+                "title": notification.title,
+                "body": notification.body,
+                "data": notification,
+            });
+            if let Err(e) = self.post(gateway, &push).await {
+                errors.push(format!("push: {e:#}"));
+            }
+        }
+        if !errors.is_empty() {
+            bail!(
+                "Notifying {} failed: {}",
+                notification.account,
+                errors.join(", ")
+            );
+        }
+        Ok(())
+    }
+
+    async fn post(&self, url: &str, body: &impl Serialize) -> Result<()> {
+        self.http
+            .post(url)
+            .json(body)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+impl Module for NotificationModule {
+    type Context = NotificationModuleCtx;
+
+    async fn build(bus: SharedMessageBus, ctx: Self::Context) -> Result<Self> {
+        let db = DbPools::connect(&ctx.db_url, &ctx.database).await?;
+
+        sqlx::query(
+            r#"CREATE TABLE IF NOT EXISTS notification_preferences (
+                account TEXT PRIMARY KEY,
+                email TEXT NULL,
+                webhook_url TEXT NULL,
+                push_token TEXT NULL,
+                large_transfer_threshold BIGINT NULL,
+                on_large_transfer BOOLEAN NOT NULL DEFAULT FALSE,
+                on_session_key_added BOOLEAN NOT NULL DEFAULT FALSE,
+                on_failed_auth BOOLEAN NOT NULL DEFAULT FALSE,
+                updated_at TIMESTAMP NOT NULL DEFAULT NOW()
+            )"#,
+        )
+        .execute(&db.primary)
+        .await?;
+
+        let inner = Arc::new(NotificationModuleInner {
+            http: reqwest::Client::builder()
+                .timeout(Duration::from_secs(ctx.conf.dispatch_timeout_secs))
+                .build()
+                .context("building notification HTTP client")?,
+            conf: ctx.conf,
+            db,
+            verifier: SignatureVerifier::new(ctx.wallet_cn.clone(), ctx.indexer_url),
+            wallet_cn: ctx.wallet_cn,
+        });
+
+        let (router, openapi) = OpenApiRouter::default()
+            .routes(routes!(route_get_preferences))
+            .routes(routes!(route_update_preferences))
+            .split_for_parts();
+        let api = router.with_state(inner.clone());
+
+        if let Ok(mut guard) = ctx.api_ctx.router.lock() {
+            if let Some(router) = guard.take() {
+                guard.replace(router.merge(api));
+            }
+        }
+        if let Ok(mut guard) = ctx.api_ctx.openapi.lock() {
+            guard.merge(openapi);
+        }
+
+        Ok(Self {
+            bus: NotificationModuleBusClient::new_from_bus(bus.new_handle()).await,
+            inner,
+        })
+    }
+
+    async fn run(&mut self) -> Result<()> {
+        module_handle_messages! {
+            on_self self,
+            listen<CSIBusEvent<SharedWalletEvent>> event => {
+                self.inner.on_wallet_event(&event.event);
+            }
+            listen<CSIBusEvent<Wrap<Arc<[HistoryEvent]>>>> event => {
+                for history_event in event.event.0.iter() {
+                    self.inner.on_history_event(history_event);
+                }
+            }
+        };
+        Ok(())
+    }
+}
+
+// --------------------------------------------------------
+//     Routes
+// --------------------------------------------------------
+
+#[utoipa::path(
+    get,
+    path = "/notifications/{account}",
+    tag = "Notifications",
+    params(
+        ("account" = String, Path, description = "Account"),
+        AccountSignature
+    ),
+    responses(
+        (status = OK, description = "Notification preferences of the account", body = AccountNotificationPreferences),
+        (status = UNAUTHORIZED, description = "Missing or invalid account signature")
+    )
+)]
+async fn route_get_preferences(
+    State(ctx): State<Arc<NotificationModuleInner>>,
+    Path(account): Path<String>,
+    Query(signature): Query<AccountSignature>,
+) -> Result<Json<AccountNotificationPreferences>, AppError> {
+    ctx.check_signature(&account, &signature, None)
+        .await
+        .map_err(|e| AppError(StatusCode::UNAUTHORIZED, e))?;
+    ctx.account_preferences(&account)
+        .await
+        .map(Json)
+        .map_err(|e| AppError(StatusCode::INTERNAL_SERVER_ERROR, e))
+}
+
+#[utoipa::path(
+    put,
+    path = "/notifications/{account}",
+    tag = "Notifications",
+    params(
+        ("account" = String, Path, description = "Account")
+    ),
+    request_body = UpdatePreferencesBody,
+    responses(
+        (status = OK, description = "Preferences replaced", body = AccountNotificationPreferences),
+        (status = UNAUTHORIZED, description = "Missing or invalid account signature")
+    )
+)]
+async fn route_update_preferences(
+    State(ctx): State<Arc<NotificationModuleInner>>,
+    Path(account): Path<String>,
+    Json(body): Json<UpdatePreferencesBody>,
+) -> Result<Json<AccountNotificationPreferences>, AppError> {
+    ctx.check_signature(&account, &body.signature, Some(&body.preferences))
+        .await
+        .map_err(|e| AppError(StatusCode::UNAUTHORIZED, e))?;
+    ctx.update(&account, &body.preferences)
+        .await
+        .map(Json)
+        .map_err(|e| AppError(StatusCode::BAD_REQUEST, e))
+}
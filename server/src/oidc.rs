@@ -0,0 +1,806 @@
+use std::{
+    collections::BTreeMap,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use client_sdk::contract_indexer::utoipa;
+use client_sdk::contract_indexer::{
+    utoipa::ToSchema,
+    utoipa_axum::{router::OpenApiRouter, routes},
+};
+use client_sdk::AppError;
+use hyli_modules::{
+    bus::SharedMessageBus,
+    module_bus_client, module_handle_messages,
+    modules::{BuildApiContextInner, Module},
+};
+use p256::ecdsa::{signature::Verifier, Signature, VerifyingKey};
+use rsa::{BigUint, Pkcs1v15Sign, RsaPublicKey};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use server::conf::{OidcConf, OidcProviderConf};
+use sha2::{Digest, Sha256};
+use tokio::sync::RwLock;
+
+/// Discovers the OpenID providers listed in the configuration and keeps their signing keys, so
+/// a provider can be enabled for JWT wallet actions without code changes.
+///
+/// Each issuer's `.well-known/openid-configuration` gives its JWKS, refreshed every
+/// `refresh_interval_secs` and whenever a client asks for a key id the cache doesn't know.
+/// Keys the provider stopped publishing are still served for `retired_key_grace_secs`, so tokens
+/// signed just before a rotation keep verifying. The last `key_history_len` keys of each provider
+/// are kept by key id under `<data_directory>/oidc_keys.json`, and served again right after a
+/// restart, before the providers are reached.
+///
+/// `POST /oidc/verify` checks an ID token of any of the providers, and tells the user it
+/// identifies by the provider's `identity_claim`. `GET /api/jwks/{provider}` gives the modulus and
+/// exponent of each RSA key by key id, as `check_jwt` blobs are built with.
+pub struct OidcModule {
+    #[allow(unused)]
+    bus: OidcModuleBusClient,
+    inner: Arc<OidcModuleInner>,
+}
+
+pub struct OidcModuleCtx {
+    pub api_ctx: Arc<BuildApiContextInner>,
+    pub conf: OidcConf,
+    pub data_directory: PathBuf,
+}
+
+module_bus_client! {
+#[derive(Debug)]
+pub struct OidcModuleBusClient {
+}
+}
+
+struct OidcModuleInner {
+    conf: OidcConf,
+    keys_path: PathBuf,
+    http: reqwest::Client,
+    providers: RwLock<BTreeMap<String, ProviderState>>,
+}
+
+/// Subset of the OpenID provider metadata the wallet needs.
+#[derive(Debug, Clone, Deserialize)]
+struct DiscoveryDocument {
+    issuer: String,
+    jwks_uri: String,
+    authorization_endpoint: Option<String>,
+    #[serde(default)]
+    id_token_signing_alg_values_supported: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct JwkSet {
+    keys: Vec<serde_json::Value>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CachedKey {
+    kid: String,
+    /// The key as published, in JWK form.
+    jwk: serde_json::Value,
+    /// When the key was first fetched.
+    first_seen: u64,
+    /// When the provider stopped publishing the key.
+    retired_at: Option<u64>,
+}
+
+#[derive(Debug, Clone)]
+struct ProviderState {
+    conf: OidcProviderConf,
+    discovery: Option<DiscoveryDocument>,
+    keys: Vec<CachedKey>,
+    refreshed_at: Option<u64>,
+    last_attempt: Option<u64>,
+    last_error: Option<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct OidcProvider {
+    pub name: String,
+    pub issuer: String,
+    /// Audiences accepted for tokens of this provider.
+    pub client_ids: Vec<String>,
+    /// Claim identifying the user of a token.
+    pub identity_claim: String,
+    /// Signing algorithms accepted for tokens of this provider, among those it supports.
+    pub accepted_algorithms: Vec<String>,
+    pub authorization_endpoint: Option<String>,
+    pub signing_algorithms: Vec<String>,
+    /// Number of keys currently served, retired ones included.
+    pub keys: usize,
+    pub refreshed_at: Option<u64>,
+    pub last_error: Option<String>,
+}
+
+/// A key of a provider, as remembered in its history.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct OidcKeyRecord {
+    pub kid: String,
+    pub first_seen: u64,
+    pub retired_at: Option<u64>,
+    /// Whether the key is still served, which retired keys are during their grace period.
+    pub served: bool,
+}
+
+/// A served key of a provider, with the parameters the `check_jwt` blob of a token it signed is
+/// built with.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ProviderKey {
+    pub kid: String,
+    pub alg: Option<String>,
+    /// Modulus of an RSA key, base64url-encoded as in the JWK.
+    pub modulus: Option<String>,
+    /// Exponent of an RSA key, base64url-encoded as in the JWK.
+    pub exponent: Option<String>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct VerifyTokenBody {
+    /// ID token, in compact JWS form.
+    pub token: String,
+}
+
+/// An ID token that verified against one of the providers.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct VerifiedToken {
+    /// Provider that issued the token.
+    pub provider: String,
+    pub subject: String,
+    /// Value of the provider's identity claim.
+    pub identity: String,
+    pub expires_at: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct JwtHeader {
+    alg: String,
+    kid: Option<String>,
+}
+
+/// Keys of a provider, in JWK set form.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct OidcJwks {
+    #[schema(value_type = Vec<Object>)]
+    pub keys: Vec<serde_json::Value>,
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+fn same_issuer(a: &str, b: &str) -> bool {
+    a.trim_end_matches('/') == b.trim_end_matches('/')
+}
+
+fn decode_segment<T: DeserializeOwned>(segment: &str) -> Result<T> {
+    Ok(serde_json::from_slice(&URL_SAFE_NO_PAD.decode(segment)?)?)
+}
+
+/// Checks `signature` over `signed` with `jwk`, for the algorithms providers are allowed.
+fn verify_signature(
+    alg: &str,
+    jwk: &serde_json::Value,
+    signed: &[u8],
+    signature: &[u8],
+) -> Result<()> {
+    let param = |name: &str| -> Result<Vec<u8>> {
+        let value = jwk
+            .get(name)
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| anyhow!("JWK has no {name}"))?;
+        URL_SAFE_NO_PAD
+            .decode(value)
+            .with_context(|| format!("decoding JWK {name}"))
+    };
+    match alg {
+        "RS256" => {
+            let key = RsaPublicKey::new(
+                BigUint::from_bytes_be(&param("n")?),
+                BigUint::from_bytes_be(&param("e")?),
+            )
+            .context("invalid RSA key")?;
+            key.verify(
+                Pkcs1v15Sign::new::<Sha256>(),
+                &Sha256::digest(signed),
+                signature,
+            )
+            .map_err(|_| anyhow!("invalid JWT signature"))
+        }
+        "ES256" => {
+            let mut sec1 = vec![0x04];
+            sec1.extend(param("x")?);
+            sec1.extend(param("y")?);
+            let key = VerifyingKey::from_sec1_bytes(&sec1)
+                .map_err(|e| anyhow!("invalid P-256 key: {e}"))?;
+            let signature = Signature::from_slice(signature)
+                .map_err(|e| anyhow!("invalid ES256 signature: {e}"))?;
+            key.verify(signed, &signature)
+                .map_err(|_| anyhow!("invalid JWT signature"))
+        }
+        alg => bail!("unsupported JWT algorithm {alg}"),
+    }
+}
+
+/// URL of the discovery document of `issuer`, as OpenID Connect Discovery defines it.
+fn discovery_url(issuer: &str) -> String {
+    format!(
+        "{}/.well-known/openid-configuration",
+        issuer.trim_end_matches('/')
+    )
+}
+
+impl ProviderState {
+    fn new(conf: OidcProviderConf) -> Self {
+        Self {
+            conf,
+            discovery: None,
+            keys: vec![],
+            refreshed_at: None,
+            last_attempt: None,
+            last_error: None,
+        }
+    }
+
+    /// Merges a freshly fetched key set: new keys are added and keys that left it are retired.
+    /// Beyond `history_len` keys, those retired the longest ago are forgotten.
+    fn rotate(&mut self, fetched: Vec<serde_json::Value>, now: u64, history_len: usize) {
+        let mut fetched: BTreeMap<String, serde_json::Value> = fetched
+            .into_iter()
+            .filter_map(|jwk| {
+                let kid = jwk.get("kid")?.as_str()?.to_string();
+                Some((kid, jwk))
+            })
+            .collect();
+
+        for key in &mut self.keys {
+            match fetched.remove(&key.kid) {
+                Some(jwk) => {
+                    key.jwk = jwk;
+                    key.retired_at = None;
+                }
+                None => {
+                    if key.retired_at.is_none() {
+                        tracing::info!(
+                            "Key {} of OIDC provider {} was rotated out",
+                            key.kid,
+                            self.conf.name
+                        );
+                    }
+                    key.retired_at.get_or_insert(now);
+                }
+            }
+        }
+        for (kid, jwk) in fetched {
+            tracing::info!("New key {} for OIDC provider {}", kid, self.conf.name);
+            self.keys.push(CachedKey {
+                kid,
+                jwk,
+                first_seen: now,
+                retired_at: None,
+            });
+        }
+        while self.keys.len() > history_len {
+            let Some((_, oldest)) = self
+                .keys
+                .iter()
+                .enumerate()
+                .filter_map(|(i, k)| Some((k.retired_at?, i)))
+                .min()
+            else {
+                break;
+            };
+            self.keys.remove(oldest);
+        }
+    }
+
+    /// Keys still served: published ones, and retired ones during their grace period.
+    fn served(&self, now: u64, grace_secs: u64) -> impl Iterator<Item = &CachedKey> {
+        self.keys
+            .iter()
+            .filter(move |k| k.retired_at.is_none_or(|at| now < at + grace_secs))
+    }
+
+    fn identity_claim(&self) -> &str {
+        self.conf.identity_claim.as_deref().unwrap_or("email")
+    }
+
+    fn accepts(&self, alg: &str) -> bool {
+        if self.conf.algorithms.is_empty() {
+            alg == "RS256"
+        } else {
+            self.conf.algorithms.iter().any(|a| a == alg)
+        }
+    }
+
+    fn summary(&self, now: u64, grace_secs: u64) -> OidcProvider {
+        OidcProvider {
+            name: self.conf.name.clone(),
+            issuer: self.conf.issuer.clone(),
+            client_ids: self.conf.client_ids.clone(),
+            identity_claim: self.identity_claim().to_string(),
+            accepted_algorithms: if self.conf.algorithms.is_empty() {
+                vec!["RS256".to_string()]
+            } else {
+                self.conf.algorithms.clone()
+            },
+            authorization_endpoint: self
+                .discovery
+                .as_ref()
+                .and_then(|d| d.authorization_endpoint.clone()),
+            signing_algorithms: self
+                .discovery
+                .as_ref()
+                .map(|d| d.id_token_signing_alg_values_supported.clone())
+                .unwrap_or_default(),
+            keys: self.served(now, grace_secs).count(),
+            refreshed_at: self.refreshed_at,
+            last_error: self.last_error.clone(),
+        }
+    }
+}
+
+impl OidcModuleInner {
+    async fn fetch(&self, conf: &OidcProviderConf) -> Result<(DiscoveryDocument, JwkSet)> {
+        let discovery: DiscoveryDocument = self
+            .http
+            .get(discovery_url(&conf.issuer))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+            .context("fetching discovery document")?;
+        if !same_issuer(&discovery.issuer, &conf.issuer) {
+            bail!(
+                "discovery document is for issuer {}, expected {}",
+                discovery.issuer,
+                conf.issuer
+            );
+        }
+        let jwks: JwkSet = self
+            .http
+            .get(&discovery.jwks_uri)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+            .context("fetching JWKS")?;
+        Ok((discovery, jwks))
+    }
+
+    async fn refresh(&self, name: &str) -> Result<()> {
+        let conf = {
+            let mut providers = self.providers.write().await;
+            let provider = providers
+                .get_mut(name)
+                .ok_or_else(|| anyhow!("Unknown OIDC provider {name}"))?;
+            provider.last_attempt = Some(now_secs());
+            provider.conf.clone()
+        };
+
+        // The lock isn't held across the requests, a slow provider mustn't block lookups.
+        let fetched = self.fetch(&conf).await;
+
+        let mut providers = self.providers.write().await;
+        let provider = providers
+            .get_mut(name)
+            .ok_or_else(|| anyhow!("Unknown OIDC provider {name}"))?;
+        match fetched {
+            Ok((discovery, jwks)) => {
+                let now = now_secs();
+                provider.rotate(jwks.keys, now, self.conf.key_history_len);
+                provider.discovery = Some(discovery);
+                provider.refreshed_at = Some(now);
+                provider.last_error = None;
+                let history = providers
+                    .iter()
+                    .map(|(name, provider)| (name.clone(), provider.keys.clone()))
+                    .collect();
+                drop(providers);
+                self.save(&history).await
+            }
+            Err(e) => {
+                provider.last_error = Some(format!("{e:#}"));
+                Err(e.context(format!("refreshing OIDC provider {name}")))
+            }
+        }
+    }
+
+    async fn refresh_all(&self) {
+        let names: Vec<String> = self.providers.read().await.keys().cloned().collect();
+        for name in names {
+            if let Err(e) = self.refresh(&name).await {
+                tracing::warn!("{:#}", e);
+            }
+        }
+    }
+
+    /// Key history of every provider, as last saved.
+    async fn load(path: &std::path::Path) -> Result<BTreeMap<String, Vec<CachedKey>>> {
+        match tokio::fs::read(path).await {
+            Ok(bytes) => serde_json::from_slice(&bytes)
+                .with_context(|| format!("decoding OIDC keys {}", path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+            Err(e) => Err(e).with_context(|| format!("reading OIDC keys {}", path.display())),
+        }
+    }
+
+    async fn save(&self, history: &BTreeMap<String, Vec<CachedKey>>) -> Result<()> {
+        let tmp = self.keys_path.with_extension("tmp");
+        tokio::fs::write(&tmp, serde_json::to_vec(history)?).await?;
+        tokio::fs::rename(&tmp, &self.keys_path)
+            .await
+            .with_context(|| format!("writing OIDC keys {}", self.keys_path.display()))
+    }
+
+    async fn jwks(&self, name: &str) -> Result<OidcJwks> {
+        let providers = self.providers.read().await;
+        let provider = providers
+            .get(name)
+            .ok_or_else(|| anyhow!("Unknown OIDC provider {name}"))?;
+        Ok(OidcJwks {
+            keys: provider
+                .served(now_secs(), self.conf.retired_key_grace_secs)
+                .map(|k| k.jwk.clone())
+                .collect(),
+        })
+    }
+
+    async fn provider_keys(&self, name: &str) -> Result<Vec<ProviderKey>> {
+        let param = |jwk: &serde_json::Value, name: &str| {
+            jwk.get(name).and_then(|v| v.as_str()).map(str::to_string)
+        };
+        let providers = self.providers.read().await;
+        let provider = providers
+            .get(name)
+            .ok_or_else(|| anyhow!("Unknown OIDC provider {name}"))?;
+        Ok(provider
+            .served(now_secs(), self.conf.retired_key_grace_secs)
+            .map(|k| ProviderKey {
+                kid: k.kid.clone(),
+                alg: param(&k.jwk, "alg"),
+                modulus: param(&k.jwk, "n"),
+                exponent: param(&k.jwk, "e"),
+            })
+            .collect())
+    }
+
+    async fn history(&self, name: &str) -> Result<Vec<OidcKeyRecord>> {
+        let providers = self.providers.read().await;
+        let provider = providers
+            .get(name)
+            .ok_or_else(|| anyhow!("Unknown OIDC provider {name}"))?;
+        let now = now_secs();
+        Ok(provider
+            .keys
+            .iter()
+            .map(|k| OidcKeyRecord {
+                kid: k.kid.clone(),
+                first_seen: k.first_seen,
+                retired_at: k.retired_at,
+                served: k
+                    .retired_at
+                    .is_none_or(|at| now < at + self.conf.retired_key_grace_secs),
+            })
+            .collect())
+    }
+
+    /// Looks a key up, refreshing the provider first when the id is unknown, as happens right
+    /// after it rotated its keys. Refreshes are spaced by `min_refresh_interval_secs` so unknown
+    /// ids can't be used to hammer the provider.
+    async fn key(&self, name: &str, kid: &str) -> Result<serde_json::Value> {
+        let last_attempt = {
+            let providers = self.providers.read().await;
+            let provider = providers
+                .get(name)
+                .ok_or_else(|| anyhow!("Unknown OIDC provider {name}"))?;
+            if let Some(key) = provider
+                .served(now_secs(), self.conf.retired_key_grace_secs)
+                .find(|k| k.kid == kid)
+            {
+                return Ok(key.jwk.clone());
+            }
+            provider.last_attempt
+        };
+
+        if last_attempt.is_none_or(|at| now_secs() >= at + self.conf.min_refresh_interval_secs) {
+            self.refresh(name).await?;
+        }
+
+        self.providers
+            .read()
+            .await
+            .get(name)
+            .and_then(|p| {
+                p.served(now_secs(), self.conf.retired_key_grace_secs)
+                    .find(|k| k.kid == kid)
+                    .map(|k| k.jwk.clone())
+            })
+            .ok_or_else(|| anyhow!("Unknown key {kid} for OIDC provider {name}"))
+    }
+
+    /// Verifies an ID token: its issuer must be one of the providers, its algorithm one the
+    /// provider accepts and its signature from one of the provider's keys. It must be meant for
+    /// one of the wallet's client ids, and not have expired.
+    async fn verify(&self, token: &str) -> Result<VerifiedToken> {
+        let (signed, signature) = token
+            .rsplit_once('.')
+            .ok_or_else(|| anyhow!("malformed JWT"))?;
+        let (header, claims) = signed
+            .split_once('.')
+            .ok_or_else(|| anyhow!("malformed JWT"))?;
+        let header: JwtHeader = decode_segment(header).context("decoding JWT header")?;
+        let claims: serde_json::Value = decode_segment(claims).context("decoding JWT claims")?;
+        let claim = |name: &str| claims.get(name).and_then(|v| v.as_str());
+
+        let issuer = claim("iss").ok_or_else(|| anyhow!("JWT has no issuer"))?;
+        let (name, identity_claim) = {
+            let providers = self.providers.read().await;
+            let provider = providers
+                .values()
+                .find(|p| same_issuer(&p.conf.issuer, issuer))
+                .ok_or_else(|| anyhow!("Issuer {issuer} is not an OIDC provider"))?;
+            ensure!(
+                provider.accepts(&header.alg),
+                "Algorithm {} is not accepted for OIDC provider {}",
+                header.alg,
+                provider.conf.name
+            );
+            ensure!(
+                match claims.get("aud") {
+                    Some(serde_json::Value::String(aud)) => provider.conf.client_ids.contains(aud),
+                    Some(serde_json::Value::Array(auds)) => auds.iter().any(|aud| aud
+                        .as_str()
+                        .is_some_and(|aud| provider.conf.client_ids.iter().any(|id| id == aud))),
+                    _ => false,
+                },
+                "JWT is not meant for the wallet"
+            );
+            (
+                provider.conf.name.clone(),
+                provider.identity_claim().to_string(),
+            )
+        };
+
+        let kid = header.kid.ok_or_else(|| anyhow!("JWT has no key id"))?;
+        let jwk = self.key(&name, &kid).await?;
+        let signature = URL_SAFE_NO_PAD
+            .decode(signature)
+            .context("decoding JWT signature")?;
+        verify_signature(&header.alg, &jwk, signed.as_bytes(), &signature)?;
+
+        let expires_at = claims
+            .get("exp")
+            .and_then(|v| v.as_u64())
+            .ok_or_else(|| anyhow!("JWT has no expiration"))?;
+        ensure!(expires_at > now_secs(), "JWT has expired");
+        // Apple sends it as a string.
+        if let Some(verified) = claims.get("email_verified") {
+            ensure!(
+                verified.as_bool() == Some(true) || verified.as_str() == Some("true"),
+                "JWT email isn't verified"
+            );
+        }
+        let identity = claim(&identity_claim)
+            .ok_or_else(|| anyhow!("JWT has no {identity_claim} claim"))?
+            .to_string();
+        Ok(VerifiedToken {
+            provider: name,
+            subject: claim("sub").unwrap_or_default().to_string(),
+            identity,
+            expires_at,
+        })
+    }
+}
+
+impl Module for OidcModule {
+    type Context = OidcModuleCtx;
+
+    async fn build(bus: SharedMessageBus, ctx: Self::Context) -> Result<Self> {
+        let keys_path = ctx.data_directory.join("oidc_keys.json");
+        let mut history = OidcModuleInner::load(&keys_path).await?;
+        let providers = ctx
+            .conf
+            .providers
+            .iter()
+            .map(|p| {
+                let mut state = ProviderState::new(p.clone());
+                state.keys = history.remove(&p.name).unwrap_or_default();
+                (p.name.clone(), state)
+            })
+            .collect();
+        let inner = Arc::new(OidcModuleInner {
+            conf: ctx.conf,
+            keys_path,
+            http: reqwest::Client::builder()
+                .timeout(Duration::from_secs(10))
+                .build()?,
+            providers: RwLock::new(providers),
+        });
+
+        let (router, openapi) = OpenApiRouter::default()
+            .routes(routes!(route_providers))
+            .routes(routes!(route_jwks))
+            .routes(routes!(route_key_history))
+            .routes(routes!(route_key))
+            .routes(routes!(route_verify))
+            .routes(routes!(route_provider_keys))
+            .split_for_parts();
+        let api = router.with_state(inner.clone());
+
+        if let Ok(mut guard) = ctx.api_ctx.router.lock() {
+            if let Some(router) = guard.take() {
+                guard.replace(router.merge(api));
+            }
+        }
+        if let Ok(mut guard) = ctx.api_ctx.openapi.lock() {
+            guard.merge(openapi);
+        }
+
+        Ok(Self {
+            bus: OidcModuleBusClient::new_from_bus(bus.new_handle()).await,
+            inner,
+        })
+    }
+
+    async fn run(&mut self) -> Result<()> {
+        // The first tick completes immediately, fetching every provider at startup.
+        let mut interval =
+            tokio::time::interval(Duration::from_secs(self.inner.conf.refresh_interval_secs));
+
+        module_handle_messages! {
+            on_self self,
+            _ = interval.tick() => {
+                self.inner.refresh_all().await;
+            }
+        };
+        Ok(())
+    }
+}
+
+// --------------------------------------------------------
+//     Routes
+// --------------------------------------------------------
+
+#[utoipa::path(
+    get,
+    path = "/oidc/providers",
+    tag = "OIDC",
+    responses(
+        (status = OK, description = "Configured OpenID providers and the state of their keys", body = Vec<OidcProvider>)
+    )
+)]
+async fn route_providers(State(ctx): State<Arc<OidcModuleInner>>) -> Json<Vec<OidcProvider>> {
+    let now = now_secs();
+    Json(
+        ctx.providers
+            .read()
+            .await
+            .values()
+            .map(|p| p.summary(now, ctx.conf.retired_key_grace_secs))
+            .collect(),
+    )
+}
+
+#[utoipa::path(
+    get,
+    path = "/oidc/providers/{name}/jwks",
+    tag = "OIDC",
+    params(
+        ("name" = String, Path, description = "Provider name")
+    ),
+    responses(
+        (status = OK, description = "Current and recently retired keys of the provider", body = OidcJwks),
+        (status = NOT_FOUND, description = "Unknown provider")
+    )
+)]
+async fn route_jwks(
+    State(ctx): State<Arc<OidcModuleInner>>,
+    Path(name): Path<String>,
+) -> Result<Json<OidcJwks>, AppError> {
+    ctx.jwks(&name)
+        .await
+        .map(Json)
+        .map_err(|e| AppError(StatusCode::NOT_FOUND, e))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/jwks/{provider}",
+    tag = "OIDC",
+    params(
+        ("provider" = String, Path, description = "Provider name")
+    ),
+    responses(
+        (status = OK, description = "Current and recently retired keys of the provider, with their RSA parameters", body = Vec<ProviderKey>),
+        (status = NOT_FOUND, description = "Unknown provider")
+    )
+)]
+async fn route_provider_keys(
+    State(ctx): State<Arc<OidcModuleInner>>,
+    Path(provider): Path<String>,
+) -> Result<Json<Vec<ProviderKey>>, AppError> {
+    ctx.provider_keys(&provider)
+        .await
+        .map(Json)
+        .map_err(|e| AppError(StatusCode::NOT_FOUND, e))
+}
+
+#[utoipa::path(
+    get,
+    path = "/oidc/providers/{name}/keys",
+    tag = "OIDC",
+    params(
+        ("name" = String, Path, description = "Provider name")
+    ),
+    responses(
+        (status = OK, description = "Keys the provider published, by key id, oldest first", body = Vec<OidcKeyRecord>),
+        (status = NOT_FOUND, description = "Unknown provider")
+    )
+)]
+async fn route_key_history(
+    State(ctx): State<Arc<OidcModuleInner>>,
+    Path(name): Path<String>,
+) -> Result<Json<Vec<OidcKeyRecord>>, AppError> {
+    ctx.history(&name)
+        .await
+        .map(Json)
+        .map_err(|e| AppError(StatusCode::NOT_FOUND, e))
+}
+
+#[utoipa::path(
+    get,
+    path = "/oidc/providers/{name}/keys/{kid}",
+    tag = "OIDC",
+    params(
+        ("name" = String, Path, description = "Provider name"),
+        ("kid" = String, Path, description = "Key id, from the `kid` header of the JWT")
+    ),
+    responses(
+        (status = OK, description = "The key, in JWK form", body = Object),
+        (status = NOT_FOUND, description = "Unknown provider or key")
+    )
+)]
+async fn route_key(
+    State(ctx): State<Arc<OidcModuleInner>>,
+    Path((name, kid)): Path<(String, String)>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    ctx.key(&name, &kid)
+        .await
+        .map(Json)
+        .map_err(|e| AppError(StatusCode::NOT_FOUND, e))
+}
+
+#[utoipa::path(
+    post,
+    path = "/oidc/verify",
+    tag = "OIDC",
+    request_body = VerifyTokenBody,
+    responses(
+        (status = OK, description = "The token is valid, returns the user it identifies", body = VerifiedToken),
+        (status = UNAUTHORIZED, description = "Unknown issuer, bad signature, wrong audience or expired token")
+    )
+)]
+async fn route_verify(
+    State(ctx): State<Arc<OidcModuleInner>>,
+    Json(body): Json<VerifyTokenBody>,
+) -> Result<Json<VerifiedToken>, AppError> {
+    ctx.verify(&body.token)
+        .await
+        .map(Json)
+        .map_err(|e| AppError(StatusCode::UNAUTHORIZED, e))
+}
@@ -0,0 +1,276 @@
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    path::PathBuf,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use borsh::{BorshDeserialize, BorshSerialize};
+use chrono::Utc;
+use client_sdk::rest_client::NodeApiClient;
+use sdk::{
+    api::{APIRegisterContract, APIStaking, NodeInfo},
+    BlobTransaction, BlockHeight, ConsensusInfo, Contract, ContractName, Hashed, ProofTransaction,
+    TxHash, UnsettledBlobTransaction,
+};
+use serde::Serialize;
+
+use crate::conf::ProofSubmissionConf;
+
+/// Proof transaction whose submission kept failing.
+#[derive(BorshSerialize, BorshDeserialize)]
+struct DeadLetter {
+    tx: ProofTransaction,
+    error: String,
+    failed_at_ms: i64,
+}
+
+#[derive(Serialize)]
+pub struct DeadLetterStatus {
+    pub tx_hash: String,
+    pub contract_name: String,
+    /// Error of the last submission.
+    pub error: String,
+    pub failed_at_ms: i64,
+}
+
+#[derive(Serialize)]
+pub struct ResubmitReport {
+    /// Proof transactions the node accepted, no longer kept.
+    pub resubmitted: Vec<String>,
+    /// Proof transactions the node refused again, still kept.
+    pub failed: Vec<DeadLetterStatus>,
+}
+
+/// Proofs of one contract whose submission kept failing, kept until an admin resubmits them.
+///
+/// Each proof is stored in its own file of `dir`, so they survive a restart.
+pub struct DeadLetters {
+    dir: PathBuf,
+    node: Arc<dyn NodeApiClient + Send + Sync>,
+    proofs: Mutex<BTreeMap<String, DeadLetter>>,
+}
+
+impl DeadLetters {
+    /// Loads the proofs stored in `dir`, resubmitted to `node`.
+    pub fn open(dir: PathBuf, node: Arc<dyn NodeApiClient + Send + Sync>) -> Result<Self> {
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("creating dead letter directory {}", dir.display()))?;
+        let mut proofs = BTreeMap::new();
+        for entry in std::fs::read_dir(&dir)
+            .with_context(|| format!("reading dead letter directory {}", dir.display()))?
+        {
+            let path = entry?.path();
+            match std::fs::read(&path)
+                .map_err(anyhow::Error::from)
+                .and_then(|bytes| Ok(borsh::from_slice::<DeadLetter>(&bytes)?))
+            {
+                Ok(dead_letter) => {
+                    proofs.insert(dead_letter.tx.hashed().0, dead_letter);
+                }
+                Err(e) => tracing::warn!("Skipping dead letter {}: {e:#}", path.display()),
+            }
+        }
+        if !proofs.is_empty() {
+            tracing::warn!(
+                "{} proofs failed to be submitted, resubmit them through the admin API",
+                proofs.len()
+            );
+        }
+        Ok(Self {
+            dir,
+            node,
+            proofs: Mutex::new(proofs),
+        })
+    }
+
+    async fn store(&self, tx: ProofTransaction, error: String) {
+        let tx_hash = tx.hashed().0;
+        let dead_letter = DeadLetter {
+            tx,
+            error,
+            failed_at_ms: Utc::now().timestamp_millis(),
+        };
+        match borsh::to_vec(&dead_letter) {
+            Ok(bytes) => {
+                if let Err(e) = tokio::fs::write(self.dir.join(&tx_hash), bytes).await {
+                    tracing::warn!("Failed to store dead letter {tx_hash}: {e:#}");
+                }
+            }
+            Err(e) => tracing::warn!("Failed to encode dead letter {tx_hash}: {e:#}"),
+        }
+        self.lock().insert(tx_hash, dead_letter);
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, BTreeMap<String, DeadLetter>> {
+        self.proofs.lock().expect("dead letters poisoned")
+    }
+
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lock().is_empty()
+    }
+
+    pub fn list(&self) -> Vec<DeadLetterStatus> {
+        self.lock()
+            .iter()
+            .map(|(tx_hash, dead_letter)| DeadLetterStatus {
+                tx_hash: tx_hash.clone(),
+                contract_name: dead_letter.tx.contract_name.0.clone(),
+                error: dead_letter.error.clone(),
+                failed_at_ms: dead_letter.failed_at_ms,
+            })
+            .collect()
+    }
+
+    /// Submits every kept proof once more, forgetting those the node accepts.
+    pub async fn resubmit(&self) -> ResubmitReport {
+        let txs: Vec<(String, ProofTransaction)> = self
+            .lock()
+            .iter()
+            .map(|(tx_hash, dead_letter)| (tx_hash.clone(), dead_letter.tx.clone()))
+            .collect();
+        let mut resubmitted = Vec::new();
+        for (tx_hash, tx) in txs {
+            match self.node.send_tx_proof(tx).await {
+                Ok(_) => {
+                    self.lock().remove(&tx_hash);
+                    if let Err(e) = tokio::fs::remove_file(self.dir.join(&tx_hash)).await {
+                        tracing::warn!("Failed to remove dead letter {tx_hash}: {e:#}");
+                    }
+                    resubmitted.push(tx_hash);
+                }
+                Err(e) => {
+                    if let Some(dead_letter) = self.lock().get_mut(&tx_hash) {
+                        dead_letter.error = format!("{e:#}");
+                        dead_letter.failed_at_ms = Utc::now().timestamp_millis();
+                    }
+                }
+            }
+        }
+        ResubmitReport {
+            failed: self
+                .list()
+                .into_iter()
+                .filter(|status| !resubmitted.contains(&status.tx_hash))
+                .collect(),
+            resubmitted,
+        }
+    }
+}
+
+/// Node client retrying proof submissions with an exponential backoff, other calls are passed
+/// through. Proofs still refused after the last retry are kept in [`DeadLetters`].
+pub struct RetryingNodeClient {
+    inner: Arc<dyn NodeApiClient + Send + Sync>,
+    conf: ProofSubmissionConf,
+    dead_letters: Arc<DeadLetters>,
+}
+
+impl RetryingNodeClient {
+    pub fn new(
+        inner: Arc<dyn NodeApiClient + Send + Sync>,
+        conf: ProofSubmissionConf,
+        dead_letters: Arc<DeadLetters>,
+    ) -> Self {
+        Self {
+            inner,
+            conf,
+            dead_letters,
+        }
+    }
+
+    async fn submit_proof(&self, tx: ProofTransaction) -> Result<TxHash> {
+        let max_backoff = Duration::from_millis(self.conf.max_backoff_ms);
+        let mut backoff = Duration::from_millis(self.conf.initial_backoff_ms).min(max_backoff);
+        let mut retries = 0;
+        loop {
+            let e = match self.inner.send_tx_proof(tx.clone()).await {
+                Ok(tx_hash) => return Ok(tx_hash),
+                Err(e) => e,
+            };
+            if retries >= self.conf.max_retries {
+                tracing::error!(
+                    "Proof of {} refused after {retries} retries, kept as a dead letter: {e:#}",
+                    tx.contract_name
+                );
+                self.dead_letters.store(tx, format!("{e:#}")).await;
+                return Err(e);
+            }
+            retries += 1;
+            tracing::warn!(
+                "Proof of {} refused, retry {retries} in {backoff:?}: {e:#}",
+                tx.contract_name
+            );
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(max_backoff);
+        }
+    }
+}
+
+impl NodeApiClient for RetryingNodeClient {
+    fn register_contract(
+        &self,
+        tx: APIRegisterContract,
+    ) -> Pin<Box<dyn Future<Output = Result<TxHash>> + Send + '_>> {
+        self.inner.register_contract(tx)
+    }
+
+    fn send_tx_blob(
+        &self,
+        tx: BlobTransaction,
+    ) -> Pin<Box<dyn Future<Output = Result<TxHash>> + Send + '_>> {
+        self.inner.send_tx_blob(tx)
+    }
+
+    fn send_tx_proof(
+        &self,
+        tx: ProofTransaction,
+    ) -> Pin<Box<dyn Future<Output = Result<TxHash>> + Send + '_>> {
+        Box::pin(self.submit_proof(tx))
+    }
+
+    fn get_consensus_info(
+        &self,
+    ) -> Pin<Box<dyn Future<Output = Result<ConsensusInfo>> + Send + '_>> {
+        self.inner.get_consensus_info()
+    }
+
+    fn get_consensus_staking_state(
+        &self,
+    ) -> Pin<Box<dyn Future<Output = Result<APIStaking>> + Send + '_>> {
+        self.inner.get_consensus_staking_state()
+    }
+
+    fn get_node_info(&self) -> Pin<Box<dyn Future<Output = Result<NodeInfo>> + Send + '_>> {
+        self.inner.get_node_info()
+    }
+
+    fn metrics(&self) -> Pin<Box<dyn Future<Output = Result<String>> + Send + '_>> {
+        self.inner.metrics()
+    }
+
+    fn get_block_height(&self) -> Pin<Box<dyn Future<Output = Result<BlockHeight>> + Send + '_>> {
+        self.inner.get_block_height()
+    }
+
+    fn get_contract(
+        &self,
+        contract_name: ContractName,
+    ) -> Pin<Box<dyn Future<Output = Result<Contract>> + Send + '_>> {
+        self.inner.get_contract(contract_name)
+    }
+
+    fn get_unsettled_tx(
+        &self,
+        blob_tx_hash: TxHash,
+    ) -> Pin<Box<dyn Future<Output = Result<UnsettledBlobTransaction>> + Send + '_>> {
+        self.inner.get_unsettled_tx(blob_tx_hash)
+    }
+}
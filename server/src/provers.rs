@@ -0,0 +1,1050 @@
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    path::PathBuf,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Context, Result};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    routing::{get, post},
+    Json, Router,
+};
+use borsh::BorshSerialize;
+use client_sdk::{
+    helpers::{risc0::Risc0Prover, ClientSdkProver},
+    rest_client::NodeApiClient,
+    AppError,
+};
+use opentelemetry::{
+    metrics::{Counter, Histogram},
+    KeyValue,
+};
+use sdk::{info, Calldata, ContractName, Identity, ProgramId, ProofData, ProofTransaction, TxHash};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tokio::sync::{broadcast, watch, Notify, Semaphore, SemaphorePermit};
+use tracing::Instrument;
+
+use crate::audit::{AuditKind, AuditRecorder};
+use crate::conf::ProverAccelerator;
+use crate::correlation::CorrelationId;
+use crate::proof_submission::{DeadLetterStatus, DeadLetters, ResubmitReport};
+
+/// Checks that the configured accelerator is usable by this build, and logs the one in use.
+pub fn check_accelerator(accelerator: ProverAccelerator) -> Result<()> {
+    match accelerator {
+        ProverAccelerator::Cuda if !cfg!(feature = "cuda") => {
+            anyhow::bail!(
+                "CUDA proving is configured but the server was built without the `cuda` feature"
+            )
+        }
+        ProverAccelerator::Metal if !cfg!(all(target_os = "macos", target_arch = "aarch64")) => {
+            anyhow::bail!("Metal proving is configured but is only available on Apple silicon")
+        }
+        ProverAccelerator::Cpu if cfg!(feature = "cuda") => {
+            tracing::warn!(
+                "The server was built with the `cuda` feature, proofs will run on the GPU"
+            )
+        }
+        _ => info!("🖥️ Proving with {:?} acceleration", accelerator),
+    }
+    Ok(())
+}
+
+/// The prover stack used by the autoprovers: RISC Zero, on Bonsai or locally, scheduled behind
+/// the proof cache, with metrics, batches split to be proven concurrently, and admin pause
+/// controls in front.
+pub type AppProver =
+    ControlledProver<SplitProver<MeteredProver<CachedProver<ScheduledProver<FallbackProver>>>>>;
+
+/// Builds the [`AppProver`] for a contract's guest program.
+#[allow(clippy::too_many_arguments)]
+pub fn app_prover(
+    contract_name: &ContractName,
+    elf: &[u8],
+    program_id: [u8; 32],
+    cache_dir: Option<PathBuf>,
+    scheduling: Option<(Arc<ProvingScheduler>, ProofPriority)>,
+    splitting: Option<BatchSplitting>,
+    bonsai: bool,
+    controls: &ProverControls,
+) -> Result<AppProver> {
+    let control = controls.register(contract_name);
+    Ok(ControlledProver::new(
+        SplitProver::new(
+            MeteredProver::new(
+                CachedProver::new(
+                    ScheduledProver::new(
+                        FallbackProver::new(elf, program_id, bonsai, control.clone()),
+                        scheduling,
+                    ),
+                    &program_id,
+                    cache_dir,
+                )?,
+                contract_name,
+            ),
+            contract_name,
+            program_id,
+            splitting,
+        ),
+        control,
+    ))
+}
+
+/// Backend a proof was generated on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProvingBackend {
+    Local,
+    Bonsai,
+}
+
+impl ProvingBackend {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ProvingBackend::Local => "local",
+            ProvingBackend::Bonsai => "bonsai",
+        }
+    }
+}
+
+/// Checks that Bonsai can be reached when contracts are configured to be proven on it.
+pub fn check_bonsai(contracts: &[ContractName]) -> Result<()> {
+    if contracts.is_empty() {
+        return Ok(());
+    }
+    for var in ["BONSAI_API_URL", "BONSAI_API_KEY"] {
+        if std::env::var(var).is_err() {
+            anyhow::bail!("Remote proving is configured but {var} is not set");
+        }
+    }
+    info!("☁️ Proving {:?} on Bonsai", contracts);
+    Ok(())
+}
+
+/// Proves with RISC Zero on Bonsai when enabled, and locally otherwise or when Bonsai fails.
+///
+/// The backend each proof was generated on is logged, counted and shown on the admin API.
+pub struct FallbackProver {
+    local: Risc0Prover,
+    elf: Vec<u8>,
+    bonsai: bool,
+    control: Arc<ProverControl>,
+    proofs: Counter<u64>,
+}
+
+impl FallbackProver {
+    pub fn new(
+        elf: &[u8],
+        program_id: [u8; 32],
+        bonsai: bool,
+        control: Arc<ProverControl>,
+    ) -> Self {
+        Self {
+            local: Risc0Prover::new(elf.to_vec(), program_id),
+            elf: elf.to_vec(),
+            bonsai,
+            control,
+            proofs: opentelemetry::global::meter("wallet_prover")
+                .u64_counter("prover_backend_proofs_total")
+                .with_description("Number of proofs generated, per proving backend")
+                .build(),
+        }
+    }
+
+    async fn prove_on_bonsai(
+        &self,
+        commitment_metadata: Vec<u8>,
+        calldata: Vec<Calldata>,
+    ) -> Result<ProofData> {
+        let input = hyli_bonsai_runner::as_input_data(&(commitment_metadata, calldata))?;
+        let receipt = hyli_bonsai_runner::run_bonsai(&self.elf, input).await?;
+        Ok(ProofData(
+            borsh::to_vec(&receipt).context("encoding Bonsai receipt")?,
+        ))
+    }
+
+    fn report(&self, backend: ProvingBackend) {
+        let contract_name = &self.control.contract_name;
+        info!(
+            "🧾 Proof of {} generated on {}",
+            contract_name,
+            backend.as_str()
+        );
+        self.proofs.add(
+            1,
+            &[
+                KeyValue::new("contract", contract_name.0.clone()),
+                KeyValue::new("backend", backend.as_str()),
+            ],
+        );
+        *self
+            .control
+            .last_backend
+            .lock()
+            .expect("prover backend poisoned") = Some(backend);
+    }
+}
+
+impl ClientSdkProver<Vec<Calldata>> for FallbackProver {
+    fn prove(
+        &self,
+        commitment_metadata: Vec<u8>,
+        calldata: Vec<Calldata>,
+    ) -> Pin<Box<dyn Future<Output = Result<ProofData>> + Send + '_>> {
+        Box::pin(async move {
+            if self.bonsai {
+                match self
+                    .prove_on_bonsai(commitment_metadata.clone(), calldata.clone())
+                    .await
+                {
+                    Ok(proof) => {
+                        self.report(ProvingBackend::Bonsai);
+                        return Ok(proof);
+                    }
+                    Err(e) => tracing::warn!(
+                        "Bonsai failed to prove for {}, proving locally: {e:#}",
+                        self.control.contract_name
+                    ),
+                }
+            }
+            let proof = self.local.prove(commitment_metadata, calldata).await?;
+            self.report(ProvingBackend::Local);
+            Ok(proof)
+        })
+    }
+}
+
+/// Splits the commitment metadata of a batch into up to the given number of sub-batches of
+/// consecutive calldatas, each starting from the state the previous ones leave.
+pub type SplitBatch =
+    fn(&[u8], Vec<Calldata>, usize) -> Result<Vec<(Vec<u8>, Vec<Calldata>)>, String>;
+
+/// How a contract's batches are split into proofs generated concurrently.
+pub struct BatchSplitting {
+    /// Proofs a batch is split into.
+    pub parts: usize,
+    pub split: SplitBatch,
+    /// Node the proofs of all but the last sub-batch are submitted to.
+    pub node: Arc<dyn NodeApiClient + Send + Sync>,
+}
+
+/// Proves the sub-batches of a batch concurrently instead of the batch at once.
+///
+/// Their proofs chain up from the batch's initial state, so each settles as soon as the node
+/// has the proofs before it. The earlier ones are submitted here as soon as they're generated,
+/// the last one is returned to the autoprover which submits it like a whole batch's.
+pub struct SplitProver<P> {
+    inner: P,
+    contract_name: ContractName,
+    program_id: ProgramId,
+    splitting: Option<BatchSplitting>,
+}
+
+impl<P> SplitProver<P> {
+    /// Creates the wrapper. With `splitting` set to `None`, batches are proven at once.
+    pub fn new(
+        inner: P,
+        contract_name: &ContractName,
+        program_id: [u8; 32],
+        splitting: Option<BatchSplitting>,
+    ) -> Self {
+        Self {
+            inner,
+            contract_name: contract_name.clone(),
+            program_id: ProgramId(program_id.to_vec()),
+            splitting,
+        }
+    }
+
+    async fn prove_and_submit(
+        &self,
+        node: &(dyn NodeApiClient + Send + Sync),
+        commitment_metadata: Vec<u8>,
+        calldata: Vec<Calldata>,
+    ) -> Result<()>
+    where
+        P: ClientSdkProver<Vec<Calldata>> + Send + Sync,
+    {
+        let txs = calldata.len();
+        let proof = self.inner.prove(commitment_metadata, calldata).await?;
+        node.send_tx_proof(ProofTransaction {
+            contract_name: self.contract_name.clone(),
+            program_id: self.program_id.clone(),
+            verifier: "risc0-3".into(),
+            proof,
+        })
+        .await
+        .with_context(|| {
+            format!(
+                "submitting the proof of {txs} txs of {}",
+                self.contract_name
+            )
+        })?;
+        Ok(())
+    }
+}
+
+impl<P> ClientSdkProver<Vec<Calldata>> for SplitProver<P>
+where
+    P: ClientSdkProver<Vec<Calldata>> + Send + Sync,
+{
+    fn prove(
+        &self,
+        commitment_metadata: Vec<u8>,
+        calldata: Vec<Calldata>,
+    ) -> Pin<Box<dyn Future<Output = Result<ProofData>> + Send + '_>> {
+        Box::pin(async move {
+            let Some(splitting) = self
+                .splitting
+                .as_ref()
+                .filter(|splitting| splitting.parts > 1 && calldata.len() > 1)
+            else {
+                return self.inner.prove(commitment_metadata, calldata).await;
+            };
+
+            // Executing the batch ahead is as slow as handling it, keep it off the runtime.
+            let (parts, split) = (splitting.parts, splitting.split);
+            let mut sub_batches =
+                tokio::task::spawn_blocking(move || split(&commitment_metadata, calldata, parts))
+                    .await?
+                    .map_err(|e| anyhow!("splitting batch of {}: {e}", self.contract_name))?;
+            let (last_metadata, last_calldata) =
+                sub_batches.pop().context("splitting an empty batch")?;
+
+            let earlier = futures::future::try_join_all(sub_batches.into_iter().map(
+                |(metadata, calldata)| {
+                    self.prove_and_submit(splitting.node.as_ref(), metadata, calldata)
+                },
+            ));
+            let (earlier, last) =
+                tokio::join!(earlier, self.inner.prove(last_metadata, last_calldata));
+            // Retried as a whole, with the proof cache the sub-batches already proven aren't
+            // proven again.
+            earlier?;
+            last
+        })
+    }
+}
+
+/// Wraps a prover with a content-addressed, disk-backed proof cache.
+///
+/// Proofs are keyed by the program id, the commitment metadata and the borsh-encoded calldata,
+/// so replays after a crash or a duplicate blob reuse the stored proof instead of re-proving.
+pub struct CachedProver<P> {
+    inner: P,
+    program_id: Vec<u8>,
+    cache_dir: Option<PathBuf>,
+}
+
+impl<P> CachedProver<P> {
+    /// Creates the wrapper. With `cache_dir` set to `None`, proofs are passed through uncached.
+    pub fn new(inner: P, program_id: &[u8], cache_dir: Option<PathBuf>) -> Result<Self> {
+        if let Some(dir) = &cache_dir {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("creating proof cache directory {}", dir.display()))?;
+        }
+        Ok(Self {
+            inner,
+            program_id: program_id.to_vec(),
+            cache_dir,
+        })
+    }
+}
+
+fn cache_key(program_id: &[u8], commitment_metadata: &[u8], calldata: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    for part in [program_id, commitment_metadata, calldata] {
+        hasher.update((part.len() as u64).to_le_bytes());
+        hasher.update(part);
+    }
+    hex::encode(hasher.finalize())
+}
+
+impl<T, P> ClientSdkProver<T> for CachedProver<P>
+where
+    T: BorshSerialize + Send + 'static,
+    P: ClientSdkProver<T> + Send + Sync,
+{
+    fn prove(
+        &self,
+        commitment_metadata: Vec<u8>,
+        calldata: T,
+    ) -> Pin<Box<dyn Future<Output = Result<ProofData>> + Send + '_>> {
+        Box::pin(async move {
+            let Some(cache_dir) = &self.cache_dir else {
+                return self.inner.prove(commitment_metadata, calldata).await;
+            };
+
+            // Batches and their metadata can be large, keep the hashing off the runtime.
+            let program_id = self.program_id.clone();
+            let (key, commitment_metadata, calldata) = tokio::task::spawn_blocking(move || {
+                let encoded =
+                    borsh::to_vec(&calldata).context("encoding calldata for proof cache")?;
+                let key = cache_key(&program_id, &commitment_metadata, &encoded);
+                Ok::<_, anyhow::Error>((key, commitment_metadata, calldata))
+            })
+            .await??;
+            let path = cache_dir.join(&key);
+
+            if let Ok(bytes) = tokio::fs::read(&path).await {
+                info!("♻️ Reusing cached proof {}", key);
+                return Ok(ProofData(bytes));
+            }
+
+            let proof = self.inner.prove(commitment_metadata, calldata).await?;
+
+            // Write to a temporary file first so a crash never leaves a truncated proof behind.
+            let tmp = cache_dir.join(format!("{key}.tmp"));
+            if let Err(e) = tokio::fs::write(&tmp, &proof.0).await {
+                tracing::warn!("Failed to write proof {} to cache: {:#}", key, e);
+            } else if let Err(e) = tokio::fs::rename(&tmp, &path).await {
+                tracing::warn!("Failed to store proof {} in cache: {:#}", key, e);
+            }
+
+            Ok(proof)
+        })
+    }
+}
+
+/// Records per-contract proving metrics: proof duration, transactions per proof and failures.
+pub struct MeteredProver<P> {
+    inner: P,
+    attributes: [KeyValue; 1],
+    proof_duration: Histogram<f64>,
+    txs_per_proof: Histogram<u64>,
+    proofs: Counter<u64>,
+    failures: Counter<u64>,
+}
+
+impl<P> MeteredProver<P> {
+    pub fn new(inner: P, contract_name: &ContractName) -> Self {
+        let meter = opentelemetry::global::meter("wallet_prover");
+        Self {
+            inner,
+            attributes: [KeyValue::new("contract", contract_name.0.clone())],
+            proof_duration: meter
+                .f64_histogram("prover_proof_duration_seconds")
+                .with_description("Time spent generating one proof")
+                .build(),
+            txs_per_proof: meter
+                .u64_histogram("prover_txs_per_proof")
+                .with_description("Number of calldatas proven together in one proof")
+                .build(),
+            proofs: meter
+                .u64_counter("prover_proofs_total")
+                .with_description("Number of proofs generated")
+                .build(),
+            failures: meter
+                .u64_counter("prover_failures_total")
+                .with_description("Number of failed proof generations")
+                .build(),
+        }
+    }
+}
+
+impl<P> ClientSdkProver<Vec<Calldata>> for MeteredProver<P>
+where
+    P: ClientSdkProver<Vec<Calldata>> + Send + Sync,
+{
+    fn prove(
+        &self,
+        commitment_metadata: Vec<u8>,
+        calldata: Vec<Calldata>,
+    ) -> Pin<Box<dyn Future<Output = Result<ProofData>> + Send + '_>> {
+        let span = tracing::info_span!(
+            "prove",
+            contract = %self.attributes[0].value,
+            txs = calldata.len()
+        );
+        Box::pin(async move {
+            let txs = calldata.len() as u64;
+            let started = Instant::now();
+            let result = self
+                .inner
+                .prove(commitment_metadata, calldata)
+                .instrument(span)
+                .await;
+            self.proof_duration
+                .record(started.elapsed().as_secs_f64(), &self.attributes);
+            match &result {
+                Ok(_) => {
+                    self.proofs.add(1, &self.attributes);
+                    self.txs_per_proof.record(txs, &self.attributes);
+                }
+                Err(_) => self.failures.add(1, &self.attributes),
+            }
+            result
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProofPriority {
+    /// Wallet operations (registrations, session keys) that block user onboarding.
+    High,
+    /// Bulk work such as token transfer batches.
+    Low,
+}
+
+/// Bounds the number of proofs generated at once across provers, serving high priority
+/// proofs before low priority ones.
+pub struct ProvingScheduler {
+    slots: Semaphore,
+    waiting_high: AtomicUsize,
+    high_served: Notify,
+}
+
+impl ProvingScheduler {
+    pub fn new(max_concurrent_proofs: usize) -> Self {
+        Self {
+            slots: Semaphore::new(max_concurrent_proofs.max(1)),
+            waiting_high: AtomicUsize::new(0),
+            high_served: Notify::new(),
+        }
+    }
+
+    async fn acquire(&self, priority: ProofPriority) -> Result<SemaphorePermit<'_>> {
+        match priority {
+            ProofPriority::High => {
+                self.waiting_high.fetch_add(1, Ordering::SeqCst);
+                let permit = self.slots.acquire().await;
+                self.waiting_high.fetch_sub(1, Ordering::SeqCst);
+                self.high_served.notify_waiters();
+                Ok(permit?)
+            }
+            ProofPriority::Low => {
+                loop {
+                    let served = self.high_served.notified();
+                    if self.waiting_high.load(Ordering::SeqCst) == 0 {
+                        break;
+                    }
+                    served.await;
+                }
+                Ok(self.slots.acquire().await?)
+            }
+        }
+    }
+}
+
+/// Waits for a slot on a shared [`ProvingScheduler`] before proving.
+pub struct ScheduledProver<P> {
+    inner: P,
+    scheduling: Option<(Arc<ProvingScheduler>, ProofPriority)>,
+}
+
+impl<P> ScheduledProver<P> {
+    /// Creates the wrapper. With `scheduling` set to `None`, proofs start immediately.
+    pub fn new(inner: P, scheduling: Option<(Arc<ProvingScheduler>, ProofPriority)>) -> Self {
+        Self { inner, scheduling }
+    }
+}
+
+impl<T, P> ClientSdkProver<T> for ScheduledProver<P>
+where
+    T: BorshSerialize + Send + 'static,
+    P: ClientSdkProver<T> + Send + Sync,
+{
+    fn prove(
+        &self,
+        commitment_metadata: Vec<u8>,
+        calldata: T,
+    ) -> Pin<Box<dyn Future<Output = Result<ProofData>> + Send + '_>> {
+        Box::pin(async move {
+            let _permit = match &self.scheduling {
+                Some((scheduler, priority)) => Some(scheduler.acquire(*priority).await?),
+                None => None,
+            };
+            self.inner.prove(commitment_metadata, calldata).await
+        })
+    }
+}
+
+/// Transactions covered by a proof that was just generated.
+#[derive(Debug, Clone)]
+pub struct ProvenTxs {
+    pub contract_name: ContractName,
+    pub txs: Vec<(TxHash, Identity)>,
+}
+
+/// Proof generation that failed, whether it's held for a retry or not.
+#[derive(Debug, Clone)]
+pub struct ProofFailure {
+    pub contract_name: ContractName,
+    pub tx_hashes: Vec<String>,
+    pub error: String,
+}
+
+/// Registry of the per-contract [`ProverControl`]s, exposed on the admin API.
+pub struct ProverControls {
+    contracts: Mutex<BTreeMap<ContractName, Arc<ProverControl>>>,
+    audit: AuditRecorder,
+    proofs: broadcast::Sender<ProvenTxs>,
+    failures: broadcast::Sender<ProofFailure>,
+    retries: Arc<ProofRetries>,
+}
+
+/// Failed proofs held for a retry, shared by the controls of every contract.
+struct ProofRetries {
+    /// Retries of a failed proof before its failure reaches the autoprover, 0 to not hold it.
+    max: AtomicU32,
+    /// Bumped to retry the held proofs.
+    round: watch::Sender<u64>,
+}
+
+impl Default for ProverControls {
+    fn default() -> Self {
+        Self::with_audit(AuditRecorder::default())
+    }
+}
+
+impl ProverControls {
+    /// Controls recording admin operations and generated proofs in the audit log.
+    pub fn with_audit(audit: AuditRecorder) -> Self {
+        Self {
+            contracts: Mutex::default(),
+            audit,
+            proofs: broadcast::channel(256).0,
+            failures: broadcast::channel(256).0,
+            retries: Arc::new(ProofRetries {
+                max: AtomicU32::new(0),
+                round: watch::Sender::new(0),
+            }),
+        }
+    }
+
+    /// Holds proofs that fail for up to `max_retries` retries, run by [`Self::retry_failed`],
+    /// instead of reporting the failure to the autoprover right away.
+    pub fn hold_failed_proofs(&self, max_retries: u32) {
+        self.retries.max.store(max_retries, Ordering::Relaxed);
+    }
+
+    /// Retries every held proof, returning how many there were.
+    pub fn retry_failed(&self) -> usize {
+        let held = self
+            .contracts
+            .lock()
+            .expect("prover controls poisoned")
+            .values()
+            .map(|control| {
+                control.with_jobs(|jobs| jobs.iter().filter(|job| job.failed.is_some()).count())
+            })
+            .sum();
+        if held > 0 {
+            self.retries.round.send_modify(|round| *round += 1);
+        }
+        held
+    }
+
+    /// Notifies every proof generated from now on.
+    pub fn subscribe_proofs(&self) -> broadcast::Receiver<ProvenTxs> {
+        self.proofs.subscribe()
+    }
+
+    /// Notifies every failed proof attempt from now on.
+    pub fn subscribe_failures(&self) -> broadcast::Receiver<ProofFailure> {
+        self.failures.subscribe()
+    }
+
+    pub fn register(&self, contract_name: &ContractName) -> Arc<ProverControl> {
+        let mut contracts = self.contracts.lock().expect("prover controls poisoned");
+        contracts
+            .entry(contract_name.clone())
+            .or_insert_with(|| {
+                Arc::new(ProverControl::new(
+                    contract_name.clone(),
+                    self.audit.clone(),
+                    self.proofs.clone(),
+                    self.failures.clone(),
+                    self.retries.clone(),
+                ))
+            })
+            .clone()
+    }
+
+    fn get(&self, contract_name: &str) -> Result<Arc<ProverControl>, AppError> {
+        self.contracts
+            .lock()
+            .expect("prover controls poisoned")
+            .get(&ContractName(contract_name.to_string()))
+            .cloned()
+            .ok_or_else(|| {
+                AppError(
+                    StatusCode::NOT_FOUND,
+                    anyhow!("No prover for contract {contract_name}"),
+                )
+            })
+    }
+
+    /// Number of proofs queued or running, per contract.
+    pub fn backlog(&self) -> BTreeMap<String, usize> {
+        self.contracts
+            .lock()
+            .expect("prover controls poisoned")
+            .iter()
+            .map(|(contract_name, control)| {
+                (
+                    contract_name.0.clone(),
+                    control.with_jobs(|jobs| jobs.len()),
+                )
+            })
+            .collect()
+    }
+
+    fn status(&self) -> Vec<ProverStatus> {
+        self.contracts
+            .lock()
+            .expect("prover controls poisoned")
+            .values()
+            .map(|control| control.status())
+            .collect()
+    }
+}
+
+struct ProofJob {
+    id: u64,
+    tx_hashes: Vec<String>,
+    queued_at: Instant,
+    started_at: Option<Instant>,
+    /// Error of the last attempt, while the proof is held for a retry.
+    failed: Option<String>,
+}
+
+/// Pause switch and queue view for one contract's prover.
+pub struct ProverControl {
+    contract_name: ContractName,
+    paused: watch::Sender<bool>,
+    next_job: AtomicU64,
+    jobs: Mutex<Vec<ProofJob>>,
+    last_proof_duration: Mutex<Option<Duration>>,
+    last_backend: Mutex<Option<ProvingBackend>>,
+    dead_letters: Mutex<Option<Arc<DeadLetters>>>,
+    audit: AuditRecorder,
+    proofs: broadcast::Sender<ProvenTxs>,
+    failures: broadcast::Sender<ProofFailure>,
+    retries: Arc<ProofRetries>,
+}
+
+#[derive(Serialize)]
+pub struct ProofJobStatus {
+    pub tx_hashes: Vec<String>,
+    pub proving: bool,
+    pub waiting_secs: u64,
+    /// Why the proof failed, while it waits for a retry.
+    pub failed: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct ProverStatus {
+    pub contract_name: String,
+    pub paused: bool,
+    pub jobs: Vec<ProofJobStatus>,
+    /// Estimate based on the duration of the last proof.
+    pub estimated_secs: Option<u64>,
+    /// Backend the last proof was generated on.
+    pub last_backend: Option<ProvingBackend>,
+    /// Proofs refused by the node after every retry, waiting to be resubmitted.
+    pub dead_letters: usize,
+}
+
+impl ProverControl {
+    fn new(
+        contract_name: ContractName,
+        audit: AuditRecorder,
+        proofs: broadcast::Sender<ProvenTxs>,
+        failures: broadcast::Sender<ProofFailure>,
+        retries: Arc<ProofRetries>,
+    ) -> Self {
+        Self {
+            contract_name,
+            paused: watch::Sender::new(false),
+            next_job: AtomicU64::new(0),
+            jobs: Mutex::new(Vec::new()),
+            last_proof_duration: Mutex::new(None),
+            last_backend: Mutex::new(None),
+            dead_letters: Mutex::new(None),
+            audit,
+            proofs,
+            failures,
+            retries,
+        }
+    }
+
+    pub fn set_paused(&self, paused: bool) {
+        info!(
+            "⏯️ Proving for {} {}",
+            self.contract_name,
+            if paused { "paused" } else { "resumed" }
+        );
+        self.paused.send_replace(paused);
+        self.audit.record(
+            AuditKind::AdminOperation,
+            "admin",
+            serde_json::json!({
+                "operation": if paused { "pause_prover" } else { "resume_prover" },
+                "contract": self.contract_name.0,
+            }),
+        );
+    }
+
+    fn status(&self) -> ProverStatus {
+        let jobs = self.jobs.lock().expect("prover jobs poisoned");
+        let last = *self
+            .last_proof_duration
+            .lock()
+            .expect("prover duration poisoned");
+        ProverStatus {
+            contract_name: self.contract_name.0.clone(),
+            paused: *self.paused.borrow(),
+            jobs: jobs
+                .iter()
+                .map(|job| ProofJobStatus {
+                    tx_hashes: job.tx_hashes.clone(),
+                    proving: job.started_at.is_some() && job.failed.is_none(),
+                    waiting_secs: job.queued_at.elapsed().as_secs(),
+                    failed: job.failed.clone(),
+                })
+                .collect(),
+            estimated_secs: last.map(|d| d.as_secs() * jobs.len() as u64),
+            last_backend: *self.last_backend.lock().expect("prover backend poisoned"),
+            dead_letters: self
+                .dead_letters()
+                .map_or(0, |dead_letters| dead_letters.len()),
+        }
+    }
+
+    /// Shows the proofs of this contract refused by the node on the admin API.
+    pub fn track_dead_letters(&self, dead_letters: Arc<DeadLetters>) {
+        *self.dead_letters.lock().expect("dead letters poisoned") = Some(dead_letters);
+    }
+
+    fn dead_letters(&self) -> Option<Arc<DeadLetters>> {
+        self.dead_letters
+            .lock()
+            .expect("dead letters poisoned")
+            .clone()
+    }
+
+    fn with_jobs<R>(&self, f: impl FnOnce(&mut Vec<ProofJob>) -> R) -> R {
+        f(&mut self.jobs.lock().expect("prover jobs poisoned"))
+    }
+}
+
+/// Removes a job from its control's queue view once the proof finishes or is cancelled.
+struct JobGuard<'a> {
+    control: &'a ProverControl,
+    id: u64,
+}
+
+impl Drop for JobGuard<'_> {
+    fn drop(&mut self) {
+        self.control
+            .with_jobs(|jobs| jobs.retain(|job| job.id != self.id));
+    }
+}
+
+/// Holds proofs while their contract is paused and tracks them for the admin API.
+pub struct ControlledProver<P> {
+    inner: P,
+    control: Arc<ProverControl>,
+}
+
+impl<P> ControlledProver<P> {
+    pub fn new(inner: P, control: Arc<ProverControl>) -> Self {
+        Self { inner, control }
+    }
+}
+
+impl<P> ClientSdkProver<Vec<Calldata>> for ControlledProver<P>
+where
+    P: ClientSdkProver<Vec<Calldata>> + Send + Sync,
+{
+    fn prove(
+        &self,
+        commitment_metadata: Vec<u8>,
+        calldata: Vec<Calldata>,
+    ) -> Pin<Box<dyn Future<Output = Result<ProofData>> + Send + '_>> {
+        let correlation_ids: Vec<String> = calldata
+            .iter()
+            .map(|c| CorrelationId::of_tx(&c.tx_hash).0)
+            .collect();
+        let span = tracing::info_span!(
+            "proof_job",
+            contract = %self.control.contract_name,
+            correlation_ids = ?correlation_ids
+        );
+        Box::pin(
+            async move {
+                let control = self.control.as_ref();
+                let id = control.next_job.fetch_add(1, Ordering::Relaxed);
+                control.with_jobs(|jobs| {
+                    jobs.push(ProofJob {
+                        id,
+                        tx_hashes: calldata.iter().map(|c| c.tx_hash.0.clone()).collect(),
+                        queued_at: Instant::now(),
+                        started_at: None,
+                        failed: None,
+                    })
+                });
+                let _guard = JobGuard { control, id };
+
+                control
+                    .paused
+                    .subscribe()
+                    .wait_for(|paused| !*paused)
+                    .await?;
+
+                let started = Instant::now();
+                control.with_jobs(|jobs| {
+                    if let Some(job) = jobs.iter_mut().find(|job| job.id == id) {
+                        job.started_at = Some(started);
+                    }
+                });
+                let txs: Vec<(TxHash, Identity)> = calldata
+                    .iter()
+                    .map(|c| (c.tx_hash.clone(), c.identity.clone()))
+                    .collect();
+                let tx_hashes: Vec<String> = txs.iter().map(|(hash, _)| hash.0.clone()).collect();
+                let mut retries = 0;
+                let result = loop {
+                    let result = self
+                        .inner
+                        .prove(commitment_metadata.clone(), calldata.clone())
+                        .await;
+                    let Err(e) = &result else {
+                        break result;
+                    };
+                    // Nobody may be listening.
+                    let _ = control.failures.send(ProofFailure {
+                        contract_name: control.contract_name.clone(),
+                        tx_hashes: tx_hashes.clone(),
+                        error: format!("{e:#}"),
+                    });
+                    if retries >= control.retries.max.load(Ordering::Relaxed) {
+                        break result;
+                    }
+                    retries += 1;
+                    let mut round = control.retries.round.subscribe();
+                    tracing::warn!(
+                        "Proof of {} failed, held for retry {retries}: {e:#}",
+                        control.contract_name
+                    );
+                    control.with_jobs(|jobs| {
+                        if let Some(job) = jobs.iter_mut().find(|job| job.id == id) {
+                            job.failed = Some(format!("{e:#}"));
+                        }
+                    });
+                    round.changed().await?;
+                    control.with_jobs(|jobs| {
+                        if let Some(job) = jobs.iter_mut().find(|job| job.id == id) {
+                            job.failed = None;
+                        }
+                    });
+                };
+                if result.is_ok() {
+                    *control
+                        .last_proof_duration
+                        .lock()
+                        .expect("prover duration poisoned") = Some(started.elapsed());
+                    control.audit.record(
+                        AuditKind::ProofGenerated,
+                        control.contract_name.0.clone(),
+                        serde_json::json!({
+                            "tx_hashes": tx_hashes,
+                            "duration_ms": started.elapsed().as_millis() as u64,
+                        }),
+                    );
+                    // Nobody may be listening.
+                    let _ = control.proofs.send(ProvenTxs {
+                        contract_name: control.contract_name.clone(),
+                        txs,
+                    });
+                }
+                result
+            }
+            .instrument(span),
+        )
+    }
+}
+
+/// Admin routes to inspect provers and pause or resume them per contract.
+pub fn prover_admin_router(controls: Arc<ProverControls>) -> Router {
+    Router::new()
+        .route("/provers", get(get_provers))
+        .route("/provers/{contract}/pause", post(pause_prover))
+        .route("/provers/{contract}/resume", post(resume_prover))
+        .route("/provers/{contract}/dead_letters", get(get_dead_letters))
+        .route(
+            "/provers/{contract}/dead_letters/resubmit",
+            post(resubmit_dead_letters),
+        )
+        .with_state(controls)
+}
+
+async fn get_provers(State(controls): State<Arc<ProverControls>>) -> Json<Vec<ProverStatus>> {
+    Json(controls.status())
+}
+
+async fn pause_prover(
+    Path(contract): Path<String>,
+    State(controls): State<Arc<ProverControls>>,
+) -> Result<Json<ProverStatus>, AppError> {
+    let control = controls.get(&contract)?;
+    control.set_paused(true);
+    Ok(Json(control.status()))
+}
+
+async fn resume_prover(
+    Path(contract): Path<String>,
+    State(controls): State<Arc<ProverControls>>,
+) -> Result<Json<ProverStatus>, AppError> {
+    let control = controls.get(&contract)?;
+    control.set_paused(false);
+    Ok(Json(control.status()))
+}
+
+async fn get_dead_letters(
+    Path(contract): Path<String>,
+    State(controls): State<Arc<ProverControls>>,
+) -> Result<Json<Vec<DeadLetterStatus>>, AppError> {
+    let control = controls.get(&contract)?;
+    Ok(Json(
+        control
+            .dead_letters()
+            .map(|dead_letters| dead_letters.list())
+            .unwrap_or_default(),
+    ))
+}
+
+async fn resubmit_dead_letters(
+    Path(contract): Path<String>,
+    State(controls): State<Arc<ProverControls>>,
+) -> Result<Json<ResubmitReport>, AppError> {
+    let control = controls.get(&contract)?;
+    let Some(dead_letters) = control.dead_letters() else {
+        return Ok(Json(ResubmitReport {
+            resubmitted: vec![],
+            failed: vec![],
+        }));
+    };
+    let report = dead_letters.resubmit().await;
+    control.audit.record(
+        AuditKind::AdminOperation,
+        "admin",
+        serde_json::json!({
+            "operation": "resubmit_dead_letters",
+            "contract": control.contract_name.0,
+            "resubmitted": report.resubmitted,
+            "failed": report.failed.len(),
+        }),
+    );
+    Ok(Json(report))
+}
@@ -0,0 +1,702 @@
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use chrono::NaiveDateTime;
+use client_sdk::contract_indexer::utoipa;
+use client_sdk::contract_indexer::{
+    utoipa::ToSchema,
+    utoipa_axum::{router::OpenApiRouter, routes},
+};
+use client_sdk::{rest_client::NodeApiClient, AppError};
+use hyli_modules::{
+    bus::SharedMessageBus,
+    log_error, module_bus_client, module_handle_messages,
+    modules::{contract_state_indexer::CSIBusEvent, BuildApiContextInner, Module},
+};
+use sdk::{Blob, BlobTransaction, ContractName, Identity};
+use serde::{Deserialize, Serialize};
+use server::audit::{AuditKind, AuditRecorder};
+use server::conf::{DatabaseConf, RecoveryConf};
+use server::correlation::send_tx_blob;
+use server::db::DbPools;
+use sqlx::FromRow;
+use wallet::{
+    client::indexer::{SharedWalletEvent, TxOutcome, WalletEvent},
+    AuthMethod, PendingRecovery,
+};
+
+/// Walks users through the recovery paths their account supports, so recovering doesn't mean
+/// assembling raw transactions.
+///
+/// A session prepares the wallet blobs of its path through the indexer routes, waits for the
+/// client's authentication blob, submits the transaction and follows it until it settles. Email
+/// recoveries then wait out the contract's timelock, and are finalized by the server once it
+/// elapsed. Sessions are stored in `db_url` and survive restarts.
+pub struct RecoveryModule {
+    bus: RecoveryModuleBusClient,
+    inner: Arc<RecoveryModuleInner>,
+}
+
+pub struct RecoveryModuleCtx {
+    pub api_ctx: Arc<BuildApiContextInner>,
+    pub conf: RecoveryConf,
+    pub db_url: String,
+    pub database: DatabaseConf,
+    pub node: Arc<dyn NodeApiClient + Send + Sync>,
+    pub wallet_cn: ContractName,
+    /// Base URL of this server's REST API, serving the indexer routes.
+    pub indexer_url: String,
+    pub audit: AuditRecorder,
+}
+
+module_bus_client! {
+#[derive(Debug)]
+pub struct RecoveryModuleBusClient {
+    receiver(CSIBusEvent<SharedWalletEvent>),
+}
+}
+
+struct RecoveryModuleInner {
+    conf: RecoveryConf,
+    db: DbPools,
+    node: Arc<dyn NodeApiClient + Send + Sync>,
+    wallet_cn: ContractName,
+    indexer_url: String,
+    http: reqwest::Client,
+    audit: AuditRecorder,
+}
+
+fn now_ms() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or_default()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum RecoveryPath {
+    /// `InitiateRecovery` with a JWT of the recovery email, finalized after the timelock.
+    Email,
+    /// `RecoverWithBackupKey`, applied as soon as it settles.
+    BackupKey,
+}
+
+impl RecoveryPath {
+    fn as_str(&self) -> &'static str {
+        match self {
+            RecoveryPath::Email => "email",
+            RecoveryPath::BackupKey => "backup_key",
+        }
+    }
+
+    fn parse(path: &str) -> Result<Self> {
+        match path {
+            "email" => Ok(RecoveryPath::Email),
+            "backup_key" => Ok(RecoveryPath::BackupKey),
+            _ => bail!("unknown recovery path {path}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum RecoveryStep {
+    /// The transaction is prepared, waiting for the client's authentication blob.
+    AwaitingAuth,
+    /// The recovery transaction was sent, waiting for it to settle.
+    Submitted,
+    /// The recovery is initiated, waiting for the timelock to elapse.
+    Timelocked,
+    /// The finalization was sent, waiting for it to settle.
+    Finalizing,
+    Completed,
+    /// The pending recovery was cancelled with the account's current auth method.
+    Cancelled,
+    Failed,
+}
+
+impl RecoveryStep {
+    fn as_str(&self) -> &'static str {
+        match self {
+            RecoveryStep::AwaitingAuth => "awaiting_auth",
+            RecoveryStep::Submitted => "submitted",
+            RecoveryStep::Timelocked => "timelocked",
+            RecoveryStep::Finalizing => "finalizing",
+            RecoveryStep::Completed => "completed",
+            RecoveryStep::Cancelled => "cancelled",
+            RecoveryStep::Failed => "failed",
+        }
+    }
+
+    fn parse(step: &str) -> Result<Self> {
+        Ok(match step {
+            "awaiting_auth" => RecoveryStep::AwaitingAuth,
+            "submitted" => RecoveryStep::Submitted,
+            "timelocked" => RecoveryStep::Timelocked,
+            "finalizing" => RecoveryStep::Finalizing,
+            "completed" => RecoveryStep::Completed,
+            "cancelled" => RecoveryStep::Cancelled,
+            "failed" => RecoveryStep::Failed,
+            _ => bail!("unknown recovery step {step}"),
+        })
+    }
+}
+
+#[derive(Debug, FromRow)]
+struct RecoverySessionRow {
+    id: String,
+    account: String,
+    path: String,
+    step: String,
+    /// JSON of the wallet blobs waiting for the authentication blob.
+    blobs: String,
+    /// JSON of what the authentication blob must contain, as described by the indexer.
+    auth: String,
+    tx_hash: Option<String>,
+    executable_at: Option<i64>,
+    error: Option<String>,
+    created_at: NaiveDateTime,
+    updated_at: NaiveDateTime,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct RecoverySession {
+    pub id: String,
+    pub account: String,
+    pub path: RecoveryPath,
+    pub step: RecoveryStep,
+    /// Wallet blobs of the transaction, to send along the authentication blob.
+    #[schema(value_type = Vec<Object>)]
+    pub blobs: Vec<Blob>,
+    /// What the authentication blob must contain while `step` is `awaiting_auth`.
+    #[schema(value_type = Object)]
+    pub auth: serde_json::Value,
+    /// Transaction of the current step, once sent.
+    pub tx_hash: Option<String>,
+    /// When the email recovery can be finalized, in milliseconds.
+    pub executable_at: Option<i64>,
+    pub error: Option<String>,
+    #[schema(value_type = String)]
+    pub created_at: NaiveDateTime,
+    #[schema(value_type = String)]
+    pub updated_at: NaiveDateTime,
+}
+
+impl TryFrom<RecoverySessionRow> for RecoverySession {
+    type Error = anyhow::Error;
+
+    fn try_from(row: RecoverySessionRow) -> Result<Self> {
+        Ok(Self {
+            path: RecoveryPath::parse(&row.path)?,
+            step: RecoveryStep::parse(&row.step)?,
+            blobs: serde_json::from_str(&row.blobs).context("decoding session blobs")?,
+            auth: serde_json::from_str(&row.auth).context("decoding session auth")?,
+            id: row.id,
+            account: row.account,
+            tx_hash: row.tx_hash,
+            executable_at: row.executable_at,
+            error: row.error,
+            created_at: row.created_at,
+            updated_at: row.updated_at,
+        })
+    }
+}
+
+/// Recovery settings of an account, as served by the indexer.
+#[derive(Debug, Deserialize)]
+struct IndexedRecovery {
+    email_recovery: bool,
+    pending: Option<PendingRecovery>,
+    backup_key: bool,
+}
+
+/// Transaction prepared by the indexer, waiting for an authentication blob.
+#[derive(Debug, Deserialize)]
+struct PreparedTransaction {
+    blobs: Vec<Blob>,
+    auth: serde_json::Value,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct RecoveryOptions {
+    pub account: String,
+    /// Paths the account can be recovered with.
+    pub paths: Vec<RecoveryPath>,
+    /// A recovery is already initiated on-chain.
+    pub pending: bool,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct StartRecoveryBody {
+    pub account: String,
+    pub path: RecoveryPath,
+    /// Auth method replacing the lost one.
+    pub auth_method: AuthMethod,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct SubmitRecoveryBody {
+    /// Authentication blobs completing the prepared transaction.
+    #[schema(value_type = Vec<Object>)]
+    pub blobs: Vec<Blob>,
+}
+
+const SESSION_COLUMNS: &str =
+    "id, account, path, step, blobs, auth, tx_hash, executable_at, error, created_at, updated_at";
+
+impl RecoveryModuleInner {
+    async fn indexed_recovery(&self, account: &str) -> Result<IndexedRecovery> {
+        self.http
+            .get(format!(
+                "{}/v1/indexer/contract/{}/recovery/{account}",
+                self.indexer_url, self.wallet_cn.0
+            ))
+            .send()
+            .await?
+            .error_for_status()
+            .context("fetching recovery settings")?
+            .json()
+            .await
+            .context("decoding recovery settings")
+    }
+
+    async fn options(&self, account: &str) -> Result<RecoveryOptions> {
+        let recovery = self.indexed_recovery(account).await?;
+        let mut paths = vec![];
+        if recovery.backup_key {
+            paths.push(RecoveryPath::BackupKey);
+        }
+        if recovery.email_recovery {
+            paths.push(RecoveryPath::Email);
+        }
+        Ok(RecoveryOptions {
+            account: account.to_string(),
+            paths,
+            pending: recovery.pending.is_some(),
+        })
+    }
+
+    async fn session(&self, id: &str) -> Result<RecoverySession> {
+        let row: Option<RecoverySessionRow> = sqlx::query_as(&format!(
+            "SELECT {SESSION_COLUMNS} FROM recovery_sessions WHERE id = $1"
+        ))
+        .bind(id)
+        .fetch_optional(&self.db.primary)
+        .await?;
+        row.ok_or_else(|| anyhow!("Recovery session {id} not found"))?
+            .try_into()
+    }
+
+    async fn sessions_at(&self, steps: &[RecoveryStep]) -> Result<Vec<RecoverySession>> {
+        let steps: Vec<&str> = steps.iter().map(|s| s.as_str()).collect();
+        let rows: Vec<RecoverySessionRow> = sqlx::query_as(&format!(
+            "SELECT {SESSION_COLUMNS} FROM recovery_sessions WHERE step = ANY($1)"
+        ))
+        .bind(steps)
+        .fetch_all(&self.db.primary)
+        .await?;
+        rows.into_iter().map(RecoverySession::try_from).collect()
+    }
+
+    async fn set_step(
+        &self,
+        id: &str,
+        step: RecoveryStep,
+        tx_hash: Option<&str>,
+        executable_at: Option<i64>,
+        error: Option<&str>,
+    ) -> Result<()> {
+        sqlx::query(
+            "
+            UPDATE recovery_sessions
+            SET step = $2,
+                tx_hash = COALESCE($3, tx_hash),
+                executable_at = COALESCE($4, executable_at),
+                error = $5,
+                updated_at = NOW()
+            WHERE id = $1
+            ",
+        )
+        .bind(id)
+        .bind(step.as_str())
+        .bind(tx_hash)
+        .bind(executable_at)
+        .bind(error)
+        .execute(&self.db.primary)
+        .await?;
+        Ok(())
+    }
+
+    async fn start(&self, body: StartRecoveryBody) -> Result<RecoverySession> {
+        let options = self.options(&body.account).await?;
+        if !options.paths.contains(&body.path) {
+            bail!(
+                "Account {} can't be recovered with its {}",
+                body.account,
+                body.path.as_str()
+            );
+        }
+        let route = match body.path {
+            RecoveryPath::Email => "recovery/initiate",
+            RecoveryPath::BackupKey => "recovery/backup_key",
+        };
+        let prepared: PreparedTransaction = self
+            .http
+            .post(format!(
+                "{}/v1/indexer/contract/{}/{route}",
+                self.indexer_url, self.wallet_cn.0
+            ))
+            .json(&serde_json::json!({
+                "account": body.account,
+                "auth_method": body.auth_method,
+            }))
+            .send()
+            .await?
+            .error_for_status()
+            .context("preparing recovery transaction")?
+            .json()
+            .await?;
+
+        let id = hex::encode(rand::random::<[u8; 32]>());
+        sqlx::query(
+            "
+            INSERT INTO recovery_sessions (id, account, path, step, blobs, auth)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            ",
+        )
+        .bind(&id)
+        .bind(&body.account)
+        .bind(body.path.as_str())
+        .bind(RecoveryStep::AwaitingAuth.as_str())
+        .bind(serde_json::to_string(&prepared.blobs)?)
+        .bind(serde_json::to_string(&prepared.auth)?)
+        .execute(&self.db.primary)
+        .await?;
+
+        self.audit.record(
+            AuditKind::Recovery,
+            &body.account,
+            serde_json::json!({ "session": id, "path": body.path.as_str() }),
+        );
+        self.session(&id).await
+    }
+
+    async fn submit(&self, id: &str, body: SubmitRecoveryBody) -> Result<RecoverySession> {
+        let session = self.session(id).await?;
+        if session.step != RecoveryStep::AwaitingAuth {
+            bail!("Recovery session {id} is not waiting for authentication");
+        }
+        let mut blobs = session.blobs;
+        blobs.extend(body.blobs);
+        let tx = BlobTransaction::new(
+            Identity::new(format!("{}@{}", session.account, self.wallet_cn.0)),
+            blobs,
+        );
+        let tx_hash = send_tx_blob(self.node.as_ref(), tx)
+            .await
+            .context("sending recovery transaction")?;
+
+        self.set_step(id, RecoveryStep::Submitted, Some(&tx_hash.0), None, None)
+            .await?;
+        self.session(id).await
+    }
+
+    /// Advances the session waiting on the settled transaction, if any.
+    async fn on_settled(&self, event: &WalletEvent) -> Result<()> {
+        let row: Option<RecoverySessionRow> = sqlx::query_as(&format!(
+            "SELECT {SESSION_COLUMNS} FROM recovery_sessions WHERE tx_hash = $1 AND step = ANY($2)"
+        ))
+        .bind(&event.tx_hash.0)
+        .bind(vec![
+            RecoveryStep::Submitted.as_str(),
+            RecoveryStep::Finalizing.as_str(),
+        ])
+        .fetch_optional(&self.db.primary)
+        .await?;
+        let Some(session) = row.map(RecoverySession::try_from).transpose()? else {
+            return Ok(());
+        };
+
+        if event.outcome != TxOutcome::Success {
+            tracing::info!(
+                "Recovery session {} failed: {}",
+                session.id,
+                event.program_outputs
+            );
+            return self
+                .set_step(
+                    &session.id,
+                    RecoveryStep::Failed,
+                    None,
+                    None,
+                    Some(&event.program_outputs),
+                )
+                .await;
+        }
+
+        match (session.path, session.step) {
+            (RecoveryPath::Email, RecoveryStep::Submitted) => {
+                let recovery = self.indexed_recovery(&session.account).await?;
+                let executable_at = recovery
+                    .pending
+                    .map(|pending| pending.executable_at.0 as i64)
+                    .context("initiated recovery is not pending")?;
+                self.set_step(
+                    &session.id,
+                    RecoveryStep::Timelocked,
+                    None,
+                    Some(executable_at),
+                    None,
+                )
+                .await
+            }
+            _ => {
+                self.audit.record(
+                    AuditKind::Recovery,
+                    &session.account,
+                    serde_json::json!({ "session": session.id, "completed": event.tx_hash.0 }),
+                );
+                self.set_step(&session.id, RecoveryStep::Completed, None, None, None)
+                    .await
+            }
+        }
+    }
+
+    /// Finalizes email recoveries whose timelock elapsed, and expires sessions the client
+    /// abandoned.
+    async fn tick(&self) -> Result<()> {
+        sqlx::query(
+            "
+            UPDATE recovery_sessions
+            SET step = $1, error = 'Expired', updated_at = NOW()
+            WHERE step = $2 AND created_at < NOW() - make_interval(secs => $3)
+            ",
+        )
+        .bind(RecoveryStep::Failed.as_str())
+        .bind(RecoveryStep::AwaitingAuth.as_str())
+        .bind(self.conf.session_ttl_secs as f64)
+        .execute(&self.db.primary)
+        .await?;
+
+        let now = now_ms() as i64;
+        for session in self.sessions_at(&[RecoveryStep::Timelocked]).await? {
+            if session.executable_at.is_some_and(|at| at > now) {
+                continue;
+            }
+            let _ = log_error!(self.finalize(&session).await, "Finalizing recovery session");
+        }
+        Ok(())
+    }
+
+    async fn finalize(&self, session: &RecoverySession) -> Result<()> {
+        let recovery = self.indexed_recovery(&session.account).await?;
+        if recovery.pending.is_none() {
+            return self
+                .set_step(&session.id, RecoveryStep::Cancelled, None, None, None)
+                .await;
+        }
+        let tx: BlobTransaction = self
+            .http
+            .post(format!(
+                "{}/v1/indexer/contract/{}/recovery/{}/finalize",
+                self.indexer_url, self.wallet_cn.0, session.account
+            ))
+            .send()
+            .await?
+            .error_for_status()
+            .context("preparing recovery finalization")?
+            .json()
+            .await?;
+        let tx_hash = send_tx_blob(self.node.as_ref(), tx)
+            .await
+            .context("sending recovery finalization")?;
+        self.set_step(
+            &session.id,
+            RecoveryStep::Finalizing,
+            Some(&tx_hash.0),
+            None,
+            None,
+        )
+        .await
+    }
+}
+
+impl Module for RecoveryModule {
+    type Context = RecoveryModuleCtx;
+
+    async fn build(bus: SharedMessageBus, ctx: Self::Context) -> Result<Self> {
+        let db = DbPools::connect(&ctx.db_url, &ctx.database).await?;
+
+        sqlx::query(
+            r#"CREATE TABLE IF NOT EXISTS recovery_sessions (
+                id TEXT PRIMARY KEY,
+                account TEXT NOT NULL,
+                path TEXT NOT NULL,
+                step TEXT NOT NULL,
+                blobs TEXT NOT NULL,
+                auth TEXT NOT NULL,
+                tx_hash TEXT NULL,
+                executable_at BIGINT NULL,
+                error TEXT NULL,
+                created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+                updated_at TIMESTAMP NOT NULL DEFAULT NOW()
+            )"#,
+        )
+        .execute(&db.primary)
+        .await?;
+        sqlx::query(
+            "CREATE INDEX IF NOT EXISTS recovery_sessions_step_idx ON recovery_sessions (step, tx_hash)",
+        )
+        .execute(&db.primary)
+        .await?;
+
+        let inner = Arc::new(RecoveryModuleInner {
+            conf: ctx.conf,
+            db,
+            node: ctx.node,
+            wallet_cn: ctx.wallet_cn,
+            indexer_url: ctx.indexer_url,
+            http: reqwest::Client::new(),
+            audit: ctx.audit,
+        });
+
+        let (router, openapi) = OpenApiRouter::default()
+            .routes(routes!(route_options))
+            .routes(routes!(route_start))
+            .routes(routes!(route_get_session))
+            .routes(routes!(route_submit))
+            .split_for_parts();
+        let api = router.with_state(inner.clone());
+
+        if let Ok(mut guard) = ctx.api_ctx.router.lock() {
+            if let Some(router) = guard.take() {
+                guard.replace(router.merge(api));
+            }
+        }
+        if let Ok(mut guard) = ctx.api_ctx.openapi.lock() {
+            guard.merge(openapi);
+        }
+
+        Ok(Self {
+            bus: RecoveryModuleBusClient::new_from_bus(bus.new_handle()).await,
+            inner,
+        })
+    }
+
+    async fn run(&mut self) -> Result<()> {
+        let mut interval =
+            tokio::time::interval(Duration::from_secs(self.inner.conf.poll_interval_secs));
+
+        module_handle_messages! {
+            on_self self,
+            listen<CSIBusEvent<SharedWalletEvent>> event => {
+                let _ = log_error!(
+                    self.inner.on_settled(&event.event).await,
+                    "Updating recovery session"
+                );
+            }
+            _ = interval.tick() => {
+                let _ = log_error!(self.inner.tick().await, "Advancing recovery sessions");
+            }
+        };
+        Ok(())
+    }
+}
+
+// --------------------------------------------------------
+//     Routes
+// --------------------------------------------------------
+
+#[utoipa::path(
+    get,
+    path = "/recovery/options/{account}",
+    tag = "Recovery",
+    params(
+        ("account" = String, Path, description = "Account")
+    ),
+    responses(
+        (status = OK, description = "Recovery paths the account supports", body = RecoveryOptions)
+    )
+)]
+async fn route_options(
+    State(ctx): State<Arc<RecoveryModuleInner>>,
+    Path(account): Path<String>,
+) -> Result<Json<RecoveryOptions>, AppError> {
+    ctx.options(&account)
+        .await
+        .map(Json)
+        .map_err(|e| AppError(StatusCode::NOT_FOUND, e))
+}
+
+#[utoipa::path(
+    post,
+    path = "/recovery/sessions",
+    tag = "Recovery",
+    request_body = StartRecoveryBody,
+    responses(
+        (status = OK, description = "Recovery session waiting for the authentication blob", body = RecoverySession)
+    )
+)]
+async fn route_start(
+    State(ctx): State<Arc<RecoveryModuleInner>>,
+    Json(body): Json<StartRecoveryBody>,
+) -> Result<Json<RecoverySession>, AppError> {
+    ctx.start(body)
+        .await
+        .map(Json)
+        .map_err(|e| AppError(StatusCode::BAD_REQUEST, e))
+}
+
+#[utoipa::path(
+    get,
+    path = "/recovery/sessions/{id}",
+    tag = "Recovery",
+    params(
+        ("id" = String, Path, description = "Recovery session id")
+    ),
+    responses(
+        (status = OK, description = "Current step of the recovery session", body = RecoverySession),
+        (status = NOT_FOUND, description = "Recovery session not found")
+    )
+)]
+async fn route_get_session(
+    State(ctx): State<Arc<RecoveryModuleInner>>,
+    Path(id): Path<String>,
+) -> Result<Json<RecoverySession>, AppError> {
+    ctx.session(&id)
+        .await
+        .map(Json)
+        .map_err(|e| AppError(StatusCode::NOT_FOUND, e))
+}
+
+#[utoipa::path(
+    post,
+    path = "/recovery/sessions/{id}/submit",
+    tag = "Recovery",
+    params(
+        ("id" = String, Path, description = "Recovery session id")
+    ),
+    request_body = SubmitRecoveryBody,
+    responses(
+        (status = OK, description = "Recovery transaction sent", body = RecoverySession)
+    )
+)]
+async fn route_submit(
+    State(ctx): State<Arc<RecoveryModuleInner>>,
+    Path(id): Path<String>,
+    Json(body): Json<SubmitRecoveryBody>,
+) -> Result<Json<RecoverySession>, AppError> {
+    ctx.submit(&id, body)
+        .await
+        .map(Json)
+        .map_err(|e| AppError(StatusCode::BAD_REQUEST, e))
+}
@@ -2,8 +2,13 @@ use crate::app::WalletModule;
 use crate::app::WalletModuleCtx;
 use crate::init::init_node;
 use crate::init::ContractInit;
+use crate::ws_auth::WsAuth;
+use crate::ws_replay::EventReplay;
 use client_sdk::transaction_builder::TxExecutorHandler;
+use server::conf::SystemStatusConf;
 use server::new_wallet;
+use server::provers::ProverControls;
+use server::secrets::Secrets;
 
 use client_sdk::rest_client::NodeApiClient;
 use hyli_modules::modules::contract_listener::{ContractListener, ContractListenerConf};
@@ -18,16 +23,26 @@ use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::Duration;
 use tracing::info;
-use wallet::client::indexer::WalletEvent;
+use wallet::client::indexer::SharedWalletEvent;
 use wallet::client::tx_executor_handler::Wallet;
 
 pub(crate) struct SdkWalletConfig {
-    pub wallet_cn: ContractName,
+    /// Wallet contracts served side by side, the first one is the main wallet advertised by
+    /// `/api/config`. Each gets its own indexer under `/v1/indexer/contract/{name}`.
+    pub wallet_cns: Vec<ContractName>,
     pub data_directory: PathBuf,
     pub noinit: bool,
     pub indexer_database_url: String,
+    /// Base URL of this server's REST API, serving the indexer routes.
+    pub indexer_url: String,
     pub listener_poll_interval_secs: u64,
+    pub listener_replay_settled_from_start: bool,
     pub additional_listener_contracts: HashSet<ContractName>,
+    pub secrets: Secrets,
+    pub ws_auth: Arc<WsAuth>,
+    pub replay: Arc<EventReplay>,
+    pub system_status: SystemStatusConf,
+    pub prover_controls: Arc<ProverControls>,
 }
 
 pub(crate) async fn setup_wallet_modules(
@@ -36,13 +51,23 @@ pub(crate) async fn setup_wallet_modules(
     api_ctx: Arc<BuildApiContextInner>,
     node_client: Arc<dyn NodeApiClient + Send + Sync>,
 ) -> anyhow::Result<()> {
-    let (wallet_constructor, wallet) = new_wallet(&config.wallet_cn);
-    let contracts = vec![ContractInit {
-        name: config.wallet_cn.clone(),
-        program_id: contracts::WALLET_ID,
-        initial_state: wallet.get_state_commitment(),
-        constructor_metadata: borsh::to_vec(&wallet_constructor).expect("must succeed"),
-    }];
+    let Some(main_wallet_cn) = config.wallet_cns.first() else {
+        anyhow::bail!("At least one wallet contract is required");
+    };
+
+    let contracts = config
+        .wallet_cns
+        .iter()
+        .map(|wallet_cn| {
+            let (wallet_constructor, wallet) = new_wallet(wallet_cn, &config.secrets);
+            ContractInit {
+                name: wallet_cn.clone(),
+                program_id: contracts::WALLET_ID,
+                initial_state: wallet.get_state_commitment(),
+                constructor_metadata: borsh::to_vec(&wallet_constructor).expect("must succeed"),
+            }
+        })
+        .collect();
 
     if config.noinit {
         info!("Skipping initialization, using existing contracts");
@@ -55,7 +80,7 @@ pub(crate) async fn setup_wallet_modules(
         }
     }
 
-    let mut listener_contracts = HashSet::from([config.wallet_cn.clone()]);
+    let mut listener_contracts: HashSet<ContractName> = config.wallet_cns.iter().cloned().collect();
     listener_contracts.extend(config.additional_listener_contracts.iter().cloned());
 
     handler
@@ -64,24 +89,35 @@ pub(crate) async fn setup_wallet_modules(
             data_directory: config.data_directory.clone(),
             contracts: listener_contracts,
             poll_interval: Duration::from_secs(config.listener_poll_interval_secs),
-            replay_settled_from_start: true,
+            replay_settled_from_start: config.listener_replay_settled_from_start,
         })
         .await?;
 
     let app_ctx = Arc::new(WalletModuleCtx {
         api: api_ctx.clone(),
-        wallet_cn: config.wallet_cn.clone(),
+        wallet_cn: main_wallet_cn.clone(),
+        ws_auth: config.ws_auth.clone(),
+        replay: config.replay.clone(),
+        node: node_client.clone(),
+        indexer_database_url: config.indexer_database_url.clone(),
+        indexer_url: config.indexer_url.clone(),
+        system_status: config.system_status.clone(),
+        prover_controls: config.prover_controls.clone(),
     });
 
     handler.build_module::<WalletModule>(app_ctx).await?;
 
-    handler
-        .build_module::<ContractStateIndexer<Wallet, WalletEvent>>(ContractStateIndexerCtx {
-            contract_name: config.wallet_cn.clone(),
-            data_directory: config.data_directory.clone(),
-            api: api_ctx,
-        })
-        .await?;
+    for wallet_cn in &config.wallet_cns {
+        handler
+            .build_module::<ContractStateIndexer<Wallet, SharedWalletEvent>>(
+                ContractStateIndexerCtx {
+                    contract_name: wallet_cn.clone(),
+                    data_directory: config.data_directory.clone(),
+                    api: api_ctx.clone(),
+                },
+            )
+            .await?;
+    }
 
     Ok(())
 }
@@ -0,0 +1,377 @@
+use std::path::PathBuf;
+
+use anyhow::{bail, Context, Result};
+use secp256k1::SecretKey;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use zeroize::Zeroizing;
+
+/// Well-known development key, only accepted outside of production mode.
+pub const DEFAULT_INVITE_CODE_PKEY: &str =
+    "0000000000000001000000000000000100000000000000010000000000000001";
+/// Well-known development password, only accepted outside of production mode.
+pub const DEFAULT_HYLI_PASSWORD: &str = "hylisecure";
+
+/// Where a secret is read from.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "provider", rename_all = "lowercase")]
+pub enum SecretSource {
+    /// An environment variable.
+    Env { var: String },
+    /// A file holding the secret, e.g. a mounted Kubernetes or Docker secret.
+    File { path: PathBuf },
+    /// A HashiCorp Vault KV secret, authenticated with the token found in `token_env`.
+    Vault {
+        addr: String,
+        path: String,
+        key: String,
+        token_env: String,
+    },
+    /// An AWS Secrets Manager secret, read with the credentials of the `AWS_ACCESS_KEY_ID`,
+    /// `AWS_SECRET_ACCESS_KEY` and, for temporary credentials, `AWS_SESSION_TOKEN` environment
+    /// variables. `key` picks a value out of a secret stored as a JSON object.
+    Aws {
+        region: String,
+        secret_id: String,
+        #[serde(default)]
+        key: Option<String>,
+        /// Replaces the regional endpoint, e.g. for a VPC endpoint or a local emulator.
+        #[serde(default)]
+        endpoint: Option<String>,
+    },
+}
+
+impl Default for SecretSource {
+    fn default() -> Self {
+        SecretSource::Env { var: String::new() }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct SecretsConf {
+    /// Refuse to start with missing secrets or the default development ones.
+    pub production: bool,
+    pub invite_code_pkey: SecretSource,
+    pub hyli_password: SecretSource,
+    /// Key the invite code key is rotated to by the admin rotation endpoint.
+    #[serde(default)]
+    pub next_invite_code_pkey: Option<SecretSource>,
+}
+
+/// Secrets resolved once at startup, wiped from memory when dropped.
+#[derive(Clone)]
+pub struct Secrets {
+    pub invite_code_secret_key: SecretKey,
+    pub hyli_password: Zeroizing<String>,
+    pub next_invite_code_secret_key: Option<SecretKey>,
+}
+
+impl Drop for Secrets {
+    fn drop(&mut self) {
+        self.invite_code_secret_key.non_secure_erase();
+        if let Some(key) = &mut self.next_invite_code_secret_key {
+            key.non_secure_erase();
+        }
+    }
+}
+
+impl std::fmt::Debug for Secrets {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Secrets").finish_non_exhaustive()
+    }
+}
+
+impl SecretSource {
+    async fn resolve(&self) -> Result<Option<Zeroizing<String>>> {
+        match self {
+            SecretSource::Env { var } => Ok(std::env::var(var).ok().map(Zeroizing::new)),
+            SecretSource::File { path } => {
+                let secret = Zeroizing::new(
+                    tokio::fs::read_to_string(path)
+                        .await
+                        .with_context(|| format!("reading secret file {}", path.display()))?,
+                );
+                Ok(Some(Zeroizing::new(secret.trim().to_string())))
+            }
+            SecretSource::Vault {
+                addr,
+                path,
+                key,
+                token_env,
+            } => {
+                let token = std::env::var(token_env)
+                    .with_context(|| format!("missing Vault token in {token_env}"))?;
+                let response: serde_json::Value = reqwest::Client::new()
+                    .get(format!("{}/v1/{}", addr.trim_end_matches('/'), path))
+                    .header("X-Vault-Token", token)
+                    .send()
+                    .await
+                    .context("querying Vault")?
+                    .error_for_status()
+                    .context("querying Vault")?
+                    .json()
+                    .await
+                    .context("decoding Vault response")?;
+                // KV v2 nests the secret under data.data, KV v1 directly under data.
+                let data = &response["data"];
+                let value = data["data"][key.as_str()]
+                    .as_str()
+                    .or_else(|| data[key.as_str()].as_str())
+                    .with_context(|| format!("key {key} not found in Vault secret {path}"))?;
+                Ok(Some(Zeroizing::new(value.to_string())))
+            }
+            SecretSource::Aws {
+                region,
+                secret_id,
+                key,
+                endpoint,
+            } => {
+                let secret = get_aws_secret(region, secret_id, endpoint.as_deref()).await?;
+                let Some(key) = key else {
+                    return Ok(Some(secret));
+                };
+                let values: Zeroizing<serde_json::Value> = Zeroizing::new(
+                    serde_json::from_str(&secret)
+                        .with_context(|| format!("AWS secret {secret_id} is not a JSON object"))?,
+                );
+                let value = values[key.as_str()]
+                    .as_str()
+                    .with_context(|| format!("key {key} not found in AWS secret {secret_id}"))?;
+                Ok(Some(Zeroizing::new(value.to_string())))
+            }
+        }
+    }
+}
+
+/// Reads the string value of an AWS Secrets Manager secret, with a SigV4 signed request.
+async fn get_aws_secret(
+    region: &str,
+    secret_id: &str,
+    endpoint: Option<&str>,
+) -> Result<Zeroizing<String>> {
+    let access_key = std::env::var("AWS_ACCESS_KEY_ID").context("missing AWS_ACCESS_KEY_ID")?;
+    let secret_key = Zeroizing::new(
+        std::env::var("AWS_SECRET_ACCESS_KEY").context("missing AWS_SECRET_ACCESS_KEY")?,
+    );
+    let session_token = std::env::var("AWS_SESSION_TOKEN").ok();
+
+    let url = endpoint
+        .map(|endpoint| endpoint.trim_end_matches('/').to_string())
+        .unwrap_or_else(|| format!("https://secretsmanager.{region}.amazonaws.com"));
+    let parsed = reqwest::Url::parse(&url).context("parsing AWS Secrets Manager endpoint")?;
+    let host = parsed
+        .host_str()
+        .context("AWS Secrets Manager endpoint has no host")?;
+    // Signed as reqwest sends it, with the port unless it's the scheme's default.
+    let host = match parsed.port() {
+        Some(port) => format!("{host}:{port}"),
+        None => host.to_string(),
+    };
+    let body = serde_json::json!({ "SecretId": secret_id }).to_string();
+    let amz_date = chrono::Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+
+    let mut headers = vec![
+        ("content-type", "application/x-amz-json-1.1".to_string()),
+        ("host", host),
+        ("x-amz-date", amz_date.clone()),
+        ("x-amz-target", "secretsmanager.GetSecretValue".to_string()),
+    ];
+    if let Some(token) = session_token {
+        headers.push(("x-amz-security-token", token));
+    }
+    headers.sort();
+    let authorization = sigv4_authorization(
+        &access_key,
+        &secret_key,
+        region,
+        "secretsmanager",
+        &amz_date,
+        &headers,
+        body.as_bytes(),
+    );
+
+    let mut request = reqwest::Client::new()
+        .post(format!("{url}/"))
+        .header("authorization", authorization)
+        .body(body);
+    for (name, value) in headers.iter().filter(|(name, _)| *name != "host") {
+        request = request.header(*name, value);
+    }
+    let response: serde_json::Value = request
+        .send()
+        .await
+        .context("querying AWS Secrets Manager")?
+        .error_for_status()
+        .context("querying AWS Secrets Manager")?
+        .json()
+        .await
+        .context("decoding AWS Secrets Manager response")?;
+    let value = response["SecretString"]
+        .as_str()
+        .with_context(|| format!("AWS secret {secret_id} has no string value"))?;
+    Ok(Zeroizing::new(value.to_string()))
+}
+
+/// `Authorization` header of an AWS Signature Version 4 `POST /` request with the given
+/// headers, lowercase and sorted by name.
+fn sigv4_authorization(
+    access_key: &str,
+    secret_key: &str,
+    region: &str,
+    service: &str,
+    amz_date: &str,
+    headers: &[(&str, String)],
+    body: &[u8],
+) -> String {
+    let date = &amz_date[..8];
+    let canonical_headers: String = headers
+        .iter()
+        .map(|(name, value)| format!("{name}:{}\n", value.trim()))
+        .collect();
+    let signed_headers = headers
+        .iter()
+        .map(|(name, _)| *name)
+        .collect::<Vec<_>>()
+        .join(";");
+    let canonical_request = format!(
+        "POST\n/\n\n{canonical_headers}\n{signed_headers}\n{}",
+        hex::encode(Sha256::digest(body))
+    );
+    let scope = format!("{date}/{region}/{service}/aws4_request");
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{amz_date}\n{scope}\n{}",
+        hex::encode(Sha256::digest(canonical_request.as_bytes()))
+    );
+    let signing_key = sigv4_signing_key(secret_key, date, region, service);
+    let signature = hex::encode(hmac_sha256(&signing_key, string_to_sign.as_bytes()));
+    format!(
+        "AWS4-HMAC-SHA256 Credential={access_key}/{scope}, SignedHeaders={signed_headers}, Signature={signature}"
+    )
+}
+
+fn sigv4_signing_key(secret_key: &str, date: &str, region: &str, service: &str) -> [u8; 32] {
+    let key = hmac_sha256(format!("AWS4{secret_key}").as_bytes(), date.as_bytes());
+    let key = hmac_sha256(&key, region.as_bytes());
+    let key = hmac_sha256(&key, service.as_bytes());
+    hmac_sha256(&key, b"aws4_request")
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> [u8; 32] {
+    let mut block = Zeroizing::new([0u8; 64]);
+    if key.len() > block.len() {
+        block[..32].copy_from_slice(&Sha256::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+    let mut inner = Sha256::new();
+    inner.update(block.map(|b| b ^ 0x36));
+    inner.update(data);
+    let mut outer = Sha256::new();
+    outer.update(block.map(|b| b ^ 0x5c));
+    outer.update(inner.finalize());
+    outer.finalize().into()
+}
+
+async fn resolve_or_default(
+    name: &str,
+    source: &SecretSource,
+    default: &str,
+    production: bool,
+) -> Result<Zeroizing<String>> {
+    let secret = source
+        .resolve()
+        .await
+        .with_context(|| format!("resolving secret {name}"))?;
+    match secret {
+        Some(secret) if production && secret.as_str() == default => {
+            bail!("{name} is set to the default development value, refusing to start in production mode")
+        }
+        Some(secret) => Ok(secret),
+        None if production => bail!("{name} is not set, refusing to start in production mode"),
+        None => {
+            tracing::warn!(
+                "{} is not set, using the insecure development default",
+                name
+            );
+            Ok(Zeroizing::new(default.to_string()))
+        }
+    }
+}
+
+impl Secrets {
+    pub async fn resolve(conf: &SecretsConf) -> Result<Self> {
+        let invite_code_pkey = resolve_or_default(
+            "invite_code_pkey",
+            &conf.invite_code_pkey,
+            DEFAULT_INVITE_CODE_PKEY,
+            conf.production,
+        )
+        .await?;
+        let invite_code_secret_key = parse_secret_key("invite_code_pkey", &invite_code_pkey)?;
+
+        let hyli_password = resolve_or_default(
+            "hyli_password",
+            &conf.hyli_password,
+            DEFAULT_HYLI_PASSWORD,
+            conf.production,
+        )
+        .await?;
+
+        let next_invite_code_secret_key = match &conf.next_invite_code_pkey {
+            Some(source) => {
+                let pkey = source
+                    .resolve()
+                    .await
+                    .context("resolving secret next_invite_code_pkey")?
+                    .context("next_invite_code_pkey is configured but not set")?;
+                Some(parse_secret_key("next_invite_code_pkey", &pkey)?)
+            }
+            None => None,
+        };
+
+        Ok(Secrets {
+            invite_code_secret_key,
+            hyli_password,
+            next_invite_code_secret_key,
+        })
+    }
+}
+
+fn parse_secret_key(name: &str, hex_key: &str) -> Result<SecretKey> {
+    let secret_key =
+        Zeroizing::new(hex::decode(hex_key).with_context(|| format!("{name} must be hex"))?);
+    let secret_key = Zeroizing::new(
+        <[u8; 32]>::try_from(secret_key.as_slice())
+            .map_err(|_| anyhow::anyhow!("{name} must be 32 bytes"))?,
+    );
+    SecretKey::from_byte_array(*secret_key)
+        .with_context(|| format!("{name} is not a valid secp256k1 key"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hmac_sha256() {
+        // RFC 4231, test case 2.
+        assert_eq!(
+            hex::encode(hmac_sha256(b"Jefe", b"what do ya want for nothing?")),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn test_sigv4_signing_key() {
+        // Example of the AWS Signature Version 4 documentation.
+        assert_eq!(
+            hex::encode(sigv4_signing_key(
+                "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+                "20120215",
+                "us-east-1",
+                "iam"
+            )),
+            "f4780e2d9f65fa895f9c67b32ce1baf0b0d8a43505a000a1a9e090d414db404d"
+        );
+    }
+}
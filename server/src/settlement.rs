@@ -0,0 +1,457 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::{Context, Result};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use chrono::{NaiveDateTime, Utc};
+use client_sdk::contract_indexer::utoipa;
+use client_sdk::contract_indexer::{
+    utoipa::ToSchema,
+    utoipa_axum::{router::OpenApiRouter, routes},
+};
+use client_sdk::AppError;
+use hyli_modules::{
+    bus::SharedMessageBus,
+    log_error, module_bus_client, module_handle_messages,
+    modules::{contract_state_indexer::CSIBusEvent, BuildApiContextInner, Module},
+};
+use sdk::{ContractName, TxHash};
+use serde::Serialize;
+use server::conf::{DatabaseConf, SettlementConf};
+use server::correlation::CorrelationId;
+use server::db::DbPools;
+use server::provers::{ProvenTxs, ProverControls};
+use sqlx::{postgres::PgPoolOptions, FromRow, PgPool};
+use wallet::client::indexer::{SharedWalletEvent, TxOutcome};
+
+const PRUNE_INTERVAL: Duration = Duration::from_secs(3_600);
+
+/// Correlates the lifecycle of the wallet transactions into a timeline per transaction hash:
+/// blob sequencing, proof generation, proof submission and settlement or failure.
+///
+/// Proofs generated by this server and settlements come from the bus, sequencing and proof
+/// submissions from the blocks of the indexer database. Timelines are stored in `db_url` and
+/// served by `GET /api/tx/{hash}/timeline`, to tell where a stuck transaction stopped.
+pub struct SettlementModule {
+    bus: SettlementModuleBusClient,
+    inner: Arc<SettlementTracker>,
+    prover_controls: Arc<ProverControls>,
+}
+
+pub struct SettlementModuleCtx {
+    pub api_ctx: Arc<BuildApiContextInner>,
+    pub conf: SettlementConf,
+    pub db_url: String,
+    pub database: DatabaseConf,
+    pub indexer_database_url: String,
+    /// Wallet contracts whose transactions are tracked.
+    pub wallet_cns: Vec<ContractName>,
+    pub prover_controls: Arc<ProverControls>,
+}
+
+module_bus_client! {
+#[derive(Debug)]
+pub struct SettlementModuleBusClient {
+    receiver(CSIBusEvent<SharedWalletEvent>),
+}
+}
+
+struct SettlementTracker {
+    conf: SettlementConf,
+    db: DbPools,
+    indexer_db: PgPool,
+    wallet_cns: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TxStage {
+    Sequenced,
+    ProofGenerated,
+    ProofSubmitted,
+    Settled,
+    Failed,
+    TimedOut,
+}
+
+impl TxStage {
+    fn as_str(&self) -> &'static str {
+        match self {
+            TxStage::Sequenced => "sequenced",
+            TxStage::ProofGenerated => "proof_generated",
+            TxStage::ProofSubmitted => "proof_submitted",
+            TxStage::Settled => "settled",
+            TxStage::Failed => "failed",
+            TxStage::TimedOut => "timed_out",
+        }
+    }
+
+    fn parse(stage: &str) -> Option<Self> {
+        [
+            TxStage::Sequenced,
+            TxStage::ProofGenerated,
+            TxStage::ProofSubmitted,
+            TxStage::Settled,
+            TxStage::Failed,
+            TxStage::TimedOut,
+        ]
+        .into_iter()
+        .find(|s| s.as_str() == stage)
+    }
+
+    fn is_final(&self) -> bool {
+        matches!(self, TxStage::Settled | TxStage::Failed | TxStage::TimedOut)
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct TimelineEvent {
+    pub stage: TxStage,
+    #[schema(value_type = String)]
+    pub at: NaiveDateTime,
+    /// Block, proving contract, proof transaction or program outputs, depending on the stage.
+    pub details: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct TxTimeline {
+    pub tx_hash: String,
+    /// Id the transaction is logged under, to look its journey up in the server logs.
+    pub correlation_id: String,
+    pub events: Vec<TimelineEvent>,
+    /// Whether the transaction settled, failed or timed out.
+    pub finished: bool,
+    /// Stage the transaction waits for, when it hasn't finished.
+    pub waiting_for: Option<TxStage>,
+}
+
+#[derive(FromRow)]
+struct TimelineRow {
+    stage: String,
+    at: NaiveDateTime,
+    details: String,
+}
+
+impl SettlementTracker {
+    async fn record(
+        &self,
+        tx_hash: &str,
+        stage: TxStage,
+        at: NaiveDateTime,
+        details: &str,
+    ) -> Result<()> {
+        // Only the first occurrence of a stage is kept, e.g. when a proof is generated again.
+        sqlx::query(
+            "INSERT INTO tx_timeline (tx_hash, stage, at, details) VALUES ($1, $2, $3, $4)
+             ON CONFLICT (tx_hash, stage) DO NOTHING",
+        )
+        .bind(tx_hash)
+        .bind(stage.as_str())
+        .bind(at)
+        .bind(details)
+        .execute(&self.db.primary)
+        .await
+        .context("recording timeline event")?;
+        Ok(())
+    }
+
+    async fn on_proven(&self, proven: &ProvenTxs) -> Result<()> {
+        if !self.wallet_cns.contains(&proven.contract_name.0) {
+            return Ok(());
+        }
+        let now = Utc::now().naive_utc();
+        for (tx_hash, _) in &proven.txs {
+            self.record(
+                &tx_hash.0,
+                TxStage::ProofGenerated,
+                now,
+                &proven.contract_name.0,
+            )
+            .await?;
+        }
+        Ok(())
+    }
+
+    async fn on_settled(&self, event: &SharedWalletEvent) -> Result<()> {
+        let stage = match event.outcome {
+            TxOutcome::Success => TxStage::Settled,
+            TxOutcome::Failure => TxStage::Failed,
+            TxOutcome::Timeout => TxStage::TimedOut,
+        };
+        self.record(
+            &event.tx_hash.0,
+            stage,
+            Utc::now().naive_utc(),
+            &event.program_outputs,
+        )
+        .await
+    }
+
+    /// Records the blob transactions and proofs of the tracked contracts included in the blocks
+    /// indexed since the last poll.
+    async fn poll_indexer(&self) -> Result<()> {
+        let indexed: Option<i64> = sqlx::query_scalar("SELECT MAX(height) FROM blocks")
+            .fetch_one(&self.indexer_db)
+            .await
+            .context("fetching indexed block height")?;
+        let Some(indexed) = indexed else {
+            return Ok(());
+        };
+        let cursor: Option<i64> =
+            sqlx::query_scalar("SELECT height FROM tx_timeline_cursor WHERE id = 0")
+                .fetch_optional(&self.db.primary)
+                .await
+                .context("fetching timeline cursor")?;
+        // Tracking starts at the current block the first time, there's no backfill.
+        let from = cursor.unwrap_or(indexed);
+
+        if indexed > from {
+            let sequenced: Vec<(String, NaiveDateTime, String)> = sqlx::query_as(
+                "SELECT DISTINCT t.tx_hash, b.timestamp, b.hash
+                 FROM blobs bl
+                 JOIN transactions t ON t.tx_hash = bl.tx_hash AND t.parent_dag_hash = bl.parent_dag_hash
+                 JOIN blocks b ON b.hash = t.block_hash
+                 WHERE bl.contract_name = ANY($1) AND b.height > $2 AND b.height <= $3",
+            )
+            .bind(&self.wallet_cns)
+            .bind(from)
+            .bind(indexed)
+            .fetch_all(&self.indexer_db)
+            .await
+            .context("fetching sequenced transactions")?;
+            for (tx_hash, at, block_hash) in sequenced {
+                self.record(
+                    &tx_hash,
+                    TxStage::Sequenced,
+                    at,
+                    &format!("block {block_hash}"),
+                )
+                .await?;
+            }
+
+            let proofs: Vec<(String, NaiveDateTime, String)> = sqlx::query_as(
+                "SELECT DISTINCT bpo.blob_tx_hash, b.timestamp, bpo.proof_tx_hash
+                 FROM blob_proof_outputs bpo
+                 JOIN transactions t ON t.tx_hash = bpo.proof_tx_hash
+                 JOIN blocks b ON b.hash = t.block_hash
+                 WHERE bpo.contract_name = ANY($1) AND b.height > $2 AND b.height <= $3",
+            )
+            .bind(&self.wallet_cns)
+            .bind(from)
+            .bind(indexed)
+            .fetch_all(&self.indexer_db)
+            .await
+            .context("fetching submitted proofs")?;
+            for (tx_hash, at, proof_tx_hash) in proofs {
+                self.record(
+                    &tx_hash,
+                    TxStage::ProofSubmitted,
+                    at,
+                    &format!("proof {proof_tx_hash}"),
+                )
+                .await?;
+            }
+        }
+
+        sqlx::query(
+            "INSERT INTO tx_timeline_cursor (id, height) VALUES (0, $1)
+             ON CONFLICT (id) DO UPDATE SET height = EXCLUDED.height",
+        )
+        .bind(indexed)
+        .execute(&self.db.primary)
+        .await
+        .context("updating timeline cursor")?;
+        Ok(())
+    }
+
+    async fn prune(&self) -> Result<()> {
+        if self.conf.retention_days == 0 {
+            return Ok(());
+        }
+        sqlx::query(
+            "DELETE FROM tx_timeline WHERE tx_hash IN (
+                SELECT tx_hash FROM tx_timeline GROUP BY tx_hash
+                HAVING MIN(at) < NOW() - make_interval(days => $1)
+            )",
+        )
+        .bind(self.conf.retention_days as i32)
+        .execute(&self.db.primary)
+        .await
+        .context("pruning timelines")?;
+        Ok(())
+    }
+
+    async fn timeline(&self, tx_hash: &str) -> Result<Option<TxTimeline>> {
+        let rows: Vec<TimelineRow> = sqlx::query_as(
+            "SELECT stage, at, details FROM tx_timeline WHERE tx_hash = $1 ORDER BY at, stage",
+        )
+        .bind(tx_hash)
+        .fetch_all(self.db.reader())
+        .await
+        .context("fetching timeline")?;
+        if rows.is_empty() {
+            return Ok(None);
+        }
+        let events: Vec<TimelineEvent> = rows
+            .into_iter()
+            .filter_map(|row| {
+                Some(TimelineEvent {
+                    stage: TxStage::parse(&row.stage)?,
+                    at: row.at,
+                    details: row.details,
+                })
+            })
+            .collect();
+        let reached = |stage| events.iter().any(|event| event.stage == stage);
+        let finished = events.iter().any(|event| event.stage.is_final());
+        let waiting_for = if finished {
+            None
+        } else {
+            [
+                TxStage::Sequenced,
+                TxStage::ProofGenerated,
+                TxStage::ProofSubmitted,
+                TxStage::Settled,
+            ]
+            .into_iter()
+            // Proofs generated elsewhere are only seen once submitted.
+            .find(|stage| {
+                !reached(*stage)
+                    && !(*stage == TxStage::ProofGenerated && reached(TxStage::ProofSubmitted))
+            })
+        };
+        Ok(Some(TxTimeline {
+            tx_hash: tx_hash.to_string(),
+            correlation_id: CorrelationId::of_tx(&TxHash(tx_hash.to_string())).0,
+            events,
+            finished,
+            waiting_for,
+        }))
+    }
+}
+
+impl Module for SettlementModule {
+    type Context = SettlementModuleCtx;
+
+    async fn build(bus: SharedMessageBus, ctx: Self::Context) -> Result<Self> {
+        let db = DbPools::connect(&ctx.db_url, &ctx.database).await?;
+
+        sqlx::query(
+            r#"CREATE TABLE IF NOT EXISTS tx_timeline (
+                tx_hash TEXT NOT NULL,
+                stage TEXT NOT NULL,
+                at TIMESTAMP NOT NULL,
+                details TEXT NOT NULL,
+                PRIMARY KEY (tx_hash, stage)
+            )"#,
+        )
+        .execute(&db.primary)
+        .await?;
+        sqlx::query(
+            r#"CREATE TABLE IF NOT EXISTS tx_timeline_cursor (
+                id INT PRIMARY KEY,
+                height BIGINT NOT NULL
+            )"#,
+        )
+        .execute(&db.primary)
+        .await?;
+
+        let indexer_db = PgPoolOptions::new()
+            .max_connections(1)
+            .connect_lazy(&ctx.indexer_database_url)
+            .context("parsing indexer database URL")?;
+
+        let inner = Arc::new(SettlementTracker {
+            conf: ctx.conf,
+            db,
+            indexer_db,
+            wallet_cns: ctx.wallet_cns.into_iter().map(|cn| cn.0).collect(),
+        });
+
+        let (router, openapi) = OpenApiRouter::default()
+            .routes(routes!(route_get_timeline))
+            .split_for_parts();
+        let api = router.with_state(inner.clone());
+
+        if let Ok(mut guard) = ctx.api_ctx.router.lock() {
+            if let Some(router) = guard.take() {
+                guard.replace(router.merge(api));
+            }
+        }
+        if let Ok(mut guard) = ctx.api_ctx.openapi.lock() {
+            guard.merge(openapi);
+        }
+
+        Ok(Self {
+            bus: SettlementModuleBusClient::new_from_bus(bus.new_handle()).await,
+            inner,
+            prover_controls: ctx.prover_controls,
+        })
+    }
+
+    async fn run(&mut self) -> Result<()> {
+        let mut interval = tokio::time::interval(Duration::from_secs(
+            self.inner.conf.poll_interval_secs.max(1),
+        ));
+        let mut prune_interval = tokio::time::interval(PRUNE_INTERVAL);
+        let mut proofs = self.prover_controls.subscribe_proofs();
+
+        module_handle_messages! {
+            on_self self,
+            listen<CSIBusEvent<SharedWalletEvent>> event => {
+                let _ = log_error!(
+                    self.inner.on_settled(&event.event).await,
+                    "Recording transaction settlement"
+                );
+            }
+            Ok(proven) = proofs.recv() => {
+                let _ = log_error!(
+                    self.inner.on_proven(&proven).await,
+                    "Recording generated proof"
+                );
+            }
+            _ = interval.tick() => {
+                let _ = log_error!(self.inner.poll_indexer().await, "Polling indexed transactions");
+            }
+            _ = prune_interval.tick() => {
+                let _ = log_error!(self.inner.prune().await, "Pruning transaction timelines");
+            }
+        };
+        Ok(())
+    }
+}
+
+// --------------------------------------------------------
+//     Routes
+// --------------------------------------------------------
+
+#[utoipa::path(
+    get,
+    path = "/api/tx/{hash}/timeline",
+    tag = "Transactions",
+    params(
+        ("hash" = String, Path, description = "Transaction hash")
+    ),
+    responses(
+        (status = OK, description = "Lifecycle of the transaction", body = TxTimeline),
+        (status = NOT_FOUND, description = "No event was recorded for the transaction")
+    )
+)]
+async fn route_get_timeline(
+    State(ctx): State<Arc<SettlementTracker>>,
+    Path(hash): Path<String>,
+) -> Result<Json<TxTimeline>, AppError> {
+    ctx.timeline(&hash)
+        .await
+        .map_err(|e| AppError(StatusCode::INTERNAL_SERVER_ERROR, e))?
+        .map(Json)
+        .ok_or_else(|| {
+            AppError(
+                StatusCode::NOT_FOUND,
+                anyhow::anyhow!("No timeline for transaction {hash}"),
+            )
+        })
+}
@@ -0,0 +1,290 @@
+use client_sdk::contract_indexer::utoipa;
+use client_sdk::contract_indexer::utoipa::ToSchema;
+use hyli_smt_token::SmtTokenAction;
+use sdk::Blob;
+use serde::Serialize;
+use wallet::WalletAction;
+
+/// Human-readable summary of a signing payload, so users don't approve opaque hex blobs.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, ToSchema)]
+pub struct DecodedPayload {
+    pub contract: Option<String>,
+    pub action: String,
+    pub account: Option<String>,
+    pub amount: Option<u128>,
+    pub recipient: Option<String>,
+    pub details: Option<String>,
+}
+
+/// Tries to interpret `bytes` as a borsh-encoded `Blob`, falling back to a bare action.
+pub fn decode_payload(bytes: &[u8]) -> Option<DecodedPayload> {
+    if let Ok(blob) = borsh::from_slice::<Blob>(bytes) {
+        if let Some(decoded) = decode_action(&blob.data.0) {
+            return Some(DecodedPayload {
+                contract: Some(blob.contract_name.0),
+                ..decoded
+            });
+        }
+    }
+    decode_action(bytes)
+}
+
+fn decode_action(bytes: &[u8]) -> Option<DecodedPayload> {
+    if let Ok(action) = borsh::from_slice::<WalletAction>(bytes) {
+        return Some(decode_wallet_action(action));
+    }
+    if let Ok(action) = borsh::from_slice::<SmtTokenAction>(bytes) {
+        return Some(decode_token_action(action));
+    }
+    None
+}
+
+fn decode_wallet_action(action: WalletAction) -> DecodedPayload {
+    match action {
+        WalletAction::RegisterIdentity {
+            account,
+            auth_method,
+            ..
+        } => DecodedPayload {
+            action: "RegisterIdentity".to_string(),
+            account: Some(account),
+            details: Some(format!("{auth_method:?}")),
+            ..Default::default()
+        },
+        WalletAction::VerifyIdentity { account, .. } => DecodedPayload {
+            action: "VerifyIdentity".to_string(),
+            account: Some(account),
+            ..Default::default()
+        },
+        WalletAction::AddSessionKey {
+            account,
+            key,
+            expiration_date,
+            whitelist,
+            ..
+        } => DecodedPayload {
+            action: "AddSessionKey".to_string(),
+            account: Some(account),
+            details: Some(format!(
+                "key {key}, expires at {expiration_date}, whitelist {}",
+                whitelist
+                    .map(|w| w.iter().map(|c| c.0.clone()).collect::<Vec<_>>().join(", "))
+                    .unwrap_or_else(|| "none".to_string())
+            )),
+            ..Default::default()
+        },
+        WalletAction::AddLimitedSessionKey {
+            account,
+            key,
+            expiration_date,
+            whitelist,
+            max_uses,
+            max_value_per_tx,
+            ..
+        } => DecodedPayload {
+            action: "AddLimitedSessionKey".to_string(),
+            account: Some(account),
+            details: Some(format!(
+                "key {key}, expires at {expiration_date}, whitelist {}, max uses {}, max value per tx {}",
+                whitelist
+                    .map(|w| w.iter().map(|c| c.0.clone()).collect::<Vec<_>>().join(", "))
+                    .unwrap_or_else(|| "none".to_string()),
+                max_uses
+                    .map(|uses| uses.to_string())
+                    .unwrap_or_else(|| "none".to_string()),
+                max_value_per_tx
+                    .map(|value| value.to_string())
+                    .unwrap_or_else(|| "none".to_string())
+            )),
+            ..Default::default()
+        },
+        WalletAction::FreezeAccount { account, .. } => DecodedPayload {
+            action: "FreezeAccount".to_string(),
+            account: Some(account),
+            ..Default::default()
+        },
+        WalletAction::UnfreezeAccount { account, .. } => DecodedPayload {
+            action: "UnfreezeAccount".to_string(),
+            account: Some(account),
+            ..Default::default()
+        },
+        WalletAction::RemoveSessionKey { account, key, .. } => DecodedPayload {
+            action: "RemoveSessionKey".to_string(),
+            account: Some(account),
+            details: Some(format!("key {key}")),
+            ..Default::default()
+        },
+        WalletAction::RenewSessionKey {
+            account,
+            key,
+            new_expiration,
+            ..
+        } => DecodedPayload {
+            action: "RenewSessionKey".to_string(),
+            account: Some(account),
+            details: Some(format!("key {key} until {new_expiration}")),
+            ..Default::default()
+        },
+        WalletAction::UseSessionKey { account, .. } => DecodedPayload {
+            action: "UseSessionKey".to_string(),
+            account: Some(account),
+            ..Default::default()
+        },
+        WalletAction::UpdateInviteCodePublicKey {
+            invite_code_public_key,
+            ..
+        } => DecodedPayload {
+            action: "UpdateInviteCodePublicKey".to_string(),
+            details: Some(hex::encode(invite_code_public_key)),
+            ..Default::default()
+        },
+        WalletAction::UpdateTimePolicy {
+            account,
+            time_policy,
+            ..
+        } => DecodedPayload {
+            action: "UpdateTimePolicy".to_string(),
+            account: Some(account),
+            details: Some(format!("skew {}ms", time_policy.skew_ms)),
+            ..Default::default()
+        },
+        WalletAction::ReportFailedAuth { account, attempt } => DecodedPayload {
+            action: "ReportFailedAuth".to_string(),
+            account: Some(account),
+            details: Some(format!("attempt {attempt}")),
+            ..Default::default()
+        },
+        WalletAction::SetRecoveryEmail {
+            account,
+            email_hash,
+            ..
+        } => DecodedPayload {
+            action: "SetRecoveryEmail".to_string(),
+            account: Some(account),
+            details: Some(if email_hash.is_some() {
+                "enable email recovery".to_string()
+            } else {
+                "disable email recovery".to_string()
+            }),
+            ..Default::default()
+        },
+        WalletAction::InitiateRecovery { account, .. } => DecodedPayload {
+            action: "InitiateRecovery".to_string(),
+            account: Some(account),
+            ..Default::default()
+        },
+        WalletAction::CancelRecovery { account, .. } => DecodedPayload {
+            action: "CancelRecovery".to_string(),
+            account: Some(account),
+            ..Default::default()
+        },
+        WalletAction::FinalizeRecovery { account } => DecodedPayload {
+            action: "FinalizeRecovery".to_string(),
+            account: Some(account),
+            ..Default::default()
+        },
+        WalletAction::RegisterBackupKey {
+            account,
+            public_key,
+            ..
+        } => DecodedPayload {
+            action: "RegisterBackupKey".to_string(),
+            account: Some(account),
+            details: Some(format!("backup key {public_key}")),
+            ..Default::default()
+        },
+        WalletAction::RecoverWithBackupKey { account, .. } => DecodedPayload {
+            action: "RecoverWithBackupKey".to_string(),
+            account: Some(account),
+            ..Default::default()
+        },
+        WalletAction::PruneSessionKeys { account } => DecodedPayload {
+            action: "PruneSessionKeys".to_string(),
+            account: Some(account),
+            ..Default::default()
+        },
+        WalletAction::UpdateAuthMethod {
+            account,
+            auth_method,
+            ..
+        } => DecodedPayload {
+            action: "UpdateAuthMethod".to_string(),
+            account: Some(account),
+            details: Some(format!("{auth_method:?}")),
+            ..Default::default()
+        },
+        WalletAction::WebAuthnAssertion { account, .. } => DecodedPayload {
+            action: "WebAuthnAssertion".to_string(),
+            account: Some(account),
+            ..Default::default()
+        },
+        WalletAction::Batch(actions) => DecodedPayload {
+            action: "Batch".to_string(),
+            account: actions.first().and_then(WalletAction::account).cloned(),
+            details: Some(
+                actions
+                    .into_iter()
+                    .map(|action| {
+                        let decoded = decode_wallet_action(action);
+                        match decoded.details {
+                            Some(details) => format!("{} ({details})", decoded.action),
+                            None => decoded.action,
+                        }
+                    })
+                    .collect::<Vec<_>>()
+                    .join("; "),
+            ),
+            ..Default::default()
+        },
+        WalletAction::Ed25519Signature {
+            account,
+            public_key,
+            ..
+        } => DecodedPayload {
+            action: "Ed25519Signature".to_string(),
+            account: Some(account),
+            details: Some(format!("session key {public_key}")),
+            ..Default::default()
+        },
+    }
+}
+
+fn decode_token_action(action: SmtTokenAction) -> DecodedPayload {
+    match action {
+        SmtTokenAction::Transfer {
+            sender,
+            recipient,
+            amount,
+        } => DecodedPayload {
+            action: "Transfer".to_string(),
+            account: Some(sender.0),
+            amount: Some(amount),
+            recipient: Some(recipient.0),
+            ..Default::default()
+        },
+        SmtTokenAction::TransferFrom {
+            owner,
+            spender,
+            recipient,
+            amount,
+        } => DecodedPayload {
+            action: "TransferFrom".to_string(),
+            account: Some(owner.0),
+            amount: Some(amount),
+            recipient: Some(recipient.0),
+            details: Some(format!("spender {}", spender.0)),
+            ..Default::default()
+        },
+        SmtTokenAction::Approve {
+            owner,
+            spender,
+            amount,
+        } => DecodedPayload {
+            action: "Approve".to_string(),
+            account: Some(owner.0),
+            amount: Some(amount),
+            recipient: Some(spender.0),
+            ..Default::default()
+        },
+    }
+}
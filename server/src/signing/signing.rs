@@ -0,0 +1,1886 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, bail, Context, Result};
+use axum::{
+    extract::{Path, Query, State},
+    http::{header::AUTHORIZATION, HeaderMap, StatusCode},
+    Json,
+};
+use chrono::{DateTime, NaiveDateTime};
+use client_sdk::contract_indexer::utoipa;
+use client_sdk::contract_indexer::{
+    utoipa::ToSchema,
+    utoipa_axum::{router::OpenApiRouter, routes},
+};
+use client_sdk::AppError;
+use hyli_modules::{
+    bus::{BusClientSender, BusMessage, SharedMessageBus},
+    log_error, module_bus_client, module_handle_messages,
+    modules::{websocket::WsTopicMessage, BuildApiContextInner, Module},
+};
+use opentelemetry::{
+    metrics::{Counter, UpDownCounter},
+    KeyValue,
+};
+use secp256k1::{ecdsa::Signature, Message, PublicKey, Secp256k1};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::FromRow;
+use tokio::sync::{mpsc, Mutex};
+
+use crate::app::{account_event_messages, AppOutWsEvent, WsMetrics};
+use crate::conf::{DatabaseConf, SigningConf};
+use crate::signing::decode::{decode_payload, DecodedPayload};
+use crate::ws_auth::WsAuth;
+use crate::ws_replay::EventReplay;
+use server::audit::{AuditKind, AuditRecorder};
+use server::db::DbPools;
+use server::deep_link::SigningDeepLink;
+use wallet::client::ledger;
+use wallet::utils::{ct_eq, ethereum_personal_sign_digest};
+
+/// How long consumed request ids are remembered to reject replays.
+const CONSUMED_ID_RETENTION_SECS: u64 = 86_400;
+
+/// How a paired device signs approvals.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DeviceKind {
+    /// Signs the sha256 of the message.
+    #[default]
+    Secp256k1,
+    /// Ledger reached through a WebHID relay, signing the message with `personal_sign`.
+    Ledger,
+}
+
+impl DeviceKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            DeviceKind::Secp256k1 => "secp256k1",
+            DeviceKind::Ledger => "ledger",
+        }
+    }
+
+    fn parse(kind: &str) -> Result<Self> {
+        match kind {
+            "secp256k1" => Ok(DeviceKind::Secp256k1),
+            "ledger" => Ok(DeviceKind::Ledger),
+            _ => bail!("Unknown device kind {kind}"),
+        }
+    }
+}
+
+/// A device (typically a mobile app) paired with an account, able to approve signing requests.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct PairedDevice {
+    pub device_id: String,
+    /// Hex-encoded compressed secp256k1 public key used to check approvals.
+    pub public_key: String,
+    pub label: Option<String>,
+    #[serde(default)]
+    pub kind: DeviceKind,
+    /// BIP-32 path of the key on a Ledger.
+    pub derivation_path: Option<String>,
+    /// Bearer token the device presents when polling, issued at pairing time. Once paired, only
+    /// its [`token_hash`] is kept.
+    #[serde(skip)]
+    pub token: String,
+}
+
+/// A paired device as stored in `signing_devices`.
+#[derive(Debug, FromRow)]
+struct DeviceRow {
+    account: String,
+    device_id: String,
+    public_key: String,
+    label: Option<String>,
+    kind: String,
+    derivation_path: Option<String>,
+    token_hash: String,
+}
+
+impl TryFrom<DeviceRow> for PairedDevice {
+    type Error = anyhow::Error;
+
+    fn try_from(row: DeviceRow) -> Result<Self> {
+        Ok(Self {
+            device_id: row.device_id,
+            public_key: row.public_key,
+            label: row.label,
+            kind: DeviceKind::parse(&row.kind)?,
+            derivation_path: row.derivation_path,
+            token: row.token_hash,
+        })
+    }
+}
+
+/// Hex-encoded sha256 of a device token, kept instead of the token itself.
+fn token_hash(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct DeviceApproval {
+    pub device_id: String,
+    pub public_key: String,
+    pub signature: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+pub enum SigningStatus {
+    Pending,
+    Approved,
+    Rejected,
+    Expired,
+}
+
+#[derive(Debug, Clone)]
+pub struct SigningRequest {
+    pub id: String,
+    pub account: String,
+    /// Hex-encoded message to sign.
+    pub message: String,
+    /// Structured summary of `message`, when it encodes a known wallet or token action.
+    pub decoded: Option<DecodedPayload>,
+    pub description: String,
+    pub origin: String,
+    /// Number of distinct paired devices that must approve before the request completes.
+    pub required_approvals: usize,
+    pub approvals: Vec<DeviceApproval>,
+    pub rejected_by: Option<String>,
+    pub status: SigningStatus,
+    pub created_at: u64,
+    created: Instant,
+    /// WebSocket topic, granted for the account, of the connection that opened the request.
+    /// Only it can read the outcome, as long as the grant lives.
+    requester_topic: String,
+}
+
+/// Audit record of a request id that reached a final state and can't be used again.
+#[derive(Debug, Clone, Serialize)]
+pub struct ConsumedRequest {
+    pub id: String,
+    pub account: String,
+    pub origin: String,
+    pub outcome: SigningStatus,
+    pub consumed_at: u64,
+}
+
+/// Signing lifecycle notifications, pushed on the account's WebSocket topics.
+#[derive(Debug, Clone, Serialize)]
+pub enum SigningEvent {
+    Requested {
+        id: String,
+        message: String,
+        decoded: Option<DecodedPayload>,
+        description: String,
+        origin: String,
+        required_approvals: usize,
+    },
+    Progress {
+        id: String,
+        approvals: usize,
+        required_approvals: usize,
+    },
+    Completed {
+        id: String,
+        approvals: Vec<DeviceApproval>,
+    },
+    Rejected {
+        id: String,
+        device_id: String,
+    },
+    Expired {
+        id: String,
+    },
+}
+
+/// Identifies a signing request in lifecycle bus events.
+#[derive(Debug, Clone)]
+pub struct SigningRequestSummary {
+    pub id: String,
+    pub account: String,
+    pub origin: String,
+    pub description: String,
+    pub message: String,
+    pub decoded: Option<DecodedPayload>,
+    pub approvals: Vec<DeviceApproval>,
+    pub required_approvals: usize,
+    pub rejected_by: Option<String>,
+    pub created_at: u64,
+}
+
+/// Signing lifecycle, broadcast on the shared bus for other modules to react to.
+#[derive(Debug, Clone)]
+pub enum SigningLifecycleEvent {
+    SigningRequestCreated(SigningRequestSummary),
+    SigningRequestApproved(SigningRequestSummary),
+    SigningRequestRejected(SigningRequestSummary),
+    SigningRequestExpired(SigningRequestSummary),
+}
+
+impl BusMessage for SigningLifecycleEvent {}
+
+struct SigningNotification {
+    account: String,
+    event: SigningEvent,
+    lifecycle: Option<SigningLifecycleEvent>,
+}
+
+pub struct SigningModule {
+    bus: SigningModuleBusClient,
+    inner: Arc<SigningModuleInner>,
+    events: mpsc::UnboundedReceiver<SigningNotification>,
+    ws_auth: Arc<WsAuth>,
+    replay: Arc<EventReplay>,
+    ws_metrics: WsMetrics,
+}
+
+pub struct SigningModuleInner {
+    config: SigningConf,
+    db: DbPools,
+    devices: Mutex<HashMap<String, Vec<PairedDevice>>>,
+    requests: Mutex<HashMap<String, SigningRequest>>,
+    consumed: Mutex<HashMap<String, ConsumedRequest>>,
+    events: mpsc::UnboundedSender<SigningNotification>,
+    audit: AuditRecorder,
+    metrics: SigningMetrics,
+    ws_auth: Arc<WsAuth>,
+}
+
+/// Counts the signing requests created and how they ended, and those still pending.
+struct SigningMetrics {
+    created: Counter<u64>,
+    completed: Counter<u64>,
+    pending: UpDownCounter<i64>,
+}
+
+impl SigningMetrics {
+    fn new() -> Self {
+        let meter = opentelemetry::global::meter("wallet_signing");
+        Self {
+            created: meter
+                .u64_counter("signing_requests_total")
+                .with_description("Number of signing requests created")
+                .build(),
+            completed: meter
+                .u64_counter("signing_requests_completed_total")
+                .with_description("Number of signing requests approved, rejected or expired")
+                .build(),
+            pending: meter
+                .i64_up_down_counter("signing_requests_pending")
+                .with_description("Number of signing requests waiting for approvals")
+                .build(),
+        }
+    }
+
+    fn created(&self) {
+        self.created.add(1, &[]);
+        self.pending.add(1, &[]);
+    }
+
+    fn completed(&self, outcome: SigningStatus) {
+        self.completed.add(
+            1,
+            &[KeyValue::new(
+                "outcome",
+                format!("{outcome:?}").to_lowercase(),
+            )],
+        );
+        self.pending.add(-1, &[]);
+    }
+}
+
+#[derive(Clone)]
+pub struct SigningModuleCtx {
+    pub api_ctx: Arc<BuildApiContextInner>,
+    pub config: SigningConf,
+    pub db_url: String,
+    pub database: DatabaseConf,
+    pub audit: AuditRecorder,
+    pub ws_auth: Arc<WsAuth>,
+    pub replay: Arc<EventReplay>,
+}
+
+module_bus_client! {
+#[derive(Debug)]
+pub struct SigningModuleBusClient {
+    sender(WsTopicMessage<AppOutWsEvent>),
+    sender(SigningLifecycleEvent),
+}
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+fn bearer_token(headers: &HeaderMap) -> Result<&str, AppError> {
+    headers
+        .get(AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .ok_or_else(|| AppError(StatusCode::UNAUTHORIZED, anyhow!("Missing bearer token")))
+}
+
+fn consume(
+    consumed: &mut HashMap<String, ConsumedRequest>,
+    metrics: &SigningMetrics,
+    request: &SigningRequest,
+) {
+    metrics.completed(request.status);
+    tracing::info!(
+        "Signing request {} for {} from {} consumed with outcome {:?}",
+        request.id,
+        request.account,
+        request.origin,
+        request.status
+    );
+    consumed.insert(
+        request.id.clone(),
+        ConsumedRequest {
+            id: request.id.clone(),
+            account: request.account.clone(),
+            origin: request.origin.clone(),
+            outcome: request.status,
+            consumed_at: now_secs(),
+        },
+    );
+}
+
+fn verify_device_signature(device: &PairedDevice, message: &str, signature: &str) -> Result<()> {
+    let public_key =
+        PublicKey::from_slice(&hex::decode(&device.public_key).context("decoding public key")?)
+            .context("parsing public key")?;
+    let signature = Signature::from_compact(&hex::decode(signature).context("decoding signature")?)
+        .context("parsing signature")?;
+    let message = hex::decode(message).context("decoding message")?;
+    let message_hash: [u8; 32] = match device.kind {
+        DeviceKind::Secp256k1 => Sha256::digest(message).into(),
+        DeviceKind::Ledger => ethereum_personal_sign_digest(message),
+    };
+    Secp256k1::verification_only()
+        .verify_ecdsa(Message::from_digest(message_hash), &signature, &public_key)
+        .map_err(|e| anyhow!("Invalid signature: {e}"))
+}
+
+impl SigningModuleInner {
+    fn notify(&self, request: &SigningRequest, event: SigningEvent) {
+        let summary = SigningRequestSummary {
+            id: request.id.clone(),
+            account: request.account.clone(),
+            origin: request.origin.clone(),
+            description: request.description.clone(),
+            message: request.message.clone(),
+            decoded: request.decoded.clone(),
+            approvals: request.approvals.clone(),
+            required_approvals: request.required_approvals,
+            rejected_by: request.rejected_by.clone(),
+            created_at: request.created_at,
+        };
+        let lifecycle = match event {
+            SigningEvent::Requested { .. } => {
+                Some(SigningLifecycleEvent::SigningRequestCreated(summary))
+            }
+            SigningEvent::Progress { .. } => None,
+            SigningEvent::Completed { .. } => {
+                Some(SigningLifecycleEvent::SigningRequestApproved(summary))
+            }
+            SigningEvent::Rejected { .. } => {
+                Some(SigningLifecycleEvent::SigningRequestRejected(summary))
+            }
+            SigningEvent::Expired { .. } => {
+                Some(SigningLifecycleEvent::SigningRequestExpired(summary))
+            }
+        };
+        let notification = SigningNotification {
+            account: request.account.clone(),
+            event,
+            lifecycle,
+        };
+        if self.events.send(notification).is_err() {
+            tracing::warn!("Signing module event channel closed");
+        }
+    }
+
+    /// Checks the bearer token is a WebSocket topic granted for `account`, which only whoever
+    /// signed a WebSocket auth challenge with one of its live session keys holds.
+    fn authenticate_owner<'a>(
+        &self,
+        account: &str,
+        headers: &'a HeaderMap,
+    ) -> Result<&'a str, AppError> {
+        let topic = bearer_token(headers)?;
+        if !self.ws_auth.is_granted(account, topic) {
+            return Err(AppError(
+                StatusCode::UNAUTHORIZED,
+                anyhow!("Not authenticated as the owner of {account}"),
+            ));
+        }
+        Ok(topic)
+    }
+
+    async fn pair_device(&self, account: &str, device: PairedDevice) -> Result<PairedDevice> {
+        PublicKey::from_slice(&hex::decode(&device.public_key).context("decoding public key")?)
+            .context("parsing public key")?;
+
+        let mut devices = self.devices.lock().await;
+        let paired = devices.entry(account.to_string()).or_default();
+        if paired.iter().any(|d| d.device_id == device.device_id) {
+            bail!(
+                "Device {} is already paired with account {}",
+                device.device_id,
+                account
+            );
+        }
+        let stored = PairedDevice {
+            token: token_hash(&device.token),
+            ..device.clone()
+        };
+        self.store_device(account, &stored).await?;
+        paired.push(stored);
+
+        tracing::info!(
+            "Paired device {} with account {}",
+            device.device_id,
+            account
+        );
+        Ok(device)
+    }
+
+    async fn store_device(&self, account: &str, device: &PairedDevice) -> Result<()> {
+        sqlx::query(
+            "
+            INSERT INTO signing_devices
+                (account, device_id, public_key, label, kind, derivation_path, token_hash, paired_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, NOW())
+            ",
+        )
+        .bind(account)
+        .bind(&device.device_id)
+        .bind(&device.public_key)
+        .bind(&device.label)
+        .bind(device.kind.as_str())
+        .bind(&device.derivation_path)
+        .bind(&device.token)
+        .execute(&self.db.primary)
+        .await
+        .context("storing paired device")?;
+        Ok(())
+    }
+
+    /// Loads the devices paired before this process started.
+    async fn load_devices(&self) -> Result<()> {
+        let rows: Vec<DeviceRow> = sqlx::query_as(
+            "
+            SELECT account, device_id, public_key, label, kind, derivation_path, token_hash
+            FROM signing_devices
+            ORDER BY paired_at
+            ",
+        )
+        .fetch_all(&self.db.primary)
+        .await?;
+        let mut devices = self.devices.lock().await;
+        for row in rows {
+            let account = row.account.clone();
+            match PairedDevice::try_from(row) {
+                Ok(device) => devices.entry(account).or_default().push(device),
+                Err(e) => tracing::warn!("Skipping paired device of {account}: {e:#}"),
+            }
+        }
+        tracing::info!("Loaded devices paired with {} accounts", devices.len());
+        Ok(())
+    }
+
+    /// Returns the accounts the device is paired with, if the token matches its pairing.
+    async fn authenticate_device(&self, device_id: &str, token: &str) -> Result<Vec<String>> {
+        let token = token_hash(token);
+        let accounts: Vec<String> = self
+            .devices
+            .lock()
+            .await
+            .iter()
+            .filter(|(_, devices)| {
+                devices.iter().any(|d| {
+                    d.device_id == device_id && ct_eq(d.token.as_bytes(), token.as_bytes())
+                })
+            })
+            .map(|(account, _)| account.clone())
+            .collect();
+        if accounts.is_empty() {
+            bail!("Unknown device or invalid token");
+        }
+        Ok(accounts)
+    }
+
+    async fn pending_requests(
+        &self,
+        device_id: &str,
+        token: &str,
+    ) -> Result<Vec<PendingSigningRequest>> {
+        let accounts = self.authenticate_device(device_id, token).await?;
+        let mut pending: Vec<PendingSigningRequest> = self
+            .requests
+            .lock()
+            .await
+            .values()
+            .filter(|r| r.status == SigningStatus::Pending && accounts.contains(&r.account))
+            .filter(|r| !r.approvals.iter().any(|a| a.device_id == device_id))
+            .map(|r| PendingSigningRequest {
+                id: r.id.clone(),
+                account: r.account.clone(),
+                message: r.message.clone(),
+                decoded: r.decoded.clone(),
+                description: r.description.clone(),
+                origin: r.origin.clone(),
+                age_secs: r.created.elapsed().as_secs(),
+                approvals: r.approvals.len(),
+                required_approvals: r.required_approvals,
+            })
+            .collect();
+        pending.sort_by_key(|r| std::cmp::Reverse(r.age_secs));
+        Ok(pending)
+    }
+
+    async fn create_request(
+        &self,
+        body: CreateSigningRequestBody,
+        requester_topic: &str,
+    ) -> Result<SigningRequest> {
+        let message_bytes = hex::decode(&body.message).context("message must be hex-encoded")?;
+
+        let paired_devices = self
+            .devices
+            .lock()
+            .await
+            .get(&body.account)
+            .map(Vec::len)
+            .unwrap_or_default();
+        if paired_devices == 0 {
+            bail!("No device paired with account {}", body.account);
+        }
+
+        let required_approvals = body.required_approvals.unwrap_or(1);
+        if required_approvals == 0 || required_approvals > paired_devices {
+            bail!(
+                "Invalid approval policy: {required_approvals} approvals required but {paired_devices} device(s) paired"
+            );
+        }
+
+        let mut requests = self.requests.lock().await;
+        let pending_from_origin = requests
+            .values()
+            .filter(|r| {
+                r.status == SigningStatus::Pending
+                    && r.account == body.account
+                    && r.origin == body.origin
+            })
+            .count();
+        if pending_from_origin >= self.config.max_pending_per_origin {
+            tracing::warn!(
+                "Origin {} reached its pending signing request quota for {}",
+                body.origin,
+                body.account
+            );
+            bail!(
+                "Too many pending signing requests from {} for account {}",
+                body.origin,
+                body.account
+            );
+        }
+
+        let consumed = self.consumed.lock().await;
+        let id = loop {
+            let id = hex::encode(rand::random::<[u8; 16]>());
+            if !requests.contains_key(&id) && !consumed.contains_key(&id) {
+                break id;
+            }
+        };
+        drop(consumed);
+
+        let request = SigningRequest {
+            id,
+            account: body.account,
+            message: body.message,
+            decoded: decode_payload(&message_bytes),
+            description: body.description,
+            origin: body.origin,
+            required_approvals,
+            approvals: vec![],
+            rejected_by: None,
+            status: SigningStatus::Pending,
+            created_at: now_secs(),
+            created: Instant::now(),
+            requester_topic: requester_topic.to_string(),
+        };
+
+        requests.insert(request.id.clone(), request.clone());
+        drop(requests);
+        self.metrics.created();
+
+        self.notify(
+            &request,
+            SigningEvent::Requested {
+                id: request.id.clone(),
+                message: request.message.clone(),
+                decoded: request.decoded.clone(),
+                description: request.description.clone(),
+                origin: request.origin.clone(),
+                required_approvals,
+            },
+        );
+
+        Ok(request)
+    }
+
+    /// Records the response of a device authenticated for `accounts`.
+    async fn respond(
+        &self,
+        body: SigningResponseBody,
+        accounts: &[String],
+    ) -> Result<SigningRequest> {
+        let mut requests = self.requests.lock().await;
+        let mut consumed = self.consumed.lock().await;
+        if let Some(record) = consumed.get(&body.id) {
+            tracing::warn!(
+                "Replayed response from device {} for consumed signing request {}",
+                body.device_id,
+                body.id
+            );
+            bail!(
+                "Signing request {} was already consumed ({:?})",
+                body.id,
+                record.outcome
+            );
+        }
+        let request = requests
+            .get_mut(&body.id)
+            .filter(|r| accounts.contains(&r.account))
+            .ok_or_else(|| anyhow!("Signing request {} not found", body.id))?;
+
+        if request.status != SigningStatus::Pending {
+            bail!("Signing request {} is no longer pending", body.id);
+        }
+
+        let device = self
+            .devices
+            .lock()
+            .await
+            .get(&request.account)
+            .and_then(|devices| devices.iter().find(|d| d.device_id == body.device_id))
+            .cloned()
+            .ok_or_else(|| {
+                anyhow!(
+                    "Device {} is not paired with account {}",
+                    body.device_id,
+                    request.account
+                )
+            })?;
+
+        if request
+            .approvals
+            .iter()
+            .any(|a| a.device_id == device.device_id)
+        {
+            bail!("Device {} already approved this request", device.device_id);
+        }
+
+        if !body.approved {
+            request.status = SigningStatus::Rejected;
+            request.rejected_by = Some(device.device_id.clone());
+            consume(&mut consumed, &self.metrics, request);
+            self.notify(
+                request,
+                SigningEvent::Rejected {
+                    id: request.id.clone(),
+                    device_id: device.device_id,
+                },
+            );
+            return Ok(request.clone());
+        }
+
+        let signature = body
+            .signature
+            .ok_or_else(|| anyhow!("Missing signature for approval"))?;
+        verify_device_signature(&device, &request.message, &signature)?;
+
+        request.approvals.push(DeviceApproval {
+            device_id: device.device_id,
+            public_key: device.public_key,
+            signature,
+        });
+
+        if request.approvals.len() >= request.required_approvals {
+            request.status = SigningStatus::Approved;
+            consume(&mut consumed, &self.metrics, request);
+            self.notify(
+                request,
+                SigningEvent::Completed {
+                    id: request.id.clone(),
+                    approvals: request.approvals.clone(),
+                },
+            );
+        } else {
+            self.notify(
+                request,
+                SigningEvent::Progress {
+                    id: request.id.clone(),
+                    approvals: request.approvals.len(),
+                    required_approvals: request.required_approvals,
+                },
+            );
+        }
+
+        Ok(request.clone())
+    }
+
+    fn deep_link(&self, request: &SigningRequest) -> Option<String> {
+        let public_url = self.config.public_url.as_ref()?;
+        let message = hex::decode(&request.message).ok()?;
+        let callback = format!("{}/signing/respond", public_url.trim_end_matches('/'));
+        Some(SigningDeepLink::new(&request.id, &callback, &message).to_url())
+    }
+
+    /// APDUs the relay of a paired Ledger must send to sign a pending request.
+    async fn ledger_apdus(&self, id: &str, device_id: &str, token: &str) -> Result<Vec<String>> {
+        let accounts = self.authenticate_device(device_id, token).await?;
+        let request = self
+            .requests
+            .lock()
+            .await
+            .get(id)
+            .filter(|r| r.status == SigningStatus::Pending && accounts.contains(&r.account))
+            .cloned()
+            .ok_or_else(|| anyhow!("Signing request {id} not found"))?;
+        let device = self
+            .devices
+            .lock()
+            .await
+            .get(&request.account)
+            .and_then(|devices| devices.iter().find(|d| d.device_id == device_id))
+            .cloned()
+            .ok_or_else(|| anyhow!("Device {device_id} is not paired with {}", request.account))?;
+        if device.kind != DeviceKind::Ledger {
+            bail!("Device {device_id} is not a Ledger");
+        }
+
+        let path = ledger::parse_derivation_path(
+            device
+                .derivation_path
+                .as_deref()
+                .unwrap_or(ledger::DEFAULT_DERIVATION_PATH),
+        )?;
+        let message = hex::decode(&request.message).context("decoding message")?;
+        Ok(ledger::sign_personal_message_apdus(&path, &message)
+            .iter()
+            .map(hex::encode)
+            .collect())
+    }
+
+    async fn get_request(&self, id: &str, requester_topic: &str) -> Result<SigningRequest> {
+        self.requests
+            .lock()
+            .await
+            .get(id)
+            .filter(|r| ct_eq(r.requester_topic.as_bytes(), requester_topic.as_bytes()))
+            .filter(|r| self.ws_auth.is_granted(&r.account, requester_topic))
+            .cloned()
+            .ok_or_else(|| anyhow!("Signing request {id} not found"))
+    }
+
+    /// Records device decisions on a request in the audit log.
+    fn audit_decision(&self, lifecycle: &SigningLifecycleEvent) {
+        let (outcome, summary) = match lifecycle {
+            SigningLifecycleEvent::SigningRequestApproved(summary) => {
+                (SigningStatus::Approved, summary)
+            }
+            SigningLifecycleEvent::SigningRequestRejected(summary) => {
+                (SigningStatus::Rejected, summary)
+            }
+            _ => return,
+        };
+        self.audit.record(
+            AuditKind::SigningApproval,
+            summary.account.clone(),
+            serde_json::json!({
+                "request_id": summary.id,
+                "origin": summary.origin,
+                "outcome": format!("{outcome:?}"),
+                "approvals": summary.approvals.iter().map(|a| &a.device_id).collect::<Vec<_>>(),
+                "rejected_by": summary.rejected_by,
+                "action": summary.decoded.as_ref().map(|d| &d.action),
+            }),
+        );
+    }
+
+    /// Stores a request that reached a final state, so users can audit it later.
+    async fn record_history(&self, lifecycle: &SigningLifecycleEvent) -> Result<()> {
+        let (outcome, summary) = match lifecycle {
+            SigningLifecycleEvent::SigningRequestCreated(_) => return Ok(()),
+            SigningLifecycleEvent::SigningRequestApproved(summary) => {
+                (SigningStatus::Approved, summary)
+            }
+            SigningLifecycleEvent::SigningRequestRejected(summary) => {
+                (SigningStatus::Rejected, summary)
+            }
+            SigningLifecycleEvent::SigningRequestExpired(summary) => {
+                (SigningStatus::Expired, summary)
+            }
+        };
+        let devices: Vec<String> = summary
+            .approvals
+            .iter()
+            .map(|a| a.device_id.clone())
+            .chain(summary.rejected_by.clone())
+            .collect();
+        let created_at = DateTime::from_timestamp(summary.created_at as i64, 0)
+            .map(|d| d.naive_utc())
+            .unwrap_or_default();
+
+        sqlx::query(
+            "
+            INSERT INTO signing_history
+                (id, account, origin, description, message, action, outcome, devices, created_at, completed_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, NOW())
+            ON CONFLICT (id) DO NOTHING
+            ",
+        )
+        .bind(&summary.id)
+        .bind(&summary.account)
+        .bind(&summary.origin)
+        .bind(&summary.description)
+        .bind(&summary.message)
+        .bind(summary.decoded.as_ref().map(|d| d.action.clone()))
+        .bind(format!("{outcome:?}"))
+        .bind(devices)
+        .bind(created_at)
+        .execute(&self.db.primary)
+        .await?;
+        Ok(())
+    }
+
+    async fn history(&self, account: &str) -> Result<Vec<SigningHistoryEntry>> {
+        Ok(sqlx::query_as(
+            "
+            SELECT id, account, origin, description, message, action, outcome, devices, created_at, completed_at
+            FROM signing_history
+            WHERE account = $1
+            ORDER BY completed_at DESC
+            LIMIT 100
+            ",
+        )
+        .bind(account)
+        .fetch_all(self.db.reader())
+        .await?)
+    }
+
+    /// Marks timed out requests as expired, and forgets them once they've been
+    /// observable in their final state for another timeout period.
+    async fn expire_requests(&self) {
+        let timeout = Duration::from_secs(self.config.request_timeout_secs);
+        let mut requests = self.requests.lock().await;
+        let mut consumed = self.consumed.lock().await;
+        requests.retain(|_, request| {
+            let elapsed = request.created.elapsed();
+            if elapsed >= timeout && request.status == SigningStatus::Pending {
+                tracing::info!("Signing request {} expired", request.id);
+                request.status = SigningStatus::Expired;
+                consume(&mut consumed, &self.metrics, request);
+                self.notify(
+                    request,
+                    SigningEvent::Expired {
+                        id: request.id.clone(),
+                    },
+                );
+            }
+            elapsed < timeout * 2
+        });
+
+        let now = now_secs();
+        consumed.retain(|_, record| {
+            now.saturating_sub(record.consumed_at) < CONSUMED_ID_RETENTION_SECS
+        });
+    }
+}
+
+impl Module for SigningModule {
+    type Context = SigningModuleCtx;
+
+    async fn build(bus: SharedMessageBus, ctx: Self::Context) -> Result<Self> {
+        let db = DbPools::connect(&ctx.db_url, &ctx.database).await?;
+
+        sqlx::query(
+            r#"CREATE TABLE IF NOT EXISTS signing_history (
+                id TEXT PRIMARY KEY,
+                account TEXT NOT NULL,
+                origin TEXT NOT NULL,
+                description TEXT NOT NULL,
+                message TEXT NOT NULL,
+                action TEXT NULL,
+                outcome TEXT NOT NULL,
+                devices TEXT[] NOT NULL,
+                created_at TIMESTAMP NOT NULL,
+                completed_at TIMESTAMP NOT NULL
+            )"#,
+        )
+        .execute(&db.primary)
+        .await?;
+        sqlx::query(
+            "CREATE INDEX IF NOT EXISTS signing_history_account_idx ON signing_history (account, completed_at)",
+        )
+        .execute(&db.primary)
+        .await?;
+        sqlx::query(
+            r#"CREATE TABLE IF NOT EXISTS signing_devices (
+                account TEXT NOT NULL,
+                device_id TEXT NOT NULL,
+                public_key TEXT NOT NULL,
+                label TEXT NULL,
+                kind TEXT NOT NULL,
+                derivation_path TEXT NULL,
+                token_hash TEXT NOT NULL,
+                paired_at TIMESTAMP NOT NULL,
+                PRIMARY KEY (account, device_id)
+            )"#,
+        )
+        .execute(&db.primary)
+        .await?;
+
+        let (events_tx, events) = mpsc::unbounded_channel();
+        let inner = Arc::new(SigningModuleInner {
+            config: ctx.config.clone(),
+            db,
+            devices: Mutex::new(HashMap::new()),
+            requests: Mutex::new(HashMap::new()),
+            consumed: Mutex::new(HashMap::new()),
+            events: events_tx,
+            audit: ctx.audit.clone(),
+            metrics: SigningMetrics::new(),
+            ws_auth: ctx.ws_auth.clone(),
+        });
+        inner.load_devices().await?;
+
+        let (router, openapi) = OpenApiRouter::default()
+            .routes(routes!(route_pair_device))
+            .routes(routes!(route_create_request))
+            .routes(routes!(route_get_request))
+            .routes(routes!(route_respond))
+            .routes(routes!(route_ledger_public_key_apdu))
+            .routes(routes!(route_pair_ledger))
+            .routes(routes!(route_ledger_apdus))
+            .routes(routes!(route_ledger_respond))
+            .routes(routes!(route_pending_requests))
+            .routes(routes!(route_history))
+            .split_for_parts();
+        let api = router.with_state(inner.clone());
+
+        if let Ok(mut guard) = ctx.api_ctx.router.lock() {
+            if let Some(router) = guard.take() {
+                guard.replace(router.merge(api));
+            }
+        }
+        if let Ok(mut guard) = ctx.api_ctx.openapi.lock() {
+            guard.merge(openapi);
+        }
+
+        tracing::info!("Signing module initialized");
+
+        Ok(Self {
+            bus: SigningModuleBusClient::new_from_bus(bus.new_handle()).await,
+            inner,
+            events,
+            ws_auth: ctx.ws_auth.clone(),
+            replay: ctx.replay.clone(),
+            ws_metrics: WsMetrics::default(),
+        })
+    }
+
+    async fn run(&mut self) -> Result<()> {
+        let mut cleanup =
+            tokio::time::interval(Duration::from_secs(self.inner.config.cleanup_interval_secs));
+
+        module_handle_messages! {
+            on_self self,
+            Some(notification) = self.events.recv() => {
+                for message in account_event_messages(
+                    &self.ws_auth,
+                    &self.replay,
+                    &notification.account,
+                    AppOutWsEvent::SigningEvent(notification.event),
+                ) {
+                    self.ws_metrics.published(&message);
+                    self.bus.send(message)?;
+                }
+                if let Some(lifecycle) = notification.lifecycle {
+                    self.inner.audit_decision(&lifecycle);
+                    let _ = log_error!(
+                        self.inner.record_history(&lifecycle).await,
+                        "Recording signing history"
+                    );
+                    self.bus.send(lifecycle)?;
+                }
+            }
+            _ = cleanup.tick() => {
+                self.inner.expire_requests().await;
+            }
+        };
+        Ok(())
+    }
+}
+
+// --------------------------------------------------------
+//     Routes
+// --------------------------------------------------------
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct PairDeviceBody {
+    pub device_id: String,
+    pub public_key: String,
+    pub label: Option<String>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct PairLedgerBody {
+    pub device_id: String,
+    pub label: Option<String>,
+    /// Defaults to the first Ethereum account, `m/44'/60'/0'/0/0`.
+    pub derivation_path: Option<String>,
+    /// Hex-encoded response of the Ledger to the public key APDU.
+    pub response: String,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct CreateSigningRequestBody {
+    pub account: String,
+    pub message: String,
+    pub description: String,
+    pub origin: String,
+    pub required_approvals: Option<usize>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct SigningResponseBody {
+    pub id: String,
+    pub device_id: String,
+    pub approved: bool,
+    /// Hex-encoded compact signature of the sha256 of the message.
+    pub signature: Option<String>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct LedgerResponseBody {
+    pub id: String,
+    pub device_id: String,
+    /// Hex-encoded response of the Ledger to the last signing APDU.
+    pub response: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct PairDeviceResponse {
+    #[serde(flatten)]
+    pub device: PairedDevice,
+    pub token: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct PairLedgerResponse {
+    #[serde(flatten)]
+    pub pairing: PairDeviceResponse,
+    /// Ethereum address of the paired key.
+    pub address: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DerivationPathQuery {
+    pub path: Option<String>,
+}
+
+/// Hex-encoded APDUs for the relay to send to the Ledger, in order.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct LedgerApdus {
+    pub apdus: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PendingQuery {
+    pub device: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct CreatedSigningRequest {
+    #[serde(flatten)]
+    pub request: SigningRequestResponse,
+    /// `hyli://sign` link opening a native wallet app on the request, when
+    /// `signing.public_url` is configured.
+    pub deep_link: Option<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct PendingSigningRequest {
+    pub id: String,
+    pub account: String,
+    pub message: String,
+    pub decoded: Option<DecodedPayload>,
+    pub description: String,
+    pub origin: String,
+    pub age_secs: u64,
+    pub approvals: usize,
+    pub required_approvals: usize,
+}
+
+#[derive(Debug, Serialize, FromRow, ToSchema)]
+pub struct SigningHistoryEntry {
+    pub id: String,
+    pub account: String,
+    pub origin: String,
+    pub description: String,
+    pub message: String,
+    /// Decoded action type, when the message was recognized.
+    pub action: Option<String>,
+    pub outcome: String,
+    /// Devices that approved, or the device that rejected, the request.
+    pub devices: Vec<String>,
+    #[schema(value_type = String)]
+    pub created_at: NaiveDateTime,
+    #[schema(value_type = String)]
+    pub completed_at: NaiveDateTime,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SigningRequestResponse {
+    pub id: String,
+    pub account: String,
+    pub decoded: Option<DecodedPayload>,
+    pub status: SigningStatus,
+    pub required_approvals: usize,
+    pub approvals: Vec<DeviceApproval>,
+    pub rejected_by: Option<String>,
+    pub created_at: u64,
+}
+
+impl From<SigningRequest> for SigningRequestResponse {
+    fn from(request: SigningRequest) -> Self {
+        Self {
+            id: request.id,
+            account: request.account,
+            decoded: request.decoded,
+            status: request.status,
+            required_approvals: request.required_approvals,
+            approvals: request.approvals,
+            rejected_by: request.rejected_by,
+            created_at: request.created_at,
+        }
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/signing/devices/{account}",
+    tag = "Signing",
+    params(
+        ("account" = String, Path, description = "Account to pair the device with")
+    ),
+    request_body = PairDeviceBody,
+    responses(
+        (status = OK, description = "Device paired, returns its polling token", body = PairDeviceResponse),
+        (status = UNAUTHORIZED, description = "Bearer token isn't a WebSocket topic granted for the account")
+    )
+)]
+async fn route_pair_device(
+    State(ctx): State<Arc<SigningModuleInner>>,
+    Path(account): Path<String>,
+    headers: HeaderMap,
+    Json(body): Json<PairDeviceBody>,
+) -> Result<Json<PairDeviceResponse>, AppError> {
+    ctx.authenticate_owner(&account, &headers)?;
+
+    let device = PairedDevice {
+        device_id: body.device_id,
+        public_key: body.public_key,
+        label: body.label,
+        kind: DeviceKind::Secp256k1,
+        derivation_path: None,
+        token: hex::encode(rand::random::<[u8; 32]>()),
+    };
+    match ctx.pair_device(&account, device).await {
+        Ok(device) => Ok(Json(PairDeviceResponse {
+            token: device.token.clone(),
+            device,
+        })),
+        Err(e) => {
+            tracing::error!("Error pairing device: {:?}", e);
+            Err(AppError::from(e))
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/signing/pending",
+    tag = "Signing",
+    params(
+        ("device" = String, Query, description = "Id of the polling device")
+    ),
+    responses(
+        (status = OK, description = "Outstanding requests for the device", body = Vec<PendingSigningRequest>),
+        (status = UNAUTHORIZED, description = "Unknown device or invalid token")
+    )
+)]
+async fn route_pending_requests(
+    State(ctx): State<Arc<SigningModuleInner>>,
+    Query(query): Query<PendingQuery>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<PendingSigningRequest>>, AppError> {
+    let token = bearer_token(&headers)?;
+
+    ctx.pending_requests(&query.device, token)
+        .await
+        .map(Json)
+        .map_err(|e| AppError(StatusCode::UNAUTHORIZED, e))
+}
+
+#[utoipa::path(
+    post,
+    path = "/signing/request",
+    tag = "Signing",
+    request_body = CreateSigningRequestBody,
+    responses(
+        (status = OK, description = "Signing request created", body = CreatedSigningRequest),
+        (status = UNAUTHORIZED, description = "Bearer token isn't a WebSocket topic granted for the account")
+    )
+)]
+async fn route_create_request(
+    State(ctx): State<Arc<SigningModuleInner>>,
+    headers: HeaderMap,
+    Json(body): Json<CreateSigningRequestBody>,
+) -> Result<Json<CreatedSigningRequest>, AppError> {
+    // The request is bound to the WebSocket connection of the requester, by its topic
+    let topic = ctx.authenticate_owner(&body.account, &headers)?;
+
+    match ctx.create_request(body, topic).await {
+        Ok(request) => Ok(Json(CreatedSigningRequest {
+            deep_link: ctx.deep_link(&request),
+            request: request.into(),
+        })),
+        Err(e) => {
+            tracing::error!("Error creating signing request: {:?}", e);
+            Err(AppError::from(e))
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/signing/request/{id}",
+    tag = "Signing",
+    params(
+        ("id" = String, Path, description = "Signing request id")
+    ),
+    responses(
+        (status = OK, description = "Current status of the signing request", body = SigningRequestResponse),
+        (status = UNAUTHORIZED, description = "Missing bearer token"),
+        (status = NOT_FOUND, description = "Signing request not found, or not opened from the bearer's WebSocket topic")
+    )
+)]
+async fn route_get_request(
+    State(ctx): State<Arc<SigningModuleInner>>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<SigningRequestResponse>, AppError> {
+    let topic = bearer_token(&headers)?;
+    // Requests of other connections look the same as unknown ones
+    ctx.get_request(&id, topic)
+        .await
+        .map(|request| Json(request.into()))
+        .map_err(|e| AppError(StatusCode::NOT_FOUND, e))
+}
+
+#[utoipa::path(
+    post,
+    path = "/signing/respond",
+    tag = "Signing",
+    request_body = SigningResponseBody,
+    responses(
+        (status = OK, description = "Response recorded", body = SigningRequestResponse),
+        (status = UNAUTHORIZED, description = "Unknown device or invalid token")
+    )
+)]
+async fn route_respond(
+    State(ctx): State<Arc<SigningModuleInner>>,
+    headers: HeaderMap,
+    Json(body): Json<SigningResponseBody>,
+) -> Result<Json<SigningRequestResponse>, AppError> {
+    let token = bearer_token(&headers)?;
+    let accounts = ctx
+        .authenticate_device(&body.device_id, token)
+        .await
+        .map_err(|e| AppError(StatusCode::UNAUTHORIZED, e))?;
+
+    match ctx.respond(body, &accounts).await {
+        Ok(request) => Ok(Json(request.into())),
+        Err(e) => {
+            tracing::error!("Error responding to signing request: {:?}", e);
+            Err(AppError::from(e))
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/signing/ledger/public_key_apdu",
+    tag = "Signing",
+    params(
+        ("path" = Option<String>, Query, description = "BIP-32 derivation path, m/44'/60'/0'/0/0 by default")
+    ),
+    responses(
+        (status = OK, description = "APDU reading the Ledger public key", body = LedgerApdus)
+    )
+)]
+async fn route_ledger_public_key_apdu(
+    Query(query): Query<DerivationPathQuery>,
+) -> Result<Json<LedgerApdus>, AppError> {
+    let path = ledger::parse_derivation_path(
+        query
+            .path
+            .as_deref()
+            .unwrap_or(ledger::DEFAULT_DERIVATION_PATH),
+    )
+    .map_err(|e| AppError(StatusCode::BAD_REQUEST, e))?;
+    Ok(Json(LedgerApdus {
+        apdus: vec![hex::encode(ledger::get_public_key_apdu(&path))],
+    }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/signing/ledger/devices/{account}",
+    tag = "Signing",
+    params(
+        ("account" = String, Path, description = "Account to pair the Ledger with")
+    ),
+    request_body = PairLedgerBody,
+    responses(
+        (status = OK, description = "Ledger paired, returns its polling token and address", body = PairLedgerResponse),
+        (status = UNAUTHORIZED, description = "Bearer token isn't a WebSocket topic granted for the account")
+    )
+)]
+async fn route_pair_ledger(
+    State(ctx): State<Arc<SigningModuleInner>>,
+    Path(account): Path<String>,
+    headers: HeaderMap,
+    Json(body): Json<PairLedgerBody>,
+) -> Result<Json<PairLedgerResponse>, AppError> {
+    ctx.authenticate_owner(&account, &headers)?;
+
+    let derivation_path = body
+        .derivation_path
+        .unwrap_or_else(|| ledger::DEFAULT_DERIVATION_PATH.to_string());
+    let public_key = ledger::parse_derivation_path(&derivation_path)
+        .and_then(|_| hex::decode(&body.response).context("decoding Ledger response"))
+        .and_then(|response| ledger::parse_public_key_response(&response))
+        .map_err(|e| AppError(StatusCode::BAD_REQUEST, e))?;
+
+    let device = PairedDevice {
+        device_id: body.device_id,
+        public_key: hex::encode(public_key.public_key),
+        label: body.label,
+        kind: DeviceKind::Ledger,
+        derivation_path: Some(derivation_path),
+        token: hex::encode(rand::random::<[u8; 32]>()),
+    };
+    match ctx.pair_device(&account, device).await {
+        Ok(device) => Ok(Json(PairLedgerResponse {
+            pairing: PairDeviceResponse {
+                token: device.token.clone(),
+                device,
+            },
+            address: public_key.address,
+        })),
+        Err(e) => {
+            tracing::error!("Error pairing Ledger: {:?}", e);
+            Err(AppError::from(e))
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/signing/ledger/apdus/{id}",
+    tag = "Signing",
+    params(
+        ("id" = String, Path, description = "Signing request id"),
+        ("device" = String, Query, description = "Id of the Ledger device")
+    ),
+    responses(
+        (status = OK, description = "APDUs signing the request on the Ledger", body = LedgerApdus),
+        (status = UNAUTHORIZED, description = "Unknown device or invalid token")
+    )
+)]
+async fn route_ledger_apdus(
+    State(ctx): State<Arc<SigningModuleInner>>,
+    Path(id): Path<String>,
+    Query(query): Query<PendingQuery>,
+    headers: HeaderMap,
+) -> Result<Json<LedgerApdus>, AppError> {
+    let token = bearer_token(&headers)?;
+
+    ctx.ledger_apdus(&id, &query.device, token)
+        .await
+        .map(|apdus| Json(LedgerApdus { apdus }))
+        .map_err(AppError::from)
+}
+
+#[utoipa::path(
+    post,
+    path = "/signing/ledger/respond",
+    tag = "Signing",
+    request_body = LedgerResponseBody,
+    responses(
+        (status = OK, description = "Response recorded", body = SigningRequestResponse),
+        (status = UNAUTHORIZED, description = "Unknown device or invalid token")
+    )
+)]
+async fn route_ledger_respond(
+    State(ctx): State<Arc<SigningModuleInner>>,
+    headers: HeaderMap,
+    Json(body): Json<LedgerResponseBody>,
+) -> Result<Json<SigningRequestResponse>, AppError> {
+    let token = bearer_token(&headers)?;
+    let accounts = ctx
+        .authenticate_device(&body.device_id, token)
+        .await
+        .map_err(|e| AppError(StatusCode::UNAUTHORIZED, e))?;
+
+    let response = hex::decode(&body.response)
+        .context("decoding Ledger response")
+        .map_err(|e| AppError(StatusCode::BAD_REQUEST, e))?;
+    let signature = if ledger::is_denied(&response) {
+        None
+    } else {
+        let signature = ledger::parse_signature_response(&response)
+            .map_err(|e| AppError(StatusCode::BAD_REQUEST, e))?;
+        Some(hex::encode(signature))
+    };
+
+    let body = SigningResponseBody {
+        id: body.id,
+        device_id: body.device_id,
+        approved: signature.is_some(),
+        signature,
+    };
+    match ctx.respond(body, &accounts).await {
+        Ok(request) => Ok(Json(request.into())),
+        Err(e) => {
+            tracing::error!("Error responding to signing request from Ledger: {:?}", e);
+            Err(AppError::from(e))
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/signing/history/{account}",
+    tag = "Signing",
+    params(
+        ("account" = String, Path, description = "Account")
+    ),
+    responses(
+        (status = OK, description = "Completed and expired signing requests of the account", body = Vec<SigningHistoryEntry>),
+        (status = UNAUTHORIZED, description = "Bearer token isn't a WebSocket topic granted for the account")
+    )
+)]
+async fn route_history(
+    State(ctx): State<Arc<SigningModuleInner>>,
+    Path(account): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<SigningHistoryEntry>>, AppError> {
+    ctx.authenticate_owner(&account, &headers)?;
+
+    match ctx.history(&account).await {
+        Ok(history) => Ok(Json(history)),
+        Err(e) => {
+            tracing::error!("Error fetching signing history: {:?}", e);
+            Err(AppError::from(e))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sdk::ContractName;
+    use secp256k1::SecretKey;
+    use server::conf::WsAuthConf;
+
+    fn signing_module(ws_auth: Arc<WsAuth>) -> Arc<SigningModuleInner> {
+        let (events, _) = mpsc::unbounded_channel();
+        Arc::new(SigningModuleInner {
+            config: SigningConf {
+                max_pending_per_origin: 8,
+                request_timeout_secs: 60,
+                ..Default::default()
+            },
+            db: DbPools::connect_lazy("postgres://localhost/signing").unwrap(),
+            devices: Mutex::new(HashMap::new()),
+            requests: Mutex::new(HashMap::new()),
+            consumed: Mutex::new(HashMap::new()),
+            events,
+            audit: AuditRecorder::default(),
+            metrics: SigningMetrics::new(),
+            ws_auth,
+        })
+    }
+
+    /// Pairs a secp256k1 device with `account` without storing it, returning its key.
+    async fn pair(ctx: &SigningModuleInner, account: &str, device_id: &str, seed: u8) -> SecretKey {
+        let secret_key = SecretKey::from_byte_array([seed; 32]).unwrap();
+        let public_key = PublicKey::from_secret_key(&Secp256k1::new(), &secret_key);
+        ctx.devices
+            .lock()
+            .await
+            .entry(account.to_string())
+            .or_default()
+            .push(PairedDevice {
+                device_id: device_id.to_string(),
+                public_key: hex::encode(public_key.serialize()),
+                label: None,
+                kind: DeviceKind::Secp256k1,
+                derivation_path: None,
+                token: token_hash(&format!("{device_id}-token")),
+            });
+        secret_key
+    }
+
+    fn request_body(origin: &str, required_approvals: Option<usize>) -> CreateSigningRequestBody {
+        CreateSigningRequestBody {
+            account: "bob".to_string(),
+            message: hex::encode(b"hello"),
+            description: "Say hello".to_string(),
+            origin: origin.to_string(),
+            required_approvals,
+        }
+    }
+
+    fn approval(id: &str, device_id: &str, key: &SecretKey) -> SigningResponseBody {
+        let digest: [u8; 32] = Sha256::digest(b"hello").into();
+        let signature = Secp256k1::new().sign_ecdsa(Message::from_digest(digest), key);
+        SigningResponseBody {
+            id: id.to_string(),
+            device_id: device_id.to_string(),
+            approved: true,
+            signature: Some(hex::encode(signature.serialize_compact())),
+        }
+    }
+
+    fn bearer(token: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, format!("Bearer {token}").parse().unwrap());
+        headers
+    }
+
+    async fn get_request_status(
+        ctx: &Arc<SigningModuleInner>,
+        id: &str,
+        headers: HeaderMap,
+    ) -> StatusCode {
+        match route_get_request(State(ctx.clone()), Path(id.to_string()), headers).await {
+            Ok(_) => StatusCode::OK,
+            Err(e) => e.0,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_request_bound_to_requester_topic() {
+        let ws_auth = Arc::new(WsAuth::new(
+            WsAuthConf {
+                grant_ttl_secs: 60,
+                ..Default::default()
+            },
+            ContractName::new("wallet"),
+            String::new(),
+            AuditRecorder::default(),
+        ));
+        let ctx = signing_module(ws_auth.clone());
+        pair(&ctx, "bob", "phone", 1).await;
+
+        ws_auth.grant("bob".to_string());
+        ws_auth.grant("bob".to_string());
+        let topics = ws_auth.granted_topics("bob");
+        ws_auth.grant("alice".to_string());
+        let alice_topic = ws_auth.granted_topics("alice").remove(0);
+
+        let body = || request_body("https://dapp.example", None);
+        let Err(e) =
+            route_create_request(State(ctx.clone()), bearer(&alice_topic), Json(body())).await
+        else {
+            panic!("created a request for bob with a topic of alice");
+        };
+        assert_eq!(e.0, StatusCode::UNAUTHORIZED);
+        let Ok(Json(created)) =
+            route_create_request(State(ctx.clone()), bearer(&topics[0]), Json(body())).await
+        else {
+            panic!("failed to create a request");
+        };
+        let id = created.request.id;
+
+        assert_eq!(
+            get_request_status(&ctx, &id, bearer(&topics[0])).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            get_request_status(&ctx, &id, HeaderMap::new()).await,
+            StatusCode::UNAUTHORIZED
+        );
+        // Another connection of the same account, a topic of another account and an unknown
+        // id can't be told apart.
+        assert_eq!(
+            get_request_status(&ctx, &id, bearer(&topics[1])).await,
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            get_request_status(&ctx, &id, bearer(&alice_topic)).await,
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            get_request_status(&ctx, "unknown", bearer(&topics[0])).await,
+            StatusCode::NOT_FOUND
+        );
+
+        // The binding ends with the connection's grant.
+        ws_auth.revoke("bob");
+        assert_eq!(
+            get_request_status(&ctx, &id, bearer(&topics[0])).await,
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    fn ws_auth() -> Arc<WsAuth> {
+        Arc::new(WsAuth::new(
+            WsAuthConf::default(),
+            ContractName::new("wallet"),
+            String::new(),
+            AuditRecorder::default(),
+        ))
+    }
+
+    #[tokio::test]
+    async fn test_approvals_complete_at_threshold() {
+        let ctx = signing_module(ws_auth());
+        let phone = pair(&ctx, "bob", "phone", 1).await;
+        let tablet = pair(&ctx, "bob", "tablet", 2).await;
+        let laptop = pair(&ctx, "bob", "laptop", 3).await;
+        let accounts = ["bob".to_string()];
+
+        // The threshold is between one and the number of paired devices.
+        for required in [0, 4] {
+            assert!(ctx
+                .create_request(
+                    request_body("https://dapp.example", Some(required)),
+                    "topic"
+                )
+                .await
+                .is_err());
+        }
+        let id = ctx
+            .create_request(request_body("https://dapp.example", Some(2)), "topic")
+            .await
+            .unwrap()
+            .id;
+
+        let request = ctx
+            .respond(approval(&id, "phone", &phone), &accounts)
+            .await
+            .unwrap();
+        assert_eq!(request.status, SigningStatus::Pending);
+        assert_eq!(request.approvals.len(), 1);
+        // A device counts once, with a signature of its own key, for its own accounts.
+        assert!(ctx
+            .respond(approval(&id, "phone", &phone), &accounts)
+            .await
+            .is_err());
+        assert!(ctx
+            .respond(approval(&id, "tablet", &phone), &accounts)
+            .await
+            .is_err());
+        assert!(ctx
+            .respond(approval(&id, "tablet", &tablet), &["alice".to_string()])
+            .await
+            .is_err());
+
+        let request = ctx
+            .respond(approval(&id, "tablet", &tablet), &accounts)
+            .await
+            .unwrap();
+        assert_eq!(request.status, SigningStatus::Approved);
+        assert_eq!(
+            request
+                .approvals
+                .iter()
+                .map(|a| a.device_id.as_str())
+                .collect::<Vec<_>>(),
+            vec!["phone", "tablet"]
+        );
+        // Completed requests are consumed.
+        assert!(ctx
+            .respond(approval(&id, "laptop", &laptop), &accounts)
+            .await
+            .is_err());
+        assert_eq!(
+            ctx.consumed.lock().await.get(&id).unwrap().outcome,
+            SigningStatus::Approved
+        );
+
+        // A single rejection ends the request.
+        let id = ctx
+            .create_request(request_body("https://dapp.example", Some(2)), "topic")
+            .await
+            .unwrap()
+            .id;
+        let rejection = SigningResponseBody {
+            approved: false,
+            signature: None,
+            ..approval(&id, "laptop", &laptop)
+        };
+        let request = ctx.respond(rejection, &accounts).await.unwrap();
+        assert_eq!(request.status, SigningStatus::Rejected);
+        assert_eq!(request.rejected_by.as_deref(), Some("laptop"));
+        assert!(ctx
+            .respond(approval(&id, "phone", &phone), &accounts)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_pending_quota_per_origin() {
+        let ctx = signing_module(ws_auth());
+        let phone = pair(&ctx, "bob", "phone", 1).await;
+        let max = ctx.config.max_pending_per_origin;
+
+        let mut ids = vec![];
+        for _ in 0..max {
+            ids.push(
+                ctx.create_request(request_body("https://dapp.example", None), "topic")
+                    .await
+                    .unwrap()
+                    .id,
+            );
+        }
+        assert!(ctx
+            .create_request(request_body("https://dapp.example", None), "topic")
+            .await
+            .is_err());
+        // The quota is per origin, and frees up as requests complete.
+        assert!(ctx
+            .create_request(request_body("https://other.example", None), "topic")
+            .await
+            .is_ok());
+        ctx.respond(approval(&ids[0], "phone", &phone), &["bob".to_string()])
+            .await
+            .unwrap();
+        assert!(ctx
+            .create_request(request_body("https://dapp.example", None), "topic")
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_pending_requests_listing() {
+        let ctx = signing_module(ws_auth());
+        let phone = pair(&ctx, "bob", "phone", 1).await;
+        pair(&ctx, "bob", "tablet", 2).await;
+        pair(&ctx, "alice", "laptop", 3).await;
+
+        let older = ctx
+            .create_request(request_body("https://dapp.example", Some(2)), "topic")
+            .await
+            .unwrap()
+            .id;
+        ctx.requests.lock().await.get_mut(&older).unwrap().created -= Duration::from_secs(10);
+        let newer = ctx
+            .create_request(request_body("https://dapp.example", Some(2)), "topic")
+            .await
+            .unwrap()
+            .id;
+
+        assert!(ctx.pending_requests("phone", "wrong-token").await.is_err());
+        assert!(ctx
+            .pending_requests("unknown", "unknown-token")
+            .await
+            .is_err());
+        let pending = ctx.pending_requests("phone", "phone-token").await.unwrap();
+        // Oldest first.
+        assert_eq!(
+            pending.iter().map(|r| &r.id).collect::<Vec<_>>(),
+            vec![&older, &newer]
+        );
+        assert!(ctx
+            .pending_requests("laptop", "laptop-token")
+            .await
+            .unwrap()
+            .is_empty());
+
+        // Requests a device approved are no longer listed for it, still for the others.
+        ctx.respond(approval(&older, "phone", &phone), &["bob".to_string()])
+            .await
+            .unwrap();
+        let pending = ctx.pending_requests("phone", "phone-token").await.unwrap();
+        assert_eq!(
+            pending.iter().map(|r| &r.id).collect::<Vec<_>>(),
+            vec![&newer]
+        );
+        let pending = ctx
+            .pending_requests("tablet", "tablet-token")
+            .await
+            .unwrap();
+        assert_eq!(pending.len(), 2);
+        assert_eq!(pending[0].approvals, 1);
+        assert_eq!(pending[0].required_approvals, 2);
+    }
+
+    #[tokio::test]
+    async fn test_expire_requests() {
+        let ctx = signing_module(ws_auth());
+        let phone = pair(&ctx, "bob", "phone", 1).await;
+        let timeout = Duration::from_secs(ctx.config.request_timeout_secs);
+        let id = ctx
+            .create_request(request_body("https://dapp.example", None), "topic")
+            .await
+            .unwrap()
+            .id;
+
+        ctx.expire_requests().await;
+        assert_eq!(
+            ctx.requests.lock().await.get(&id).unwrap().status,
+            SigningStatus::Pending
+        );
+
+        // Expired once timed out, and kept for another timeout in its final state.
+        ctx.requests.lock().await.get_mut(&id).unwrap().created -= timeout;
+        ctx.expire_requests().await;
+        assert_eq!(
+            ctx.requests.lock().await.get(&id).unwrap().status,
+            SigningStatus::Expired
+        );
+        assert!(ctx
+            .pending_requests("phone", "phone-token")
+            .await
+            .unwrap()
+            .is_empty());
+        assert!(ctx
+            .respond(approval(&id, "phone", &phone), &["bob".to_string()])
+            .await
+            .is_err());
+
+        ctx.requests.lock().await.get_mut(&id).unwrap().created -= timeout;
+        ctx.expire_requests().await;
+        assert!(ctx.requests.lock().await.get(&id).is_none());
+        assert_eq!(
+            ctx.consumed.lock().await.get(&id).unwrap().outcome,
+            SigningStatus::Expired
+        );
+    }
+}
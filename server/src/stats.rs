@@ -0,0 +1,197 @@
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use anyhow::Result;
+use axum::{extract::State, Json};
+use client_sdk::contract_indexer::utoipa;
+use client_sdk::contract_indexer::{
+    utoipa::ToSchema,
+    utoipa_axum::{router::OpenApiRouter, routes},
+};
+use hyli_modules::{
+    bus::SharedMessageBus,
+    module_bus_client, module_handle_messages,
+    modules::{contract_state_indexer::CSIBusEvent, BuildApiContextInner, Module},
+};
+use sdk::ContractName;
+use serde::Serialize;
+use server::provers::ProverControls;
+use wallet::client::indexer::{SharedWalletEvent, TxOutcome};
+
+use crate::app::cors_layer;
+
+/// Counters the modules update in place for `GET /api/stats`, so the operator dashboard reads
+/// them without scraping the metrics exporter.
+#[derive(Default)]
+pub struct StatsCounters {
+    /// Invite codes not used yet, as of the invite module's last count.
+    pub invites_available: AtomicU64,
+    pub invites_consumed: AtomicU64,
+    /// Consumptions refused: unknown or used codes, database errors or a rotating key.
+    pub invites_rejected: AtomicU64,
+    /// Clients connected to the WebSocket gateway.
+    pub ws_gateway_connections: AtomicU64,
+    settled: AtomicU64,
+    failed: AtomicU64,
+    timed_out: AtomicU64,
+}
+
+/// Serves `GET /api/stats` and counts the wallet transactions settled since the server started.
+pub struct StatsModule {
+    bus: StatsModuleBusClient,
+    counters: Arc<StatsCounters>,
+}
+
+pub struct StatsModuleCtx {
+    pub api_ctx: Arc<BuildApiContextInner>,
+    pub counters: Arc<StatsCounters>,
+    /// Wallet contracts whose accounts are counted.
+    pub wallet_cns: Vec<ContractName>,
+    pub prover_controls: Arc<ProverControls>,
+}
+
+module_bus_client! {
+#[derive(Debug)]
+pub struct StatsModuleBusClient {
+    receiver(CSIBusEvent<SharedWalletEvent>),
+}
+}
+
+struct StatsRouterCtx {
+    counters: Arc<StatsCounters>,
+    wallet_cns: Vec<ContractName>,
+    prover_controls: Arc<ProverControls>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SystemStats {
+    /// Accounts registered per wallet contract, as indexed.
+    pub accounts: BTreeMap<String, usize>,
+    /// Wallet transactions settled since the server started, by outcome.
+    pub transactions: TxVolume,
+    /// Proofs queued or running, per contract.
+    pub proving_backlog: BTreeMap<String, usize>,
+    pub invites: InviteUsage,
+    /// Clients connected to the WebSocket gateway.
+    pub ws_connections: u64,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct TxVolume {
+    pub settled: u64,
+    pub failed: u64,
+    pub timed_out: u64,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct InviteUsage {
+    /// Codes not used yet, counted every minute.
+    pub available: u64,
+    /// Codes consumed since the server started.
+    pub consumed: u64,
+    /// Consumptions refused since the server started.
+    pub rejected: u64,
+}
+
+impl StatsCounters {
+    fn on_settled(&self, outcome: TxOutcome) {
+        let counter = match outcome {
+            TxOutcome::Success => &self.settled,
+            TxOutcome::Failure => &self.failed,
+            TxOutcome::Timeout => &self.timed_out,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl StatsRouterCtx {
+    async fn stats(&self) -> SystemStats {
+        let mut accounts = BTreeMap::new();
+        for contract_name in &self.wallet_cns {
+            let Some(store) = wallet::client::indexer::store(contract_name) else {
+                continue;
+            };
+            let store = store.read().await;
+            if let Some(wallet) = store.state.as_ref() {
+                accounts.insert(contract_name.0.clone(), wallet.iter_accounts().count());
+            }
+        }
+        let counters = &self.counters;
+        SystemStats {
+            accounts,
+            transactions: TxVolume {
+                settled: counters.settled.load(Ordering::Relaxed),
+                failed: counters.failed.load(Ordering::Relaxed),
+                timed_out: counters.timed_out.load(Ordering::Relaxed),
+            },
+            proving_backlog: self.prover_controls.backlog(),
+            invites: InviteUsage {
+                available: counters.invites_available.load(Ordering::Relaxed),
+                consumed: counters.invites_consumed.load(Ordering::Relaxed),
+                rejected: counters.invites_rejected.load(Ordering::Relaxed),
+            },
+            ws_connections: counters.ws_gateway_connections.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl Module for StatsModule {
+    type Context = StatsModuleCtx;
+
+    async fn build(bus: SharedMessageBus, ctx: Self::Context) -> Result<Self> {
+        let state = Arc::new(StatsRouterCtx {
+            counters: ctx.counters.clone(),
+            wallet_cns: ctx.wallet_cns,
+            prover_controls: ctx.prover_controls,
+        });
+        let (router, openapi) = OpenApiRouter::default()
+            .routes(routes!(route_get_stats))
+            .split_for_parts();
+        let api = router.with_state(state).layer(cors_layer());
+
+        if let Ok(mut guard) = ctx.api_ctx.router.lock() {
+            if let Some(router) = guard.take() {
+                guard.replace(router.merge(api));
+            }
+        }
+        if let Ok(mut guard) = ctx.api_ctx.openapi.lock() {
+            guard.merge(openapi);
+        }
+
+        Ok(Self {
+            bus: StatsModuleBusClient::new_from_bus(bus.new_handle()).await,
+            counters: ctx.counters,
+        })
+    }
+
+    async fn run(&mut self) -> Result<()> {
+        module_handle_messages! {
+            on_self self,
+            listen<CSIBusEvent<SharedWalletEvent>> event => {
+                self.counters.on_settled(event.event.outcome);
+            }
+        };
+        Ok(())
+    }
+}
+
+// --------------------------------------------------------
+//     Routes
+// --------------------------------------------------------
+
+#[utoipa::path(
+    get,
+    path = "/api/stats",
+    tag = "System",
+    responses(
+        (status = OK, description = "Aggregated counters for the operator dashboard", body = SystemStats)
+    )
+)]
+async fn route_get_stats(State(ctx): State<Arc<StatsRouterCtx>>) -> Json<SystemStats> {
+    Json(ctx.stats().await)
+}
@@ -0,0 +1,49 @@
+use anyhow::{Context, Result};
+use hyli_modules::utils::logger::setup_tracing;
+use opentelemetry::trace::TracerProvider;
+use opentelemetry_otlp::{WithExportConfig, WithHttpConfig};
+use opentelemetry_sdk::{
+    trace::{Sampler, SdkTracerProvider},
+    Resource,
+};
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
+
+use crate::conf::Conf;
+
+/// Sets up logging, and when `otlp.enabled` is set, exports traces to an OTLP collector.
+pub fn setup_telemetry(conf: &Conf, name: String) -> Result<()> {
+    if !conf.otlp.enabled {
+        return setup_tracing(&conf.log_format, name);
+    }
+
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_http()
+        .with_endpoint(&conf.otlp.endpoint)
+        .with_headers(conf.otlp.headers.clone())
+        .build()
+        .context("building OTLP exporter")?;
+
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
+            conf.otlp.sampling_ratio,
+        ))))
+        .with_resource(Resource::builder().with_service_name(name).build())
+        .build();
+    let tracer = provider.tracer("wallet");
+    opentelemetry::global::set_tracer_provider(provider);
+
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let fmt = tracing_subscriber::fmt::layer();
+    let registry = tracing_subscriber::registry()
+        .with(filter)
+        .with(tracing_opentelemetry::layer().with_tracer(tracer));
+    if conf.log_format == "json" {
+        registry.with(fmt.json()).try_init()
+    } else {
+        registry.with(fmt).try_init()
+    }
+    .context("installing tracing subscriber")?;
+
+    Ok(())
+}
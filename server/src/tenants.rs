@@ -0,0 +1,186 @@
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use axum::Router;
+use client_sdk::rest_client::NodeApiClient;
+use client_sdk::transaction_builder::TxExecutorHandler;
+use hyli_modules::modules::contract_listener::{ContractListener, ContractListenerConf};
+use hyli_modules::modules::contract_state_indexer::{
+    ContractStateIndexer, ContractStateIndexerCtx,
+};
+use hyli_modules::modules::{BuildApiContextInner, ModulesHandler};
+use sdk::ContractName;
+use server::audit::AuditRecorder;
+use server::autoprovers::{self, AutoProversConfig};
+use server::conf::{Conf, TenantConf};
+use server::new_wallet;
+use server::provers::ProverControls;
+use server::secrets::Secrets;
+use tracing::info;
+use wallet::client::indexer::SharedWalletEvent;
+use wallet::client::tx_executor_handler::Wallet;
+
+use crate::app::{cors_layer, deployment_router};
+use crate::init::{init_node, ContractInit};
+use crate::invites::invite::{InviteKey, InviteModule, InviteModuleCtx, MockInviteModule};
+use crate::signing::signing::{SigningModule, SigningModuleCtx};
+use crate::stats::StatsCounters;
+use crate::ws_auth::WsAuth;
+use crate::ws_replay::EventReplay;
+
+/// What the tenants share with the main wallet.
+pub(crate) struct TenantsCtx {
+    pub conf: Arc<Conf>,
+    /// Wallet contracts of the main deployment, which tenants can't reuse.
+    pub wallet_cns: Vec<ContractName>,
+    pub noinit: bool,
+    /// Base URL of this server's REST API.
+    pub indexer_url: String,
+    pub wallet_auto_prove: bool,
+    pub secrets: Secrets,
+    pub invite_key: Arc<InviteKey>,
+    pub audit: AuditRecorder,
+    pub ws_auth: Arc<WsAuth>,
+    pub replay: Arc<EventReplay>,
+    pub prover_controls: Arc<ProverControls>,
+    pub stats: Arc<StatsCounters>,
+}
+
+/// Hosts the wallet deployments listed in `tenants` next to the main one.
+///
+/// Each tenant gets its own contract listener, indexer and autoprover with their state under
+/// `<data_directory>/tenants/{name}`, and its own invite and signing modules on its database.
+/// All its routes, `/api/config` included, are served under `/{name}`, so a frontend only needs
+/// `/{name}` appended to the server URL. The invite code key, the audit log and the WebSocket
+/// server are shared with the main wallet.
+pub(crate) async fn setup_tenants(
+    ctx: &TenantsCtx,
+    handler: &mut ModulesHandler,
+    api_ctx: Arc<BuildApiContextInner>,
+    node_client: Arc<dyn NodeApiClient + Send + Sync>,
+) -> Result<()> {
+    for tenant in &ctx.conf.tenants {
+        let router = setup_tenant(ctx, tenant, handler, node_client.clone())
+            .await
+            .with_context(|| format!("setting up tenant {}", tenant.name))?;
+        if let Ok(mut guard) = api_ctx.router.lock() {
+            if let Some(main) = guard.take() {
+                guard.replace(main.nest(&format!("/{}", tenant.name), router));
+            }
+        }
+        info!(
+            "Serving tenant {} with wallet {} under /{}",
+            tenant.name, tenant.wallet_cn, tenant.name
+        );
+    }
+    Ok(())
+}
+
+async fn setup_tenant(
+    ctx: &TenantsCtx,
+    tenant: &TenantConf,
+    handler: &mut ModulesHandler,
+    node_client: Arc<dyn NodeApiClient + Send + Sync>,
+) -> Result<Router> {
+    let wallet_cn = ContractName(tenant.wallet_cn.clone());
+    if ctx.wallet_cns.contains(&wallet_cn) {
+        bail!("wallet {wallet_cn} is already served by the main deployment");
+    }
+    let data_directory = ctx.conf.data_directory.join("tenants").join(&tenant.name);
+    std::fs::create_dir_all(&data_directory).context("creating tenant data directory")?;
+    // Tenant modules register their routes here, nested under the prefix once all are built.
+    let api = Arc::new(BuildApiContextInner {
+        router: Mutex::new(Some(Router::new())),
+        openapi: Default::default(),
+    });
+
+    if !ctx.noinit {
+        let (wallet_constructor, wallet) = new_wallet(&wallet_cn, &ctx.secrets);
+        init_node(
+            node_client.clone(),
+            vec![ContractInit {
+                name: wallet_cn.clone(),
+                program_id: contracts::WALLET_ID,
+                initial_state: wallet.get_state_commitment(),
+                constructor_metadata: borsh::to_vec(&wallet_constructor)?,
+            }],
+        )
+        .await?;
+    }
+
+    handler
+        .build_module::<ContractListener>(ContractListenerConf {
+            database_url: ctx.conf.indexer_database_url.clone(),
+            data_directory: data_directory.clone(),
+            contracts: HashSet::from([wallet_cn.clone()]),
+            poll_interval: Duration::from_secs(ctx.conf.auto_prover_listener_poll_interval_secs),
+            replay_settled_from_start: ctx.conf.listener_replay_settled_from_start,
+        })
+        .await?;
+    handler
+        .build_module::<ContractStateIndexer<Wallet, SharedWalletEvent>>(ContractStateIndexerCtx {
+            contract_name: wallet_cn.clone(),
+            data_directory: data_directory.clone(),
+            api: api.clone(),
+        })
+        .await?;
+
+    if ctx.wallet_auto_prove {
+        autoprovers::setup_autoprovers_modules(
+            &AutoProversConfig {
+                wallet_cns: vec![wallet_cn.clone()],
+                wallet_auto_prove: true,
+                smt_auto_prove: false,
+                smt_contracts: vec![],
+                data_directory: data_directory.clone(),
+                ..AutoProversConfig::new(&ctx.conf, vec![])
+            },
+            handler,
+            api.clone(),
+            node_client.clone(),
+            &ctx.prover_controls,
+        )
+        .await?;
+    }
+
+    let invites = InviteModuleCtx {
+        db_url: tenant.db_url.clone(),
+        database: ctx.conf.database.clone(),
+        api_ctx: api.clone(),
+        invite_key: ctx.invite_key.clone(),
+        audit: ctx.audit.clone(),
+        stats: ctx.stats.clone(),
+    };
+    if tenant.mock_invites {
+        handler.build_module::<MockInviteModule>(invites).await?;
+    } else {
+        handler.build_module::<InviteModule>(invites).await?;
+    }
+
+    if ctx.conf.signing.enabled {
+        handler
+            .build_module::<SigningModule>(SigningModuleCtx {
+                api_ctx: api.clone(),
+                config: ctx.conf.signing.clone(),
+                db_url: tenant.db_url.clone(),
+                database: ctx.conf.database.clone(),
+                audit: ctx.audit.clone(),
+                ws_auth: ctx.ws_auth.clone(),
+                replay: ctx.replay.clone(),
+            })
+            .await?;
+    }
+
+    let deployment = deployment_router(wallet_cn, format!("{}/{}", ctx.indexer_url, tenant.name))
+        .layer(cors_layer());
+    #[allow(clippy::expect_used, reason = "Fail on misconfiguration")]
+    let router = api
+        .router
+        .lock()
+        .expect("Tenant router should be available.")
+        .take()
+        .expect("Tenant router should be available.");
+    Ok(router.merge(deployment))
+}
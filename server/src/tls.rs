@@ -0,0 +1,164 @@
+use std::{
+    path::Path,
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{Context, Result};
+use hyli_modules::{
+    bus::SharedMessageBus, module_bus_client, module_handle_messages, modules::Module,
+};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    task::JoinSet,
+};
+use tokio_rustls::{
+    rustls::{
+        crypto::ring,
+        pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+        ServerConfig,
+    },
+    TlsAcceptor,
+};
+
+use crate::conf::TlsConf;
+
+/// Terminates TLS in front of the plain REST and WebSocket servers.
+///
+/// Each TLS port forwards decrypted traffic to a local plain port, so it works for HTTP and
+/// WebSocket upgrades alike. Certificates are reloaded when their files change on disk.
+pub struct TlsProxy {
+    bus: TlsProxyBusClient,
+    conf: TlsConf,
+    acceptor: Arc<RwLock<TlsAcceptor>>,
+    loaded_at: SystemTime,
+    listeners: Vec<(TcpListener, u16)>,
+}
+
+pub struct TlsProxyCtx {
+    pub conf: TlsConf,
+    /// Pairs of (TLS port, local plain port to forward to).
+    pub routes: Vec<(u16, u16)>,
+}
+
+module_bus_client! {
+#[derive(Debug)]
+pub struct TlsProxyBusClient {
+}
+}
+
+fn load_acceptor(conf: &TlsConf) -> Result<TlsAcceptor> {
+    let certs = CertificateDer::pem_file_iter(&conf.cert_path)
+        .with_context(|| format!("reading certificates from {}", conf.cert_path.display()))?
+        .collect::<Result<Vec<_>, _>>()
+        .context("parsing certificates")?;
+    let key = PrivateKeyDer::from_pem_file(&conf.key_path)
+        .with_context(|| format!("reading private key from {}", conf.key_path.display()))?;
+
+    let config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .context("building TLS server config")?;
+
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+fn modified_at(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+async fn forward(acceptor: TlsAcceptor, stream: TcpStream, upstream_port: u16) -> Result<()> {
+    let mut tls = acceptor.accept(stream).await.context("TLS handshake")?;
+    let mut upstream = TcpStream::connect(("127.0.0.1", upstream_port))
+        .await
+        .context("connecting to upstream")?;
+    tokio::io::copy_bidirectional(&mut tls, &mut upstream).await?;
+    Ok(())
+}
+
+async fn serve(listener: TcpListener, upstream_port: u16, acceptor: Arc<RwLock<TlsAcceptor>>) {
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                tracing::warn!("Failed to accept TLS connection: {:#}", e);
+                continue;
+            }
+        };
+        let acceptor = acceptor.read().expect("TLS acceptor poisoned").clone();
+        tokio::spawn(async move {
+            if let Err(e) = forward(acceptor, stream, upstream_port).await {
+                tracing::debug!("TLS connection from {} closed: {:#}", peer, e);
+            }
+        });
+    }
+}
+
+impl TlsProxy {
+    fn reload_if_changed(&mut self) {
+        let changed = [&self.conf.cert_path, &self.conf.key_path]
+            .iter()
+            .filter_map(|path| modified_at(path))
+            .any(|modified| modified > self.loaded_at);
+        if !changed {
+            return;
+        }
+        match load_acceptor(&self.conf) {
+            Ok(acceptor) => {
+                *self.acceptor.write().expect("TLS acceptor poisoned") = acceptor;
+                self.loaded_at = SystemTime::now();
+                tracing::info!("🔐 Reloaded TLS certificate");
+            }
+            Err(e) => tracing::error!("Failed to reload TLS certificate: {:#}", e),
+        }
+    }
+}
+
+impl Module for TlsProxy {
+    type Context = TlsProxyCtx;
+
+    async fn build(bus: SharedMessageBus, ctx: Self::Context) -> Result<Self> {
+        let acceptor = load_acceptor(&ctx.conf)?;
+        let mut listeners = Vec::new();
+        for (tls_port, upstream_port) in ctx.routes {
+            let listener = TcpListener::bind(("0.0.0.0", tls_port))
+                .await
+                .with_context(|| format!("binding TLS port {tls_port}"))?;
+            tracing::info!(
+                "🔐 TLS on port {} forwarding to {}",
+                tls_port,
+                upstream_port
+            );
+            listeners.push((listener, upstream_port));
+        }
+
+        Ok(TlsProxy {
+            bus: TlsProxyBusClient::new_from_bus(bus.new_handle()).await,
+            conf: ctx.conf,
+            acceptor: Arc::new(RwLock::new(acceptor)),
+            loaded_at: SystemTime::now(),
+            listeners,
+        })
+    }
+
+    async fn run(&mut self) -> Result<()> {
+        let mut servers = JoinSet::new();
+        for (listener, upstream_port) in std::mem::take(&mut self.listeners) {
+            servers.spawn(serve(listener, upstream_port, self.acceptor.clone()));
+        }
+
+        let mut reload =
+            tokio::time::interval(Duration::from_secs(self.conf.reload_interval_secs.max(1)));
+
+        module_handle_messages! {
+            on_self self,
+            _ = reload.tick() => {
+                self.reload_if_changed();
+            }
+        };
+
+        servers.abort_all();
+        Ok(())
+    }
+}
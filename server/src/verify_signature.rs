@@ -0,0 +1,236 @@
+use std::{
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result};
+use axum::{extract::State, http::StatusCode, routing::post, Json, Router};
+use client_sdk::AppError;
+use sdk::ContractName;
+use secp256k1::{ecdsa::Signature, Message, PublicKey, Secp256k1};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use wallet::{
+    utils::{
+        decode_hex, ethereum_address_from_public_key, ethereum_personal_sign_digest,
+        parse_public_key,
+    },
+    AuthMethod,
+};
+
+/// Checks signatures of wallet users off-chain, in the spirit of EIP-1271, so external services
+/// can authenticate them without sending a transaction.
+///
+/// A signature is valid when it's made by the account's own key (HyliApp or Ethereum accounts)
+/// or by one of its live session keys allowed to act on the requested contract.
+pub struct SignatureVerifier {
+    wallet_cn: ContractName,
+    /// Base URL of this server's REST API, serving the indexer routes.
+    indexer_url: String,
+    http: reqwest::Client,
+}
+
+#[derive(Deserialize)]
+struct IndexedSessionKey {
+    key: String,
+    expiration_date: u128,
+    whitelist: Option<Vec<ContractName>>,
+}
+
+#[derive(Deserialize)]
+struct IndexedAccount {
+    auth_method: AuthMethod,
+    session_keys: Vec<IndexedSessionKey>,
+    /// Frozen accounts sign with none of their keys.
+    #[serde(default)]
+    frozen: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct VerifySignatureRequest {
+    pub account: String,
+    pub message: String,
+    /// Hex-encoded compact signature. Ethereum signatures may keep their recovery byte.
+    pub signature: String,
+    /// Hex-encoded compressed public key that made the signature.
+    pub public_key: String,
+    /// Contract the signature is meant for, checked against session key whitelists. Session
+    /// keys restricted to some contracts are only accepted when it's one of them.
+    pub contract: Option<String>,
+}
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum SignerKind {
+    /// The account's own key, of its HyliApp or Ethereum auth method.
+    AuthMethod,
+    SessionKey,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct VerifySignatureResponse {
+    pub valid: bool,
+    signer: Option<SignerKind>,
+    /// Why the signature was rejected.
+    pub reason: Option<String>,
+}
+
+impl VerifySignatureResponse {
+    fn invalid(reason: impl Into<String>) -> Self {
+        Self {
+            valid: false,
+            signer: None,
+            reason: Some(reason.into()),
+        }
+    }
+}
+
+fn now_ms() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or_default()
+}
+
+fn verify_ecdsa(digest: [u8; 32], signature: &[u8], public_key: &PublicKey) -> bool {
+    let Ok(signature) = Signature::from_compact(signature) else {
+        return false;
+    };
+    Secp256k1::verification_only()
+        .verify_ecdsa(Message::from_digest(digest), &signature, public_key)
+        .is_ok()
+}
+
+impl SignatureVerifier {
+    pub fn new(wallet_cn: ContractName, indexer_url: String) -> Self {
+        Self {
+            wallet_cn,
+            indexer_url,
+            http: reqwest::Client::new(),
+        }
+    }
+
+    pub(crate) async fn verify(
+        &self,
+        request: VerifySignatureRequest,
+    ) -> Result<VerifySignatureResponse> {
+        let public_key_hex = request.public_key.trim_start_matches("0x").to_lowercase();
+        // Ed25519 session keys sign through the contract, not through this endpoint.
+        let Ok(public_key) =
+            PublicKey::from_slice(&decode_hex(&public_key_hex).map_err(anyhow::Error::msg)?)
+        else {
+            return Ok(VerifySignatureResponse::invalid(
+                "Not a secp256k1 public key",
+            ));
+        };
+        let signature = decode_hex(&request.signature).map_err(anyhow::Error::msg)?;
+        // Drop the recovery byte of 65-byte Ethereum signatures.
+        let compact = &signature[..signature.len().min(64)];
+
+        let account: IndexedAccount = self
+            .http
+            .get(format!(
+                "{}/v1/indexer/contract/{}/account/{}",
+                self.indexer_url, self.wallet_cn.0, request.account
+            ))
+            .send()
+            .await?
+            .error_for_status()
+            .context("fetching account")?
+            .json()
+            .await?;
+        if account.frozen {
+            return Ok(VerifySignatureResponse::invalid("Account is frozen"));
+        }
+
+        let digest: [u8; 32] = Sha256::digest(request.message.as_bytes()).into();
+        match &account.auth_method {
+            AuthMethod::HyliApp { address } => {
+                let derived = hex::encode(&public_key.serialize()[..20]);
+                if derived == address.trim_start_matches("0x").to_lowercase()
+                    && verify_ecdsa(digest, compact, &public_key)
+                {
+                    return Ok(VerifySignatureResponse {
+                        valid: true,
+                        signer: Some(SignerKind::AuthMethod),
+                        reason: None,
+                    });
+                }
+            }
+            AuthMethod::Ethereum { address } => {
+                // The wallet crate depends on another secp256k1 release.
+                let wallet_key =
+                    parse_public_key(&public_key.serialize()).map_err(anyhow::Error::msg)?;
+                let derived = ethereum_address_from_public_key(&wallet_key);
+                if derived == address.trim_start_matches("0x").to_lowercase()
+                    && verify_ecdsa(
+                        ethereum_personal_sign_digest(&request.message),
+                        compact,
+                        &public_key,
+                    )
+                {
+                    return Ok(VerifySignatureResponse {
+                        valid: true,
+                        signer: Some(SignerKind::AuthMethod),
+                        reason: None,
+                    });
+                }
+            }
+            // Password and JWT accounts can only sign through their session keys.
+            _ => {}
+        }
+
+        let Some(session_key) = account
+            .session_keys
+            .iter()
+            .find(|sk| sk.key.to_lowercase() == public_key_hex)
+        else {
+            return Ok(VerifySignatureResponse::invalid(
+                "Not a key of the account, or invalid signature",
+            ));
+        };
+        if session_key.expiration_date <= now_ms() {
+            return Ok(VerifySignatureResponse::invalid("Session key expired"));
+        }
+        if let Some(whitelist) = &session_key.whitelist {
+            match &request.contract {
+                Some(contract) if whitelist.iter().any(|c| &c.0 == contract) => {}
+                Some(contract) => {
+                    return Ok(VerifySignatureResponse::invalid(format!(
+                        "Session key not allowed for {contract}"
+                    )))
+                }
+                None => {
+                    return Ok(VerifySignatureResponse::invalid(
+                        "Session key is restricted to some contracts, which must be given",
+                    ))
+                }
+            }
+        }
+        if !verify_ecdsa(digest, compact, &public_key) {
+            return Ok(VerifySignatureResponse::invalid("Invalid signature"));
+        }
+        Ok(VerifySignatureResponse {
+            valid: true,
+            signer: Some(SignerKind::SessionKey),
+            reason: None,
+        })
+    }
+}
+
+pub fn verify_signature_router(verifier: Arc<SignatureVerifier>) -> Router {
+    Router::new()
+        .route("/api/verify_signature", post(route_verify_signature))
+        .with_state(verifier)
+}
+
+async fn route_verify_signature(
+    State(verifier): State<Arc<SignatureVerifier>>,
+    Json(request): Json<VerifySignatureRequest>,
+) -> Result<Json<VerifySignatureResponse>, AppError> {
+    verifier
+        .verify(request)
+        .await
+        .map(Json)
+        .map_err(|e| AppError(StatusCode::BAD_REQUEST, e))
+}
@@ -0,0 +1,739 @@
+use std::{
+    collections::HashMap,
+    io::Cursor,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use axum::{
+    extract::{Path, State},
+    http::{header::AUTHORIZATION, HeaderMap, StatusCode},
+    Json,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::NaiveDateTime;
+use ciborium::Value;
+use client_sdk::contract_indexer::utoipa;
+use client_sdk::contract_indexer::{
+    utoipa::ToSchema,
+    utoipa_axum::{router::OpenApiRouter, routes},
+};
+use client_sdk::AppError;
+use hyli_modules::{
+    bus::SharedMessageBus,
+    module_bus_client, module_handle_messages,
+    modules::{BuildApiContextInner, Module},
+};
+use p256::{
+    ecdsa::{signature::Verifier, Signature, VerifyingKey},
+    elliptic_curve::sec1::ToEncodedPoint,
+};
+use serde::{Deserialize, Serialize};
+use server::conf::{DatabaseConf, WebAuthnConf};
+use server::db::DbPools;
+use sha2::{Digest, Sha256};
+use sqlx::FromRow;
+use tokio::sync::Mutex;
+
+use crate::ws_auth::WsAuth;
+
+/// Runs the WebAuthn registration and authentication ceremonies of passkeys.
+///
+/// Registration checks the authenticator's attestation and stores the credential's P-256 key,
+/// which the account then registers as its auth method. Authentication checks an assertion
+/// against the stored key and hands back the pieces the wallet contract verifies again on-chain:
+/// authenticator data, client data and signature. Assertions for a transaction sign
+/// [`wallet::webauthn_challenge`] of the account's nonce.
+///
+/// Attestation statements aren't checked (`attestation: "none"` is requested), the wallet
+/// trusts the key the browser reports, as for any other self-custodied key. Passkeys of
+/// registered accounts can only be added by their owner, proven through WebSocket auth.
+pub struct WebAuthnModule {
+    #[allow(unused)]
+    bus: WebAuthnModuleBusClient,
+    inner: Arc<WebAuthnModuleInner>,
+}
+
+pub struct WebAuthnModuleCtx {
+    pub api_ctx: Arc<BuildApiContextInner>,
+    pub conf: WebAuthnConf,
+    pub db_url: String,
+    pub database: DatabaseConf,
+    pub ws_auth: Arc<WsAuth>,
+}
+
+module_bus_client! {
+#[derive(Debug)]
+pub struct WebAuthnModuleBusClient {
+}
+}
+
+struct WebAuthnModuleInner {
+    conf: WebAuthnConf,
+    db: DbPools,
+    /// Outstanding challenges, base64url-encoded, with the ceremony they were issued for.
+    challenges: Mutex<HashMap<String, PendingChallenge>>,
+    ws_auth: Arc<WsAuth>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Ceremony {
+    Registration,
+    Authentication,
+}
+
+impl Ceremony {
+    /// `type` of the client data of the ceremony.
+    fn client_data_type(&self) -> &'static str {
+        match self {
+            Ceremony::Registration => "webauthn.create",
+            Ceremony::Authentication => "webauthn.get",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct PendingChallenge {
+    account: String,
+    ceremony: Ceremony,
+    issued: Instant,
+}
+
+/// COSE algorithm id of ECDSA with P-256 and SHA-256, the only one the contract verifies.
+const COSE_ALG_ES256: i64 = -7;
+
+/// Authenticator data flags.
+const FLAG_USER_PRESENT: u8 = 0x01;
+const FLAG_ATTESTED_CREDENTIAL: u8 = 0x40;
+
+#[derive(Debug, Deserialize)]
+struct ClientData {
+    #[serde(rename = "type")]
+    kind: String,
+    challenge: String,
+    origin: String,
+}
+
+#[derive(Debug)]
+struct AuthenticatorData {
+    rp_id_hash: [u8; 32],
+    flags: u8,
+    sign_count: u32,
+    /// Credential id and COSE public key, present in registrations.
+    attested: Option<(Vec<u8>, Value)>,
+}
+
+#[derive(Debug, FromRow)]
+struct CredentialRow {
+    credential_id: String,
+    account: String,
+    public_key: String,
+    sign_count: i64,
+    created_at: NaiveDateTime,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct WebAuthnCredential {
+    /// Base64url-encoded credential id.
+    pub credential_id: String,
+    pub account: String,
+    /// Hex-encoded compressed P-256 public key, to register as the account's auth method.
+    pub public_key: String,
+    /// Relying party id the passkey is scoped to, to register with the public key.
+    pub rp_id: String,
+    pub sign_count: i64,
+    #[schema(value_type = String)]
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct RegistrationOptionsBody {
+    pub account: String,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct AuthenticationOptionsBody {
+    pub account: String,
+    /// Nonce of the wallet transaction the assertion will authenticate. Without it, the
+    /// challenge is random and the assertion only proves possession of the passkey.
+    pub nonce: Option<u128>,
+}
+
+/// Options for `navigator.credentials.create`, binary fields base64url-encoded.
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RegistrationOptions {
+    pub challenge: String,
+    #[schema(value_type = Object)]
+    pub rp: serde_json::Value,
+    #[schema(value_type = Object)]
+    pub user: serde_json::Value,
+    #[schema(value_type = Vec<Object>)]
+    pub pub_key_cred_params: Vec<serde_json::Value>,
+    #[schema(value_type = Vec<Object>)]
+    pub exclude_credentials: Vec<serde_json::Value>,
+    pub timeout: u64,
+    pub attestation: String,
+}
+
+/// Options for `navigator.credentials.get`, binary fields base64url-encoded.
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthenticationOptions {
+    pub challenge: String,
+    pub rp_id: String,
+    #[schema(value_type = Vec<Object>)]
+    pub allow_credentials: Vec<serde_json::Value>,
+    pub timeout: u64,
+    pub user_verification: String,
+}
+
+/// Response of the authenticator to a registration, binary fields base64url-encoded.
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct RegistrationResponseBody {
+    pub client_data_json: String,
+    pub attestation_object: String,
+}
+
+/// Response of the authenticator to an authentication, binary fields base64url-encoded.
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct AuthenticationResponseBody {
+    pub credential_id: String,
+    pub client_data_json: String,
+    pub authenticator_data: String,
+    /// DER-encoded signature.
+    pub signature: String,
+}
+
+/// A verified assertion, in the form the wallet contract checks it.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct WebAuthnAssertion {
+    pub account: String,
+    pub credential_id: String,
+    /// Hex-encoded compressed P-256 public key.
+    pub public_key: String,
+    /// Hex-encoded authenticator data.
+    pub authenticator_data: String,
+    /// Hex-encoded client data JSON, as the authenticator signed it.
+    pub client_data_json: String,
+    /// Hex-encoded compact (r‖s) signature, with a low s.
+    pub signature: String,
+}
+
+fn decode_b64url(field: &str, value: &str) -> Result<Vec<u8>> {
+    URL_SAFE_NO_PAD
+        .decode(value.trim_end_matches('='))
+        .with_context(|| format!("{field} is not base64url"))
+}
+
+fn parse_authenticator_data(data: &[u8]) -> Result<AuthenticatorData> {
+    ensure!(data.len() >= 37, "authenticator data too short");
+    let rp_id_hash: [u8; 32] = data[..32].try_into()?;
+    let flags = data[32];
+    let sign_count = u32::from_be_bytes(data[33..37].try_into()?);
+
+    let attested = if flags & FLAG_ATTESTED_CREDENTIAL != 0 {
+        // AAGUID, then the length-prefixed credential id, then the COSE key.
+        let rest = data
+            .get(37 + 16..)
+            .context("truncated attested credential")?;
+        ensure!(rest.len() >= 2, "truncated attested credential");
+        let id_len = u16::from_be_bytes([rest[0], rest[1]]) as usize;
+        let credential_id = rest
+            .get(2..2 + id_len)
+            .context("truncated credential id")?
+            .to_vec();
+        // Extensions may follow the key, only the first CBOR item is read.
+        let key: Value = ciborium::de::from_reader(Cursor::new(&rest[2 + id_len..]))
+            .map_err(|e| anyhow!("invalid COSE key: {e}"))?;
+        Some((credential_id, key))
+    } else {
+        None
+    };
+
+    Ok(AuthenticatorData {
+        rp_id_hash,
+        flags,
+        sign_count,
+        attested,
+    })
+}
+
+/// Reads an ES256 COSE key.
+fn cose_public_key(key: &Value) -> Result<VerifyingKey> {
+    let entries = key.as_map().context("COSE key is not a map")?;
+    let get = |label: i64| {
+        entries.iter().find_map(|(k, v)| {
+            k.as_integer()
+                .filter(|k| i128::from(*k) == label as i128)
+                .map(|_| v)
+        })
+    };
+    let integer = |label: i64| {
+        get(label)
+            .and_then(Value::as_integer)
+            .map(i128::from)
+            .with_context(|| format!("COSE key label {label} missing"))
+    };
+    let bytes = |label: i64| {
+        get(label)
+            .and_then(Value::as_bytes)
+            .with_context(|| format!("COSE key label {label} missing"))
+    };
+
+    // kty EC2, alg ES256, crv P-256.
+    ensure!(integer(1)? == 2, "COSE key is not an EC2 key");
+    ensure!(
+        integer(3)? == COSE_ALG_ES256 as i128,
+        "only ES256 passkeys are supported"
+    );
+    ensure!(integer(-1)? == 1, "COSE key is not on P-256");
+    let (x, y) = (bytes(-2)?, bytes(-3)?);
+    ensure!(
+        x.len() == 32 && y.len() == 32,
+        "invalid COSE key coordinates"
+    );
+
+    let mut sec1 = vec![0x04];
+    sec1.extend_from_slice(x);
+    sec1.extend_from_slice(y);
+    VerifyingKey::from_sec1_bytes(&sec1).map_err(|e| anyhow!("invalid P-256 key: {e}"))
+}
+
+impl WebAuthnModuleInner {
+    fn rp_id_hash(&self) -> [u8; 32] {
+        Sha256::digest(self.conf.rp_id.as_bytes()).into()
+    }
+
+    fn credential(&self, row: CredentialRow) -> WebAuthnCredential {
+        WebAuthnCredential {
+            credential_id: row.credential_id,
+            account: row.account,
+            public_key: row.public_key,
+            rp_id: self.conf.rp_id.clone(),
+            sign_count: row.sign_count,
+            created_at: row.created_at,
+        }
+    }
+
+    async fn issue_challenge(
+        &self,
+        account: &str,
+        ceremony: Ceremony,
+        challenge: [u8; 32],
+    ) -> String {
+        let challenge = URL_SAFE_NO_PAD.encode(challenge);
+        let ttl = Duration::from_secs(self.conf.challenge_ttl_secs);
+        let mut challenges = self.challenges.lock().await;
+        challenges.retain(|_, c| c.issued.elapsed() < ttl);
+        challenges.insert(
+            challenge.clone(),
+            PendingChallenge {
+                account: account.to_string(),
+                ceremony,
+                issued: Instant::now(),
+            },
+        );
+        challenge
+    }
+
+    /// Checks the client data of a ceremony, consuming its challenge. Returns the account the
+    /// challenge was issued for.
+    async fn check_client_data(
+        &self,
+        client_data_json: &[u8],
+        ceremony: Ceremony,
+    ) -> Result<String> {
+        let client_data: ClientData =
+            serde_json::from_slice(client_data_json).context("parsing client data")?;
+        ensure!(
+            client_data.kind == ceremony.client_data_type(),
+            "unexpected client data type {}",
+            client_data.kind
+        );
+        ensure!(
+            self.conf.origins.contains(&client_data.origin),
+            "origin {} is not allowed",
+            client_data.origin
+        );
+
+        let challenge = self
+            .challenges
+            .lock()
+            .await
+            .remove(client_data.challenge.trim_end_matches('='))
+            .filter(|c| c.issued.elapsed() < Duration::from_secs(self.conf.challenge_ttl_secs))
+            .filter(|c| c.ceremony == ceremony)
+            .ok_or_else(|| anyhow!("unknown or expired challenge"))?;
+        Ok(challenge.account)
+    }
+
+    fn check_authenticator_data(&self, data: &AuthenticatorData) -> Result<()> {
+        ensure!(
+            data.rp_id_hash == self.rp_id_hash(),
+            "authenticator data is for another relying party"
+        );
+        ensure!(
+            data.flags & FLAG_USER_PRESENT != 0,
+            "user presence not asserted"
+        );
+        Ok(())
+    }
+
+    async fn credentials(&self, account: &str) -> Result<Vec<CredentialRow>> {
+        Ok(sqlx::query_as(
+            "SELECT credential_id, account, public_key, sign_count, created_at FROM webauthn_credentials WHERE account = $1 ORDER BY created_at",
+        )
+        .bind(account)
+        .fetch_all(self.db.reader())
+        .await?)
+    }
+
+    /// Anyone may register the first passkey of an account that doesn't exist yet, to use it as
+    /// its auth method. Registered accounts need a bearer WebSocket topic granted for them,
+    /// which is only issued to holders of one of their session keys.
+    async fn authorize_registration(&self, account: &str, headers: &HeaderMap) -> Result<()> {
+        let topic = headers
+            .get(AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "));
+        if topic.is_some_and(|topic| self.ws_auth.is_granted(account, topic)) {
+            return Ok(());
+        }
+        if self.ws_auth.is_registered(account).await? {
+            bail!("{account} is registered, only its owner can add a passkey");
+        }
+        Ok(())
+    }
+
+    async fn registration_options(&self, account: &str) -> Result<RegistrationOptions> {
+        let existing = self.credentials(account).await?;
+        let challenge = self
+            .issue_challenge(account, Ceremony::Registration, rand::random())
+            .await;
+        Ok(RegistrationOptions {
+            challenge,
+            rp: serde_json::json!({ "id": self.conf.rp_id, "name": self.conf.rp_name }),
+            user: serde_json::json!({
+                "id": URL_SAFE_NO_PAD.encode(Sha256::digest(account.as_bytes())),
+                "name": account,
+                "displayName": account,
+            }),
+            pub_key_cred_params: vec![
+                serde_json::json!({ "type": "public-key", "alg": COSE_ALG_ES256 }),
+            ],
+            exclude_credentials: existing
+                .into_iter()
+                .map(|c| serde_json::json!({ "type": "public-key", "id": c.credential_id }))
+                .collect(),
+            timeout: self.conf.challenge_ttl_secs * 1000,
+            attestation: "none".to_string(),
+        })
+    }
+
+    async fn register(&self, body: RegistrationResponseBody) -> Result<WebAuthnCredential> {
+        let client_data_json = decode_b64url("client_data_json", &body.client_data_json)?;
+        let attestation_object = decode_b64url("attestation_object", &body.attestation_object)?;
+        let account = self
+            .check_client_data(&client_data_json, Ceremony::Registration)
+            .await?;
+
+        let attestation: Value = ciborium::de::from_reader(Cursor::new(&attestation_object))
+            .map_err(|e| anyhow!("invalid attestation object: {e}"))?;
+        let auth_data = attestation
+            .as_map()
+            .and_then(|entries| {
+                entries
+                    .iter()
+                    .find(|(k, _)| k.as_text() == Some("authData"))
+                    .and_then(|(_, v)| v.as_bytes())
+            })
+            .context("attestation object has no authData")?;
+        let auth_data = parse_authenticator_data(auth_data)?;
+        self.check_authenticator_data(&auth_data)?;
+        let (credential_id, key) = auth_data
+            .attested
+            .context("registration without attested credential")?;
+        let public_key = cose_public_key(&key)?;
+
+        let row: CredentialRow = sqlx::query_as(
+            "
+            INSERT INTO webauthn_credentials (credential_id, account, public_key, sign_count)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (credential_id) DO NOTHING
+            RETURNING credential_id, account, public_key, sign_count, created_at
+            ",
+        )
+        .bind(URL_SAFE_NO_PAD.encode(&credential_id))
+        .bind(&account)
+        .bind(hex::encode(public_key.to_encoded_point(true).as_bytes()))
+        .bind(auth_data.sign_count as i64)
+        .fetch_optional(&self.db.primary)
+        .await?
+        .ok_or_else(|| anyhow!("credential is already registered"))?;
+
+        tracing::info!("Registered passkey {} for {}", row.credential_id, account);
+        Ok(self.credential(row))
+    }
+
+    async fn authentication_options(
+        &self,
+        body: AuthenticationOptionsBody,
+    ) -> Result<AuthenticationOptions> {
+        let credentials = self.credentials(&body.account).await?;
+        if credentials.is_empty() {
+            bail!("No passkey registered for {}", body.account);
+        }
+        let challenge = match body.nonce {
+            Some(nonce) => wallet::webauthn_challenge(&body.account, nonce),
+            None => rand::random(),
+        };
+        let challenge = self
+            .issue_challenge(&body.account, Ceremony::Authentication, challenge)
+            .await;
+        Ok(AuthenticationOptions {
+            challenge,
+            rp_id: self.conf.rp_id.clone(),
+            allow_credentials: credentials
+                .into_iter()
+                .map(|c| serde_json::json!({ "type": "public-key", "id": c.credential_id }))
+                .collect(),
+            timeout: self.conf.challenge_ttl_secs * 1000,
+            user_verification: "preferred".to_string(),
+        })
+    }
+
+    async fn authenticate(&self, body: AuthenticationResponseBody) -> Result<WebAuthnAssertion> {
+        let client_data_json = decode_b64url("client_data_json", &body.client_data_json)?;
+        let authenticator_data = decode_b64url("authenticator_data", &body.authenticator_data)?;
+        let signature = decode_b64url("signature", &body.signature)?;
+        let credential_id = body.credential_id.trim_end_matches('=');
+
+        let account = self
+            .check_client_data(&client_data_json, Ceremony::Authentication)
+            .await?;
+        let auth_data = parse_authenticator_data(&authenticator_data)?;
+        self.check_authenticator_data(&auth_data)?;
+
+        let credential: CredentialRow = sqlx::query_as(
+            "SELECT credential_id, account, public_key, sign_count, created_at FROM webauthn_credentials WHERE credential_id = $1 AND account = $2",
+        )
+        .bind(credential_id)
+        .bind(&account)
+        .fetch_optional(&self.db.primary)
+        .await?
+        .ok_or_else(|| anyhow!("Unknown passkey {credential_id} for {account}"))?;
+
+        let public_key = VerifyingKey::from_sec1_bytes(&hex::decode(&credential.public_key)?)
+            .map_err(|e| anyhow!("invalid stored key: {e}"))?;
+        let signature =
+            Signature::from_der(&signature).map_err(|e| anyhow!("invalid signature: {e}"))?;
+        let mut signed = authenticator_data.clone();
+        signed.extend_from_slice(&Sha256::digest(&client_data_json));
+        public_key
+            .verify(&signed, &signature)
+            .map_err(|_| anyhow!("Invalid passkey signature"))?;
+
+        // Authenticators without a counter always report 0, others must increase it.
+        let sign_count = i64::from(auth_data.sign_count);
+        if (sign_count != 0 || credential.sign_count != 0) && sign_count <= credential.sign_count {
+            bail!("Passkey signature counter went backwards, the authenticator may be cloned");
+        }
+        sqlx::query("UPDATE webauthn_credentials SET sign_count = $2 WHERE credential_id = $1")
+            .bind(credential_id)
+            .bind(sign_count)
+            .execute(&self.db.primary)
+            .await?;
+
+        let signature = signature.normalize_s().unwrap_or(signature);
+        Ok(WebAuthnAssertion {
+            account,
+            credential_id: credential.credential_id,
+            public_key: credential.public_key,
+            authenticator_data: hex::encode(&authenticator_data),
+            client_data_json: hex::encode(&client_data_json),
+            signature: hex::encode(signature.to_bytes()),
+        })
+    }
+}
+
+impl Module for WebAuthnModule {
+    type Context = WebAuthnModuleCtx;
+
+    async fn build(bus: SharedMessageBus, ctx: Self::Context) -> Result<Self> {
+        let db = DbPools::connect(&ctx.db_url, &ctx.database).await?;
+
+        sqlx::query(
+            r#"CREATE TABLE IF NOT EXISTS webauthn_credentials (
+                credential_id TEXT PRIMARY KEY,
+                account TEXT NOT NULL,
+                public_key TEXT NOT NULL,
+                sign_count BIGINT NOT NULL,
+                created_at TIMESTAMP NOT NULL DEFAULT NOW()
+            )"#,
+        )
+        .execute(&db.primary)
+        .await?;
+        sqlx::query(
+            "CREATE INDEX IF NOT EXISTS webauthn_credentials_account_idx ON webauthn_credentials (account)",
+        )
+        .execute(&db.primary)
+        .await?;
+
+        let inner = Arc::new(WebAuthnModuleInner {
+            conf: ctx.conf,
+            db,
+            challenges: Mutex::new(HashMap::new()),
+            ws_auth: ctx.ws_auth,
+        });
+
+        let (router, openapi) = OpenApiRouter::default()
+            .routes(routes!(route_registration_options))
+            .routes(routes!(route_register))
+            .routes(routes!(route_authentication_options))
+            .routes(routes!(route_authenticate))
+            .routes(routes!(route_credentials))
+            .split_for_parts();
+        let api = router.with_state(inner.clone());
+
+        if let Ok(mut guard) = ctx.api_ctx.router.lock() {
+            if let Some(router) = guard.take() {
+                guard.replace(router.merge(api));
+            }
+        }
+        if let Ok(mut guard) = ctx.api_ctx.openapi.lock() {
+            guard.merge(openapi);
+        }
+
+        Ok(Self {
+            bus: WebAuthnModuleBusClient::new_from_bus(bus.new_handle()).await,
+            inner,
+        })
+    }
+
+    async fn run(&mut self) -> Result<()> {
+        let ttl = Duration::from_secs(self.inner.conf.challenge_ttl_secs);
+        let mut interval = tokio::time::interval(ttl);
+
+        module_handle_messages! {
+            on_self self,
+            _ = interval.tick() => {
+                self.inner
+                    .challenges
+                    .lock()
+                    .await
+                    .retain(|_, c| c.issued.elapsed() < ttl);
+            }
+        };
+        Ok(())
+    }
+}
+
+// --------------------------------------------------------
+//     Routes
+// --------------------------------------------------------
+
+#[utoipa::path(
+    post,
+    path = "/webauthn/register/options",
+    tag = "WebAuthn",
+    request_body = RegistrationOptionsBody,
+    responses(
+        (status = OK, description = "Options to create a passkey with", body = RegistrationOptions),
+        (status = UNAUTHORIZED, description = "Account is registered and the bearer token isn't a WebSocket topic granted for it")
+    )
+)]
+async fn route_registration_options(
+    State(ctx): State<Arc<WebAuthnModuleInner>>,
+    headers: HeaderMap,
+    Json(body): Json<RegistrationOptionsBody>,
+) -> Result<Json<RegistrationOptions>, AppError> {
+    // Challenges are only issued once authorized, so the registration they sign is too
+    ctx.authorize_registration(&body.account, &headers)
+        .await
+        .map_err(|e| AppError(StatusCode::UNAUTHORIZED, e))?;
+    ctx.registration_options(&body.account)
+        .await
+        .map(Json)
+        .map_err(AppError::from)
+}
+
+#[utoipa::path(
+    post,
+    path = "/webauthn/register",
+    tag = "WebAuthn",
+    request_body = RegistrationResponseBody,
+    responses(
+        (status = OK, description = "Passkey registered, returns the key to use as auth method", body = WebAuthnCredential)
+    )
+)]
+async fn route_register(
+    State(ctx): State<Arc<WebAuthnModuleInner>>,
+    Json(body): Json<RegistrationResponseBody>,
+) -> Result<Json<WebAuthnCredential>, AppError> {
+    ctx.register(body)
+        .await
+        .map(Json)
+        .map_err(|e| AppError(StatusCode::BAD_REQUEST, e))
+}
+
+#[utoipa::path(
+    post,
+    path = "/webauthn/authenticate/options",
+    tag = "WebAuthn",
+    request_body = AuthenticationOptionsBody,
+    responses(
+        (status = OK, description = "Options to get an assertion with", body = AuthenticationOptions),
+        (status = NOT_FOUND, description = "No passkey registered for the account")
+    )
+)]
+async fn route_authentication_options(
+    State(ctx): State<Arc<WebAuthnModuleInner>>,
+    Json(body): Json<AuthenticationOptionsBody>,
+) -> Result<Json<AuthenticationOptions>, AppError> {
+    ctx.authentication_options(body)
+        .await
+        .map(Json)
+        .map_err(|e| AppError(StatusCode::NOT_FOUND, e))
+}
+
+#[utoipa::path(
+    post,
+    path = "/webauthn/authenticate",
+    tag = "WebAuthn",
+    request_body = AuthenticationResponseBody,
+    responses(
+        (status = OK, description = "Assertion verified, in the form the contract checks", body = WebAuthnAssertion)
+    )
+)]
+async fn route_authenticate(
+    State(ctx): State<Arc<WebAuthnModuleInner>>,
+    Json(body): Json<AuthenticationResponseBody>,
+) -> Result<Json<WebAuthnAssertion>, AppError> {
+    ctx.authenticate(body)
+        .await
+        .map(Json)
+        .map_err(|e| AppError(StatusCode::UNAUTHORIZED, e))
+}
+
+#[utoipa::path(
+    get,
+    path = "/webauthn/credentials/{account}",
+    tag = "WebAuthn",
+    params(
+        ("account" = String, Path, description = "Account")
+    ),
+    responses(
+        (status = OK, description = "Passkeys registered for the account", body = Vec<WebAuthnCredential>)
+    )
+)]
+async fn route_credentials(
+    State(ctx): State<Arc<WebAuthnModuleInner>>,
+    Path(account): Path<String>,
+) -> Result<Json<Vec<WebAuthnCredential>>, AppError> {
+    ctx.credentials(&account)
+        .await
+        .map(|rows| Json(rows.into_iter().map(|row| ctx.credential(row)).collect()))
+        .map_err(AppError::from)
+}
@@ -0,0 +1,337 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    routing::post,
+    Json, Router,
+};
+use client_sdk::AppError;
+use sdk::ContractName;
+use secp256k1::{ecdsa::Signature, Message, PublicKey, Secp256k1};
+use serde::{Deserialize, Serialize};
+use server::audit::{AuditKind, AuditRecorder};
+use server::conf::WsAuthConf;
+use sha2::{Digest, Sha256};
+use wallet::utils::ct_eq;
+
+/// Gates account-scoped WebSocket topics behind a session key challenge.
+///
+/// Topic registration happens in the WebSocket module, which anyone can do for any name, so
+/// account events are published on random topics handed out to clients that signed a server
+/// challenge with one of the account's live session keys. Unless auth is required, events are
+/// also published on the account name, for older clients. Admins can grant topics for any
+/// account, or revoke an account's grants, through the admin API.
+pub struct WsAuth {
+    conf: WsAuthConf,
+    audit: AuditRecorder,
+    wallet_cn: ContractName,
+    /// Base URL of this server's REST API, serving the indexer routes.
+    indexer_url: String,
+    http: reqwest::Client,
+    /// Pending challenges by value, with their account and expiry.
+    challenges: Mutex<HashMap<String, (String, u64)>>,
+    /// Topics granted to each account, with their expiry.
+    grants: Mutex<HashMap<String, Vec<(String, u64)>>>,
+}
+
+/// Bound on unanswered challenges, which anyone can request.
+const MAX_PENDING_CHALLENGES: usize = 10_000;
+/// Topics kept per account, the oldest grant is dropped first.
+const MAX_GRANTS_PER_ACCOUNT: usize = 16;
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+fn now_ms() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or_default()
+}
+
+/// Message the session key signs to answer `challenge` for `account`.
+pub fn challenge_message(account: &str, challenge: &str) -> String {
+    format!("{account}:{challenge}:ws")
+}
+
+#[derive(Deserialize)]
+struct IndexedSessionKey {
+    key: String,
+    expiration_date: u128,
+}
+
+#[derive(Deserialize)]
+struct IndexedAccount {
+    session_keys: Vec<IndexedSessionKey>,
+}
+
+impl WsAuth {
+    pub fn new(
+        conf: WsAuthConf,
+        wallet_cn: ContractName,
+        indexer_url: String,
+        audit: AuditRecorder,
+    ) -> Self {
+        Self {
+            conf,
+            audit,
+            wallet_cn,
+            indexer_url,
+            http: reqwest::Client::new(),
+            challenges: Mutex::default(),
+            grants: Mutex::default(),
+        }
+    }
+
+    /// Whether events are also published on the account name, for clients that don't
+    /// authenticate.
+    pub fn publishes_on_account_name(&self) -> bool {
+        !self.conf.required
+    }
+
+    /// Live topics granted for `account`.
+    pub fn granted_topics(&self, account: &str) -> Vec<String> {
+        let now = now_secs();
+        let mut topics = vec![];
+        let mut grants = self.grants.lock().expect("ws grants poisoned");
+        if let Some(granted) = grants.get_mut(account) {
+            granted.retain(|(_, expires_at)| *expires_at > now);
+            topics.extend(granted.iter().map(|(topic, _)| topic.clone()));
+            if granted.is_empty() {
+                grants.remove(account);
+            }
+        }
+        topics
+    }
+
+    /// Whether `topic` is a live topic granted for `account`, so its holder answered a challenge
+    /// with one of the account's session keys.
+    pub fn is_granted(&self, account: &str, topic: &str) -> bool {
+        self.granted_topics(account)
+            .iter()
+            .any(|granted| ct_eq(granted.as_bytes(), topic.as_bytes()))
+    }
+
+    /// Whether `account` is registered in the wallet contract.
+    pub async fn is_registered(&self, account: &str) -> Result<bool> {
+        let response = self
+            .http
+            .get(format!(
+                "{}/v1/indexer/contract/{}/account/{}",
+                self.indexer_url, self.wallet_cn.0, account
+            ))
+            .send()
+            .await?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(false);
+        }
+        response.error_for_status().context("fetching account")?;
+        Ok(true)
+    }
+
+    fn challenge(&self, account: String) -> Result<ChallengeResponse> {
+        let now = now_secs();
+        let mut challenges = self.challenges.lock().expect("ws challenges poisoned");
+        challenges.retain(|_, (_, expires_at)| *expires_at > now);
+        if challenges.len() >= MAX_PENDING_CHALLENGES {
+            bail!("Too many pending challenges");
+        }
+        let challenge = hex::encode(rand::random::<[u8; 32]>());
+        let expires_at = now + self.conf.challenge_ttl_secs;
+        challenges.insert(challenge.clone(), (account, expires_at));
+        Ok(ChallengeResponse {
+            challenge,
+            expires_at,
+        })
+    }
+
+    async fn authenticate(&self, request: AuthenticateWsRequest) -> Result<GrantResponse> {
+        let pending = self
+            .challenges
+            .lock()
+            .expect("ws challenges poisoned")
+            .remove(&request.challenge);
+        match pending {
+            Some((account, expires_at))
+                if account == request.account && expires_at > now_secs() => {}
+            _ => bail!("Unknown or expired challenge"),
+        }
+
+        let public_key = PublicKey::from_slice(
+            &hex::decode(&request.public_key).context("decoding public key")?,
+        )
+        .context("parsing public key")?;
+        let signature = Signature::from_compact(
+            &hex::decode(&request.signature).context("decoding signature")?,
+        )
+        .context("parsing signature")?;
+        let digest: [u8; 32] =
+            Sha256::digest(challenge_message(&request.account, &request.challenge)).into();
+        Secp256k1::verification_only()
+            .verify_ecdsa(Message::from_digest(digest), &signature, &public_key)
+            .map_err(|e| anyhow!("Invalid signature: {e}"))?;
+
+        let account: IndexedAccount = self
+            .http
+            .get(format!(
+                "{}/v1/indexer/contract/{}/account/{}",
+                self.indexer_url, self.wallet_cn.0, request.account
+            ))
+            .send()
+            .await?
+            .error_for_status()
+            .context("fetching account")?
+            .json()
+            .await?;
+        let now = now_ms();
+        if !account
+            .session_keys
+            .iter()
+            .any(|sk| sk.key == request.public_key && sk.expiration_date > now)
+        {
+            bail!("Not a live session key of {}", request.account);
+        }
+
+        Ok(self.grant(request.account))
+    }
+
+    pub(crate) fn grant(&self, account: String) -> GrantResponse {
+        let topic = hex::encode(rand::random::<[u8; 32]>());
+        let expires_at = now_secs() + self.conf.grant_ttl_secs;
+        let mut grants = self.grants.lock().expect("ws grants poisoned");
+        let granted = grants.entry(account).or_default();
+        if granted.len() >= MAX_GRANTS_PER_ACCOUNT {
+            granted.remove(0);
+        }
+        granted.push((topic.clone(), expires_at));
+        GrantResponse { topic, expires_at }
+    }
+
+    /// Stops publishing on every topic granted for `account`, returning how many there were.
+    pub(crate) fn revoke(&self, account: &str) -> usize {
+        self.grants
+            .lock()
+            .expect("ws grants poisoned")
+            .remove(account)
+            .map(|granted| granted.len())
+            .unwrap_or_default()
+    }
+}
+
+// --------------------------------------------------------
+//     Routes
+// --------------------------------------------------------
+
+#[derive(Debug, Deserialize)]
+struct ChallengeRequest {
+    account: String,
+}
+
+#[derive(Debug, Serialize)]
+struct ChallengeResponse {
+    challenge: String,
+    expires_at: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct AuthenticateWsRequest {
+    account: String,
+    challenge: String,
+    /// Hex-encoded compressed session key.
+    public_key: String,
+    /// Hex-encoded compact signature of the SHA-256 of [`challenge_message`].
+    signature: String,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct GrantResponse {
+    /// Topic to register on the WebSocket to receive the account's events.
+    topic: String,
+    expires_at: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct RevokeResponse {
+    revoked: usize,
+}
+
+pub fn ws_auth_router(ws_auth: Arc<WsAuth>) -> Router {
+    Router::new()
+        .route("/api/ws/challenge", post(route_challenge))
+        .route("/api/ws/authenticate", post(route_authenticate))
+        .with_state(ws_auth)
+}
+
+/// Admin routes granting topics for any account, e.g. for support tooling, and revoking an
+/// account's topics, e.g. after one of its session keys leaked.
+pub fn ws_auth_admin_router(ws_auth: Arc<WsAuth>) -> Router {
+    Router::new()
+        .route(
+            "/ws/grants/{account}",
+            post(admin_grant).delete(admin_revoke),
+        )
+        .with_state(ws_auth)
+}
+
+async fn admin_grant(
+    Path(account): Path<String>,
+    State(ws_auth): State<Arc<WsAuth>>,
+) -> Json<GrantResponse> {
+    ws_auth.audit.record(
+        AuditKind::AdminOperation,
+        "admin",
+        serde_json::json!({
+            "operation": "grant_ws_topic",
+            "account": account,
+        }),
+    );
+    Json(ws_auth.grant(account))
+}
+
+async fn admin_revoke(
+    Path(account): Path<String>,
+    State(ws_auth): State<Arc<WsAuth>>,
+) -> Json<RevokeResponse> {
+    let revoked = ws_auth.revoke(&account);
+    ws_auth.audit.record(
+        AuditKind::AdminOperation,
+        "admin",
+        serde_json::json!({
+            "operation": "revoke_ws_topics",
+            "account": account,
+            "revoked": revoked,
+        }),
+    );
+    Json(RevokeResponse { revoked })
+}
+
+async fn route_challenge(
+    State(ws_auth): State<Arc<WsAuth>>,
+    Json(request): Json<ChallengeRequest>,
+) -> Result<Json<ChallengeResponse>, AppError> {
+    ws_auth
+        .challenge(request.account)
+        .map(Json)
+        .map_err(|e| AppError(StatusCode::TOO_MANY_REQUESTS, e))
+}
+
+async fn route_authenticate(
+    State(ws_auth): State<Arc<WsAuth>>,
+    Json(request): Json<AuthenticateWsRequest>,
+) -> Result<Json<GrantResponse>, AppError> {
+    ws_auth
+        .authenticate(request)
+        .await
+        .map(Json)
+        .map_err(|e| AppError(StatusCode::UNAUTHORIZED, e))
+}
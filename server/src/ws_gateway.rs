@@ -0,0 +1,306 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use anyhow::{Context, Result};
+use axum::{
+    extract::{
+        ws::{Message, WebSocket},
+        State, WebSocketUpgrade,
+    },
+    response::Response,
+    routing::get,
+    Router,
+};
+use futures::{SinkExt, StreamExt};
+use hyli_modules::{
+    bus::{BusClientSender, SharedMessageBus},
+    log_error, module_bus_client, module_handle_messages,
+    modules::{
+        websocket::{WsInMessage, WsTopicMessage},
+        BuildApiContextInner, Module,
+    },
+};
+use opentelemetry::{
+    metrics::{Counter, UpDownCounter},
+    KeyValue,
+};
+use serde::Deserialize;
+use server::conf::WsGatewayConf;
+use tokio::sync::mpsc;
+
+use crate::app::{AppOutWsEvent, AppWsInMessage};
+use crate::stats::StatsCounters;
+
+/// WebSocket endpoint on the REST server negotiating its frame encoding at upgrade, through the
+/// `Sec-WebSocket-Protocol` header: `msgpack` for MessagePack binary frames, `json` or nothing
+/// for JSON text frames.
+///
+/// It relays the same topics as the WebSocket module, for clients where bandwidth and parse cost
+/// matter, like mobile wallets following busy history topics.
+pub struct WsGateway {
+    bus: WsGatewayBusClient,
+    connections: Arc<Connections>,
+    inbound: mpsc::UnboundedReceiver<WsInMessage<AppWsInMessage>>,
+}
+
+pub struct WsGatewayCtx {
+    pub api: Arc<BuildApiContextInner>,
+    pub conf: WsGatewayConf,
+    pub stats: Arc<StatsCounters>,
+}
+
+module_bus_client! {
+#[derive(Debug)]
+pub struct WsGatewayBusClient {
+    sender(WsInMessage<AppWsInMessage>),
+    receiver(WsTopicMessage<AppOutWsEvent>),
+}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum FrameEncoding {
+    Json,
+    MessagePack,
+}
+
+impl FrameEncoding {
+    fn attributes(&self) -> [KeyValue; 1] {
+        [KeyValue::new(
+            "encoding",
+            match self {
+                FrameEncoding::Json => "json",
+                FrameEncoding::MessagePack => "msgpack",
+            },
+        )]
+    }
+
+    fn encode(&self, event: &AppOutWsEvent) -> Result<Message> {
+        Ok(match self {
+            FrameEncoding::Json => Message::Text(serde_json::to_string(event)?.into()),
+            FrameEncoding::MessagePack => Message::Binary(rmp_serde::to_vec_named(event)?.into()),
+        })
+    }
+
+    fn decode(&self, message: &Message) -> Result<Option<ClientFrame>> {
+        Ok(match (self, message) {
+            (FrameEncoding::Json, Message::Text(text)) => Some(serde_json::from_str(text)?),
+            (FrameEncoding::MessagePack, Message::Binary(bytes)) => {
+                Some(rmp_serde::from_slice(bytes)?)
+            }
+            _ => None,
+        })
+    }
+}
+
+/// Frames clients send, in the format the WebSocket module expects.
+#[derive(Debug, Deserialize)]
+enum ClientFrame {
+    RegisterTopic(String),
+    Message(AppWsInMessage),
+}
+
+struct Connection {
+    encoding: FrameEncoding,
+    topics: HashSet<String>,
+    frames: mpsc::UnboundedSender<Message>,
+}
+
+struct Connections {
+    conf: WsGatewayConf,
+    next_id: AtomicU64,
+    open: Mutex<HashMap<u64, Connection>>,
+    inbound: mpsc::UnboundedSender<WsInMessage<AppWsInMessage>>,
+    metrics: GatewayMetrics,
+}
+
+/// Counts the connected clients and the frames exchanged with them, by encoding.
+struct GatewayMetrics {
+    connections: UpDownCounter<i64>,
+    frames_sent: Counter<u64>,
+    frames_received: Counter<u64>,
+    stats: Arc<StatsCounters>,
+}
+
+impl GatewayMetrics {
+    fn new(stats: Arc<StatsCounters>) -> Self {
+        let meter = opentelemetry::global::meter("wallet_ws");
+        Self {
+            connections: meter
+                .i64_up_down_counter("ws_gateway_connections")
+                .with_description("Number of clients connected to the WebSocket gateway")
+                .build(),
+            frames_sent: meter
+                .u64_counter("ws_gateway_frames_sent_total")
+                .with_description("Number of frames sent to WebSocket gateway clients")
+                .build(),
+            frames_received: meter
+                .u64_counter("ws_gateway_frames_received_total")
+                .with_description("Number of frames received from WebSocket gateway clients")
+                .build(),
+            stats,
+        }
+    }
+
+    fn connected(&self, encoding: FrameEncoding) {
+        self.connections.add(1, &encoding.attributes());
+        self.stats
+            .ws_gateway_connections
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn disconnected(&self, encoding: FrameEncoding) {
+        self.connections.add(-1, &encoding.attributes());
+        self.stats
+            .ws_gateway_connections
+            .fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Connections {
+    fn dispatch(&self, message: &WsTopicMessage<AppOutWsEvent>) {
+        let mut encoded: HashMap<FrameEncoding, Message> = HashMap::new();
+        let open = self.open.lock().expect("ws connections poisoned");
+        for connection in open.values() {
+            if !connection.topics.contains(&message.topic) {
+                continue;
+            }
+            let frame = match encoded.get(&connection.encoding) {
+                Some(frame) => frame.clone(),
+                None => {
+                    let Ok(frame) = log_error!(
+                        connection.encoding.encode(&message.message),
+                        "Encoding WebSocket frame"
+                    ) else {
+                        continue;
+                    };
+                    encoded.insert(connection.encoding, frame.clone());
+                    frame
+                }
+            };
+            // A closed connection is removed by its own task.
+            if connection.frames.send(frame).is_ok() {
+                self.metrics
+                    .frames_sent
+                    .add(1, &connection.encoding.attributes());
+            }
+        }
+    }
+
+    async fn serve(self: Arc<Self>, socket: WebSocket, encoding: FrameEncoding) {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (frames_tx, mut frames) = mpsc::unbounded_channel();
+        self.open.lock().expect("ws connections poisoned").insert(
+            id,
+            Connection {
+                encoding,
+                topics: HashSet::new(),
+                frames: frames_tx,
+            },
+        );
+        self.metrics.connected(encoding);
+
+        let (mut sink, mut stream) = socket.split();
+        let writer = tokio::spawn(async move {
+            while let Some(frame) = frames.recv().await {
+                if sink.send(frame).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        while let Some(Ok(message)) = stream.next().await {
+            if matches!(message, Message::Close(_)) {
+                break;
+            }
+            self.metrics.frames_received.add(1, &encoding.attributes());
+            match encoding.decode(&message) {
+                Ok(Some(frame)) => self.on_frame(id, frame),
+                Ok(None) => {}
+                Err(e) => tracing::debug!("Invalid frame from WebSocket client {}: {:#}", id, e),
+            }
+        }
+
+        self.open
+            .lock()
+            .expect("ws connections poisoned")
+            .remove(&id);
+        self.metrics.disconnected(encoding);
+        writer.abort();
+    }
+
+    fn on_frame(&self, id: u64, frame: ClientFrame) {
+        match frame {
+            ClientFrame::RegisterTopic(topic) => {
+                let mut open = self.open.lock().expect("ws connections poisoned");
+                if let Some(connection) = open.get_mut(&id) {
+                    if connection.topics.len() < self.conf.max_topics_per_connection {
+                        connection.topics.insert(topic);
+                    }
+                }
+            }
+            ClientFrame::Message(message) => {
+                let _ = self.inbound.send(WsInMessage {
+                    addr: format!("gateway-{id}"),
+                    message,
+                });
+            }
+        }
+    }
+}
+
+impl Module for WsGateway {
+    type Context = WsGatewayCtx;
+
+    async fn build(bus: SharedMessageBus, ctx: Self::Context) -> Result<Self> {
+        let (inbound_tx, inbound) = mpsc::unbounded_channel();
+        let connections = Arc::new(Connections {
+            conf: ctx.conf,
+            next_id: AtomicU64::new(0),
+            open: Mutex::new(HashMap::new()),
+            inbound: inbound_tx,
+            metrics: GatewayMetrics::new(ctx.stats),
+        });
+
+        let api = Router::new()
+            .route("/api/ws", get(upgrade))
+            .with_state(connections.clone());
+        if let Ok(mut guard) = ctx.api.router.lock() {
+            if let Some(router) = guard.take() {
+                guard.replace(router.merge(api));
+            }
+        }
+
+        Ok(Self {
+            bus: WsGatewayBusClient::new_from_bus(bus.new_handle()).await,
+            connections,
+            inbound,
+        })
+    }
+
+    async fn run(&mut self) -> Result<()> {
+        module_handle_messages! {
+            on_self self,
+            listen<WsTopicMessage<AppOutWsEvent>> message => {
+                self.connections.dispatch(&message);
+            }
+            Some(message) = self.inbound.recv() => {
+                self.bus.send(message).context("forwarding WebSocket client message")?;
+            }
+        };
+        Ok(())
+    }
+}
+
+async fn upgrade(State(connections): State<Arc<Connections>>, ws: WebSocketUpgrade) -> Response {
+    let ws = ws.protocols(["msgpack", "json"]);
+    let encoding = match ws.selected_protocol().and_then(|p| p.to_str().ok()) {
+        Some("msgpack") => FrameEncoding::MessagePack,
+        _ => FrameEncoding::Json,
+    };
+    ws.on_upgrade(move |socket| connections.serve(socket, encoding))
+}
@@ -0,0 +1,115 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::app::AppOutWsEvent;
+
+/// Bound on the accounts with buffered events, the least recently active is dropped first.
+const MAX_BUFFERED_ACCOUNTS: usize = 10_000;
+
+/// Recent account events, so clients that reconnect can ask for the ones they missed.
+///
+/// Events are numbered with a cursor shared by all accounts. It starts from the boot time in
+/// microseconds, so it keeps growing across restarts and a client only needs to remember the last
+/// cursor it received.
+pub struct EventReplay {
+    capacity: usize,
+    inner: Mutex<ReplayInner>,
+}
+
+struct ReplayInner {
+    /// Cursors before this one were handed out by a previous run of the server.
+    boot_cursor: u64,
+    next_cursor: u64,
+    /// Latest cursor of the buffers dropped to make room for other accounts.
+    evicted: u64,
+    buffers: HashMap<String, AccountBuffer>,
+}
+
+#[derive(Default)]
+struct AccountBuffer {
+    events: VecDeque<(u64, AppOutWsEvent)>,
+    /// Cursor of the last event dropped to make room.
+    dropped: u64,
+}
+
+/// Events buffered after a cursor.
+pub struct Replay {
+    pub events: Vec<(u64, AppOutWsEvent)>,
+    /// Some events after the cursor were already dropped from the buffer.
+    pub truncated: bool,
+}
+
+impl EventReplay {
+    /// Keeps the last `capacity` events of each account, 0 disables buffering.
+    pub fn new(capacity: usize) -> Self {
+        let boot_cursor = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_micros() as u64)
+            .unwrap_or_default();
+        Self {
+            capacity,
+            inner: Mutex::new(ReplayInner {
+                boot_cursor,
+                next_cursor: boot_cursor,
+                evicted: 0,
+                buffers: HashMap::new(),
+            }),
+        }
+    }
+
+    /// Numbers `event` and buffers it for `account`, returning its cursor.
+    pub fn record(&self, account: &str, event: &AppOutWsEvent) -> u64 {
+        let mut inner = self.inner.lock().expect("ws replay poisoned");
+        inner.next_cursor += 1;
+        let cursor = inner.next_cursor;
+        if self.capacity == 0 {
+            return cursor;
+        }
+
+        if !inner.buffers.contains_key(account) && inner.buffers.len() >= MAX_BUFFERED_ACCOUNTS {
+            let stalest = inner
+                .buffers
+                .iter()
+                .filter_map(|(account, buffer)| Some((buffer.events.back()?.0, account)))
+                .min()
+                .map(|(last, account)| (last, account.clone()));
+            if let Some((last, stalest)) = stalest {
+                inner.buffers.remove(&stalest);
+                inner.evicted = inner.evicted.max(last);
+            }
+        }
+        let buffer = inner.buffers.entry(account.to_string()).or_default();
+        if buffer.events.len() >= self.capacity {
+            if let Some((dropped, _)) = buffer.events.pop_front() {
+                buffer.dropped = dropped;
+            }
+        }
+        buffer.events.push_back((cursor, event.clone()));
+        cursor
+    }
+
+    /// Events of `account` numbered after `cursor`, oldest first.
+    pub fn since(&self, account: &str, cursor: u64) -> Replay {
+        let inner = self.inner.lock().expect("ws replay poisoned");
+        // Events of the previous run are gone.
+        let restarted = cursor < inner.boot_cursor;
+        let Some(buffer) = inner.buffers.get(account) else {
+            return Replay {
+                events: vec![],
+                truncated: restarted || inner.evicted > cursor,
+            };
+        };
+        Replay {
+            events: buffer
+                .events
+                .iter()
+                .filter(|(c, _)| *c > cursor)
+                .cloned()
+                .collect(),
+            truncated: restarted || buffer.dropped > cursor,
+        }
+    }
+}